@@ -0,0 +1,83 @@
+//! Character set detection
+//!
+//! Text files written on Windows systems are often stored in the legacy
+//! encoding of the system's language instead of UTF-8: GBK, Big5,
+//! Shift_JIS, EUC-KR, Windows-1251, KOI8 and the like. [`detect`] picks the
+//! encoding of such content:
+//! - a byte order mark wins, then content that is valid UTF-8 is UTF-8
+//! - otherwise [chardetng] guesses among the legacy encodings, falling back
+//!   to Windows-1252
+//!
+//! Only the head of the content ([`SAMPLE_LEN`]) is looked at.
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+
+/// Bytes of the content looked at
+pub const SAMPLE_LEN: usize = 64 * 1024;
+
+/// Encoding of `content`
+pub fn detect(content: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(content) {
+        return encoding;
+    }
+    let sample = &content[..content.len().min(SAMPLE_LEN)];
+    // A sequence cut off at the end of the sample is no error
+    match std::str::from_utf8(sample) {
+        Ok(_) => return UTF_8,
+        Err(e) if e.error_len().is_none() => return UTF_8,
+        Err(_) => {}
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(sample, sample.len() == content.len());
+    detector.guess(None, false)
+}
+
+/// `content` decoded from `encoding` to UTF-8, a byte order mark dropped
+/// and malformed sequences replaced
+pub fn to_utf8(content: &[u8], encoding: &'static Encoding) -> String {
+    encoding.decode_with_bom_removal(content).0.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{BIG5, EUC_KR, GBK, KOI8_R, KOI8_U, SHIFT_JIS, WINDOWS_1251, WINDOWS_1252};
+
+    fn encode(encoding: &'static Encoding, text: &str) -> Vec<u8> {
+        let (bytes, _, unmappable) = encoding.encode(text);
+        assert!(!unmappable);
+        bytes.into_owned()
+    }
+
+    #[test]
+    fn test_detect() {
+        let chinese = "这是一个测试文件，我们在中国使用它。";
+        let traditional = "這是一個測試文件，我們在臺灣使用它。";
+        let japanese = "これは日本語のテストファイルです。";
+        let korean = "이것은 한국어 테스트 파일입니다.";
+
+        assert_eq!(detect(chinese.as_bytes()), UTF_8);
+        assert_eq!(detect(b"plain ascii"), UTF_8);
+        assert_eq!(detect(&encode(GBK, chinese)), GBK);
+        assert_eq!(detect(&encode(BIG5, traditional)), BIG5);
+        assert_eq!(detect(&encode(SHIFT_JIS, japanese)), SHIFT_JIS);
+        assert_eq!(detect(&encode(EUC_KR, korean)), EUC_KR);
+        assert_eq!(detect(&encode(WINDOWS_1252, "Café crème brûlée")), WINDOWS_1252);
+        assert_eq!(detect(&encode(WINDOWS_1251, "Это тестовый файл на русском языке.")), WINDOWS_1251);
+        // KOI8-U is a superset of KOI8-R
+        assert_eq!(detect(&encode(KOI8_R, "Это тестовый файл на русском языке.")), KOI8_U);
+        assert_eq!(detect(b"\xFF\xFEa\0b\0"), encoding_rs::UTF_16LE);
+
+        // Cut off in the middle of a character
+        let long = chinese.repeat(SAMPLE_LEN / chinese.len() + 1);
+        assert_eq!(detect(long.as_bytes()), UTF_8);
+        assert_eq!(detect(&encode(GBK, &long)), GBK);
+    }
+
+    #[test]
+    fn test_to_utf8() {
+        assert_eq!(to_utf8(&encode(GBK, "中文"), GBK), "中文");
+        assert_eq!(to_utf8(b"\xEF\xBB\xBFtext", UTF_8), "text");
+    }
+}
@@ -0,0 +1,45 @@
+//! ApiToken entity - API 访问令牌表
+//!
+//! 用于服务账号和个人脚本等非交互式客户端认证, 仅保存令牌的 SHA-256 哈希
+//! 表名: disk_api_token
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_api_token")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 令牌所属用户名
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 令牌名称 (用途说明)
+    #[sea_orm(column_type = "String(Some(64))")]
+    pub name: String,
+
+    /// 令牌 SHA-256 哈希 (十六进制)
+    #[sea_orm(column_type = "String(Some(64))", unique)]
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+
+    /// 创建时间 (Unix 时间戳)
+    pub create_time: i64,
+
+    /// 最后使用时间 (Unix 时间戳, 0 表示未使用)
+    pub last_used_time: i64,
+
+    /// 过期时间 (Unix 时间戳, 0 表示永不过期)
+    pub expire_time: i64,
+
+    /// 令牌权限范围 (逗号分隔的权限名, 空表示拥有用户的全部权限)
+    #[sea_orm(column_type = "String(Some(128))", default_value = "")]
+    pub scopes: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,44 @@
+//! ColdFile entity - 冷存储文件表
+//!
+//! 长期未访问的文件移入冷存储后, 原位置只留下空的占位文件, 访问时自动取回.
+//! 压缩存储的文件同样以占位文件加压缩副本的方式保存
+//! 表名: disk_cold_file
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_cold_file")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 所有者用户名
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 文件路径 (相对用户根目录, 以 / 开头)
+    #[sea_orm(column_type = "String(Some(1024))")]
+    pub path: String,
+
+    /// 冷存储中的文件路径
+    #[sea_orm(column_type = "String(Some(1024))")]
+    pub cold_path: String,
+
+    /// 文件大小 (字节)
+    pub size: i64,
+
+    /// 文件修改时间 (Unix 时间戳)
+    pub modify_time: i64,
+
+    /// 移入冷存储的时间 (Unix 时间戳)
+    pub tier_time: i64,
+
+    /// 压缩副本的大小 (字节), 副本未压缩时为空
+    pub compressed_size: Option<i64>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
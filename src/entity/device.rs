@@ -0,0 +1,54 @@
+//! Device entity - 已记住的设备表
+//!
+//! 登录时选择"记住此设备"的浏览器保存一个长期令牌, 网页会话过期后凭它重新登录.
+//! 令牌每次使用后轮换, 仅保存 SHA-256 哈希
+//! 表名: disk_device
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_device")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 所属用户
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 设备名称 (浏览器的 User-Agent)
+    #[sea_orm(column_type = "String(Some(255))")]
+    pub name: String,
+
+    /// 当前令牌的 SHA-256 哈希
+    #[sea_orm(column_type = "String(Some(64))", unique)]
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+
+    /// 上一个令牌的哈希, 轮换后短时间内仍然有效
+    #[sea_orm(column_type = "String(Some(64))", nullable)]
+    #[serde(skip_serializing)]
+    pub prev_token_hash: Option<String>,
+
+    /// 最近一次轮换时间 (Unix 时间戳)
+    pub rotate_time: i64,
+
+    /// 最近使用的 IP
+    #[sea_orm(column_type = "String(Some(64))", nullable)]
+    pub last_ip: Option<String>,
+
+    /// 创建时间 (Unix 时间戳)
+    pub create_time: i64,
+
+    /// 最近使用时间 (Unix 时间戳)
+    pub last_used_time: i64,
+
+    /// 过期时间 (Unix 时间戳), 每次使用后顺延
+    pub expire_time: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,44 @@
+//! Digest entity - 动态邮件摘要设置表
+//!
+//! 用户选择的摘要频率，以及上次发送时的状态，用于计算两次摘要之间的变化
+//! 表名: disk_digest
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_digest")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 用户名
+    #[sea_orm(column_type = "String(Some(32))", unique)]
+    pub username: String,
+
+    /// 频率: off, daily, weekly
+    #[sea_orm(column_type = "String(Some(16))")]
+    pub frequency: String,
+
+    /// 上次发送时间 (Unix 时间戳, 0 = 未发送)
+    pub last_sent: i64,
+
+    /// 上次发送时的已用空间 (字节)
+    pub last_usage: i64,
+
+    /// 上次发送时所在的群组ID, 逗号分隔
+    #[sea_orm(column_type = "Text")]
+    pub groups: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// 不发送
+pub const FREQUENCY_OFF: &str = "off";
+/// 每天发送
+pub const FREQUENCY_DAILY: &str = "daily";
+/// 每周发送
+pub const FREQUENCY_WEEKLY: &str = "weekly";
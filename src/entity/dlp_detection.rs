@@ -0,0 +1,75 @@
+//! DlpDetection entity - 数据防泄漏检出记录表
+//!
+//! 命中数据防泄漏规则的上传，需审批的上传在审批前保存在暂存目录
+//! 表名: disk_dlp_detection
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_dlp_detection")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 上传的用户
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 目标目录树的所有者 (用户或部门/群组空间)
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub owner: String,
+
+    /// 目录树在文件接口中的路径前缀, 用户自己的文件为空
+    #[sea_orm(column_type = "String(Some(256))")]
+    pub prefix: String,
+
+    /// 目标目录 (相对所有者根目录)
+    #[sea_orm(column_type = "String(Some(1024))")]
+    pub parent_path: String,
+
+    /// 文件名
+    #[sea_orm(column_type = "String(Some(255))")]
+    pub name: String,
+
+    /// 文件大小 (字节)
+    pub size: i64,
+
+    /// 命中的规则名称, 以逗号分隔
+    #[sea_orm(column_type = "String(Some(1024))")]
+    pub rules: String,
+
+    /// 执行的动作: warn / approve / block
+    #[sea_orm(column_type = "String(Some(16))")]
+    pub action: String,
+
+    /// 命中次数
+    pub matches: i64,
+
+    /// 第一处命中内容 (已打码)
+    #[sea_orm(column_type = "String(Some(256))")]
+    pub sample: String,
+
+    /// 状态: stored / blocked / pending / approved / rejected
+    #[sea_orm(column_type = "String(Some(16))")]
+    pub status: String,
+
+    /// 暂存目录中的文件名, 不需审批时为空
+    #[sea_orm(column_type = "String(Some(64))")]
+    pub hold_file: String,
+
+    /// 审批的审计员
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub reviewed_by: String,
+
+    /// 审批时间 (Unix 时间戳, 0 = 未审批)
+    pub review_time: i64,
+
+    /// 检出时间 (Unix 时间戳)
+    pub create_time: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
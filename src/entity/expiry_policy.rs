@@ -0,0 +1,43 @@
+//! ExpiryPolicy entity - 文件夹过期策略表
+//!
+//! 文件夹中超过保留天数未修改的内容由后台任务移入回收站, 删除前提前通知所有者
+//! 表名: disk_expiry_policy
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_expiry_policy")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 所有者用户名
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 文件夹路径 (相对用户根目录, 以 / 开头)
+    #[sea_orm(column_type = "String(Some(1024))")]
+    pub path: String,
+
+    /// 内容保留天数
+    pub max_age_days: i32,
+
+    /// 删除前提前通知的天数
+    pub notice_days: i32,
+
+    /// 设置策略的用户
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub created_by: String,
+
+    /// 创建或最后修改时间 (Unix 时间戳)
+    pub update_time: i64,
+
+    /// 最后一次通知时间 (Unix 时间戳, 0 表示未通知)
+    pub notice_time: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
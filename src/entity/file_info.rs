@@ -42,6 +42,10 @@ pub struct Model {
 
     /// 是否为目录
     pub is_directory: bool,
+
+    /// 内容的 SHA-256 (十六进制)，上传时计算，用于秒传
+    #[sea_orm(column_type = "String(Some(64))", nullable)]
+    pub sha256: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
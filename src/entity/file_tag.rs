@@ -0,0 +1,33 @@
+//! FileTag entity - 文件标签表
+//!
+//! 表名: disk_file_tag
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_file_tag")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 所有者用户名
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 文件路径 (相对用户根目录, 以 / 开头)
+    #[sea_orm(column_type = "String(Some(1024))")]
+    pub path: String,
+
+    /// 标签
+    #[sea_orm(column_type = "String(Some(64))")]
+    pub tag: String,
+
+    /// 创建时间 (Unix 时间戳)
+    pub create_time: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
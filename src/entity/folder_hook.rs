@@ -0,0 +1,46 @@
+//! FolderHook entity - 文件夹钩子表
+//!
+//! 文件落入文件夹时执行的动作：调用 Webhook、通知群组、解压压缩包或运行配置的命令
+//! 表名: disk_folder_hook
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_folder_hook")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 文件夹所属的目录树 (用户名或部门/群组空间)
+    #[sea_orm(column_type = "String(Some(64))")]
+    pub owner: String,
+
+    /// 文件夹在目录树中的路径 ("/" 开头)
+    #[sea_orm(column_type = "Text")]
+    pub path: String,
+
+    /// 文件夹在创建者文件 API 中的路径
+    #[sea_orm(column_type = "Text")]
+    pub shown_path: String,
+
+    /// 动作: webhook, notify, extract 或 command
+    #[sea_orm(column_type = "String(Some(16))")]
+    pub action: String,
+
+    /// 动作的目标: Webhook 地址、群组名称或命令名称, extract 为空
+    #[sea_orm(column_type = "Text")]
+    pub target: String,
+
+    /// 创建者用户名
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub created_by: String,
+
+    /// 创建时间 (Unix 时间戳)
+    pub create_time: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -15,6 +15,10 @@ pub struct Model {
     /// 群组名称 (最大32字符)
     #[sea_orm(column_type = "String(Some(32))", unique)]
     pub name: String,
+
+    /// 普通成员能否修改群组空间中的文件 (所有者总是可以)
+    #[sea_orm(default_value = true)]
+    pub member_write: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
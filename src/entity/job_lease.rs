@@ -0,0 +1,33 @@
+//! JobLease entity - 任务租约表
+//!
+//! 多个实例共用数据库时，维护任务由持有租约的实例执行，每次计划执行只运行一次
+//! 表名: disk_job_lease
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_job_lease")]
+pub struct Model {
+    /// 任务名称
+    #[sea_orm(primary_key, auto_increment = false, column_type = "String(Some(64))")]
+    pub name: String,
+
+    /// 持有租约的实例, 未被持有过时为空
+    #[sea_orm(column_type = "String(Some(128))")]
+    pub holder: String,
+
+    /// 最近一次取得租约所执行的计划时间 (Unix 时间戳)
+    pub slot: i64,
+
+    /// 取得租约的时间 (Unix 时间戳)
+    pub acquired_at: i64,
+
+    /// 租约到期时间 (Unix 时间戳), 到期或释放后其他实例可以取得
+    pub expires_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
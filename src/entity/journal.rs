@@ -0,0 +1,40 @@
+//! Journal entity - 文件操作日志表
+//!
+//! 多步骤的文件操作 (上传完成, 移动, 从回收站还原) 执行前记录意图, 完成后删除.
+//! 启动时仍在表中的记录属于被中断的操作, 根据磁盘状态继续完成或撤销
+//! 表名: disk_journal
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_journal")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 操作类型: upload, move, restore
+    #[sea_orm(column_type = "String(Some(16))")]
+    pub op: String,
+
+    /// 操作的参数 (JSON)
+    #[sea_orm(column_type = "Text")]
+    pub intent: String,
+
+    /// 已完成的步骤
+    #[sea_orm(column_type = "String(Some(16))")]
+    pub step: String,
+
+    /// 记录时间 (Unix 时间戳)
+    pub create_time: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// 尚未改动文件
+pub const STEP_STARTED: &str = "started";
+/// 移动时已复制完成, 尚未删除源文件
+pub const STEP_COPIED: &str = "copied";
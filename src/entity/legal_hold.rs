@@ -0,0 +1,38 @@
+//! LegalHold entity - 法律保留表
+//!
+//! 处于保留状态的文件/目录及其下的所有内容不可删除、覆盖、重命名或从回收站清除
+//! 表名: disk_legal_hold
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_legal_hold")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 所有者用户名
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 文件路径 (相对用户根目录, 以 / 开头)
+    #[sea_orm(column_type = "String(Some(1024))")]
+    pub path: String,
+
+    /// 保留原因
+    #[sea_orm(column_type = "String(Some(512))")]
+    pub reason: String,
+
+    /// 设置保留的管理员
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub created_by: String,
+
+    /// 创建时间 (Unix 时间戳)
+    pub create_time: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
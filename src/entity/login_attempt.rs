@@ -0,0 +1,36 @@
+//! LoginAttempt entity - 登录失败记录表
+//!
+//! 记录每个用户连续登录失败的次数，达到上限后锁定账号一段时间
+//! 表名: disk_login_attempt
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_login_attempt")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 用户名
+    #[sea_orm(column_type = "String(Some(32))", unique)]
+    pub username: String,
+
+    /// 统计窗口内连续失败次数
+    pub failures: i32,
+
+    /// 统计窗口内首次失败时间 (Unix 时间戳)
+    pub first_failure: i64,
+
+    /// 最近一次失败的IP
+    #[sea_orm(column_type = "String(Some(64))", nullable)]
+    pub last_ip: Option<String>,
+
+    /// 锁定截止时间 (Unix 时间戳, 0 = 未锁定)
+    pub locked_until: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -2,11 +2,31 @@
 //!
 //! 包含所有数据库表对应的实体模型
 
+pub mod api_token;
 pub mod casbin_rule;
+pub mod cold_file;
 pub mod department;
+pub mod device;
+pub mod digest;
+pub mod dlp_detection;
+pub mod expiry_policy;
 pub mod file_access;
 pub mod file_info;
+pub mod file_tag;
+pub mod folder_hook;
 pub mod group;
 pub mod group_user;
+pub mod job_lease;
+pub mod journal;
+pub mod legal_hold;
+pub mod login_attempt;
+pub mod op_journal;
 pub mod op_log;
+pub mod sensitive_folder;
+pub mod session;
+pub mod task;
+pub mod traffic;
+pub mod trash;
 pub mod user;
+pub mod watch;
+pub mod watch_event;
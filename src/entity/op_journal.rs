@@ -0,0 +1,35 @@
+//! Op journal entity - 可撤销操作表
+//!
+//! 网页上的重命名, 移动和删除 (移入回收站) 完成后记录撤销所需的信息,
+//! 用户可在短时间内撤销自己最近的一次操作. 与 journal 不同, 记录在操作完成后写入
+//! 表名: disk_op_journal
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_op_journal")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 执行操作的用户
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 操作类型: rename, move, delete
+    #[sea_orm(column_type = "String(Some(16))")]
+    pub op: String,
+
+    /// 撤销所需的信息 (JSON)
+    #[sea_orm(column_type = "Text")]
+    pub detail: String,
+
+    /// 操作时间 (Unix 时间戳)
+    pub create_time: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -7,7 +7,9 @@ use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
 /// 操作类型
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// 日志中保存的是 [`code`](OpType::code)，显示名称在查询时按语言解析。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum OpType {
     /// 登录
     Login,
@@ -29,31 +31,33 @@ pub enum OpType {
     Upload,
     /// 下载
     Download,
-    /// 创建用户
+    /// 还原
+    Restore,
+    /// 彻底删除
+    Purge,
+    /// 创建用户信息
     CreateUser,
-    /// 更新用户
+    /// 修改用户信息
     UpdateUser,
-    /// 删除用户
+    /// 删除用户信息
     DeleteUser,
+    /// 查询用户信息
+    QueryUser,
     /// 启用用户
     EnableUser,
     /// 禁用用户
     DisableUser,
-    /// 导出用户
-    ExportUser,
-    /// 查询用户
-    QueryUser,
     /// 修改密码
     UpdatePassword,
-    /// 创建部门
+    /// 创建部门信息
     CreateDept,
-    /// 更新部门
+    /// 修改部门信息
     UpdateDept,
-    /// 删除部门
+    /// 删除部门信息
     DeleteDept,
-    /// 查询部门
+    /// 查询部门信息
     QueryDept,
-    /// 创建群组
+    /// 添加群组
     CreateGroup,
     /// 删除群组
     DeleteGroup,
@@ -63,47 +67,268 @@ pub enum OpType {
     AddGroupUser,
     /// 删除群组用户
     DeleteGroupUser,
-    /// 统计
-    Stat,
-    /// 更新统计
-    UpdateStat,
+    /// 查询群组用户
+    QueryGroupUser,
+    /// 设置群组空间权限
+    SetMemberWrite,
+    /// 创建角色
+    CreateRole,
+    /// 修改角色
+    UpdateRole,
+    /// 删除角色
+    DeleteRole,
+    /// 创建服务账号
+    CreateServiceAccount,
+    /// 删除服务账号
+    DeleteServiceAccount,
+    /// 创建访问令牌
+    CreateToken,
+    /// 撤销访问令牌
+    RevokeToken,
+    /// 设置法律保留
+    SetLegalHold,
+    /// 解除法律保留
+    ReleaseLegalHold,
+    /// 设置过期策略
+    SetExpiry,
+    /// 删除过期策略
+    RemoveExpiry,
+    /// 过期清理
+    Expire,
+    /// 清理临时文件
+    CleanArtifacts,
+    /// 删除日志
+    DeleteLog,
+    /// HR同步
+    HrSync,
+    /// 异常行为告警
+    AbuseAlert,
+    /// 解除限速
+    ReleaseThrottle,
+    /// 锁定账号
+    LockUser,
+    /// 解锁账号
+    UnlockUser,
+    /// 运行维护任务
+    RunJob,
+    /// 压缩
+    Compress,
+    /// 解压
+    Extract,
+    /// 设置任务限速
+    SetTaskLimit,
+    /// 移除已记住的设备
+    RevokeDevice,
+    /// 发现扫描行为
+    ScannerDetected,
+    /// 标记敏感文件夹
+    SetSensitive,
+    /// 取消敏感文件夹
+    UnsetSensitive,
+    /// 数据防泄漏检出
+    DlpDetected,
+    /// 审批通过上传
+    DlpApprove,
+    /// 驳回上传
+    DlpReject,
+    /// 添加文件夹钩子
+    AddHook,
+    /// 删除文件夹钩子
+    RemoveHook,
+    /// 编辑文件
+    EditFile,
+    /// 新建文件
+    CreateFile,
+}
+
+/// 显示语言
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Lang {
+    #[default]
+    Zh,
+    En,
+}
+
+impl Lang {
+    /// 解析语言参数 ("zh", "en", "en-US" ...)，未知时使用中文
+    pub fn parse(lang: Option<&str>) -> Self {
+        match lang {
+            Some(lang) if lang.to_ascii_lowercase().starts_with("en") => Lang::En,
+            _ => Lang::Zh,
+        }
+    }
 }
 
 impl OpType {
-    /// 转换为中文显示
-    pub fn to_chinese(&self) -> &'static str {
+    pub const ALL: [OpType; 64] = [
+        OpType::Login,
+        OpType::Logout,
+        OpType::Mkdir,
+        OpType::OpenFile,
+        OpType::Delete,
+        OpType::Rename,
+        OpType::Copy,
+        OpType::Move,
+        OpType::Upload,
+        OpType::Download,
+        OpType::Restore,
+        OpType::Purge,
+        OpType::CreateUser,
+        OpType::UpdateUser,
+        OpType::DeleteUser,
+        OpType::QueryUser,
+        OpType::EnableUser,
+        OpType::DisableUser,
+        OpType::UpdatePassword,
+        OpType::CreateDept,
+        OpType::UpdateDept,
+        OpType::DeleteDept,
+        OpType::QueryDept,
+        OpType::CreateGroup,
+        OpType::DeleteGroup,
+        OpType::QueryGroup,
+        OpType::AddGroupUser,
+        OpType::DeleteGroupUser,
+        OpType::QueryGroupUser,
+        OpType::SetMemberWrite,
+        OpType::CreateRole,
+        OpType::UpdateRole,
+        OpType::DeleteRole,
+        OpType::CreateServiceAccount,
+        OpType::DeleteServiceAccount,
+        OpType::CreateToken,
+        OpType::RevokeToken,
+        OpType::SetLegalHold,
+        OpType::ReleaseLegalHold,
+        OpType::SetExpiry,
+        OpType::RemoveExpiry,
+        OpType::Expire,
+        OpType::CleanArtifacts,
+        OpType::DeleteLog,
+        OpType::HrSync,
+        OpType::AbuseAlert,
+        OpType::ReleaseThrottle,
+        OpType::LockUser,
+        OpType::UnlockUser,
+        OpType::RunJob,
+        OpType::Compress,
+        OpType::Extract,
+        OpType::SetTaskLimit,
+        OpType::RevokeDevice,
+        OpType::ScannerDetected,
+        OpType::SetSensitive,
+        OpType::UnsetSensitive,
+        OpType::DlpDetected,
+        OpType::DlpApprove,
+        OpType::DlpReject,
+        OpType::AddHook,
+        OpType::RemoveHook,
+        OpType::EditFile,
+        OpType::CreateFile,
+    ];
+
+    /// 代码、中文名称和英文名称
+    ///
+    /// 中文名称是旧版本直接写入日志的值，迁移时据此转换为代码，不能修改。
+    fn names(self) -> (&'static str, &'static str, &'static str) {
         match self {
-            OpType::Login => "登录",
-            OpType::Logout => "登出",
-            OpType::Mkdir => "创建目录",
-            OpType::OpenFile => "访问目录/文件",
-            OpType::Delete => "删除",
-            OpType::Rename => "重命名",
-            OpType::Copy => "复制",
-            OpType::Move => "移动",
-            OpType::Upload => "上传",
-            OpType::Download => "下载",
-            OpType::CreateUser => "创建用户",
-            OpType::UpdateUser => "更新用户",
-            OpType::DeleteUser => "删除用户",
-            OpType::EnableUser => "启用用户",
-            OpType::DisableUser => "禁用用户",
-            OpType::ExportUser => "导出用户",
-            OpType::QueryUser => "查询用户",
-            OpType::UpdatePassword => "修改密码",
-            OpType::CreateDept => "创建部门",
-            OpType::UpdateDept => "更新部门",
-            OpType::DeleteDept => "删除部门",
-            OpType::QueryDept => "查询部门",
-            OpType::CreateGroup => "创建群组",
-            OpType::DeleteGroup => "删除群组",
-            OpType::QueryGroup => "查询群组",
-            OpType::AddGroupUser => "添加群组用户",
-            OpType::DeleteGroupUser => "删除群组用户",
-            OpType::Stat => "统计",
-            OpType::UpdateStat => "更新统计",
+            OpType::Login => ("login", "登录", "Log in"),
+            OpType::Logout => ("logout", "登出", "Log out"),
+            OpType::Mkdir => ("mkdir", "创建目录", "Create folder"),
+            OpType::OpenFile => ("open_file", "访问目录/文件", "Open file or folder"),
+            OpType::Delete => ("delete", "删除", "Delete"),
+            OpType::Rename => ("rename", "重命名", "Rename"),
+            OpType::Copy => ("copy", "复制", "Copy"),
+            OpType::Move => ("move", "移动", "Move"),
+            OpType::Upload => ("upload", "上传", "Upload"),
+            OpType::Download => ("download", "下载", "Download"),
+            OpType::Restore => ("restore", "还原", "Restore from trash"),
+            OpType::Purge => ("purge", "彻底删除", "Delete permanently"),
+            OpType::CreateUser => ("create_user", "创建用户信息", "Create user"),
+            OpType::UpdateUser => ("update_user", "修改用户信息", "Update user"),
+            OpType::DeleteUser => ("delete_user", "删除用户信息", "Delete user"),
+            OpType::QueryUser => ("query_user", "查询用户信息", "Query users"),
+            OpType::EnableUser => ("enable_user", "启用用户", "Enable user"),
+            OpType::DisableUser => ("disable_user", "禁用用户", "Disable user"),
+            OpType::UpdatePassword => ("update_password", "修改密码", "Change password"),
+            OpType::CreateDept => ("create_dept", "创建部门信息", "Create department"),
+            OpType::UpdateDept => ("update_dept", "修改部门信息", "Update department"),
+            OpType::DeleteDept => ("delete_dept", "删除部门信息", "Delete department"),
+            OpType::QueryDept => ("query_dept", "查询部门信息", "Query departments"),
+            OpType::CreateGroup => ("create_group", "添加群组", "Create group"),
+            OpType::DeleteGroup => ("delete_group", "删除群组", "Delete group"),
+            OpType::QueryGroup => ("query_group", "查询群组", "Query groups"),
+            OpType::AddGroupUser => ("add_group_user", "添加群组用户", "Add group member"),
+            OpType::DeleteGroupUser => ("delete_group_user", "删除群组用户", "Remove group member"),
+            OpType::QueryGroupUser => ("query_group_user", "查询群组用户", "Query group members"),
+            OpType::SetMemberWrite => ("set_member_write", "设置群组空间权限", "Set group space access"),
+            OpType::CreateRole => ("create_role", "创建角色", "Create role"),
+            OpType::UpdateRole => ("update_role", "修改角色", "Update role"),
+            OpType::DeleteRole => ("delete_role", "删除角色", "Delete role"),
+            OpType::CreateServiceAccount => ("create_service_account", "创建服务账号", "Create service account"),
+            OpType::DeleteServiceAccount => ("delete_service_account", "删除服务账号", "Delete service account"),
+            OpType::CreateToken => ("create_token", "创建访问令牌", "Create access token"),
+            OpType::RevokeToken => ("revoke_token", "撤销访问令牌", "Revoke access token"),
+            OpType::SetLegalHold => ("set_legal_hold", "设置法律保留", "Set legal hold"),
+            OpType::ReleaseLegalHold => ("release_legal_hold", "解除法律保留", "Release legal hold"),
+            OpType::SetExpiry => ("set_expiry", "设置过期策略", "Set expiry policy"),
+            OpType::RemoveExpiry => ("remove_expiry", "删除过期策略", "Remove expiry policy"),
+            OpType::Expire => ("expire", "过期清理", "Expiry cleanup"),
+            OpType::CleanArtifacts => ("clean_artifacts", "清理临时文件", "Clean temporary files"),
+            OpType::DeleteLog => ("delete_log", "删除日志", "Delete logs"),
+            OpType::HrSync => ("hr_sync", "HR同步", "HR sync"),
+            OpType::AbuseAlert => ("abuse_alert", "异常行为告警", "Abuse alert"),
+            OpType::ReleaseThrottle => ("release_throttle", "解除限速", "Release throttle"),
+            OpType::LockUser => ("lock_user", "锁定账号", "Lock account"),
+            OpType::UnlockUser => ("unlock_user", "解锁账号", "Unlock account"),
+            OpType::RunJob => ("run_job", "运行维护任务", "Run maintenance job"),
+            OpType::Compress => ("compress", "压缩", "Compress"),
+            OpType::Extract => ("extract", "解压", "Extract"),
+            OpType::SetTaskLimit => ("set_task_limit", "设置任务限速", "Set task throughput limit"),
+            OpType::RevokeDevice => ("revoke_device", "移除已记住的设备", "Revoke remembered device"),
+            OpType::ScannerDetected => ("scanner_detected", "发现扫描行为", "Scanner detected"),
+            OpType::SetSensitive => ("set_sensitive", "标记敏感文件夹", "Mark folder sensitive"),
+            OpType::UnsetSensitive => ("unset_sensitive", "取消敏感文件夹", "Unmark sensitive folder"),
+            OpType::DlpDetected => ("dlp_detected", "数据防泄漏检出", "DLP rule matched"),
+            OpType::DlpApprove => ("dlp_approve", "审批通过上传", "Approve held upload"),
+            OpType::DlpReject => ("dlp_reject", "驳回上传", "Reject held upload"),
+            OpType::AddHook => ("add_hook", "添加文件夹钩子", "Add folder hook"),
+            OpType::RemoveHook => ("remove_hook", "删除文件夹钩子", "Remove folder hook"),
+            OpType::EditFile => ("edit_file", "编辑文件", "Edit file"),
+            OpType::CreateFile => ("create_file", "新建文件", "Create file"),
+        }
+    }
+
+    /// 保存在日志中的代码
+    pub fn code(self) -> &'static str {
+        self.names().0
+    }
+
+    /// 按代码查找
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|op| op.code() == code)
+    }
+
+    /// 显示名称
+    pub fn label(self, lang: Lang) -> &'static str {
+        match lang {
+            Lang::Zh => self.names().1,
+            Lang::En => self.names().2,
         }
     }
+
+    /// 转换为中文显示
+    pub fn to_chinese(self) -> &'static str {
+        self.label(Lang::Zh)
+    }
+}
+
+/// 日志中操作类型的显示名称，未知的代码原样显示
+pub fn op_type_label(code: &str, lang: Lang) -> String {
+    match OpType::from_code(code) {
+        Some(op) => op.label(lang).to_string(),
+        None => code.to_string(),
+    }
 }
 
 /// 操作结果
@@ -154,8 +379,21 @@ pub struct Model {
     /// 操作者IP
     #[sea_orm(column_type = "String(Some(64))", nullable)]
     pub ip: Option<String>,
+
+    /// 日志类别: general=普通操作, admin=管理操作
+    #[sea_orm(column_type = "String(Some(16))", default_value = "general")]
+    pub category: String,
+
+    /// 关联ID: 同一批量操作产生的日志相同
+    #[sea_orm(column_type = "String(Some(36))", nullable)]
+    pub correlation_id: Option<String>,
 }
 
+/// 普通操作日志类别 (文件操作、登录等)
+pub const CATEGORY_GENERAL: &str = "general";
+/// 管理操作日志类别 (用户、部门、角色管理等)
+pub const CATEGORY_ADMIN: &str = "admin";
+
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {}
 
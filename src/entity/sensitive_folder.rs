@@ -0,0 +1,34 @@
+//! SensitiveFolder entity - 敏感文件夹表
+//!
+//! 敏感文件夹及其下的图片和 PDF 在预览和下载时加上查看者用户名和时间的水印
+//! 表名: disk_sensitive_folder
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_sensitive_folder")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 所有者用户名 (用户或部门/群组空间)
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 文件夹路径 (相对用户根目录, 以 / 开头)
+    #[sea_orm(column_type = "String(Some(1024))")]
+    pub path: String,
+
+    /// 标记的用户
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub created_by: String,
+
+    /// 创建时间 (Unix 时间戳)
+    pub create_time: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,27 @@
+//! Session entity - 登录会话表
+//!
+//! 数据库会话存储使用, 服务重启或多实例部署时会话不丢失
+//! 表名: disk_session
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_session")]
+pub struct Model {
+    /// 会话 ID (Cookie 中的值)
+    #[sea_orm(primary_key, auto_increment = false, column_type = "String(Some(32))")]
+    pub id: String,
+
+    /// 会话数据 (JSON)
+    #[sea_orm(column_type = "Text")]
+    pub data: String,
+
+    /// 过期时间 (Unix 时间戳)
+    pub expiry_date: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,84 @@
+//! Task entity - 任务记录表
+//!
+//! 持久化后台任务 (复制/移动/打包) 的状态, 服务重启后任务列表不丢失,
+//! 已结束的任务同时用于任务历史查询和统计
+//! 表名: disk_task
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize, utoipa::ToSchema)]
+#[sea_orm(table_name = "disk_task")]
+#[schema(as = TaskRecord)]
+pub struct Model {
+    /// 任务 ID (UUID)
+    #[sea_orm(primary_key, auto_increment = false, column_type = "String(Some(36))")]
+    pub id: String,
+
+    /// 所属用户 ID
+    pub user_id: i64,
+
+    /// 任务来源: web / webdav 等
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub agent: String,
+
+    /// 任务类型: copy / move / archive
+    #[sea_orm(column_type = "String(Some(16))")]
+    pub task_type: String,
+
+    /// 任务状态: pending / queued / starting / running / suspended / completed / failed / cancelled
+    #[sea_orm(column_type = "String(Some(16))")]
+    pub status: String,
+
+    /// 源目录
+    #[sea_orm(column_type = "String(Some(512))")]
+    pub source: String,
+
+    /// 目标目录
+    #[sea_orm(column_type = "String(Some(512))")]
+    pub target: String,
+
+    /// 文件列表 (JSON 数组)
+    #[sea_orm(column_type = "Text")]
+    pub files: String,
+
+    /// 文件总数
+    pub total_files: i64,
+
+    /// 已处理文件数
+    pub copied_files: i64,
+
+    /// 总大小 (字节)
+    pub total_size: i64,
+
+    /// 已处理大小 (字节)
+    pub copied_size: i64,
+
+    /// 错误信息
+    #[sea_orm(column_type = "Text", nullable)]
+    pub error: Option<String>,
+
+    /// 处理失败的文件及原因 (JSON 数组)
+    #[sea_orm(column_type = "Text", nullable)]
+    pub failed_files: Option<String>,
+
+    /// 创建时间 (Unix 时间戳)
+    pub created_at: i64,
+
+    /// 开始时间 (Unix 时间戳, 0 表示未开始)
+    pub started_at: i64,
+
+    /// 更新时间 (Unix 时间戳)
+    pub updated_at: i64,
+
+    /// 结束时间 (Unix 时间戳, 0 表示未结束)
+    pub finished_at: i64,
+
+    /// 是否已从任务列表中移除 (仍保留在任务历史中)
+    pub dismissed: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
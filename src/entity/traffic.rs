@@ -0,0 +1,32 @@
+//! Traffic entity - 用户每日流量统计表
+//!
+//! 表名: disk_traffic
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_traffic")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 用户名
+    #[sea_orm(column_type = "String(Some(64))")]
+    pub username: String,
+
+    /// 日期 (UTC, YYYY-MM-DD)
+    #[sea_orm(column_type = "String(Some(10))")]
+    pub day: String,
+
+    /// 上传字节数
+    pub upload_bytes: i64,
+
+    /// 下载字节数
+    pub download_bytes: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,44 @@
+//! Trash entity - 回收站表
+//!
+//! 删除的文件/目录移动到用户回收站目录, 此表记录其原始位置
+//! 表名: disk_trash
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_trash")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 所有者用户名
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 原文件/目录名称
+    #[sea_orm(column_type = "String(Some(256))")]
+    pub name: String,
+
+    /// 原父目录路径 (相对用户根目录, 空字符串表示根目录)
+    #[sea_orm(column_type = "String(Some(512))")]
+    pub original_path: String,
+
+    /// 回收站中的存储名称
+    #[sea_orm(column_type = "String(Some(64))")]
+    pub trash_name: String,
+
+    /// 是否为目录
+    pub is_directory: bool,
+
+    /// 大小 (字节, 目录为累计大小)
+    pub size: i64,
+
+    /// 删除时间 (Unix 时间戳)
+    pub delete_time: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -81,6 +81,22 @@ pub struct Model {
     /// 用户权限 (已弃用，权限现由 Casbin 管理，保留此字段用于向后兼容)
     #[sea_orm(column_type = "String(Some(128))", default_value = "")]
     pub permissions: String,
+
+    /// 账号类型: normal=普通用户, service=服务账号 (仅支持 API Token 认证)
+    #[sea_orm(column_type = "String(Some(16))", default_value = "normal")]
+    pub account_type: String,
+}
+
+/// 普通用户账号类型
+pub const ACCOUNT_TYPE_NORMAL: &str = "normal";
+/// 服务账号类型
+pub const ACCOUNT_TYPE_SERVICE: &str = "service";
+
+impl Model {
+    /// 是否为服务账号
+    pub fn is_service_account(&self) -> bool {
+        self.account_type == ACCOUNT_TYPE_SERVICE
+    }
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
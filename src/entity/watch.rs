@@ -0,0 +1,44 @@
+//! Watch entity - 关注文件夹表
+//!
+//! 用户关注的文件夹，其中的任何变动都会生成一条 [`watch_event`](super::watch_event)
+//! 表名: disk_watch
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_watch")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 关注者用户ID
+    pub user_id: i64,
+
+    /// 关注者用户名
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 文件夹所属的目录树 (用户名或部门/群组空间)
+    #[sea_orm(column_type = "String(Some(64))")]
+    pub owner: String,
+
+    /// 文件夹在目录树中的路径 ("/" 开头)
+    #[sea_orm(column_type = "Text")]
+    pub path: String,
+
+    /// 文件夹在关注者文件 API 中的路径
+    #[sea_orm(column_type = "Text")]
+    pub shown_path: String,
+
+    /// 是否加入邮件摘要
+    pub digest: bool,
+
+    /// 创建时间 (Unix 时间戳)
+    pub create_time: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,48 @@
+//! WatchEvent entity - 关注文件夹变动记录表
+//!
+//! 关注的文件夹中发生变动时为关注者生成的通知
+//! 表名: disk_watch_event
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "disk_watch_event")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    /// 关注记录ID
+    pub watch_id: i64,
+
+    /// 关注者用户名
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub username: String,
+
+    /// 变动的文件在关注者文件 API 中的路径
+    #[sea_orm(column_type = "Text")]
+    pub path: String,
+
+    /// 变动类型: create, update, delete, rename, move, restore
+    #[sea_orm(column_type = "String(Some(16))")]
+    pub kind: String,
+
+    /// 执行变动的用户
+    #[sea_orm(column_type = "String(Some(32))")]
+    pub actor: String,
+
+    /// 变动来源: web, webdav
+    #[sea_orm(column_type = "String(Some(16))")]
+    pub client: String,
+
+    /// 变动时间 (Unix 时间戳)
+    pub create_time: i64,
+
+    /// 是否已读
+    pub read: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -103,6 +103,12 @@ impl IntoResponse for AppError {
 /// Result type alias for application
 pub type AppResult<T> = Result<T, AppError>;
 
+/// `{"error": message}` response of handlers serving file content, which
+/// answer with the content itself on success rather than an API envelope
+pub fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(serde_json::json!({"error": message}))).into_response()
+}
+
 /// Helper trait for converting Option to AppError::NotFound
 pub trait OptionExt<T> {
     fn ok_or_not_found(self, msg: impl Into<String>) -> AppResult<T>;
@@ -0,0 +1,221 @@
+//! File name policy
+//!
+//! Rules for names and paths sent by clients:
+//! - [`check_name`] / [`check_path`] reject names that can't safely reference
+//!   a file: separators, dot segments, control and Windows-restricted
+//!   characters, and names or paths over the length limits
+//! - [`check_new_name`] additionally rejects reserved names and returns the
+//!   name in Unicode NFC form, so a file uploaded from macOS (which sends
+//!   decomposed names) and one created on Windows end up with the same name
+//! - [`check_upload_type`] rejects uploads of the blocked extensions
+//!
+//! The reserved names, blocked extensions and limits come from `[filename]` in the configuration,
+//! installed once at startup with [`init`].
+
+use std::fmt;
+use std::sync::OnceLock;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config::FilenameConfig;
+
+/// Characters Windows doesn't allow in names
+const RESTRICTED_CHARS: &str = "<>:\"/\\|?*";
+
+static POLICY: OnceLock<FilenameConfig> = OnceLock::new();
+
+/// Install the configured policy
+pub fn init(config: &FilenameConfig) {
+    if POLICY.set(config.clone()).is_err() {
+        tracing::warn!("File name policy already initialized");
+    }
+}
+
+fn policy() -> &'static FilenameConfig {
+    POLICY.get_or_init(FilenameConfig::default)
+}
+
+/// Why a name or path was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+    Empty,
+    /// ".", ".." or other names made of dots only
+    DotName,
+    InvalidChar(char),
+    ControlChar,
+    Reserved(String),
+    /// Longer than the limit (bytes)
+    NameTooLong(usize),
+    /// Longer than the limit (bytes)
+    PathTooLong(usize),
+    /// Extension of files that can't be uploaded
+    Blocked(String),
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Empty => write!(f, "名称不能为空"),
+            NameError::DotName => write!(f, "名称不能只由点组成"),
+            NameError::InvalidChar(c) => write!(f, "名称不能包含字符 {}", c),
+            NameError::ControlChar => write!(f, "名称不能包含控制字符"),
+            NameError::Reserved(name) => write!(f, "{} 是系统保留名称", name),
+            NameError::NameTooLong(max) => write!(f, "名称过长, 最多 {} 字节", max),
+            NameError::PathTooLong(max) => write!(f, "路径过长, 最多 {} 字节", max),
+            NameError::Blocked(ext) => write!(f, "不允许上传 .{} 文件", ext),
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+/// Unicode NFC form of a name
+pub fn normalize(name: &str) -> String {
+    name.nfc().collect()
+}
+
+fn check_name_with(config: &FilenameConfig, name: &str) -> Result<(), NameError> {
+    if name.is_empty() {
+        return Err(NameError::Empty);
+    }
+    if name.chars().all(|c| c == '.') {
+        return Err(NameError::DotName);
+    }
+    if let Some(c) = name.chars().find(|c| RESTRICTED_CHARS.contains(*c)) {
+        return Err(NameError::InvalidChar(c));
+    }
+    if name.chars().any(|c| c.is_control()) {
+        return Err(NameError::ControlChar);
+    }
+    if name.len() > config.max_name_length {
+        return Err(NameError::NameTooLong(config.max_name_length));
+    }
+    Ok(())
+}
+
+fn check_path_with(config: &FilenameConfig, path: &str) -> Result<(), NameError> {
+    if path.len() > config.max_path_length {
+        return Err(NameError::PathTooLong(config.max_path_length));
+    }
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        if segment.chars().all(|c| c == '.') {
+            return Err(NameError::DotName);
+        }
+        if segment.chars().any(|c| c.is_control()) {
+            return Err(NameError::ControlChar);
+        }
+        if segment.len() > config.max_name_length {
+            return Err(NameError::NameTooLong(config.max_name_length));
+        }
+    }
+    Ok(())
+}
+
+fn check_new_name_with(config: &FilenameConfig, name: &str) -> Result<String, NameError> {
+    let name = normalize(name);
+    check_name_with(config, &name)?;
+
+    // Windows reserves device names with any extension ("con.txt")
+    let stem = name.split('.').next().unwrap_or("").trim_end();
+    if let Some(reserved) = config
+        .reserved_names
+        .iter()
+        .find(|r| r.eq_ignore_ascii_case(stem))
+    {
+        return Err(NameError::Reserved(reserved.clone()));
+    }
+    // Windows drops trailing dots and spaces, the names wouldn't round-trip
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err(NameError::InvalidChar(name.chars().last().unwrap()));
+    }
+    Ok(name)
+}
+
+fn check_upload_type_with(config: &FilenameConfig, name: &str) -> Result<(), NameError> {
+    let Some((_, ext)) = name.rsplit_once('.') else {
+        return Ok(());
+    };
+    match config.blocked_extensions.iter().find(|b| b.trim_start_matches('.').eq_ignore_ascii_case(ext)) {
+        Some(_) => Err(NameError::Blocked(ext.to_lowercase())),
+        None => Ok(()),
+    }
+}
+
+/// Check a single name referencing a file (no separators)
+pub fn check_name(name: &str) -> Result<(), NameError> {
+    check_name_with(policy(), name)
+}
+
+/// Check a path relative to the user root ("" and "/" are the root)
+pub fn check_path(path: &str) -> Result<(), NameError> {
+    check_path_with(policy(), path)
+}
+
+/// Check the name of a file or folder about to be created, returning it normalized
+pub fn check_new_name(name: &str) -> Result<String, NameError> {
+    check_new_name_with(policy(), name)
+}
+
+/// Check whether a file of this name may be uploaded
+pub fn check_upload_type(name: &str) -> Result<(), NameError> {
+    check_upload_type_with(policy(), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_name() {
+        let config = FilenameConfig::default();
+        let check = |name: &str| check_name_with(&config, name);
+        assert_eq!(check("file.txt"), Ok(()));
+        assert_eq!(check("报告 2024.docx"), Ok(()));
+        assert_eq!(check(""), Err(NameError::Empty));
+        assert_eq!(check(".."), Err(NameError::DotName));
+        assert_eq!(check("a/b"), Err(NameError::InvalidChar('/')));
+        assert_eq!(check("a:b"), Err(NameError::InvalidChar(':')));
+        assert_eq!(check("a\tb"), Err(NameError::ControlChar));
+        assert_eq!(check(&"a".repeat(256)), Err(NameError::NameTooLong(255)));
+        // Limits count bytes, not characters
+        assert_eq!(check(&"文".repeat(86)), Err(NameError::NameTooLong(255)));
+    }
+
+    #[test]
+    fn test_check_path() {
+        let config = FilenameConfig { max_path_length: 16, ..FilenameConfig::default() };
+        let check = |path: &str| check_path_with(&config, path);
+        assert_eq!(check(""), Ok(()));
+        assert_eq!(check("/"), Ok(()));
+        assert_eq!(check("/a/b//c/"), Ok(()));
+        assert_eq!(check("a/./b"), Err(NameError::DotName));
+        assert_eq!(check("../a"), Err(NameError::DotName));
+        assert_eq!(check("a/\u{0}b"), Err(NameError::ControlChar));
+        assert_eq!(check("/aaaa/bbbb/cccc/d"), Err(NameError::PathTooLong(16)));
+    }
+
+    #[test]
+    fn test_check_new_name() {
+        let config = FilenameConfig::default();
+        let check = |name: &str| check_new_name_with(&config, name);
+        // Decomposed "é" (e + combining acute) is stored composed
+        assert_eq!(check("Cafe\u{301}.txt"), Ok("Caf\u{e9}.txt".to_string()));
+        assert_eq!(check("con"), Err(NameError::Reserved("CON".to_string())));
+        assert_eq!(check("Com1.log"), Err(NameError::Reserved("COM1".to_string())));
+        assert_eq!(check("console.txt"), Ok("console.txt".to_string()));
+        assert_eq!(check("name."), Err(NameError::InvalidChar('.')));
+        assert_eq!(check("name "), Err(NameError::InvalidChar(' ')));
+
+        let config = FilenameConfig { reserved_names: vec![], ..FilenameConfig::default() };
+        assert_eq!(check_new_name_with(&config, "nul"), Ok("nul".to_string()));
+    }
+
+    #[test]
+    fn test_check_upload_type() {
+        let config = FilenameConfig { blocked_extensions: vec!["exe".to_string(), ".BAT".to_string()], ..FilenameConfig::default() };
+        let check = |name: &str| check_upload_type_with(&config, name);
+        assert_eq!(check("setup.EXE"), Err(NameError::Blocked("exe".to_string())));
+        assert_eq!(check("run.bat"), Err(NameError::Blocked("bat".to_string())));
+        assert_eq!(check("exe"), Ok(()));
+        assert_eq!(check("report.exe.txt"), Ok(()));
+    }
+}
@@ -0,0 +1,369 @@
+//! Abuse detection
+//!
+//! Counts deletions (seen on the audit log stream), downloaded bytes and
+//! denied permission checks per user in fixed windows. A user going over a
+//! limit of `[abuse]` raises an alert: it is written to the admin audit log,
+//! pushed to connected auditors over the WebSocket and POSTed to the
+//! configured webhook. With `throttle` on, the account is also held to a low
+//! request rate for a while, until it expires or an auditor releases it.
+
+use axum::{response::Json, Extension};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::entity::op_log::OpType;
+use crate::handlers::audit::service::log_admin_operation;
+use crate::handlers::quota::format_size;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::rate_limit::RateLimiter;
+use crate::outbound;
+use crate::routes::ApiResponse;
+use crate::ws::{WsMessage, HUB};
+
+const OP_RESULT_ALERT: &str = "告警";
+const OP_SUCCESS: &str = "成功";
+
+/// Alerts kept for `/api/abuse/alerts`
+const MAX_ALERTS: usize = 100;
+/// Records between removals of finished windows
+const PRUNE_INTERVAL: u64 = 10_000;
+
+/// What an alert was raised for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AlertKind {
+    MassDelete,
+    DownloadBurst,
+    PermissionDenied,
+}
+
+/// A user going over a limit
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub username: String,
+    pub kind: AlertKind,
+    /// Deletions, bytes or denials counted in the window
+    pub amount: u64,
+    #[serde(rename = "windowSecs")]
+    pub window_secs: u64,
+    /// Unix timestamp
+    pub time: i64,
+    /// Whether the account was throttled
+    pub throttled: bool,
+}
+
+impl Alert {
+    /// Description for the audit log
+    fn description(&self) -> String {
+        let what = match self.kind {
+            AlertKind::MassDelete => format!("{}秒内删除 {} 个文件", self.window_secs, self.amount),
+            AlertKind::DownloadBurst => {
+                format!("{}秒内下载 {}", self.window_secs, format_size(self.amount as i64))
+            }
+            AlertKind::PermissionDenied => {
+                format!("{}秒内 {} 次权限检查被拒绝", self.window_secs, self.amount)
+            }
+        };
+        if self.throttled {
+            format!("{}，已限速", what)
+        } else {
+            what
+        }
+    }
+}
+
+struct Detector {
+    config: Config,
+    /// Window start and count by (kind, username)
+    counters: DashMap<(AlertKind, String), (Instant, u64)>,
+    /// Latest alerts, newest first
+    alerts: Mutex<VecDeque<Alert>>,
+    /// Throttled users and the end of their throttle (Unix timestamp)
+    throttled: DashMap<String, i64>,
+    limiter: RateLimiter,
+    records: AtomicU64,
+}
+
+impl Detector {
+    fn new(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            counters: DashMap::new(),
+            alerts: Mutex::new(VecDeque::new()),
+            throttled: DashMap::new(),
+            limiter: RateLimiter::new(config.abuse.throttle_per_minute),
+            records: AtomicU64::new(0),
+        }
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.config.abuse.window_secs.max(1))
+    }
+
+    /// Limit of a kind per window, 0 if not checked
+    fn limit(&self, kind: AlertKind) -> u64 {
+        let config = &self.config.abuse;
+        match kind {
+            AlertKind::MassDelete => config.max_deletes,
+            AlertKind::DownloadBurst => config.max_download_mb * 1024 * 1024,
+            AlertKind::PermissionDenied => config.max_denied,
+        }
+    }
+
+    /// Count `amount` for a user, returning an alert when this crosses the limit
+    ///
+    /// A user raises at most one alert of each kind per window.
+    fn record(&self, kind: AlertKind, username: &str, amount: u64, now: Instant) -> Option<Alert> {
+        let limit = self.limit(kind);
+        if limit == 0 {
+            return None;
+        }
+        let window = self.window();
+        if self.records.fetch_add(1, Ordering::Relaxed) % PRUNE_INTERVAL == PRUNE_INTERVAL - 1 {
+            self.counters.retain(|_, (start, _)| now.duration_since(*start) < window);
+        }
+
+        let mut counter = self.counters.entry((kind, username.to_string())).or_insert((now, 0));
+        let (start, count) = counter.value_mut();
+        if now.duration_since(*start) >= window {
+            *start = now;
+            *count = 0;
+        }
+        let before = *count;
+        *count += amount;
+        (before <= limit && *count > limit).then(|| Alert {
+            username: username.to_string(),
+            kind,
+            amount: *count,
+            window_secs: window.as_secs(),
+            time: chrono::Utc::now().timestamp(),
+            throttled: false,
+        })
+    }
+
+    /// Throttle the account if configured and send the alert out
+    fn raise(&self, mut alert: Alert) {
+        if self.config.abuse.throttle {
+            let until = alert.time + (self.config.abuse.throttle_minutes * 60) as i64;
+            self.throttled.insert(alert.username.clone(), until);
+            alert.throttled = true;
+        }
+        tracing::warn!("Abuse alert for {}: {}", alert.username, alert.description());
+
+        {
+            let mut alerts = self.alerts.lock().unwrap();
+            alerts.push_front(alert.clone());
+            alerts.truncate(MAX_ALERTS);
+        }
+        log_admin_operation(&alert.username, OpType::AbuseAlert, &alert.description(), OP_RESULT_ALERT, None);
+        let payload = serde_json::to_value(&alert).unwrap_or_default();
+        HUB.send_auditors(WsMessage::AbuseAlert(payload.clone()));
+
+        let url = self.config.abuse.webhook.clone();
+        if url.is_empty() {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let config = self.config.clone();
+        runtime.spawn(async move {
+            let result = outbound::post_json(&config, &url, &payload)
+                .await
+                .and_then(|res| res.error_for_status().map_err(Into::into));
+            if let Err(e) = result {
+                tracing::error!("Failed to send abuse alert to webhook: {}", e);
+            }
+        });
+    }
+
+    /// Apply the throttle of a user, if any
+    fn check_throttle(&self, username: &str, now: Instant) -> Result<(), Duration> {
+        let Some(until) = self.throttled.get(username).map(|until| *until) else {
+            return Ok(());
+        };
+        if chrono::Utc::now().timestamp() >= until {
+            self.throttled.remove(username);
+            return Ok(());
+        }
+        self.limiter.check(username, now)
+    }
+}
+
+static DETECTOR: std::sync::OnceLock<Detector> = std::sync::OnceLock::new();
+
+/// Start watching accounts if enabled in the configuration
+pub fn init(config: &Config) {
+    if config.abuse.enabled && DETECTOR.set(Detector::new(config)).is_err() {
+        tracing::debug!("Abuse detection already initialized");
+    }
+}
+
+fn record(kind: AlertKind, username: &str, amount: u64) {
+    let Some(detector) = DETECTOR.get() else {
+        return;
+    };
+    if let Some(alert) = detector.record(kind, username, amount, Instant::now()) {
+        detector.raise(alert);
+    }
+}
+
+/// Count an entry of the operation log
+pub fn observe_log(username: &str, op_type: OpType) {
+    if op_type == OpType::Delete {
+        record(AlertKind::MassDelete, username, 1);
+    }
+}
+
+/// Count bytes sent to a user
+pub fn record_download(username: &str, bytes: u64) {
+    record(AlertKind::DownloadBurst, username, bytes);
+}
+
+/// Count a request refused for missing permissions
+pub fn record_denied(username: &str) {
+    record(AlertKind::PermissionDenied, username, 1);
+}
+
+/// Check the request budget of a throttled user
+///
+/// Returns the time until the budget resets if it is used up.
+pub fn check_throttle(username: &str, now: Instant) -> Result<(), Duration> {
+    match DETECTOR.get() {
+        Some(detector) => detector.check_throttle(username, now),
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThrottledUser {
+    pub username: String,
+    /// End of the throttle (Unix timestamp)
+    pub until: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AbuseStatus {
+    pub enabled: bool,
+    /// Latest alerts, newest first
+    pub alerts: Vec<Alert>,
+    pub throttled: Vec<ThrottledUser>,
+}
+
+/// GET /api/abuse/alerts - Latest alerts and throttled accounts
+pub async fn get_alerts(Extension(current_user): Extension<CurrentUser>) -> Json<ApiResponse<AbuseStatus>> {
+    if !current_user.can_audit() {
+        record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let Some(detector) = DETECTOR.get() else {
+        return Json(ApiResponse::success(AbuseStatus {
+            enabled: false,
+            alerts: Vec::new(),
+            throttled: Vec::new(),
+        }));
+    };
+    let now = chrono::Utc::now().timestamp();
+    let mut throttled: Vec<_> = detector
+        .throttled
+        .iter()
+        .filter(|e| *e.value() > now)
+        .map(|e| ThrottledUser {
+            username: e.key().clone(),
+            until: *e.value(),
+        })
+        .collect();
+    throttled.sort_by(|a, b| a.username.cmp(&b.username));
+    let alerts = detector.alerts.lock().unwrap().iter().cloned().collect();
+    Json(ApiResponse::success(AbuseStatus {
+        enabled: true,
+        alerts,
+        throttled,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReleaseRequest {
+    pub username: String,
+}
+
+/// POST /api/abuse/release - Lift the throttle of an account
+pub async fn release(
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<ReleaseRequest>,
+) -> Json<ApiResponse<()>> {
+    if !current_user.can_audit() {
+        record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let released = DETECTOR
+        .get()
+        .is_some_and(|detector| detector.throttled.remove(&req.username).is_some());
+    if !released {
+        return Json(ApiResponse::error(404, "该用户未被限速"));
+    }
+    log_admin_operation(&current_user.username, OpType::ReleaseThrottle, &req.username, OP_SUCCESS, None);
+    Json(ApiResponse::success_msg("已解除限速"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector(throttle: bool) -> Detector {
+        let mut config = Config::default();
+        config.abuse.window_secs = 60;
+        config.abuse.max_deletes = 3;
+        config.abuse.max_download_mb = 1;
+        config.abuse.throttle = throttle;
+        config.abuse.throttle_per_minute = 2;
+        Detector::new(&config)
+    }
+
+    #[test]
+    fn test_record() {
+        let detector = detector(false);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(detector.record(AlertKind::MassDelete, "alice", 1, now).is_none());
+        }
+        let alert = detector.record(AlertKind::MassDelete, "alice", 1, now).unwrap();
+        assert_eq!((alert.kind, alert.amount), (AlertKind::MassDelete, 4));
+        // Once per window
+        assert!(detector.record(AlertKind::MassDelete, "alice", 1, now).is_none());
+        // Other users and kinds are counted separately
+        assert!(detector.record(AlertKind::MassDelete, "bob", 1, now).is_none());
+        assert!(detector.record(AlertKind::DownloadBurst, "alice", 1024 * 1024, now).is_none());
+        assert!(detector.record(AlertKind::DownloadBurst, "alice", 1, now).is_some());
+
+        // The next window starts over
+        let later = now + Duration::from_secs(60);
+        assert!(detector.record(AlertKind::MassDelete, "alice", 3, later).is_none());
+        assert!(detector.record(AlertKind::MassDelete, "alice", 1, later).is_some());
+    }
+
+    #[test]
+    fn test_throttle() {
+        let detector = detector(true);
+        let now = Instant::now();
+        let alert = detector.record(AlertKind::MassDelete, "alice", 10, now).unwrap();
+        detector.raise(alert);
+        assert!(detector.alerts.lock().unwrap()[0].throttled);
+
+        assert!(detector.check_throttle("alice", now).is_ok());
+        assert!(detector.check_throttle("alice", now).is_ok());
+        assert!(detector.check_throttle("alice", now).is_err());
+        assert!(detector.check_throttle("bob", now).is_ok());
+
+        detector.throttled.insert("alice".to_string(), chrono::Utc::now().timestamp() - 1);
+        assert!(detector.check_throttle("alice", now).is_ok());
+        assert!(detector.throttled.is_empty());
+    }
+}
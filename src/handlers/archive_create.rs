@@ -0,0 +1,173 @@
+//! Server-side archive creation
+//!
+//! `POST /api/file/compress` packs a selection of a folder into a zip or
+//! tar.gz archive stored in the user's tree, as a task whose progress is
+//! pushed over the WebSocket like copies and moves.
+
+use axum::{extract::State, response::Json, Extension};
+use serde::Deserialize;
+use std::path::PathBuf;
+use utoipa::ToSchema;
+
+use crate::filename;
+use crate::handlers::artifact::{self, ArtifactKind};
+use crate::handlers::file::{get_user_path, is_safe_filename, is_safe_path, locate, locate_for_write, resolve_in_root};
+use crate::handlers::quota;
+use crate::handlers::tiering;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+use crate::task::{ArchiveFormat, TaskDir, TaskInfo, TASK_MANAGER};
+
+/// Compress request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CompressRequest {
+    /// Folder of the files
+    #[serde(rename = "parentDir")]
+    pub parent_dir: String,
+    /// Names of the files and folders to pack
+    pub files: Vec<String>,
+    /// Folder to create the archive in, `parentDir` if not set
+    pub target: Option<String>,
+    /// Archive name; the extension of the format is added if missing
+    pub name: String,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+}
+
+/// POST /api/file/compress - Start packing files into an archive
+#[utoipa::path(
+    post,
+    path = "/api/file/compress",
+    tag = "file",
+    request_body = CompressRequest,
+    responses((status = 200, body = ApiResponse<TaskInfo>)),
+)]
+pub async fn compress(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<CompressRequest>,
+) -> Json<ApiResponse<TaskInfo>> {
+    let target = req.target.clone().unwrap_or_else(|| req.parent_dir.clone());
+    if req.files.is_empty() || !is_safe_path(&req.parent_dir) || !is_safe_path(&target) {
+        return Json(ApiResponse::error(400, "invalid request"));
+    }
+    if !req.files.iter().all(|f| is_safe_filename(f)) {
+        return Json(ApiResponse::error(400, "invalid file name"));
+    }
+    let file_name = match filename::check_new_name(&req.format.file_name(req.name.trim())) {
+        Ok(name) => name,
+        Err(e) => return Json(ApiResponse::error(400, format!("invalid name: {}", e))),
+    };
+
+    let source = match locate(&state, &db, &current_user, &req.parent_dir).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    let to = match locate_for_write(&state, &db, &current_user, &target).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    let source_root = get_user_path(&state.config, &source.owner);
+    let target_root = get_user_path(&state.config, &to.owner);
+    if !resolve_in_root(&target_root, &to.path).is_some_and(|dir| dir.is_dir()) {
+        return Json(ApiResponse::error(400, "parent_dir_not_exists"));
+    }
+
+    // Files in cold storage are packed with their content
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for file in &req.files {
+        let path = format!("{}/{}", source.path, file);
+        if let Err(e) = tiering::recall(&db, &source.owner, &source_root, &path).await {
+            tracing::error!("Failed to recall {}: {}", path, e);
+            return Json(ApiResponse::error(500, "failed to recall file from cold storage"));
+        }
+        match resolve_in_root(&source_root, &path) {
+            Some(full) if full.exists() => paths.push(full),
+            _ => return Json(ApiResponse::error(404, "文件不存在")),
+        }
+    }
+
+    // The archive takes at most about as much as its contents
+    let size = tokio::task::spawn_blocking(move || paths.iter().map(|p| quota::path_size(p)).sum::<i64>())
+        .await
+        .unwrap_or(0);
+    if let Err(exceeded) = quota::check_quota(&db, &state.config, &to.owner, size).await {
+        return Json(ApiResponse::error(413, exceeded.message()));
+    }
+
+    let info = TASK_MANAGER.create_compress_task(
+        current_user.id,
+        &current_user.username,
+        req.parent_dir.clone(),
+        target,
+        req.files.clone(),
+        TaskDir { owner: source.owner, root: source_root, path: source.path },
+        TaskDir { owner: to.owner, root: target_root, path: to.path },
+        file_name,
+        req.format,
+        artifact::user_dir(&state.config, ArtifactKind::Archive, &current_user.username),
+    );
+
+    // The task records the audit entry when done
+    Json(ApiResponse::success(info))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handlers::file::get_user_path;
+    use crate::testing::TestApp;
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn test_compress() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "a.txt", b"hello").await.status().is_success());
+        let body = serde_json::json!({ "parentPath": "/", "name": "docs" });
+        assert!(admin.post_json("/api/file/mkdir", &body).await.status().is_success());
+        assert!(admin.upload("/docs", "b.txt", b"world").await.status().is_success());
+        let root = get_user_path(&app.env.config, "admin");
+
+        let mut ws = admin.ws().await;
+        let compress = |format: &'static str| {
+            let admin = &admin;
+            async move {
+                let body = serde_json::json!({
+                    "parentDir": "/", "files": ["a.txt", "docs"], "name": "backup", "format": format,
+                });
+                let res: serde_json::Value = admin.post_json("/api/file/compress", &body).await.json().await.unwrap();
+                assert_eq!(res["code"], true, "{}", res);
+                res["data"]["id"].as_str().unwrap().to_string()
+            }
+        };
+
+        let id = compress("zip").await;
+        ws.wait_for(|m| m["data"]["id"] == id.as_str() && m["data"]["status"] == "completed").await;
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(root.join("backup.zip")).unwrap()).unwrap();
+        let mut content = String::new();
+        zip.by_name("docs/b.txt").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "world");
+
+        let id = compress("tar.gz").await;
+        let done = ws.wait_for(|m| m["data"]["id"] == id.as_str() && m["data"]["status"] == "completed").await;
+        assert_eq!(done["data"]["target"], "/backup.tar.gz");
+        let gz = flate2::read::GzDecoder::new(std::fs::File::open(root.join("backup.tar.gz")).unwrap());
+        let mut names: Vec<String> = tar::Archive::new(gz)
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a.txt", "docs/b.txt"]);
+
+        // Taken names are numbered
+        let id = compress("zip").await;
+        let done = ws.wait_for(|m| m["data"]["id"] == id.as_str() && m["data"]["status"] == "completed").await;
+        assert_eq!(done["data"]["target"], "/backup (1).zip");
+        assert!(root.join("backup (1).zip").exists());
+        app.close().await;
+    }
+}
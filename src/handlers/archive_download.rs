@@ -0,0 +1,229 @@
+//! Resumable archive downloads
+//!
+//! Large folder downloads can be built into a zip file by a background task
+//! first. The finished archive is kept in the temp artifact area until it
+//! expires and served with Range support, so an interrupted download can
+//! resume where it stopped.
+
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    Extension,
+};
+use serde::Deserialize;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::config::Config;
+use crate::entity::op_log::OpType;
+use crate::handlers::artifact::{self, ArtifactKind};
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::file::{
+    get_user_path, is_safe_filename, is_safe_path, resolve_in_root, DownloadFormat, DownloadPreRequest,
+};
+use crate::handlers::quota::path_size;
+use crate::handlers::sensitive;
+use crate::handlers::tiering;
+use crate::handlers::traffic;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+use crate::task::{TaskInfo, TASK_MANAGER};
+
+const OP_SUCCESS: &str = "成功";
+
+/// Archive query
+#[derive(Debug, Deserialize)]
+pub struct ArchiveQuery {
+    pub id: String,
+}
+
+/// Directory holding a user's built archives
+pub fn get_archive_dir(config: &Config, username: &str) -> PathBuf {
+    artifact::user_dir(config, ArtifactKind::Archive, username)
+}
+
+/// POST /api/file/download/archive - Start building an archive
+pub async fn create_archive(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<DownloadPreRequest>,
+) -> Json<ApiResponse<TaskInfo>> {
+    if req.files.is_empty() || !is_safe_path(&req.parent_dir) {
+        return Json(ApiResponse::error(400, "invalid request"));
+    }
+    if !req.files.iter().all(|f| is_safe_filename(f)) {
+        return Json(ApiResponse::error(400, "invalid file name"));
+    }
+    // Built archives are always zip, stored
+    if req.format != DownloadFormat::Zip || req.level.is_some_and(|level| level > 0) {
+        return Json(ApiResponse::error(400, "only stored zip archives can be built"));
+    }
+
+    // Archives are stored, so they are about as large as their contents
+    let user_path = get_user_path(&state.config, &current_user.username);
+    let Some(base_dir) = resolve_in_root(&user_path, &req.parent_dir) else {
+        return Json(ApiResponse::error(400, "invalid request"));
+    };
+    for file in &req.files {
+        let path = format!("{}/{}", req.parent_dir, file);
+        // Files of sensitive folders are only served one by one, watermarked
+        match sensitive::contains_sensitive(&db, &current_user.username, &path).await {
+            Ok(false) => {}
+            Ok(true) => return Json(ApiResponse::error(403, "敏感文件夹中的文件不能打包下载")),
+            Err(e) => {
+                tracing::error!("Failed to look up sensitive folders of {}: {}", path, e);
+                return Json(ApiResponse::error(500, "internal error"));
+            }
+        }
+        if let Err(e) = tiering::recall(&db, &current_user.username, &user_path, &path).await {
+            tracing::error!("Failed to recall {}: {}", path, e);
+            return Json(ApiResponse::error(500, "failed to recall file from cold storage"));
+        }
+    }
+    let paths: Vec<PathBuf> = req.files.iter().filter_map(|f| resolve_in_root(&base_dir, f)).collect();
+    let size = tokio::task::spawn_blocking(move || paths.iter().map(|p| path_size(p)).sum::<i64>())
+        .await
+        .unwrap_or(0);
+    if let Err(msg) = artifact::check_quota(&state.config, &current_user.username, size).await {
+        return Json(ApiResponse::error(413, &msg));
+    }
+
+    let info = TASK_MANAGER.create_archive_task(
+        current_user.id,
+        req.parent_dir,
+        req.files,
+        &user_path,
+        &get_archive_dir(&state.config, &current_user.username),
+    );
+    Json(ApiResponse::success(info))
+}
+
+/// GET /api/file/download/archive - Download a built archive, honoring Range
+pub async fn download_archive(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<ArchiveQuery>,
+    headers: HeaderMap,
+) -> Response {
+    // Task IDs are UUIDs, anything else can't name an archive
+    if uuid::Uuid::parse_str(&query.id).is_err() {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid id"}))).into_response();
+    }
+    let path = get_archive_dir(&state.config, &current_user.username).join(format!("{}.zip", query.id));
+    let len = match fs::metadata(&path).await {
+        Ok(m) => m.len(),
+        Err(_) => {
+            return (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "archive not found"}))).into_response();
+        }
+    };
+
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) => match parse_range(value, len) {
+            Some(range) => Some(range),
+            None => {
+                return Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                    .body(Body::empty())
+                    .unwrap();
+            }
+        },
+        None => None,
+    };
+
+    let mut file = match fs::File::open(&path).await {
+        Ok(f) => f,
+        Err(e) => {
+            tracing::error!("Failed to open archive: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "failed to open file"}))).into_response();
+        }
+    };
+
+    // Log the download once, not for every resumed range
+    if range.is_none_or(|(start, _)| start == 0) {
+        log_operation(&current_user.username, OpType::Download, &format!("打包下载 {}", query.id), OP_SUCCESS, None);
+    }
+
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(header::CONTENT_DISPOSITION, "attachment; filename=download.zip")
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, format!("\"{}\"", query.id));
+
+    match range {
+        Some((start, end)) => {
+            if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+                tracing::error!("Failed to seek archive: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            let stream = ReaderStream::new(file.take(end - start + 1));
+            let body = Body::from_stream(traffic::counted(&current_user.username, stream));
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+                .header(header::CONTENT_LENGTH, end - start + 1)
+                .body(body)
+                .unwrap()
+        }
+        None => builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, len)
+            .body(Body::from_stream(traffic::counted(&current_user.username, ReaderStream::new(file))))
+            .unwrap(),
+    }
+}
+
+/// Parse a single `bytes=` range into inclusive (start, end)
+///
+/// Returns None if the range is malformed or unsatisfiable. Multiple ranges
+/// are not supported.
+pub(crate) fn parse_range(value: &str, len: u64) -> Option<(u64, u64)> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') || len == 0 {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // Suffix range: the last N bytes
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return None;
+            }
+            (len.saturating_sub(suffix), len - 1)
+        }
+        (start, "") => (start.parse().ok()?, len - 1),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(len - 1)),
+    };
+    if start > end || start >= len {
+        return None;
+    }
+    Some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_range;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(parse_range("bytes=500-", 1000), Some((500, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some((0, 999)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=50-10", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+    }
+}
@@ -0,0 +1,175 @@
+//! Server-side archive extraction
+//!
+//! `POST /api/archive/extract` unpacks an archive the preview can list into a
+//! folder of the user's tree, as a task whose progress is pushed over the
+//! WebSocket and whose name conflicts are resolved like those of copies.
+
+use axum::{extract::State, response::Json, Extension};
+use serde::Deserialize;
+use std::path::PathBuf;
+use utoipa::ToSchema;
+
+use crate::handlers::archive_preview::{list_entries, ArchiveEntry, ArchiveError, ArchiveKind};
+use crate::handlers::artifact::{self, ArtifactKind};
+use crate::handlers::file::{get_user_path, is_safe_path, locate, locate_for_write, resolve_in_root};
+use crate::handlers::quota;
+use crate::handlers::tiering;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+use crate::task::{ConflictPolicy, TaskDir, TaskInfo, TASK_MANAGER};
+
+/// Extract request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ExtractRequest {
+    /// The archive
+    pub path: String,
+    /// Folder to extract to, the folder of the archive if not set
+    pub target: Option<String>,
+    /// What to do with entries whose name is taken, asked by default
+    #[serde(default, rename = "conflictPolicy")]
+    pub conflict_policy: ConflictPolicy,
+}
+
+/// Format and entries of the archive at `path`, None if the format is not
+/// supported
+pub(crate) async fn inspect(path: PathBuf) -> Option<Result<(ArchiveKind, Vec<ArchiveEntry>), ArchiveError>> {
+    tokio::task::spawn_blocking(move || {
+        let kind = ArchiveKind::detect(&path)?;
+        Some(list_entries(&path)?.map(|entries| (kind, entries)))
+    })
+    .await
+    .unwrap_or_else(|e| Some(Err(ArchiveError::Invalid(e.to_string()))))
+}
+
+/// POST /api/archive/extract - Start unpacking an archive
+#[utoipa::path(
+    post,
+    path = "/api/archive/extract",
+    tag = "file",
+    request_body = ExtractRequest,
+    responses((status = 200, body = ApiResponse<TaskInfo>)),
+)]
+pub async fn extract(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<ExtractRequest>,
+) -> Json<ApiResponse<TaskInfo>> {
+    let folder = match req.path.rsplit_once('/') {
+        Some((folder, _)) if !folder.is_empty() => folder.to_string(),
+        _ => "/".to_string(),
+    };
+    let target = req.target.clone().unwrap_or(folder);
+    if !is_safe_path(&req.path) || !is_safe_path(&target) {
+        return Json(ApiResponse::error(400, "invalid path"));
+    }
+
+    let source = match locate(&state, &db, &current_user, &req.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    let to = match locate_for_write(&state, &db, &current_user, &target).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    let source_root = get_user_path(&state.config, &source.owner);
+    let target_root = get_user_path(&state.config, &to.owner);
+    let Some(archive) = resolve_in_root(&source_root, &source.path).filter(|p| p.is_file()) else {
+        return Json(ApiResponse::error(404, "文件不存在"));
+    };
+    if !resolve_in_root(&target_root, &to.path).is_some_and(|dir| dir.is_dir()) {
+        return Json(ApiResponse::error(400, "parent_dir_not_exists"));
+    }
+
+    if let Err(e) = tiering::recall(&db, &source.owner, &source_root, &source.path).await {
+        tracing::error!("Failed to recall {}: {}", req.path, e);
+        return Json(ApiResponse::error(500, "failed to recall file from cold storage"));
+    }
+
+    // The listing tells the format works and how much the entries take
+    let (kind, entries) = match inspect(archive.clone()).await {
+        Some(Ok(listed)) => listed,
+        Some(Err(e @ ArchiveError::Invalid(_))) => return Json(ApiResponse::error(400, e.to_string())),
+        Some(Err(e)) => return Json(ApiResponse::error(413, e.to_string())),
+        None => return Json(ApiResponse::error(400, "不支持的压缩格式")),
+    };
+    let size = entries.iter().filter(|e| !e.dir).map(|e| e.size as i64).sum::<i64>();
+    if let Err(exceeded) = quota::check_quota(&db, &state.config, &to.owner, size).await {
+        return Json(ApiResponse::error(413, exceeded.message()));
+    }
+
+    let info = TASK_MANAGER.create_extract_task(
+        current_user.id,
+        &current_user.username,
+        req.path.clone(),
+        target,
+        archive,
+        kind,
+        size,
+        TaskDir { owner: to.owner, root: target_root, path: to.path },
+        req.conflict_policy,
+        artifact::user_dir(&state.config, ArtifactKind::Archive, &current_user.username),
+        state.config.clone(),
+        db.0.clone(),
+    );
+
+    // The task records the audit entry when done
+    Json(ApiResponse::success(info))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::file_info;
+    use crate::handlers::file::get_user_path;
+    use crate::testing::TestApp;
+    use sea_orm::{ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter};
+    use std::io::Write;
+
+    fn zip(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_extract() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let archive = zip(&[("docs/a.txt", "hello"), ("b.txt", "world"), ("../evil.txt", "x")]);
+        assert!(admin.upload("/", "pack.zip", &archive).await.status().is_success());
+        assert!(admin.upload("/", "b.txt", b"mine").await.status().is_success());
+        let root = get_user_path(&app.env.config, "admin");
+
+        let mut ws = admin.ws().await;
+        let body = serde_json::json!({ "path": "/pack.zip", "conflictPolicy": "rename" });
+        let res: serde_json::Value = admin.post_json("/api/archive/extract", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        let id = res["data"]["id"].as_str().unwrap().to_string();
+        let done = ws.wait_for(|m| m["data"]["id"] == id.as_str() && m["data"]["status"] == "completed").await;
+        assert_eq!(done["data"]["copiedSize"], 10);
+
+        assert_eq!(std::fs::read_to_string(root.join("docs/a.txt")).unwrap(), "hello");
+        assert_eq!(std::fs::read_to_string(root.join("b.txt")).unwrap(), "mine");
+        assert_eq!(std::fs::read_to_string(root.join("b(1).txt")).unwrap(), "world");
+        assert!(!root.parent().unwrap().join("evil.txt").exists());
+
+        // Rows for the listing
+        let rows = file_info::Entity::find()
+            .filter(file_info::Column::Username.eq("admin"))
+            .filter(file_info::Column::Name.is_in(["docs", "a.txt", "b(1).txt"]))
+            .count(&app.env.db)
+            .await
+            .unwrap();
+        assert_eq!(rows, 3);
+
+        let body = serde_json::json!({ "path": "/b.txt" });
+        let res: serde_json::Value = admin.post_json("/api/archive/extract", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        app.close().await;
+    }
+}
@@ -1,6 +1,11 @@
 //! Archive preview handlers
 //!
 //! Supports previewing contents of ZIP, TAR, TAR.GZ, TAR.XZ, RAR, and 7Z archives
+//!
+//! Listings stop at the first entry over `[archive_limits]` (entry count,
+//! declared unpacked size, path depth), installed once at startup with
+//! [`init`]. The extraction lists an archive before unpacking it, so the
+//! same limits keep archive bombs from being unpacked.
 
 use axum::{
     extract::{Query, State},
@@ -9,10 +14,17 @@ use axum::{
     Extension,
 };
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-
-use crate::handlers::file::get_user_path;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::config::ArchiveLimitsConfig;
+use crate::handlers::file::{get_user_path, resolve_in_user_root};
+use crate::handlers::tiering;
 use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::mime;
 use crate::state::AppState;
 
 /// Archive file entry for preview
@@ -30,154 +42,236 @@ pub struct ArchivePreviewQuery {
     pub path: String,
 }
 
-/// Detect archive type by MIME type (reading file magic bytes)
-fn detect_mime_type(path: &PathBuf) -> Option<&'static str> {
-    let mut file = match std::fs::File::open(path) {
-        Ok(f) => f,
-        Err(_) => return None,
+/// GET /api/archive/preview - Preview archive file contents
+pub async fn archive_preview(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<ArchivePreviewQuery>,
+) -> Result<Json<Vec<ArchiveEntry>>, (StatusCode, Json<serde_json::Value>)> {
+    let Some(file_path) = resolve_in_user_root(&state.config, &current_user.username, &query.path) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "invalid path"})),
+        ));
     };
 
-    let mut buffer = [0u8; 16];
-    use std::io::Read;
-    if file.read(&mut buffer).is_err() {
-        return None;
+    if !file_path.exists() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "文件不存在"})),
+        ));
     }
 
-    // Check magic bytes
-    // ZIP: PK (0x50 0x4B)
-    if buffer.starts_with(&[0x50, 0x4B, 0x03, 0x04])
-        || buffer.starts_with(&[0x50, 0x4B, 0x05, 0x06])
-        || buffer.starts_with(&[0x50, 0x4B, 0x07, 0x08])
-    {
-        return Some("application/zip");
+    let user_path = get_user_path(&state.config, &current_user.username);
+    if let Err(e) = tiering::recall(&db, &current_user.username, &user_path, &query.path).await {
+        tracing::error!("Failed to recall {}: {}", query.path, e);
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "failed to recall file from cold storage"})),
+        ));
     }
 
-    // RAR: Rar! (0x52 0x61 0x72 0x21)
-    if buffer.starts_with(&[0x52, 0x61, 0x72, 0x21]) {
-        return Some("application/vnd.rar");
+    let result = tokio::task::spawn_blocking(move || list_entries(&file_path))
+        .await
+        .unwrap_or_else(|e| Some(Err(ArchiveError::Invalid(e.to_string()))));
+    match result {
+        Some(Ok(list)) => Ok(Json(list)),
+        Some(Err(e)) => {
+            tracing::error!("Failed to preview archive: {}", e);
+            Err((e.status(), Json(serde_json::json!({"error": e.to_string()}))))
+        }
+        None => Err((
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "不支持的压缩格式"})),
+        )),
     }
+}
 
-    // 7Z: 7z (0x37 0x7A 0xBC 0xAF 0x27 0x1C)
-    if buffer.starts_with(&[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C]) {
-        return Some("application/x-7z-compressed");
-    }
+static LIMITS: OnceLock<ArchiveLimitsConfig> = OnceLock::new();
 
-    // GZIP: (0x1F 0x8B)
-    if buffer.starts_with(&[0x1F, 0x8B]) {
-        return Some("application/gzip");
+/// Install the configured limits
+pub fn init(config: &ArchiveLimitsConfig) {
+    if LIMITS.set(*config).is_err() {
+        tracing::warn!("Archive limits already initialized");
     }
+}
+
+pub(crate) fn limits() -> &'static ArchiveLimitsConfig {
+    LIMITS.get_or_init(ArchiveLimitsConfig::default)
+}
 
-    // XZ: (0xFD 0x37 0x7A 0x58 0x5A 0x00)
-    if buffer.starts_with(&[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00]) {
-        return Some("application/x-xz");
+/// Why the entries of an archive were not listed or unpacked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveError {
+    /// Broken archive or failed read
+    Invalid(String),
+    /// Over the entry limit
+    TooManyEntries(u64),
+    /// Over the unpacked size limit (MiB)
+    TooLarge(u64),
+    /// Over the path depth limit
+    TooDeep(usize),
+}
+
+impl ArchiveError {
+    /// Status the error is answered with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ArchiveError::Invalid(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::PAYLOAD_TOO_LARGE,
+        }
     }
+}
 
-    // TAR: ustar at offset 257 (need to read more bytes)
-    let mut tar_buffer = [0u8; 265];
-    use std::io::Seek;
-    if file.seek(std::io::SeekFrom::Start(0)).is_ok() {
-        if let Ok(n) = file.read(&mut tar_buffer) {
-            if n >= 265 && &tar_buffer[257..262] == b"ustar" {
-                return Some("application/x-tar");
-            }
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Invalid(e) => write!(f, "无法解析压缩文件: {}", e),
+            ArchiveError::TooManyEntries(max) => write!(f, "压缩文件条目过多, 最多 {} 个", max),
+            ArchiveError::TooLarge(max) => write!(f, "压缩文件解压后过大, 最多 {} MB", max),
+            ArchiveError::TooDeep(max) => write!(f, "压缩文件路径层级过深, 最多 {} 层", max),
         }
     }
+}
+
+impl std::error::Error for ArchiveError {}
 
-    None
+impl From<String> for ArchiveError {
+    fn from(e: String) -> Self {
+        ArchiveError::Invalid(e)
+    }
 }
 
-/// GET /api/archive/preview - Preview archive file contents
-pub async fn archive_preview(
-    State(state): State<AppState>,
-    Extension(current_user): Extension<CurrentUser>,
-    Query(query): Query<ArchivePreviewQuery>,
-) -> Result<Json<Vec<ArchiveEntry>>, (StatusCode, Json<serde_json::Value>)> {
-    let user_path = get_user_path(&state.config, &current_user.username);
-    let file_path = user_path.join(query.path.trim_start_matches('/'));
+/// Error if `size` unpacked bytes are over the limit
+pub(crate) fn check_size(config: &ArchiveLimitsConfig, size: u64) -> Result<(), ArchiveError> {
+    let max = config.max_total_size_mb;
+    if max != 0 && size > max.saturating_mul(1024 * 1024) {
+        return Err(ArchiveError::TooLarge(max));
+    }
+    Ok(())
+}
 
-    if !file_path.exists() {
-        return Err((
-            StatusCode::NOT_FOUND,
-            Json(serde_json::json!({"error": "文件不存在"})),
-        ));
+/// Entries of one archive counted against the limits
+struct EntryBudget<'a> {
+    config: &'a ArchiveLimitsConfig,
+    entries: u64,
+    size: u64,
+}
+
+impl<'a> EntryBudget<'a> {
+    fn new(config: &'a ArchiveLimitsConfig) -> Self {
+        Self { config, entries: 0, size: 0 }
     }
 
-    // First try to detect by MIME type (magic bytes)
-    let mime_type = detect_mime_type(&file_path);
-
-    let entries = match mime_type {
-        Some("application/zip") => preview_zip(&file_path),
-        Some("application/x-tar") => preview_tar(&file_path),
-        Some("application/gzip") => preview_tar_gz(&file_path),
-        Some("application/x-xz") => preview_tar_xz(&file_path),
-        Some("application/vnd.rar") => preview_rar(&file_path),
-        Some("application/x-7z-compressed") => preview_7z(&file_path),
-        _ => {
-            // Fall back to extension detection
-            let extension = file_path
-                .extension()
-                .and_then(|e| e.to_str())
-                .unwrap_or("")
-                .to_lowercase();
-
-            // Check for .tar.xz extension
-            let file_name = file_path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("")
-                .to_lowercase();
-
-            if file_name.ends_with(".tar.xz") || file_name.ends_with(".txz") {
-                return match preview_tar_xz(&file_path) {
-                    Ok(list) => Ok(Json(list)),
-                    Err(e) => {
-                        tracing::error!("Failed to preview archive: {}", e);
-                        Err((
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            Json(serde_json::json!({"error": format!("无法解析压缩文件: {}", e)})),
-                        ))
-                    }
-                };
-            }
-
-            match extension.as_str() {
-                "zip" => preview_zip(&file_path),
-                "tar" => preview_tar(&file_path),
-                "gz" | "tgz" => preview_tar_gz(&file_path),
-                "xz" => preview_tar_xz(&file_path),
-                "rar" => preview_rar(&file_path),
-                "7z" => preview_7z(&file_path),
-                _ => {
-                    return Err((
-                        StatusCode::BAD_REQUEST,
-                        Json(serde_json::json!({"error": "不支持的压缩格式"})),
-                    ));
-                }
-            }
+    /// Count the entry at `path` declaring `size` unpacked bytes
+    fn add(&mut self, path: &str, size: u64) -> Result<(), ArchiveError> {
+        self.entries += 1;
+        if self.config.max_entries != 0 && self.entries > self.config.max_entries {
+            return Err(ArchiveError::TooManyEntries(self.config.max_entries));
         }
-    };
+        self.size = self.size.saturating_add(size);
+        check_size(self.config, self.size)?;
+        let depth = path.split(['/', '\\']).filter(|part| !part.is_empty() && *part != ".").count();
+        if self.config.max_depth != 0 && depth > self.config.max_depth {
+            return Err(ArchiveError::TooDeep(self.config.max_depth));
+        }
+        Ok(())
+    }
+}
 
-    match entries {
-        Ok(list) => Ok(Json(list)),
-        Err(e) => {
-            tracing::error!("Failed to preview archive: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error": format!("无法解析压缩文件: {}", e)})),
-            ))
+/// Format of an archive file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarXz,
+    Rar,
+    SevenZ,
+}
+
+impl ArchiveKind {
+    /// Detect the format of an archive, None if it is not supported
+    pub fn detect(file_path: &Path) -> Option<Self> {
+        // First try to detect by MIME type (magic bytes)
+        let sniffed = mime::read_head(file_path).ok().and_then(|head| mime::sniff(&head));
+        match sniffed {
+            Some("application/zip") => return Some(ArchiveKind::Zip),
+            Some("application/x-tar") => return Some(ArchiveKind::Tar),
+            Some("application/gzip") => return Some(ArchiveKind::TarGz),
+            Some("application/x-xz") => return Some(ArchiveKind::TarXz),
+            Some("application/vnd.rar") => return Some(ArchiveKind::Rar),
+            Some("application/x-7z-compressed") => return Some(ArchiveKind::SevenZ),
+            _ => {}
+        }
+
+        // Fall back to extension detection
+        let extension = file_path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+
+        // Check for .tar.xz extension
+        let file_name = file_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+
+        if file_name.ends_with(".tar.xz") || file_name.ends_with(".txz") {
+            return Some(ArchiveKind::TarXz);
+        }
+
+        match extension.as_str() {
+            "zip" => Some(ArchiveKind::Zip),
+            "tar" => Some(ArchiveKind::Tar),
+            "gz" | "tgz" => Some(ArchiveKind::TarGz),
+            "xz" => Some(ArchiveKind::TarXz),
+            "rar" => Some(ArchiveKind::Rar),
+            "7z" => Some(ArchiveKind::SevenZ),
+            _ => None,
         }
     }
 }
 
+/// List the entries of an archive, None if the format is not supported
+pub(crate) fn list_entries(file_path: &PathBuf) -> Option<Result<Vec<ArchiveEntry>, ArchiveError>> {
+    list_entries_with(file_path, limits())
+}
+
+fn list_entries_with(
+    file_path: &PathBuf,
+    config: &ArchiveLimitsConfig,
+) -> Option<Result<Vec<ArchiveEntry>, ArchiveError>> {
+    let mut budget = EntryBudget::new(config);
+    let open = || std::fs::File::open(file_path).map_err(|e| ArchiveError::Invalid(e.to_string()));
+    let entries = match ArchiveKind::detect(file_path)? {
+        ArchiveKind::Zip => preview_zip(file_path, &mut budget),
+        ArchiveKind::Tar => open().and_then(|f| preview_tar(f, &mut budget)),
+        ArchiveKind::TarGz => open().and_then(|f| preview_tar(flate2::read::GzDecoder::new(f), &mut budget)),
+        ArchiveKind::TarXz => open().and_then(|f| preview_tar(xz2::read::XzDecoder::new(f), &mut budget)),
+        ArchiveKind::Rar => preview_rar(file_path, &mut budget),
+        ArchiveKind::SevenZ => preview_7z(file_path, &mut budget),
+    };
+    Some(entries)
+}
+
 /// Preview ZIP file contents
-fn preview_zip(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
+fn preview_zip(path: &PathBuf, budget: &mut EntryBudget) -> Result<Vec<ArchiveEntry>, ArchiveError> {
     let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
     let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+    let max_entries = budget.config.max_entries;
+    if max_entries != 0 && archive.len() as u64 > max_entries {
+        return Err(ArchiveError::TooManyEntries(max_entries));
+    }
 
     let mut entries = Vec::new();
     for i in 0..archive.len() {
         let file = archive.by_index(i).map_err(|e| e.to_string())?;
         let name = file.name().to_string();
+        budget.add(&name, file.size())?;
         let is_dir = file.is_dir();
 
         // Get file name from path
@@ -218,10 +312,10 @@ fn preview_zip(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
     Ok(entries)
 }
 
-/// Preview TAR file contents
-fn preview_tar(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
-    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
-    let mut archive = tar::Archive::new(file);
+/// Preview TAR file contents, read from the file or a decompressing reader
+/// for TAR.GZ / TGZ and TAR.XZ / TXZ
+fn preview_tar(reader: impl Read, budget: &mut EntryBudget) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+    let mut archive = tar::Archive::new(reader);
 
     let mut entries = Vec::new();
     for entry in archive.entries().map_err(|e| e.to_string())? {
@@ -231,6 +325,8 @@ fn preview_tar(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
             .map_err(|e| e.to_string())?
             .to_string_lossy()
             .to_string();
+        let size = entry.header().size().unwrap_or(0);
+        budget.add(&path_str, size)?;
         let is_dir = entry.header().entry_type().is_dir();
 
         let file_name = if is_dir {
@@ -258,107 +354,7 @@ fn preview_tar(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
         entries.push(ArchiveEntry {
             name: file_name,
             path: path_str.trim_end_matches('/').to_string(),
-            size: entry.header().size().unwrap_or(0),
-            dir: is_dir,
-            date,
-        });
-    }
-
-    Ok(entries)
-}
-
-/// Preview TAR.GZ / TGZ file contents
-fn preview_tar_gz(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
-    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
-    let gz = flate2::read::GzDecoder::new(file);
-    let mut archive = tar::Archive::new(gz);
-
-    let mut entries = Vec::new();
-    for entry in archive.entries().map_err(|e| e.to_string())? {
-        let entry = entry.map_err(|e| e.to_string())?;
-        let path_str = entry
-            .path()
-            .map_err(|e| e.to_string())?
-            .to_string_lossy()
-            .to_string();
-        let is_dir = entry.header().entry_type().is_dir();
-
-        let file_name = if is_dir {
-            path_str
-                .trim_end_matches('/')
-                .split('/')
-                .last()
-                .unwrap_or(&path_str)
-                .to_string()
-        } else {
-            path_str.split('/').last().unwrap_or(&path_str).to_string()
-        };
-
-        let date = entry
-            .header()
-            .mtime()
-            .ok()
-            .map(|ts| {
-                chrono::DateTime::from_timestamp(ts as i64, 0)
-                    .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
-                    .unwrap_or_default()
-            })
-            .unwrap_or_default();
-
-        entries.push(ArchiveEntry {
-            name: file_name,
-            path: path_str.trim_end_matches('/').to_string(),
-            size: entry.header().size().unwrap_or(0),
-            dir: is_dir,
-            date,
-        });
-    }
-
-    Ok(entries)
-}
-
-/// Preview TAR.XZ / TXZ file contents
-fn preview_tar_xz(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
-    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
-    let xz = xz2::read::XzDecoder::new(file);
-    let mut archive = tar::Archive::new(xz);
-
-    let mut entries = Vec::new();
-    for entry in archive.entries().map_err(|e| e.to_string())? {
-        let entry = entry.map_err(|e| e.to_string())?;
-        let path_str = entry
-            .path()
-            .map_err(|e| e.to_string())?
-            .to_string_lossy()
-            .to_string();
-        let is_dir = entry.header().entry_type().is_dir();
-
-        let file_name = if is_dir {
-            path_str
-                .trim_end_matches('/')
-                .split('/')
-                .last()
-                .unwrap_or(&path_str)
-                .to_string()
-        } else {
-            path_str.split('/').last().unwrap_or(&path_str).to_string()
-        };
-
-        let date = entry
-            .header()
-            .mtime()
-            .ok()
-            .map(|ts| {
-                chrono::DateTime::from_timestamp(ts as i64, 0)
-                    .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
-                    .unwrap_or_default()
-            })
-            .unwrap_or_default();
-
-        entries.push(ArchiveEntry {
-            name: file_name,
-            path: path_str.trim_end_matches('/').to_string(),
-            size: entry.header().size().unwrap_or(0),
+            size,
             dir: is_dir,
             date,
         });
@@ -368,7 +364,7 @@ fn preview_tar_xz(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
 }
 
 /// Preview RAR file contents
-fn preview_rar(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
+fn preview_rar(path: &PathBuf, budget: &mut EntryBudget) -> Result<Vec<ArchiveEntry>, ArchiveError> {
     let archive =
         unrar::Archive::new(path).open_for_listing().map_err(|e| format!("{:?}", e))?;
 
@@ -376,8 +372,8 @@ fn preview_rar(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
     for entry in archive {
         let entry = entry.map_err(|e| format!("{:?}", e))?;
         let path_str = entry.filename.to_string_lossy().to_string();
+        budget.add(&path_str, entry.unpacked_size)?;
         let is_dir = entry.is_directory();
-
         let file_name = if is_dir {
             path_str
                 .trim_end_matches(['/', '\\'])
@@ -414,11 +410,16 @@ fn preview_rar(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
 }
 
 /// Preview 7z file contents
-fn preview_7z(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
+fn preview_7z(path: &PathBuf, budget: &mut EntryBudget) -> Result<Vec<ArchiveEntry>, ArchiveError> {
     let mut entries = Vec::new();
+    let mut exceeded = None;
 
-    sevenz_rust::decompress_file_with_extract_fn(path, ".", |entry, _, _| {
+    let result = sevenz_rust::decompress_file_with_extract_fn(path, ".", |entry, _, _| {
         let path_str = entry.name().to_string();
+        if let Err(e) = budget.add(&path_str, entry.size()) {
+            exceeded = Some(e);
+            return Ok(false);
+        }
         let is_dir = entry.is_directory();
 
         let file_name = if is_dir {
@@ -455,8 +456,74 @@ fn preview_7z(path: &PathBuf) -> Result<Vec<ArchiveEntry>, String> {
 
         // Return Ok with true to continue iteration without extracting
         Ok(true)
-    })
-    .map_err(|e| format!("{:?}", e))?;
+    });
+    if let Some(e) = exceeded {
+        return Err(e);
+    }
+    result.map_err(|e| format!("{:?}", e))?;
 
     Ok(entries)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_zip(dir: &Path, entries: &[&str]) -> PathBuf {
+        let path = dir.join("test.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        for name in entries {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(b"0123456789").unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    fn write_tar_gz(dir: &Path, size: u64) -> PathBuf {
+        let path = dir.join("test.tar.gz");
+        let gz = flate2::write::GzEncoder::new(std::fs::File::create(&path).unwrap(), flate2::Compression::default());
+        let mut tar = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "zeros.bin", std::io::repeat(0).take(size)).unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_limits() {
+        let dir = std::env::temp_dir().join(format!("datadisk-archive-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = ArchiveLimitsConfig {
+            max_entries: 3,
+            max_total_size_mb: 1,
+            max_depth: 2,
+        };
+
+        let zip = write_zip(&dir, &["a.txt", "docs/b.txt", "docs/c.txt"]);
+        assert_eq!(list_entries_with(&zip, &config).unwrap().unwrap().len(), 3);
+        let zip = write_zip(&dir, &["a.txt", "b.txt", "c.txt", "d.txt"]);
+        assert_eq!(list_entries_with(&zip, &config).unwrap().unwrap_err(), ArchiveError::TooManyEntries(3));
+        let zip = write_zip(&dir, &["a/b/c.txt"]);
+        let error = list_entries_with(&zip, &config).unwrap().unwrap_err();
+        assert_eq!(error, ArchiveError::TooDeep(2));
+        assert_eq!(error.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // A few KiB declaring more than the limit
+        let tar_gz = write_tar_gz(&dir, 2 * 1024 * 1024);
+        assert!(std::fs::metadata(&tar_gz).unwrap().len() < 64 * 1024);
+        assert_eq!(list_entries_with(&tar_gz, &config).unwrap().unwrap_err(), ArchiveError::TooLarge(1));
+        let unlimited = ArchiveLimitsConfig {
+            max_entries: 0,
+            max_total_size_mb: 0,
+            max_depth: 0,
+        };
+        assert_eq!(list_entries_with(&tar_gz, &unlimited).unwrap().unwrap()[0].size, 2 * 1024 * 1024);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,325 @@
+//! Temp artifact storage
+//!
+//! Short-lived files produced for a user - built download archives,
+//! conversion outputs, export bundles, OCR intermediates - are kept in one
+//! managed area laid out as `{artifact_dir}/{kind}/{username}/`. Each user's
+//! artifacts count against the artifact quota, and anything older than the
+//! TTL is removed periodically. Admins can inspect and clear the area.
+
+use axum::{extract::State, response::Json, Extension};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::config::Config;
+use crate::entity::op_log::OpType;
+use crate::handlers::abuse;
+use crate::handlers::audit::service::log_admin_operation;
+use crate::handlers::file::is_safe_filename;
+use crate::handlers::quota::{format_size, parse_quota};
+use crate::handlers::trash::dir_size;
+use crate::middleware::auth::CurrentUser;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+
+const OP_SUCCESS: &str = "成功";
+
+/// Kind of artifact, each kept in its own subdirectory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactKind {
+    /// Zip archives built for resumable downloads
+    Archive,
+    /// Outputs of document conversions
+    Conversion,
+    /// Export bundles
+    Export,
+    /// OCR intermediates
+    Ocr,
+}
+
+impl ArtifactKind {
+    pub const ALL: [ArtifactKind; 4] = [
+        ArtifactKind::Archive,
+        ArtifactKind::Conversion,
+        ArtifactKind::Export,
+        ArtifactKind::Ocr,
+    ];
+
+    pub fn dir_name(&self) -> &'static str {
+        match self {
+            ArtifactKind::Archive => "archives",
+            ArtifactKind::Conversion => "conversions",
+            ArtifactKind::Export => "exports",
+            ArtifactKind::Ocr => "ocr",
+        }
+    }
+}
+
+/// Directory holding a user's artifacts of one kind
+pub fn user_dir(config: &Config, kind: ArtifactKind, username: &str) -> PathBuf {
+    config.artifact_dir().join(kind.dir_name()).join(username)
+}
+
+/// Bytes used by all artifacts of a user
+pub fn usage(config: &Config, username: &str) -> i64 {
+    ArtifactKind::ALL
+        .iter()
+        .map(|kind| dir_size(&user_dir(config, *kind, username)) as i64)
+        .sum()
+}
+
+/// Check that `additional` bytes of artifacts fit in the user's artifact quota
+///
+/// Returns the user facing message if they don't.
+pub async fn check_quota(config: &Config, username: &str, additional: i64) -> Result<(), String> {
+    let Some(limit) = parse_quota(&config.artifacts.user_quota) else {
+        return Ok(());
+    };
+    let used = {
+        let config = config.clone();
+        let username = username.to_string();
+        tokio::task::spawn_blocking(move || usage(&config, &username))
+            .await
+            .unwrap_or(0)
+    };
+    if used + additional > limit {
+        return Err(format!(
+            "临时文件空间不足, 已使用 {} / 配额 {}",
+            format_size(used),
+            format_size(limit)
+        ));
+    }
+    Ok(())
+}
+
+/// Remove a file or directory
+async fn remove_entry(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path).await?.is_dir() {
+        fs::remove_dir_all(path).await
+    } else {
+        fs::remove_file(path).await
+    }
+}
+
+/// Remove artifacts older than the TTL
+async fn purge_expired(config: &Config) -> std::io::Result<usize> {
+    let cutoff = std::time::SystemTime::now()
+        - std::time::Duration::from_secs(config.artifacts.ttl_hours * 3600);
+
+    let mut removed = 0;
+    for kind in ArtifactKind::ALL {
+        let Ok(mut users) = fs::read_dir(config.artifact_dir().join(kind.dir_name())).await else {
+            continue;
+        };
+        while let Some(user_dir) = users.next_entry().await? {
+            let mut entries = fs::read_dir(user_dir.path()).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let expired = entry
+                    .metadata()
+                    .await
+                    .and_then(|m| m.modified())
+                    .is_ok_and(|t| t < cutoff);
+                if expired && remove_entry(&entry.path()).await.is_ok() {
+                    removed += 1;
+                }
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Start periodic removal of expired artifacts
+pub fn start(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            ticker.tick().await;
+            match purge_expired(&state.config).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Removed {} expired artifacts", n),
+                Err(e) => tracing::error!("Failed to remove expired artifacts: {}", e),
+            }
+        }
+    });
+}
+
+/// Artifact usage of one user and kind
+#[derive(Debug, Serialize)]
+pub struct ArtifactUsage {
+    pub username: String,
+    pub kind: ArtifactKind,
+    pub count: u64,
+    pub size: i64,
+    /// Modification time of the oldest artifact (Unix timestamp)
+    #[serde(rename = "oldestAt")]
+    pub oldest_at: i64,
+}
+
+/// Collect usage of every user and kind
+fn collect_usage(config: &Config) -> Vec<ArtifactUsage> {
+    let mut result = Vec::new();
+    for kind in ArtifactKind::ALL {
+        let Ok(users) = std::fs::read_dir(config.artifact_dir().join(kind.dir_name())) else {
+            continue;
+        };
+        for user_dir in users.flatten() {
+            let Ok(entries) = std::fs::read_dir(user_dir.path()) else {
+                continue;
+            };
+            let mut usage = ArtifactUsage {
+                username: user_dir.file_name().to_string_lossy().to_string(),
+                kind,
+                count: 0,
+                size: 0,
+                oldest_at: 0,
+            };
+            for entry in entries.flatten() {
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                usage.count += 1;
+                usage.size += if metadata.is_dir() {
+                    dir_size(&entry.path()) as i64
+                } else {
+                    metadata.len() as i64
+                };
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or(0);
+                if usage.oldest_at == 0 || modified < usage.oldest_at {
+                    usage.oldest_at = modified;
+                }
+            }
+            if usage.count > 0 {
+                result.push(usage);
+            }
+        }
+    }
+    result.sort_by_key(|u| std::cmp::Reverse(u.size));
+    result
+}
+
+/// GET /api/artifact/usage - Artifact usage per user and kind
+pub async fn get_usage(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Json<ApiResponse<Vec<ArtifactUsage>>> {
+    if !current_user.can_audit() {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let config = state.config.clone();
+    match tokio::task::spawn_blocking(move || collect_usage(&config)).await {
+        Ok(usage) => Json(ApiResponse::success(usage)),
+        Err(e) => {
+            tracing::error!("Failed to collect artifact usage: {}", e);
+            Json(ApiResponse::error(500, "internal error"))
+        }
+    }
+}
+
+/// Clean artifacts request
+#[derive(Debug, Deserialize)]
+pub struct CleanRequest {
+    pub username: String,
+    /// Only clean this kind, all kinds if not set
+    pub kind: Option<ArtifactKind>,
+}
+
+/// POST /api/artifact/clean - Remove a user's artifacts
+pub async fn clean(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<CleanRequest>,
+) -> Json<ApiResponse<()>> {
+    if !current_user.can_audit() {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+    if !is_safe_filename(&req.username) {
+        return Json(ApiResponse::error(400, "invalid username"));
+    }
+
+    let kinds = match req.kind {
+        Some(kind) => vec![kind],
+        None => ArtifactKind::ALL.to_vec(),
+    };
+    for kind in &kinds {
+        let dir = user_dir(&state.config, *kind, &req.username);
+        match fs::remove_dir_all(&dir).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                tracing::error!("Failed to remove artifacts {:?}: {}", dir, e);
+                return Json(ApiResponse::error(500, "删除临时文件失败"));
+            }
+        }
+    }
+
+    let kinds: Vec<&str> = kinds.iter().map(|k| k.dir_name()).collect();
+    log_admin_operation(
+        &current_user.username,
+        OpType::CleanArtifacts,
+        &format!("{} ({})", req.username, kinds.join(", ")),
+        OP_SUCCESS,
+        None,
+    );
+    Json(ApiResponse::success_msg("临时文件已清理"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> Config {
+        let mut config = Config::default();
+        config.artifacts.dir = Some(std::env::temp_dir().join(format!("artifact-test-{}", uuid::Uuid::new_v4())));
+        config
+    }
+
+    #[test]
+    fn test_usage() {
+        let config = test_config();
+        for (kind, size) in [(ArtifactKind::Archive, 100), (ArtifactKind::Ocr, 20)] {
+            let dir = user_dir(&config, kind, "alice");
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("a"), vec![0u8; size]).unwrap();
+        }
+        let other = user_dir(&config, ArtifactKind::Archive, "bob");
+        std::fs::create_dir_all(&other).unwrap();
+        std::fs::write(other.join("b"), vec![0u8; 7]).unwrap();
+
+        assert_eq!(usage(&config, "alice"), 120);
+        assert_eq!(usage(&config, "bob"), 7);
+        assert_eq!(usage(&config, "carol"), 0);
+
+        let collected = collect_usage(&config);
+        assert_eq!(collected.len(), 3);
+        assert_eq!((collected[0].username.as_str(), collected[0].kind), ("alice", ArtifactKind::Archive));
+        assert_eq!(collected[0].size, 100);
+
+        std::fs::remove_dir_all(config.artifact_dir()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired() {
+        let mut config = test_config();
+        let dir = user_dir(&config, ArtifactKind::Export, "alice");
+        std::fs::create_dir_all(dir.join("bundle")).unwrap();
+        std::fs::write(dir.join("bundle").join("x"), b"x").unwrap();
+        std::fs::write(dir.join("y"), b"y").unwrap();
+
+        assert_eq!(purge_expired(&config).await.unwrap(), 0);
+        // With a zero TTL everything is expired
+        config.artifacts.ttl_hours = 0;
+        assert_eq!(purge_expired(&config).await.unwrap(), 2);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(config.artifact_dir()).unwrap();
+    }
+}
@@ -3,8 +3,10 @@
 //! Implements operation log query and management
 
 use axum::{
+    body::Body,
     extract::Query,
-    response::Json,
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
     Extension,
 };
 use sea_orm::{
@@ -12,11 +14,16 @@ use sea_orm::{
 };
 use serde::{Deserialize, Serialize};
 
-use crate::entity::op_log;
+use crate::entity::op_log::{self, Lang, OpType};
+use crate::entity::user;
+use crate::handlers::abuse;
+use crate::handlers::department::get_department_subtree_ids;
 use crate::middleware::auth::CurrentUser;
 use crate::middleware::DbConn;
 use crate::routes::ApiResponse;
 
+const OP_SUCCESS: &str = "成功";
+
 /// Query parameters for log pagination
 #[derive(Debug, Deserialize)]
 pub struct LogQuery {
@@ -24,6 +31,25 @@ pub struct LogQuery {
     pub page: i64,
     #[serde(rename = "pageSize", default = "default_page_size")]
     pub page_size: i64,
+    /// Only return logs of this category ("general" or "admin")
+    pub category: Option<String>,
+    /// Language of the operation type names ("zh" or "en", default "zh")
+    pub lang: Option<String>,
+    /// Only return logs of this batch request
+    #[serde(rename = "correlationId")]
+    pub correlation_id: Option<String>,
+}
+
+/// Query parameters for log export
+#[derive(Debug, Deserialize)]
+pub struct LogExportQuery {
+    pub category: Option<String>,
+    /// Start time (Unix timestamp, inclusive)
+    pub start: Option<i64>,
+    /// End time (Unix timestamp, inclusive)
+    pub end: Option<i64>,
+    /// Language of the operation type names ("zh" or "en", default "zh")
+    pub lang: Option<String>,
 }
 
 fn default_page() -> i64 {
@@ -41,27 +67,42 @@ pub struct LogResponse {
     #[serde(rename = "opTime")]
     pub op_time: i64,
     pub username: String,
+    /// Display name of the operation type
     #[serde(rename = "opType")]
     pub op_type: String,
+    /// Code of the operation type, see [`OpType::code`]
+    #[serde(rename = "opTypeCode")]
+    pub op_type_code: String,
     #[serde(rename = "opDesc")]
     pub op_desc: String,
     #[serde(rename = "oldValue")]
     pub old_value: String,
     pub result: String,
     pub ip: String,
+    pub category: String,
+    /// Shared by the logs of one batch request
+    #[serde(rename = "correlationId", skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Whether the operator is a service account
+    #[serde(rename = "serviceAccount")]
+    pub service_account: bool,
 }
 
-impl From<op_log::Model> for LogResponse {
-    fn from(m: op_log::Model) -> Self {
+impl LogResponse {
+    fn new(m: op_log::Model, lang: Lang) -> Self {
         Self {
             id: m.id,
             op_time: m.op_time,
             username: m.username,
-            op_type: m.op_type,
+            op_type: op_log::op_type_label(&m.op_type, lang),
+            op_type_code: m.op_type,
             op_desc: m.op_desc,
             old_value: m.old_value.unwrap_or_default(),
             result: m.result,
             ip: m.ip.unwrap_or_default(),
+            category: m.category,
+            correlation_id: m.correlation_id,
+            service_account: false,
         }
     }
 }
@@ -73,45 +114,93 @@ pub struct LogQueryResponse {
     pub total: u64,
 }
 
+/// Which logs a user may view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AuditScope {
+    /// All logs
+    Global,
+    /// Logs of users in the viewer's department subtree
+    Department(i64),
+    None,
+}
+
 /// Check if user has audit permission
 fn can_view_audit(user: &CurrentUser) -> bool {
     user.can_audit()
 }
 
+/// Determine the audit scope of a user
+fn audit_scope(user: &CurrentUser) -> AuditScope {
+    if user.can_audit() {
+        AuditScope::Global
+    } else if user.can_audit_dept() {
+        AuditScope::Department(user.department_id)
+    } else {
+        AuditScope::None
+    }
+}
+
 /// GET /api/oplog/query
 pub async fn query_oplog(
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Query(query): Query<LogQuery>,
 ) -> Json<LogQueryResponse> {
-    // Permission check: only admin can view audit logs
-    if !can_view_audit(&current_user) {
-        return Json(LogQueryResponse { logs: vec![], total: 0 });
+    let db = &*db;
+
+    let mut select = match scoped_select(db, &current_user).await {
+        Some(select) => select,
+        None => return Json(LogQueryResponse { logs: vec![], total: 0 }),
+    };
+    if let Some(category) = query.category.as_deref().filter(|c| !c.is_empty()) {
+        select = select.filter(op_log::Column::Category.eq(category));
+    }
+    if let Some(id) = query.correlation_id.as_deref().filter(|id| !id.is_empty()) {
+        select = select.filter(op_log::Column::CorrelationId.eq(id));
     }
 
-    let db = &*db;
     let page = query.page.max(1) as u64;
     let page_size = query.page_size.max(1).min(100) as u64;
     let offset = (page - 1) * page_size;
 
     // Query logs with pagination
-    let result = op_log::Entity::find()
+    let result = select
+        .clone()
         .order_by_desc(op_log::Column::Id)
         .offset(offset)
         .limit(page_size)
         .all(db)
         .await;
 
-    let logs = match result {
-        Ok(logs) => logs.into_iter().map(|l| l.into()).collect(),
+    let mut logs: Vec<LogResponse> = match result {
+        Ok(logs) => {
+            let lang = Lang::parse(query.lang.as_deref());
+            logs.into_iter().map(|l| LogResponse::new(l, lang)).collect()
+        }
         Err(e) => {
             tracing::error!("Failed to query logs: {}", e);
             return Json(LogQueryResponse { logs: vec![], total: 0 });
         }
     };
 
+    // Mark operations performed by service accounts
+    let usernames: Vec<String> = logs.iter().map(|l| l.username.clone()).collect();
+    match user::Entity::find()
+        .filter(user::Column::Username.is_in(usernames))
+        .filter(user::Column::AccountType.eq(user::ACCOUNT_TYPE_SERVICE))
+        .all(db)
+        .await
+    {
+        Ok(accounts) => {
+            for log in logs.iter_mut() {
+                log.service_account = accounts.iter().any(|a| a.username == log.username);
+            }
+        }
+        Err(e) => tracing::error!("Failed to query service accounts: {}", e),
+    }
+
     // Get total count
-    let total = match op_log::Entity::find().count(db).await {
+    let total = match select.count(db).await {
         Ok(count) => count,
         Err(e) => {
             tracing::error!("Failed to count logs: {}", e);
@@ -122,17 +211,375 @@ pub async fn query_oplog(
     Json(LogQueryResponse { logs, total })
 }
 
-/// POST /api/oplog/delete
+/// Operation type with its display name
+#[derive(Debug, Serialize)]
+pub struct OpTypeResponse {
+    pub code: &'static str,
+    pub name: &'static str,
+}
+
+/// Query parameters of the operation type list
+#[derive(Debug, Deserialize)]
+pub struct OpTypeQuery {
+    /// Language of the names ("zh" or "en", default "zh")
+    pub lang: Option<String>,
+}
+
+/// GET /api/oplog/types - Codes and display names of the operation types
+pub async fn get_op_types(Query(query): Query<OpTypeQuery>) -> Json<Vec<OpTypeResponse>> {
+    let lang = Lang::parse(query.lang.as_deref());
+    Json(
+        OpType::ALL
+            .into_iter()
+            .map(|op| OpTypeResponse { code: op.code(), name: op.label(lang) })
+            .collect(),
+    )
+}
+
+/// Build the base log query visible to the user, or None if they may not view logs
+///
+/// Global auditors see everything, department auditors only see operations of
+/// users in their department subtree.
+async fn scoped_select(
+    db: &sea_orm::DatabaseConnection,
+    user: &CurrentUser,
+) -> Option<sea_orm::Select<op_log::Entity>> {
+    let select = op_log::Entity::find();
+    match audit_scope(user) {
+        AuditScope::Global => Some(select),
+        AuditScope::Department(dept_id) => match department_usernames(db, dept_id).await {
+            Ok(usernames) => Some(select.filter(op_log::Column::Username.is_in(usernames))),
+            Err(e) => {
+                tracing::error!("Failed to resolve department users: {}", e);
+                None
+            }
+        },
+        AuditScope::None => None,
+    }
+}
+
+/// GET /api/oplog/export - Export logs as CSV
+pub async fn export_oplog(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<LogExportQuery>,
+) -> Response {
+    let db = db.0.clone();
+    let Some(mut select) = scoped_select(&db, &current_user).await else {
+        abuse::record_denied(&current_user.username);
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": "forbidden"})),
+        ).into_response();
+    };
+
+    let category = query.category.clone().filter(|c| !c.is_empty());
+    if let Some(category) = &category {
+        select = select.filter(op_log::Column::Category.eq(category.as_str()));
+    }
+    if let Some(start) = query.start {
+        select = select.filter(op_log::Column::OpTime.gte(start));
+    }
+    if let Some(end) = query.end {
+        select = select.filter(op_log::Column::OpTime.lte(end));
+    }
+
+    let lang = Lang::parse(query.lang.as_deref());
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Vec<u8>, std::io::Error>>(4);
+    tokio::spawn(async move {
+        // UTF-8 BOM so spreadsheet software detects the encoding of Chinese text
+        let header = "\u{feff}id,time,username,category,type_code,type,description,result,ip\n";
+        if tx.send(Ok(header.as_bytes().to_vec())).await.is_err() {
+            return;
+        }
+
+        let mut paginator = select.order_by_asc(op_log::Column::Id).paginate(&db, 1000);
+        loop {
+            let logs = match paginator.fetch_and_next().await {
+                Ok(Some(logs)) => logs,
+                Ok(None) => break,
+                Err(e) => {
+                    tracing::error!("Failed to export logs: {}", e);
+                    let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                    break;
+                }
+            };
+            let mut chunk = String::new();
+            for log in logs {
+                let time = chrono::DateTime::from_timestamp(log.op_time, 0)
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                chunk.push_str(&format!(
+                    "{},{},{},{},{},{},{},{},{}\n",
+                    log.id,
+                    time,
+                    csv_field(&log.username),
+                    log.category,
+                    csv_field(&log.op_type),
+                    csv_field(&op_log::op_type_label(&log.op_type, lang)),
+                    csv_field(&log.op_desc),
+                    csv_field(&log.result),
+                    csv_field(log.ip.as_deref().unwrap_or("")),
+                ));
+            }
+            if tx.send(Ok(chunk.into_bytes())).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let filename = format!("oplog_{}.csv", category.as_deref().unwrap_or("all"));
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename={}", filename))
+        .body(Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)))
+        .unwrap()
+}
+
+/// Quote a CSV field if needed
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Usernames of all users in a department and its descendants
+pub(crate) async fn department_usernames(
+    db: &sea_orm::DatabaseConnection,
+    dept_id: i64,
+) -> Result<Vec<String>, sea_orm::DbErr> {
+    let dept_ids = get_department_subtree_ids(db, dept_id).await?;
+    let users = user::Entity::find()
+        .filter(user::Column::DepartmentId.is_in(dept_ids))
+        .all(db)
+        .await?;
+    Ok(users.into_iter().map(|u| u.username).collect())
+}
+
+/// Logs to delete: by their IDs, or all logs matching a filter
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum DeleteLogRequest {
+    Ids(Vec<i64>),
+    Filter(DeleteLogFilter),
+}
+
+/// Criteria of a bulk deletion, all given ones must match
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct DeleteLogFilter {
+    /// Start time (Unix timestamp, inclusive)
+    pub start: Option<i64>,
+    /// End time (Unix timestamp, inclusive)
+    pub end: Option<i64>,
+    pub username: Option<String>,
+    /// Code of the operation type, see `/api/oplog/types`
+    #[serde(rename = "opType")]
+    pub op_type: Option<String>,
+    pub category: Option<String>,
+    /// Token of the first request with this filter, deletes the logs
+    #[serde(default, skip_serializing)]
+    pub token: Option<String>,
+}
+
+impl DeleteLogFilter {
+    fn is_empty(&self) -> bool {
+        self.start.is_none()
+            && self.end.is_none()
+            && self.username.is_none()
+            && self.op_type.is_none()
+            && self.category.is_none()
+    }
+
+    fn apply(&self, mut select: sea_orm::Select<op_log::Entity>) -> sea_orm::Select<op_log::Entity> {
+        if let Some(start) = self.start {
+            select = select.filter(op_log::Column::OpTime.gte(start));
+        }
+        if let Some(end) = self.end {
+            select = select.filter(op_log::Column::OpTime.lte(end));
+        }
+        if let Some(username) = &self.username {
+            select = select.filter(op_log::Column::Username.eq(username.as_str()));
+        }
+        if let Some(op_type) = &self.op_type {
+            select = select.filter(op_log::Column::OpType.eq(op_type.as_str()));
+        }
+        if let Some(category) = &self.category {
+            select = select.filter(op_log::Column::Category.eq(category.as_str()));
+        }
+        select
+    }
+
+    /// The criteria for the audit log
+    fn describe(&self) -> String {
+        let time = |t: Option<i64>| {
+            t.and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default()
+        };
+        let mut parts = Vec::new();
+        if self.start.is_some() || self.end.is_some() {
+            parts.push(format!("时间 {} ~ {}", time(self.start), time(self.end)));
+        }
+        if let Some(username) = &self.username {
+            parts.push(format!("用户 {}", username));
+        }
+        if let Some(op_type) = &self.op_type {
+            parts.push(format!("类型 {}", op_log::op_type_label(op_type, Lang::Zh)));
+        }
+        if let Some(category) = &self.category {
+            parts.push(format!("类别 {}", category));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Result of a deletion
+#[derive(Debug, Serialize)]
+pub struct DeleteLogResponse {
+    /// Logs matching the filter, or deleted
+    pub count: u64,
+    /// Token to send with the same filter to delete the matching logs, None
+    /// once they are deleted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// Seconds a bulk deletion may be confirmed
+const CONFIRM_SECS: i64 = 300;
+
+/// Logs deleted per statement
+const DELETE_BATCH: u64 = 1000;
+
+/// Bulk deletion waiting for its confirmation
+struct PendingDelete {
+    username: String,
+    filter: DeleteLogFilter,
+    /// Logs written after the first request are kept
+    max_id: i64,
+    expires: i64,
+}
+
+/// Pending bulk deletions by token
+static PENDING: std::sync::LazyLock<dashmap::DashMap<String, PendingDelete>> =
+    std::sync::LazyLock::new(dashmap::DashMap::new);
+
+/// Number and largest ID of the logs matching a filter
+async fn preview_delete(db: &sea_orm::DatabaseConnection, filter: &DeleteLogFilter) -> Result<(u64, i64), sea_orm::DbErr> {
+    let select = filter.apply(op_log::Entity::find());
+    let count = select.clone().count(db).await?;
+    let max_id = select
+        .order_by_desc(op_log::Column::Id)
+        .one(db)
+        .await?
+        .map(|log| log.id)
+        .unwrap_or(0);
+    Ok((count, max_id))
+}
+
+/// Delete the logs matching a filter up to `max_id` in batches, the number
+/// deleted
+async fn purge_matching(
+    db: &sea_orm::DatabaseConnection,
+    filter: &DeleteLogFilter,
+    max_id: i64,
+) -> Result<u64, sea_orm::DbErr> {
+    let mut deleted = 0;
+    loop {
+        let ids: Vec<i64> = filter
+            .apply(op_log::Entity::find())
+            .filter(op_log::Column::Id.lte(max_id))
+            .select_only()
+            .column(op_log::Column::Id)
+            .limit(DELETE_BATCH)
+            .into_tuple()
+            .all(db)
+            .await?;
+        if ids.is_empty() {
+            return Ok(deleted);
+        }
+        let res = op_log::Entity::delete_many()
+            .filter(op_log::Column::Id.is_in(ids))
+            .exec(db)
+            .await?;
+        deleted += res.rows_affected;
+    }
+}
+
+/// POST /api/oplog/delete - Delete logs by ID, or by filter
+///
+/// A filter deletes nothing at first: the response has the number of matching
+/// logs and a token, and the logs are deleted when the same filter is sent
+/// again with the token within five minutes.
 pub async fn delete_oplog(
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
-    Json(ids): Json<Vec<i64>>,
-) -> Json<ApiResponse<()>> {
+    Json(req): Json<DeleteLogRequest>,
+) -> Json<ApiResponse<DeleteLogResponse>> {
     // Permission check: only admin can delete audit logs
     if !can_view_audit(&current_user) {
+        abuse::record_denied(&current_user.username);
         return Json(ApiResponse::error(403, "权限不足，仅管理员可删除审计日志"));
     }
 
+    let mut filter = match req {
+        DeleteLogRequest::Ids(ids) => return delete_by_ids(&db, &current_user, ids).await,
+        DeleteLogRequest::Filter(filter) => filter,
+    };
+    if filter.is_empty() {
+        return Json(ApiResponse::error(400, "请至少指定一个删除条件"));
+    }
+    let now = chrono::Utc::now().timestamp();
+    PENDING.retain(|_, pending| pending.expires > now);
+
+    let Some(token) = filter.token.take() else {
+        let (count, max_id) = match preview_delete(&db, &filter).await {
+            Ok(preview) => preview,
+            Err(e) => {
+                tracing::error!("Failed to count logs: {}", e);
+                return Json(ApiResponse::error(500, "Failed to delete logs"));
+            }
+        };
+        let token = uuid::Uuid::new_v4().to_string();
+        let pending = PendingDelete {
+            username: current_user.username.clone(),
+            filter,
+            max_id,
+            expires: now + CONFIRM_SECS,
+        };
+        PENDING.insert(token.clone(), pending);
+        let mut response = ApiResponse::success(DeleteLogResponse { count, token: Some(token) });
+        response.message = format!("将删除{}条日志，请确认", count);
+        return Json(response);
+    };
+
+    let pending = match PENDING.remove(&token) {
+        Some((_, pending)) if pending.username == current_user.username && pending.filter == filter => pending,
+        _ => return Json(ApiResponse::error(400, "确认已失效，请重新提交删除条件")),
+    };
+    match purge_matching(&db, &pending.filter, pending.max_id).await {
+        Ok(count) => {
+            let op_desc = format!("按条件删除{}条日志: {}", count, pending.filter.describe());
+            service::log_admin_operation(&current_user.username, OpType::DeleteLog, &op_desc, OP_SUCCESS, None);
+            let mut response = ApiResponse::success(DeleteLogResponse { count, token: None });
+            response.message = format!("成功删除{}条日志", count);
+            Json(response)
+        }
+        Err(e) => {
+            tracing::error!("Failed to delete logs: {}", e);
+            Json(ApiResponse::error(500, "Failed to delete logs"))
+        }
+    }
+}
+
+/// Delete the logs with the given IDs
+async fn delete_by_ids(
+    db: &sea_orm::DatabaseConnection,
+    current_user: &CurrentUser,
+    ids: Vec<i64>,
+) -> Json<ApiResponse<DeleteLogResponse>> {
     if ids.is_empty() {
         return Json(ApiResponse::error(400, "No IDs provided"));
     }
@@ -140,13 +587,16 @@ pub async fn delete_oplog(
     // Delete logs
     let result = op_log::Entity::delete_many()
         .filter(op_log::Column::Id.is_in(ids))
-        .exec(&*db)
+        .exec(db)
         .await;
 
     match result {
         Ok(res) => {
-            let message = format!("成功删除{}条日志", res.rows_affected);
-            Json(ApiResponse::success_msg(message))
+            let op_desc = format!("删除{}条日志", res.rows_affected);
+            service::log_admin_operation(&current_user.username, OpType::DeleteLog, &op_desc, OP_SUCCESS, None);
+            let mut response = ApiResponse::success(DeleteLogResponse { count: res.rows_affected, token: None });
+            response.message = format!("成功删除{}条日志", res.rows_affected);
+            Json(response)
         }
         Err(e) => {
             tracing::error!("Failed to delete logs: {}", e);
@@ -157,20 +607,40 @@ pub async fn delete_oplog(
 
 /// Service for adding operation logs
 pub mod service {
-    use sea_orm::{ActiveModelTrait, Set};
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, Set};
     use tokio::sync::mpsc;
 
-    use crate::entity::op_log;
+    use crate::entity::op_log::{self, OpType};
+    use crate::metrics::{self, AuditFailure};
+    use crate::repository::OpLogRepository;
 
     /// Log entry to be added
     #[derive(Debug, Clone)]
     pub struct LogEntry {
         pub username: String,
-        pub op_type: String,
+        pub op_type: OpType,
         pub op_desc: String,
         pub old_value: Option<String>,
         pub result: String,
         pub ip: Option<String>,
+        /// Log category (op_log::CATEGORY_GENERAL or op_log::CATEGORY_ADMIN)
+        pub category: String,
+        /// Set by [`add_log`] inside [`correlated`]
+        pub correlation_id: Option<String>,
+    }
+
+    tokio::task_local! {
+        static CORRELATION_ID: String;
+    }
+
+    /// Run `f` with every log it adds carrying `correlation_id`
+    pub async fn correlated<F: std::future::Future>(correlation_id: String, f: F) -> F::Output {
+        CORRELATION_ID.scope(correlation_id, f).await
+    }
+
+    /// Correlation ID of the enclosing [`correlated`], if any
+    pub(super) fn correlation_id() -> Option<String> {
+        CORRELATION_ID.try_with(|id| id.clone()).ok()
     }
 
     /// Global log channel
@@ -199,47 +669,203 @@ pub mod service {
                 let log = op_log::ActiveModel {
                     op_time: Set(now),
                     username: Set(entry.username),
-                    op_type: Set(entry.op_type),
+                    op_type: Set(entry.op_type.code().to_string()),
                     op_desc: Set(entry.op_desc),
                     old_value: Set(entry.old_value),
                     result: Set(entry.result),
                     ip: Set(entry.ip),
+                    category: Set(entry.category),
+                    correlation_id: Set(entry.correlation_id),
                     ..Default::default()
                 };
 
-                if let Err(e) = log.insert(&db).await {
+                if let Err(e) = db.insert_log(log).await {
                     tracing::error!("Failed to log operation: {}", e);
+                    metrics::count_audit_failure(AuditFailure::Insert);
                 }
             }
         });
     }
 
     /// Add an operation log entry
-    pub fn add_log(entry: LogEntry) {
+    pub fn add_log(mut entry: LogEntry) {
+        if entry.correlation_id.is_none() {
+            entry.correlation_id = correlation_id();
+        }
+        crate::handlers::abuse::observe_log(&entry.username, entry.op_type);
         if let Some(tx) = LOG_TX.get() {
             if tx.try_send(entry).is_err() {
                 tracing::warn!("Log channel is full, operation log dropped");
+                metrics::count_audit_failure(AuditFailure::Dropped);
             }
         } else {
-            tracing::warn!("Audit log service not initialized, log dropped: {} - {}", entry.op_type, entry.op_desc);
+            tracing::warn!("Audit log service not initialized, log dropped: {} - {}", entry.op_type.code(), entry.op_desc);
+            metrics::count_audit_failure(AuditFailure::Dropped);
         }
     }
 
     /// Helper function to create a log entry from request context
     pub fn log_operation(
         username: &str,
-        op_type: &str,
+        op_type: OpType,
         op_desc: &str,
         result: &str,
         ip: Option<&str>,
     ) {
         add_log(LogEntry {
             username: username.to_string(),
-            op_type: op_type.to_string(),
+            op_type,
             op_desc: op_desc.to_string(),
             old_value: None,
             result: result.to_string(),
             ip: ip.map(|s| s.to_string()),
+            category: op_log::CATEGORY_GENERAL.to_string(),
+            correlation_id: None,
         });
     }
+
+    /// Log an administrative action (user, department, role management, ...)
+    ///
+    /// Admin actions go to a separate stream with its own retention and export.
+    pub fn log_admin_operation(
+        username: &str,
+        op_type: OpType,
+        op_desc: &str,
+        result: &str,
+        ip: Option<&str>,
+    ) {
+        add_log(LogEntry {
+            username: username.to_string(),
+            op_type,
+            op_desc: op_desc.to_string(),
+            old_value: None,
+            result: result.to_string(),
+            ip: ip.map(|s| s.to_string()),
+            category: op_log::CATEGORY_ADMIN.to_string(),
+            correlation_id: None,
+        });
+    }
+
+    /// Delete logs of a category older than `retention_days` (0 = keep forever)
+    pub async fn purge_expired(
+        db: &sea_orm::DatabaseConnection,
+        category: &str,
+        retention_days: u64,
+    ) -> Result<u64, sea_orm::DbErr> {
+        if retention_days == 0 {
+            return Ok(0);
+        }
+        let cutoff = chrono::Utc::now().timestamp() - (retention_days * 86400) as i64;
+        let res = op_log::Entity::delete_many()
+            .filter(op_log::Column::Category.eq(category))
+            .filter(op_log::Column::OpTime.lt(cutoff))
+            .exec(db)
+            .await?;
+        Ok(res.rows_affected)
+    }
+
+    /// Apply the per-category log retention, returning the logs deleted
+    pub async fn apply_retention(
+        db: &sea_orm::DatabaseConnection,
+        config: &crate::config::AuditConfig,
+    ) -> Result<u64, sea_orm::DbErr> {
+        let mut purged = 0;
+        for (category, days) in [
+            (op_log::CATEGORY_GENERAL, config.general_retention_days),
+            (op_log::CATEGORY_ADMIN, config.admin_retention_days),
+        ] {
+            purged += purge_expired(db, category, days).await?;
+        }
+        Ok(purged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission::perm;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_op_types() {
+        let codes: std::collections::HashSet<_> = OpType::ALL.iter().map(|op| op.code()).collect();
+        assert_eq!(codes.len(), OpType::ALL.len());
+        for op in OpType::ALL {
+            assert_eq!(OpType::from_code(op.code()), Some(op));
+            assert!(op.code().len() <= 32);
+        }
+        assert_eq!(op_log::op_type_label("upload", Lang::parse(Some("en-US"))), "Upload");
+        assert_eq!(op_log::op_type_label("upload", Lang::parse(None)), "上传");
+        // Types of older versions are shown as they were stored
+        assert_eq!(op_log::op_type_label("统计", Lang::En), "统计");
+    }
+
+    #[tokio::test]
+    async fn test_correlated() {
+        assert_eq!(service::correlation_id(), None);
+        let id = service::correlated("batch-1".to_string(), async {
+            tokio::task::yield_now().await;
+            service::correlation_id()
+        })
+        .await;
+        assert_eq!(id.as_deref(), Some("batch-1"));
+        assert_eq!(service::correlation_id(), None);
+    }
+
+    #[tokio::test]
+    async fn test_audit_scope() {
+        let env = crate::testing::TestEnv::new().await;
+        let user_with = |permissions: &[&str]| CurrentUser { department_id: 7, ..env.user("u", permissions) };
+        assert_eq!(audit_scope(&user_with(&[perm::AUDIT])), AuditScope::Global);
+        assert_eq!(audit_scope(&user_with(&[perm::AUDIT, perm::AUDIT_DEPT])), AuditScope::Global);
+        assert_eq!(audit_scope(&user_with(&[perm::AUDIT_DEPT])), AuditScope::Department(7));
+        assert_eq!(audit_scope(&user_with(&[perm::FILE])), AuditScope::None);
+        env.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_purge_matching() {
+        use sea_orm::{ActiveModelTrait, Set};
+        let env = crate::testing::TestEnv::new().await;
+        for (time, username, op_type) in [(100, "alice", "upload"), (200, "alice", "delete"), (300, "bob", "upload")] {
+            let log = op_log::ActiveModel {
+                op_time: Set(time),
+                username: Set(username.to_string()),
+                op_type: Set(op_type.to_string()),
+                op_desc: Set(String::new()),
+                result: Set(OP_SUCCESS.to_string()),
+                category: Set(op_log::CATEGORY_GENERAL.to_string()),
+                ..Default::default()
+            };
+            log.insert(&env.db).await.unwrap();
+        }
+
+        let filter = DeleteLogFilter {
+            end: Some(250),
+            op_type: Some("upload".to_string()),
+            ..Default::default()
+        };
+        assert!(!filter.is_empty());
+        assert!(DeleteLogFilter::default().is_empty());
+        let (count, max_id) = preview_delete(&env.db, &filter).await.unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(purge_matching(&env.db, &filter, max_id).await.unwrap(), 1);
+        assert_eq!(op_log::Entity::find().count(&env.db).await.unwrap(), 2);
+
+        // Logs written after the preview are kept
+        let filter = DeleteLogFilter {
+            username: Some("alice".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(purge_matching(&env.db, &filter, 0).await.unwrap(), 0);
+        assert_eq!(purge_matching(&env.db, &filter, i64::MAX).await.unwrap(), 1);
+
+        env.close().await;
+    }
 }
@@ -3,31 +3,50 @@
 //! Implements login, logout, and current user endpoints
 
 use axum::{
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{ConnectInfo, State},
+    http::{
+        header::{SET_COOKIE, USER_AGENT},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
     Extension, Json,
 };
-use sea_orm::{EntityTrait, QueryFilter, ColumnTrait, ActiveModelTrait, Set};
+use sea_orm::{ActiveModelTrait, DatabaseConnection, Set};
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 use tower_sessions::Session;
 
 use crate::entity::user;
+use crate::entity::op_log::OpType;
 use crate::handlers::audit::service::log_operation;
-use crate::middleware::auth::{CurrentUser, SESSION_USER_KEY, SESSION_TIMESTAMP_KEY};
+use crate::handlers::auth_provider::{self, Refusal};
+use crate::handlers::device;
+use crate::handlers::lockout;
+use crate::middleware::auth::{CurrentUser, SESSION_DEVICE_KEY, SESSION_USER_KEY, SESSION_TIMESTAMP_KEY};
+use crate::middleware::rate_limit::client_ip;
 use crate::middleware::DbConn;
 use crate::routes::ApiResponse;
+use crate::state::AppState;
 
 /// Operation types for auth
-const OP_LOGIN: &str = "登录";
-const OP_LOGOUT: &str = "登出";
 const OP_SUCCESS: &str = "成功";
 const OP_FAILED: &str = "失败";
 
 /// Login request body
+///
+/// Either a username and password, or an ID token of an OIDC provider
+/// exchanged for a session
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
+    #[serde(default)]
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    #[serde(default, rename = "idToken")]
+    pub id_token: Option<String>,
+    /// Keep this browser signed in after the session ends
+    #[serde(default)]
+    pub remember: bool,
 }
 
 /// Login response
@@ -54,64 +73,54 @@ pub struct CurrentUserResponse {
 }
 
 /// POST /api/login
+///
+/// Credentials go through the provider chain. Locked accounts are refused
+/// before the password is checked, a wrong password counts towards the
+/// lockout.
 pub async fn login(
+    State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     session: Session,
     Json(req): Json<LoginRequest>,
-) -> (StatusCode, Json<serde_json::Value>) {
-    // Validate input
-    if req.username.is_empty() || req.password.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "bad request"})),
-        );
-    }
-
-    // Find user in database
+) -> Response {
     let db = &*db;
-    let user_result = user::Entity::find()
-        .filter(user::Column::Username.eq(&req.username))
-        .one(db)
-        .await;
-
-    let db_user = match user_result {
-        Ok(Some(user)) => user,
-        Ok(None) => {
-            tracing::warn!("Login failed: user not found - {}", req.username);
-            log_operation(&req.username, OP_LOGIN, "用户不存在", OP_FAILED, None);
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(serde_json::json!({"error": "username or password error"})),
-            );
-        }
-        Err(e) => {
-            tracing::error!("Database error during login: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error": "internal error"})),
-            );
-        }
+    let peer = connect_info.map(|ConnectInfo(addr)| addr.ip());
+    let ip = client_ip(&headers, peer, state.config.rate_limit.trust_proxy).map(|ip| ip.to_string());
+    let db_user = match &req.id_token {
+        Some(token) => match auth_provider::authenticate_id_token(&state, db, token).await {
+            Ok(Ok(db_user)) => db_user,
+            Ok(Err(refusal)) => {
+                tracing::warn!("Login failed: ID token refused ({:?})", refusal);
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({"error": "invalid token"})),
+                ).into_response();
+            }
+            Err(e) => {
+                tracing::error!("Error during token login: {}", e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({"error": "internal error"})),
+                ).into_response();
+            }
+        },
+        None => match password_login(&state, db, &req, ip.as_deref()).await {
+            Ok(db_user) => db_user,
+            Err(response) => return response.into_response(),
+        },
     };
-
-    // Verify password using bcrypt
-    let password_valid = bcrypt::verify(&req.password, &db_user.password).unwrap_or(false);
-    if !password_valid {
-        tracing::warn!("Login failed: wrong password - {}", req.username);
-        log_operation(&req.username, OP_LOGIN, "密码错误", OP_FAILED, None);
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "username or password error"})),
-        );
-    }
+    let username = db_user.username.clone();
 
     // Check user status (2 = disabled)
     if db_user.status == 2 {
-        tracing::warn!("Login failed: user disabled - {}", req.username);
-        log_operation(&req.username, OP_LOGIN, "用户已禁用", OP_FAILED, None);
+        tracing::warn!("Login failed: user disabled - {}", username);
+        log_operation(&username, OpType::Login, "用户已禁用", OP_FAILED, None);
         return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({"error": "user is disabled"})),
-        );
+        ).into_response();
     }
 
     // Update last login time
@@ -124,45 +133,148 @@ pub async fn login(
     }
 
     // Save session
-    if let Err(e) = session.insert(SESSION_USER_KEY, &req.username).await {
+    if let Err(e) = session.insert(SESSION_USER_KEY, &username).await {
         tracing::error!("Failed to save session: {}", e);
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({"error": "internal error"})),
-        );
+        ).into_response();
     }
     if let Err(e) = session.insert(SESSION_TIMESTAMP_KEY, chrono::Utc::now().timestamp()).await {
         tracing::error!("Failed to save session timestamp: {}", e);
     }
 
-    tracing::info!("User logged in: {}", req.username);
-    log_operation(&req.username, OP_LOGIN, "", OP_SUCCESS, None);
+    // Keep the browser signed in beyond the session if asked
+    let mut cookies = HeaderMap::new();
+    if req.remember && state.config.remember.enabled {
+        let agent = headers.get(USER_AGENT).and_then(|v| v.to_str().ok()).unwrap_or_default();
+        match device::remember(db, &state.config.remember, &username, agent, ip.as_deref()).await {
+            Ok((id, value)) => {
+                if let Err(e) = session.insert(SESSION_DEVICE_KEY, id).await {
+                    tracing::error!("Failed to save session device: {}", e);
+                }
+                let cookie = device::cookie(value, state.config.remember.days);
+                if let Ok(value) = HeaderValue::from_str(&cookie.to_string()) {
+                    cookies.append(SET_COOKIE, value);
+                }
+            }
+            Err(e) => tracing::error!("Failed to remember device of {}: {}", username, e),
+        }
+    }
+
+    tracing::info!("User logged in: {}", username);
+    log_operation(&username, OpType::Login, "", OP_SUCCESS, ip.as_deref());
 
     (
         StatusCode::OK,
+        cookies,
         Json(serde_json::json!({"message": "login success"})),
     )
+        .into_response()
+}
+
+/// Check the username and password of `req` against the password providers,
+/// the response to send if they are refused
+async fn password_login(
+    state: &AppState,
+    db: &DatabaseConnection,
+    req: &LoginRequest,
+    ip: Option<&str>,
+) -> Result<user::Model, (StatusCode, Json<serde_json::Value>)> {
+    if req.username.is_empty() || req.password.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "bad request"})),
+        ));
+    }
+
+    match lockout::locked_until(db, &req.username).await {
+        Ok(None) => {}
+        Ok(Some(until)) => {
+            tracing::warn!("Login failed: account locked - {}", req.username);
+            log_operation(&req.username, OpType::Login, "账号已锁定", OP_FAILED, ip);
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({"error": "account is locked", "lockedUntil": until})),
+            ));
+        }
+        Err(e) => {
+            tracing::error!("Database error during login: {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": "internal error"})),
+            ));
+        }
+    }
+
+    let refusal = match auth_provider::authenticate_password(state, db, &req.username, &req.password).await {
+        Ok(Ok(db_user)) => {
+            if let Err(e) = lockout::clear(db, &req.username).await {
+                tracing::error!("Failed to clear failed logins of {}: {}", req.username, e);
+            }
+            return Ok(db_user);
+        }
+        Ok(Err(refusal)) => refusal,
+        Err(e) => {
+            tracing::error!("Error during login: {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": "internal error"})),
+            ));
+        }
+    };
+
+    tracing::warn!("Login failed: {:?} - {}", refusal, req.username);
+    log_operation(&req.username, OpType::Login, refusal.describe(), OP_FAILED, ip);
+    // Only wrong passwords of known users count towards the lockout
+    if refusal == Refusal::WrongCredentials {
+        let locked = lockout::record_failure(db, &state.config.lockout, &req.username, ip).await;
+        if let Ok(Some(until)) = locked {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({"error": "account is locked", "lockedUntil": until})),
+            ));
+        }
+        if let Err(e) = locked {
+            tracing::error!("Failed to record failed login of {}: {}", req.username, e);
+        }
+    }
+    Err((
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({"error": "username or password error"})),
+    ))
 }
 
 /// POST /api/logout
+///
+/// Also forgets the device the session was opened with, if remembered.
 pub async fn logout(
+    db: Option<Extension<DbConn>>,
     session: Session,
-    Extension(current_user): Extension<CurrentUser>,
 ) -> impl IntoResponse {
-    let username = current_user.username.clone();
+    let username: Option<String> = session.get(SESSION_USER_KEY).await.unwrap_or(None);
+    let device_id: Option<i64> = session.get(SESSION_DEVICE_KEY).await.unwrap_or(None);
+    if let (Some(Extension(db)), Some(id)) = (db, device_id) {
+        device::forget(&db, id).await;
+    }
+    let removal = [(SET_COOKIE, device::removal_cookie().to_string())];
 
     if let Err(e) = session.flush().await {
         tracing::error!("Failed to flush session: {}", e);
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
+            removal,
             Json(ApiResponse::<()>::error(500, "internal error")),
         );
     }
 
-    log_operation(&username, OP_LOGOUT, "", OP_SUCCESS, None);
+    if let Some(username) = username {
+        log_operation(&username, OpType::Logout, "", OP_SUCCESS, None);
+    }
 
     (
         StatusCode::OK,
+        removal,
         Json(ApiResponse::success_msg("logout success")),
     )
 }
@@ -0,0 +1,430 @@
+//! Authentication provider chain
+//!
+//! Logins and bearer tokens are checked against the providers configured in
+//! `[auth]`, in order, until one accepts them:
+//! - `local`: passwords stored in the users table
+//! - `ldap`: a simple bind to a directory with the login name and password
+//! - `oidc`: ID tokens of an OpenID Connect provider, exchanged for a session
+//!   at login or sent as bearer tokens
+//! - `api_token`: API tokens of users and service accounts
+//!
+//! Users of external providers are matched to local accounts by the
+//! provider's mapping rules, and created at their first login if it allows.
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use std::time::Duration;
+
+use crate::config::{AuthProviderConfig, Config, LdapProviderConfig, OidcProviderConfig, UserMappingConfig};
+use crate::entity::api_token;
+use crate::entity::op_log::OpType;
+use crate::entity::user;
+use crate::handlers::audit::service::log_admin_operation;
+use crate::handlers::file::{get_user_path, is_space_owner};
+use crate::handlers::user::get_department_name;
+use crate::middleware::auth::{find_token, API_TOKEN_PREFIX};
+use crate::outbound;
+use crate::state::AppState;
+
+/// Seconds fetched signing keys are used before fetching them again
+const JWKS_CACHE_SECS: i64 = 3600;
+
+/// Signing keys of OIDC providers: JWKS URL -> (expiry, keys)
+static JWKS: std::sync::LazyLock<dashmap::DashMap<String, (i64, JwkSet)>> =
+    std::sync::LazyLock::new(dashmap::DashMap::new);
+
+/// Why no provider accepted a login, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Refusal {
+    /// No provider knows the user
+    UnknownUser,
+    /// A service account, which authenticates with API tokens only
+    ServiceAccount,
+    /// The user is known but the password or token is wrong
+    WrongCredentials,
+}
+
+impl Refusal {
+    /// Description for the audit log
+    pub fn describe(self) -> &'static str {
+        match self {
+            Refusal::UnknownUser => "用户不存在",
+            Refusal::ServiceAccount => "服务账号不支持密码登录",
+            Refusal::WrongCredentials => "密码错误",
+        }
+    }
+}
+
+/// Outcome of one provider: the user, or why it refused, or a failure to ask
+type Outcome = anyhow::Result<Result<user::Model, Refusal>>;
+
+/// Check a login name and password against the password providers
+///
+/// Failing providers are skipped; their error is returned only if no other
+/// provider got as far as refusing the login.
+pub async fn authenticate_password(
+    state: &AppState,
+    db: &DatabaseConnection,
+    login: &str,
+    password: &str,
+) -> Outcome {
+    let mut refusal = None;
+    let mut error = None;
+    for provider in &state.config.auth.providers {
+        let outcome = match provider {
+            AuthProviderConfig::Local => local(db, login, password).await,
+            AuthProviderConfig::Ldap(ldap) => ldap_bind(state, db, ldap, login, password).await,
+            AuthProviderConfig::Oidc(_) | AuthProviderConfig::ApiToken => continue,
+        };
+        match outcome {
+            Ok(Ok(user)) => return Ok(Ok(user)),
+            Ok(Err(r)) => refusal = refusal.max(Some(r)),
+            Err(e) => {
+                tracing::error!("Authentication provider failed for {}: {}", login, e);
+                error = Some(e);
+            }
+        }
+    }
+    match (refusal, error) {
+        (None, Some(e)) => Err(e),
+        (refusal, _) => Ok(Err(refusal.unwrap_or(Refusal::UnknownUser))),
+    }
+}
+
+/// Check an ID token exchanged at login against the OIDC providers
+pub async fn authenticate_id_token(state: &AppState, db: &DatabaseConnection, token: &str) -> Outcome {
+    let mut refusal = Refusal::UnknownUser;
+    for provider in &state.config.auth.providers {
+        let AuthProviderConfig::Oidc(oidc) = provider else {
+            continue;
+        };
+        match oidc_user(state, db, oidc, token).await? {
+            Ok(user) => return Ok(Ok(user)),
+            Err(r) => refusal = refusal.max(r),
+        }
+    }
+    Ok(Err(refusal))
+}
+
+/// Who a bearer token authenticates
+pub struct BearerUser {
+    pub username: String,
+    /// The API token, whose scopes narrow the user's permissions
+    pub token: Option<api_token::Model>,
+}
+
+/// Check a bearer token against the API token and OIDC providers
+pub async fn authenticate_bearer(state: &AppState, db: &DatabaseConnection, bearer: &str) -> Option<BearerUser> {
+    for provider in &state.config.auth.providers {
+        match provider {
+            AuthProviderConfig::ApiToken if bearer.starts_with(API_TOKEN_PREFIX) => {
+                if let Some(token) = find_token(db, bearer).await {
+                    return Some(BearerUser { username: token.username.clone(), token: Some(token) });
+                }
+            }
+            AuthProviderConfig::Oidc(oidc) => match oidc_user(state, db, oidc, bearer).await {
+                Ok(Ok(user)) => return Some(BearerUser { username: user.username, token: None }),
+                Ok(Err(_)) => {}
+                Err(e) => tracing::error!("OIDC provider {} failed: {}", oidc.issuer, e),
+            },
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Local username of `name` as known to an external provider, None if the
+/// rules leave no valid username
+pub fn map_username(mapping: &UserMappingConfig, name: &str) -> Option<String> {
+    let mut name = name.trim();
+    if mapping.strip_domain {
+        name = name.rsplit_once('\\').map_or(name, |(_, n)| n);
+        name = name.split_once('@').map_or(name, |(n, _)| n);
+    }
+    let name = if mapping.lowercase { name.to_lowercase() } else { name.to_string() };
+    let name = format!("{}{}", mapping.prefix, name);
+    let valid = !name.is_empty()
+        && name.len() <= 32
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        && !name.starts_with('.')
+        && !is_space_owner(&name);
+    valid.then_some(name)
+}
+
+async fn local(db: &DatabaseConnection, login: &str, password: &str) -> Outcome {
+    let Some(db_user) = user::Entity::find()
+        .filter(user::Column::Username.eq(login))
+        .one(db)
+        .await?
+    else {
+        return Ok(Err(Refusal::UnknownUser));
+    };
+    if db_user.is_service_account() {
+        return Ok(Err(Refusal::ServiceAccount));
+    }
+    if !bcrypt::verify(password, &db_user.password).unwrap_or(false) {
+        return Ok(Err(Refusal::WrongCredentials));
+    }
+    Ok(Ok(db_user))
+}
+
+/// Invalid credentials
+const LDAP_INVALID_CREDENTIALS: u32 = 49;
+
+async fn ldap_bind(
+    state: &AppState,
+    db: &DatabaseConnection,
+    provider: &LdapProviderConfig,
+    login: &str,
+    password: &str,
+) -> Outcome {
+    // An empty password would make an anonymous bind, which always succeeds
+    if password.is_empty() {
+        return Ok(Err(Refusal::WrongCredentials));
+    }
+    let timeout = Duration::from_secs(provider.timeout_secs);
+    let settings = ldap3::LdapConnSettings::new()
+        .set_conn_timeout(timeout)
+        .set_starttls(provider.starttls);
+    let (conn, mut ldap) = ldap3::LdapConnAsync::with_settings(settings, &provider.url).await?;
+    ldap3::drive!(conn);
+
+    let dn = provider.bind_dn.replace("{username}", &ldap3::dn_escape(login));
+    let bound = ldap.with_timeout(timeout).simple_bind(&dn, password).await?;
+    if bound.rc == LDAP_INVALID_CREDENTIALS {
+        let _ = ldap.unbind().await;
+        return Ok(Err(Refusal::WrongCredentials));
+    }
+    bound.success()?;
+
+    let (mut full_name, mut email) = (None, None);
+    if !provider.search_base.is_empty() {
+        let filter = provider.user_filter.replace("{username}", &ldap3::ldap_escape(login));
+        let attrs = vec![provider.name_attr.as_str(), provider.mail_attr.as_str()];
+        let (entries, _) = ldap
+            .with_timeout(timeout)
+            .search(&provider.search_base, ldap3::Scope::Subtree, &filter, attrs)
+            .await?
+            .success()?;
+        if let Some(entry) = entries.into_iter().next() {
+            let mut attrs = ldap3::SearchEntry::construct(entry).attrs;
+            let mut first = |attr: &str| attrs.remove(attr).and_then(|values| values.into_iter().next());
+            full_name = first(&provider.name_attr);
+            email = first(&provider.mail_attr);
+        }
+    }
+    let _ = ldap.unbind().await;
+
+    provision(state, db, &provider.mapping, "LDAP", login, full_name, email).await
+}
+
+/// The user an ID token of `provider` is for, refused if the token isn't one
+/// of its tokens
+async fn oidc_user(state: &AppState, db: &DatabaseConnection, provider: &OidcProviderConfig, token: &str) -> Outcome {
+    let Ok(header) = decode_header(token) else {
+        return Ok(Err(Refusal::UnknownUser));
+    };
+    let (key, mut validation) = if !provider.secret.is_empty() {
+        (DecodingKey::from_secret(provider.secret.as_bytes()), Validation::new(Algorithm::HS256))
+    } else {
+        // Keys published for anyone to fetch can't check shared-secret signatures
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Ok(Err(Refusal::UnknownUser));
+        }
+        let keys = signing_keys(&state.config, &provider.jwks_url).await?;
+        let jwk = match &header.kid {
+            Some(kid) => keys.find(kid),
+            None if keys.keys.len() == 1 => keys.keys.first(),
+            None => None,
+        };
+        let Some(jwk) = jwk else {
+            return Ok(Err(Refusal::UnknownUser));
+        };
+        (DecodingKey::from_jwk(jwk)?, Validation::new(header.alg))
+    };
+    validation.set_issuer(&[&provider.issuer]);
+    validation.set_audience(&[&provider.audience]);
+
+    let claims = match decode::<serde_json::Value>(token, &key, &validation) {
+        Ok(data) => data.claims,
+        Err(e) => {
+            tracing::debug!("ID token refused by {}: {}", provider.issuer, e);
+            return Ok(Err(Refusal::WrongCredentials));
+        }
+    };
+    let claim = |name: &str| claims.get(name).and_then(|v| v.as_str()).map(str::to_string);
+    let Some(name) = claim(&provider.username_claim) else {
+        tracing::warn!("ID token of {} has no {} claim", provider.issuer, provider.username_claim);
+        return Ok(Err(Refusal::UnknownUser));
+    };
+    let (full_name, email) = (claim(&provider.name_claim), claim(&provider.email_claim));
+    provision(state, db, &provider.mapping, "OIDC", &name, full_name, email).await
+}
+
+/// Signing keys published at `url`, cached for an hour
+async fn signing_keys(config: &Config, url: &str) -> anyhow::Result<JwkSet> {
+    let now = chrono::Utc::now().timestamp();
+    if let Some(cached) = JWKS.get(url).filter(|cached| cached.0 > now) {
+        return Ok(cached.1.clone());
+    }
+    let response = outbound::get(config, url).await?.error_for_status()?;
+    let keys: JwkSet = serde_json::from_slice(&outbound::read_body(config, response).await?)?;
+    JWKS.insert(url.to_string(), (now + JWKS_CACHE_SECS, keys.clone()));
+    Ok(keys)
+}
+
+/// Local account of `name`, authenticated by the external `source`, created
+/// if the mapping allows
+async fn provision(
+    state: &AppState,
+    db: &DatabaseConnection,
+    mapping: &UserMappingConfig,
+    source: &str,
+    name: &str,
+    full_name: Option<String>,
+    email: Option<String>,
+) -> Outcome {
+    let Some(username) = map_username(mapping, name) else {
+        tracing::warn!("{} user {} has no valid local username", source, name);
+        return Ok(Err(Refusal::UnknownUser));
+    };
+    let find = || user::Entity::find().filter(user::Column::Username.eq(&username)).one(db);
+    match find().await? {
+        Some(existing) if existing.is_service_account() => return Ok(Err(Refusal::ServiceAccount)),
+        Some(existing) => return Ok(Ok(existing)),
+        None if !mapping.auto_create => {
+            tracing::warn!("{} user {} has no local account", source, name);
+            return Ok(Err(Refusal::UnknownUser));
+        }
+        None => {}
+    }
+
+    let dept_name = get_department_name(db, mapping.department_id).await;
+    let new_user = user::ActiveModel {
+        username: Set(username.clone()),
+        // No local password: nothing verifies against an empty hash
+        password: Set(String::new()),
+        full_name: Set(full_name.unwrap_or_else(|| username.clone())),
+        email: Set(email),
+        department_id: Set(mapping.department_id),
+        dept_name: Set(dept_name.clone()),
+        status: Set(0),
+        last_login: Set(0),
+        ..Default::default()
+    };
+    let created = match new_user.insert(db).await {
+        Ok(created) => created,
+        // Created by a login at the same time
+        Err(e) => return find().await?.map(Ok).ok_or_else(|| e.into()),
+    };
+
+    if let Err(e) = tokio::fs::create_dir_all(get_user_path(&state.config, &username)).await {
+        tracing::error!("Failed to create user directory: {}", e);
+    }
+    if let Some(perm) = state.get_perm().await.as_ref() {
+        if !mapping.role.is_empty() {
+            if let Err(e) = perm.set_user_role(&username, Some(&mapping.role)).await {
+                tracing::error!("Failed to assign role: {}", e);
+            }
+        }
+        if let Err(e) = perm.set_user_department(&username, mapping.department_id).await {
+            tracing::error!("Failed to assign department: {}", e);
+        }
+    }
+
+    let op_desc = format!("{}首次登录, 所属部门: {}, 用户名: {}", source, dept_name, username);
+    log_admin_operation(&username, OpType::CreateUser, &op_desc, "成功", None);
+    Ok(Ok(created))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::token::insert_token;
+    use crate::testing::TestEnv;
+
+    #[test]
+    fn test_map_username() {
+        let mapping = UserMappingConfig::default();
+        assert_eq!(map_username(&mapping, "Alice@Corp.Example.com").as_deref(), Some("alice"));
+        assert_eq!(map_username(&mapping, "CORP\\Bob").as_deref(), Some("bob"));
+        assert_eq!(map_username(&mapping, "bad name"), None);
+        assert_eq!(map_username(&mapping, "@corp"), None);
+
+        let mapping = UserMappingConfig { strip_domain: false, lowercase: false, prefix: "ext_".into(), ..Default::default() };
+        assert_eq!(map_username(&mapping, "Carol"), Some("ext_Carol".into()));
+        assert_eq!(map_username(&mapping, "carol@corp"), None);
+    }
+
+    fn id_token(secret: &str, claims: serde_json::Value) -> String {
+        let key = jsonwebtoken::EncodingKey::from_secret(secret.as_bytes());
+        jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_chain() {
+        let mut env = TestEnv::new().await;
+        let oidc = OidcProviderConfig {
+            issuer: "https://idp.example.com".into(),
+            audience: "datadisk".into(),
+            jwks_url: String::new(),
+            secret: "shared".into(),
+            username_claim: "preferred_username".into(),
+            name_claim: "name".into(),
+            email_claim: "email".into(),
+            mapping: UserMappingConfig { auto_create: true, ..Default::default() },
+        };
+        env.config.auth.providers = vec![AuthProviderConfig::Oidc(oidc), AuthProviderConfig::Local];
+        let state = env.state();
+        user::ActiveModel {
+            username: Set("alice".into()),
+            password: Set(bcrypt::hash("secret", 4).unwrap()),
+            full_name: Set("Alice".into()),
+            department_id: Set(0),
+            dept_name: Set(String::new()),
+            status: Set(1),
+            last_login: Set(0),
+            ..Default::default()
+        }
+        .insert(&env.db)
+        .await
+        .unwrap();
+
+        // Passwords go to the local provider only
+        let ok = authenticate_password(&state, &env.db, "alice", "secret").await.unwrap();
+        assert_eq!(ok.unwrap().username, "alice");
+        let wrong = authenticate_password(&state, &env.db, "alice", "nope").await.unwrap();
+        assert_eq!(wrong.unwrap_err(), Refusal::WrongCredentials);
+        let unknown = authenticate_password(&state, &env.db, "nobody", "secret").await.unwrap();
+        assert_eq!(unknown.unwrap_err(), Refusal::UnknownUser);
+
+        // A valid ID token creates the mapped user, at login or as bearer token
+        let exp = chrono::Utc::now().timestamp() + 600;
+        let claims = serde_json::json!({
+            "iss": "https://idp.example.com", "aud": "datadisk", "exp": exp,
+            "preferred_username": "Bob@corp.example.com", "name": "Bob B", "email": "bob@corp.example.com",
+        });
+        let token = id_token("shared", claims.clone());
+        let bob = authenticate_id_token(&state, &env.db, &token).await.unwrap().unwrap();
+        assert_eq!((bob.username.as_str(), bob.full_name.as_str()), ("bob", "Bob B"));
+        assert!(get_user_path(&env.config, "bob").is_dir());
+        let bearer = authenticate_bearer(&state, &env.db, &token).await.unwrap();
+        assert!(bearer.username == "bob" && bearer.token.is_none());
+
+        // Other signers and audiences are refused
+        let forged = id_token("other", claims.clone());
+        assert_eq!(authenticate_id_token(&state, &env.db, &forged).await.unwrap().unwrap_err(), Refusal::WrongCredentials);
+        let mut foreign = claims;
+        foreign["aud"] = "someone-else".into();
+        assert!(authenticate_bearer(&state, &env.db, &id_token("shared", foreign)).await.is_none());
+
+        // API tokens only count with their provider in the chain
+        let api_token = insert_token(&env.db, "alice", "ci", 0, &[]).await.unwrap().token;
+        assert!(authenticate_bearer(&state, &env.db, &api_token).await.is_none());
+        env.config.auth.providers.push(AuthProviderConfig::ApiToken);
+        let bearer = authenticate_bearer(&env.state(), &env.db, &api_token).await.unwrap();
+        assert!(bearer.username == "alice" && bearer.token.is_some());
+        env.close().await;
+    }
+}
@@ -0,0 +1,280 @@
+//! Batch file operations
+//!
+//! `POST /api/file/batch` runs a list of renames, deletes, new folders and
+//! tag changes in order, as the UI sends them for a multi-select action. Each
+//! operation succeeds or fails on its own and gets its result at the same
+//! index of the response. The audit logs written by the batch share one
+//! correlation ID, returned with the results, so `/api/oplog/query` can
+//! show them together.
+
+use axum::{extract::State, response::Json, Extension};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::handlers::audit::service::correlated;
+use crate::handlers::file::{locate_for_write, resolve_in_user_root};
+use crate::handlers::legal_hold;
+use crate::handlers::tag;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::ApiResponse;
+use crate::service::{FileError, FileService, IfMatch};
+use crate::state::AppState;
+
+/// Most operations in one batch
+const MAX_OPERATIONS: usize = 1000;
+
+/// One operation of a batch
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BatchOperation {
+    /// Rename the file at `path` in place
+    Rename {
+        path: String,
+        #[serde(rename = "newName")]
+        new_name: String,
+        /// `lastmod` of the file as listed; fails if it changed since
+        #[serde(default, rename = "ifMatch")]
+        if_match: Option<IfMatch>,
+    },
+    /// Move the file at `path` to the trash
+    Delete {
+        path: String,
+        #[serde(default, rename = "ifMatch")]
+        if_match: Option<IfMatch>,
+    },
+    /// Create the folder `name` in `parentPath`
+    Mkdir {
+        #[serde(rename = "parentPath")]
+        parent_path: String,
+        name: String,
+    },
+    /// Add and remove tags of the file at `path`
+    Tag {
+        path: String,
+        #[serde(default)]
+        add: Vec<String>,
+        #[serde(default)]
+        remove: Vec<String>,
+    },
+}
+
+/// Batch request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperation>,
+}
+
+/// Result of one operation
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchItemResult {
+    pub success: bool,
+    /// HTTP-like status: 200, or the status the single-item endpoint would give
+    pub status: u16,
+    pub message: String,
+}
+
+impl BatchItemResult {
+    fn ok(message: impl Into<String>) -> Self {
+        Self { success: true, status: 200, message: message.into() }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self { success: false, status, message: message.into() }
+    }
+}
+
+impl From<FileError> for BatchItemResult {
+    fn from(e: FileError) -> Self {
+        match e {
+            FileError::InvalidName(e) => Self::error(400, format!("invalid name: {}", e)),
+            FileError::InvalidPath => Self::error(400, "invalid path"),
+            FileError::NotFound => Self::error(404, "file not found"),
+            FileError::AlreadyExists => Self::error(409, "file already exists"),
+            FileError::ParentNotFound => Self::error(400, "parent_dir_not_exists"),
+            FileError::LegalHold(path) => Self::error(423, legal_hold::message(&path)),
+            FileError::Modified(path) => Self::error(412, format!("文件已被修改: {}", path)),
+            e => {
+                tracing::error!("Batch operation failed: {}", e);
+                Self::error(500, "operation failed")
+            }
+        }
+    }
+}
+
+/// Batch response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchResponse {
+    /// Correlation ID of the audit logs of the batch
+    #[serde(rename = "correlationId")]
+    pub correlation_id: String,
+    /// Results in the order of the operations
+    pub results: Vec<BatchItemResult>,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// POST /api/file/batch
+#[utoipa::path(
+    post,
+    path = "/api/file/batch",
+    tag = "file",
+    request_body = BatchRequest,
+    responses((status = 200, body = ApiResponse<BatchResponse>)),
+)]
+pub async fn batch(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<BatchRequest>,
+) -> Json<ApiResponse<BatchResponse>> {
+    if req.operations.is_empty() {
+        return Json(ApiResponse::error(400, "no operations"));
+    }
+    if req.operations.len() > MAX_OPERATIONS {
+        return Json(ApiResponse::error(400, format!("每批最多{}个操作", MAX_OPERATIONS)));
+    }
+
+    let correlation_id = uuid::Uuid::new_v4().to_string();
+    let results = correlated(correlation_id.clone(), async {
+        let mut results = Vec::with_capacity(req.operations.len());
+        for operation in &req.operations {
+            results.push(run(&state, &db, &current_user, operation).await);
+        }
+        results
+    })
+    .await;
+
+    let succeeded = results.iter().filter(|r| r.success).count();
+    Json(ApiResponse::success(BatchResponse {
+        correlation_id,
+        failed: results.len() - succeeded,
+        succeeded,
+        results,
+    }))
+}
+
+/// Run one operation, as the single-item endpoint would
+async fn run(
+    state: &AppState,
+    db: &sea_orm::DatabaseConnection,
+    user: &CurrentUser,
+    operation: &BatchOperation,
+) -> BatchItemResult {
+    match operation {
+        BatchOperation::Rename { path, new_name, if_match } => {
+            let location = match locate_for_write(state, db, user, path).await {
+                Ok(location) => location,
+                Err((status, error)) => return BatchItemResult::error(status.as_u16(), error),
+            };
+            let service = FileService::new(&state.config, db, &location.owner)
+                .on_behalf_of(&user.username, &location.prefix);
+            if let Some(seen) = if_match {
+                if let Err(e) = service.check_unchanged(&location.path, seen).await {
+                    return e.into();
+                }
+            }
+            match service.rename(&location.path, new_name).await {
+                Ok(name) => BatchItemResult::ok(name),
+                Err(e) => e.into(),
+            }
+        }
+        BatchOperation::Delete { path, if_match } => {
+            let path = path.trim_end_matches('/');
+            let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+            let location = match locate_for_write(state, db, user, parent).await {
+                Ok(location) => location,
+                Err((status, error)) => return BatchItemResult::error(status.as_u16(), error),
+            };
+            let service = FileService::new(&state.config, db, &location.owner)
+                .on_behalf_of(&user.username, &location.prefix);
+            if let Some(seen) = if_match {
+                let relative = format!("{}/{}", location.path, name);
+                if let Err(e) = service.check_unchanged(&relative, seen).await {
+                    return e.into();
+                }
+            }
+            match service.delete(&location.path, name).await {
+                Ok(_) => BatchItemResult::ok("deleted"),
+                Err(e) => e.into(),
+            }
+        }
+        BatchOperation::Mkdir { parent_path, name } => {
+            let location = match locate_for_write(state, db, user, parent_path).await {
+                Ok(location) => location,
+                Err((status, error)) => return BatchItemResult::error(status.as_u16(), error),
+            };
+            let service = FileService::new(&state.config, db, &location.owner)
+                .on_behalf_of(&user.username, &location.prefix);
+            match service.mkdir(&location.path, None, name).await {
+                Ok(model) => BatchItemResult::ok(model.name),
+                Err(e) => e.into(),
+            }
+        }
+        BatchOperation::Tag { path, add, remove } => {
+            // Tags are kept for the user's own tree, like /api/file/tag/*
+            let exists = resolve_in_user_root(&state.config, &user.username, path)
+                .is_some_and(|full| full.exists());
+            if !exists {
+                return BatchItemResult::error(404, "文件不存在");
+            }
+            let Some(add) = tag::check_tags(add) else {
+                return BatchItemResult::error(400, tag::INVALID_TAG);
+            };
+            if let Err(e) = tag::remove(db, &user.username, path, remove).await {
+                tracing::error!("Failed to remove tags: {}", e);
+                return BatchItemResult::error(500, "删除标签失败");
+            }
+            match tag::add(db, &user.username, path, add).await {
+                Ok(Some(tags)) => BatchItemResult::ok(tags.join(",")),
+                Ok(None) => BatchItemResult::error(400, tag::too_many_tags()),
+                Err(e) => {
+                    tracing::error!("Failed to add tags: {}", e);
+                    BatchItemResult::error(500, "添加标签失败")
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestApp;
+
+    #[tokio::test]
+    async fn test_batch() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "a.txt", b"hello").await.status().is_success());
+        assert!(admin.upload("/", "b.txt", b"hello").await.status().is_success());
+
+        let body = serde_json::json!({ "operations": [
+            { "op": "mkdir", "parentPath": "/", "name": "docs" },
+            { "op": "rename", "path": "/a.txt", "newName": "c.txt" },
+            { "op": "delete", "path": "/missing.txt" },
+            { "op": "tag", "path": "/c.txt", "add": ["work"] },
+            { "op": "delete", "path": "/b.txt" },
+        ]});
+        let res: serde_json::Value = admin.post_json("/api/file/batch", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], true);
+        let data = &res["data"];
+        let statuses: Vec<u64> = data["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, [200, 200, 404, 200, 200]);
+        assert_eq!(data["succeeded"], 4);
+        assert_eq!(data["failed"], 1);
+        assert_eq!(data["results"][3]["message"], "work");
+
+        let root = crate::handlers::file::get_user_path(&app.env.config, "admin");
+        assert!(root.join("docs").is_dir());
+        assert!(root.join("c.txt").exists());
+        assert!(!root.join("b.txt").exists());
+
+        assert_eq!(data["correlationId"].as_str().unwrap().len(), 36);
+        app.close().await;
+    }
+}
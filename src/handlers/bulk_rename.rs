@@ -0,0 +1,288 @@
+//! Bulk rename
+//!
+//! Renames the entries of a folder whose name matches a pattern, a substring
+//! or a regular expression. The matches are replaced by a template in which
+//! `{n}` stands for a counter, numbering the renamed entries in name order
+//! (`photo_{n}.jpg`); in regex mode `$1` and `${name}` insert groups.
+//! `POST /api/file/rename/preview` answers the plan without touching
+//! anything, `POST /api/file/rename/bulk` checks it and renames as a task.
+
+use axum::{extract::State, response::Json, Extension};
+use regex::{NoExpand, Regex};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use utoipa::ToSchema;
+
+use crate::filename;
+use crate::handlers::dept_space::Location;
+use crate::handlers::file::{get_user_path, is_safe_path, locate_for_write, resolve_in_root};
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+use crate::task::{TaskInfo, TASK_MANAGER};
+
+/// Most entries renamed at once
+const MAX_RENAMES: usize = 10_000;
+
+/// Bulk rename request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BulkRenameRequest {
+    /// Folder of the entries
+    pub path: String,
+    /// Substring, or regular expression with `regex`, to replace
+    pub pattern: String,
+    #[serde(default)]
+    pub regex: bool,
+    /// What replaces the matches, `{n}` is the counter
+    pub replacement: String,
+    /// First value of the counter
+    #[serde(default = "default_start")]
+    pub start: u64,
+    /// Digits of the counter, padded with zeros
+    #[serde(default)]
+    pub width: usize,
+}
+
+fn default_start() -> u64 {
+    1
+}
+
+/// Planned rename of one entry
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RenameItem {
+    pub from: String,
+    pub to: String,
+    /// Why the entry can't get the name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Renames a bulk rename would do
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RenamePlan {
+    /// Matching entries whose name changes, in name order
+    pub items: Vec<RenameItem>,
+    /// Items that can't be renamed
+    pub conflicts: usize,
+}
+
+/// Plan the renames of the entries `names` of a folder
+///
+/// `pattern` is replaced by `replacement` as is unless `expand` is set, then
+/// groups are inserted. Entries are numbered in the order of `names`.
+fn plan(names: &[String], pattern: &Regex, expand: bool, replacement: &str, start: u64, width: usize) -> RenamePlan {
+    let existing: HashSet<&str> = names.iter().map(String::as_str).collect();
+    let mut taken = HashSet::new();
+    let mut items = Vec::new();
+    let mut counter = start;
+    for name in names {
+        if !pattern.is_match(name) {
+            continue;
+        }
+        let template = replacement.replace("{n}", &format!("{:0width$}", counter, width = width));
+        counter += 1;
+        let to = if expand {
+            pattern.replace_all(name, template.as_str())
+        } else {
+            pattern.replace_all(name, NoExpand(&template))
+        };
+        if to == name.as_str() {
+            continue;
+        }
+
+        let (to, error) = match filename::check_new_name(&to) {
+            Ok(to) if to == *name => continue,
+            Ok(to) if existing.contains(to.as_str()) => (to, Some("名称已存在".to_string())),
+            Ok(to) if !taken.insert(to.clone()) => (to, Some("与其他文件重名".to_string())),
+            Ok(to) => (to, None),
+            Err(e) => (to.into_owned(), Some(e.to_string())),
+        };
+        items.push(RenameItem { from: name.clone(), to, error });
+    }
+    let conflicts = items.iter().filter(|item| item.error.is_some()).count();
+    RenamePlan { items, conflicts }
+}
+
+/// Plan the renames of a request in the folder it names
+async fn plan_request(
+    state: &AppState,
+    db: &DbConn,
+    current_user: &CurrentUser,
+    req: &BulkRenameRequest,
+) -> Result<(Location, RenamePlan), (i32, String)> {
+    if !is_safe_path(&req.path) {
+        return Err((400, "invalid path".to_string()));
+    }
+    if req.pattern.is_empty() {
+        return Err((400, "pattern is empty".to_string()));
+    }
+    let source = if req.regex { req.pattern.clone() } else { regex::escape(&req.pattern) };
+    let pattern = Regex::new(&source).map_err(|e| (400, format!("invalid pattern: {}", e)))?;
+
+    let location = locate_for_write(state, db, current_user, &req.path)
+        .await
+        .map_err(|(status, error)| (status.as_u16() as i32, error.to_string()))?;
+    let root = get_user_path(&state.config, &location.owner);
+    let Some(dir) = resolve_in_root(&root, &location.path).filter(|dir| dir.is_dir()) else {
+        return Err((404, "目录不存在".to_string()));
+    };
+    let mut names = Vec::new();
+    let mut entries = tokio::fs::read_dir(&dir).await.map_err(|e| (500, e.to_string()))?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+
+    let plan = plan(&names, &pattern, req.regex, &req.replacement, req.start, req.width);
+    if plan.items.len() > MAX_RENAMES {
+        return Err((400, format!("每次最多重命名{}个文件", MAX_RENAMES)));
+    }
+    Ok((location, plan))
+}
+
+/// POST /api/file/rename/preview - Show what a bulk rename would do
+#[utoipa::path(
+    post,
+    path = "/api/file/rename/preview",
+    tag = "file",
+    request_body = BulkRenameRequest,
+    responses((status = 200, body = ApiResponse<RenamePlan>)),
+)]
+pub async fn preview(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<BulkRenameRequest>,
+) -> Json<ApiResponse<RenamePlan>> {
+    match plan_request(&state, &db, &current_user, &req).await {
+        Ok((_, plan)) => Json(ApiResponse::success(plan)),
+        Err((code, error)) => Json(ApiResponse::error(code, error)),
+    }
+}
+
+/// POST /api/file/rename/bulk - Start a bulk rename
+///
+/// Nothing is renamed if any planned name can't be used.
+#[utoipa::path(
+    post,
+    path = "/api/file/rename/bulk",
+    tag = "file",
+    request_body = BulkRenameRequest,
+    responses((status = 200, body = ApiResponse<TaskInfo>)),
+)]
+pub async fn bulk_rename(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<BulkRenameRequest>,
+) -> Json<ApiResponse<TaskInfo>> {
+    let (location, plan) = match plan_request(&state, &db, &current_user, &req).await {
+        Ok(planned) => planned,
+        Err((code, error)) => return Json(ApiResponse::error(code, error)),
+    };
+    if plan.items.is_empty() {
+        return Json(ApiResponse::error(400, "没有需要重命名的文件"));
+    }
+    if plan.conflicts > 0 {
+        return Json(ApiResponse::error(409, format!("{}个文件无法重命名, 请先预览", plan.conflicts)));
+    }
+
+    let renames = plan.items.into_iter().map(|item| (item.from, item.to)).collect();
+    let info = TASK_MANAGER.create_rename_task(
+        current_user.id,
+        &current_user.username,
+        req.path.clone(),
+        renames,
+        location,
+        state.config.clone(),
+        db.0.clone(),
+    );
+    // Each rename is audited by the task
+    Json(ApiResponse::success(info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn renames(plan: &RenamePlan) -> Vec<(&str, &str, bool)> {
+        plan.items.iter().map(|i| (i.from.as_str(), i.to.as_str(), i.error.is_none())).collect()
+    }
+
+    #[test]
+    fn test_plan() {
+        let listing = names(&["IMG_001.jpg", "IMG_002.jpg", "notes.txt", "photo_1.jpg"]);
+
+        // Substrings are replaced as is, matching entries numbered in order
+        let pattern = Regex::new(&regex::escape("IMG_")).unwrap();
+        let planned = plan(&listing, &pattern, false, "trip-{n}-$1", 7, 3);
+        assert_eq!(
+            renames(&planned),
+            [("IMG_001.jpg", "trip-007-$1001.jpg", true), ("IMG_002.jpg", "trip-008-$1002.jpg", true)]
+        );
+
+        // Whole names with groups, a name taken by an entry is a conflict
+        let pattern = Regex::new(r"^IMG_(\d+)\.jpg$").unwrap();
+        let planned = plan(&listing, &pattern, true, "photo_{n}.jpg", 1, 0);
+        assert_eq!(renames(&planned), [("IMG_001.jpg", "photo_1.jpg", false), ("IMG_002.jpg", "photo_2.jpg", true)]);
+        assert_eq!(planned.conflicts, 1);
+        let planned = plan(&listing, &pattern, true, "$1.jpg", 1, 0);
+        assert_eq!(renames(&planned), [("IMG_001.jpg", "001.jpg", true), ("IMG_002.jpg", "002.jpg", true)]);
+
+        // Names given twice, or not allowed
+        let planned = plan(&listing, &pattern, true, "same.jpg", 1, 0);
+        assert_eq!(renames(&planned), [("IMG_001.jpg", "same.jpg", true), ("IMG_002.jpg", "same.jpg", false)]);
+        let planned = plan(&listing, &pattern, true, "a/{n}", 1, 0);
+        assert_eq!(planned.conflicts, 2);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_rename() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let body = serde_json::json!({ "parentPath": "/", "name": "trip" });
+        assert!(admin.post_json("/api/file/mkdir", &body).await.status().is_success());
+        for name in ["IMG_2.jpg", "IMG_1.jpg", "notes.txt"] {
+            assert!(admin.upload("/trip", name, b"x").await.status().is_success());
+        }
+        let root = get_user_path(&app.env.config, "admin").join("trip");
+
+        let body = serde_json::json!({
+            "path": "/trip",
+            "pattern": r"^IMG_\d+",
+            "regex": true,
+            "replacement": "photo_{n}",
+            "width": 2,
+        });
+        let res: serde_json::Value = admin.post_json("/api/file/rename/preview", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        assert_eq!(res["data"]["items"][0]["from"], "IMG_1.jpg");
+        assert_eq!(res["data"]["items"][0]["to"], "photo_01.jpg");
+        assert_eq!(res["data"]["items"].as_array().unwrap().len(), 2);
+        // The preview renames nothing
+        assert!(root.join("IMG_1.jpg").exists());
+
+        let mut ws = admin.ws().await;
+        let res: serde_json::Value = admin.post_json("/api/file/rename/bulk", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        let id = res["data"]["id"].as_str().unwrap().to_string();
+        ws.wait_for(|m| m["data"]["id"] == id.as_str() && m["data"]["status"] == "completed").await;
+        assert!(root.join("photo_01.jpg").exists());
+        assert!(root.join("photo_02.jpg").exists());
+        assert!(root.join("notes.txt").exists());
+        assert!(!root.join("IMG_1.jpg").exists());
+
+        // Conflicts refuse the whole rename
+        let body = serde_json::json!({ "path": "/trip", "pattern": "photo_01", "replacement": "photo_02" });
+        let res: serde_json::Value = admin.post_json("/api/file/rename/bulk", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        assert!(root.join("photo_01.jpg").exists());
+        app.close().await;
+    }
+}
@@ -0,0 +1,299 @@
+//! Transparent compression
+//!
+//! Files of the types listed in `[compression]` are stored zstd-compressed
+//! once nobody has read or changed them for an hour. They are kept like files
+//! in [cold storage](crate::handlers::tiering): an empty stub holds their
+//! place in the tree and a `disk_cold_file` row points at the compressed copy,
+//! so renames, moves, the trash and every read that recalls cold files work
+//! on them unchanged.
+//!
+//! Downloads and plain previews decompress the copy on the fly with [`open`],
+//! other reads restore the file through [`tiering::recall`]; it is
+//! compressed again an hour after the last read.
+
+use async_compression::tokio::bufread::ZstdDecoder;
+use axum::{response::Json, Extension};
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::io::BufReader;
+
+use crate::config::Config;
+use crate::entity::cold_file;
+use crate::handlers::abuse;
+use crate::handlers::file::get_user_path;
+use crate::handlers::quota;
+use crate::handlers::path::normalize;
+use crate::handlers::tiering::{self, timestamp};
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::mime;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+
+/// Files read or changed within this many seconds are left alone
+const SETTLE_SECS: i64 = 3600;
+
+/// zstd level for a file, None if it is stored as it is
+pub fn level(config: &Config, name: &str) -> Option<i32> {
+    config.compression.extensions.get(&mime::extension(name)).copied()
+}
+
+/// Row of the compressed copy of the file at `path`, None if the file is
+/// stored as it is
+pub async fn stored(
+    db: &DatabaseConnection,
+    username: &str,
+    path: &str,
+) -> Result<Option<cold_file::Model>, DbErr> {
+    let stub = cold_file::Entity::find()
+        .filter(cold_file::Column::Username.eq(username))
+        .filter(cold_file::Column::Path.eq(normalize(path)))
+        .one(db)
+        .await?;
+    Ok(stub.filter(|stub| stub.compressed_size.is_some()))
+}
+
+/// Reader of the original content of a compressed copy
+pub async fn open(stub: &cold_file::Model) -> std::io::Result<ZstdDecoder<BufReader<tokio::fs::File>>> {
+    let file = tokio::fs::File::open(&stub.cold_path).await?;
+    Ok(ZstdDecoder::new(BufReader::new(file)))
+}
+
+/// Write the original content of the compressed copy `source` to `target`
+pub(crate) fn decompress(source: &Path, target: &Path) -> std::io::Result<()> {
+    let input = std::fs::File::open(source)?;
+    let output = std::fs::File::create(target)?;
+    zstd::stream::copy_decode(input, output)
+}
+
+/// Replace a file with a stub and a compressed copy, false if it changed
+/// while it was compressed or didn't get smaller
+async fn compress(
+    db: &DatabaseConnection,
+    dir: &Path,
+    username: &str,
+    root: &Path,
+    path: &str,
+    level: i32,
+    now: i64,
+) -> anyhow::Result<bool> {
+    let full = root.join(path.trim_start_matches('/'));
+    let _guard = tiering::LOCK.lock().await;
+
+    let before = tokio::fs::metadata(&full).await?;
+    tokio::fs::create_dir_all(dir).await?;
+    let copy_path = dir.join(format!("{}.zst", uuid::Uuid::new_v4()));
+    let (source, target) = (full.clone(), copy_path.clone());
+    let compressed = tokio::task::spawn_blocking(move || -> std::io::Result<u64> {
+        let input = std::fs::File::open(&source)?;
+        let output = std::fs::File::create(&target)?;
+        zstd::stream::copy_encode(input, &output, level)?;
+        Ok(output.metadata()?.len())
+    })
+    .await?;
+    let compressed = match compressed {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&copy_path).await;
+            return Err(e.into());
+        }
+    };
+    let after = tokio::fs::metadata(&full).await?;
+    if compressed >= before.len()
+        || after.len() != before.len()
+        || after.modified().ok() != before.modified().ok()
+    {
+        let _ = tokio::fs::remove_file(&copy_path).await;
+        return Ok(false);
+    }
+
+    // Left by a stub replaced outside the web UI
+    tiering::discard(db, username, path).await?;
+    cold_file::ActiveModel {
+        username: Set(username.to_string()),
+        path: Set(path.to_string()),
+        cold_path: Set(copy_path.to_string_lossy().to_string()),
+        size: Set(before.len() as i64),
+        modify_time: Set(timestamp(before.modified())),
+        tier_time: Set(now),
+        compressed_size: Set(Some(compressed as i64)),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    tiering::leave_stub(&full, before.modified())?;
+    Ok(true)
+}
+
+/// Compress the files of the configured types nobody read or changed for an
+/// hour, the number compressed
+pub async fn run(config: &Config, db: &DatabaseConnection, now: i64) -> anyhow::Result<usize> {
+    if !config.compression.enabled {
+        return Ok(0);
+    }
+    let dir = config.compression_dir();
+    let mut compressed = 0;
+    for owner in tiering::owners(config, db).await? {
+        let root = get_user_path(config, &owner);
+        let (walk_root, min_size) = (root.clone(), config.compression.min_size);
+        let files = tokio::task::spawn_blocking(move || {
+            let mut found = Vec::new();
+            tiering::candidates(&walk_root, &walk_root, min_size, now - SETTLE_SECS, &mut found);
+            found
+        })
+        .await?;
+
+        let mut owner_compressed = 0;
+        for path in files {
+            let Some(level) = level(config, &path) else {
+                continue;
+            };
+            match compress(db, &dir, &owner, &root, &path, level, now).await {
+                Ok(true) => owner_compressed += 1,
+                Ok(false) => tracing::debug!("Not compressing {} of {}: changed or incompressible", path, owner),
+                Err(e) => tracing::error!("Failed to compress {} of {}: {}", path, owner, e),
+            }
+        }
+        if owner_compressed > 0 {
+            quota::invalidate(&owner);
+            compressed += owner_compressed;
+        }
+    }
+    Ok(compressed)
+}
+
+/// Start the background job that compresses files
+pub fn start(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            ticker.tick().await;
+            let Some(db) = state.get_db().await else {
+                continue;
+            };
+            match run(&state.config, &db, chrono::Utc::now().timestamp()).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Compressed {} files", n),
+                Err(e) => tracing::error!("Failed to compress files: {}", e),
+            }
+        }
+    });
+}
+
+/// Compressed files of an owner
+#[derive(Debug, Serialize)]
+pub struct CompressionUsage {
+    /// User, or shared space
+    pub username: String,
+    pub count: i64,
+    /// Size of the files as they were written
+    pub size: i64,
+    /// Size of their compressed copies on disk
+    #[serde(rename = "compressedSize")]
+    pub compressed_size: i64,
+}
+
+/// GET /api/compression/usage - Logical and physical size of the compressed
+/// files per owner
+pub async fn get_usage(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Json<ApiResponse<Vec<CompressionUsage>>> {
+    if !current_user.can_audit() {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let stubs = match cold_file::Entity::find()
+        .filter(cold_file::Column::CompressedSize.is_not_null())
+        .all(&*db)
+        .await
+    {
+        Ok(stubs) => stubs,
+        Err(e) => {
+            tracing::error!("Failed to query compressed files: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+    let mut by_owner: BTreeMap<String, CompressionUsage> = BTreeMap::new();
+    for stub in stubs {
+        let usage = by_owner.entry(stub.username.clone()).or_insert_with(|| CompressionUsage {
+            username: stub.username,
+            count: 0,
+            size: 0,
+            compressed_size: 0,
+        });
+        usage.count += 1;
+        usage.size += stub.size;
+        usage.compressed_size += stub.compressed_size.unwrap_or(0);
+    }
+    let mut usage: Vec<CompressionUsage> = by_owner.into_values().collect();
+    usage.sort_by_key(|u| std::cmp::Reverse(u.size));
+    Json(ApiResponse::success(usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::FileService;
+    use crate::testing::TestEnv;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_compress_and_read() {
+        let mut env = TestEnv::new().await;
+        env.config.compression.enabled = true;
+        env.config.compression.min_size = 16;
+        crate::entity::user::ActiveModel {
+            username: Set("alice".to_string()),
+            password: Set(String::new()),
+            full_name: Set("alice".to_string()),
+            department_id: Set(0),
+            dept_name: Set(String::new()),
+            status: Set(1),
+            last_login: Set(0),
+            permissions: Set(String::new()),
+            ..Default::default()
+        }
+        .insert(&env.db)
+        .await
+        .unwrap();
+
+        let service = FileService::new(&env.config, &env.db, "alice");
+        let log = "GET /index.html 200\n".repeat(200);
+        let tmp = env.dir.join("upload.tmp");
+        for (name, data) in [("access.log", log.as_bytes()), ("photo.jpg", log.as_bytes())] {
+            std::fs::write(&tmp, data).unwrap();
+            service.upload_finalize(&tmp, "/", None, name, data.len() as i64, None).await.unwrap();
+        }
+
+        // Only settled files of the listed types are compressed
+        let now = chrono::Utc::now().timestamp();
+        assert_eq!(run(&env.config, &env.db, now).await.unwrap(), 0);
+        assert_eq!(run(&env.config, &env.db, now + 2 * SETTLE_SECS).await.unwrap(), 1);
+        let full = env.config.root_dir.join("alice/access.log");
+        assert_eq!(std::fs::metadata(&full).unwrap().len(), 0);
+        let items = service.list("/").await.unwrap();
+        let item = items.iter().find(|i| i.basename == "access.log").unwrap();
+        assert_eq!((item.size, item.tier.as_deref()), (log.len() as i64, None));
+
+        // Read on the fly, or restored as it was
+        let stub = stored(&env.db, "alice", "access.log").await.unwrap().unwrap();
+        assert!(stub.compressed_size.unwrap() < stub.size);
+        let mut content = String::new();
+        open(&stub).await.unwrap().read_to_string(&mut content).await.unwrap();
+        assert_eq!(content, log);
+        assert!(stored(&env.db, "alice", "photo.jpg").await.unwrap().is_none());
+
+        let root = get_user_path(&env.config, "alice");
+        assert_eq!(tiering::recall(&env.db, "alice", &root, "/").await.unwrap(), 1);
+        assert_eq!(std::fs::read_to_string(&full).unwrap(), log);
+        assert!(cold_file::Entity::find().all(&env.db).await.unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(env.config.compression_dir()).unwrap().count(), 0);
+
+        env.close().await;
+    }
+}
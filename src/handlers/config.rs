@@ -2,23 +2,28 @@
 //!
 //! Returns public configuration settings to the frontend
 
-use axum::{extract::State, response::Json};
+use axum::{extract::State, response::Json, Extension};
 use serde::Serialize;
 
+use crate::handlers::upload_limit;
+use crate::middleware::auth::CurrentUser;
 use crate::state::AppState;
 
 /// Public configuration response
 #[derive(Debug, Serialize)]
 pub struct PublicConfig {
-    /// Maximum upload file size in bytes
+    /// Maximum upload file size of the caller in bytes
     #[serde(rename = "maxUploadSize")]
     pub max_upload_size: usize,
 }
 
 /// GET /api/config
 /// Returns public configuration settings
-pub async fn get_config(State(state): State<AppState>) -> Json<PublicConfig> {
+pub async fn get_config(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Json<PublicConfig> {
     Json(PublicConfig {
-        max_upload_size: state.config.max_upload_size,
+        max_upload_size: upload_limit::limit(&state, &current_user.username).await,
     })
 }
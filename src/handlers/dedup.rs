@@ -0,0 +1,141 @@
+//! Instant upload
+//!
+//! Uploads record the SHA-256 of their content in `disk_file_info`. Before
+//! sending a file, clients may ask `/api/file/upload/check` with its hash and
+//! size; if a file with the same content is already stored, it is hard linked
+//! to the target (copied across filesystems) and the bytes needn't be sent.
+//!
+//! Only files in the trees the user can read are looked at: their own and
+//! the one of the target folder. Otherwise knowing the hash of somebody
+//! else's file would be enough to get a copy of it.
+//!
+//! Files changed outside uploads keep their old hash, so a match is hashed
+//! again before it is linked. Everything that changes a file in place breaks
+//! the link first, see [`tiering::leave_stub`](crate::handlers::tiering::leave_stub).
+
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::entity::file_info;
+use crate::handlers::file::resolve_in_user_root;
+
+/// Whether `hash` looks like a hex SHA-256
+pub fn is_valid(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Hex SHA-256 of a file's content
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Path of a row relative to the root of its tree, None if a parent is gone
+pub(crate) async fn row_path(db: &DatabaseConnection, row: &file_info::Model) -> Result<Option<String>, DbErr> {
+    let mut parts = vec![row.name.clone()];
+    let mut parent_id = row.parent_id;
+    while parent_id > 0 {
+        let Some(parent) = file_info::Entity::find_by_id(parent_id).one(db).await? else {
+            return Ok(None);
+        };
+        parts.push(parent.name);
+        parent_id = parent.parent_id;
+    }
+    parts.reverse();
+    Ok(Some(parts.join("/")))
+}
+
+/// A stored file of `owners` with the given content, None if there is none
+pub async fn find(
+    config: &Config,
+    db: &DatabaseConnection,
+    owners: &[&str],
+    sha256: &str,
+    size: i64,
+) -> Result<Option<PathBuf>, DbErr> {
+    let rows = file_info::Entity::find()
+        .filter(file_info::Column::Sha256.eq(sha256.to_ascii_lowercase()))
+        .filter(file_info::Column::Size.eq(size))
+        .filter(file_info::Column::IsDirectory.eq(false))
+        .filter(file_info::Column::Username.is_in(owners.iter().copied()))
+        .all(db)
+        .await?;
+    for row in rows {
+        let Some(path) = row_path(db, &row).await? else {
+            continue;
+        };
+        let Some(full) = resolve_in_user_root(config, &row.username, &path) else {
+            continue;
+        };
+        // Stubs of cold or compressed files are empty
+        if !tokio::fs::metadata(&full).await.is_ok_and(|m| m.is_file() && m.len() == size as u64) {
+            continue;
+        }
+        let source = full.clone();
+        match tokio::task::spawn_blocking(move || hash_file(&source)).await {
+            Ok(Ok(hash)) if hash.eq_ignore_ascii_case(sha256) => return Ok(Some(full)),
+            Ok(Ok(_)) => tracing::debug!("{} of {} changed since it was uploaded", path, row.username),
+            Ok(Err(e)) => tracing::warn!("Failed to hash {} of {}: {}", path, row.username, e),
+            Err(e) => tracing::error!("Hashing worker panicked: {}", e),
+        }
+    }
+    Ok(None)
+}
+
+/// Make `target` a file with the content of `source`, a hard link if they
+/// are on the same filesystem
+pub async fn link(source: &Path, target: &Path) -> std::io::Result<()> {
+    if tokio::fs::hard_link(source, target).await.is_err() {
+        tokio::fs::copy(source, target).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::FileService;
+    use crate::testing::TestEnv;
+
+    #[tokio::test]
+    async fn test_find_and_link() {
+        let env = TestEnv::new().await;
+        let service = FileService::new(&env.config, &env.db, "alice");
+        let root = env.config.root_dir.join("alice");
+        std::fs::create_dir_all(&root).unwrap();
+        service.mkdir("/", None, "docs").await.unwrap();
+        let tmp = root.join("a.uploading");
+        std::fs::write(&tmp, b"report").unwrap();
+        let sha256 = hash_file(&tmp).unwrap();
+        assert!(is_valid(&sha256));
+        service.upload_finalize(&tmp, "/docs", None, "a.txt", 6, Some(sha256.clone())).await.unwrap();
+
+        // Only the trees of the given owners are looked at
+        let found = find(&env.config, &env.db, &["alice"], &sha256, 6).await.unwrap().unwrap();
+        assert_eq!(found, root.join("docs/a.txt"));
+        assert!(find(&env.config, &env.db, &["bob"], &sha256, 6).await.unwrap().is_none());
+        assert!(find(&env.config, &env.db, &["alice"], &sha256, 7).await.unwrap().is_none());
+
+        let copy = root.join("b.txt");
+        link(&found, &copy).await.unwrap();
+        assert_eq!(std::fs::read(&copy).unwrap(), b"report");
+
+        // Changed outside the web UI
+        std::fs::write(root.join("docs/a.txt"), b"edited").unwrap();
+        assert!(find(&env.config, &env.db, &["alice"], &sha256, 6).await.unwrap().is_none());
+
+        env.close().await;
+    }
+}
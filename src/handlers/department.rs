@@ -13,7 +13,10 @@ use sea_orm::{
 use serde::{Deserialize, Serialize};
 
 use crate::entity::department;
-use crate::handlers::audit::service::log_operation;
+use crate::entity::op_log::OpType;
+use crate::handlers::abuse;
+use crate::handlers::audit::service::{log_admin_operation, log_operation};
+use crate::handlers::dept_admin::{admin_scope, can_grant, AdminScope};
 use crate::middleware::auth::CurrentUser;
 use crate::middleware::DbConn;
 use crate::permission::normalize_permissions;
@@ -21,15 +24,14 @@ use crate::routes::ApiResponse;
 use crate::state::AppState;
 
 // Operation types (matching Go version)
-const OP_CREATE_DEPT: &str = "创建部门信息";
-const OP_DELETE_DEPT: &str = "删除部门信息";
-const OP_UPDATE_DEPT: &str = "修改部门信息";
-const OP_QUERY_DEPT: &str = "查询部门信息";
 const OP_SUCCESS: &str = "成功";
 
-/// Check if user has contacts permission (for department management)
-fn can_manage_departments(user: &CurrentUser) -> bool {
-    user.can_contacts()
+/// Departments `user` may manage, none if they can't be read
+async fn manage_scope(db: &DbConn, user: &CurrentUser) -> AdminScope {
+    admin_scope(db, user).await.unwrap_or_else(|e| {
+        tracing::error!("Failed to load department subtree: {}", e);
+        AdminScope::None
+    })
 }
 
 /// Add department request
@@ -101,17 +103,23 @@ pub async fn add_department(
     Extension(user): Extension<CurrentUser>,
     Json(req): Json<AddDepartmentRequest>,
 ) -> Json<ApiResponse<Option<DepartmentResponse>>> {
-    // Permission check: only admin can add departments
-    if !can_manage_departments(&user) {
+    let parent_id = req.parent_id.unwrap_or(0);
+
+    // Permission check: only admins of the parent can add departments
+    let scope = manage_scope(&db, &user).await;
+    if !scope.covers(parent_id) {
+        abuse::record_denied(&user.username);
         return Json(ApiResponse::error(403, "权限不足，仅管理员可创建部门"));
     }
+    if !can_grant(&user, &normalize_permissions(req.permissions.as_deref().unwrap_or(""))) {
+        abuse::record_denied(&user.username);
+        return Json(ApiResponse::error(403, "权限不足，不能分配该权限"));
+    }
 
     if req.name.chars().count() > 32 {
         return Json(ApiResponse::error(400, "部门名称不能超过32个字符"));
     }
 
-    let parent_id = req.parent_id.unwrap_or(0);
-
     let existing = department::Entity::find()
         .filter(department::Column::Name.eq(&req.name))
         .filter(department::Column::ParentId.eq(parent_id))
@@ -163,7 +171,7 @@ pub async fn add_department(
             } else {
                 format!("部门名称: {}/{}", parent_name, req.name)
             };
-            log_operation(&user.username, OP_CREATE_DEPT, &op_desc, OP_SUCCESS, None);
+            log_admin_operation(&user.username, OpType::CreateDept, &op_desc, OP_SUCCESS, None);
             let mut response = DepartmentResponse::from(dept);
             if let Some(ref enforcer) = state.get_perm().await {
                 if let Ok(perms) = enforcer.get_department_permissions(response.id).await {
@@ -192,7 +200,8 @@ pub async fn delete_department(
     Query(query): Query<IdQuery>,
 ) -> Json<ApiResponse<()>> {
     // Permission check: only admin can delete departments
-    if !can_manage_departments(&user) {
+    if !manage_scope(&db, &user).await.covers_department(query.id) {
+        abuse::record_denied(&user.username);
         return Json(ApiResponse::error(403, "权限不足，仅管理员可删除部门"));
     }
 
@@ -237,7 +246,7 @@ pub async fn delete_department(
             } else {
                 format!("部门名称: {}/{}", dept_info.parent_name, dept_info.name)
             };
-            log_operation(&user.username, OP_DELETE_DEPT, &op_desc, OP_SUCCESS, None);
+            log_admin_operation(&user.username, OpType::DeleteDept, &op_desc, OP_SUCCESS, None);
             Json(ApiResponse::success_msg("success"))
         }
         Err(e) => {
@@ -254,17 +263,23 @@ pub async fn update_department(
     Extension(user): Extension<CurrentUser>,
     Json(req): Json<UpdateDepartmentRequest>,
 ) -> Json<ApiResponse<Option<DepartmentResponse>>> {
-    // Permission check: only admin can update departments
-    if !can_manage_departments(&user) {
+    let parent_id = req.parent_id.unwrap_or(0);
+
+    // Permission check: only admins of the department and its new parent can update it
+    let scope = manage_scope(&db, &user).await;
+    if !scope.covers_department(req.id) || !scope.covers(parent_id) {
+        abuse::record_denied(&user.username);
         return Json(ApiResponse::error(403, "权限不足，仅管理员可修改部门"));
     }
+    if !can_grant(&user, &normalize_permissions(req.permissions.as_deref().unwrap_or(""))) {
+        abuse::record_denied(&user.username);
+        return Json(ApiResponse::error(403, "权限不足，不能分配该权限"));
+    }
 
     if req.name.chars().count() > 32 {
         return Json(ApiResponse::error(400, "部门名称不能超过32个字符"));
     }
 
-    let parent_id = req.parent_id.unwrap_or(0);
-
     let existing = department::Entity::find()
         .filter(department::Column::Name.eq(&req.name))
         .filter(department::Column::ParentId.eq(parent_id))
@@ -325,7 +340,7 @@ pub async fn update_department(
             } else {
                 format!("部门名称: {}/{}", parent_name, req.name)
             };
-            log_operation(&user.username, OP_UPDATE_DEPT, &op_desc, OP_SUCCESS, None);
+            log_admin_operation(&user.username, OpType::UpdateDept, &op_desc, OP_SUCCESS, None);
             let mut response = DepartmentResponse::from(dept);
             if let Some(perms) = req.permissions.as_deref() {
                 let perm_list = normalize_permissions(perms);
@@ -373,7 +388,7 @@ pub async fn get_departments(
                 response.push(item);
             }
             // Log operation
-            log_operation(&user.username, OP_QUERY_DEPT, "", OP_SUCCESS, None);
+            log_operation(&user.username, OpType::QueryDept, "", OP_SUCCESS, None);
             Json(DeptQueryResponse {
                 success: true,
                 data: response,
@@ -491,3 +506,24 @@ async fn get_department_path(db: &sea_orm::DatabaseConnection, id: i64) -> Strin
         _ => String::new(),
     }
 }
+
+/// Get the ids of a department and all of its descendants
+pub async fn get_department_subtree_ids(
+    db: &sea_orm::DatabaseConnection,
+    root_id: i64,
+) -> Result<Vec<i64>, sea_orm::DbErr> {
+    let all = department::Entity::find().all(db).await?;
+    let mut ids = vec![root_id];
+    let mut i = 0;
+    while i < ids.len() {
+        let parent = ids[i];
+        let children: Vec<i64> = all
+            .iter()
+            .filter(|d| d.parent_id == parent && !ids.contains(&d.id))
+            .map(|d| d.id)
+            .collect();
+        ids.extend(children);
+        i += 1;
+    }
+    Ok(ids)
+}
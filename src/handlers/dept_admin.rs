@@ -0,0 +1,240 @@
+//! Department admin delegation
+//!
+//! `contacts` lets a user manage every user and department. `contacts_dept`,
+//! granted like any other permission through a role, the user's own policies
+//! or those of a department, delegates the same within the user's own
+//! department subtree: the users of it, their quotas, and the departments
+//! below the user's own, whose quotas and spaces come with them.
+//!
+//! A department admin can't raise anyone above themselves: permissions and
+//! roles they hand out must be ones they hold, never `contacts`, `role` or
+//! `audit`, and users already holding others are out of their reach.
+
+use sea_orm::{DatabaseConnection, DbErr};
+
+use crate::entity::user;
+use crate::handlers::department::get_department_subtree_ids;
+use crate::middleware::auth::{perm, CurrentUser};
+use crate::state::AppState;
+
+/// Permissions that reach beyond a department, never delegated
+const GLOBAL: [&str; 3] = [perm::CONTACTS, perm::ROLE, perm::AUDIT];
+
+/// Users and departments a user may manage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminScope {
+    /// All of them
+    Global,
+    /// Those of the user's department and its descendants
+    Department {
+        /// The user's own department
+        root: i64,
+        /// IDs of the subtree, `root` first
+        ids: Vec<i64>,
+    },
+    None,
+}
+
+impl AdminScope {
+    /// Whether users of department `dept_id` may be managed
+    pub fn covers(&self, dept_id: i64) -> bool {
+        match self {
+            AdminScope::Global => true,
+            AdminScope::Department { ids, .. } => ids.contains(&dept_id),
+            AdminScope::None => false,
+        }
+    }
+
+    /// Whether department `dept_id` itself may be changed
+    ///
+    /// Department admins manage the departments below their own, not the
+    /// one they were given.
+    pub fn covers_department(&self, dept_id: i64) -> bool {
+        match self {
+            AdminScope::Department { root, .. } => dept_id != *root && self.covers(dept_id),
+            scope => scope.covers(dept_id),
+        }
+    }
+
+    pub fn is_none(&self) -> bool {
+        *self == AdminScope::None
+    }
+}
+
+/// Determine the users and departments `user` may manage
+///
+/// Users outside any department have nothing to administer.
+pub async fn admin_scope(db: &DatabaseConnection, user: &CurrentUser) -> Result<AdminScope, DbErr> {
+    if user.can_contacts() {
+        Ok(AdminScope::Global)
+    } else if user.can_contacts_dept() && user.department_id > 0 {
+        let ids = get_department_subtree_ids(db, user.department_id).await?;
+        Ok(AdminScope::Department { root: user.department_id, ids })
+    } else {
+        Ok(AdminScope::None)
+    }
+}
+
+/// Whether `user` may hand out all of `permissions`
+pub fn can_grant<S: AsRef<str>>(user: &CurrentUser, permissions: &[S]) -> bool {
+    user.can_contacts()
+        || permissions.iter().all(|p| {
+            let p = p.as_ref();
+            !GLOBAL.contains(&p) && user.has_permission(p)
+        })
+}
+
+/// Whether `user` may assign `role`, which must then exist
+pub async fn can_assign_role(state: &AppState, user: &CurrentUser, role: &str) -> bool {
+    if user.can_contacts() {
+        return true;
+    }
+    let Some(enforcer) = state.get_perm().await else {
+        return false;
+    };
+    match enforcer.get_role_permissions(role).await {
+        Ok(permissions) => !permissions.is_empty() && can_grant(user, &permissions),
+        Err(e) => {
+            tracing::error!("Failed to load permissions of role {}: {}", role, e);
+            false
+        }
+    }
+}
+
+/// Whether `user` may manage the account `target`
+///
+/// The department of the account must be in the scope, and a department
+/// admin may only manage accounts whose permissions they could grant.
+pub async fn can_manage_user(state: &AppState, user: &CurrentUser, scope: &AdminScope, target: &user::Model) -> bool {
+    if !scope.covers(target.department_id) {
+        return false;
+    }
+    if *scope == AdminScope::Global {
+        return true;
+    }
+    let Some(enforcer) = state.get_perm().await else {
+        return false;
+    };
+    can_grant(user, &enforcer.get_user_permissions(&target.username).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::department;
+    use crate::testing::{TestApp, TestEnv};
+    use sea_orm::{ActiveModelTrait, EntityTrait, Set};
+
+    async fn add_department(db: &DatabaseConnection, id: i64, name: &str, parent_id: i64) {
+        department::ActiveModel {
+            id: Set(id),
+            name: Set(name.to_string()),
+            level: Set(1),
+            parent_id: Set(parent_id),
+            parent_name: Set(String::new()),
+            quota: Set(None),
+        }
+        .insert(db)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        let env = TestEnv::new().await;
+        for (id, name, parent_id) in [(1, "研发", 0), (2, "后端", 1), (3, "销售", 0)] {
+            add_department(&env.db, id, name, parent_id).await;
+        }
+
+        let mut manager = env.user("alice", &[perm::FILE, perm::CONTACTS_DEPT]);
+        manager.department_id = 1;
+        let scope = admin_scope(&env.db, &manager).await.unwrap();
+        assert!(scope.covers(1) && scope.covers(2) && !scope.covers(3));
+        assert!(!scope.covers_department(1) && scope.covers_department(2));
+
+        assert_eq!(admin_scope(&env.db, &env.user("root", &[perm::CONTACTS])).await.unwrap(), AdminScope::Global);
+        assert!(admin_scope(&env.db, &env.user("bob", &[perm::FILE])).await.unwrap().is_none());
+
+        // Only what the admin holds, never the global permissions
+        assert!(can_grant(&manager, &[perm::FILE, perm::CONTACTS_DEPT]));
+        assert!(!can_grant(&manager, &[perm::GROUP]));
+        manager.permissions.push(perm::CONTACTS.to_string());
+        assert!(can_grant(&manager, &[perm::ROLE]));
+        env.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_department_admin() {
+        let app = TestApp::spawn().await;
+        let db = &app.env.db;
+        for (id, name, parent_id) in [(1, "研发", 0), (2, "后端", 1), (3, "销售", 0)] {
+            add_department(db, id, name, parent_id).await;
+        }
+        let enforcer = app.state.get_perm().await.unwrap();
+        for (id, parent_id) in [(1, 0), (2, 1), (3, 0)] {
+            enforcer.set_department_parent(id, Some(parent_id)).await.unwrap();
+        }
+        let mut users = Vec::new();
+        for (name, dept_id) in [("lead", 1), ("dev", 2), ("sales", 3)] {
+            let user = app.add_user(name, "secret", "user").await;
+            let mut model: user::ActiveModel = user.into();
+            model.department_id = Set(dept_id);
+            users.push(model.update(db).await.unwrap());
+            enforcer.set_user_department(name, dept_id).await.unwrap();
+        }
+        enforcer.set_permissions("lead", &[perm::CONTACTS_DEPT]).await.unwrap();
+        let lead = app.login("lead", "secret").await;
+
+        // Users of the subtree, with roles the admin could grant
+        let add = |name: &str, dept_id: i64, role: &str| {
+            serde_json::json!({
+                "username": name,
+                "password": "secret",
+                "fullName": name,
+                "departmentId": dept_id,
+                "role": role,
+                "quota": "10G",
+            })
+        };
+        let res: serde_json::Value = lead.post_json("/api/user/add", &add("dev2", 2, "user")).await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        let res: serde_json::Value = lead.post_json("/api/user/add", &add("sales2", 3, "user")).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        let res: serde_json::Value = lead.post_json("/api/user/add", &add("boss", 2, "admin")).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+
+        let status = |user: &user::Model| serde_json::json!([{ "id": user.id, "username": user.username }]);
+        let res: serde_json::Value = lead.post_json("/api/user/disable", &status(&users[1])).await.json().await.unwrap();
+        assert_eq!(res["message"], "成功禁用1个用户, 失败0个", "{}", res);
+        let res: serde_json::Value = lead.post_json("/api/user/disable", &status(&users[2])).await.json().await.unwrap();
+        assert_eq!(res["message"], "成功禁用0个用户, 失败1个", "{}", res);
+        let search: serde_json::Value = lead.get("/api/user/search").await.json().await.unwrap();
+        let names: Vec<_> = search["data"]["users"].as_array().unwrap().iter().map(|u| u["username"].clone()).collect();
+        assert_eq!(names, ["dev", "dev2", "lead"]);
+
+        // Departments below their own, not their own nor others
+        let body = serde_json::json!({ "name": "前端", "parentId": 1, "quota": "100G" });
+        let res: serde_json::Value = lead.post_json("/api/departments/add", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        let body = serde_json::json!({ "name": "市场", "parentId": 3 });
+        let res: serde_json::Value = lead.post_json("/api/departments/add", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        let body = serde_json::json!({ "id": 1, "name": "研发", "quota": "1T" });
+        let res: serde_json::Value = lead.post_json("/api/department/update", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        let body = serde_json::json!({ "id": 2, "name": "后端", "parentId": 3 });
+        let res: serde_json::Value = lead.post_json("/api/department/update", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        let body = serde_json::json!({ "id": 2, "name": "后端", "parentId": 1, "quota": "50G" });
+        let res: serde_json::Value = lead.post_json("/api/department/update", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        let dept = department::Entity::find_by_id(2).one(db).await.unwrap().unwrap();
+        assert_eq!(dept.quota.as_deref(), Some("50G"));
+
+        // Nothing for users without the permission
+        let dev = app.login("dev2", "secret").await;
+        let res: serde_json::Value = dev.post_json("/api/user/disable", &status(&users[0])).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        app.close().await;
+    }
+}
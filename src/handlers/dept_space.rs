@@ -0,0 +1,238 @@
+//! Department spaces
+//!
+//! Every department has a shared folder, shown to its members as
+//! `/dept/<name>` in the file API next to their own files. Members of a
+//! sub-department see the spaces of the departments above it, following the
+//! `dept:` Casbin roles.
+//!
+//! A space is stored like the tree of a user whose name is the department's
+//! role name (`dept:<id>`): file rows, trash items, tags and quota are kept
+//! under that owner, and [`get_user_path`](crate::handlers::file::get_user_path)
+//! maps it to the department's folder.
+
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder};
+use thiserror::Error;
+
+use crate::entity::department;
+use crate::middleware::auth::CurrentUser;
+use crate::permission::PermissionEnforcer;
+use crate::service::file::DirectoryItem;
+use crate::state::AppState;
+
+/// Folder of the file API holding the spaces
+pub const ROOT: &str = "dept";
+
+/// Owner of the files of a department space
+pub fn owner(dept_id: i64) -> String {
+    format!("{}{}", PermissionEnforcer::DEPT_PREFIX, dept_id)
+}
+
+/// Department of a space owner, None for users
+pub fn dept_id(owner: &str) -> Option<i64> {
+    owner.strip_prefix(PermissionEnforcer::DEPT_PREFIX)?.parse().ok()
+}
+
+/// Where a path of the file API points
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// Owner of the tree: the user, or a department space
+    pub owner: String,
+    /// Path inside the tree
+    pub path: String,
+    /// Path of the tree's root in the file API, empty for the user's own files
+    pub prefix: String,
+    /// Whether the user may change files of the tree
+    pub writable: bool,
+}
+
+impl Location {
+    /// Whether the path is inside a department or group space
+    pub fn is_space(&self) -> bool {
+        !self.prefix.is_empty()
+    }
+}
+
+/// Why a path can't be located
+#[derive(Debug, Error)]
+pub enum LocateError {
+    #[error("no such space")]
+    NotFound,
+    #[error("not a member of the space")]
+    Forbidden,
+    #[error("database error: {0}")]
+    Database(#[from] DbErr),
+}
+
+/// Whether `path` is the folder listing the spaces
+pub fn is_space_list(state: &AppState, path: &str) -> bool {
+    state.config.dept_space.enabled && path.trim_matches('/') == ROOT
+}
+
+/// Departments whose spaces the user sees, by name
+pub async fn spaces(
+    state: &AppState,
+    db: &DatabaseConnection,
+    username: &str,
+) -> Result<Vec<department::Model>, DbErr> {
+    if !state.config.dept_space.enabled {
+        return Ok(Vec::new());
+    }
+    let Some(enforcer) = state.get_perm().await else {
+        return Ok(Vec::new());
+    };
+    let ids = enforcer.get_user_departments(username).await;
+    department::Entity::find()
+        .filter(department::Column::Id.is_in(ids))
+        .order_by_asc(department::Column::Name)
+        .order_by_asc(department::Column::Id)
+        .all(db)
+        .await
+}
+
+/// Listing item of a folder that isn't on disk
+pub(crate) fn folder_item(basename: &str, filename: String) -> DirectoryItem {
+    DirectoryItem {
+        basename: basename.to_string(),
+        filename,
+        item_type: "directory".to_string(),
+        size: 0,
+        lastmod: String::new(),
+        mime: String::new(),
+        tier: None,
+        thumbnail: false,
+        size_pending: false,
+    }
+}
+
+/// Item of the folder holding the spaces, listed in the user's root
+pub fn root_item() -> DirectoryItem {
+    folder_item(ROOT, format!("/{}", ROOT))
+}
+
+/// Listing of the folder holding the spaces
+pub fn space_items(spaces: &[department::Model]) -> Vec<DirectoryItem> {
+    let mut items: Vec<DirectoryItem> = Vec::new();
+    for dept in spaces {
+        if !items.iter().any(|item| item.basename == dept.name) {
+            items.push(folder_item(&dept.name, format!("/{}/{}", ROOT, dept.name)));
+        }
+    }
+    items
+}
+
+/// Locate a path of the file API for the user
+///
+/// Paths below `/dept/<name>` are in the space of that department if the
+/// user is a member, all others in the user's own tree.
+pub async fn locate(
+    state: &AppState,
+    db: &DatabaseConnection,
+    user: &CurrentUser,
+    path: &str,
+) -> Result<Location, LocateError> {
+    let own = || Location {
+        owner: user.username.clone(),
+        path: path.to_string(),
+        prefix: String::new(),
+        writable: true,
+    };
+    if !state.config.dept_space.enabled {
+        return Ok(own());
+    }
+    let mut parts = path.trim_start_matches('/').splitn(3, '/');
+    if parts.next() != Some(ROOT) {
+        return Ok(own());
+    }
+    let Some(name) = parts.next().filter(|name| !name.is_empty()) else {
+        // The listing of the spaces isn't in any tree
+        return Err(LocateError::NotFound);
+    };
+    let rest = parts.next().unwrap_or("");
+
+    // Names may repeat in different branches, the first one the user sees wins
+    let visible = spaces(state, db, &user.username).await?;
+    match visible.iter().find(|dept| dept.name == name) {
+        Some(dept) => Ok(Location {
+            owner: owner(dept.id),
+            path: rest.to_string(),
+            prefix: format!("/{}/{}", ROOT, name),
+            writable: true,
+        }),
+        None => {
+            let exists = department::Entity::find()
+                .filter(department::Column::Name.eq(name))
+                .one(db)
+                .await?
+                .is_some();
+            Err(if exists { LocateError::Forbidden } else { LocateError::NotFound })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission::perm;
+    use crate::service::FileService;
+    use crate::testing::TestEnv;
+    use sea_orm::{ActiveModelTrait, Set};
+
+    #[test]
+    fn test_owner() {
+        assert_eq!(owner(3), "dept:3");
+        assert_eq!(dept_id("dept:3"), Some(3));
+        assert_eq!(dept_id("alice"), None);
+        assert_eq!(dept_id("dept:x"), None);
+    }
+
+    #[tokio::test]
+    async fn test_locate() {
+        let mut env = TestEnv::new().await;
+        env.config.dept_space.enabled = true;
+        let enforcer = PermissionEnforcer::new(env.db.clone(), env.config.casbin_conf.to_str().unwrap())
+            .await
+            .unwrap();
+        for (id, name, parent_id) in [(1, "研发", 0), (2, "后端", 1)] {
+            department::ActiveModel {
+                id: Set(id),
+                name: Set(name.to_string()),
+                level: Set(1),
+                parent_id: Set(parent_id),
+                parent_name: Set(String::new()),
+                quota: Set(None),
+            }
+            .insert(&env.db)
+            .await
+            .unwrap();
+        }
+        enforcer.set_department_parent(2, Some(1)).await.unwrap();
+        enforcer.set_user_department("alice", 2).await.unwrap();
+        enforcer.set_user_department("bob", 1).await.unwrap();
+        let state = AppState::new(Some(env.db.clone()), Some(enforcer), env.config.clone());
+
+        // Members of a sub-department see the spaces above it
+        let alice = env.user("alice", &[perm::FILE]);
+        let names: Vec<_> = spaces(&state, &env.db, "alice").await.unwrap().into_iter().map(|d| d.name).collect();
+        assert_eq!(names, ["后端", "研发"]);
+        let location = locate(&state, &env.db, &alice, "/dept/研发/docs").await.unwrap();
+        assert_eq!(location, Location { owner: owner(1), path: "docs".to_string(), prefix: "/dept/研发".to_string(), writable: true });
+        let own = locate(&state, &env.db, &alice, "/docs").await.unwrap();
+        assert!(!own.is_space());
+        assert!(matches!(locate(&state, &env.db, &alice, "/dept").await, Err(LocateError::NotFound)));
+        assert!(matches!(locate(&state, &env.db, &alice, "/dept/销售").await, Err(LocateError::NotFound)));
+
+        // But not the other way round
+        let bob = env.user("bob", &[perm::FILE]);
+        assert!(matches!(locate(&state, &env.db, &bob, "/dept/后端").await, Err(LocateError::Forbidden)));
+
+        // Files of a space are kept in the department's folder
+        let service = FileService::new(&env.config, &env.db, &location.owner)
+            .on_behalf_of(&alice.username, &location.prefix);
+        service.mkdir("/", None, "docs").await.unwrap();
+        assert!(env.config.dept_space_dir(1).join("docs").is_dir());
+        let items = service.list("/").await.unwrap();
+        assert_eq!(items[0].filename, "/dept/研发/docs");
+
+        env.close().await;
+    }
+}
@@ -0,0 +1,331 @@
+//! Remembered devices
+//!
+//! Logging in with "remember this device" sets a long-lived cookie beside the
+//! web session, holding the ID of a `disk_device` row and a secret. Once the
+//! web session is gone, the middleware signs the browser in again with it
+//! and replaces the secret, so a copied cookie works only until the browser
+//! uses its own. A replaced secret coming back means two browsers hold the
+//! cookie: the device is forgotten and both have to log in again. The
+//! previous secret stays valid for a few seconds, for requests the browser
+//! sent at the same time.
+//!
+//! Users list their devices and revoke them in the settings, which also ends
+//! the sessions opened with them.
+
+use axum::{http::HeaderMap, response::Json, Extension};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use tower_sessions::cookie::{time, Cookie, SameSite};
+use tower_sessions::Session;
+
+use crate::config::RememberConfig;
+use crate::entity::device;
+use crate::entity::op_log::OpType;
+use crate::handlers::audit::service::log_operation;
+use crate::middleware::auth::{hash_api_token, CurrentUser, SESSION_DEVICE_KEY};
+use crate::middleware::DbConn;
+use crate::routes::ApiResponse;
+
+const OP_SUCCESS: &str = "成功";
+
+/// Cookie holding `<device id>.<secret>`
+pub const REMEMBER_COOKIE: &str = "dd_remember";
+
+/// Seconds the previous secret of a device is still accepted
+const ROTATION_GRACE_SECS: i64 = 30;
+
+/// Longest device name kept
+const MAX_NAME_LEN: usize = 255;
+
+/// Cookie remembering a device for `days`
+pub fn cookie(value: String, days: u64) -> Cookie<'static> {
+    Cookie::build((REMEMBER_COOKIE, value))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::days(days as i64))
+        .build()
+}
+
+/// Cookie removing the remembered device from the browser
+pub fn removal_cookie() -> Cookie<'static> {
+    let mut cookie = cookie(String::new(), 0);
+    cookie.make_removal();
+    cookie
+}
+
+/// Value of the remember cookie sent with a request
+pub fn cookie_value(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(axum::http::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(Cookie::split_parse)
+        .filter_map(Result::ok)
+        .find(|cookie| cookie.name() == REMEMBER_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+}
+
+/// A new secret and its hash
+fn new_secret() -> (String, String) {
+    let secret = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let hash = hash_api_token(&secret);
+    (secret, hash)
+}
+
+/// Remember a device of `username` named `name`, its ID and cookie value
+///
+/// Expired devices of the user are dropped, and the least recently used ones
+/// beyond the limit.
+pub async fn remember(
+    db: &DatabaseConnection,
+    config: &RememberConfig,
+    username: &str,
+    name: &str,
+    ip: Option<&str>,
+) -> Result<(i64, String), DbErr> {
+    let now = chrono::Utc::now().timestamp();
+    device::Entity::delete_many()
+        .filter(device::Column::Username.eq(username))
+        .filter(device::Column::ExpireTime.lte(now))
+        .exec(db)
+        .await?;
+    let known = device::Entity::find()
+        .filter(device::Column::Username.eq(username))
+        .order_by_asc(device::Column::LastUsedTime)
+        .all(db)
+        .await?;
+    let excess = (known.len() + 1).saturating_sub(config.max_devices.max(1) as usize);
+    for old in known.into_iter().take(excess) {
+        device::Entity::delete_by_id(old.id).exec(db).await?;
+    }
+
+    let (secret, hash) = new_secret();
+    let name: String = name.chars().take(MAX_NAME_LEN).collect();
+    let created = device::ActiveModel {
+        username: Set(username.to_string()),
+        name: Set(if name.is_empty() { "unknown".to_string() } else { name }),
+        token_hash: Set(hash),
+        prev_token_hash: Set(None),
+        rotate_time: Set(now),
+        last_ip: Set(ip.map(str::to_string)),
+        create_time: Set(now),
+        last_used_time: Set(now),
+        expire_time: Set(now + config.days as i64 * 86400),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+    Ok((created.id, format!("{}.{}", created.id, secret)))
+}
+
+/// A browser signed in again by its remembered device
+#[derive(Debug)]
+pub struct Resumed {
+    pub username: String,
+    pub device_id: i64,
+    /// Replacement cookie value, None if the browser has it already
+    pub cookie: Option<String>,
+}
+
+/// Sign a browser in again with the remember cookie `value`
+pub async fn resume(db: &DatabaseConnection, config: &RememberConfig, value: &str, ip: Option<&str>) -> Option<Resumed> {
+    if !config.enabled {
+        return None;
+    }
+    let (id, secret) = value.split_once('.')?;
+    let id: i64 = id.parse().ok()?;
+    let record = device::Entity::find_by_id(id)
+        .one(db)
+        .await
+        .map_err(|e| tracing::error!("Database error during device login: {}", e))
+        .ok()??;
+
+    let now = chrono::Utc::now().timestamp();
+    let hash = hash_api_token(secret);
+    if record.expire_time <= now {
+        forget(db, record.id).await;
+        return None;
+    }
+    if record.prev_token_hash.as_deref() == Some(hash.as_str()) && now - record.rotate_time <= ROTATION_GRACE_SECS {
+        return Some(Resumed { username: record.username, device_id: record.id, cookie: None });
+    }
+    if record.token_hash != hash {
+        tracing::warn!("Replaced secret of device {} of {} used again, forgetting it", record.id, record.username);
+        forget(db, record.id).await;
+        return None;
+    }
+
+    let (secret, new_hash) = new_secret();
+    let username = record.username.clone();
+    let mut active: device::ActiveModel = record.into();
+    active.prev_token_hash = Set(Some(hash));
+    active.token_hash = Set(new_hash);
+    active.rotate_time = Set(now);
+    active.last_used_time = Set(now);
+    active.last_ip = Set(ip.map(str::to_string));
+    active.expire_time = Set(now + config.days as i64 * 86400);
+    if let Err(e) = active.update(db).await {
+        tracing::error!("Failed to rotate device {}: {}", id, e);
+        return None;
+    }
+    Some(Resumed { username, device_id: id, cookie: Some(format!("{}.{}", id, secret)) })
+}
+
+/// Whether device `id` is still remembered; kept on database errors
+pub async fn is_remembered(db: &DatabaseConnection, id: i64) -> bool {
+    match device::Entity::find_by_id(id).count(db).await {
+        Ok(count) => count > 0,
+        Err(e) => {
+            tracing::error!("Database error checking device {}: {}", id, e);
+            true
+        }
+    }
+}
+
+/// Forget device `id`
+pub async fn forget(db: &DatabaseConnection, id: i64) {
+    if let Err(e) = device::Entity::delete_by_id(id).exec(db).await {
+        tracing::error!("Failed to forget device {}: {}", id, e);
+    }
+}
+
+/// Remembered device as listed in the settings
+#[derive(Debug, Serialize)]
+pub struct DeviceResponse {
+    pub id: i64,
+    pub name: String,
+    #[serde(rename = "lastIp")]
+    pub last_ip: Option<String>,
+    #[serde(rename = "createTime")]
+    pub create_time: i64,
+    #[serde(rename = "lastUsedTime")]
+    pub last_used_time: i64,
+    #[serde(rename = "expireTime")]
+    pub expire_time: i64,
+    /// The device of this session
+    pub current: bool,
+}
+
+/// Revoke device request
+#[derive(Debug, Deserialize)]
+pub struct RevokeDeviceRequest {
+    pub id: i64,
+}
+
+/// GET /api/device/list - List the current user's remembered devices
+pub async fn list_devices(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    session: Session,
+) -> Json<ApiResponse<Vec<DeviceResponse>>> {
+    let current = session.get::<i64>(SESSION_DEVICE_KEY).await.unwrap_or(None);
+    let now = chrono::Utc::now().timestamp();
+    match device::Entity::find()
+        .filter(device::Column::Username.eq(&current_user.username))
+        .filter(device::Column::ExpireTime.gt(now))
+        .order_by_desc(device::Column::LastUsedTime)
+        .all(&*db)
+        .await
+    {
+        Ok(devices) => Json(ApiResponse::success(
+            devices
+                .into_iter()
+                .map(|d| DeviceResponse {
+                    current: current == Some(d.id),
+                    id: d.id,
+                    name: d.name,
+                    last_ip: d.last_ip,
+                    create_time: d.create_time,
+                    last_used_time: d.last_used_time,
+                    expire_time: d.expire_time,
+                })
+                .collect(),
+        )),
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            Json(ApiResponse::error(500, "internal error"))
+        }
+    }
+}
+
+/// POST /api/device/revoke - Forget one of the current user's devices,
+/// ending the sessions opened with it
+pub async fn revoke_device(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<RevokeDeviceRequest>,
+) -> Json<ApiResponse<()>> {
+    let device = match device::Entity::find_by_id(req.id)
+        .filter(device::Column::Username.eq(&current_user.username))
+        .one(&*db)
+        .await
+    {
+        Ok(Some(device)) => device,
+        Ok(None) => return Json(ApiResponse::error(404, "设备不存在")),
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    if let Err(e) = device::Entity::delete_by_id(device.id).exec(&*db).await {
+        tracing::error!("Failed to revoke device: {}", e);
+        return Json(ApiResponse::error(500, "internal error"));
+    }
+
+    log_operation(&current_user.username, OpType::RevokeDevice, &device.name, OP_SUCCESS, None);
+    Json(ApiResponse::success_msg("success"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{TestApp, ADMIN_PASSWORD, ADMIN_USERNAME};
+
+    #[tokio::test]
+    async fn test_remember_device() {
+        let app = TestApp::spawn().await;
+        let client = reqwest::Client::builder().cookie_store(false).build().unwrap();
+        let body = serde_json::json!({ "username": ADMIN_USERNAME, "password": ADMIN_PASSWORD, "remember": true });
+        let res = client.post(app.url("/api/login")).json(&body).send().await.unwrap();
+        assert!(res.status().is_success());
+        let remember = |res: &reqwest::Response| {
+            res.cookies().find(|c| c.name() == super::REMEMBER_COOKIE).map(|c| c.value().to_string())
+        };
+        let first = remember(&res).unwrap();
+
+        // Without a session the device signs in and gets a new secret
+        let get = |cookie: String| {
+            client.get(app.url("/api/user/current")).header("Cookie", format!("{}={}", super::REMEMBER_COOKIE, cookie)).send()
+        };
+        let res = get(first.clone()).await.unwrap();
+        assert!(res.status().is_success());
+        let second = remember(&res).unwrap();
+        assert_ne!(first, second);
+        let session = res.cookies().find(|c| c.name() == "id").unwrap().value().to_string();
+
+        // The old secret still works for a moment, without rotating again
+        let res = get(first.clone()).await.unwrap();
+        assert!(res.status().is_success() && remember(&res).is_none());
+
+        // Revoking the device ends its session and its cookie
+        let session_get = |path: &str| client.get(app.url(path)).header("Cookie", format!("id={}", session)).send();
+        let devices: serde_json::Value = session_get("/api/device/list").await.unwrap().json().await.unwrap();
+        assert_eq!(devices["data"][0]["current"], true, "{}", devices);
+        let id = devices["data"][0]["id"].as_i64().unwrap();
+        let res = client
+            .post(app.url("/api/device/revoke"))
+            .header("Cookie", format!("id={}", session))
+            .json(&serde_json::json!({ "id": id }))
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(session_get("/api/user/current").await.unwrap().status(), 401);
+        assert_eq!(get(second).await.unwrap().status(), 401);
+        app.close().await;
+    }
+}
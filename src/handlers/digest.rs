@@ -0,0 +1,444 @@
+//! Activity digests
+//!
+//! Users opt in to a daily or weekly email (`/api/user/digest`) summing up
+//! what happened since the last one: changes in the watched folders marked
+//! for the digest, group spaces they were added to, and how their used space
+//! changed. Digests go out at `send_hour` local time, weekly ones on
+//! `weekly_day`, and only while `[mail]` has an SMTP server. A digest with
+//! nothing to tell is skipped.
+//!
+//! The state of the last digest (time, used space, groups) is kept per user,
+//! starting when they opt in.
+
+use axum::{extract::State, response::Json, Extension};
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, NaiveTime};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::config::{Config, DigestConfig};
+use crate::entity::digest::{self, FREQUENCY_DAILY, FREQUENCY_OFF, FREQUENCY_WEEKLY};
+use crate::entity::{group, group_user, user, watch, watch_event};
+use crate::handlers::quota::{self, format_size};
+use crate::mail;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::{ApiMessage, ApiResponse};
+use crate::state::AppState;
+
+/// Changes listed one by one, more are only counted
+const MAX_LISTED: usize = 50;
+/// Changes looked at for one digest
+const MAX_EVENTS: u64 = 10_000;
+
+/// Whether a digest of `frequency` last sent at `last_sent` is due at `now`,
+/// both local times
+fn due(config: &DigestConfig, frequency: &str, last_sent: NaiveDateTime, now: NaiveDateTime) -> bool {
+    let send_time = NaiveTime::from_hms_opt(config.send_hour.min(23), 0, 0).unwrap_or_default();
+    let today = now.date().and_time(send_time);
+    let day_ok = match frequency {
+        FREQUENCY_DAILY => true,
+        FREQUENCY_WEEKLY => now.weekday().number_from_monday() == config.weekly_day,
+        _ => false,
+    };
+    day_ok && now >= today && last_sent < today
+}
+
+/// Local time of a Unix timestamp
+fn local(timestamp: i64) -> NaiveDateTime {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|t| t.with_timezone(&Local).naive_local())
+        .unwrap_or_default()
+}
+
+/// What a digest is about
+#[derive(Debug, Default)]
+struct Summary {
+    /// Changes in watched folders, oldest first
+    events: Vec<watch_event::Model>,
+    /// Names of the groups joined since the last digest
+    new_groups: Vec<String>,
+    used: i64,
+    last_usage: i64,
+    limit: Option<i64>,
+}
+
+/// Subject and text of a digest, None if there is nothing to tell
+fn compose(frequency: &str, summary: &Summary) -> Option<(String, String)> {
+    if summary.events.is_empty() && summary.new_groups.is_empty() && summary.used == summary.last_usage {
+        return None;
+    }
+    let period = if frequency == FREQUENCY_WEEKLY { "本周" } else { "今日" };
+    let subject = format!("Datadisk {}动态", period);
+
+    let mut body = String::new();
+    if !summary.events.is_empty() {
+        body.push_str(&format!("关注的文件夹中有{}项变动:\n", summary.events.len()));
+        for event in summary.events.iter().take(MAX_LISTED) {
+            let time = local(event.create_time).format("%m-%d %H:%M");
+            body.push_str(&format!("  {} {} {} ({})\n", time, kind_label(&event.kind), event.path, event.actor));
+        }
+        if summary.events.len() > MAX_LISTED {
+            body.push_str(&format!("  ……还有{}项\n", summary.events.len() - MAX_LISTED));
+        }
+        body.push('\n');
+    }
+    if !summary.new_groups.is_empty() {
+        body.push_str(&format!("新加入的群组: {}\n\n", summary.new_groups.join("、")));
+    }
+    let change = summary.used - summary.last_usage;
+    let sign = if change < 0 { "-" } else { "+" };
+    body.push_str(&format!(
+        "已用空间: {} ({}{})",
+        format_size(summary.used),
+        sign,
+        format_size(change.abs())
+    ));
+    if let Some(limit) = summary.limit.filter(|limit| *limit > 0) {
+        body.push_str(&format!(", 配额 {}, 已用 {}%", format_size(limit), summary.used * 100 / limit));
+    }
+    body.push('\n');
+    Some((subject, body))
+}
+
+fn kind_label(kind: &str) -> &str {
+    match kind {
+        "create" => "新建",
+        "update" => "修改",
+        "delete" => "删除",
+        "rename" => "重命名",
+        "move" => "移动",
+        "restore" => "还原",
+        other => other,
+    }
+}
+
+/// IDs of the groups of a user, sorted
+async fn group_ids(db: &DatabaseConnection, user_id: i64) -> Result<Vec<i64>, DbErr> {
+    let mut ids: Vec<i64> = group_user::Entity::find()
+        .filter(group_user::Column::UserId.eq(user_id))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|m| m.group_id)
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    Ok(ids)
+}
+
+fn join_ids(ids: &[i64]) -> String {
+    ids.iter().map(i64::to_string).collect::<Vec<_>>().join(",")
+}
+
+/// Gather what happened since the digest `row` was last sent
+async fn summarize(
+    config: &Config,
+    db: &DatabaseConnection,
+    row: &digest::Model,
+    db_user: &user::Model,
+) -> Result<(Summary, Vec<i64>), DbErr> {
+    let watch_ids: Vec<i64> = watch::Entity::find()
+        .filter(watch::Column::UserId.eq(db_user.id))
+        .filter(watch::Column::Digest.eq(true))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|w| w.id)
+        .collect();
+    let events = if watch_ids.is_empty() {
+        Vec::new()
+    } else {
+        watch_event::Entity::find()
+            .filter(watch_event::Column::WatchId.is_in(watch_ids))
+            .filter(watch_event::Column::CreateTime.gt(row.last_sent))
+            .order_by_asc(watch_event::Column::Id)
+            .limit(MAX_EVENTS)
+            .all(db)
+            .await?
+    };
+
+    let groups = group_ids(db, db_user.id).await?;
+    let known: Vec<&str> = row.groups.split(',').collect();
+    let joined: Vec<i64> = groups.iter().copied().filter(|id| !known.contains(&id.to_string().as_str())).collect();
+    let new_groups = if joined.is_empty() {
+        Vec::new()
+    } else {
+        group::Entity::find()
+            .filter(group::Column::Id.is_in(joined))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|g| g.name)
+            .collect()
+    };
+
+    let summary = Summary {
+        events,
+        new_groups,
+        used: quota::used_bytes(config, &db_user.username).await,
+        last_usage: row.last_usage,
+        limit: quota::quota_limit(db, config, &db_user.username).await,
+    };
+    Ok((summary, groups))
+}
+
+/// Send the digests due at `now`, returning how many were sent
+pub async fn run(config: &Config, db: &DatabaseConnection, now: DateTime<Local>) -> Result<usize, DbErr> {
+    if !mail::enabled(&config.mail) {
+        return Ok(0);
+    }
+    let rows = digest::Entity::find()
+        .filter(digest::Column::Frequency.ne(FREQUENCY_OFF))
+        .all(db)
+        .await?;
+    let mut sent = 0;
+    for row in rows {
+        if !due(&config.digest, &row.frequency, local(row.last_sent), now.naive_local()) {
+            continue;
+        }
+        let Some(db_user) = user::Entity::find()
+            .filter(user::Column::Username.eq(&row.username))
+            .one(db)
+            .await?
+        else {
+            continue;
+        };
+        let (summary, groups) = summarize(config, db, &row, &db_user).await?;
+        let email = db_user.email.clone().unwrap_or_default();
+        if let (Some((subject, body)), false) = (compose(&row.frequency, &summary), email.is_empty()) {
+            if let Err(e) = mail::send(&config.mail, &email, &subject, body).await {
+                // Tried again within the hour
+                tracing::warn!("Failed to send digest to {}: {:#}", row.username, e);
+                continue;
+            }
+            sent += 1;
+        }
+
+        let mut active: digest::ActiveModel = row.into();
+        active.last_sent = Set(now.timestamp());
+        active.last_usage = Set(summary.used);
+        active.groups = Set(join_ids(&groups));
+        active.update(db).await?;
+    }
+    Ok(sent)
+}
+
+/// Start sending digests
+pub fn start(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(600));
+        loop {
+            ticker.tick().await;
+            let Some(db) = state.get_db().await else {
+                continue;
+            };
+            match run(&state.config, &db, Local::now()).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Sent {} activity digests", n),
+                Err(e) => tracing::error!("Failed to send activity digests: {}", e),
+            }
+        }
+    });
+}
+
+/// Digest settings of the current user
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DigestSettings {
+    /// off, daily or weekly
+    pub frequency: String,
+    /// Where digests are sent, empty if the user has no email address
+    pub email: String,
+    /// Whether the server can send email at all
+    #[serde(rename = "mailEnabled")]
+    pub mail_enabled: bool,
+}
+
+/// Digest settings request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DigestRequest {
+    /// off, daily or weekly
+    pub frequency: String,
+}
+
+/// GET /api/user/digest
+#[utoipa::path(
+    get,
+    path = "/api/user/digest",
+    tag = "user",
+    responses((status = 200, body = ApiResponse<DigestSettings>)),
+)]
+pub async fn get_digest(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Json<ApiResponse<DigestSettings>> {
+    let result = digest::Entity::find()
+        .filter(digest::Column::Username.eq(&current_user.username))
+        .one(&*db)
+        .await;
+    match result {
+        Ok(row) => Json(ApiResponse::success(DigestSettings {
+            frequency: row.map_or(FREQUENCY_OFF.to_string(), |r| r.frequency),
+            email: current_user.email.clone(),
+            mail_enabled: mail::enabled(&state.config.mail),
+        })),
+        Err(e) => {
+            tracing::error!("Failed to query digest settings: {}", e);
+            Json(ApiResponse::error(500, "查询失败"))
+        }
+    }
+}
+
+/// POST /api/user/digest
+#[utoipa::path(
+    post,
+    path = "/api/user/digest",
+    tag = "user",
+    request_body = DigestRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
+pub async fn set_digest(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<DigestRequest>,
+) -> Json<ApiResponse<()>> {
+    let frequency = req.frequency.as_str();
+    if ![FREQUENCY_OFF, FREQUENCY_DAILY, FREQUENCY_WEEKLY].contains(&frequency) {
+        return Json(ApiResponse::error(400, "频率只能是 off、daily 或 weekly"));
+    }
+
+    let result = async {
+        let existing = digest::Entity::find()
+            .filter(digest::Column::Username.eq(&current_user.username))
+            .one(&*db)
+            .await?;
+        match existing {
+            // Keep counting from the last digest
+            Some(row) if row.frequency != FREQUENCY_OFF => {
+                let mut active: digest::ActiveModel = row.into();
+                active.frequency = Set(frequency.to_string());
+                active.update(&*db).await?;
+            }
+            existing => {
+                // The first digest tells what happened from now on
+                let mut active: digest::ActiveModel = match existing {
+                    Some(row) => row.into(),
+                    None => digest::ActiveModel {
+                        username: Set(current_user.username.clone()),
+                        ..Default::default()
+                    },
+                };
+                active.frequency = Set(frequency.to_string());
+                active.last_sent = Set(chrono::Utc::now().timestamp());
+                active.last_usage = Set(quota::used_bytes(&state.config, &current_user.username).await);
+                active.groups = Set(join_ids(&group_ids(&db, current_user.id).await?));
+                active.save(&*db).await?;
+            }
+        }
+        Ok::<_, DbErr>(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => Json(ApiResponse::success_msg("success")),
+        Err(e) => {
+            tracing::error!("Failed to save digest settings: {}", e);
+            Json(ApiResponse::error(500, "保存失败"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        // 2026-10-12 is a Monday
+        chrono::NaiveDate::from_ymd_opt(2026, 10, day).unwrap().and_hms_opt(hour, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn test_due() {
+        let config = DigestConfig { send_hour: 8, weekly_day: 1 };
+        assert!(!due(&config, FREQUENCY_DAILY, at(12, 9), at(12, 10)));
+        assert!(!due(&config, FREQUENCY_DAILY, at(12, 9), at(13, 7)));
+        assert!(due(&config, FREQUENCY_DAILY, at(12, 9), at(13, 8)));
+        assert!(!due(&config, FREQUENCY_OFF, at(12, 9), at(13, 8)));
+        assert!(!due(&config, FREQUENCY_WEEKLY, at(12, 9), at(13, 8)));
+        assert!(due(&config, FREQUENCY_WEEKLY, at(12, 9), at(19, 8)));
+        assert!(!due(&config, FREQUENCY_WEEKLY, at(19, 8), at(19, 9)));
+    }
+
+    #[test]
+    fn test_compose() {
+        assert!(compose(FREQUENCY_DAILY, &Summary::default()).is_none());
+
+        let event = watch_event::Model {
+            id: 1,
+            watch_id: 1,
+            username: "alice".to_string(),
+            path: "/docs/a.txt".to_string(),
+            kind: "update".to_string(),
+            actor: "bob".to_string(),
+            client: "web".to_string(),
+            create_time: 0,
+            read: false,
+        };
+        let summary = Summary {
+            events: vec![event; MAX_LISTED + 2],
+            new_groups: vec!["设计".to_string()],
+            used: 3 * 1024 * 1024,
+            last_usage: 1024 * 1024,
+            limit: Some(10 * 1024 * 1024),
+        };
+        let (subject, body) = compose(FREQUENCY_WEEKLY, &summary).unwrap();
+        assert!(subject.contains("本周"));
+        assert!(body.contains("修改 /docs/a.txt (bob)"));
+        assert!(body.contains("还有2项"));
+        assert!(body.contains("新加入的群组: 设计"));
+        assert!(body.contains("3.0 MB (+2.0 MB)"));
+        assert!(body.contains("已用 30%"));
+
+        // Used space alone is worth telling when it changed
+        let summary = Summary { used: 1, ..Summary::default() };
+        assert!(compose(FREQUENCY_DAILY, &summary).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_digest_settings() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let res: serde_json::Value = admin.get("/api/user/digest").await.json().await.unwrap();
+        assert_eq!(res["data"]["frequency"], FREQUENCY_OFF);
+        assert_eq!(res["data"]["mailEnabled"], false);
+
+        let set = |frequency: &'static str| {
+            let admin = &admin;
+            async move {
+                let body = serde_json::json!({ "frequency": frequency });
+                let res: serde_json::Value = admin.post_json("/api/user/digest", &body).await.json().await.unwrap();
+                res["code"].as_bool().unwrap()
+            }
+        };
+        assert!(!set("hourly").await);
+        assert!(set("weekly").await);
+        let row = digest::Entity::find().one(&app.env.db).await.unwrap().unwrap();
+        assert_eq!(row.frequency, FREQUENCY_WEEKLY);
+        assert!(row.last_sent > 0);
+
+        // Changing the frequency keeps the state of the last digest
+        let mut active: digest::ActiveModel = row.into();
+        active.last_sent = Set(1);
+        active.update(&app.env.db).await.unwrap();
+        assert!(set("daily").await);
+        let row = digest::Entity::find().one(&app.env.db).await.unwrap().unwrap();
+        assert_eq!((row.frequency.as_str(), row.last_sent), (FREQUENCY_DAILY, 1));
+        app.close().await;
+    }
+}
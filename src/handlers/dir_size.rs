@@ -0,0 +1,213 @@
+//! Recursive directory sizes in listings
+//!
+//! `/api/file/list?withDirSize=true` gives directories the size of all the
+//! files below them rather than the size of the directory entry, so users
+//! can see which folders take up their quota. Walking a tree takes a while,
+//! so sizes are computed in the background and cached:
+//! - a listing waits [`WAIT`] at most for the sizes it needs, directories
+//!   still being walked are marked `sizePending` and have their size in a
+//!   later listing
+//! - a directory is walked once at a time, however many listings ask for it
+//! - cached sizes of a directory and of the directories above it are dropped
+//!   with every change of an entry the server makes, which bumps the
+//!   directory's version ([`dir_version::bump`]); changes made by anything
+//!   else show after [`MAX_AGE`]
+//!
+//! [`dir_version::bump`]: crate::handlers::dir_version::bump
+
+use dashmap::DashMap;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::handlers::file::resolve_in_user_root;
+use crate::handlers::quota;
+use crate::service::file::DirectoryItem;
+
+/// Longest a listing waits for the sizes of its directories
+pub const WAIT: Duration = Duration::from_secs(2);
+
+/// Age after which a cached size is computed again
+pub const MAX_AGE: Duration = Duration::from_secs(600);
+
+type Key = (String, String);
+
+type Computation = Shared<BoxFuture<'static, Option<i64>>>;
+
+/// Size of a directory, computed at `at`
+#[derive(Clone, Copy)]
+struct Cached {
+    size: i64,
+    at: Instant,
+}
+
+/// Sizes by (owner, directory path relative to the owner's root)
+static SIZES: LazyLock<DashMap<Key, Cached>> = LazyLock::new(DashMap::new);
+
+/// Walks running, by the same key
+static RUNNING: LazyLock<DashMap<Key, Computation>> = LazyLock::new(DashMap::new);
+
+/// Changes by owner, so a walk that raced with a change isn't cached
+static GENERATIONS: LazyLock<DashMap<String, u64>> = LazyLock::new(DashMap::new);
+
+fn key(owner: &str, dir: &str) -> Key {
+    (owner.to_string(), dir.trim_matches('/').to_string())
+}
+
+fn generation(owner: &str) -> u64 {
+    GENERATIONS.get(owner).map_or(0, |g| *g)
+}
+
+/// Drop the cached sizes of `dir` in the tree of `owner`, and of the
+/// directories above it
+pub fn invalidate(owner: &str, dir: &str) {
+    *GENERATIONS.entry(owner.to_string()).or_insert(0) += 1;
+    let mut dir = dir.trim_matches('/');
+    loop {
+        SIZES.remove(&key(owner, dir));
+        match dir.rsplit_once('/') {
+            Some((parent, _)) => dir = parent,
+            None if dir.is_empty() => break,
+            None => dir = "",
+        }
+    }
+}
+
+/// Cached size of `dir`, if it is recent enough
+fn cached(owner: &str, dir: &str) -> Option<i64> {
+    SIZES
+        .get(&key(owner, dir))
+        .filter(|cached| cached.at.elapsed() < MAX_AGE)
+        .map(|cached| cached.size)
+}
+
+/// Walk of `dir` at `path`, started unless one is running already
+fn computation(owner: &str, dir: &str, path: PathBuf) -> Computation {
+    let key = key(owner, dir);
+    let mut started = None;
+    let computation = RUNNING
+        .entry(key.clone())
+        .or_insert_with(|| {
+            let owner = owner.to_string();
+            let generation = generation(&owner);
+            let walk = async move {
+                let size = tokio::task::spawn_blocking(move || quota::path_size(&path)).await.ok();
+                if let Some(size) = size.filter(|_| self::generation(&owner) == generation) {
+                    SIZES.insert(key.clone(), Cached { size, at: Instant::now() });
+                }
+                RUNNING.remove(&key);
+                size
+            }
+            .boxed()
+            .shared();
+            started = Some(walk.clone());
+            walk
+        })
+        .clone();
+    // Run to the end even if every listing waiting for it gives up
+    if let Some(walk) = started {
+        tokio::spawn(walk);
+    }
+    computation
+}
+
+/// Give the directories among `items`, listed from `dir` of `owner`, their
+/// recursive sizes
+pub async fn fill(config: &Config, owner: &str, dir: &str, items: &mut [DirectoryItem]) {
+    let mut waiting = Vec::new();
+    for (i, item) in items.iter_mut().enumerate() {
+        if item.item_type != "directory" {
+            continue;
+        }
+        let path = format!("{}/{}", dir.trim_end_matches('/'), item.basename);
+        if let Some(size) = cached(owner, &path) {
+            item.size = size;
+            continue;
+        }
+        let Some(full_path) = resolve_in_user_root(config, owner, &path) else {
+            continue;
+        };
+        waiting.push((i, computation(owner, &path, full_path)));
+    }
+    if waiting.is_empty() {
+        return;
+    }
+
+    let (indexes, walks): (Vec<_>, Vec<_>) = waiting.into_iter().unzip();
+    let sizes = tokio::time::timeout(WAIT, futures::future::join_all(walks.clone())).await;
+    for (n, (i, walk)) in indexes.into_iter().zip(walks).enumerate() {
+        let size = match &sizes {
+            Ok(sizes) => sizes[n],
+            // Walks that finished in time have their result
+            Err(_) => walk.peek().copied().flatten(),
+        };
+        match size {
+            Some(size) => items[i].size = size,
+            None => items[i].size_pending = true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::dir_version;
+    use crate::testing::TestApp;
+
+    #[test]
+    fn test_invalidate() {
+        let owner = format!("dir-size-{}", uuid::Uuid::new_v4());
+        let now = Instant::now();
+        for dir in ["", "a", "a/b", "a/b/c", "d"] {
+            SIZES.insert(key(&owner, dir), Cached { size: 1, at: now });
+        }
+        invalidate(&owner, "/a/b/");
+        assert_eq!(cached(&owner, "a/b/c"), Some(1));
+        assert_eq!(cached(&owner, "d"), Some(1));
+        for dir in ["", "a", "a/b"] {
+            assert_eq!(cached(&owner, dir), None);
+        }
+
+        // Every server-side change of an entry drops the sizes above it
+        dir_version::bump_entry(&owner, "a/b/c/file.txt");
+        assert_eq!(cached(&owner, "a/b/c"), None);
+        assert_eq!(cached(&owner, "d"), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_listing_sizes() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        for (path, name) in [("/", "docs"), ("/docs", "old")] {
+            let body = serde_json::json!({ "path": path, "name": name });
+            assert!(admin.post_json("/api/file/mkdir", &body).await.status().is_success());
+        }
+        assert!(admin.upload("/docs", "a.txt", b"hello").await.status().is_success());
+        assert!(admin.upload("/docs/old", "b.txt", b"world!").await.status().is_success());
+        let size = |items: &serde_json::Value, name: &str| {
+            let item = items.as_array().unwrap().iter().find(|item| item["basename"] == name).unwrap();
+            assert!(item.get("sizePending").is_none());
+            item["size"].as_i64().unwrap()
+        };
+
+        let res = admin.get("/api/file/list?path=/&withDirSize=true").await;
+        assert!(!res.headers().contains_key("etag"));
+        let items: serde_json::Value = res.json().await.unwrap();
+        assert_eq!(size(&items, "docs"), 11);
+        let items: serde_json::Value = admin.get("/api/file/list?path=/docs&withDirSize=true").await.json().await.unwrap();
+        assert_eq!(size(&items, "old"), 6);
+        assert_eq!(size(&items, "a.txt"), 5);
+
+        // Uploads and deletions below show in the sizes above
+        assert!(admin.upload("/docs/old", "c.txt", b"again").await.status().is_success());
+        let items: serde_json::Value = admin.get("/api/file/list?path=/&withDirSize=true").await.json().await.unwrap();
+        assert_eq!(size(&items, "docs"), 16);
+        let body = serde_json::json!({ "files": ["b.txt"], "parentDir": "/docs/old" });
+        assert!(admin.post_json("/api/file/delete", &body).await.status().is_success());
+        let items: serde_json::Value = admin.get("/api/file/list?path=/&withDirSize=true").await.json().await.unwrap();
+        assert_eq!(size(&items, "docs"), 10);
+        app.close().await;
+    }
+}
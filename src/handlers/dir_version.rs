@@ -0,0 +1,170 @@
+//! Directory versions for listing cache hints
+//!
+//! `/api/file/list` and `/api/file/query/files` answer with an ETag made of
+//! a version counter of the directory and its modification time, and with
+//! `Cache-Control: private, no-cache` so the browser keeps the listing but
+//! asks each time. A request whose `If-None-Match` still matches gets 304.
+//!
+//! The counters live in memory and are bumped by every server-side change of
+//! an entry in the directory, including changes of a file's content that
+//! leave the directory itself untouched. The modification time catches
+//! entries added, removed or renamed by anything else. Counters start over
+//! with the process, so the tag also carries an epoch picked at startup.
+
+use axum::http::{header, HeaderMap, HeaderValue};
+use dashmap::DashMap;
+use std::sync::LazyLock;
+use std::time::SystemTime;
+
+use crate::handlers::dir_size;
+
+/// `Cache-Control` of listings: kept by the browser, revalidated every time
+pub const CACHE_CONTROL: &str = "private, no-cache";
+
+/// Version by (owner, directory path relative to the owner's root)
+static VERSIONS: LazyLock<DashMap<(String, String), u64>> = LazyLock::new(DashMap::new);
+
+/// Tells tags of this process from those of earlier ones
+static EPOCH: LazyLock<String> = LazyLock::new(|| uuid::Uuid::new_v4().simple().to_string()[..8].to_string());
+
+fn key(owner: &str, dir: &str) -> (String, String) {
+    (owner.to_string(), dir.trim_matches('/').to_string())
+}
+
+/// Note a change of the listing of `dir` in the tree of `owner`
+///
+/// The cached recursive sizes of `dir` and the directories above it are
+/// dropped with it.
+pub fn bump(owner: &str, dir: &str) {
+    *VERSIONS.entry(key(owner, dir)).or_insert(0) += 1;
+    dir_size::invalidate(owner, dir);
+}
+
+/// Note a change of the entry at `path`, in the listing of its directory
+///
+/// A changed directory also gets a new version of its own: its listing may
+/// have changed with it, or it was renamed away and a new one may take its
+/// place.
+pub fn bump_entry(owner: &str, path: &str) {
+    let path = path.trim_matches('/');
+    let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+    bump(owner, parent);
+    if !path.is_empty() {
+        bump(owner, path);
+    }
+}
+
+/// Current version of `dir`
+pub fn version(owner: &str, dir: &str) -> u64 {
+    VERSIONS.get(&key(owner, dir)).map_or(0, |v| *v)
+}
+
+/// ETag of the listing of `dir`, `modified` being the directory's
+/// modification time and `extra` anything else the listing depends on
+pub fn etag(owner: &str, dir: &str, modified: Option<SystemTime>, extra: &str) -> String {
+    let modified = modified
+        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    format!("\"{}-{:x}-{:x}{}\"", *EPOCH, version(owner, dir), modified, extra)
+}
+
+/// Whether the client's `If-None-Match` holds `etag`
+pub fn matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+/// Add the cache hints of a listing to `headers`
+pub fn set_headers(headers: &mut HeaderMap, etag: &str) {
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(CACHE_CONTROL));
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    #[test]
+    fn test_versions() {
+        let owner = format!("dir-version-{}", uuid::Uuid::new_v4());
+        assert_eq!(version(&owner, "/docs"), 0);
+        bump_entry(&owner, "/docs/a.txt");
+        assert_eq!(version(&owner, "docs"), 1);
+        assert_eq!(version(&owner, "docs/a.txt"), 1);
+        bump_entry(&owner, "b.txt");
+        assert_eq!(version(&owner, "/"), 1);
+        assert_eq!(version(&owner, "docs"), 1);
+        assert_eq!(version("someone-else", "docs"), 0);
+
+        let before = etag(&owner, "docs", None, "");
+        bump(&owner, "docs/");
+        assert_ne!(etag(&owner, "docs", None, ""), before);
+    }
+
+    #[test]
+    fn test_matches() {
+        let etag = etag("alice", "/", None, "");
+        let mut headers = HeaderMap::new();
+        assert!(!matches(&headers, &etag));
+        headers.insert(header::IF_NONE_MATCH, format!("\"x\", W/{}", etag).parse().unwrap());
+        assert!(matches(&headers, &etag));
+        headers.insert(header::IF_NONE_MATCH, "\"x\"".parse().unwrap());
+        assert!(!matches(&headers, &etag));
+        headers.insert(header::IF_NONE_MATCH, "*".parse().unwrap());
+        assert!(matches(&headers, &etag));
+    }
+
+    #[tokio::test]
+    async fn test_listing_not_modified() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "a.txt", b"hello").await.status().is_success());
+
+        let list = |etag: Option<String>| {
+            let mut request = admin.http.get(app.url("/api/file/list?path=/"));
+            if let Some(etag) = etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            request.send()
+        };
+        let res = list(None).await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()[header::CACHE_CONTROL], CACHE_CONTROL);
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_eq!(list(Some(etag.clone())).await.unwrap().status(), 304);
+
+        // Replacing a file changes the listing
+        assert!(admin.upload("/", "a.txt", b"hello again").await.status().is_success());
+        let res = list(Some(etag.clone())).await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_ne!(res.headers()[header::ETAG].to_str().unwrap(), etag);
+
+        let files = |etag: Option<&str>| {
+            let mut request = admin.http.get(app.url("/api/file/query/files?parentId=-1"));
+            if let Some(etag) = etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            request.send()
+        };
+        let res = files(None).await.unwrap();
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_eq!(files(Some(&etag)).await.unwrap().status(), 304);
+        let res: serde_json::Value = admin
+            .post_json("/api/file/mkdir", &serde_json::json!({ "parentPath": "/", "name": "docs" }))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(res["code"], true);
+        assert_eq!(files(Some(&etag)).await.unwrap().status(), 200);
+        app.close().await;
+    }
+}
@@ -11,23 +11,36 @@ use axum::{
 };
 use dashmap::DashMap;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use sea_orm::{sea_query::Expr, ColumnTrait, EntityTrait, QueryFilter};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::path::PathBuf;
 use std::sync::LazyLock;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
 
 use crate::entity::file_info;
+use crate::handlers::dir_version;
+use crate::handlers::file::{get_user_path, resolve_in_user_root};
+use crate::handlers::legal_hold;
+use crate::handlers::quota;
 use crate::handlers::recent::record_file_access;
+use crate::handlers::tiering;
+use crate::handlers::trash::backup_to_trash;
+use crate::handlers::watch::{self, ChangeKind, Client};
 use crate::middleware::auth::CurrentUser;
 use crate::middleware::DbConn;
+use crate::mime;
+use crate::outbound;
 use crate::state::AppState;
 
 /// Global editing sessions storage
 static EDITING_SESSIONS: LazyLock<DashMap<String, EditingSession>> =
     LazyLock::new(DashMap::new);
 
+/// Seconds a view session stays usable; they are never closed by a callback
+const VIEW_SESSION_SECS: i64 = 24 * 3600;
+
 /// Editing session information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -51,6 +64,26 @@ pub struct EditingSession {
     pub email: String,
     pub doc_server_url: String,
     pub datadisk_url: String,
+    /// Session mode: edit or view
+    pub mode: SessionMode,
+}
+
+impl EditingSession {
+    /// Whether this is a view session older than [`VIEW_SESSION_SECS`]
+    fn is_expired(&self, now: i64) -> bool {
+        self.mode == SessionMode::View && self.created_at + VIEW_SESSION_SECS <= now
+    }
+}
+
+/// Editing session mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionMode {
+    /// Collaborative editing, saved back through the callback
+    #[default]
+    Edit,
+    /// Read-only viewing, no save callback
+    View,
 }
 
 /// Create session request
@@ -58,6 +91,8 @@ pub struct EditingSession {
 #[serde(rename_all = "camelCase")]
 pub struct CreateSessionRequest {
     pub file_path: String,
+    #[serde(default)]
+    pub mode: SessionMode,
 }
 
 /// Document status from OnlyOffice callback
@@ -120,7 +155,9 @@ pub struct DocumentClaims {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EditorConfigClaims {
-    pub callback_url: String,
+    /// Not set for view sessions, which are never saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
     pub mode: String,
 }
 
@@ -162,11 +199,6 @@ fn choose_display_name(full_name: &str, user_name: &str) -> String {
     }
 }
 
-/// Get user's file storage path
-fn get_user_path(config: &crate::config::Config, username: &str) -> PathBuf {
-    config.root_dir.join(username)
-}
-
 /// Resolve file ID from path
 async fn resolve_file_id(
     db: &sea_orm::DatabaseConnection,
@@ -250,8 +282,22 @@ pub async fn create_editing_session(
     Extension(current_user): Extension<CurrentUser>,
     Json(req): Json<CreateSessionRequest>,
 ) -> impl IntoResponse {
+    let Some(abs_file_path) = resolve_in_user_root(&state.config, &current_user.username, &req.file_path) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "invalid path"})),
+        )
+            .into_response();
+    };
     let user_path = get_user_path(&state.config, &current_user.username);
-    let abs_file_path = user_path.join(req.file_path.trim_start_matches('/'));
+    if let Err(e) = tiering::recall(&db, &current_user.username, &user_path, &req.file_path).await {
+        tracing::error!("Failed to recall {}: {}", req.file_path, e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "failed to recall file from cold storage"})),
+        )
+            .into_response();
+    }
 
     // Check if file exists
     let file_info = match fs::metadata(&abs_file_path).await {
@@ -265,8 +311,21 @@ pub async fn create_editing_session(
         }
     };
 
-    // Generate consistent session ID based on absolute path for collaboration
-    let session_id = generate_session_id(&abs_file_path.to_string_lossy());
+    // Generate consistent session ID based on absolute path for collaboration.
+    // View sessions get their own ID per file version, so they never join an
+    // editing session and show the latest saved content.
+    let session_id = match req.mode {
+        SessionMode::Edit => generate_session_id(&abs_file_path.to_string_lossy()),
+        SessionMode::View => {
+            let mtime = file_info
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            generate_session_id(&format!("view:{}:{}", abs_file_path.to_string_lossy(), mtime))
+        }
+    };
 
     // Record file access for document editing
     let file_name = std::path::Path::new(&req.file_path)
@@ -284,12 +343,14 @@ pub async fn create_editing_session(
             file_id,
             &clean_path,
             &file_name,
-            "edit",
+            if req.mode == SessionMode::View { "preview" } else { "edit" },
             false,
         ).await;
     }
 
     // Check if session already exists
+    let now = chrono::Utc::now().timestamp();
+    EDITING_SESSIONS.retain(|_, session| !session.is_expired(now));
     if let Some(existing) = EDITING_SESSIONS.get(&session_id) {
         tracing::info!(
             "Returning existing session: {} for file: {} by user: {}",
@@ -317,9 +378,15 @@ pub async fn create_editing_session(
             key: session_id.clone(),
             url: format!("{}/api/editing/download/{}", doc_config.datadisk_url, session_id),
         },
-        editor_config: EditorConfigClaims {
-            callback_url: format!("{}/api/editing/save/{}", doc_config.datadisk_url, session_id),
-            mode: "edit".to_string(),
+        editor_config: match req.mode {
+            SessionMode::Edit => EditorConfigClaims {
+                callback_url: Some(format!("{}/api/editing/save/{}", doc_config.datadisk_url, session_id)),
+                mode: "edit".to_string(),
+            },
+            SessionMode::View => EditorConfigClaims {
+                callback_url: None,
+                mode: "view".to_string(),
+            },
         },
     };
 
@@ -337,7 +404,7 @@ pub async fn create_editing_session(
 
     let session = EditingSession {
         session_id: session_id.clone(),
-        created_at: chrono::Utc::now().timestamp(),
+        created_at: now,
         file_path: req.file_path.clone(),
         abs_file_path: abs_file_path.clone(),
         file_size: file_info.len() as i64,
@@ -352,6 +419,7 @@ pub async fn create_editing_session(
         email: current_user.email.clone(),
         doc_server_url: doc_config.doc_server_url.clone(),
         datadisk_url: doc_config.datadisk_url.clone(),
+        mode: req.mode,
     };
 
     EDITING_SESSIONS.insert(session_id.clone(), session.clone());
@@ -389,9 +457,10 @@ pub async fn get_editing_session(
     }
 
     // Get session
+    let now = chrono::Utc::now().timestamp();
     let session = match EDITING_SESSIONS.get(&session_id) {
-        Some(s) => s.clone(),
-        None => {
+        Some(s) if !s.is_expired(now) => s.clone(),
+        _ => {
             tracing::error!("Session not found: {}", session_id);
             return (
                 StatusCode::UNAUTHORIZED,
@@ -465,6 +534,14 @@ pub async fn save_editing_session(
         }
     };
 
+    if session.mode == SessionMode::View {
+        tracing::warn!("Save callback for view session {} rejected", session_id);
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": "read-only session"})),
+        );
+    }
+
     tracing::debug!(
         "Handle file {} save callback request: {:?}",
         session.abs_file_path.display(),
@@ -475,7 +552,7 @@ pub async fn save_editing_session(
     let status = callback.status;
     if status == 2 || status == 6 || status == 3 || status == 7 {
         // ReadyForSave, BeingEditedSaved, SaveWithError, ForceSaveWithError
-        if let Err(e) = on_save(&callback, &session).await {
+        if let Err(e) = on_save(&state, &callback, &session).await {
             tracing::error!("Failed to save file: {}", e);
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -488,19 +565,30 @@ pub async fn save_editing_session(
 }
 
 /// Handle document save from OnlyOffice
-async fn on_save(callback: &CallbackRequest, session: &EditingSession) -> Result<(), String> {
+///
+/// The document is downloaded next to the original and validated, the
+/// previous content is kept in the trash, then the new file atomically
+/// replaces the original.
+async fn on_save(
+    state: &AppState,
+    callback: &CallbackRequest,
+    session: &EditingSession,
+) -> Result<(), String> {
     if callback.url.is_empty() {
         return Err("No download URL provided".to_string());
     }
 
-    // Create temp directory
-    let tmp_dir = std::env::temp_dir().join("datadisk_editing");
-    fs::create_dir_all(&tmp_dir).await.map_err(|e| format!("Failed to create temp dir: {}", e))?;
-
-    let tmp_path = tmp_dir.join(&callback.key);
+    let ext = std::path::Path::new(&session.file_path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    if !callback.file_type.is_empty() && !callback.file_type.eq_ignore_ascii_case(&ext) {
+        return Err(format!("Unexpected file type {}, expected {}", callback.file_type, ext));
+    }
 
     // Download file from OnlyOffice
-    let response = reqwest::get(&callback.url)
+    let mut response = outbound::get(&state.config, &callback.url)
         .await
         .map_err(|e| format!("Failed to download file: {}", e))?;
 
@@ -508,20 +596,142 @@ async fn on_save(callback: &CallbackRequest, session: &EditingSession) -> Result
         return Err(format!("Download failed with status: {}", response.status()));
     }
 
-    let bytes = response
-        .bytes()
+    // Error pages from a misbehaving server are not documents
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if content_type.starts_with("text/html") || content_type.starts_with("application/json") {
+        return Err(format!("Unexpected content type: {}", content_type));
+    }
+    let limit = (state.config.max_upload_size as u64).min(state.config.outbound.max_response_size);
+    if response.content_length().is_some_and(|len| len > limit) {
+        return Err("Document exceeds the maximum file size".to_string());
+    }
+
+    // Write to a temp file next to the original, so the final rename is atomic
+    let file_name = session
+        .abs_file_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or("Invalid file path")?
+        .to_string();
+    let tmp_path = session
+        .abs_file_path
+        .with_file_name(format!(".{}.{}.saving", file_name, uuid::Uuid::new_v4()));
+    let mut tmp_file = fs::File::create(&tmp_path)
         .await
-        .map_err(|e| format!("Failed to read response: {}", e))?;
+        .map_err(|e| format!("Failed to create temp file: {}", e))?;
+
+    let result = async {
+        let mut head = Vec::with_capacity(8);
+        let mut size: u64 = 0;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| format!("Failed to read response: {}", e))?
+        {
+            size += chunk.len() as u64;
+            if size > limit {
+                return Err("Document exceeds the maximum file size".to_string());
+            }
+            if head.len() < 8 {
+                head.extend(chunk.iter().take(8 - head.len()));
+            }
+            tmp_file
+                .write_all(&chunk)
+                .await
+                .map_err(|e| format!("Failed to write temp file: {}", e))?;
+        }
+        tmp_file
+            .sync_all()
+            .await
+            .map_err(|e| format!("Failed to write temp file: {}", e))?;
+        validate_document(&ext, &head)?;
+        replace_document(state, session, &file_name, &tmp_path, size as i64).await
+    }
+    .await;
 
-    // Write to temp file
-    fs::write(&tmp_path, &bytes)
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path).await;
+    }
+    result
+}
+
+/// Check that downloaded content looks like a document of the given extension
+fn validate_document(ext: &str, head: &[u8]) -> Result<(), String> {
+    if !mime::signature_matches(ext, head) {
+        return Err(format!("Downloaded content is not a valid {} document", ext));
+    }
+    Ok(())
+}
+
+/// Replace the edited document with the validated temp file
+///
+/// Checks the size change against the quota and backs up the previous
+/// content first, then updates the file record.
+async fn replace_document(
+    state: &AppState,
+    session: &EditingSession,
+    file_name: &str,
+    tmp_path: &std::path::Path,
+    new_size: i64,
+) -> Result<(), String> {
+    let old_size = fs::metadata(&session.abs_file_path)
         .await
-        .map_err(|e| format!("Failed to write temp file: {}", e))?;
+        .map(|m| m.len() as i64)
+        .unwrap_or(0);
+    let parent_path = session
+        .file_path
+        .trim_matches('/')
+        .rsplit_once('/')
+        .map(|(parent, _)| parent)
+        .unwrap_or("");
+    let db = state.get_db().await;
 
-    // Move temp file to target location
-    fs::rename(&tmp_path, &session.abs_file_path)
+    if let Some(db) = &db {
+        match legal_hold::held(db, &session.user_name, &session.file_path).await {
+            Ok(None) => {}
+            Ok(Some(hold)) => return Err(legal_hold::message(&hold.path)),
+            Err(e) => return Err(format!("Failed to check legal hold: {}", e)),
+        }
+        if new_size > old_size {
+            quota::check_quota(db, &state.config, &session.user_name, new_size - old_size)
+                .await
+                .map_err(|e| e.message())?;
+        }
+        // A failed backup shouldn't lose the user's edits
+        if old_size > 0 {
+            if let Err(e) =
+                backup_to_trash(&state.config, db, &session.user_name, parent_path, file_name).await
+            {
+                tracing::warn!("Failed to back up {} before save: {}", session.file_path, e);
+            }
+        }
+    }
+
+    fs::rename(tmp_path, &session.abs_file_path)
         .await
         .map_err(|e| format!("Failed to save file: {}", e))?;
+    dir_version::bump_entry(&session.user_name, &session.file_path);
+    watch::publish(&session.user_name, &session.file_path, &session.user_name, ChangeKind::Modified, Client::Web);
+    quota::add_usage(&session.user_name, new_size - old_size);
+
+    if let Some(db) = &db {
+        let file_id = resolve_file_id(db, &session.user_name, &session.file_path).await;
+        if file_id > 0 {
+            let updated = file_info::Entity::update_many()
+                .col_expr(file_info::Column::Size, Expr::value(new_size))
+                .col_expr(file_info::Column::ModifyTime, Expr::value(chrono::Utc::now().timestamp()))
+                .filter(file_info::Column::Id.eq(file_id))
+                .exec(db)
+                .await;
+            if let Err(e) = updated {
+                tracing::error!("Failed to update file info of {}: {}", session.file_path, e);
+            }
+        }
+    }
 
     tracing::info!("Successfully saved file {}", session.abs_file_path.display());
     Ok(())
@@ -540,3 +750,61 @@ pub async fn get_editing_session_info(
         ),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_document() {
+        assert!(validate_document("docx", b"PK\x03\x04\x14\x00").is_ok());
+        assert!(validate_document("xlsx", b"<html>").is_err());
+        assert!(validate_document("pptx", b"").is_err());
+        assert!(validate_document("doc", &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1]).is_ok());
+        assert!(validate_document("pdf", b"%PDF-1.7").is_ok());
+        assert!(validate_document("txt", b"").is_ok());
+    }
+
+    #[test]
+    fn test_view_mode_has_no_callback() {
+        let req: CreateSessionRequest = serde_json::from_str(r#"{"filePath":"/a.docx"}"#).unwrap();
+        assert_eq!(req.mode, SessionMode::Edit);
+        let req: CreateSessionRequest =
+            serde_json::from_str(r#"{"filePath":"/a.docx","mode":"view"}"#).unwrap();
+        assert_eq!(req.mode, SessionMode::View);
+
+        let claims = EditorConfigClaims {
+            callback_url: None,
+            mode: "view".to_string(),
+        };
+        assert_eq!(serde_json::to_value(&claims).unwrap(), serde_json::json!({"mode": "view"}));
+    }
+
+    #[test]
+    fn test_view_sessions_expire() {
+        let session = |mode| EditingSession {
+            session_id: "s".to_string(),
+            created_at: 1000,
+            file_path: "/a.docx".to_string(),
+            abs_file_path: PathBuf::from("/data/alice/a.docx"),
+            file_size: 0,
+            content_type: String::new(),
+            token: String::new(),
+            user_id: 1,
+            user_name: "alice".to_string(),
+            full_name: String::new(),
+            display_name: String::new(),
+            first_name: String::new(),
+            last_name: String::new(),
+            email: String::new(),
+            doc_server_url: String::new(),
+            datadisk_url: String::new(),
+            mode,
+        };
+        let later = 1000 + VIEW_SESSION_SECS;
+        assert!(!session(SessionMode::View).is_expired(later - 1));
+        assert!(session(SessionMode::View).is_expired(later));
+        // Edit sessions stay for the callback saving the document
+        assert!(!session(SessionMode::Edit).is_expired(later));
+    }
+}
@@ -0,0 +1,506 @@
+//! Folder expiration policies
+//!
+//! A policy on a folder moves what's in it to the trash once it hasn't been
+//! modified for `max_age_days`, for temp and exchange folders. Entries right
+//! in the folder expire as a whole, a subfolder when nothing below it has
+//! changed for that long.
+//!
+//! The owner is told `notice_days` ahead: connected clients get an
+//! `expiryNotice` message once a day while entries are about to expire, and
+//! `/api/expiry/pending` lists them. A new or changed policy deletes nothing
+//! before its notice period has passed. Entries under legal hold are kept.
+//!
+//! Policies stay with the path they were set on.
+
+use axum::{
+    extract::{Query, State},
+    response::Json,
+    Extension,
+};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    Set,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::Config;
+use crate::entity::{expiry_policy, user};
+use crate::entity::op_log::OpType;
+use crate::handlers::abuse::record_denied;
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::file::resolve_in_user_root;
+use crate::handlers::path::normalize;
+use crate::handlers::trash::move_to_trash;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::{ApiMessage, ApiResponse};
+use crate::service::{FileError, FileService};
+use crate::state::AppState;
+use crate::ws::{WsMessage, HUB};
+
+const OP_SUCCESS: &str = "成功";
+
+const DAY_SECS: i64 = 86400;
+/// Longest retention of a policy
+const MAX_AGE_DAYS: i32 = 3650;
+
+/// An entry of an expiring folder
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ExpiringItem {
+    pub path: String,
+    /// Last modification of the entry or anything below it (Unix timestamp)
+    #[serde(rename = "modifyTime")]
+    pub modify_time: i64,
+    /// When the entry is moved to the trash (Unix timestamp)
+    #[serde(rename = "expireTime")]
+    pub expire_time: i64,
+}
+
+/// Newest modification time of `path` and everything below it
+fn newest_mtime(path: &Path) -> i64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    let mut newest = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    if metadata.is_dir() {
+        if let Ok(entries) = std::fs::read_dir(path) {
+            for entry in entries.flatten() {
+                newest = newest.max(newest_mtime(&entry.path()));
+            }
+        }
+    }
+    newest
+}
+
+/// Entries of the policy's folder with the time they expire
+fn scan(dir: &Path, policy: &expiry_policy::Model) -> Vec<ExpiringItem> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    // Nothing expires before the owner had the full notice period
+    let earliest = policy.update_time + policy.notice_days as i64 * DAY_SECS;
+    let mut items: Vec<ExpiringItem> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let modify_time = newest_mtime(&entry.path());
+            Some(ExpiringItem {
+                path: format!("{}/{}", policy.path.trim_end_matches('/'), name),
+                modify_time,
+                expire_time: (modify_time + policy.max_age_days as i64 * DAY_SECS).max(earliest),
+            })
+        })
+        .collect();
+    items.sort_by(|a, b| a.expire_time.cmp(&b.expire_time).then_with(|| a.path.cmp(&b.path)));
+    items
+}
+
+/// Entries of the policy's folder, None if the folder is gone
+async fn policy_items(config: &Config, policy: &expiry_policy::Model) -> Option<Vec<ExpiringItem>> {
+    let dir = resolve_in_user_root(config, &policy.username, &policy.path)?;
+    if !dir.is_dir() {
+        return None;
+    }
+    let policy = policy.clone();
+    tokio::task::spawn_blocking(move || scan(&dir, &policy)).await.ok()
+}
+
+/// Whether `item` is announced at `now`: it expires within the notice period
+fn is_upcoming(policy: &expiry_policy::Model, item: &ExpiringItem, now: i64) -> bool {
+    item.expire_time > now && item.expire_time <= now + policy.notice_days as i64 * DAY_SECS
+}
+
+/// Move an expired entry to the trash, false if a legal hold keeps it
+async fn expire_item(
+    config: &Config,
+    db: &DatabaseConnection,
+    policy: &expiry_policy::Model,
+    item: &ExpiringItem,
+) -> Result<bool, FileError> {
+    let service = FileService::new(config, db, &policy.username);
+    match service.check_hold(&item.path).await {
+        Err(FileError::LegalHold(_)) => return Ok(false),
+        result => result?,
+    }
+    let (parent, name) = item.path.rsplit_once('/').unwrap_or(("", &item.path));
+    move_to_trash(config, db, &policy.username, parent, name).await?;
+    service.remove_rows(parent, name).await?;
+    // Not a deletion by the user, so it doesn't count towards the mass deletion alert
+    log_operation(&policy.username, OpType::Expire, &item.path, OP_SUCCESS, None);
+    Ok(true)
+}
+
+/// Tell the owner about entries expiring soon, at most once a day
+async fn notify(
+    db: &DatabaseConnection,
+    policy: &expiry_policy::Model,
+    items: Vec<ExpiringItem>,
+    now: i64,
+) -> Result<(), DbErr> {
+    if items.is_empty() || now - policy.notice_time < DAY_SECS {
+        return Ok(());
+    }
+    let owner = user::Entity::find()
+        .filter(user::Column::Username.eq(&policy.username))
+        .one(db)
+        .await?;
+    if let Some(owner) = owner {
+        let payload = serde_json::json!({ "path": policy.path, "items": items });
+        HUB.send(owner.id, WsMessage::ExpiryNotice(payload));
+    }
+    let mut policy: expiry_policy::ActiveModel = policy.clone().into();
+    policy.notice_time = Set(now);
+    policy.update(db).await?;
+    Ok(())
+}
+
+/// Apply every policy at `now`, returning the number of entries moved to the trash
+pub async fn run(config: &Config, db: &DatabaseConnection, now: i64) -> anyhow::Result<usize> {
+    let policies = expiry_policy::Entity::find().all(db).await?;
+    let mut expired = 0;
+    for policy in &policies {
+        let Some(items) = policy_items(config, policy).await else {
+            continue;
+        };
+        let mut upcoming = Vec::new();
+        for item in items {
+            if item.expire_time <= now {
+                match expire_item(config, db, policy, &item).await {
+                    Ok(true) => expired += 1,
+                    Ok(false) => tracing::debug!("Not expiring {} of {}: legal hold", item.path, policy.username),
+                    Err(e) => tracing::error!("Failed to expire {} of {}: {}", item.path, policy.username, e),
+                }
+            } else if is_upcoming(policy, &item, now) {
+                upcoming.push(item);
+            }
+        }
+        notify(db, policy, upcoming, now).await?;
+    }
+    Ok(expired)
+}
+
+/// Start the background job that applies the expiration policies
+pub fn start(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            ticker.tick().await;
+            let Some(db) = state.get_db().await else {
+                continue;
+            };
+            match run(&state.config, &db, chrono::Utc::now().timestamp()).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Moved {} expired items to the trash", n),
+                Err(e) => tracing::error!("Failed to apply expiration policies: {}", e),
+            }
+        }
+    });
+}
+
+/// User whose policies a request is about: the current user, or any user
+/// for user administrators
+fn target_user(current_user: &CurrentUser, username: Option<&str>) -> Option<String> {
+    match username.filter(|u| !u.is_empty()) {
+        None => Some(current_user.username.clone()),
+        Some(username) if username == current_user.username => Some(username.to_string()),
+        Some(username) => current_user.can_contacts().then(|| username.to_string()),
+    }
+}
+
+/// Query parameters selecting whose policies
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExpiryUserQuery {
+    /// Another user, for user administrators
+    pub username: Option<String>,
+}
+
+/// Set policy request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetPolicyRequest {
+    /// Another user, for user administrators
+    pub username: Option<String>,
+    pub path: String,
+    #[serde(rename = "maxAgeDays")]
+    pub max_age_days: i32,
+    #[serde(rename = "noticeDays", default)]
+    pub notice_days: i32,
+}
+
+/// Remove policy request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RemovePolicyRequest {
+    /// Another user, for user administrators
+    pub username: Option<String>,
+    pub path: String,
+}
+
+/// An expiration policy
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PolicyResponse {
+    pub username: String,
+    pub path: String,
+    #[serde(rename = "maxAgeDays")]
+    pub max_age_days: i32,
+    #[serde(rename = "noticeDays")]
+    pub notice_days: i32,
+    #[serde(rename = "createdBy")]
+    pub created_by: String,
+    #[serde(rename = "updateTime")]
+    pub update_time: i64,
+}
+
+impl From<expiry_policy::Model> for PolicyResponse {
+    fn from(policy: expiry_policy::Model) -> Self {
+        Self {
+            username: policy.username,
+            path: policy.path,
+            max_age_days: policy.max_age_days,
+            notice_days: policy.notice_days,
+            created_by: policy.created_by,
+            update_time: policy.update_time,
+        }
+    }
+}
+
+/// Policies of a user
+async fn user_policies(db: &DatabaseConnection, username: &str) -> Result<Vec<expiry_policy::Model>, DbErr> {
+    expiry_policy::Entity::find()
+        .filter(expiry_policy::Column::Username.eq(username))
+        .order_by_asc(expiry_policy::Column::Path)
+        .all(db)
+        .await
+}
+
+/// GET /api/expiry/list - Expiration policies of a user
+#[utoipa::path(
+    get,
+    path = "/api/expiry/list",
+    tag = "file",
+    params(ExpiryUserQuery),
+    responses((status = 200, body = ApiResponse<Vec<PolicyResponse>>)),
+)]
+pub async fn list_policies(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<ExpiryUserQuery>,
+) -> Json<ApiResponse<Vec<PolicyResponse>>> {
+    let Some(username) = target_user(&current_user, query.username.as_deref()) else {
+        record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    };
+    match user_policies(&db, &username).await {
+        Ok(policies) => Json(ApiResponse::success(policies.into_iter().map(Into::into).collect())),
+        Err(e) => {
+            tracing::error!("Failed to list expiration policies: {}", e);
+            Json(ApiResponse::error(500, "查询过期策略失败"))
+        }
+    }
+}
+
+/// POST /api/expiry/set - Set the expiration policy of a folder
+#[utoipa::path(
+    post,
+    path = "/api/expiry/set",
+    tag = "file",
+    request_body = SetPolicyRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
+pub async fn set_policy(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<SetPolicyRequest>,
+) -> Json<ApiResponse<()>> {
+    let Some(username) = target_user(&current_user, req.username.as_deref()) else {
+        record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    };
+    if !(1..=MAX_AGE_DAYS).contains(&req.max_age_days) {
+        return Json(ApiResponse::error(400, format!("保留天数须在1到{}之间", MAX_AGE_DAYS)));
+    }
+    if req.notice_days < 0 || req.notice_days >= req.max_age_days {
+        return Json(ApiResponse::error(400, "提前通知天数须小于保留天数"));
+    }
+    let path = normalize(&req.path);
+    if path == "/" {
+        return Json(ApiResponse::error(400, "不能为根目录设置过期策略"));
+    }
+    let is_dir = resolve_in_user_root(&state.config, &username, &path).is_some_and(|full| full.is_dir());
+    if !is_dir {
+        return Json(ApiResponse::error(404, "文件夹不存在"));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let result = async {
+        let existing = expiry_policy::Entity::find()
+            .filter(expiry_policy::Column::Username.eq(&username))
+            .filter(expiry_policy::Column::Path.eq(&path))
+            .one(&*db)
+            .await?;
+        let mut policy = match existing {
+            Some(policy) => policy.into(),
+            None => expiry_policy::ActiveModel {
+                username: Set(username.clone()),
+                path: Set(path.clone()),
+                ..Default::default()
+            },
+        };
+        // A changed policy starts a new notice period
+        policy.max_age_days = Set(req.max_age_days);
+        policy.notice_days = Set(req.notice_days);
+        policy.created_by = Set(current_user.username.clone());
+        policy.update_time = Set(now);
+        policy.notice_time = Set(0);
+        policy.save(&*db).await?;
+        Ok::<_, DbErr>(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => {
+            let op_desc = format!("{}: {}天, 提前{}天通知", path, req.max_age_days, req.notice_days);
+            log_operation(&current_user.username, OpType::SetExpiry, &op_desc, OP_SUCCESS, None);
+            Json(ApiResponse::success_msg("过期策略已设置"))
+        }
+        Err(e) => {
+            tracing::error!("Failed to set expiration policy: {}", e);
+            Json(ApiResponse::error(500, "设置过期策略失败"))
+        }
+    }
+}
+
+/// POST /api/expiry/remove - Remove the expiration policy of a folder
+#[utoipa::path(
+    post,
+    path = "/api/expiry/remove",
+    tag = "file",
+    request_body = RemovePolicyRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
+pub async fn remove_policy(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<RemovePolicyRequest>,
+) -> Json<ApiResponse<()>> {
+    let Some(username) = target_user(&current_user, req.username.as_deref()) else {
+        record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    };
+    let path = normalize(&req.path);
+    let result = expiry_policy::Entity::delete_many()
+        .filter(expiry_policy::Column::Username.eq(&username))
+        .filter(expiry_policy::Column::Path.eq(&path))
+        .exec(&*db)
+        .await;
+    match result {
+        Ok(res) if res.rows_affected == 0 => Json(ApiResponse::error(404, "该文件夹未设置过期策略")),
+        Ok(_) => {
+            log_operation(&current_user.username, OpType::RemoveExpiry, &path, OP_SUCCESS, None);
+            Json(ApiResponse::success_msg("过期策略已删除"))
+        }
+        Err(e) => {
+            tracing::error!("Failed to remove expiration policy: {}", e);
+            Json(ApiResponse::error(500, "删除过期策略失败"))
+        }
+    }
+}
+
+/// GET /api/expiry/pending - Entries of a user expiring within the notice period
+#[utoipa::path(
+    get,
+    path = "/api/expiry/pending",
+    tag = "file",
+    params(ExpiryUserQuery),
+    responses((status = 200, body = ApiResponse<Vec<ExpiringItem>>)),
+)]
+pub async fn pending_items(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<ExpiryUserQuery>,
+) -> Json<ApiResponse<Vec<ExpiringItem>>> {
+    let Some(username) = target_user(&current_user, query.username.as_deref()) else {
+        record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    };
+    let policies = match user_policies(&db, &username).await {
+        Ok(policies) => policies,
+        Err(e) => {
+            tracing::error!("Failed to list expiration policies: {}", e);
+            return Json(ApiResponse::error(500, "查询过期策略失败"));
+        }
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let mut pending = Vec::new();
+    for policy in &policies {
+        if let Some(items) = policy_items(&state.config, policy).await {
+            pending.extend(items.into_iter().filter(|item| is_upcoming(policy, item, now)));
+        }
+    }
+    pending.sort_by(|a, b| a.expire_time.cmp(&b.expire_time).then_with(|| a.path.cmp(&b.path)));
+    Json(ApiResponse::success(pending))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::trash;
+    use crate::permission::perm;
+    use crate::testing::TestEnv;
+
+    #[tokio::test]
+    async fn test_run() {
+        let env = TestEnv::new().await;
+        let service = FileService::new(&env.config, &env.db, "alice");
+        service.mkdir("/", None, "tmp").await.unwrap();
+        service.mkdir("/tmp", None, "old").await.unwrap();
+        let tmp = env.dir.join("upload.tmp");
+        for (parent, name) in [("/tmp", "a.txt"), ("/tmp/old", "b.txt")] {
+            std::fs::write(&tmp, b"x").unwrap();
+            service.upload_finalize(&tmp, parent, None, name, 1, None).await.unwrap();
+        }
+
+        let user = env.user("alice", &[perm::FILE]);
+        let req = SetPolicyRequest {
+            username: None,
+            path: "tmp".to_string(),
+            max_age_days: 30,
+            notice_days: 7,
+        };
+        assert!(set_policy(State(env.state()), Extension(env.db_conn()), Extension(user.clone()), Json(req)).await.code);
+        let req = SetPolicyRequest { username: Some("bob".to_string()), path: "/x".to_string(), max_age_days: 30, notice_days: 7 };
+        assert!(!set_policy(State(env.state()), Extension(env.db_conn()), Extension(user.clone()), Json(req)).await.code);
+
+        // Everything is new
+        let now = chrono::Utc::now().timestamp();
+        assert_eq!(run(&env.config, &env.db, now).await.unwrap(), 0);
+        let pending = pending_items(State(env.state()), Extension(env.db_conn()), Extension(user.clone()), Query(ExpiryUserQuery { username: None })).await;
+        assert!(pending.0.data.unwrap().is_empty());
+
+        // 25 days on both entries are announced, 31 days on they're in the trash
+        let policy = expiry_policy::Entity::find().one(&env.db).await.unwrap().unwrap();
+        let items = policy_items(&env.config, &policy).await.unwrap();
+        assert_eq!(items.iter().map(|i| i.path.as_str()).collect::<Vec<_>>(), vec!["/tmp/a.txt", "/tmp/old"]);
+        assert!(items.iter().all(|i| is_upcoming(&policy, i, now + 25 * DAY_SECS)));
+        assert_eq!(run(&env.config, &env.db, now + 25 * DAY_SECS).await.unwrap(), 0);
+        let policy = expiry_policy::Entity::find().one(&env.db).await.unwrap().unwrap();
+        assert_eq!(policy.notice_time, now + 25 * DAY_SECS);
+
+        assert_eq!(run(&env.config, &env.db, now + 31 * DAY_SECS).await.unwrap(), 2);
+        assert!(env.config.root_dir.join("alice/tmp").is_dir());
+        assert!(!env.config.root_dir.join("alice/tmp/a.txt").exists());
+        assert_eq!(trash::Entity::find().all(&env.db).await.unwrap().len(), 2);
+        assert_eq!(env.file_rows().await, vec![(-1, "tmp".to_string())]);
+
+        env.close().await;
+    }
+}
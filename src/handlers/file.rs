@@ -5,84 +5,103 @@
 use axum::{
     body::Body,
     extract::{Multipart, Query, State},
-    http::{header, StatusCode},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Json, Response},
     Extension,
 };
-use sea_orm::{
-    ActiveModelTrait, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, Set,
-    TransactionTrait,
-};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::fs;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio_util::io::ReaderStream;
-
-use crate::entity::{file_access, file_info};
+use sha2::{Digest, Sha256};
+use flate2::write::GzEncoder;
+
+use crate::charset;
+use crate::entity::{file_info};
+use crate::entity::op_log::OpType;
+use crate::filename;
+use crate::handlers::compression;
+use crate::handlers::dedup;
+use crate::handlers::dir_version;
+use crate::handlers::dlp::{self, Verdict};
 use crate::handlers::audit::service::log_operation;
+use crate::handlers::abuse::record_denied;
+use crate::handlers::dept_space::{self, LocateError};
+use crate::handlers::dir_size;
+use crate::handlers::group_space;
+use crate::handlers::heic;
+use crate::handlers::hot_cache;
+use crate::handlers::legal_hold;
+use crate::handlers::listing::{self, Arrangement};
+use crate::handlers::media;
+use crate::handlers::preview::{self, PreviewHandler, PreviewKind};
+use crate::handlers::quota;
+use crate::handlers::tiering;
+use crate::handlers::traffic;
+use crate::handlers::upload_limit;
 use crate::handlers::recent::record_file_access;
+use crate::handlers::sensitive;
+use crate::handlers::shredder;
+use crate::handlers::template;
+use crate::handlers::undo;
+use crate::metrics::{self, UploadRejection};
 use crate::middleware::auth::CurrentUser;
 use crate::middleware::DbConn;
-use crate::routes::ApiResponse;
+use crate::mime;
+use crate::repository::FileInfoRepository;
+use crate::routes::{ApiMessage, ApiResponse};
+use crate::service::file::DirectoryItem;
+use crate::service::{FileError, FileService, IfMatch};
+use crate::zip_stream::ZipStream;
 use crate::state::AppState;
 
-/// Check if a path is safe (no .. or traversal)
-fn is_safe_path(path: &str) -> bool {
-    let path = path.trim_start_matches('/');
-    if path.is_empty() {
-        return true;
-    }
-    std::path::Path::new(path)
-        .components()
-        .all(|c| matches!(c, std::path::Component::Normal(_)))
+/// Check if a path is safe (no .. or traversal), see [`filename::check_path`]
+pub(crate) fn is_safe_path(path: &str) -> bool {
+    filename::check_path(path).is_ok()
 }
 
-/// Check if a filename is safe (no path separators)
-fn is_safe_filename(name: &str) -> bool {
-    if name.is_empty() {
-        return false;
-    }
-
-    // Windows restricted characters
-    if name.chars().any(|c| "<>:\"/\\|?*".contains(c)) {
-        return false;
-    }
-
-    // Control characters
-    if name.chars().any(|c| c.is_control()) {
-        return false;
-    }
+/// Check if a filename is safe (no path separators), see [`filename::check_name`]
+pub(crate) fn is_safe_filename(name: &str) -> bool {
+    filename::check_name(name).is_ok()
+}
 
-    // Ban names consisting entirely of dots (., .., ..., ....)
-    if name.chars().all(|c| c == '.') {
-        return false;
+/// Refuse a change with 412 if any of `expected` (a path and the `lastmod`
+/// the client saw) changed since, before anything is changed
+async fn check_unchanged<'a>(
+    service: &FileService<'_>,
+    expected: impl IntoIterator<Item = (String, &'a IfMatch)>,
+) -> Result<(), Response> {
+    let mut changed = Vec::new();
+    for (path, seen) in expected {
+        match service.check_unchanged(&path, seen).await {
+            Ok(()) => {}
+            Err(FileError::Modified(path)) => changed.push(path),
+            Err(FileError::NotFound) => changed.push(path),
+            Err(FileError::InvalidPath) => {
+                return Err(Json(ApiResponse::<()>::error(400, "invalid path")).into_response())
+            }
+            Err(e) => {
+                tracing::error!("Failed to check {}: {}", path, e);
+                return Err(Json(ApiResponse::<()>::error(500, "failed to check file")).into_response());
+            }
+        }
     }
-
-    let path = std::path::Path::new(name);
-    let mut components = path.components();
-    match (components.next(), components.next()) {
-        (Some(std::path::Component::Normal(c)), None) => c == name,
-        _ => false,
+    if changed.is_empty() {
+        return Ok(());
     }
-}
-
-/// Operation types (matching Go version)
-mod op_type {
-    pub const MKDIR: &str = "创建目录";
-    pub const OPEN_FILE: &str = "访问目录/文件";
-    pub const DELETE: &str = "删除";
-    pub const RENAME: &str = "重命名";
-    pub const COPY: &str = "复制";
-    pub const MOVE: &str = "移动";
-    pub const UPLOAD: &str = "上传";
-    pub const DOWNLOAD: &str = "下载";
+    let message = format!("文件已被修改: {}", changed.join(", "));
+    Err((StatusCode::PRECONDITION_FAILED, Json(ApiResponse::<()>::error(412, message))).into_response())
 }
 
 const OP_SUCCESS: &str = "成功";
+const OP_PARTIAL: &str = "部分完成";
 
 /// Download info storage
 static DOWNLOAD_MAP: std::sync::LazyLock<Mutex<HashMap<String, DownloadInfo>>> =
@@ -92,10 +111,12 @@ static DOWNLOAD_MAP: std::sync::LazyLock<Mutex<HashMap<String, DownloadInfo>>> =
 struct DownloadInfo {
     files: Vec<String>,
     parent_dir: String,
+    format: DownloadFormat,
+    level: Option<u32>,
 }
 
 /// Mkdir request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct MkdirRequest {
     pub path: Option<String>,
     pub name: String,
@@ -105,57 +126,124 @@ pub struct MkdirRequest {
     pub parent_path: Option<String>,
 }
 
+/// New file request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TouchRequest {
+    pub name: String,
+    #[serde(rename = "parentId")]
+    pub parent_id: Option<i64>,
+    #[serde(rename = "parentPath")]
+    pub parent_path: Option<String>,
+    /// `docx`, `xlsx`, `md` or `txt`, added to the name if it doesn't end in
+    /// it; by default the template of the name's extension, if there is one
+    pub template: Option<String>,
+}
+
 /// Delete file request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct DeleteFileRequest {
     pub ids: Vec<i64>,
     #[serde(rename = "parentPath")]
     pub parent_path: String,
+    /// `lastmod` of the files as listed, by ID; nothing is deleted if one changed since
+    #[serde(default, rename = "ifMatch")]
+    pub if_match: HashMap<i64, IfMatch>,
+    /// Remove the files for good instead of moving them to the trash
+    #[serde(default)]
+    pub permanent: bool,
 }
 
 /// File query parameters
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct FileQuery {
     #[serde(rename = "parentId")]
     pub parent_id: i64,
 }
 
 /// Download pre request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct DownloadPreRequest {
     pub files: Vec<String>,
     #[serde(rename = "parentDir")]
     pub parent_dir: String,
+    /// Archive the files are streamed in, zip by default
+    #[serde(default)]
+    pub format: DownloadFormat,
+    /// Compression level from 0 to 9: zip entries are stored uncompressed
+    /// unless a level above 0 is given, tar.gz uses 6 by default
+    #[serde(default)]
+    pub level: Option<u32>,
+}
+
+/// Archive format of a multi-file download
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+pub enum DownloadFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz", alias = "tgz")]
+    TarGz,
+}
+
+impl DownloadFormat {
+    fn file_name(self) -> &'static str {
+        match self {
+            DownloadFormat::Zip => "download.zip",
+            DownloadFormat::Tar => "download.tar",
+            DownloadFormat::TarGz => "download.tar.gz",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            DownloadFormat::Zip => "application/octet-stream",
+            DownloadFormat::Tar => "application/x-tar",
+            DownloadFormat::TarGz => "application/gzip",
+        }
+    }
 }
 
 /// Download query
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct DownloadQuery {
     pub guid: String,
 }
 
 /// Download pre response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct DownloadPreResponse {
     pub result: bool,
     pub guid: String,
 }
 
 /// Rename request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct RenameRequest {
     #[serde(rename = "oldPath")]
     pub old_path: String,
     #[serde(rename = "newName")]
     pub new_name: String,
+    /// `lastmod` of the file as listed; the rename fails if it changed since
+    #[serde(default, rename = "ifMatch")]
+    pub if_match: Option<IfMatch>,
 }
 
 /// Delete files request (new API)
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct DeleteFilesRequest {
     pub files: Vec<String>,
     #[serde(rename = "parentDir")]
     pub parent_dir: String,
+    /// `lastmod` of the files as listed, by name; nothing is deleted if one changed since
+    #[serde(default, rename = "ifMatch")]
+    pub if_match: HashMap<String, IfMatch>,
+    /// Remove the files for good instead of moving them to the trash
+    #[serde(default)]
+    pub permanent: bool,
 }
 
 /// Create directory request (new API)
@@ -166,13 +254,47 @@ pub struct CreateDirRequest {
 }
 
 /// Path query
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct PathQuery {
     pub path: String,
 }
 
+/// Directory listing query
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    pub path: String,
+    /// Give directories the size of all files below them, see [`dir_size`]
+    #[serde(rename = "withDirSize", default)]
+    pub with_dir_size: bool,
+    /// `name` (default), `size`, `mtime` or `type`, see [`listing`]
+    #[serde(rename = "sortBy")]
+    pub sort_by: Option<String>,
+    /// `asc` (default) or `desc`
+    pub order: Option<String>,
+    /// Glob of names (`*.txt`), or extensions of files (`jpg,png`)
+    pub filter: Option<String>,
+    /// Entries skipped
+    #[serde(default)]
+    pub offset: usize,
+    /// Entries listed at most, all if unset
+    pub limit: Option<usize>,
+}
+
+/// File content query
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ContentQuery {
+    pub path: String,
+    /// Serve the bytes as stored, with their detected charset in the content
+    /// type, instead of converting them to UTF-8
+    #[serde(default)]
+    pub raw: bool,
+}
+
 /// File info response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct FileInfoResponse {
     pub id: i64,
     pub name: String,
@@ -206,27 +328,79 @@ impl From<file_info::Model> for FileInfoResponse {
     }
 }
 
-/// Directory listing item (for new API)
-#[derive(Debug, Serialize)]
-pub struct DirectoryItem {
-    pub basename: String,
-    pub filename: String,
-    #[serde(rename = "type")]
-    pub item_type: String,
-    pub size: i64,
-    pub lastmod: String,
-    pub mime: String,
-}
-
 /// Get user path from config and username
-/// Path format: {root_dir}/{username} (matching Go version)
+/// Path format: {root_dir}/{username} (matching Go version), see
+/// [`dept_space`] and [`group_space`] for the folders of shared spaces
 pub fn get_user_path(config: &crate::config::Config, username: &str) -> PathBuf {
-    config.root_dir.join(username)
+    if let Some(dept_id) = dept_space::dept_id(username) {
+        return config.dept_space_dir(dept_id);
+    }
+    match group_space::group_id(username) {
+        Some(group_id) => config.group_space_dir(group_id),
+        None => config.root_dir.join(username),
+    }
+}
+
+/// Whether a name is taken by the owner of a shared space and so can't be
+/// a username
+pub fn is_space_owner(name: &str) -> bool {
+    dept_space::dept_id(name).is_some() || group_space::group_id(name).is_some()
+}
+
+/// Resolve a client path inside a user's root directory
+///
+/// Rejects unsafe paths and paths that leave the user root through symlinks.
+/// The path may not exist yet (upload and mkdir targets); its existing part is
+/// canonicalized. Returns the joined, non-canonical path so callers can keep
+/// relating it to [`get_user_path`].
+pub(crate) fn resolve_in_user_root(
+    config: &crate::config::Config,
+    username: &str,
+    path: &str,
+) -> Option<PathBuf> {
+    resolve_in_root(&get_user_path(config, username), path)
+}
+
+/// Resolve a relative path inside `root`, see [`resolve_in_user_root`]
+pub(crate) fn resolve_in_root(root: &Path, path: &str) -> Option<PathBuf> {
+    if !is_safe_path(path) {
+        return None;
+    }
+    let full = root.join(path.trim_start_matches('/'));
+    let root_canonical = canonicalize_existing(root)?;
+    let full_canonical = canonicalize_existing(&full)?;
+    if !full_canonical.starts_with(&root_canonical) {
+        tracing::warn!("Path {:?} resolves outside of {:?}", full, root);
+        return None;
+    }
+    Some(full)
+}
+
+/// Canonicalize the longest existing prefix of a path and append the rest
+///
+/// Returns None for dangling symlinks, whose target could be created later.
+fn canonicalize_existing(path: &Path) -> Option<PathBuf> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(canonical) => {
+                return Some(missing.iter().rev().fold(canonical, |p, name| p.join(name)));
+            }
+            Err(_) if existing.symlink_metadata().is_ok() => return None,
+            Err(_) => {}
+        }
+        missing.push(existing.file_name()?);
+        existing = match existing.parent()? {
+            parent if parent.as_os_str().is_empty() => Path::new("."),
+            parent => parent,
+        };
+    }
 }
 
 /// Resolve directory ID from path
-async fn resolve_dir_id(
-    db: &sea_orm::DatabaseConnection,
+pub(crate) async fn resolve_dir_id(
+    db: &(impl FileInfoRepository + ?Sized),
     username: &str,
     path: &str,
 ) -> i64 {
@@ -243,14 +417,7 @@ async fn resolve_dir_id(
     let mut parent_id: i64 = -1;
 
     for part in parts {
-        let file_info = file_info::Entity::find()
-            .filter(file_info::Column::ParentId.eq(parent_id))
-            .filter(file_info::Column::Username.eq(username))
-            .filter(file_info::Column::Name.eq(part))
-            .one(db)
-            .await;
-
-        match file_info {
+        match db.find_child(username, parent_id, part).await {
             Ok(Some(f)) => {
                 if !f.is_directory {
                     return 0;
@@ -303,90 +470,191 @@ async fn resolve_file_info(
     last_file.map(|f| (f.id, f.name))
 }
 
-/// POST /api/file/mkdir
-pub async fn mkdir(
-    State(state): State<AppState>,
-    Extension(db): Extension<DbConn>,
-    Extension(current_user): Extension<CurrentUser>,
-    Json(req): Json<MkdirRequest>,
-) -> Json<ApiResponse<()>> {
-    if req.name.is_empty() || !is_safe_filename(&req.name) {
-        return Json(ApiResponse::error(400, "文件夹名称无效"));
+/// Locate a path of the file API, see [`group_space::locate`] and
+/// [`dept_space::locate`]
+///
+/// Paths in spaces the user isn't a member of count as denied requests.
+pub(crate) async fn locate(
+    state: &AppState,
+    db: &sea_orm::DatabaseConnection,
+    user: &CurrentUser,
+    path: &str,
+) -> Result<dept_space::Location, (StatusCode, &'static str)> {
+    let located = match group_space::locate(state, db, user, path).await {
+        Ok(Some(location)) => Ok(location),
+        Ok(None) => dept_space::locate(state, db, user, path).await,
+        Err(e) => Err(e),
+    };
+    match located {
+        Ok(location) => Ok(location),
+        Err(LocateError::NotFound) => Err((StatusCode::NOT_FOUND, "path not found")),
+        Err(LocateError::Forbidden) => {
+            record_denied(&user.username);
+            Err((StatusCode::FORBIDDEN, "permission denied"))
+        }
+        Err(LocateError::Database(e)) => {
+            tracing::error!("Failed to locate {}: {}", path, e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "internal error"))
+        }
+    }
+}
+
+/// Locate a path the user is about to change
+///
+/// Like [`locate`], but group spaces whose members may only read are denied.
+pub(crate) async fn locate_for_write(
+    state: &AppState,
+    db: &sea_orm::DatabaseConnection,
+    user: &CurrentUser,
+    path: &str,
+) -> Result<dept_space::Location, (StatusCode, &'static str)> {
+    let location = locate(state, db, user, path).await?;
+    if !location.writable {
+        record_denied(&user.username);
+        return Err((StatusCode::FORBIDDEN, "read-only space"));
     }
+    Ok(location)
+}
 
-    let user_path = get_user_path(&state.config, &current_user.username);
-    let parent_path = req.parent_path.clone().or(req.path.clone()).unwrap_or_default();
-    if !is_safe_path(&parent_path) {
-        return Json(ApiResponse::error(400, "invalid parent path"));
+/// Bring `path` of `owner` back from cold storage before it's read
+async fn recall(
+    state: &AppState,
+    db: &sea_orm::DatabaseConnection,
+    owner: &str,
+    path: &str,
+) -> Result<(), (StatusCode, &'static str)> {
+    let root = get_user_path(&state.config, owner);
+    match tiering::recall(db, owner, &root, path).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::error!("Failed to recall {} of {}: {}", path, owner, e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "failed to recall file from cold storage"))
+        }
     }
-    let parent_path = parent_path.trim_start_matches('/').to_string();
+}
 
-    // Resolve parent ID
-    let parent_id = if let Some(pid) = req.parent_id {
-        if pid > 0 { pid } else { -1 }
-    } else {
-        resolve_dir_id(&*db, &current_user.username, &parent_path).await
+/// Reader of a file being sent to the client
+///
+/// Compressed files are decompressed on the fly, files in cold storage are
+/// recalled first.
+async fn open_content(
+    state: &AppState,
+    db: &sea_orm::DatabaseConnection,
+    owner: &str,
+    path: &str,
+    file_path: &Path,
+) -> Result<std::pin::Pin<Box<dyn tokio::io::AsyncRead + Send>>, (StatusCode, &'static str)> {
+    let opened = match compression::stored(db, owner, path).await {
+        Ok(Some(stub)) => compression::open(&stub).await.map(|reader| Box::pin(reader) as _),
+        Ok(None) => {
+            recall(state, db, owner, path).await?;
+            fs::File::open(file_path).await.map(|file| Box::pin(file) as _)
+        }
+        Err(e) => {
+            tracing::error!("Failed to look up compressed copy of {}: {}", path, e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "internal error"));
+        }
     };
+    opened.map_err(|e| {
+        tracing::error!("Failed to open file: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "failed to open file")
+    })
+}
 
-    // Check if parent exists (if parent_id > 0)
-    if parent_id > 0 {
-        let count = file_info::Entity::find()
-            .filter(file_info::Column::Id.eq(parent_id))
-            .filter(file_info::Column::Username.eq(&current_user.username))
-            .count(&*db)
-            .await;
+/// Content of a small file from the [hot file cache](hot_cache), None if it
+/// isn't cached and is read the usual way
+async fn hot_file(
+    state: &AppState,
+    location: &dept_space::Location,
+    file_path: &Path,
+    metadata: &std::fs::Metadata,
+) -> Option<std::sync::Arc<hot_cache::Cached>> {
+    hot_cache::read(&state.config.hot_cache, &location.owner, &location.path, file_path, metadata)
+        .await
+        .inspect_err(|e| tracing::debug!("Failed to read {:?} into the hot cache: {}", file_path, e))
+        .ok()
+        .flatten()
+}
 
-        match count {
-            Ok(0) => return Json(ApiResponse::error(500, "parent_dir_not_exists")),
-            Err(e) => {
-                tracing::error!("Database error: {}", e);
-                return Json(ApiResponse::error(500, "internal error"));
-            }
-            _ => {}
+/// The file watermarked for `viewer` if it is an image or PDF below a
+/// sensitive folder, None if it is served as it is
+///
+/// `disposition` is `inline` or `attachment`.
+async fn watermarked(
+    state: &AppState,
+    db: &sea_orm::DatabaseConnection,
+    location: &dept_space::Location,
+    file_path: &Path,
+    viewer: &str,
+    disposition: &str,
+) -> Option<Response> {
+    let error = |status: StatusCode, error: &str| Some((status, Json(serde_json::json!({ "error": error }))).into_response());
+    let kind = match sensitive::watermark_kind(db, &location.owner, &location.path).await {
+        Ok(kind) => kind?,
+        Err(e) => {
+            tracing::error!("Failed to look up sensitive folders of {}: {}", location.path, e);
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "internal error");
         }
-    } else if parent_id == 0 {
-        return Json(ApiResponse::error(400, "parent_dir_not_exists"));
+    };
+    let reader = match open_content(state, db, &location.owner, &location.path, file_path).await {
+        Ok(reader) => reader,
+        Err((status, message)) => return error(status, message),
+    };
+    let mut content = Vec::new();
+    if let Err(e) = reader.take(sensitive::MAX_SIZE + 1).read_to_end(&mut content).await {
+        tracing::error!("Failed to read file: {}", e);
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "failed to read file");
     }
+    if content.len() as u64 > sensitive::MAX_SIZE {
+        return error(StatusCode::PAYLOAD_TOO_LARGE, "file too large to watermark");
+    }
+    let (data, content_type) = match sensitive::render(viewer, kind, content).await {
+        Ok(rendered) => rendered,
+        Err(response) => return Some(response),
+    };
 
-    // Create directory in transaction
-    let now = chrono::Utc::now().timestamp();
-    let dir_name = req.name.clone();
-    let parent_path_for_log = parent_path.clone();
-    let username_for_log = current_user.username.clone();
-    let result = (&*db)
-        .transaction::<_, (), sea_orm::DbErr>(|txn| {
-            Box::pin(async move {
-                // Insert into database
-                let new_dir = file_info::ActiveModel {
-                    username: Set(current_user.username.clone()),
-                    file_type: Set("dir".to_string()),
-                    name: Set(req.name.clone()),
-                    parent_id: Set(parent_id),
-                    create_time: Set(now),
-                    modify_time: Set(now),
-                    is_directory: Set(true),
-                    size: Set(0),
-                    ..Default::default()
-                };
-                new_dir.insert(txn).await?;
-
-                // Create directory on filesystem
-                let dir_path = user_path.join(&parent_path).join(&req.name);
-                tokio::fs::create_dir_all(&dir_path)
-                    .await
-                    .map_err(|e: std::io::Error| sea_orm::DbErr::Custom(e.to_string()))?;
-
-                Ok(())
-            })
-        })
-        .await;
+    let filename = file_path.file_name().and_then(|n| n.to_str()).unwrap_or("download");
+    let body = futures::stream::iter([Ok::<_, std::io::Error>(data)]);
+    Some(
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            // Marked with the time, never reused
+            .header(header::CACHE_CONTROL, "private, no-store")
+            .header(header::CONTENT_DISPOSITION, format!("{}; filename=\"{}\"", disposition, filename))
+            .body(Body::from_stream(traffic::counted(viewer, body)))
+            .unwrap(),
+    )
+}
 
-    match result {
-        Ok(_) => {
-            let op_desc = format!("{}/{}", parent_path_for_log, dir_name);
-            log_operation(&username_for_log, op_type::MKDIR, &op_desc, OP_SUCCESS, None);
-            Json(ApiResponse::success_msg("success"))
-        }
+/// POST /api/file/mkdir
+#[utoipa::path(
+    post,
+    path = "/api/file/mkdir",
+    tag = "file",
+    request_body = MkdirRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
+pub async fn mkdir(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<MkdirRequest>,
+) -> Json<ApiResponse<()>> {
+    let parent_path = req.parent_path.or(req.path).unwrap_or_default();
+    let location = match locate_for_write(&state, &db, &current_user, &parent_path).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    let service = FileService::new(&state.config, &db, &location.owner)
+        .on_behalf_of(&current_user.username, &location.prefix);
+    match service.mkdir(&location.path, req.parent_id, &req.name).await {
+        Ok(_) => Json(ApiResponse::success_msg("success")),
+        Err(FileError::InvalidName(e)) => Json(ApiResponse::error(400, format!("文件夹名称无效: {}", e))),
+        Err(FileError::InvalidPath) => Json(ApiResponse::error(400, "invalid parent path")),
+        Err(FileError::ParentNotFound) => Json(ApiResponse::error(400, "parent_dir_not_exists")),
+        Err(FileError::AlreadyExists) => Json(ApiResponse::error(409, "文件夹已存在")),
         Err(e) => {
             tracing::error!("Failed to create directory: {}", e);
             Json(ApiResponse::error(500, "create_dir_error"))
@@ -394,13 +662,95 @@ pub async fn mkdir(
     }
 }
 
+/// POST /api/file/touch - Create an empty file, or one from a template
+#[utoipa::path(
+    post,
+    path = "/api/file/touch",
+    tag = "file",
+    request_body = TouchRequest,
+    responses((status = 200, body = ApiResponse<FileInfoResponse>)),
+)]
+pub async fn touch(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<TouchRequest>,
+) -> Json<ApiResponse<FileInfoResponse>> {
+    let mut name = req.name.trim().to_string();
+    let template = match req.template.as_deref().map(str::to_ascii_lowercase) {
+        Some(template) => {
+            if !template::NAMES.contains(&template.as_str()) {
+                return Json(ApiResponse::error(400, format!("未知的模板: {}", template)));
+            }
+            if mime::extension(&name) != template {
+                name = format!("{}.{}", name, template);
+            }
+            Some(template)
+        }
+        None => Some(mime::extension(&name)).filter(|ext| template::NAMES.contains(&ext.as_str())),
+    };
+    let content = template.and_then(|template| template::render(&template, &name)).unwrap_or_default();
+
+    let parent_path = req.parent_path.unwrap_or_default();
+    let location = match locate_for_write(&state, &db, &current_user, &parent_path).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    if let Err(exceeded) = quota::check_quota(&db, &state.config, &location.owner, content.len() as i64).await {
+        return Json(ApiResponse::error(413, exceeded.message()));
+    }
+    let service = FileService::new(&state.config, &db, &location.owner)
+        .on_behalf_of(&current_user.username, &location.prefix);
+    match service.touch(&location.path, req.parent_id, &name, &content).await {
+        Ok(model) => Json(ApiResponse::success(model.into())),
+        Err(FileError::InvalidName(e)) => Json(ApiResponse::error(400, format!("文件名称无效: {}", e))),
+        Err(FileError::InvalidPath) => Json(ApiResponse::error(400, "invalid parent path")),
+        Err(FileError::ParentNotFound) => Json(ApiResponse::error(400, "parent_dir_not_exists")),
+        Err(FileError::AlreadyExists) => Json(ApiResponse::error(409, "文件已存在")),
+        Err(e) => {
+            tracing::error!("Failed to create file: {}", e);
+            Json(ApiResponse::error(500, "create_file_error"))
+        }
+    }
+}
+
 /// GET /api/file/query/files
+#[utoipa::path(
+    get,
+    path = "/api/file/query/files",
+    tag = "file",
+    params(FileQuery),
+    responses((status = 200, body = ApiResponse<Vec<FileInfoResponse>>),
+        (status = 304, description = "Listing unchanged since the ETag in If-None-Match")),
+)]
 pub async fn get_files(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Query(query): Query<FileQuery>,
-) -> Json<ApiResponse<Vec<FileInfoResponse>>> {
+    headers: HeaderMap,
+) -> Response {
+    let username = &current_user.username;
+    let dir = match query.parent_id {
+        -1 => Some(String::new()),
+        id => match db.find_file(username, id).await {
+            Ok(Some(dir)) if dir.is_directory => dedup::row_path(&db, &dir).await.ok().flatten(),
+            _ => None,
+        },
+    };
+    let modified = match dir.as_deref().and_then(|dir| resolve_in_user_root(&state.config, username, dir)) {
+        Some(path) => fs::metadata(&path).await.and_then(|m| m.modified()).ok(),
+        None => None,
+    };
+    let etag = dir
+        .zip(modified)
+        .map(|(dir, modified)| dir_version::etag(username, &dir, Some(modified), ""));
+    if let Some(etag) = etag.as_deref().filter(|etag| dir_version::matches(&headers, etag)) {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        dir_version::set_headers(response.headers_mut(), etag);
+        return response;
+    }
+
     let result = file_info::Entity::find()
         .filter(file_info::Column::ParentId.eq(query.parent_id))
         .filter(file_info::Column::Username.eq(&current_user.username))
@@ -410,119 +760,130 @@ pub async fn get_files(
 
     match result {
         Ok(files) => {
-            let response: Vec<FileInfoResponse> = files.into_iter().map(|f| f.into()).collect();
-            Json(ApiResponse::success(response))
+            let files: Vec<FileInfoResponse> = files.into_iter().map(|f| f.into()).collect();
+            let mut response = Json(ApiResponse::success(files)).into_response();
+            if let Some(etag) = &etag {
+                dir_version::set_headers(response.headers_mut(), etag);
+            }
+            response
         }
         Err(e) => {
             tracing::error!("Failed to get files: {}", e);
-            Json(ApiResponse::error(500, e.to_string()))
+            Json(ApiResponse::<()>::error(500, e.to_string())).into_response()
         }
     }
 }
 
 /// POST /api/file/remove/file
+#[utoipa::path(
+    post,
+    path = "/api/file/remove/file",
+    tag = "file",
+    request_body = DeleteFileRequest,
+    responses(
+        (status = 200, body = ApiMessage),
+        (status = 412, description = "A file changed since it was listed", body = ApiMessage),
+    ),
+)]
 pub async fn remove_file(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Json(req): Json<DeleteFileRequest>,
-) -> Json<ApiResponse<()>> {
+) -> Response {
     if !is_safe_path(&req.parent_path) {
-        return Json(ApiResponse::error(400, "invalid parent path"));
+        return Json(ApiResponse::<()>::error(400, "invalid parent path")).into_response();
     }
-    let user_path = get_user_path(&state.config, &current_user.username);
-    let parent_path = req.parent_path.trim_start_matches('/');
-    let mut success_count = 0;
-    let mut error_count = 0;
-
-    for id in req.ids {
-        // Get file info
-        let file_info = file_info::Entity::find_by_id(id)
-            .filter(file_info::Column::Username.eq(&current_user.username))
-            .one(&*db)
-            .await;
-
-        let file = match file_info {
-            Ok(Some(f)) => f,
-            Ok(None) => {
-                tracing::error!("File not found: {}", id);
-                error_count += 1;
-                continue;
+    let location = match locate_for_write(&state, &db, &current_user, &req.parent_path).await {
+        Ok(location) => location,
+        Err((status, error)) => {
+            return Json(ApiResponse::<()>::error(status.as_u16() as i32, error)).into_response()
+        }
+    };
+    let service = FileService::new(&state.config, &db, &location.owner)
+        .on_behalf_of(&current_user.username, &location.prefix);
+
+    let mut expected = Vec::new();
+    for (id, seen) in &req.if_match {
+        match service.name_by_id(*id).await {
+            Ok(name) => expected.push((format!("{}/{}", location.path, name), seen)),
+            Err(FileError::NotFound) => {
+                let message = format!("文件已被修改: {}", id);
+                return (StatusCode::PRECONDITION_FAILED, Json(ApiResponse::<()>::error(412, message)))
+                    .into_response();
             }
             Err(e) => {
-                tracing::error!("Database error: {}", e);
-                error_count += 1;
-                continue;
+                tracing::error!("Failed to look up file {}: {}", id, e);
+                return Json(ApiResponse::<()>::error(500, "failed to check file")).into_response();
             }
-        };
-
-        let file_path = user_path.join(parent_path).join(&file.name);
+        }
+    }
+    if let Err(response) = check_unchanged(&service, expected).await {
+        return response;
+    }
+    let mut success_count = 0;
+    let mut error_count = 0;
+    let mut held = Vec::new();
 
-        if file.is_directory {
-            // Delete children recursively
-            delete_children(&*db, id, &current_user.username).await;
+    let mut trashed = Vec::new();
 
-            // Delete directory from filesystem
-            if let Err(e) = fs::remove_dir_all(&file_path).await {
-                tracing::error!("Failed to delete directory: {}", e);
-                error_count += 1;
-                continue;
+    for id in req.ids {
+        match service.delete_by_id(&location.path, id, req.permanent).await {
+            Ok(item) => {
+                success_count += 1;
+                trashed.extend(item);
             }
-        } else {
-            // Delete file from database
-            if let Err(e) = file_info::Entity::delete_by_id(id)
-                .exec(&*db)
-                .await
-            {
-                tracing::error!("Failed to delete file from database: {}", e);
+            Err(FileError::LegalHold(path)) => {
                 error_count += 1;
-                continue;
+                held.push(path);
             }
-
-            // Delete file from filesystem
-            if let Err(e) = fs::remove_file(&file_path).await {
-                tracing::error!("Failed to delete file from filesystem: {}", e);
+            Err(e) => {
+                tracing::error!("Failed to delete file {}: {}", id, e);
+                error_count += 1;
             }
         }
-
-        // Audit log
-        let op_desc = if parent_path == "/" {
-            format!("/{}", file.name)
-        } else {
-            format!("{}/{}", parent_path, file.name)
-        };
-        log_operation(&current_user.username, op_type::DELETE, &op_desc, OP_SUCCESS, None);
-        success_count += 1;
     }
 
-    let message = format!(
+    let operation = undo::Operation::Delete { dir: req.parent_path.clone(), items: trashed };
+    undo::record(&db, &current_user.username, operation).await;
+
+    let mut message = format!(
         "删除成功{}个文件，失败{}个文件",
         success_count, error_count
     );
-    Json(ApiResponse::success_msg(message))
+    for path in &held {
+        message.push('；');
+        message.push_str(&legal_hold::message(path));
+    }
+    Json(ApiResponse::<()>::success_msg(message)).into_response()
 }
 
 /// Delete children recursively
-async fn delete_children(db: &sea_orm::DatabaseConnection, parent_id: i64, username: &str) {
-    let children = file_info::Entity::find()
-        .filter(file_info::Column::ParentId.eq(parent_id))
-        .filter(file_info::Column::Username.eq(username))
-        .all(db)
-        .await;
-
-    if let Ok(children) = children {
+pub(crate) async fn delete_children(
+    db: &(impl FileInfoRepository + ?Sized),
+    parent_id: i64,
+    username: &str,
+) {
+    if let Ok(children) = db.children(username, parent_id).await {
         for child in children {
             if child.is_directory {
                 Box::pin(delete_children(db, child.id, username)).await;
             }
-            let _ = file_info::Entity::delete_by_id(child.id).exec(db).await;
+            let _ = db.delete_file(child.id).await;
         }
     }
 
-    let _ = file_info::Entity::delete_by_id(parent_id).exec(db).await;
+    let _ = db.delete_file(parent_id).await;
 }
 
 /// POST /api/file/download/pre
+#[utoipa::path(
+    post,
+    path = "/api/file/download/pre",
+    tag = "file",
+    request_body = DownloadPreRequest,
+    responses((status = 200, body = DownloadPreResponse)),
+)]
 pub async fn download_pre(
     Extension(_current_user): Extension<CurrentUser>,
     Json(req): Json<DownloadPreRequest>,
@@ -550,11 +911,20 @@ pub async fn download_pre(
         }
     }
 
+    if req.level.is_some_and(|level| level > 9) {
+        return Json(DownloadPreResponse {
+            result: false,
+            guid: String::new(),
+        });
+    }
+
     let guid = uuid::Uuid::new_v4().to_string();
 
     let download_info = DownloadInfo {
         files: req.files,
         parent_dir: req.parent_dir,
+        format: req.format,
+        level: req.level,
     };
 
     DOWNLOAD_MAP.lock().unwrap().insert(guid.clone(), download_info);
@@ -566,9 +936,16 @@ pub async fn download_pre(
 }
 
 /// GET /api/file/download
+#[utoipa::path(
+    get,
+    path = "/api/file/download",
+    tag = "file",
+    params(DownloadQuery),
+    responses((status = 200, description = "Zip, tar or tar.gz archive of the prepared files", content_type = "application/octet-stream")),
+)]
 pub async fn download_file(
     State(state): State<AppState>,
-    Extension(_db): Extension<DbConn>,
+    Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Query(query): Query<DownloadQuery>,
 ) -> impl IntoResponse {
@@ -589,78 +966,130 @@ pub async fn download_file(
         }
     };
 
-    let user_path = get_user_path(&state.config, &current_user.username);
-    let base_dir = user_path.join(download_info.parent_dir.trim_start_matches('/'));
+    let location = match locate(&state, &db, &current_user, &download_info.parent_dir).await {
+        Ok(location) => location,
+        Err((status, error)) => return (status, Json(serde_json::json!({ "error": error }))).into_response(),
+    };
+    let Some(base_dir) = resolve_in_user_root(&state.config, &location.owner, &location.path) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            [(header::CONTENT_TYPE, "application/json")],
+            Body::from(r#"{"error": "invalid path"}"#),
+        )
+            .into_response();
+    };
+    for file_name in &download_info.files {
+        let path = format!("{}/{}", location.path, file_name);
+        // Files of sensitive folders are only served one by one, watermarked
+        match sensitive::contains_sensitive(&db, &location.owner, &path).await {
+            Ok(false) => {}
+            Ok(true) => {
+                return (StatusCode::FORBIDDEN, Json(serde_json::json!({ "error": "敏感文件夹中的文件不能打包下载" }))).into_response();
+            }
+            Err(e) => {
+                tracing::error!("Failed to look up sensitive folders of {}: {}", path, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({ "error": "internal error" }))).into_response();
+            }
+        }
+        if let Err((status, error)) = recall(&state, &db, &location.owner, &path).await {
+            return (status, Json(serde_json::json!({ "error": error }))).into_response();
+        }
+    }
     let username = current_user.username.clone();
 
-    // Create a channel for streaming zip data
-    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Vec<u8>, std::io::Error>>(32);
+    // Create a channel for streaming archive data. Keep it short so the
+    // archive worker is throttled to the client's download speed
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Vec<u8>, std::io::Error>>(4);
+    let sent = Arc::new(AtomicU64::new(0));
 
-    // Spawn a task to write zip data
+    // Spawn a task to write archive data
     let base_dir_clone = base_dir.clone();
     let files = download_info.files.clone();
     let parent_dir = download_info.parent_dir.clone();
+    let format = download_info.format;
+    let level = download_info.level;
 
     tokio::task::spawn_blocking(move || {
         // Use a custom Write implementation that sends to the channel
-        let writer = ChannelWriter::new(tx.clone());
-        // Use new_stream for non-seekable writer (zip 7.0+)
-        let mut zip = zip::ZipWriter::new_stream(writer);
-        // Use Stored (no compression) for faster download speed
-        let options: zip::write::FileOptions<()> = zip::write::FileOptions::default()
-            .compression_method(zip::CompressionMethod::Stored);
+        let writer = ChannelWriter::new(tx.clone(), sent.clone());
+        let mut archive = StreamArchive::new(format, level, writer);
 
+        let mut interrupted = false;
         for file_name in &files {
-            let file_path = base_dir_clone.join(file_name);
+            let Some(file_path) = resolve_in_root(&base_dir_clone, file_name) else {
+                continue;
+            };
+
+            if let Err(e) = add_to_archive_streaming(&mut archive, &base_dir_clone, &file_path, &username, &parent_dir) {
+                if e.kind() == std::io::ErrorKind::BrokenPipe {
+                    interrupted = true;
+                    break;
+                }
+                tracing::error!("Failed to add file to archive: {}", e);
+            }
+        }
 
-            if let Err(e) = add_to_zip_streaming(&mut zip, &base_dir_clone, &file_path, &options, &username, &parent_dir) {
-                tracing::error!("Failed to add file to zip: {}", e);
+        if !interrupted {
+            if let Err(e) = archive.finish() {
+                interrupted = tx.is_closed();
+                if !interrupted {
+                    tracing::error!("Failed to finish archive: {}", e);
+                }
             }
         }
 
-        if let Err(e) = zip.finish() {
-            tracing::error!("Failed to finish zip: {}", e);
+        // Files that completed were already logged one by one
+        if interrupted {
+            let log_path = format!("{}/{}", parent_dir, files.join(",")).replace("//", "/");
+            let op_desc = format!("{} (下载中断, 已发送{}字节)", log_path, sent.load(Ordering::Relaxed));
+            tracing::info!("Archive download aborted by client: {}", op_desc);
+            log_operation(&username, OpType::Download, &op_desc, OP_PARTIAL, None);
         }
     });
 
     // Convert receiver to stream
     let stream = tokio_stream::wrappers::ReceiverStream::new(rx);
-    let body = Body::from_stream(stream);
+    let body = Body::from_stream(traffic::counted(&current_user.username, stream));
 
     // Return streaming response
     Response::builder()
         .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::CONTENT_TYPE, format.content_type())
         .header(
             header::CONTENT_DISPOSITION,
-            "attachment; filename=download.zip",
+            format!("attachment; filename={}", format.file_name()),
         )
         .header(header::TRANSFER_ENCODING, "chunked")
         .body(body)
         .unwrap()
 }
 
-/// Channel-based writer for streaming zip
+/// Channel-based writer for streaming archives
 struct ChannelWriter {
     tx: tokio::sync::mpsc::Sender<Result<Vec<u8>, std::io::Error>>,
     buffer: Vec<u8>,
+    /// Bytes handed to the response body so far
+    sent: Arc<AtomicU64>,
 }
 
 const CHANNEL_BUFFER_SIZE: usize = 1024 * 1024; // 1MB buffer for better throughput
 
 impl ChannelWriter {
-    fn new(tx: tokio::sync::mpsc::Sender<Result<Vec<u8>, std::io::Error>>) -> Self {
+    fn new(tx: tokio::sync::mpsc::Sender<Result<Vec<u8>, std::io::Error>>, sent: Arc<AtomicU64>) -> Self {
         Self {
             tx,
             buffer: Vec::with_capacity(CHANNEL_BUFFER_SIZE),
+            sent,
         }
     }
 
     fn flush_buffer(&mut self) -> std::io::Result<()> {
         if !self.buffer.is_empty() {
             let data = std::mem::take(&mut self.buffer);
+            let len = data.len() as u64;
             self.tx.blocking_send(Ok(data))
                 .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "channel closed"))?;
+            self.sent.fetch_add(len, Ordering::Relaxed);
         }
         Ok(())
     }
@@ -668,6 +1097,12 @@ impl ChannelWriter {
 
 impl Write for ChannelWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // The receiver is dropped together with the response body when the
+        // client disconnects; stop before reading any more files
+        if self.tx.is_closed() {
+            return Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client disconnected"));
+        }
+
         self.buffer.extend_from_slice(buf);
 
         // Flush when buffer reaches threshold
@@ -689,277 +1124,342 @@ impl Drop for ChannelWriter {
     }
 }
 
-/// Add file or directory to zip with streaming and audit logging
-fn add_to_zip_streaming<W: Write>(
-    zip: &mut zip::ZipWriter<zip::write::StreamWriter<W>>,
+/// Archive written to a download stream
+enum StreamArchive<W: Write> {
+    Zip(ZipStream<W>),
+    Tar(tar::Builder<W>),
+    TarGz(tar::Builder<GzEncoder<W>>),
+}
+
+impl<W: Write> StreamArchive<W> {
+    fn new(format: DownloadFormat, level: Option<u32>, writer: W) -> Self {
+        match format {
+            // Stored (no compression) by default for faster download speed
+            DownloadFormat::Zip => StreamArchive::Zip(ZipStream::new(writer, level.filter(|level| *level > 0))),
+            DownloadFormat::Tar => StreamArchive::Tar(tar::Builder::new(writer)),
+            DownloadFormat::TarGz => {
+                let level = level.map_or(flate2::Compression::default(), flate2::Compression::new);
+                StreamArchive::TarGz(tar::Builder::new(GzEncoder::new(writer, level)))
+            }
+        }
+    }
+
+    /// Add the empty directory `path` as `name`
+    fn add_dir(&mut self, name: &str, path: &Path) -> std::io::Result<()> {
+        match self {
+            StreamArchive::Zip(zip) => zip.add_dir(name, std::fs::metadata(path)?.modified().ok()),
+            StreamArchive::Tar(tar) => tar.append_dir(name, path),
+            StreamArchive::TarGz(tar) => tar.append_dir(name, path),
+        }
+    }
+
+    /// Add the file at `path` as `name`
+    fn add_file(&mut self, name: &str, path: &Path) -> std::io::Result<()> {
+        let file = std::fs::File::open(path)?;
+        match self {
+            StreamArchive::Zip(zip) => {
+                let modified = file.metadata()?.modified().ok();
+                zip.add_file(name, file, modified)
+            }
+            StreamArchive::Tar(tar) => append_tar(tar, name, file),
+            StreamArchive::TarGz(tar) => append_tar(tar, name, file),
+        }
+    }
+
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            StreamArchive::Zip(zip) => zip.finish().map(drop),
+            StreamArchive::Tar(tar) => tar.into_inner().map(drop),
+            StreamArchive::TarGz(tar) => tar.into_inner()?.finish().map(drop),
+        }
+    }
+}
+
+fn append_tar<W: Write>(tar: &mut tar::Builder<W>, name: &str, file: std::fs::File) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata(&file.metadata()?);
+    tar.append_data(&mut header, name, std::io::BufReader::with_capacity(1024 * 1024, file))
+}
+
+/// Add file or directory to a download archive with audit logging
+fn add_to_archive_streaming<W: Write>(
+    archive: &mut StreamArchive<W>,
     base_dir: &PathBuf,
     path: &PathBuf,
-    options: &zip::write::FileOptions<()>,
     username: &str,
     parent_dir: &str,
 ) -> std::io::Result<()> {
+    let name = path
+        .strip_prefix(base_dir)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.file_name().unwrap().to_string_lossy().to_string());
     if path.is_dir() {
         let entries: Vec<_> = std::fs::read_dir(path)?.collect();
 
-        // If directory is empty, add directory entry to zip
+        // If directory is empty, add directory entry to the archive
         if entries.is_empty() {
-            let dir_name = path
-                .strip_prefix(base_dir)
-                .map(|p| format!("{}/", p.to_string_lossy()))
-                .unwrap_or_else(|_| format!("{}/", path.file_name().unwrap().to_string_lossy()));
-            zip.add_directory(&dir_name, options.clone())?;
+            archive.add_dir(&name, path)?;
         } else {
             for entry in entries {
                 let entry = entry?;
-                add_to_zip_streaming(zip, base_dir, &entry.path(), options, username, parent_dir)?;
+                // Only the selected files were checked against the user root
+                if entry.file_type()?.is_symlink() {
+                    continue;
+                }
+                add_to_archive_streaming(archive, base_dir, &entry.path(), username, parent_dir)?;
             }
         }
     } else if path.is_file() {
-        let name = path
-            .strip_prefix(base_dir)
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|_| path.file_name().unwrap().to_string_lossy().to_string());
-
-        zip.start_file(&name, options.clone())?;
-        let mut file = std::fs::File::open(path)?;
-        let mut buffer = vec![0u8; 1024 * 1024]; // 1MB read buffer for better throughput
-        loop {
-            let n = file.read(&mut buffer)?;
-            if n == 0 {
-                break;
-            }
-            zip.write_all(&buffer[..n])?;
-        }
+        archive.add_file(&name, path)?;
 
         // Audit log for each downloaded file
         let log_path = format!("{}/{}", parent_dir, name).replace("//", "/");
-        log_operation(username, op_type::DOWNLOAD, &log_path, OP_SUCCESS, None);
+        log_operation(username, OpType::Download, &log_path, OP_SUCCESS, None);
     }
     Ok(())
 }
 
 /// GET /api/file/list - List directory contents (new API)
 /// Returns array directly (no ApiResponse wrapper, matching Go behavior)
+///
+/// Carries an ETag of the directory version, see [`dir_version`], unless
+/// directories are given their recursive sizes, which it doesn't cover. The
+/// listing is sorted, filtered and paged by the query, see [`listing`], with
+/// the number of entries before paging in `X-Total-Count`.
+#[utoipa::path(
+    get,
+    path = "/api/file/list",
+    tag = "file",
+    params(ListQuery),
+    responses((status = 200, body = Vec<DirectoryItem>),
+        (status = 304, description = "Listing unchanged since the ETag in If-None-Match"),
+        (status = 400, description = "Invalid path, sort order or filter"),
+        (status = 404, description = "Path not found")),
+)]
 pub async fn list_directory(
     State(state): State<AppState>,
-    Extension(_db): Extension<DbConn>,
+    Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
-    Query(query): Query<PathQuery>,
+    Query(query): Query<ListQuery>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    if !is_safe_path(&query.path) {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "invalid path"})),
-        ).into_response();
+    let arrangement = match Arrangement::parse(
+        query.sort_by.as_deref(),
+        query.order.as_deref(),
+        query.filter.as_deref(),
+        query.offset,
+        query.limit,
+    ) {
+        Ok(arrangement) => arrangement,
+        Err(error) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": error }))).into_response(),
+    };
+    if dept_space::is_space_list(&state, &query.path) {
+        return match dept_space::spaces(&state, &db, &current_user.username).await {
+            Ok(spaces) => {
+                let (page, total) = arrangement.apply(dept_space::space_items(&spaces));
+                listing_response(page, total)
+            }
+            Err(e) => {
+                tracing::error!("Failed to list department spaces: {}", e);
+                let error = serde_json::json!({ "error": "failed to read directory" });
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response()
+            }
+        };
     }
-    let user_path = get_user_path(&state.config, &current_user.username);
-    let path = if query.path.is_empty() { "/" } else { &query.path };
-    let full_path = user_path.join(path.trim_start_matches('/'));
-
-    // Ensure user root directory exists (create if not)
-    if !user_path.exists() {
-        if let Err(e) = fs::create_dir_all(&user_path).await {
-            tracing::error!("Failed to create user directory: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error": "failed to create user directory"})),
-            ).into_response();
-        }
+    if group_space::is_space_list(&state, &query.path) {
+        return match group_space::spaces(&state, &db, &current_user).await {
+            Ok(spaces) => {
+                let (page, total) = arrangement.apply(group_space::space_items(&spaces));
+                listing_response(page, total)
+            }
+            Err(e) => {
+                tracing::error!("Failed to list group spaces: {}", e);
+                let error = serde_json::json!({ "error": "failed to read directory" });
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response()
+            }
+        };
     }
-
-    // Check if path exists
-    if !full_path.exists() {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(serde_json::json!({"error": "path not found"})),
-        ).into_response();
+    let location = match locate(&state, &db, &current_user, &query.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return (status, Json(serde_json::json!({ "error": error }))).into_response(),
+    };
+    let service = FileService::new(&state.config, &db, &location.owner)
+        .on_behalf_of(&current_user.username, &location.prefix);
+
+    // The spaces are shown next to the user's own files
+    let (mut dept_spaces, mut group_spaces) = (false, false);
+    if location.path.trim_matches('/').is_empty() && !location.is_space() {
+        match dept_space::spaces(&state, &db, &current_user.username).await {
+            Ok(spaces) => dept_spaces = !spaces.is_empty(),
+            Err(e) => tracing::error!("Failed to list department spaces: {}", e),
+        }
+        match group_space::spaces(&state, &db, &current_user).await {
+            Ok(spaces) => group_spaces = !spaces.is_empty(),
+            Err(e) => tracing::error!("Failed to list group spaces: {}", e),
+        }
     }
 
-    // Read directory
-    let entries = match fs::read_dir(&full_path).await {
-        Ok(e) => e,
+    let modified = match service.resolve(&location.path) {
+        Ok(dir) => fs::metadata(&dir).await.and_then(|m| m.modified()).ok(),
+        Err(_) => None,
+    };
+    let etag = modified.filter(|_| !query.with_dir_size).map(|modified| {
+        let extra = format!(
+            "{}{}{}",
+            if dept_spaces { "d" } else { "" },
+            if group_spaces { "g" } else { "" },
+            arrangement.tag()
+        );
+        dir_version::etag(&location.owner, &location.path, Some(modified), &extra)
+    });
+    if let Some(etag) = etag.as_deref().filter(|etag| dir_version::matches(&headers, etag)) {
+        service.log_open(&location.path);
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        dir_version::set_headers(response.headers_mut(), etag);
+        return response;
+    }
+
+    let (status, error) = match service.list(&location.path).await {
+        // Return array directly (matching Go behavior)
+        Ok(mut items) => {
+            // Sizes are only computed for the page, unless it's sorted by them
+            let fill_all = query.with_dir_size && arrangement.needs_sizes();
+            if fill_all {
+                dir_size::fill(&state.config, &location.owner, &location.path, &mut items).await;
+            }
+            if dept_spaces {
+                items.retain(|item| item.basename != dept_space::ROOT);
+                items.push(dept_space::root_item());
+            }
+            if group_spaces {
+                items.retain(|item| item.basename != group_space::ROOT);
+                items.push(group_space::root_item());
+            }
+            let (mut page, total) = arrangement.apply(items);
+            if query.with_dir_size && !fill_all {
+                dir_size::fill(&state.config, &location.owner, &location.path, &mut page).await;
+            }
+            let mut response = listing_response(page, total);
+            if let Some(etag) = &etag {
+                dir_version::set_headers(response.headers_mut(), etag);
+            }
+            return response;
+        }
+        Err(FileError::InvalidPath) => (StatusCode::BAD_REQUEST, "invalid path"),
+        Err(FileError::NotFound) => (StatusCode::NOT_FOUND, "path not found"),
         Err(e) => {
             tracing::error!("Failed to read directory: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error": "failed to read directory"})),
-            ).into_response();
+            (StatusCode::INTERNAL_SERVER_ERROR, "failed to read directory")
         }
     };
+    (status, Json(serde_json::json!({ "error": error }))).into_response()
+}
 
-    let mut items = Vec::new();
-    let mut entries = entries;
-
-    while let Some(entry) = entries.next_entry().await.ok().flatten() {
-        let metadata = match entry.metadata().await {
-            Ok(m) => m,
-            Err(_) => continue,
-        };
-
-        let basename = entry.file_name().to_string_lossy().to_string();
-        let filename = format!("{}/{}", path.trim_end_matches('/'), basename);
-
-        let (item_type, mime) = if metadata.is_dir() {
-            ("directory".to_string(), String::new())
-        } else {
-            let mime = get_mime_type(&basename);
-            ("file".to_string(), mime)
-        };
-
-        let lastmod = metadata
-            .modified()
-            .ok()
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| {
-                chrono::DateTime::from_timestamp(d.as_secs() as i64, 0)
-                    .map(|dt| dt.format("%Y-%m-%dT%H:%M:%SZ").to_string())
-                    .unwrap_or_default()
-            })
-            .unwrap_or_default();
-
-        items.push(DirectoryItem {
-            basename,
-            filename,
-            item_type,
-            size: metadata.len() as i64,
-            lastmod,
-            mime,
-        });
-    }
-
-    // Audit log for directory access
-    let clean_path = if path == "/" { "/".to_string() } else { format!("/{}", path.trim_matches('/')) };
-    log_operation(&current_user.username, op_type::OPEN_FILE, &clean_path, OP_SUCCESS, None);
-
-    // Return array directly (matching Go behavior)
-    Json(items).into_response()
-}
-
-/// Get MIME type from file extension
-fn get_mime_type(filename: &str) -> String {
-    let ext = std::path::Path::new(filename)
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("")
-        .to_lowercase();
-
-    match ext.as_str() {
-        "jpg" | "jpeg" => "image/jpeg",
-        "png" => "image/png",
-        "gif" => "image/gif",
-        "bmp" => "image/bmp",
-        "webp" => "image/webp",
-        "pdf" => "application/pdf",
-        "txt" | "md" => "text/plain",
-        "json" => "application/json",
-        "js" => "application/javascript",
-        "css" => "text/css",
-        "html" => "text/html",
-        "xml" => "application/xml",
-        "doc" => "application/msword",
-        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
-        "xls" => "application/vnd.ms-excel",
-        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
-        "ppt" => "application/vnd.ms-powerpoint",
-        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
-        "zip" => "application/zip",
-        "rar" => "application/vnd.rar",
-        "7z" => "application/x-7z-compressed",
-        "tar" => "application/x-tar",
-        "gz" | "tgz" => "application/gzip",
-        _ => "application/octet-stream",
-    }
-    .to_string()
+/// Listing of a page of entries, with the number of entries before paging
+fn listing_response(page: Vec<DirectoryItem>, total: usize) -> Response {
+    let mut response = Json(page).into_response();
+    response.headers_mut().insert(listing::TOTAL_COUNT, HeaderValue::from(total));
+    response
 }
 
 /// POST /api/file/rename
+#[utoipa::path(
+    post,
+    path = "/api/file/rename",
+    tag = "file",
+    request_body = RenameRequest,
+    responses(
+        (status = 200, body = ApiMessage),
+        (status = 412, description = "The file changed since it was listed", body = ApiMessage),
+    ),
+)]
 pub async fn rename_file(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Json(req): Json<RenameRequest>,
-) -> Json<ApiResponse<()>> {
-    if !is_safe_path(&req.old_path) {
-        return Json(ApiResponse::error(400, "invalid old path"));
-    }
-    if req.old_path == "/" || req.old_path.trim().is_empty() {
-        return Json(ApiResponse::error(400, "invalid old path"));
-    }
-    if !is_safe_filename(&req.new_name) {
-        return Json(ApiResponse::error(400, "invalid new name"));
-    }
-
-    let user_path = get_user_path(&state.config, &current_user.username);
-    let old_path = user_path.join(req.old_path.trim_start_matches('/'));
-    let new_path = old_path.parent().unwrap().join(&req.new_name);
-
-    // Check if old file exists
-    if !old_path.exists() {
-        return Json(ApiResponse::error(404, "file not found"));
-    }
-
-    // Check if new name already exists
-    if new_path.exists() {
-        return Json(ApiResponse::error(409, "file with new name already exists"));
-    }
-
-    // Rename the file
-    if let Err(e) = fs::rename(&old_path, &new_path).await {
-        tracing::error!("Failed to rename file: {}", e);
-        return Json(ApiResponse::error(500, "failed to rename file"));
-    }
-
-    // Resolve parent_id from old_path's parent directory
-    let parent_path = std::path::Path::new(&req.old_path)
-        .parent()
-        .map(|p| p.to_string_lossy().to_string())
-        .unwrap_or_default();
-    let parent_id = resolve_dir_id(&*db, &current_user.username, &parent_path).await;
-
-    // Update database (with correct parent_id to avoid updating same-name files in other dirs)
-    let old_name = match old_path.file_name().and_then(|n| n.to_str()) {
-        Some(name) if !name.is_empty() => name.to_string(),
-        _ => return Json(ApiResponse::error(400, "invalid old path")),
+) -> Response {
+    let location = match locate_for_write(&state, &db, &current_user, &req.old_path).await {
+        Ok(location) => location,
+        Err((status, error)) => {
+            return Json(ApiResponse::<()>::error(status.as_u16() as i32, error)).into_response()
+        }
     };
-    let db_result = file_info::Entity::update_many()
-        .col_expr(file_info::Column::Name, sea_orm::sea_query::Expr::value(&req.new_name))
-        .filter(file_info::Column::Username.eq(&current_user.username))
-        .filter(file_info::Column::ParentId.eq(parent_id))
-        .filter(file_info::Column::Name.eq(&old_name))
-        .exec(&*db)
-        .await;
-
-    if let Err(e) = db_result {
-        tracing::error!("Failed to update database during rename: {}", e);
-        // Try to rollback filesystem change
-        if let Err(re) = fs::rename(&new_path, &old_path).await {
-            tracing::error!("Failed to rollback file rename: {}", re);
+    let service = FileService::new(&state.config, &db, &location.owner)
+        .on_behalf_of(&current_user.username, &location.prefix);
+    if let Some(seen) = &req.if_match {
+        if let Err(response) = check_unchanged(&service, [(location.path.clone(), seen)]).await {
+            return response;
         }
-        return Json(ApiResponse::error(500, "database error"));
     }
+    let result: Json<ApiResponse<()>> = match service.rename(&location.path, &req.new_name).await {
+        Ok(new_name) => {
+            let old_path = req.old_path.trim_end_matches('/');
+            let (dir, old_name) = old_path.rsplit_once('/').unwrap_or(("", old_path));
+            let operation = undo::Operation::Rename {
+                dir: dir.to_string(),
+                renames: vec![(old_name.to_string(), new_name)],
+            };
+            undo::record(&db, &current_user.username, operation).await;
+            Json(ApiResponse::success_msg("file renamed successfully"))
+        }
+        Err(FileError::InvalidName(e)) => Json(ApiResponse::error(400, format!("invalid new name: {}", e))),
+        Err(FileError::InvalidPath) => Json(ApiResponse::error(400, "invalid old path")),
+        Err(FileError::NotFound) => Json(ApiResponse::error(404, "file not found")),
+        Err(FileError::AlreadyExists) => Json(ApiResponse::error(409, "file with new name already exists")),
+        Err(FileError::LegalHold(path)) => Json(ApiResponse::error(423, legal_hold::message(&path))),
+        Err(e) => {
+            tracing::error!("Failed to rename file: {}", e);
+            Json(ApiResponse::error(500, "failed to rename file"))
+        }
+    };
+    result.into_response()
+}
 
-    // Audit log
-    let op_desc = format!("{} => {}", req.old_path, req.new_name);
-    log_operation(&current_user.username, op_type::RENAME, &op_desc, OP_SUCCESS, None);
-    Json(ApiResponse::success_msg("file renamed successfully"))
+/// Header of `/api/file/content` responses naming the charset the file is stored in
+const CONTENT_CHARSET: &str = "x-content-charset";
+
+/// Entity tag of the content of a file, by its size and modification time
+/// to the nanosecond, so a save right after another one is told apart
+pub(crate) fn content_etag(metadata: &std::fs::Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("\"{:x}-{:x}\"", metadata.len(), modified)
 }
 
 /// GET /api/file/content
+#[utoipa::path(
+    get,
+    path = "/api/file/content",
+    tag = "file",
+    params(ContentQuery),
+    responses((status = 200, description = "Content of the file, converted to UTF-8 unless raw", content_type = "text/plain")),
+)]
 pub async fn get_file_content(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
-    Query(query): Query<PathQuery>,
+    headers: HeaderMap,
+    Query(query): Query<ContentQuery>,
 ) -> impl IntoResponse {
-    if !is_safe_path(&query.path) {
+    let location = match locate(&state, &db, &current_user, &query.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return (status, Json(serde_json::json!({ "error": error }))).into_response(),
+    };
+    let Some(file_path) = resolve_in_user_root(&state.config, &location.owner, &location.path) else {
         return (
             StatusCode::BAD_REQUEST,
             [(header::CONTENT_TYPE, "application/json")],
             Body::from(r#"{"error": "invalid path"}"#),
         ).into_response();
+    };
+    if let Err((status, error)) = recall(&state, &db, &location.owner, &location.path).await {
+        return (status, Json(serde_json::json!({ "error": error }))).into_response();
     }
-    let user_path = get_user_path(&state.config, &current_user.username);
-    let file_path = user_path.join(query.path.trim_start_matches('/'));
 
     // Check if file exists
     let metadata = match fs::metadata(&file_path).await {
@@ -1011,24 +1511,19 @@ pub async fn get_file_content(
         }
     };
 
-    // Determine content type
-    let ext = file_path
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("");
-
-    let content_type = match ext {
-        "json" => "application/json",
-        "html" => "text/html",
-        "css" => "text/css",
-        "js" => "application/javascript",
-        "xml" => "application/xml",
-        _ => "text/plain",
+    // Text in legacy encodings (GBK, Big5, Shift_JIS...) is converted to
+    // UTF-8, or labeled with its charset when asked for the bytes as stored
+    let content_type = preview::TextPreview.content_type(&mime::extension(&query.path));
+    let encoding = charset::detect(&content);
+    let (content, content_type) = if query.raw {
+        (content, format!("{}; charset={}", content_type, encoding.name()))
+    } else {
+        (charset::to_utf8(&content, encoding).into_bytes(), format!("{}; charset=utf-8", content_type))
     };
 
     // Record file access for recent files
     let clean_path = format!("/{}", query.path.trim_start_matches('/'));
-    if let Some((file_id, file_name)) = resolve_file_info(&*db, &current_user.username, &query.path).await {
+    if let Some((file_id, file_name)) = resolve_file_info(&db, &location.owner, &location.path).await {
         record_file_access(
             &*db,
             current_user.id,
@@ -1041,129 +1536,293 @@ pub async fn get_file_content(
     }
 
     // Audit log
-    log_operation(&current_user.username, op_type::OPEN_FILE, &clean_path, OP_SUCCESS, None);
+    log_operation(&current_user.username, OpType::OpenFile, &clean_path, OP_SUCCESS, None);
 
-    Response::builder()
+    let response = Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, content_type)
+        .header(CONTENT_CHARSET, encoding.name())
+        // Sent back in If-Match when the edited text is saved
+        .header(header::ETAG, content_etag(&metadata))
         .body(Body::from(content))
-        .unwrap()
+        .unwrap();
+    preview::sandbox(&state.config.html_preview, request_host(&headers), response)
+}
+
+/// Saved text file
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SavedContent {
+    /// Entity tag of the new content, for the If-Match of the next save
+    pub etag: String,
+    pub size: i64,
+}
+
+/// POST /api/file/content - Save the edited text of a file
+///
+/// The request body replaces the content of the file as it is. `If-Match`
+/// must be the `ETag` the content was read with, or `*` to overwrite
+/// whatever is there; a file changed since is not overwritten.
+#[utoipa::path(
+    post,
+    path = "/api/file/content",
+    tag = "file",
+    params(PathQuery),
+    request_body(content = String, content_type = "text/plain"),
+    responses(
+        (status = 200, body = ApiResponse<SavedContent>),
+        (status = 412, description = "The file changed since it was read", body = ApiMessage),
+        (status = 428, description = "No If-Match", body = ApiMessage),
+    ),
+)]
+pub async fn save_file_content(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    headers: HeaderMap,
+    Query(query): Query<PathQuery>,
+    body: axum::body::Bytes,
+) -> Response {
+    let error = |status: StatusCode, message: String| {
+        (status, Json(ApiResponse::<()>::error(status.as_u16() as i32, message))).into_response()
+    };
+    let location = match locate_for_write(&state, &db, &current_user, &query.path).await {
+        Ok(location) => location,
+        Err((status, message)) => return error(status, message.to_string()),
+    };
+    if matches!(preview::find(&location.path), Some(handler) if handler.kind() != PreviewKind::Text) {
+        return error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "不是文本文件".to_string());
+    }
+    let Some(file_path) = resolve_in_user_root(&state.config, &location.owner, &location.path) else {
+        return error(StatusCode::BAD_REQUEST, "invalid path".to_string());
+    };
+    let metadata = match fs::metadata(&file_path).await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return error(StatusCode::NOT_FOUND, "file not found".to_string()),
+    };
+
+    // Optimistic concurrency: only the content the editor started from is replaced
+    let Some(if_match) = headers.get(header::IF_MATCH).and_then(|v| v.to_str().ok()) else {
+        return error(StatusCode::PRECONDITION_REQUIRED, "缺少 If-Match".to_string());
+    };
+    let etag = content_etag(&metadata);
+    if if_match.trim() != "*" && !if_match.split(',').any(|tag| tag.trim().trim_start_matches("W/") == etag) {
+        return error(StatusCode::PRECONDITION_FAILED, format!("文件已被修改: {}", query.path));
+    }
+
+    let size = body.len() as i64;
+    if body.len() > state.config.max_upload_size {
+        return error(StatusCode::PAYLOAD_TOO_LARGE, "文件过大".to_string());
+    }
+    let growth = size - metadata.len() as i64;
+    if growth > 0 {
+        if let Err(exceeded) = quota::check_quota(&db, &state.config, &location.owner, growth).await {
+            return error(StatusCode::PAYLOAD_TOO_LARGE, exceeded.message());
+        }
+    }
+
+    // Written next to the file, so it is put in place by a rename
+    let relative = location.path.trim_matches('/');
+    let (parent_path, name) = relative.rsplit_once('/').unwrap_or(("", relative));
+    let tmp_path = file_path.with_file_name(format!(".{}.{}.saving", name, uuid::Uuid::new_v4()));
+    if let Err(e) = fs::write(&tmp_path, &body).await {
+        tracing::error!("Failed to write {:?}: {}", tmp_path, e);
+        let _ = fs::remove_file(&tmp_path).await;
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "保存文件失败".to_string());
+    }
+
+    let target = dlp::Target {
+        uploader: &current_user.username,
+        owner: &location.owner,
+        prefix: &location.prefix,
+        parent_path,
+        name,
+        size,
+    };
+    let warning = match dlp::screen(&state.config, &db, &target, &tmp_path).await {
+        Ok(Verdict::Store(warning)) => warning,
+        Ok(Verdict::Held(message)) => {
+            return (StatusCode::ACCEPTED, Json(ApiResponse::success_msg(message))).into_response()
+        }
+        Ok(Verdict::Blocked(message)) => return error(StatusCode::FORBIDDEN, message),
+        Err(e) => {
+            tracing::error!("Failed to check {} against DLP rules: {}", query.path, e);
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "保存文件失败".to_string());
+        }
+    };
+
+    let service = FileService::new(&state.config, &db, &location.owner)
+        .on_behalf_of(&current_user.username, &location.prefix);
+    match service.save_content(&tmp_path, parent_path, name, size).await {
+        Ok(_) => {}
+        Err(FileError::LegalHold(path)) => return error(StatusCode::LOCKED, legal_hold::message(&path)),
+        Err(e) => {
+            tracing::error!("Failed to save {}: {}", query.path, e);
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "保存文件失败".to_string());
+        }
+    }
+    let etag = match fs::metadata(&file_path).await {
+        Ok(metadata) => content_etag(&metadata),
+        Err(_) => String::new(),
+    };
+    let mut response = ApiResponse::success(SavedContent { etag: etag.clone(), size });
+    if let Some(warning) = warning {
+        response.message = warning;
+    }
+    let mut response = Json(response).into_response();
+    if let Ok(value) = header::HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    response
 }
 
 /// POST /api/file/delete (new API)
+#[utoipa::path(
+    post,
+    path = "/api/file/delete",
+    tag = "file",
+    request_body = DeleteFilesRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 412, description = "A file changed since it was listed", body = ApiMessage),
+    ),
+)]
 pub async fn delete_files(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Json(req): Json<DeleteFilesRequest>,
-) -> Json<ApiResponse<serde_json::Value>> {
+) -> Response {
     if !is_safe_path(&req.parent_dir) {
-        return Json(ApiResponse::error(400, "invalid parent directory"));
+        return Json(ApiResponse::<()>::error(400, "invalid parent directory")).into_response();
     }
     for file in &req.files {
         if !is_safe_filename(file) {
-            return Json(ApiResponse::error(400, "invalid file name"));
+            return Json(ApiResponse::<()>::error(400, "invalid file name")).into_response();
         }
     }
 
-    let user_path = get_user_path(&state.config, &current_user.username);
-    let parent_dir = req.parent_dir.trim_start_matches('/');
+    let location = match locate_for_write(&state, &db, &current_user, &req.parent_dir).await {
+        Ok(location) => location,
+        Err((status, error)) => {
+            return Json(ApiResponse::<()>::error(status.as_u16() as i32, error)).into_response()
+        }
+    };
+    let parent_dir = location.path.trim_start_matches('/');
 
     // Resolve parent_id from parent_dir path
-    let parent_id = resolve_dir_id(&*db, &current_user.username, parent_dir).await;
+    let parent_id = resolve_dir_id(&*db, &location.owner, parent_dir).await;
     if parent_id == 0 {
-        return Json(ApiResponse::error(400, "parent_dir_not_exists"));
+        return Json(ApiResponse::<()>::error(400, "parent_dir_not_exists")).into_response();
+    }
+
+    let service = FileService::new(&state.config, &db, &location.owner)
+        .on_behalf_of(&current_user.username, &location.prefix);
+    let expected = req
+        .if_match
+        .iter()
+        .map(|(name, seen)| (format!("{}/{}", parent_dir, name), seen))
+        .collect::<Vec<_>>();
+    if let Err(response) = check_unchanged(&service, expected).await {
+        return response;
+    }
+
+    // Many files are moved to the trash in the background
+    let root = get_user_path(&state.config, &location.owner);
+    let paths: Vec<PathBuf> = req
+        .files
+        .iter()
+        .filter_map(|f| resolve_in_root(&root, &format!("{}/{}", parent_dir, f)))
+        .collect();
+    let limit = state.config.shredder.task_threshold;
+    if tokio::task::spawn_blocking(move || shredder::is_large(&paths, limit)).await.unwrap_or(false) {
+        use crate::task::TASK_MANAGER;
+
+        let info = TASK_MANAGER.create_delete_task(
+            current_user.id,
+            &current_user.username,
+            req.parent_dir.clone(),
+            req.files.clone(),
+            req.permanent,
+            location,
+            state.config.clone(),
+            db.0.clone(),
+        );
+        return Json(ApiResponse::success(serde_json::json!({
+            "message": "删除任务已添加, 请查看任务列表",
+            "taskId": info.id
+        }))).into_response();
     }
 
     let mut success = 0;
     let mut failed = 0;
+    let mut held = Vec::new();
 
-    for file_name in &req.files {
-        let file_path = user_path.join(parent_dir).join(file_name);
+    let mut trashed = Vec::new();
 
-        // Check if file exists
-        let metadata = match fs::metadata(&file_path).await {
-            Ok(m) => m,
-            Err(_) => {
-                failed += 1;
-                continue;
-            }
-        };
-
-        // Delete from filesystem
-        let result = if metadata.is_dir() {
-            fs::remove_dir_all(&file_path).await
+    for file_name in &req.files {
+        let result = if req.permanent {
+            service.delete_permanently(parent_dir, file_name).await.map(|()| None)
         } else {
-            fs::remove_file(&file_path).await
+            service.delete(parent_dir, file_name).await.map(Some)
         };
-
-        if let Err(e) = result {
-            tracing::error!("Failed to delete file {}: {}", file_name, e);
-            failed += 1;
-            continue;
-        }
-
-        // Delete from database (with correct parent_id to avoid deleting same-name files in other dirs)
-        // First, get file_id to delete from recent access
-        let file_record = file_info::Entity::find()
-            .filter(file_info::Column::Username.eq(&current_user.username))
-            .filter(file_info::Column::ParentId.eq(parent_id))
-            .filter(file_info::Column::Name.eq(file_name))
-            .one(&*db)
-            .await;
-
-        if let Ok(Some(file)) = file_record {
-            // Delete from recent access records
-            let _ = file_access::Entity::delete_many()
-                .filter(file_access::Column::UserId.eq(current_user.id))
-                .filter(file_access::Column::FileId.eq(file.id))
-                .exec(&*db)
-                .await;
+        match result {
+            Ok(item) => {
+                success += 1;
+                trashed.extend(item);
+            }
+            Err(FileError::LegalHold(path)) => {
+                failed += 1;
+                held.push(path);
+            }
+            Err(e) => {
+                tracing::error!("Failed to delete {}: {}", file_name, e);
+                failed += 1;
+            }
         }
+    }
 
-        // Delete file info
-        let _ = file_info::Entity::delete_many()
-            .filter(file_info::Column::Username.eq(&current_user.username))
-            .filter(file_info::Column::ParentId.eq(parent_id))
-            .filter(file_info::Column::Name.eq(file_name))
-            .exec(&*db)
-            .await;
+    let operation = undo::Operation::Delete { dir: req.parent_dir.clone(), items: trashed };
+    undo::record(&db, &current_user.username, operation).await;
 
-        // Audit log
-        let op_desc = if req.parent_dir == "/" {
-            format!("/{}", file_name)
-        } else {
-            format!("{}/{}", req.parent_dir, file_name)
-        };
-        log_operation(&current_user.username, op_type::DELETE, &op_desc, OP_SUCCESS, None);
-        success += 1;
+    let mut message = format!("删除成功{}个文件，失败{}个文件", success, failed);
+    for path in &held {
+        message.push('；');
+        message.push_str(&legal_hold::message(path));
     }
-
-    let message = format!("删除成功{}个文件，失败{}个文件", success, failed);
     Json(ApiResponse::success(serde_json::json!({
         "message": message,
         "success": success,
-        "failed": failed
+        "failed": failed,
+        "held": held
     })))
+    .into_response()
 }
 
 /// GET /api/file/download/single
+#[utoipa::path(
+    get,
+    path = "/api/file/download/single",
+    tag = "file",
+    params(PathQuery),
+    responses((status = 200, description = "The file as attachment", content_type = "application/octet-stream")),
+)]
 pub async fn download_single_file(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Query(query): Query<PathQuery>,
 ) -> impl IntoResponse {
-    if !is_safe_path(&query.path) {
+    let location = match locate(&state, &db, &current_user, &query.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return (status, Json(serde_json::json!({ "error": error }))).into_response(),
+    };
+    let Some(file_path) = resolve_in_user_root(&state.config, &location.owner, &location.path) else {
         return (
             StatusCode::BAD_REQUEST,
             [(header::CONTENT_TYPE, "application/json")],
             Body::from(r#"{"error": "invalid path"}"#),
         ).into_response();
-    }
-    let user_path = get_user_path(&state.config, &current_user.username);
-    let file_path = user_path.join(query.path.trim_start_matches('/'));
-
+    };
     // Check if file exists
     let metadata = match fs::metadata(&file_path).await {
         Ok(m) => m,
@@ -1187,31 +1846,49 @@ pub async fn download_single_file(
             .into_response();
     }
 
-    // Read file
-    let file = match tokio::fs::File::open(&file_path).await {
-        Ok(f) => f,
-        Err(e) => {
-            tracing::error!("Failed to open file: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                [(header::CONTENT_TYPE, "application/json")],
-                Body::from(r#"{"error": "failed to open file"}"#),
-            )
-                .into_response();
+    // Images and PDFs of sensitive folders are marked with the viewer
+    let marked = watermarked(&state, &db, &location, &file_path, &current_user.username, "attachment").await;
+    let response = match marked {
+        Some(response) if !response.status().is_success() => return response,
+        Some(response) => response,
+        None => {
+            let (body, content_type) = match hot_file(&state, &location, &file_path, &metadata).await {
+                Some(cached) => {
+                    let stream = futures::stream::iter([Ok::<_, std::io::Error>(cached.data.clone())]);
+                    (Body::from_stream(traffic::counted(&current_user.username, stream)), cached.content_type)
+                }
+                None => {
+                    let file = match open_content(&state, &db, &location.owner, &location.path, &file_path).await {
+                        Ok(file) => file,
+                        Err((status, error)) => {
+                            return (status, Json(serde_json::json!({ "error": error }))).into_response()
+                        }
+                    };
+                    let stream = ReaderStream::new(file);
+                    let body = Body::from_stream(traffic::counted(&current_user.username, stream));
+                    (body, mime::detect_file(&file_path).await)
+                }
+            };
+            let filename = file_path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("download");
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+                .header(
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", filename),
+                )
+                .body(body)
+                .unwrap()
         }
     };
 
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
-
-    let filename = file_path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("download");
-
     // Record file access for recent files
     let clean_path = format!("/{}", query.path.trim_start_matches('/'));
-    if let Some((file_id, file_name)) = resolve_file_info(&*db, &current_user.username, &query.path).await {
+    if let Some((file_id, file_name)) = resolve_file_info(&db, &location.owner, &location.path).await {
         record_file_access(
             &*db,
             current_user.id,
@@ -1224,36 +1901,40 @@ pub async fn download_single_file(
     }
 
     // Audit log
-    log_operation(&current_user.username, op_type::DOWNLOAD, &clean_path, OP_SUCCESS, None);
+    log_operation(&current_user.username, OpType::Download, &clean_path, OP_SUCCESS, None);
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/octet-stream")
-        .header(
-            header::CONTENT_DISPOSITION,
-            format!("attachment; filename=\"{}\"", filename),
-        )
-        .body(body)
-        .unwrap()
+    response
 }
 
 /// GET /api/file/preview/single
+#[utoipa::path(
+    get,
+    path = "/api/file/preview/single",
+    tag = "file",
+    params(PathQuery),
+    responses(
+        (status = 200, description = "The file or its preview rendering", content_type = "application/octet-stream"),
+        (status = 206, description = "The byte range of audio or video asked for by `Range`"),
+    ),
+)]
 pub async fn preview_single_file(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
+    headers: HeaderMap,
     Query(query): Query<PathQuery>,
 ) -> impl IntoResponse {
-    if !is_safe_path(&query.path) {
+    let location = match locate(&state, &db, &current_user, &query.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return (status, Json(serde_json::json!({ "error": error }))).into_response(),
+    };
+    let Some(file_path) = resolve_in_user_root(&state.config, &location.owner, &location.path) else {
         return (
             StatusCode::BAD_REQUEST,
             [(header::CONTENT_TYPE, "application/json")],
             Body::from(r#"{"error": "invalid path"}"#),
         ).into_response();
-    }
-    let user_path = get_user_path(&state.config, &current_user.username);
-    let file_path = user_path.join(query.path.trim_start_matches('/'));
-
+    };
     // Check if file exists
     let metadata = match fs::metadata(&file_path).await {
         Ok(m) => m,
@@ -1277,68 +1958,143 @@ pub async fn preview_single_file(
             .into_response();
     }
 
-    // Read file
-    let file = match tokio::fs::File::open(&file_path).await {
-        Ok(f) => f,
-        Err(e) => {
-            tracing::error!("Failed to open file: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                [(header::CONTENT_TYPE, "application/json")],
-                Body::from(r#"{"error": "failed to open file"}"#),
+    // Files without a preview handler are served as they are, and so is
+    // text, decompressed on the fly if needed; other handlers need the file
+    let ext = mime::extension(&query.path);
+    let mut first_request = true;
+    let response = match preview::find(&query.path) {
+        // Players ask for ranges as they seek
+        Some(handler) if handler.kind() == PreviewKind::Media => {
+            if let Err((status, error)) = recall(&state, &db, &location.owner, &location.path).await {
+                return (status, Json(serde_json::json!({ "error": error }))).into_response();
+            }
+            first_request = media::is_first_request(&headers);
+            media::stream(
+                &state.config.media,
+                &current_user.username,
+                &file_path,
+                &ext,
+                &handler.content_type(&ext),
+                &headers,
             )
-                .into_response();
+            .await
+        }
+        // Shown as JPEG, watermarked after the conversion
+        Some(_) if heic::converts(&state.config.media, &ext) => {
+            if let Err((status, error)) = recall(&state, &db, &location.owner, &location.path).await {
+                return (status, Json(serde_json::json!({ "error": error }))).into_response();
+            }
+            heic::preview(&state.config, &db, &location, &file_path, &current_user.username).await
+        }
+        Some(handler) if handler.kind() != PreviewKind::Text => {
+            if let Err((status, error)) = recall(&state, &db, &location.owner, &location.path).await {
+                return (status, Json(serde_json::json!({ "error": error }))).into_response();
+            }
+            match watermarked(&state, &db, &location, &file_path, &current_user.username, "inline").await {
+                Some(response) if !response.status().is_success() => return response,
+                Some(response) => response,
+                None if handler.kind() == PreviewKind::Image => {
+                    match hot_file(&state, &location, &file_path, &metadata).await {
+                        Some(cached) => {
+                            let reader = std::io::Cursor::new(cached.data.clone());
+                            preview::serve_reader(reader, &file_path, &handler.content_type(&ext))
+                        }
+                        None => handler.render(&file_path, &ext).await,
+                    }
+                }
+                None => handler.render(&file_path, &ext).await,
+            }
+        }
+        handler => {
+            let cached = hot_file(&state, &location, &file_path, &metadata).await;
+            let content_type = match (handler, &cached) {
+                (Some(handler), _) => handler.content_type(&ext),
+                (None, Some(cached)) => cached.content_type.to_string(),
+                (None, None) => mime::detect_file(&file_path).await.to_string(),
+            };
+            match cached {
+                Some(cached) => preview::serve_reader(std::io::Cursor::new(cached.data.clone()), &file_path, &content_type),
+                None => match open_content(&state, &db, &location.owner, &location.path, &file_path).await {
+                    Ok(reader) => preview::serve_reader(reader, &file_path, &content_type),
+                    Err((status, error)) => {
+                        return (status, Json(serde_json::json!({ "error": error }))).into_response()
+                    }
+                },
+            }
         }
     };
 
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
-
-    let filename = file_path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("preview");
-
-    let content_type = get_mime_type(filename);
-
-    // Record file access for recent files
+    // Record file access for recent files, once per playback of media
     let clean_path = format!("/{}", query.path.trim_start_matches('/'));
-    if let Some((file_id, file_name)) = resolve_file_info(&*db, &current_user.username, &query.path).await {
-        record_file_access(
-            &*db,
-            current_user.id,
-            file_id,
-            &clean_path,
-            &file_name,
-            "preview",
-            false,
-        ).await;
+    if first_request {
+        if let Some((file_id, file_name)) = resolve_file_info(&db, &location.owner, &location.path).await {
+            record_file_access(
+                &db,
+                current_user.id,
+                file_id,
+                &clean_path,
+                &file_name,
+                "preview",
+                false,
+            ).await;
+        }
+
+        // Audit log
+        log_operation(&current_user.username, OpType::OpenFile, &clean_path, OP_SUCCESS, None);
     }
 
-    // Audit log
-    log_operation(&current_user.username, op_type::OPEN_FILE, &clean_path, OP_SUCCESS, None);
+    preview::sandbox(&state.config.html_preview, request_host(&headers), response)
+}
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, content_type)
-        .header(
-            header::CONTENT_DISPOSITION,
-            format!("inline; filename=\"{}\"", filename),
-        )
-        .body(body)
-        .unwrap()
+/// Host a request was sent to
+fn request_host(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::HOST).and_then(|v| v.to_str().ok())
+}
+
+/// Multipart form of an upload (documentation only, the handler streams it)
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct UploadForm {
+    /// Row ID of the target directory, takes precedence over `parentPath`
+    #[schema(rename = "parentId")]
+    parent_id: Option<i64>,
+    /// Target directory
+    #[schema(rename = "parentPath")]
+    parent_path: Option<String>,
+    /// File content, named by the part's file name
+    #[schema(format = Binary, value_type = String)]
+    file: Vec<u8>,
 }
 
 /// Upload response matching Go version format
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct UploadResponse {
     result: bool,
     message: String,
 }
 
+/// Move a temp file into the root of `owner`, which may be on another filesystem
+async fn move_into_root(config: &crate::config::Config, owner: &str, tmp_path: &Path) -> std::io::Result<PathBuf> {
+    let root = get_user_path(config, owner);
+    fs::create_dir_all(&root).await?;
+    let target = root.join(tmp_path.file_name().unwrap_or_default());
+    if fs::rename(tmp_path, &target).await.is_err() {
+        fs::copy(tmp_path, &target).await?;
+        fs::remove_file(tmp_path).await?;
+    }
+    Ok(target)
+}
+
 /// POST /api/file/upload
 /// Supports streaming upload for large files - data is written directly to disk
 /// without loading the entire file into memory.
+#[utoipa::path(
+    post,
+    path = "/api/file/upload",
+    tag = "file",
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses((status = 200, body = UploadResponse)),
+)]
 pub async fn upload_file(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
@@ -1348,11 +2104,12 @@ pub async fn upload_file(
     let mut parent_id: Option<i64> = None;
     let mut parent_path = String::new();
     let mut file_name = String::new();
-    let mut content_type = String::new();
     let mut file_written = false;
     let mut actual_size: i64 = 0;
+    let mut sha256: Option<String> = None;
 
-    let user_path = get_user_path(&state.config, &current_user.username);
+    // Tree the file is streamed into and counted against
+    let mut tmp_owner = current_user.username.clone();
     let mut tmp_file: Option<tokio::fs::File> = None;
     let mut tmp_file_path: Option<PathBuf> = None;
 
@@ -1379,14 +2136,31 @@ pub async fn upload_file(
                 }
             }
             "file" => {
-                file_name = field.file_name().unwrap_or("").to_string();
-                if !is_safe_filename(&file_name) {
-                     return (
-                        StatusCode::BAD_REQUEST,
-                        Json(UploadResponse { result: false, message: "invalid file name".to_string() })
+                file_name = match filename::check_new_name(field.file_name().unwrap_or("")) {
+                    Ok(name) => name,
+                    Err(e) => {
+                        return (
+                            StatusCode::BAD_REQUEST,
+                            Json(UploadResponse { result: false, message: format!("invalid file name: {}", e) })
+                        );
+                    }
+                };
+                if let Err(e) = filename::check_upload_type(&file_name) {
+                    metrics::count_upload_rejected(UploadRejection::Type);
+                    return (
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        Json(UploadResponse { result: false, message: e.to_string() })
                     );
                 }
-                content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+
+                // The space of parentPath if it came first, the user's own root otherwise
+                tmp_owner = match locate_for_write(&state, &db, &current_user, &parent_path).await {
+                    Ok(location) => location.owner,
+                    Err((status, error)) => {
+                        return (status, Json(UploadResponse { result: false, message: error.to_string() }));
+                    }
+                };
+                let user_path = get_user_path(&state.config, &tmp_owner);
 
                 // Use a unique temp file to avoid collisions/issues if parentPath comes late
                 // We'll rename it to the correct path after the upload is complete
@@ -1424,12 +2198,20 @@ pub async fn upload_file(
                 tmp_file = Some(file);
                 tmp_file_path = Some(temp_path);
 
-                // Get max upload size from config for validation
-                let max_size = state.config.max_upload_size as i64;
+                // Upload size limit of the user
+                let max_size = upload_limit::limit(&state, &current_user.username).await as i64;
+
+                // Storage quota, None if unlimited
+                let quota_limit = quota::quota_limit(&db, &state.config, &tmp_owner).await;
+                let quota_used = match quota_limit {
+                    Some(_) => quota::used_bytes(&state.config, &tmp_owner).await,
+                    None => 0,
+                };
 
                 // Stream the file data directly to disk
                 let file_ref = tmp_file.as_mut().unwrap();
                 let mut field = field;
+                let mut hasher = Sha256::new();
                 
                 loop {
                     match field.chunk().await {
@@ -1439,20 +2221,34 @@ pub async fn upload_file(
                             // Check if file size exceeds limit
                             if actual_size > max_size {
                                 tracing::warn!("Upload rejected: file size {} exceeds limit {}", actual_size, max_size);
+                                metrics::count_upload_rejected(UploadRejection::Size);
                                 // Clean up temp file
                                 if let Some(ref path) = tmp_file_path {
                                     let _ = fs::remove_file(path).await;
                                 }
-                                let max_size_mb = max_size / (1024 * 1024);
                                 return (
                                     StatusCode::PAYLOAD_TOO_LARGE,
-                                    Json(UploadResponse { 
-                                        result: false, 
-                                        message: format!("文件大小超过限制，最大允许 {}MB", max_size_mb) 
+                                    Json(UploadResponse {
+                                        result: false,
+                                        message: upload_limit::exceeded_message(max_size as usize),
                                     })
                                 );
                             }
                             
+                            // Check if the upload exceeds the storage quota
+                            if let Some(limit) = quota_limit.filter(|limit| quota_used + actual_size > *limit) {
+                                tracing::warn!("Upload rejected: quota of {} exceeded", tmp_owner);
+                                metrics::count_upload_rejected(UploadRejection::Quota);
+                                if let Some(ref path) = tmp_file_path {
+                                    let _ = fs::remove_file(path).await;
+                                }
+                                let exceeded = quota::QuotaExceeded { used: quota_used, limit };
+                                return (
+                                    StatusCode::PAYLOAD_TOO_LARGE,
+                                    Json(UploadResponse { result: false, message: exceeded.message() })
+                                );
+                            }
+
                             if let Err(e) = file_ref.write_all(&chunk).await {
                                 tracing::error!("Failed to write chunk: {}", e);
                                 // Clean up temp file
@@ -1464,10 +2260,12 @@ pub async fn upload_file(
                                     Json(UploadResponse { result: false, message: "上传文件失败".to_string() })
                                 );
                             }
+                            hasher.update(&chunk);
                         }
                         Ok(None) => {
                             // End of stream
                             file_written = true;
+                            sha256 = Some(hex::encode(hasher.finalize_reset()));
                             break;
                         }
                         Err(e) => {
@@ -1487,8 +2285,8 @@ pub async fn upload_file(
                                 || error_msg_lower.contains("content-length");
 
                             let (status, response_msg) = if is_size_error {
-                                let max_size_mb = max_size / (1024 * 1024);
-                                (StatusCode::PAYLOAD_TOO_LARGE, format!("文件大小超过限制，最大允许 {}MB", max_size_mb))
+                                metrics::count_upload_rejected(UploadRejection::Size);
+                                (StatusCode::PAYLOAD_TOO_LARGE, upload_limit::exceeded_message(max_size as usize))
                             } else {
                                 (StatusCode::INTERNAL_SERVER_ERROR, "上传文件失败，请检查网络连接后重试".to_string())
                             };
@@ -1523,18 +2321,30 @@ pub async fn upload_file(
     // Close the file handle before renaming
     drop(tmp_file);
 
-    let tmp_path = tmp_file_path.unwrap();
+    let mut tmp_path = tmp_file_path.unwrap();
 
-    // Recalculate destination path to ensure we use the latest parent_path
-    // This fixes the issue where "file" field appears before "parentPath" field
-    let clean_parent_path = parent_path.trim_start_matches('/');
-    let final_dest_path = user_path.join(clean_parent_path).join(&file_name);
-
-    // Ensure parent directory exists for the final destination
-    if let Some(parent) = final_dest_path.parent() {
-        if !parent.exists() {
-            if let Err(e) = fs::create_dir_all(parent).await {
-                tracing::error!("Failed to create parent directory: {}", e);
+    // The destination is only known now: "file" may come before "parentPath"
+    let location = match locate_for_write(&state, &db, &current_user, &parent_path).await {
+        Ok(location) => location,
+        Err((status, error)) => {
+            let _ = fs::remove_file(&tmp_path).await;
+            return (status, Json(UploadResponse { result: false, message: error.to_string() }));
+        }
+    };
+    if location.owner != tmp_owner {
+        // Streamed into the user's root before parentPath named a space
+        if let Err(exceeded) = quota::check_quota(&db, &state.config, &location.owner, actual_size).await {
+            metrics::count_upload_rejected(UploadRejection::Quota);
+            let _ = fs::remove_file(&tmp_path).await;
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(UploadResponse { result: false, message: exceeded.message() })
+            );
+        }
+        match move_into_root(&state.config, &location.owner, &tmp_path).await {
+            Ok(moved) => tmp_path = moved,
+            Err(e) => {
+                tracing::error!("Failed to move upload {} into {}: {}", file_name, location.owner, e);
                 let _ = fs::remove_file(&tmp_path).await;
                 return (
                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -1544,101 +2354,407 @@ pub async fn upload_file(
         }
     }
 
-    // Rename temp file to final file
-    if let Err(e) = fs::rename(&tmp_path, &final_dest_path).await {
-        tracing::error!("Failed to rename temp file: {}", e);
-        let _ = fs::remove_file(&tmp_path).await;
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(UploadResponse { result: false, message: "上传文件失败".to_string() })
-        );
-    }
-
-    // Resolve parent_id from parentPath if not provided or is root
-    let resolved_parent_id = match parent_id {
-        Some(id) if id > 0 => id,
-        _ => {
-            if !clean_parent_path.is_empty() {
-                resolve_dir_id(&*db, &current_user.username, clean_parent_path).await
-            } else {
-                -1
-            }
+    let target = dlp::Target {
+        uploader: &current_user.username,
+        owner: &location.owner,
+        prefix: &location.prefix,
+        parent_path: &location.path,
+        name: &file_name,
+        size: actual_size,
+    };
+    let warning = match dlp::screen(&state.config, &db, &target, &tmp_path).await {
+        Ok(Verdict::Store(warning)) => warning,
+        Ok(Verdict::Held(message)) => {
+            return (StatusCode::ACCEPTED, Json(UploadResponse { result: true, message }));
+        }
+        Ok(Verdict::Blocked(message)) => {
+            metrics::count_upload_rejected(UploadRejection::Content);
+            return (StatusCode::FORBIDDEN, Json(UploadResponse { result: false, message }));
+        }
+        Err(e) => {
+            tracing::error!("Failed to check upload {} against DLP rules: {}", file_name, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(UploadResponse { result: false, message: "上传文件失败".to_string() })
+            );
         }
     };
-    if resolved_parent_id == 0 {
-        return (
+
+    let service = FileService::new(&state.config, &db, &location.owner)
+        .on_behalf_of(&current_user.username, &location.prefix);
+    match service
+        .upload_finalize(&tmp_path, &location.path, parent_id, &file_name, actual_size, sha256)
+        .await
+    {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(UploadResponse {
+                result: true,
+                message: warning.unwrap_or_else(|| "上传文件成功".to_string()),
+            })
+        ),
+        Err(FileError::InvalidPath) => (
+            StatusCode::BAD_REQUEST,
+            Json(UploadResponse { result: false, message: "invalid parent path".to_string() })
+        ),
+        Err(FileError::ParentNotFound) => (
             StatusCode::BAD_REQUEST,
             Json(UploadResponse { result: false, message: "parent_dir_not_exists".to_string() })
-        );
+        ),
+        Err(FileError::LegalHold(path)) => (
+            StatusCode::LOCKED,
+            Json(UploadResponse { result: false, message: legal_hold::message(&path) })
+        ),
+        Err(e) => {
+            tracing::error!("Failed to store upload {}: {}", file_name, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(UploadResponse { result: false, message: "上传文件失败".to_string() })
+            )
+        }
+    }
+}
+
+/// Checks of an upload before its content arrives: the target folder, the
+/// name and the size limit, returning the stored name and the location
+async fn check_upload_target(
+    state: &AppState,
+    db: &sea_orm::DatabaseConnection,
+    user: &CurrentUser,
+    parent_path: &str,
+    name: &str,
+    size: i64,
+) -> Result<(String, dept_space::Location), (i32, String)> {
+    if !is_safe_path(parent_path) {
+        return Err((400, "invalid parent path".to_string()));
+    }
+    let file_name = filename::check_new_name(name).map_err(|e| (400, format!("invalid file name: {}", e)))?;
+    if let Err(e) = filename::check_upload_type(&file_name) {
+        metrics::count_upload_rejected(UploadRejection::Type);
+        return Err((415, e.to_string()));
+    }
+    let max_size = upload_limit::limit(state, &user.username).await;
+    if size > max_size as i64 {
+        metrics::count_upload_rejected(UploadRejection::Size);
+        return Err((413, upload_limit::exceeded_message(max_size)));
+    }
+    let location = locate_for_write(state, db, user, parent_path)
+        .await
+        .map_err(|(status, error)| (status.as_u16() as i32, error.to_string()))?;
+    Ok((file_name, location))
+}
+
+/// Instant upload request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UploadCheckRequest {
+    /// Row ID of the target directory, takes precedence over `parentPath`
+    #[serde(rename = "parentId")]
+    pub parent_id: Option<i64>,
+    /// Target directory
+    #[serde(rename = "parentPath", default)]
+    pub parent_path: String,
+    pub name: String,
+    pub size: i64,
+    /// Hex SHA-256 of the content
+    pub sha256: String,
+}
+
+/// Instant upload result
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadCheckResponse {
+    /// Whether the file was stored from a copy on the server, the client
+    /// uploads it as usual otherwise
+    pub exists: bool,
+}
+
+/// POST /api/file/upload/check - Store a file from a copy with the same
+/// content already on the server, see [`dedup`]
+#[utoipa::path(
+    post,
+    path = "/api/file/upload/check",
+    tag = "file",
+    request_body = UploadCheckRequest,
+    responses((status = 200, body = UploadCheckResponse)),
+)]
+pub async fn check_upload(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<UploadCheckRequest>,
+) -> Json<ApiResponse<UploadCheckResponse>> {
+    if !dedup::is_valid(&req.sha256) {
+        return Json(ApiResponse::error(400, "invalid sha256"));
+    }
+    let (file_name, location) =
+        match check_upload_target(&state, &db, &current_user, &req.parent_path, &req.name, req.size).await {
+            Ok(target) => target,
+            Err((code, message)) => return Json(ApiResponse::error(code, message)),
+        };
+    let missing = || Json(ApiResponse::success(UploadCheckResponse { exists: false }));
+    // Nothing to save on empty files
+    if req.size <= 0 {
+        return missing();
     }
 
-    // Save to database
-    let now = chrono::Utc::now().timestamp();
-    let file_info = file_info::ActiveModel {
-        username: Set(current_user.username.clone()),
-        name: Set(file_name.clone()),
-        file_type: Set(content_type),
-        size: Set(actual_size),
-        parent_id: Set(resolved_parent_id),
-        create_time: Set(now),
-        modify_time: Set(now),
-        is_directory: Set(false),
-        ..Default::default()
+    let owners = [current_user.username.as_str(), location.owner.as_str()];
+    let source = match dedup::find(&state.config, &db, &owners, &req.sha256, req.size).await {
+        Ok(Some(source)) => source,
+        Ok(None) => return missing(),
+        Err(e) => {
+            tracing::error!("Failed to look up {}: {}", req.sha256, e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
     };
+    if let Err(exceeded) = quota::check_quota(&db, &state.config, &location.owner, req.size).await {
+        metrics::count_upload_rejected(UploadRejection::Quota);
+        return Json(ApiResponse::error(413, exceeded.message()));
+    }
 
-    if let Err(e) = file_info.insert(&*db).await {
-        tracing::error!("Failed to save file info: {}", e);
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(UploadResponse { result: false, message: "上传文件失败".to_string() })
-        );
+    let root = get_user_path(&state.config, &location.owner);
+    let tmp_path = root.join(uuid::Uuid::new_v4().to_string()).with_extension("uploading");
+    let linked = async {
+        fs::create_dir_all(&root).await?;
+        dedup::link(&source, &tmp_path).await
+    }
+    .await;
+    if let Err(e) = linked {
+        tracing::error!("Failed to link {:?} for {}: {}", source, file_name, e);
+        let _ = fs::remove_file(&tmp_path).await;
+        return missing();
+    }
+    let target = dlp::Target {
+        uploader: &current_user.username,
+        owner: &location.owner,
+        prefix: &location.prefix,
+        parent_path: &location.path,
+        name: &file_name,
+        size: req.size,
+    };
+    match dlp::screen(&state.config, &db, &target, &tmp_path).await {
+        Ok(Verdict::Store(_)) => {}
+        Ok(Verdict::Held(message)) => return Json(ApiResponse::error(202, message)),
+        Ok(Verdict::Blocked(message)) => {
+            metrics::count_upload_rejected(UploadRejection::Content);
+            return Json(ApiResponse::error(403, message));
+        }
+        Err(e) => {
+            tracing::error!("Failed to check upload {} against DLP rules: {}", file_name, e);
+            return Json(ApiResponse::error(500, "上传文件失败"));
+        }
     }
 
-    // Audit log
-    let log_path = format!("/{}/{}", clean_parent_path, file_name);
-    let log_path = log_path.replace("//", "/");
-    log_operation(&current_user.username, op_type::UPLOAD, &log_path, OP_SUCCESS, None);
+    let service = FileService::new(&state.config, &db, &location.owner)
+        .on_behalf_of(&current_user.username, &location.prefix);
+    let sha256 = req.sha256.to_ascii_lowercase();
+    match service
+        .upload_finalize(&tmp_path, &location.path, req.parent_id, &file_name, req.size, Some(sha256))
+        .await
+    {
+        Ok(_) => Json(ApiResponse::success(UploadCheckResponse { exists: true })),
+        Err(FileError::InvalidPath) => Json(ApiResponse::error(400, "invalid parent path")),
+        Err(FileError::ParentNotFound) => Json(ApiResponse::error(400, "parent_dir_not_exists")),
+        Err(FileError::LegalHold(path)) => Json(ApiResponse::error(423, legal_hold::message(&path))),
+        Err(e) => {
+            tracing::error!("Failed to store upload {}: {}", file_name, e);
+            Json(ApiResponse::error(500, "上传文件失败"))
+        }
+    }
+}
 
-    (
-        StatusCode::OK,
-        Json(UploadResponse { result: true, message: "上传文件成功".to_string() })
-    )
+/// Upload precheck request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UploadPrecheckRequest {
+    /// Target directory
+    #[serde(rename = "parentPath", default)]
+    pub parent_path: String,
+    pub name: String,
+    pub size: i64,
+    /// Hex SHA-256 of the content, to learn whether it can be uploaded instantly
+    pub sha256: Option<String>,
+}
+
+/// Upload precheck result of an upload that would be accepted
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadPrecheckResponse {
+    /// A file of the same name exists and would be replaced
+    pub conflict: bool,
+    /// A file with the same content is stored, `/api/file/upload/check`
+    /// can store it without sending it
+    pub instant: bool,
+}
+
+/// POST /api/file/upload/precheck - Whether an upload would be accepted,
+/// before its content is sent
+///
+/// Checks the name, the blocked types, the size limit, the target folder, the
+/// quota, files of the same name and legal holds on them.
+#[utoipa::path(
+    post,
+    path = "/api/file/upload/precheck",
+    tag = "file",
+    request_body = UploadPrecheckRequest,
+    responses((status = 200, body = UploadPrecheckResponse)),
+)]
+pub async fn precheck_upload(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<UploadPrecheckRequest>,
+) -> Json<ApiResponse<UploadPrecheckResponse>> {
+    if req.sha256.as_deref().is_some_and(|hash| !dedup::is_valid(hash)) {
+        return Json(ApiResponse::error(400, "invalid sha256"));
+    }
+    let (file_name, location) =
+        match check_upload_target(&state, &db, &current_user, &req.parent_path, &req.name, req.size).await {
+            Ok(target) => target,
+            Err((code, message)) => return Json(ApiResponse::error(code, message)),
+        };
+
+    let parent = resolve_in_user_root(&state.config, &location.owner, &location.path);
+    if !parent.is_some_and(|parent| parent.is_dir()) {
+        return Json(ApiResponse::error(400, "parent_dir_not_exists"));
+    }
+    let relative = format!("{}/{}", location.path.trim_end_matches('/'), file_name);
+    let existing = match resolve_in_user_root(&state.config, &location.owner, &relative) {
+        Some(full) => fs::metadata(&full).await.ok(),
+        None => return Json(ApiResponse::error(400, "invalid file name")),
+    };
+    if existing.as_ref().is_some_and(|m| m.is_dir()) {
+        return Json(ApiResponse::error(409, "文件夹已存在"));
+    }
+    let conflict = existing.is_some();
+    if conflict {
+        let service = FileService::new(&state.config, &db, &location.owner)
+            .on_behalf_of(&current_user.username, &location.prefix);
+        match service.check_hold(&relative).await {
+            Ok(()) => {}
+            Err(FileError::LegalHold(path)) => return Json(ApiResponse::error(423, legal_hold::message(&path))),
+            Err(e) => {
+                tracing::error!("Failed to check legal holds of {}: {}", relative, e);
+                return Json(ApiResponse::error(500, "internal error"));
+            }
+        }
+    }
+    if let Err(exceeded) = quota::check_quota(&db, &state.config, &location.owner, req.size).await {
+        metrics::count_upload_rejected(UploadRejection::Quota);
+        return Json(ApiResponse::error(413, exceeded.message()));
+    }
+
+    let instant = match req.sha256.as_deref().filter(|_| req.size > 0) {
+        Some(sha256) => {
+            let owners = [current_user.username.as_str(), location.owner.as_str()];
+            match dedup::find(&state.config, &db, &owners, sha256, req.size).await {
+                Ok(source) => source.is_some(),
+                Err(e) => {
+                    tracing::error!("Failed to look up {}: {}", sha256, e);
+                    false
+                }
+            }
+        }
+        None => false,
+    };
+    Json(ApiResponse::success(UploadPrecheckResponse { conflict, instant }))
 }
 
 /// Copy/Move request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CopyMoveRequest {
     #[serde(rename = "isCopy")]
     pub is_copy: bool,
     pub source: String,
     pub target: String,
     pub files: Vec<String>,
+    /// `lastmod` of the files as listed, by name; nothing is moved if one
+    /// changed since. Ignored for copies.
+    #[serde(default, rename = "ifMatch")]
+    pub if_match: HashMap<String, IfMatch>,
 }
 
 /// POST /api/file/copy
+#[utoipa::path(
+    post,
+    path = "/api/file/copy",
+    tag = "file",
+    request_body = CopyMoveRequest,
+    responses(
+        (status = 200, body = ApiMessage),
+        (status = 412, description = "A moved file changed since it was listed", body = ApiMessage),
+    ),
+)]
 pub async fn copy_move_file(
     State(state): State<AppState>,
-    Extension(_db): Extension<DbConn>,
+    Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Json(req): Json<CopyMoveRequest>,
-) -> Json<ApiResponse<()>> {
-    use crate::task::TASK_MANAGER;
+) -> Response {
+    use crate::task::{TaskDir, TASK_MANAGER};
 
     if !is_safe_path(&req.source) {
-        return Json(ApiResponse::error(400, "invalid source path"));
+        return Json(ApiResponse::<()>::error(400, "invalid source path")).into_response();
     }
     if !is_safe_path(&req.target) {
-        return Json(ApiResponse::error(400, "invalid target path"));
+        return Json(ApiResponse::<()>::error(400, "invalid target path")).into_response();
     }
     for file in &req.files {
         if !is_safe_filename(file) {
-            return Json(ApiResponse::error(400, "invalid file name"));
+            return Json(ApiResponse::<()>::error(400, "invalid file name")).into_response();
+        }
+    }
+
+    // Moving takes the files away from the source
+    let source = if req.is_copy {
+        locate(&state, &db, &current_user, &req.source).await
+    } else {
+        locate_for_write(&state, &db, &current_user, &req.source).await
+    };
+    let source = match source {
+        Ok(location) => location,
+        Err((status, error)) => {
+            return Json(ApiResponse::<()>::error(status.as_u16() as i32, error)).into_response()
+        }
+    };
+    let target = match locate_for_write(&state, &db, &current_user, &req.target).await {
+        Ok(location) => location,
+        Err((status, error)) => {
+            return Json(ApiResponse::<()>::error(status.as_u16() as i32, error)).into_response()
+        }
+    };
+    let source_root = get_user_path(&state.config, &source.owner);
+
+    // Only moves take the files as listed away
+    if !req.is_copy {
+        let service = FileService::new(&state.config, &db, &source.owner)
+            .on_behalf_of(&current_user.username, &source.prefix);
+        let expected = req
+            .if_match
+            .iter()
+            .map(|(name, seen)| (format!("{}/{}", source.path, name), seen))
+            .collect::<Vec<_>>();
+        if let Err(response) = check_unchanged(&service, expected).await {
+            return response;
         }
     }
 
-    let user_path = get_user_path(&state.config, &current_user.username);
+    // Copies and moves into another space add to the used space of the
+    // target, moves within a tree don't
+    if req.is_copy || source.owner != target.owner {
+        let sources: Vec<PathBuf> = req
+            .files
+            .iter()
+            .filter_map(|f| resolve_in_root(&source_root, &format!("{}/{}", source.path, f)))
+            .collect();
+        let size = tokio::task::spawn_blocking(move || {
+            sources.iter().map(|p| quota::path_size(p)).sum::<i64>()
+        })
+        .await
+        .unwrap_or(0);
+        if let Err(exceeded) = quota::check_quota(&db, &state.config, &target.owner, size).await {
+            return Json(ApiResponse::<()>::error(413, exceeded.message())).into_response();
+        }
+    }
 
     // Create and add task
+    let target_root = get_user_path(&state.config, &target.owner);
     let _task_info = TASK_MANAGER.create_copy_task(
         current_user.id,
         &current_user.username,
@@ -1647,26 +2763,18 @@ pub async fn copy_move_file(
         req.source.clone(),
         req.target.clone(),
         req.files.clone(),
-        user_path,
+        TaskDir { owner: source.owner, root: source_root, path: source.path },
+        TaskDir { owner: target.owner, root: target_root, path: target.path },
+        db.0.clone(),
     );
 
-    // Audit log - one entry per file/directory
-    let op_type_str = if req.is_copy { op_type::COPY } else { op_type::MOVE };
-    for file in &req.files {
-        let src_path = if req.source == "/" {
-            format!("/{}", file)
-        } else {
-            format!("{}/{}", req.source, file)
-        };
-        let op_desc = format!("{} => {}", src_path, req.target);
-        log_operation(&current_user.username, op_type_str, &op_desc, OP_SUCCESS, None);
-    }
+    // Audit entries are recorded by the task as it runs
 
-    Json(ApiResponse::success_msg("任务添加成功, 请查看任务列表"))
+    Json(ApiResponse::<()>::success_msg("任务添加成功, 请查看任务列表")).into_response()
 }
 
 /// Conflict resolution request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ResolveConflictRequest {
     #[serde(rename = "taskId")]
     pub task_id: String,
@@ -1676,6 +2784,13 @@ pub struct ResolveConflictRequest {
 }
 
 /// POST /api/file/resolve-conflict
+#[utoipa::path(
+    post,
+    path = "/api/file/resolve-conflict",
+    tag = "file",
+    request_body = ResolveConflictRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
 pub async fn resolve_conflict(
     Extension(current_user): Extension<CurrentUser>,
     Json(req): Json<ResolveConflictRequest>,
@@ -1701,7 +2816,27 @@ pub async fn resolve_conflict(
 
 #[cfg(test)]
 mod tests {
-    use super::{get_mime_type, is_safe_filename, is_safe_path};
+    use super::*;
+    use crate::testing::{TestApp, TestEnv};
+    use async_trait::async_trait;
+    use sea_orm::DbErr;
+    use std::io::Read;
+
+    #[test]
+    fn channel_writer_stops_after_client_disconnect() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let sent = Arc::new(AtomicU64::new(0));
+        let mut writer = ChannelWriter::new(tx, sent.clone());
+
+        writer.write_all(b"hello").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(rx.try_recv().unwrap().unwrap(), b"hello");
+        assert_eq!(sent.load(Ordering::Relaxed), 5);
+
+        drop(rx);
+        let err = writer.write_all(b"more").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+    }
 
     #[test]
     fn safe_path_allows_root_and_normal_segments() {
@@ -1737,10 +2872,418 @@ mod tests {
         assert!(is_safe_filename("a-b_c 1.txt"));
     }
 
+    #[cfg(unix)]
     #[test]
-    fn mime_type_from_extension() {
-        assert_eq!(get_mime_type("photo.jpg"), "image/jpeg");
-        assert_eq!(get_mime_type("doc.pdf"), "application/pdf");
-        assert_eq!(get_mime_type("unknown.bin"), "application/octet-stream");
+    fn resolve_in_root_rejects_symlink_escapes() {
+        let dir = std::env::temp_dir().join(format!("resolve-test-{}", uuid::Uuid::new_v4()));
+        let root = dir.join("alice");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::create_dir_all(dir.join("bob")).unwrap();
+        std::os::unix::fs::symlink(dir.join("bob"), root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(root.join("docs"), root.join("inside")).unwrap();
+        std::os::unix::fs::symlink(dir.join("missing"), root.join("dangling")).unwrap();
+
+        assert_eq!(resolve_in_root(&root, "/docs/a.txt"), Some(root.join("docs/a.txt")));
+        // Targets that don't exist yet
+        assert_eq!(resolve_in_root(&root, "new/dir/b.txt"), Some(root.join("new/dir/b.txt")));
+        assert_eq!(resolve_in_root(&root, "inside/c.txt"), Some(root.join("inside/c.txt")));
+        assert_eq!(resolve_in_root(&root, "escape"), None);
+        assert_eq!(resolve_in_root(&root, "escape/new.txt"), None);
+        assert_eq!(resolve_in_root(&root, "dangling"), None);
+        assert_eq!(resolve_in_root(&root, "../bob"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// File rows kept in memory instead of the database
+    struct Rows(Mutex<Vec<file_info::Model>>);
+
+    impl Rows {
+        fn new(rows: &[(i64, i64, &str, bool)]) -> Self {
+            let rows = rows
+                .iter()
+                .map(|&(id, parent_id, name, is_directory)| file_info::Model {
+                    id,
+                    parent_id,
+                    parent_path: None,
+                    username: "alice".to_string(),
+                    name: name.to_string(),
+                    file_type: String::new(),
+                    size: 0,
+                    create_time: 0,
+                    modify_time: 0,
+                    is_directory,
+                    sha256: None,
+                })
+                .collect();
+            Self(Mutex::new(rows))
+        }
+
+        fn ids(&self) -> Vec<i64> {
+            self.0.lock().unwrap().iter().map(|r| r.id).collect()
+        }
+    }
+
+    #[async_trait]
+    impl FileInfoRepository for Rows {
+        async fn find_file(&self, username: &str, id: i64) -> Result<Option<file_info::Model>, DbErr> {
+            let rows = self.0.lock().unwrap();
+            Ok(rows.iter().find(|r| r.username == username && r.id == id).cloned())
+        }
+
+        async fn find_child(
+            &self,
+            username: &str,
+            parent_id: i64,
+            name: &str,
+        ) -> Result<Option<file_info::Model>, DbErr> {
+            let rows = self.0.lock().unwrap();
+            Ok(rows
+                .iter()
+                .find(|r| r.username == username && r.parent_id == parent_id && r.name == name)
+                .cloned())
+        }
+
+        async fn children(&self, username: &str, parent_id: i64) -> Result<Vec<file_info::Model>, DbErr> {
+            let rows = self.0.lock().unwrap();
+            Ok(rows
+                .iter()
+                .filter(|r| r.username == username && r.parent_id == parent_id)
+                .cloned()
+                .collect())
+        }
+
+        async fn delete_file(&self, id: i64) -> Result<(), DbErr> {
+            self.0.lock().unwrap().retain(|r| r.id != id);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_and_delete_rows() {
+        let rows = Rows::new(&[
+            (1, -1, "docs", true),
+            (2, 1, "work", true),
+            (3, 2, "a.txt", false),
+            (4, -1, "b.txt", false),
+        ]);
+
+        assert_eq!(resolve_dir_id(&rows, "alice", "/").await, -1);
+        assert_eq!(resolve_dir_id(&rows, "alice", "/docs/work").await, 2);
+        assert_eq!(resolve_dir_id(&rows, "alice", "docs/missing").await, 0);
+        // Files are no directories
+        assert_eq!(resolve_dir_id(&rows, "alice", "b.txt").await, 0);
+        assert_eq!(resolve_dir_id(&rows, "bob", "docs").await, 0);
+
+        delete_children(&rows, 1, "alice").await;
+        assert_eq!(rows.ids(), vec![4]);
+    }
+
+    #[tokio::test]
+    async fn mkdir_and_list_handlers() {
+        let env = TestEnv::new().await;
+        let user = env.user("alice", &[]);
+        let mkdir_request = || MkdirRequest {
+            path: None,
+            name: "docs".to_string(),
+            parent_id: None,
+            parent_path: Some("/".to_string()),
+        };
+
+        let Json(res) = mkdir(
+            State(env.state()),
+            Extension(env.db_conn()),
+            Extension(user.clone()),
+            Json(mkdir_request()),
+        )
+        .await;
+        assert!(res.code);
+        let Json(res) = mkdir(
+            State(env.state()),
+            Extension(env.db_conn()),
+            Extension(user.clone()),
+            Json(mkdir_request()),
+        )
+        .await;
+        assert!(!res.code);
+        assert_eq!(res.message, "文件夹已存在");
+
+        let list = |path: &str| {
+            list_directory(
+                State(env.state()),
+                Extension(env.db_conn()),
+                Extension(user.clone()),
+                Query(ListQuery { path: path.to_string(), ..ListQuery::default() }),
+                HeaderMap::new(),
+            )
+        };
+        let response = list("/").await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let items: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(items[0]["filename"], "/docs");
+        assert_eq!(items[0]["type"], "directory");
+        assert_eq!(list("/missing").await.into_response().status(), StatusCode::NOT_FOUND);
+
+        env.close().await;
+    }
+
+    #[tokio::test]
+    async fn changes_refused_after_file_was_replaced() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "a.txt", b"hello").await.status().is_success());
+        let items: serde_json::Value = admin.get("/api/file/list?path=/").await.json().await.unwrap();
+        let lastmod = items[0]["lastmod"].as_str().unwrap().to_string();
+
+        // Someone else replaces the file after it was listed
+        let path = get_user_path(&app.env.config, "admin").join("a.txt");
+        let earlier = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(earlier).unwrap();
+
+        let body = serde_json::json!({ "oldPath": "/a.txt", "newName": "b.txt", "ifMatch": lastmod });
+        assert_eq!(admin.post_json("/api/file/rename", &body).await.status(), StatusCode::PRECONDITION_FAILED);
+        let body = serde_json::json!({ "files": ["a.txt"], "parentDir": "/", "ifMatch": { "a.txt": lastmod } });
+        assert_eq!(admin.post_json("/api/file/delete", &body).await.status(), StatusCode::PRECONDITION_FAILED);
+        assert!(path.exists());
+
+        // Seen as it is now, in either form
+        let seconds = earlier.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let body = serde_json::json!({ "files": ["a.txt"], "parentDir": "/", "ifMatch": { "a.txt": seconds } });
+        let res: serde_json::Value = admin.post_json("/api/file/delete", &body).await.json().await.unwrap();
+        assert_eq!(res["data"]["success"], 1);
+        assert!(!path.exists());
+        app.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_download_formats() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "a.txt", b"hello").await.status().is_success());
+        let body = serde_json::json!({ "parentPath": "/", "name": "docs" });
+        assert!(admin.post_json("/api/file/mkdir", &body).await.status().is_success());
+        assert!(admin.upload("/docs", "b.txt", b"world").await.status().is_success());
+
+        let body = serde_json::json!({ "files": ["a.txt", "docs"], "parentDir": "/", "format": "tar.gz", "level": 9 });
+        let res: serde_json::Value = admin.post_json("/api/file/download/pre", &body).await.json().await.unwrap();
+        let guid = res["guid"].as_str().unwrap().to_string();
+        let res = admin.get(&format!("/api/file/download?guid={}", guid)).await;
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/gzip");
+        let data = res.bytes().await.unwrap();
+
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(&data[..]));
+        let mut entries: Vec<(String, String)> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                (entry.path().unwrap().to_string_lossy().to_string(), content)
+            })
+            .collect();
+        entries.sort();
+        assert_eq!(entries, [("a.txt".into(), "hello".into()), ("docs/b.txt".into(), "world".into())]);
+
+        // Zip by default
+        let body = serde_json::json!({ "files": ["a.txt", "docs"], "parentDir": "/" });
+        let res: serde_json::Value = admin.post_json("/api/file/download/pre", &body).await.json().await.unwrap();
+        let url = format!("/api/file/download?guid={}", res["guid"].as_str().unwrap());
+        let data = admin.get(&url).await.bytes().await.unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).unwrap();
+        let mut content = String::new();
+        archive.by_name("docs/b.txt").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!((archive.len(), content.as_str()), (2, "world"));
+
+        let body = serde_json::json!({ "files": ["a.txt"], "parentDir": "/", "level": 10 });
+        let res: serde_json::Value = admin.post_json("/api/file/download/pre", &body).await.json().await.unwrap();
+        assert_eq!(res["result"], false);
+        app.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_preview_html_sandboxed() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let page = b"<script>fetch('/api/user/info')</script>";
+        assert!(admin.upload("/", "page.html", page).await.status().is_success());
+        assert!(admin.upload("/", "a.txt", b"hello").await.status().is_success());
+
+        for url in ["/api/file/content?path=/page.html", "/api/file/preview/single?path=/page.html"] {
+            let res = admin.get(url).await;
+            assert!(res.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+            let csp = res.headers()[header::CONTENT_SECURITY_POLICY].to_str().unwrap();
+            assert!(csp.starts_with("sandbox;"), "{}", csp);
+            assert_eq!(res.bytes().await.unwrap().as_ref(), page);
+        }
+        let res = admin.get("/api/file/content?path=/a.txt").await;
+        assert!(!res.headers().contains_key(header::CONTENT_SECURITY_POLICY));
+        assert_eq!(res.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        app.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_content_charset() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let text = "这是一个测试文件，我们在中国使用它。";
+        let (gbk, _, _) = encoding_rs::GBK.encode(text);
+        assert!(admin.upload("/", "gbk.txt", &gbk).await.status().is_success());
+
+        let res = admin.get("/api/file/content?path=/gbk.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert_eq!(res.headers()[CONTENT_CHARSET], "GBK");
+        assert_eq!(res.text().await.unwrap(), text);
+
+        // The bytes as stored
+        let res = admin.get("/api/file/content?path=/gbk.txt&raw=true").await;
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/plain; charset=GBK");
+        assert_eq!(res.bytes().await.unwrap().as_ref(), gbk.as_ref());
+        app.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_save_content() {
+        use crate::entity::file_info;
+
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "notes.md", b"# Notes").await.status().is_success());
+        let save = |if_match: Option<String>, body: &'static str| {
+            let mut request = admin.http.post(app.url("/api/file/content?path=/notes.md")).body(body);
+            if let Some(if_match) = if_match {
+                request = request.header(header::IF_MATCH, if_match);
+            }
+            request.send()
+        };
+
+        let res = admin.get("/api/file/content?path=/notes.md").await;
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        let res = save(Some(etag.clone()), "# Notes\n\n- milk").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let saved = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        let body: serde_json::Value = res.json().await.unwrap();
+        assert_eq!(body["data"]["etag"], saved);
+        assert_eq!(body["data"]["size"], 15);
+        let res = admin.get("/api/file/content?path=/notes.md").await;
+        assert_eq!(res.headers()[header::ETAG], saved.as_str());
+        assert_eq!(res.text().await.unwrap(), "# Notes\n\n- milk");
+        let row = file_info::Entity::find()
+            .filter(file_info::Column::Name.eq("notes.md"))
+            .one(&app.env.db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.size, 15);
+
+        // Saved by someone else since it was read
+        let res = save(Some(etag), "stale").await.unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(save(None, "blind").await.unwrap().status(), StatusCode::PRECONDITION_REQUIRED);
+        assert_eq!(save(Some("*".to_string()), "forced").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(admin.get("/api/file/content?path=/notes.md").await.text().await.unwrap(), "forced");
+
+        // Only existing text files
+        assert!(admin.upload("/", "photo.png", b"png").await.status().is_success());
+        let res = admin
+            .http
+            .post(app.url("/api/file/content?path=/photo.png"))
+            .header(header::IF_MATCH, "*")
+            .body("text")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let res = admin
+            .http
+            .post(app.url("/api/file/content?path=/missing.txt"))
+            .header(header::IF_MATCH, "*")
+            .body("text")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        app.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_touch() {
+        use crate::entity::file_info;
+
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let admin = &admin;
+        let touch = |body: serde_json::Value| async move {
+            let res: serde_json::Value = admin.post_json("/api/file/touch", &body).await.json().await.unwrap();
+            res
+        };
+
+        let res = touch(serde_json::json!({"name": "todo.txt", "parentPath": "/"})).await;
+        assert_eq!(res["code"], true, "{}", res);
+        assert_eq!(res["data"]["name"], "todo.txt");
+        assert_eq!(admin.get("/api/file/content?path=/todo.txt").await.text().await.unwrap(), "");
+
+        // The template's extension is added to the name
+        let res = touch(serde_json::json!({"name": "周报", "parentPath": "/", "template": "docx"})).await;
+        assert_eq!(res["code"], true, "{}", res);
+        assert_eq!(res["data"]["name"], "周报.docx");
+        let content = std::fs::read(get_user_path(&app.env.config, "admin").join("周报.docx")).unwrap();
+        assert!(mime::signature_matches("docx", &content));
+        let row = file_info::Entity::find()
+            .filter(file_info::Column::Name.eq("周报.docx"))
+            .one(&app.env.db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.size, content.len() as i64);
+
+        // By default the template of the name's extension
+        let res = touch(serde_json::json!({"name": "README.md", "parentPath": "/"})).await;
+        assert_eq!(res["code"], true, "{}", res);
+        assert_eq!(admin.get("/api/file/content?path=/README.md").await.text().await.unwrap(), "# README\n");
+
+        let res = touch(serde_json::json!({"name": "todo.txt", "parentPath": "/"})).await;
+        assert_eq!(res["code"], false);
+        assert_eq!(res["message"], "文件已存在");
+        let res = touch(serde_json::json!({"name": "slides", "parentPath": "/", "template": "pptx"})).await;
+        assert_eq!(res["code"], false);
+        let res = touch(serde_json::json!({"name": "a/b.txt", "parentPath": "/"})).await;
+        assert_eq!(res["code"], false);
+        app.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_permanently() {
+        use crate::entity::{file_info, trash};
+
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            assert!(admin.upload("/", name, b"hello").await.status().is_success());
+        }
+        let root = get_user_path(&app.env.config, "admin");
+        let trashed = || trash::Entity::find().all(&app.env.db);
+
+        // To the trash unless asked otherwise
+        let body = serde_json::json!({ "parentDir": "/", "files": ["a.txt"] });
+        assert!(admin.post_json("/api/file/delete", &body).await.status().is_success());
+        let body = serde_json::json!({ "parentDir": "/", "files": ["b.txt"], "permanent": true });
+        assert!(admin.post_json("/api/file/delete", &body).await.status().is_success());
+        let c = file_info::Entity::find()
+            .filter(file_info::Column::Name.eq("c.txt"))
+            .one(&app.env.db)
+            .await
+            .unwrap()
+            .unwrap();
+        let body = serde_json::json!({ "parentPath": "/", "ids": [c.id], "permanent": true });
+        assert!(admin.post_json("/api/file/remove/file", &body).await.status().is_success());
+
+        assert!(!root.join("b.txt").exists() && !root.join("c.txt").exists());
+        let names: Vec<_> = trashed().await.unwrap().into_iter().map(|item| item.name).collect();
+        assert_eq!(names, ["a.txt"]);
+        app.close().await;
     }
 }
@@ -0,0 +1,658 @@
+//! Folder hooks
+//!
+//! Users set hooks on folders they may change, turning them into drop zones:
+//! every file landing in the folder (uploaded, saved over WebDAV, copied or
+//! moved there) runs the hooks of the folder, in the order they were added:
+//! - `webhook` posts the file to a URL, under the [outbound policy](crate::outbound)
+//! - `notify` tells the members of a group the hook's creator belongs to, over
+//!   the WebSocket and by email when mail is set up
+//! - `extract` unpacks archives into a new folder next to them, as a task of
+//!   the user who dropped the archive
+//! - `command` runs one of the commands of `[hooks] commands`, such as an
+//!   indexer, with the file's path on disk
+//!
+//! Hooks run for the files right in the folder, not for those in its
+//! subfolders, so extracting an archive doesn't run them again. Hidden files
+//! and Office lock files are left alone. Like watches, hooks stay with the
+//! path when the folder is renamed. Hooks subscribe to the
+//! [change bus](crate::handlers::watch) and run in the background; failures are
+//! logged and don't affect the change.
+
+use axum::{
+    extract::{Query, State},
+    response::Json,
+    Extension,
+};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::Config;
+use crate::entity::op_log::OpType;
+use crate::entity::{folder_hook, group, group_user, user};
+use crate::handlers::archive_extract;
+use crate::handlers::artifact::{self, ArtifactKind};
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::file::{get_user_path, locate, locate_for_write, resolve_in_user_root};
+use crate::handlers::path::{normalize, split};
+use crate::handlers::quota;
+use crate::handlers::watch::{self, Change, ChangeKind};
+use crate::mail;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::outbound;
+use crate::repository::UserRepository;
+use crate::routes::{ApiMessage, ApiResponse};
+use crate::service::{FileError, FileService};
+use crate::state::AppState;
+use crate::task::{ConflictPolicy, TaskDir, TASK_MANAGER};
+use crate::ws::{WsMessage, HUB};
+
+const OP_SUCCESS: &str = "成功";
+
+/// What a hook does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Webhook,
+    Notify,
+    Extract,
+    Command,
+}
+
+impl Action {
+    pub fn code(self) -> &'static str {
+        match self {
+            Action::Webhook => "webhook",
+            Action::Notify => "notify",
+            Action::Extract => "extract",
+            Action::Command => "command",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<Self> {
+        [Action::Webhook, Action::Notify, Action::Extract, Action::Command]
+            .into_iter()
+            .find(|action| action.code() == code)
+    }
+}
+
+/// Whether a file named `name` is left alone: hidden files and the lock
+/// files of Office
+fn ignored(name: &str) -> bool {
+    name.is_empty() || name.starts_with('.') || name.starts_with("~$")
+}
+
+/// A file that landed in a folder with hooks, as webhooks and notices get it
+#[derive(Debug, Clone, Serialize)]
+pub struct Landed {
+    #[serde(rename = "hookId")]
+    pub hook_id: i64,
+    /// The file, in the file API of the hook's creator
+    pub path: String,
+    pub name: String,
+    pub size: u64,
+    /// create, update or move
+    pub kind: &'static str,
+    /// User who put the file there
+    pub actor: String,
+    pub time: i64,
+}
+
+/// Run the hooks of the folder `change` happened in, returning how many ran
+pub async fn run(config: &Arc<Config>, db: &DatabaseConnection, change: &Change) -> Result<usize, DbErr> {
+    if !matches!(change.kind, ChangeKind::Created | ChangeKind::Modified | ChangeKind::Moved) {
+        return Ok(0);
+    }
+    let (folder, name) = split(&change.path);
+    if ignored(name) {
+        return Ok(0);
+    }
+    let hooks = folder_hook::Entity::find()
+        .filter(folder_hook::Column::Owner.eq(&change.owner))
+        .filter(folder_hook::Column::Path.eq(&folder))
+        .order_by_asc(folder_hook::Column::Id)
+        .all(db)
+        .await?;
+    if hooks.is_empty() {
+        return Ok(0);
+    }
+    // Folders, and files moved away rather than here
+    let Some(file) = resolve_in_user_root(config, &change.owner, &change.path).filter(|p| p.is_file()) else {
+        return Ok(0);
+    };
+    let size = file.metadata().map_or(0, |m| m.len());
+
+    for hook in &hooks {
+        let landed = Landed {
+            hook_id: hook.id,
+            path: format!("{}/{}", hook.shown_path.trim_end_matches('/'), name),
+            name: name.to_string(),
+            size,
+            kind: change.kind.code(),
+            actor: change.actor.clone(),
+            time: chrono::Utc::now().timestamp(),
+        };
+        let result = match Action::parse(&hook.action) {
+            Some(Action::Webhook) => post(config, &hook.target, &landed).await,
+            Some(Action::Notify) => notify(config, db, &hook.target, &landed).await,
+            Some(Action::Extract) => extract(config, db, change, &file).await,
+            Some(Action::Command) => command(config, &hook.target, &file, &landed).await,
+            None => Err(anyhow::anyhow!("unknown action {}", hook.action)),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Hook {} of {} failed for {}: {:#}", hook.id, hook.shown_path, name, e);
+        }
+    }
+    Ok(hooks.len())
+}
+
+async fn post(config: &Config, url: &str, landed: &Landed) -> anyhow::Result<()> {
+    let payload = serde_json::json!({ "event": "fileLanded", "file": landed });
+    outbound::post_json(config, url, &payload).await?.error_for_status()?;
+    Ok(())
+}
+
+/// Tell the members of `group_name` but the one who dropped the file
+async fn notify(config: &Config, db: &DatabaseConnection, group_name: &str, landed: &Landed) -> anyhow::Result<()> {
+    let Some(group) = group::Entity::find().filter(group::Column::Name.eq(group_name)).one(db).await? else {
+        anyhow::bail!("group {} not found", group_name);
+    };
+    let member_ids: Vec<i64> = group_user::Entity::find()
+        .filter(group_user::Column::GroupId.eq(group.id))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|member| member.user_id)
+        .collect();
+    let members = user::Entity::find().filter(user::Column::Id.is_in(member_ids)).all(db).await?;
+    let payload = serde_json::to_value(landed)?;
+    let subject = format!("Datadisk 新文件: {}", landed.name);
+    let body = format!("{} 放入了文件 {} ({} 字节)", landed.actor, landed.path, landed.size);
+    for member in members.into_iter().filter(|m| m.username != landed.actor) {
+        HUB.send(member.id, WsMessage::FolderHook(payload.clone()));
+        let email = member.email.unwrap_or_default();
+        if mail::enabled(&config.mail) && !email.is_empty() {
+            if let Err(e) = mail::send(&config.mail, &email, &subject, body.clone()).await {
+                tracing::warn!("Failed to send hook notice to {}: {:#}", member.username, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Folder named after the archive `name`: without its extensions
+fn extract_folder_name(name: &str) -> String {
+    let stem = Path::new(name).file_stem().and_then(|s| s.to_str()).unwrap_or(name);
+    let stem = stem.strip_suffix(".tar").unwrap_or(stem);
+    if stem.is_empty() { name.to_string() } else { stem.to_string() }
+}
+
+/// Unpack the archive `file` into a new folder next to it, as a task of the
+/// user who dropped it; other files are left alone
+async fn extract(config: &Arc<Config>, db: &DatabaseConnection, change: &Change, file: &Path) -> anyhow::Result<()> {
+    let (kind, entries) = match archive_extract::inspect(file.to_path_buf()).await {
+        Some(listed) => listed?,
+        None => return Ok(()),
+    };
+    let size = entries.iter().filter(|e| !e.dir).map(|e| e.size as i64).sum::<i64>();
+    if let Err(exceeded) = quota::check_quota(db, config, &change.owner, size).await {
+        anyhow::bail!(exceeded.message());
+    }
+    let Some(actor) = db.find_user(&change.actor).await? else {
+        anyhow::bail!("user {} not found", change.actor);
+    };
+
+    let (folder, name) = split(&change.path);
+    let stem = extract_folder_name(name);
+    let service = FileService::new(config, db, &change.owner).on_behalf_of(&actor.username, "");
+    let mut target = None;
+    for n in 0..100 {
+        let candidate = if n == 0 { stem.clone() } else { format!("{}({})", stem, n) };
+        match service.mkdir(&folder, None, &candidate).await {
+            Ok(_) => {
+                target = Some(format!("{}/{}", folder.trim_end_matches('/'), candidate));
+                break;
+            }
+            Err(FileError::AlreadyExists) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let Some(target) = target else {
+        anyhow::bail!("no free folder name for {}", stem);
+    };
+
+    TASK_MANAGER.create_extract_task(
+        actor.id,
+        &actor.username,
+        change.path.clone(),
+        target.clone(),
+        file.to_path_buf(),
+        kind,
+        size,
+        TaskDir { owner: change.owner.clone(), root: get_user_path(config, &change.owner), path: target },
+        ConflictPolicy::Rename,
+        artifact::user_dir(config, ArtifactKind::Archive, &actor.username),
+        config.clone(),
+        db.clone(),
+    );
+    Ok(())
+}
+
+/// Run the configured command `name` on `file`
+async fn command(config: &Config, name: &str, file: &Path, landed: &Landed) -> anyhow::Result<()> {
+    let Some(program) = config.hooks.commands.get(name) else {
+        anyhow::bail!("command {} not configured", name);
+    };
+    let mut command = tokio::process::Command::new(program);
+    command
+        .arg(file)
+        .env("DATADISK_PATH", &landed.path)
+        .env("DATADISK_ACTOR", &landed.actor)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .kill_on_drop(true);
+    let timeout = Duration::from_secs(config.hooks.command_timeout_secs);
+    let status = match tokio::time::timeout(timeout, command.status()).await {
+        Ok(status) => status?,
+        Err(_) => anyhow::bail!("command {} timed out", name),
+    };
+    if !status.success() {
+        anyhow::bail!("command {} exited with {}", name, status);
+    }
+    Ok(())
+}
+
+/// Start running the hooks of folders files land in
+pub fn start(state: AppState) {
+    let mut rx = watch::subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let change = match rx.recv().await {
+                Ok(change) => change,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Folder hooks lagged, {} changes dropped", n);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let Some(db) = state.get_db().await else {
+                continue;
+            };
+            // Slow webhooks and commands don't hold up the next changes
+            let config = state.config.clone();
+            tokio::spawn(async move {
+                if let Err(e) = run(&config, &db, &change).await {
+                    tracing::error!("Failed to run hooks for {}: {}", change.path, e);
+                }
+            });
+        }
+    });
+}
+
+/// Add hook request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct HookRequest {
+    /// Folder, in the file API
+    pub path: String,
+    /// webhook, notify, extract or command
+    pub action: String,
+    /// URL of a webhook, group to notify or name of a configured command;
+    /// not used by extract
+    #[serde(default)]
+    pub target: String,
+}
+
+/// Remove hook request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RemoveHookRequest {
+    pub id: i64,
+}
+
+/// Query parameters for listing hooks
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HookListQuery {
+    /// A path in the tree whose hooks are listed; the user's own if not set
+    pub path: Option<String>,
+}
+
+/// A folder hook
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HookResponse {
+    pub id: i64,
+    /// Folder, in the file API of the hook's creator
+    pub path: String,
+    pub action: String,
+    pub target: String,
+    #[serde(rename = "createdBy")]
+    pub created_by: String,
+    #[serde(rename = "createTime")]
+    pub create_time: i64,
+}
+
+impl From<folder_hook::Model> for HookResponse {
+    fn from(m: folder_hook::Model) -> Self {
+        Self {
+            id: m.id,
+            path: m.shown_path,
+            action: m.action,
+            target: m.target,
+            created_by: m.created_by,
+            create_time: m.create_time,
+        }
+    }
+}
+
+/// Check the target of a new hook, returning the message to answer if it's
+/// not usable
+async fn check_target(
+    config: &Config,
+    db: &DatabaseConnection,
+    user: &CurrentUser,
+    action: Action,
+    target: &str,
+) -> Result<Result<(), &'static str>, DbErr> {
+    Ok(match action {
+        Action::Webhook => match reqwest::Url::parse(target) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+            _ => Err("无效的 Webhook 地址"),
+        },
+        Action::Notify => {
+            let group = group::Entity::find().filter(group::Column::Name.eq(target)).one(db).await?;
+            let member = match group {
+                Some(group) => group_user::Entity::find()
+                    .filter(group_user::Column::GroupId.eq(group.id))
+                    .filter(group_user::Column::UserId.eq(user.id))
+                    .one(db)
+                    .await?
+                    .is_some(),
+                None => false,
+            };
+            if member { Ok(()) } else { Err("只能通知所在的群组") }
+        }
+        Action::Extract => Ok(()),
+        Action::Command => {
+            if config.hooks.commands.contains_key(target) { Ok(()) } else { Err("命令未配置") }
+        }
+    })
+}
+
+/// POST /api/file/hook/add - Run an action for the files landing in a folder
+#[utoipa::path(
+    post,
+    path = "/api/file/hook/add",
+    tag = "file",
+    request_body = HookRequest,
+    responses((status = 200, body = ApiResponse<HookResponse>)),
+)]
+pub async fn add_hook(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<HookRequest>,
+) -> Json<ApiResponse<HookResponse>> {
+    let Some(action) = Action::parse(&req.action) else {
+        return Json(ApiResponse::error(400, "不支持的动作"));
+    };
+    let location = match locate_for_write(&state, &db, &current_user, &req.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    let is_dir = resolve_in_user_root(&state.config, &location.owner, &location.path).is_some_and(|full| full.is_dir());
+    if !is_dir {
+        return Json(ApiResponse::error(404, "文件夹不存在"));
+    }
+    let target = if action == Action::Extract { String::new() } else { req.target.trim().to_string() };
+    match check_target(&state.config, &db, &current_user, action, &target).await {
+        Ok(Ok(())) => {}
+        Ok(Err(message)) => return Json(ApiResponse::error(400, message)),
+        Err(e) => {
+            tracing::error!("Failed to check hook target: {}", e);
+            return Json(ApiResponse::error(500, "添加钩子失败"));
+        }
+    }
+
+    let hook = folder_hook::ActiveModel {
+        owner: Set(location.owner.clone()),
+        path: Set(normalize(&location.path)),
+        shown_path: Set(normalize(&req.path)),
+        action: Set(action.code().to_string()),
+        target: Set(target),
+        created_by: Set(current_user.username.clone()),
+        create_time: Set(chrono::Utc::now().timestamp()),
+        ..Default::default()
+    }
+    .insert(&*db)
+    .await;
+    match hook {
+        Ok(hook) => {
+            let detail = format!("{} ({})", hook.shown_path, hook.action);
+            log_operation(&current_user.username, OpType::AddHook, &detail, OP_SUCCESS, None);
+            Json(ApiResponse::success(hook.into()))
+        }
+        Err(e) => {
+            tracing::error!("Failed to add hook to {}: {}", req.path, e);
+            Json(ApiResponse::error(500, "添加钩子失败"))
+        }
+    }
+}
+
+/// POST /api/file/hook/remove - Remove a hook
+///
+/// Whoever may change the folder may remove its hooks.
+#[utoipa::path(
+    post,
+    path = "/api/file/hook/remove",
+    tag = "file",
+    request_body = RemoveHookRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
+pub async fn remove_hook(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<RemoveHookRequest>,
+) -> Json<ApiResponse<()>> {
+    let hook = match folder_hook::Entity::find_by_id(req.id).one(&*db).await {
+        Ok(Some(hook)) => hook,
+        Ok(None) => return Json(ApiResponse::error(404, "钩子不存在")),
+        Err(e) => {
+            tracing::error!("Failed to look up hook {}: {}", req.id, e);
+            return Json(ApiResponse::error(500, "删除钩子失败"));
+        }
+    };
+    // The folder as this user sees it must be the hook's
+    let location = match locate_for_write(&state, &db, &current_user, &hook.shown_path).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    if location.owner != hook.owner || normalize(&location.path) != hook.path {
+        return Json(ApiResponse::error(404, "钩子不存在"));
+    }
+
+    match folder_hook::Entity::delete_by_id(hook.id).exec(&*db).await {
+        Ok(_) => {
+            let detail = format!("{} ({})", hook.shown_path, hook.action);
+            log_operation(&current_user.username, OpType::RemoveHook, &detail, OP_SUCCESS, None);
+            Json(ApiResponse::success_msg("已删除钩子"))
+        }
+        Err(e) => {
+            tracing::error!("Failed to remove hook {}: {}", hook.id, e);
+            Json(ApiResponse::error(500, "删除钩子失败"))
+        }
+    }
+}
+
+/// GET /api/file/hook/list - Hooks of the folders of a tree
+#[utoipa::path(
+    get,
+    path = "/api/file/hook/list",
+    tag = "file",
+    params(HookListQuery),
+    responses((status = 200, body = ApiResponse<Vec<HookResponse>>)),
+)]
+pub async fn list_hooks(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<HookListQuery>,
+) -> Json<ApiResponse<Vec<HookResponse>>> {
+    let location = match locate(&state, &db, &current_user, query.path.as_deref().unwrap_or("/")).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    let hooks = folder_hook::Entity::find()
+        .filter(folder_hook::Column::Owner.eq(&location.owner))
+        .order_by_asc(folder_hook::Column::Path)
+        .order_by_asc(folder_hook::Column::Id)
+        .all(&*db)
+        .await;
+    match hooks {
+        Ok(hooks) => Json(ApiResponse::success(
+            hooks
+                .into_iter()
+                .map(|hook| HookResponse {
+                    // As this user sees the folder
+                    path: format!("{}{}", location.prefix, hook.path),
+                    ..hook.into()
+                })
+                .collect(),
+        )),
+        Err(e) => {
+            tracing::error!("Failed to list hooks: {}", e);
+            Json(ApiResponse::error(500, "查询钩子失败"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::watch::Client;
+    use crate::testing::{TestApp, TestEnv};
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn change(owner: &str, path: &str, kind: ChangeKind) -> Change {
+        Change {
+            owner: owner.to_string(),
+            path: normalize(path),
+            actor: owner.to_string(),
+            kind,
+            client: Client::Web,
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        assert!(ignored(".DS_Store"));
+        assert!(ignored("~$report.docx"));
+        assert!(!ignored("report.docx"));
+        assert_eq!(extract_folder_name("pack.zip"), "pack");
+        assert_eq!(extract_folder_name("pack.tar.gz"), "pack");
+        assert_eq!(extract_folder_name(".zip"), ".zip");
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let mut env = TestEnv::new().await;
+        std::fs::create_dir_all(&env.dir).unwrap();
+        let out = env.dir.join("out");
+        let script = env.dir.join("record.sh");
+        let body = format!("#!/bin/sh\necho \"$1 $DATADISK_PATH\" >> {}\n", out.display());
+        std::fs::write(&script, body).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        env.config.hooks.commands.insert("record".to_string(), script.display().to_string());
+
+        let service = FileService::new(&env.config, &env.db, "alice");
+        service.mkdir("/", None, "drop").await.unwrap();
+        service.mkdir("/drop", None, "sub").await.unwrap();
+        let alice = env.user("alice", &[crate::permission::perm::FILE]);
+        let add = |action: &str, target: &str| {
+            let req = HookRequest { path: "/drop".to_string(), action: action.to_string(), target: target.to_string() };
+            add_hook(State(env.state()), Extension(env.db_conn()), Extension(alice.clone()), Json(req))
+        };
+        assert!(add("command", "record").await.code);
+        assert!(!add("command", "missing").await.code);
+        assert!(!add("notify", "nobody").await.code);
+        assert!(!add("webhook", "ftp://example.com/").await.code);
+        assert!(!add("delete", "").await.code);
+
+        let root = get_user_path(&env.config, "alice");
+        std::fs::write(root.join("drop/a.txt"), b"a").unwrap();
+        std::fs::write(root.join("drop/.hidden"), b"h").unwrap();
+        std::fs::write(root.join("drop/sub/b.txt"), b"b").unwrap();
+        let config = Arc::new(env.config.clone());
+        let run = |path: &str, kind| {
+            let change = change("alice", path, kind);
+            let (config, db) = (config.clone(), env.db.clone());
+            async move { run(&config, &db, &change).await.unwrap() }
+        };
+        assert_eq!(run("/drop/a.txt", ChangeKind::Created).await, 1);
+        let recorded = std::fs::read_to_string(&out).unwrap();
+        assert_eq!(recorded, format!("{} /drop/a.txt\n", root.join("drop/a.txt").display()));
+
+        // Only files right in the folder, and only when they land there
+        assert_eq!(run("/drop/.hidden", ChangeKind::Created).await, 0);
+        assert_eq!(run("/drop/sub/b.txt", ChangeKind::Created).await, 0);
+        assert_eq!(run("/drop/sub", ChangeKind::Created).await, 0);
+        assert_eq!(run("/drop/a.txt", ChangeKind::Deleted).await, 0);
+        assert_eq!(run("/drop/gone.txt", ChangeKind::Moved).await, 0);
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), recorded);
+        env.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_extract() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let body = serde_json::json!({ "parentPath": "/", "name": "drop" });
+        assert!(admin.post_json("/api/file/mkdir", &body).await.status().is_success());
+        let body = serde_json::json!({ "path": "/drop", "action": "extract" });
+        let res: serde_json::Value = admin.post_json("/api/file/hook/add", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        let list: serde_json::Value = admin.get("/api/file/hook/list").await.json().await.unwrap();
+        assert_eq!(list["data"][0]["path"], "/drop");
+        assert_eq!(list["data"][0]["action"], "extract");
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("a.txt", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"hello").unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        assert!(admin.upload("/drop", "pack.zip", &zip).await.status().is_success());
+        assert!(admin.upload("/drop", "notes.txt", b"plain").await.status().is_success());
+
+        // The server started without the subscriber, run the hooks here
+        let run = |path: &str| {
+            let change = change("admin", path, ChangeKind::Created);
+            let (config, db) = (app.state.config.clone(), app.env.db.clone());
+            async move { run(&config, &db, &change).await.unwrap() }
+        };
+        assert_eq!(run("/drop/pack.zip").await, 1);
+        assert_eq!(run("/drop/notes.txt").await, 1);
+        let root = get_user_path(&app.env.config, "admin");
+        let extracted = root.join("drop/pack/a.txt");
+        for _ in 0..100 {
+            if extracted.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(std::fs::read_to_string(&extracted).unwrap(), "hello");
+        // Other files are left alone
+        assert_eq!(std::fs::read_dir(root.join("drop")).unwrap().count(), 3);
+
+        let id = list["data"][0]["id"].clone();
+        let res: serde_json::Value = admin.post_json("/api/file/hook/remove", &serde_json::json!({ "id": id })).await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        assert_eq!(run("/drop/pack.zip").await, 0);
+        app.close().await;
+    }
+}
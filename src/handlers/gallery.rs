@@ -0,0 +1,472 @@
+//! Photo gallery
+//!
+//! `GET /api/file/gallery?path=` lists the images of a directory with what a
+//! photo view needs to lay them out: their dimensions, and the capture date,
+//! camera and orientation from their EXIF data. Only the headers are read, a
+//! JPEG up to its first scan ([`scan_jpeg`]), so a directory of originals is
+//! listed without downloading or decoding them. Images are sorted by capture
+//! time, or by modification time for those without one.
+//!
+//! Files in cold storage aren't recalled for this, they are listed without
+//! dimensions or EXIF data.
+
+use axum::{
+    extract::{Query, State},
+    response::Json,
+    Extension,
+};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use utoipa::ToSchema;
+
+use crate::handlers::file::{locate, PathQuery};
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::ApiResponse;
+use crate::service::{FileError, FileService};
+use crate::state::AppState;
+
+/// Largest EXIF block read, the APP1 segment holding it can't be larger
+const MAX_EXIF_LEN: usize = 64 * 1024;
+
+/// EXIF tags read
+const TAG_MAKE: u16 = 0x010F;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+/// Metadata of an image, from its header and EXIF data
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImageMeta {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub exif: Exif,
+}
+
+/// The EXIF fields shown in the gallery
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Exif {
+    /// Capture time as `YYYY-MM-DDTHH:MM:SS`, in the camera's local time
+    pub taken_at: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// 1 to 8, as defined by EXIF
+    pub orientation: Option<u16>,
+}
+
+/// Image entry of a gallery listing
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GalleryItem {
+    pub name: String,
+    pub path: String,
+    pub size: i64,
+    pub lastmod: String,
+    pub mime: String,
+    /// Stored size in pixels, swapped on display for orientations 5 to 8
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Capture time from EXIF, without a time zone
+    #[serde(rename = "takenAt", skip_serializing_if = "Option::is_none")]
+    pub taken_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub make: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<u16>,
+    /// `cold` for files moved to cold storage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tier: Option<String>,
+}
+
+/// Byte order of a TIFF block
+#[derive(Clone, Copy)]
+enum Order {
+    Little,
+    Big,
+}
+
+impl Order {
+    fn u16(self, data: &[u8], at: usize) -> Option<u16> {
+        let bytes: [u8; 2] = data.get(at..at + 2)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u16::from_le_bytes(bytes),
+            Self::Big => u16::from_be_bytes(bytes),
+        })
+    }
+
+    fn u32(self, data: &[u8], at: usize) -> Option<u32> {
+        let bytes: [u8; 4] = data.get(at..at + 4)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u32::from_le_bytes(bytes),
+            Self::Big => u32::from_be_bytes(bytes),
+        })
+    }
+}
+
+/// Parse the EXIF fields of a TIFF block, as found in JPEG APP1 segments
+/// after `Exif\0\0` and returned by the decoders of the other formats
+///
+/// Malformed blocks give the fields read before the error.
+pub fn parse_exif(data: &[u8]) -> Exif {
+    let mut exif = Exif::default();
+    let order = match data.get(..4) {
+        Some(b"II*\0") => Order::Little,
+        Some(b"MM\0*") => Order::Big,
+        _ => return exif,
+    };
+    let Some(ifd0) = order.u32(data, 4) else {
+        return exif;
+    };
+    let mut date_time = None;
+    let mut exif_ifd = None;
+    for (tag, at) in entries(data, order, ifd0 as usize) {
+        match tag {
+            TAG_MAKE => exif.make = ascii(data, order, at),
+            TAG_MODEL => exif.model = ascii(data, order, at),
+            TAG_ORIENTATION => exif.orientation = order.u16(data, at + 8).filter(|o| (1..=8).contains(o)),
+            TAG_DATE_TIME => date_time = ascii(data, order, at),
+            TAG_EXIF_IFD => exif_ifd = order.u32(data, at + 8),
+            _ => {}
+        }
+    }
+    let original = exif_ifd.and_then(|offset| {
+        entries(data, order, offset as usize)
+            .find(|&(tag, _)| tag == TAG_DATE_TIME_ORIGINAL)
+            .and_then(|(_, at)| ascii(data, order, at))
+    });
+    exif.taken_at = original.or(date_time).and_then(|value| parse_date(&value));
+    exif
+}
+
+/// Tags of the IFD at `offset` with the offsets of their entries
+fn entries(data: &[u8], order: Order, offset: usize) -> impl Iterator<Item = (u16, usize)> + '_ {
+    let count = order.u16(data, offset).unwrap_or(0) as usize;
+    (0..count).map_while(move |i| {
+        let at = offset + 2 + i * 12;
+        Some((order.u16(data, at)?, at)).filter(|_| at + 12 <= data.len())
+    })
+}
+
+/// Value of the ASCII entry at `at`, inline if it fits in 4 bytes
+fn ascii(data: &[u8], order: Order, at: usize) -> Option<String> {
+    if order.u16(data, at + 2)? != 2 {
+        return None;
+    }
+    let count = order.u32(data, at + 4)? as usize;
+    let start = if count <= 4 { at + 8 } else { order.u32(data, at + 8)? as usize };
+    let bytes = data.get(start..start.checked_add(count)?)?;
+    let bytes = bytes.split(|&b| b == 0).next().unwrap_or_default();
+    let value = String::from_utf8_lossy(bytes).trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+/// `YYYY-MM-DDTHH:MM:SS` from the EXIF `YYYY:MM:DD HH:MM:SS`
+fn parse_date(value: &str) -> Option<String> {
+    chrono::NaiveDateTime::parse_from_str(value, "%Y:%m:%d %H:%M:%S")
+        .ok()
+        .map(|t| t.format("%Y-%m-%dT%H:%M:%S").to_string())
+}
+
+/// Dimensions and EXIF data of a JPEG, read from its segments up to the
+/// first scan rather than through a decoder that reads the whole file
+fn scan_jpeg(reader: &mut impl Read) -> std::io::Result<ImageMeta> {
+    let mut marker = [0u8; 2];
+    reader.read_exact(&mut marker)?;
+    if marker != [0xFF, 0xD8] {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a JPEG"));
+    }
+    let (mut size, mut exif) = (None, None);
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] != 0xFF {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "bad JPEG marker"));
+        }
+        // Any number of fill bytes may come before the marker
+        let code = loop {
+            reader.read_exact(&mut byte)?;
+            if byte[0] != 0xFF {
+                break byte[0];
+            }
+        };
+        match code {
+            // Markers without a length
+            0x01 | 0xD0..=0xD7 => continue,
+            // Start of scan or end of image
+            0xDA | 0xD9 => break,
+            _ => {}
+        }
+        let mut len = [0u8; 2];
+        reader.read_exact(&mut len)?;
+        let len = (u16::from_be_bytes(len) as usize).saturating_sub(2);
+        let mut segment = vec![0u8; len];
+        reader.read_exact(&mut segment)?;
+        match code {
+            // Start of frame, other than DHT, JPG and DAC
+            0xC0..=0xCF if !matches!(code, 0xC4 | 0xC8 | 0xCC) && segment.len() >= 5 => {
+                let height = u16::from_be_bytes([segment[1], segment[2]]) as u32;
+                let width = u16::from_be_bytes([segment[3], segment[4]]) as u32;
+                size = Some((width, height));
+            }
+            0xE1 if exif.is_none() && segment.starts_with(b"Exif\0\0") => {
+                segment.drain(..6);
+                segment.truncate(MAX_EXIF_LEN);
+                exif = Some(segment);
+            }
+            _ => {}
+        }
+        if size.is_some() && exif.is_some() {
+            break;
+        }
+    }
+    Ok(meta(size, exif))
+}
+
+fn meta(size: Option<(u32, u32)>, exif: Option<Vec<u8>>) -> ImageMeta {
+    ImageMeta {
+        width: size.map(|(w, _)| w),
+        height: size.map(|(_, h)| h),
+        exif: exif.map(|data| parse_exif(&data)).unwrap_or_default(),
+    }
+}
+
+/// Read the dimensions and EXIF data of the image at `path`
+pub fn read_image_meta(path: &Path) -> anyhow::Result<ImageMeta> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head)?;
+    if head == [0xFF, 0xD8] {
+        return Ok(scan_jpeg(&mut (&head[..]).chain(reader))?);
+    }
+    use image::ImageDecoder;
+    let mut decoder = image::ImageReader::open(path)?.with_guessed_format()?.into_decoder()?;
+    Ok(meta(Some(decoder.dimensions()), decoder.exif_metadata().ok().flatten()))
+}
+
+/// GET /api/file/gallery - Images of a directory with their dimensions and EXIF data
+#[utoipa::path(
+    get,
+    path = "/api/file/gallery",
+    tag = "file",
+    params(PathQuery),
+    responses((status = 200, body = ApiResponse<Vec<GalleryItem>>)),
+)]
+pub async fn gallery(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<PathQuery>,
+) -> Json<ApiResponse<Vec<GalleryItem>>> {
+    let location = match locate(&state, &db, &current_user, &query.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    let service = FileService::new(&state.config, &db, &location.owner)
+        .on_behalf_of(&current_user.username, &location.prefix);
+    let items = match service.list(&location.path).await {
+        Ok(items) => items,
+        Err(FileError::NotFound) => return Json(ApiResponse::error(404, "目录不存在")),
+        Err(FileError::InvalidPath) => return Json(ApiResponse::error(400, "invalid path")),
+        Err(e) => {
+            tracing::error!("Failed to list {} of {}: {}", location.path, location.owner, e);
+            return Json(ApiResponse::error(500, "failed to read directory"));
+        }
+    };
+
+    let images: Vec<_> = items
+        .into_iter()
+        .filter(|item| item.item_type == "file" && item.mime.starts_with("image/"))
+        .map(|item| {
+            let path = format!("{}/{}", location.path.trim_end_matches('/'), item.basename);
+            // Cold files are empty stubs, their content isn't recalled for this
+            let file = service.resolve(&path).ok().filter(|_| item.tier.is_none());
+            (item, file)
+        })
+        .collect();
+    let result = tokio::task::spawn_blocking(move || {
+        images
+            .into_iter()
+            .map(|(item, file)| {
+                let meta = file
+                    .and_then(|file| {
+                        read_image_meta(&file)
+                            .inspect_err(|e| tracing::debug!("Failed to read image metadata of {}: {}", item.filename, e))
+                            .ok()
+                    })
+                    .unwrap_or_default();
+                GalleryItem {
+                    name: item.basename,
+                    path: item.filename,
+                    size: item.size,
+                    lastmod: item.lastmod,
+                    mime: item.mime,
+                    width: meta.width,
+                    height: meta.height,
+                    taken_at: meta.exif.taken_at,
+                    make: meta.exif.make,
+                    model: meta.exif.model,
+                    orientation: meta.exif.orientation,
+                    tier: item.tier,
+                }
+            })
+            .collect::<Vec<_>>()
+    })
+    .await;
+    match result {
+        Ok(mut gallery) => {
+            // `lastmod` has the same format with a trailing `Z`, close enough to order by
+            gallery.sort_by(|a, b| {
+                let time = |item: &GalleryItem| item.taken_at.clone().unwrap_or_else(|| item.lastmod.clone());
+                time(a).cmp(&time(b)).then_with(|| a.name.cmp(&b.name))
+            });
+            Json(ApiResponse::success(gallery))
+        }
+        Err(e) => {
+            tracing::error!("Gallery worker failed: {}", e);
+            Json(ApiResponse::error(500, "internal error"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    /// Big-endian TIFF block with Make, Model, Orientation and an EXIF IFD
+    /// holding DateTimeOriginal
+    fn tiff(model: &str, taken: &str, orientation: u16) -> Vec<u8> {
+        let mut data = b"MM\0*".to_vec();
+        data.extend(8u32.to_be_bytes());
+        // IFD0 with 4 entries, then the IFD link, then the EXIF IFD with one entry
+        let ifd0_end = 8 + 2 + 4 * 12 + 4;
+        let exif_end = ifd0_end + 2 + 12 + 4;
+        let make = b"Kuun\0";
+        let model = [model.as_bytes(), b"\0"].concat();
+        let taken = [taken.as_bytes(), b"\0"].concat();
+        let make_at = exif_end;
+        let model_at = make_at + make.len();
+        let taken_at = model_at + model.len();
+        let entry = |data: &mut Vec<u8>, tag: u16, kind: u16, count: usize, value: u32| {
+            data.extend(tag.to_be_bytes());
+            data.extend(kind.to_be_bytes());
+            data.extend((count as u32).to_be_bytes());
+            data.extend(value.to_be_bytes());
+        };
+        data.extend(4u16.to_be_bytes());
+        entry(&mut data, TAG_MAKE, 2, make.len(), make_at as u32);
+        entry(&mut data, TAG_MODEL, 2, model.len(), model_at as u32);
+        entry(&mut data, TAG_ORIENTATION, 3, 1, (orientation as u32) << 16);
+        entry(&mut data, TAG_EXIF_IFD, 4, 1, ifd0_end as u32);
+        data.extend(0u32.to_be_bytes());
+        data.extend(1u16.to_be_bytes());
+        entry(&mut data, TAG_DATE_TIME_ORIGINAL, 2, taken.len(), taken_at as u32);
+        data.extend(0u32.to_be_bytes());
+        data.extend(make);
+        data.extend(&model);
+        data.extend(&taken);
+        data
+    }
+
+    /// A 12x8 JPEG with `exif` in an APP1 segment after the JFIF header
+    fn jpeg(exif: Option<&[u8]>) -> Vec<u8> {
+        let mut out = Vec::new();
+        let img = image::RgbImage::from_pixel(12, 8, image::Rgb([200, 80, 40]));
+        image::codecs::jpeg::JpegEncoder::new(&mut out).encode_image(&img).unwrap();
+        if let Some(exif) = exif {
+            let mut segment = vec![0xFF, 0xE1];
+            segment.extend(((exif.len() + 8) as u16).to_be_bytes());
+            segment.extend(b"Exif\0\0");
+            segment.extend(exif);
+            // After SOI and the APP0 that follows it
+            let app0_end = 4 + u16::from_be_bytes([out[4], out[5]]) as usize;
+            out.splice(app0_end..app0_end, segment);
+        }
+        out
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(width, height).write_to(&mut out, image::ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn test_parse_exif() {
+        let exif = parse_exif(&tiff("X100V", "2024:05:01 09:30:00", 6));
+        assert_eq!(exif.make.as_deref(), Some("Kuun"));
+        assert_eq!(exif.model.as_deref(), Some("X100V"));
+        assert_eq!(exif.orientation, Some(6));
+        assert_eq!(exif.taken_at.as_deref(), Some("2024-05-01T09:30:00"));
+
+        assert_eq!(parse_exif(&tiff("X", "0000:00:00 00:00:00", 9)).taken_at, None);
+        assert_eq!(parse_exif(&tiff("X", "0000:00:00 00:00:00", 9)).orientation, None);
+        assert_eq!(parse_exif(b"not a tiff"), Exif::default());
+        // Truncated blocks keep what was read
+        let data = tiff("X100V", "2024:05:01 09:30:00", 6);
+        assert_eq!(parse_exif(&data[..40]).orientation, None);
+        assert_eq!(parse_exif(&data[..data.len() - 4]).model.as_deref(), Some("X100V"));
+    }
+
+    #[test]
+    fn test_read_image_meta() {
+        let dir = std::env::temp_dir().join(format!("gallery-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.jpg");
+        std::fs::write(&path, jpeg(Some(&tiff("X100V", "2024:05:01 09:30:00", 1)))).unwrap();
+        let meta = read_image_meta(&path).unwrap();
+        assert_eq!((meta.width, meta.height), (Some(12), Some(8)));
+        assert_eq!(meta.exif.model.as_deref(), Some("X100V"));
+
+        std::fs::write(&path, jpeg(None)).unwrap();
+        let meta = read_image_meta(&path).unwrap();
+        assert_eq!((meta.width, meta.height), (Some(12), Some(8)));
+        assert_eq!(meta.exif, Exif::default());
+
+        let path = dir.join("b.png");
+        std::fs::write(&path, png(5, 3)).unwrap();
+        assert_eq!(read_image_meta(&path).unwrap().width, Some(5));
+        std::fs::write(&path, b"").unwrap();
+        assert!(read_image_meta(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_gallery() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        admin.post_json("/api/file/mkdir", &serde_json::json!({ "parentPath": "/", "name": "photos" })).await;
+        let later = jpeg(Some(&tiff("X100V", "2024:05:01 09:30:00", 6)));
+        let earlier = jpeg(Some(&tiff("GR III", "2023:12:24 18:00:00", 1)));
+        assert!(admin.upload("/photos", "a.jpg", &later).await.status().is_success());
+        assert!(admin.upload("/photos", "b.jpg", &earlier).await.status().is_success());
+        assert!(admin.upload("/photos", "c.png", &png(5, 3)).await.status().is_success());
+        assert!(admin.upload("/photos", "notes.txt", b"trip").await.status().is_success());
+
+        let res: serde_json::Value = admin.get("/api/file/gallery?path=/photos").await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        let items = res["data"].as_array().unwrap();
+        let names: Vec<_> = items.iter().map(|item| item["name"].as_str().unwrap()).collect();
+        // Photos by capture time, the PNG without one by its upload time
+        assert_eq!(names, ["b.jpg", "a.jpg", "c.png"]);
+        assert_eq!(items[0]["takenAt"], "2023-12-24T18:00:00");
+        assert_eq!(items[0]["model"], "GR III");
+        assert_eq!(items[1]["orientation"], 6);
+        assert_eq!(items[1]["width"], 12);
+        assert_eq!(items[1]["path"], "/photos/a.jpg");
+        assert_eq!(items[2]["height"], 3);
+        assert!(items[2].get("takenAt").is_none());
+
+        let res: serde_json::Value = admin.get("/api/file/gallery?path=/missing").await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        app.close().await;
+    }
+}
@@ -3,7 +3,7 @@
 //! Implements group CRUD and member management operations
 
 use axum::{
-    extract::Query,
+    extract::{Query, State},
     response::Json,
     Extension,
 };
@@ -13,18 +13,17 @@ use sea_orm::{
 use serde::{Deserialize, Serialize};
 
 use crate::entity::{group, group_user, user};
+use crate::entity::op_log::OpType;
+use crate::handlers::abuse;
 use crate::handlers::audit::service::log_operation;
+use crate::handlers::file::get_user_path;
+use crate::handlers::group_space;
 use crate::middleware::auth::CurrentUser;
 use crate::middleware::DbConn;
 use crate::routes::ApiResponse;
+use crate::state::AppState;
 
 // Operation types (matching Go version)
-const OP_CREATE_GROUP: &str = "添加群组";
-const OP_DELETE_GROUP: &str = "删除群组";
-const OP_QUERY_GROUP: &str = "查询群组";
-const OP_ADD_GROUP_USER: &str = "添加群组用户";
-const OP_DEL_GROUP_USER: &str = "删除群组用户";
-const OP_QUERY_GROUP_USER: &str = "查询群组用户";
 const OP_SUCCESS: &str = "成功";
 
 /// Add group request
@@ -39,6 +38,9 @@ pub struct GroupResponse {
     pub id: i64,
     pub name: String,
     pub owner: bool,
+    /// Whether members other than the owners may change the group space
+    #[serde(rename = "memberWrite")]
+    pub member_write: bool,
 }
 
 /// Group user response
@@ -58,6 +60,14 @@ pub struct IdQuery {
     pub id: i64,
 }
 
+/// Set member write request
+#[derive(Debug, Deserialize)]
+pub struct SetMemberWriteRequest {
+    pub id: i64,
+    #[serde(rename = "memberWrite")]
+    pub member_write: bool,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GroupIdQuery {
     #[serde(rename = "groupId")]
@@ -91,6 +101,7 @@ pub async fn add_group(
             // Create group
             let new_group = group::ActiveModel {
                 name: Set(req.name.clone()),
+                member_write: Set(true),
                 ..Default::default()
             };
             let group = new_group.insert(txn).await?;
@@ -112,11 +123,12 @@ pub async fn add_group(
         Ok(group) => {
             // Log operation
             let op_desc = format!("群组名称: {}", group.name);
-            log_operation(&current_user.username, OP_CREATE_GROUP, &op_desc, OP_SUCCESS, None);
+            log_operation(&current_user.username, OpType::CreateGroup, &op_desc, OP_SUCCESS, None);
             Json(ApiResponse::success(Some(GroupResponse {
                 id: group.id,
                 name: group.name,
                 owner: true,
+                member_write: group.member_write,
             })))
         }
         Err(e) => {
@@ -128,6 +140,7 @@ pub async fn add_group(
 
 /// POST /api/group/delete
 pub async fn delete_group(
+    State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Query(query): Query<IdQuery>,
@@ -146,6 +159,12 @@ pub async fn delete_group(
         }
     };
 
+    // The files of the group space would be left without an owner
+    let space = get_user_path(&state.config, &group_space::owner(query.id));
+    if std::fs::read_dir(&space).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Json(ApiResponse::error(400, "群组空间中还有文件，请先清空"));
+    }
+
     // Delete group and members in transaction
     let result = (&*db).transaction::<_, (), sea_orm::DbErr>(|txn| {
         Box::pin(async move {
@@ -168,7 +187,7 @@ pub async fn delete_group(
         Ok(_) => {
             // Log operation
             let op_desc = format!("群组名称: {}", group_info.name);
-            log_operation(&current_user.username, OP_DELETE_GROUP, &op_desc, OP_SUCCESS, None);
+            log_operation(&current_user.username, OpType::DeleteGroup, &op_desc, OP_SUCCESS, None);
             Json(ApiResponse::success_msg("success"))
         }
         Err(e) => {
@@ -208,15 +227,67 @@ pub async fn get_groups(
                 id: g.id,
                 name: g.name,
                 owner: gu.owner,
+                member_write: g.member_write,
             });
         }
     }
 
     // Log operation
-    log_operation(&current_user.username, OP_QUERY_GROUP, "", OP_SUCCESS, None);
+    log_operation(&current_user.username, OpType::QueryGroup, "", OP_SUCCESS, None);
     Json(ApiResponse::success(groups))
 }
 
+/// POST /api/group/setMemberWrite - Let members other than the owners change
+/// the group space, or not
+pub async fn set_member_write(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<SetMemberWriteRequest>,
+) -> Json<ApiResponse<()>> {
+    let group_info = match group::Entity::find_by_id(req.id).one(&*db).await {
+        Ok(Some(g)) => g,
+        Ok(None) => return Json(ApiResponse::error(400, "未找到该群组")),
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    let is_owner = group_user::Entity::find()
+        .filter(group_user::Column::GroupId.eq(req.id))
+        .filter(group_user::Column::UserId.eq(current_user.id))
+        .filter(group_user::Column::Owner.eq(true))
+        .one(&*db)
+        .await;
+    match is_owner {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            abuse::record_denied(&current_user.username);
+            return Json(ApiResponse::error(403, "只有群组所有者可以修改"));
+        }
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    }
+
+    let name = group_info.name.clone();
+    let mut active: group::ActiveModel = group_info.into();
+    active.member_write = Set(req.member_write);
+    if let Err(e) = active.update(&*db).await {
+        tracing::error!("Failed to update group: {}", e);
+        return Json(ApiResponse::error(500, e.to_string()));
+    }
+
+    let op_desc = format!(
+        "群组名称: {}, 成员{}",
+        name,
+        if req.member_write { "可写" } else { "只读" }
+    );
+    log_operation(&current_user.username, OpType::SetMemberWrite, &op_desc, OP_SUCCESS, None);
+    Json(ApiResponse::success_msg("success"))
+}
+
 /// POST /api/group/addUsers
 pub async fn add_users_to_group(
     Extension(db): Extension<DbConn>,
@@ -281,7 +352,7 @@ pub async fn add_users_to_group(
         Ok(_) => {
             // Log operation
             let op_desc = format!("群组名称: {}", group_info.name);
-            log_operation(&current_user.username, OP_ADD_GROUP_USER, &op_desc, OP_SUCCESS, None);
+            log_operation(&current_user.username, OpType::AddGroupUser, &op_desc, OP_SUCCESS, None);
             Json(ApiResponse::success_msg("success"))
         }
         Err(e) => {
@@ -332,7 +403,7 @@ pub async fn delete_users_from_group(
         Ok(_) => {
             // Log operation
             let op_desc = format!("群组名称: {}", group_info.name);
-            log_operation(&current_user.username, OP_DEL_GROUP_USER, &op_desc, OP_SUCCESS, None);
+            log_operation(&current_user.username, OpType::DeleteGroupUser, &op_desc, OP_SUCCESS, None);
             Json(ApiResponse::success_msg("success"))
         }
         Err(e) => {
@@ -397,6 +468,6 @@ pub async fn get_group_users(
     }
 
     // Log operation
-    log_operation(&current_user.username, OP_QUERY_GROUP_USER, "", OP_SUCCESS, None);
+    log_operation(&current_user.username, OpType::QueryGroupUser, "", OP_SUCCESS, None);
     Json(ApiResponse::success(users))
 }
@@ -0,0 +1,185 @@
+//! Group spaces
+//!
+//! Every group has a shared folder, shown to its members as `/group/<id>` in
+//! the file API next to their own files, so files can be passed around
+//! without downloading and uploading them again. Owners of the group can
+//! always change the files; other members only while the group allows it
+//! (`member_write`).
+//!
+//! Like [department spaces](crate::handlers::dept_space), a space is stored
+//! like the tree of a user named `group:<id>`, and
+//! [`get_user_path`](crate::handlers::file::get_user_path) maps it to the
+//! group's folder.
+
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder};
+
+use crate::entity::{group, group_user};
+use crate::handlers::dept_space::{folder_item, LocateError, Location};
+use crate::middleware::auth::CurrentUser;
+use crate::service::file::DirectoryItem;
+use crate::state::AppState;
+
+/// Folder of the file API holding the spaces
+pub const ROOT: &str = "group";
+
+/// Prefix of the owners of group spaces
+const PREFIX: &str = "group:";
+
+/// Owner of the files of a group space
+pub fn owner(group_id: i64) -> String {
+    format!("{}{}", PREFIX, group_id)
+}
+
+/// Group of a space owner, None for users
+pub fn group_id(owner: &str) -> Option<i64> {
+    owner.strip_prefix(PREFIX)?.parse().ok()
+}
+
+/// Whether `path` is the folder listing the spaces
+pub fn is_space_list(state: &AppState, path: &str) -> bool {
+    state.config.group_space.enabled && path.trim_matches('/') == ROOT
+}
+
+/// Groups whose spaces the user sees, by name
+pub async fn spaces(
+    state: &AppState,
+    db: &DatabaseConnection,
+    user: &CurrentUser,
+) -> Result<Vec<group::Model>, DbErr> {
+    if !state.config.group_space.enabled {
+        return Ok(Vec::new());
+    }
+    let ids: Vec<i64> = group_user::Entity::find()
+        .filter(group_user::Column::UserId.eq(user.id))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|member| member.group_id)
+        .collect();
+    group::Entity::find()
+        .filter(group::Column::Id.is_in(ids))
+        .order_by_asc(group::Column::Name)
+        .all(db)
+        .await
+}
+
+/// Item of the folder holding the spaces, listed in the user's root
+pub fn root_item() -> DirectoryItem {
+    folder_item(ROOT, format!("/{}", ROOT))
+}
+
+/// Listing of the folder holding the spaces
+pub fn space_items(spaces: &[group::Model]) -> Vec<DirectoryItem> {
+    spaces
+        .iter()
+        .map(|group| folder_item(&group.name, format!("/{}/{}", ROOT, group.id)))
+        .collect()
+}
+
+/// Locate a path of the file API below `/group/<id>` for the user
+///
+/// Returns None for paths outside the group spaces.
+pub async fn locate(
+    state: &AppState,
+    db: &DatabaseConnection,
+    user: &CurrentUser,
+    path: &str,
+) -> Result<Option<Location>, LocateError> {
+    if !state.config.group_space.enabled {
+        return Ok(None);
+    }
+    let mut parts = path.trim_start_matches('/').splitn(3, '/');
+    if parts.next() != Some(ROOT) {
+        return Ok(None);
+    }
+    // The listing of the spaces isn't in any tree
+    let id = parts
+        .next()
+        .and_then(|id| id.parse::<i64>().ok())
+        .ok_or(LocateError::NotFound)?;
+    let rest = parts.next().unwrap_or("");
+
+    let group = group::Entity::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(LocateError::NotFound)?;
+    let member = group_user::Entity::find()
+        .filter(group_user::Column::GroupId.eq(id))
+        .filter(group_user::Column::UserId.eq(user.id))
+        .one(db)
+        .await?
+        .ok_or(LocateError::Forbidden)?;
+    Ok(Some(Location {
+        owner: owner(id),
+        path: rest.to_string(),
+        prefix: format!("/{}/{}", ROOT, id),
+        writable: member.owner || group.member_write,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission::perm;
+    use crate::testing::TestEnv;
+    use sea_orm::{ActiveModelTrait, Set};
+
+    #[test]
+    fn test_owner() {
+        assert_eq!(owner(3), "group:3");
+        assert_eq!(group_id("group:3"), Some(3));
+        assert_eq!(group_id("alice"), None);
+        assert_eq!(group_id("dept:3"), None);
+    }
+
+    #[tokio::test]
+    async fn test_locate() {
+        let mut env = TestEnv::new().await;
+        env.config.group_space.enabled = true;
+        let state = env.state();
+        group::ActiveModel {
+            id: Set(1),
+            name: Set("项目组".to_string()),
+            member_write: Set(false),
+        }
+        .insert(&env.db)
+        .await
+        .unwrap();
+        let mut alice = env.user("alice", &[perm::FILE]);
+        alice.id = 1;
+        let mut bob = env.user("bob", &[perm::FILE]);
+        bob.id = 2;
+        let mut carol = env.user("carol", &[perm::FILE]);
+        carol.id = 3;
+        for (user_id, owner) in [(1, true), (2, false)] {
+            group_user::ActiveModel {
+                group_id: Set(1),
+                user_id: Set(user_id),
+                owner: Set(owner),
+                ..Default::default()
+            }
+            .insert(&env.db)
+            .await
+            .unwrap();
+        }
+
+        let names: Vec<_> = spaces(&state, &env.db, &bob).await.unwrap().into_iter().map(|g| g.name).collect();
+        assert_eq!(names, ["项目组"]);
+        assert!(spaces(&state, &env.db, &carol).await.unwrap().is_empty());
+
+        // Only the owner may change files while members can't
+        let location = locate(&state, &env.db, &alice, "/group/1/docs").await.unwrap().unwrap();
+        assert_eq!(
+            location,
+            Location { owner: owner(1), path: "docs".to_string(), prefix: "/group/1".to_string(), writable: true }
+        );
+        assert!(!locate(&state, &env.db, &bob, "/group/1").await.unwrap().unwrap().writable);
+
+        assert!(locate(&state, &env.db, &bob, "/docs").await.unwrap().is_none());
+        assert!(matches!(locate(&state, &env.db, &bob, "/group").await, Err(LocateError::NotFound)));
+        assert!(matches!(locate(&state, &env.db, &bob, "/group/2").await, Err(LocateError::NotFound)));
+        assert!(matches!(locate(&state, &env.db, &carol, "/group/1").await, Err(LocateError::Forbidden)));
+
+        env.close().await;
+    }
+}
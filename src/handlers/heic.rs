@@ -0,0 +1,162 @@
+//! HEIC/HEIF photos
+//!
+//! Photos taken on iPhones are HEIC files, which most browsers can't show.
+//! With `[media] heif_convert` set, previews and thumbnails of them are made
+//! from a JPEG conversion done by that command, such as libheif's
+//! `heif-convert`. Conversions run on the job pool like thumbnails and are
+//! cached with them, keyed by the file and its modification time, so a photo
+//! is converted once. Photos of sensitive folders are watermarked after the
+//! conversion like other images.
+
+use axum::{http::StatusCode, response::Response};
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, MediaConfig};
+use crate::error::error_response;
+use crate::handlers::dept_space::Location;
+use crate::handlers::sensitive;
+use crate::handlers::thumbnail;
+use crate::task::TASK_MANAGER;
+use crate::watermark::Kind;
+
+/// Whether files with the extension `ext` are converted to JPEG
+pub fn converts(config: &MediaConfig, ext: &str) -> bool {
+    !config.heif_convert.is_empty() && matches!(ext.to_ascii_lowercase().as_str(), "heic" | "heif")
+}
+
+/// Path of the JPEG conversion of the HEIC file at `src`, converted now if
+/// it isn't cached yet
+///
+/// Callers hold a worker of the job pool.
+pub async fn converted(config: &Config, src: &Path) -> anyhow::Result<PathBuf> {
+    thumbnail::derived(config, "heic", src, |output| async move {
+        let mut command = tokio::process::Command::new(&config.media.heif_convert);
+        command.arg(src).arg(&output);
+        thumbnail::run_converter(command, &output).await
+    })
+    .await
+}
+
+/// Preview of the HEIC file at `file_path`: its JPEG conversion, watermarked
+/// for `viewer` in sensitive folders
+pub async fn preview(
+    config: &Config,
+    db: &sea_orm::DatabaseConnection,
+    location: &Location,
+    file_path: &Path,
+    viewer: &str,
+) -> Response {
+    let Ok(worker) = TASK_MANAGER.jobs().worker(viewer).await else {
+        return thumbnail::busy_response();
+    };
+    let jpeg = converted(config, file_path).await;
+    // Watermarking takes a worker of its own
+    drop(worker);
+    let jpeg = match jpeg {
+        Ok(jpeg) => jpeg,
+        Err(e) => {
+            tracing::warn!("Failed to convert {:?} to JPEG: {}", file_path, e);
+            return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "cannot convert image");
+        }
+    };
+    // Named after the photo rather than the cache entry
+    let shown = file_path.with_extension("jpg");
+    sensitive::serve_converted(db, location, &jpeg, &shown, Kind::Image, viewer).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+    use axum::http::header;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// A converter script writing a 40x30 JPEG and counting its runs in
+    /// `runs`, or failing for inputs named `broken.*`
+    fn converter(dir: &Path) -> String {
+        let jpeg = dir.join("photo.jpg");
+        image::RgbImage::from_pixel(40, 30, image::Rgb([20, 120, 200])).save(&jpeg).unwrap();
+        let script = dir.join("convert.sh");
+        let body = format!(
+            "#!/bin/sh\necho run >> {runs}\ncase \"$1\" in */broken.*) exit 1;; esac\ncp {jpeg} \"$2\"\n",
+            runs = dir.join("runs").display(),
+            jpeg = jpeg.display()
+        );
+        std::fs::write(&script, body).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        script.display().to_string()
+    }
+
+    fn runs(dir: &Path) -> usize {
+        std::fs::read_to_string(dir.join("runs")).map_or(0, |runs| runs.lines().count())
+    }
+
+    #[test]
+    fn test_converts() {
+        let config = MediaConfig { heif_convert: "heif-convert".to_string(), ..MediaConfig::default() };
+        assert!(converts(&config, "HEIC"));
+        assert!(converts(&config, "heif"));
+        assert!(!converts(&config, "jpg"));
+        assert!(!converts(&MediaConfig::default(), "heic"));
+    }
+
+    #[tokio::test]
+    async fn test_converted() {
+        let dir = std::env::temp_dir().join(format!("heic-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config {
+            thumbnail_cache_dir: Some(dir.join("cache")),
+            media: MediaConfig { heif_convert: converter(&dir), ..MediaConfig::default() },
+            ..Config::default()
+        };
+        let src = dir.join("a.heic");
+        std::fs::write(&src, b"heic").unwrap();
+
+        let jpeg = converted(&config, &src).await.unwrap();
+        assert_eq!(image::open(&jpeg).unwrap().width(), 40);
+        assert!(jpeg.starts_with(dir.join("cache").join("heic")));
+        // Converted once
+        assert_eq!(converted(&config, &src).await.unwrap(), jpeg);
+        assert_eq!(runs(&dir), 1);
+
+        let broken = dir.join("broken.heic");
+        std::fs::write(&broken, b"heic").unwrap();
+        assert!(converted(&config, &broken).await.is_err());
+        // Nothing is left behind by a failed conversion
+        let entries = std::fs::read_dir(jpeg.parent().unwrap()).unwrap().count();
+        assert_eq!(entries, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_preview() {
+        let dir = std::env::temp_dir().join(format!("heic-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = converter(&dir);
+        let app = TestApp::spawn_with(|app_config| app_config.media.heif_convert = script).await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "IMG_0001.HEIC", b"heic").await.status().is_success());
+
+        let res = admin.get("/api/file/preview/single?path=/IMG_0001.HEIC").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "image/jpeg");
+        assert!(res.headers()[header::CONTENT_DISPOSITION].to_str().unwrap().contains("IMG_0001.jpg"));
+        assert_eq!(image::load_from_memory(&res.bytes().await.unwrap()).unwrap().height(), 30);
+
+        let res = admin.get("/api/file/thumbnail?path=/IMG_0001.HEIC&size=20").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(image::load_from_memory(&res.bytes().await.unwrap()).unwrap().width(), 20);
+        assert_eq!(runs(&dir), 1);
+
+        let res: serde_json::Value = admin.get("/api/file/preview/info?path=/IMG_0001.HEIC").await.json().await.unwrap();
+        assert_eq!(res["kind"], "image");
+        assert_eq!(res["contentType"], "image/jpeg");
+
+        assert!(admin.upload("/", "broken.heic", b"heic").await.status().is_success());
+        let res = admin.get("/api/file/preview/single?path=/broken.heic").await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        app.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
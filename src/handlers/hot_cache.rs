@@ -0,0 +1,266 @@
+//! Hot file cache
+//!
+//! Small files read over and over, such as avatars, icons and the images and
+//! READMEs of shared folders, are kept in memory with `[hot_cache]` enabled,
+//! so their downloads and previews don't touch the disk. Entries are keyed by
+//! the owner and path of the file and remember the version (modification time
+//! and size) they were read at: a file changed by any means is read again,
+//! and changes published by the file API, WebDAV and the other writers drop
+//! the entries of the path and everything below it right away ([`invalidate`]).
+//! When the cache is full, the least recently read files are dropped first.
+//!
+//! Stubs of files in cold storage or compressed are empty and never cached,
+//! their content is read the usual way.
+
+use axum::body::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::Metadata;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+
+use crate::config::HotCacheConfig;
+use crate::handlers::path::normalize;
+use crate::metrics;
+use crate::mime;
+
+/// Owner the avatars are cached under, by username, apart from the trees
+pub const AVATARS: &str = "@avatar";
+
+/// A cached file
+#[derive(Debug)]
+pub struct Cached {
+    pub data: Bytes,
+    /// Detected from the name and content
+    pub content_type: &'static str,
+}
+
+/// Modification time and size of a file
+type Version = (Option<SystemTime>, u64);
+
+/// Owner and `/path` of a file
+type Key = (String, String);
+
+struct Entry {
+    version: Version,
+    file: Arc<Cached>,
+    /// Last read, the key of the entry in `Lru::order`
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<Key, Entry>,
+    /// Keys from the least to the most recently read
+    order: BTreeMap<u64, Key>,
+    tick: u64,
+    bytes: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &Key, version: Version) -> Option<Arc<Cached>> {
+        let entry = self.entries.get_mut(key)?;
+        if entry.version != version {
+            self.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        self.order.remove(&entry.tick);
+        self.order.insert(self.tick, key.clone());
+        entry.tick = self.tick;
+        Some(entry.file.clone())
+    }
+
+    fn insert(&mut self, key: Key, version: Version, file: Arc<Cached>, capacity: u64) {
+        self.remove(&key);
+        let len = file.data.len() as u64;
+        while self.bytes + len > capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.bytes -= entry.file.data.len() as u64;
+            }
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.bytes += len;
+        self.entries.insert(key, Entry { version, file, tick: self.tick });
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.tick);
+            self.bytes -= entry.file.data.len() as u64;
+        }
+    }
+}
+
+static CACHE: LazyLock<Mutex<Lru>> = LazyLock::new(Mutex::default);
+
+fn version(metadata: &Metadata) -> Version {
+    (metadata.modified().ok(), metadata.len())
+}
+
+/// Whether a file of `metadata` is cached
+fn caches(config: &HotCacheConfig, metadata: &Metadata) -> bool {
+    let len = metadata.len();
+    config.enabled
+        && metadata.is_file()
+        && len > 0
+        && len <= config.max_file_kb * 1024
+        && len <= config.capacity_mb * 1024 * 1024
+}
+
+/// Content of `path` of `owner`, stored at `file_path` and described by
+/// `metadata`, from the cache or read into it now
+///
+/// None if the file is too large to cache or the cache is disabled.
+pub async fn read(
+    config: &HotCacheConfig,
+    owner: &str,
+    path: &str,
+    file_path: &Path,
+    metadata: &Metadata,
+) -> std::io::Result<Option<Arc<Cached>>> {
+    if !caches(config, metadata) {
+        return Ok(None);
+    }
+    let key = (owner.to_string(), normalize(path));
+    let version = version(metadata);
+    if let Some(file) = CACHE.lock().unwrap().get(&key, version) {
+        metrics::count_hot_cache(true);
+        return Ok(Some(file));
+    }
+    metrics::count_hot_cache(false);
+
+    let data = tokio::fs::read(file_path).await?;
+    let name = file_path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let content_type = mime::detect(name, &data[..data.len().min(mime::SNIFF_LEN)]);
+    let file = Arc::new(Cached { data: Bytes::from(data), content_type });
+    // Content of a file changed while it was read is served once, not kept
+    let unchanged = tokio::fs::metadata(file_path).await.is_ok_and(|after| self::version(&after) == version);
+    if unchanged && file.data.len() as u64 == version.1 {
+        CACHE
+            .lock()
+            .unwrap()
+            .insert(key, version, file.clone(), config.capacity_mb * 1024 * 1024);
+    }
+    Ok(Some(file))
+}
+
+/// Drop the cached files at `path` of `owner` and below it
+pub fn invalidate(owner: &str, path: &str) {
+    let path = normalize(path);
+    let below = format!("{}/", path.trim_end_matches('/'));
+    let mut cache = CACHE.lock().unwrap();
+    let stale: Vec<Key> = cache
+        .entries
+        .keys()
+        .filter(|(o, p)| o == owner && (*p == path || p.starts_with(&below)))
+        .cloned()
+        .collect();
+    for key in &stale {
+        cache.remove(key);
+    }
+}
+
+/// Bytes of cached content
+pub fn cached_bytes() -> u64 {
+    CACHE.lock().unwrap().bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+    use axum::http::{header, StatusCode};
+
+    fn file(data: &'static [u8]) -> Arc<Cached> {
+        Arc::new(Cached { data: Bytes::from_static(data), content_type: mime::OCTET_STREAM })
+    }
+
+    fn key(path: &str) -> Key {
+        ("alice".to_string(), path.to_string())
+    }
+
+    #[test]
+    fn test_lru() {
+        let mut lru = Lru::default();
+        let v = (None, 4);
+        lru.insert(key("/a"), v, file(b"aaaa"), 10);
+        lru.insert(key("/b"), v, file(b"bbbb"), 10);
+        // Reading /a leaves /b the least recently read
+        assert!(lru.get(&key("/a"), v).is_some());
+        lru.insert(key("/c"), v, file(b"cccc"), 10);
+        assert!(lru.get(&key("/b"), v).is_none());
+        assert!(lru.get(&key("/a"), v).is_some());
+        assert_eq!(lru.bytes, 8);
+
+        // Another version is a miss and drops the entry
+        assert!(lru.get(&key("/a"), (None, 5)).is_none());
+        assert!(lru.get(&key("/a"), v).is_none());
+        assert_eq!(lru.bytes, 4);
+        assert_eq!(lru.entries.len(), lru.order.len());
+
+        lru.insert(key("/c"), v, file(b"cc"), 10);
+        assert_eq!(lru.bytes, 2);
+    }
+
+    #[tokio::test]
+    async fn test_read_and_invalidate() {
+        let dir = std::env::temp_dir().join(format!("hot-cache-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        let config = HotCacheConfig { enabled: true, capacity_mb: 1, max_file_kb: 1 };
+        let owner = dir.display().to_string();
+        let path = dir.join("docs/README.md");
+        std::fs::write(&path, b"# Hello").unwrap();
+
+        let read = |path: &Path| {
+            let metadata = std::fs::metadata(path).unwrap();
+            let path = path.to_path_buf();
+            let (config, owner) = (config, owner.clone());
+            async move { read(&config, &owner, "/docs/README.md", &path, &metadata).await.unwrap() }
+        };
+        let first = read(&path).await.unwrap();
+        assert_eq!(first.content_type, mime::detect_file(&path).await);
+        assert!(Arc::ptr_eq(&first, &read(&path).await.unwrap()));
+
+        // Changes are seen by their size or modification time
+        std::fs::write(&path, b"# Hello, world").unwrap();
+        assert_eq!(read(&path).await.unwrap().data.as_ref(), b"# Hello, world");
+
+        let cached = read(&path).await.unwrap();
+        invalidate(&owner, "/docs");
+        assert!(!Arc::ptr_eq(&cached, &read(&path).await.unwrap()));
+
+        // Too large, and empty stubs of files stored elsewhere
+        std::fs::write(&path, vec![b'x'; 2048]).unwrap();
+        assert!(read(&path).await.is_none());
+        std::fs::write(&path, b"").unwrap();
+        assert!(read(&path).await.is_none());
+
+        invalidate(&owner, "/");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download() {
+        let app = TestApp::spawn_with(|app_config| app_config.hot_cache.enabled = true).await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "logo.svg", b"<svg/>").await.status().is_success());
+
+        let res = admin.get("/api/file/download/single?path=/logo.svg").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "image/svg+xml");
+        assert_eq!(res.bytes().await.unwrap().as_ref(), b"<svg/>");
+
+        // Replaced through the API with content of the same size
+        assert!(admin.upload("/", "logo.svg", b"<svg1>").await.status().is_success());
+        let res = admin.get("/api/file/download/single?path=/logo.svg").await;
+        assert_eq!(res.bytes().await.unwrap().as_ref(), b"<svg1>");
+        let res = admin.get("/api/file/preview/single?path=/logo.svg").await;
+        assert_eq!(res.bytes().await.unwrap().as_ref(), b"<svg1>");
+        app.close().await;
+    }
+}
@@ -0,0 +1,629 @@
+//! HR sync handlers
+//!
+//! Reconciles departments, users and group memberships with an external HR
+//! system. Records are either pulled periodically from a source (CSV URL) or
+//! pushed to the webhook receiver.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json},
+};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set,
+};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use crate::config::{Config, HrSyncConfig};
+use crate::entity::{department, group, group_user, user};
+use crate::entity::op_log::OpType;
+use crate::handlers::audit::service::log_admin_operation;
+use crate::handlers::user_import::check_username;
+use crate::outbound;
+use crate::permission::PermissionEnforcer;
+use crate::state::AppState;
+
+/// Operation type for audit logs
+const OP_SUCCESS: &str = "成功";
+const OP_FAILED: &str = "失败";
+
+/// Username recorded in audit logs for sync changes
+const SYNC_OPERATOR: &str = "hr-sync";
+
+/// Header carrying the webhook shared secret
+const SYNC_TOKEN_HEADER: &str = "x-sync-token";
+
+/// One person as described by the HR system
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct HrRecord {
+    pub username: String,
+    #[serde(rename = "fullName", default)]
+    pub full_name: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub phone: Option<String>,
+    /// Department path, e.g. "总部/研发部"
+    #[serde(default)]
+    pub department: String,
+    /// Group names the user belongs to
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// Summary of changes made by one sync run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncReport {
+    #[serde(rename = "departmentsCreated")]
+    pub departments_created: usize,
+    #[serde(rename = "usersCreated")]
+    pub users_created: usize,
+    #[serde(rename = "usersUpdated")]
+    pub users_updated: usize,
+    #[serde(rename = "usersDisabled")]
+    pub users_disabled: usize,
+    #[serde(rename = "membershipsAdded")]
+    pub memberships_added: usize,
+    #[serde(rename = "membershipsRemoved")]
+    pub memberships_removed: usize,
+    /// Records left out because their username can't be used
+    pub skipped: Vec<SkippedRecord>,
+}
+
+/// A record the sync left out, and why
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SkippedRecord {
+    pub username: String,
+    pub reason: String,
+}
+
+/// A place HR records can be pulled from
+#[async_trait]
+pub trait HrSource: Send + Sync {
+    /// Fetch the full current snapshot of HR records
+    async fn fetch(&self) -> anyhow::Result<Vec<HrRecord>>;
+}
+
+/// CSV file served over HTTP
+///
+/// Columns: username, fullName, email, phone, department, groups, active.
+/// Groups are separated by `;`.
+pub struct CsvUrlSource {
+    pub url: String,
+    /// Server configuration, for the outbound HTTP policy
+    pub config: Arc<Config>,
+}
+
+#[async_trait]
+impl HrSource for CsvUrlSource {
+    async fn fetch(&self) -> anyhow::Result<Vec<HrRecord>> {
+        let resp = outbound::get(&self.config, &self.url).await?.error_for_status()?;
+        let body = outbound::read_body(&self.config, resp).await?;
+        parse_csv(&String::from_utf8_lossy(&body))
+    }
+}
+
+/// Build the configured pull source, if any
+fn build_source(config: &Arc<Config>) -> Option<Box<dyn HrSource>> {
+    let hr_sync = &config.hr_sync;
+    match hr_sync.source.as_str() {
+        "csv" if !hr_sync.csv_url.is_empty() => Some(Box::new(CsvUrlSource {
+            url: hr_sync.csv_url.clone(),
+            config: config.clone(),
+        })),
+        _ => None,
+    }
+}
+
+/// Parse HR records from CSV text with a header row
+pub fn parse_csv(text: &str) -> anyhow::Result<Vec<HrRecord>> {
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<String> = match lines.next() {
+        Some(h) => split_csv_line(h).into_iter().map(|s| s.to_lowercase()).collect(),
+        None => return Ok(Vec::new()),
+    };
+    let col = |name: &str| header.iter().position(|h| h == name);
+    let username_col = col("username").ok_or_else(|| anyhow::anyhow!("missing username column"))?;
+    let full_name_col = col("fullname").or_else(|| col("full_name"));
+    let email_col = col("email");
+    let phone_col = col("phone");
+    let dept_col = col("department");
+    let groups_col = col("groups");
+    let active_col = col("active");
+
+    let mut records = Vec::new();
+    for line in lines {
+        let fields = split_csv_line(line);
+        let get = |idx: Option<usize>| {
+            idx.and_then(|i| fields.get(i))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        let username = get(Some(username_col));
+        if username.is_empty() {
+            continue;
+        }
+        let non_empty = |s: String| if s.is_empty() { None } else { Some(s) };
+        let active = get(active_col).to_lowercase();
+        records.push(HrRecord {
+            username,
+            full_name: get(full_name_col),
+            email: non_empty(get(email_col)),
+            phone: non_empty(get(phone_col)),
+            department: get(dept_col),
+            groups: get(groups_col)
+                .split(';')
+                .map(|g| g.trim().to_string())
+                .filter(|g| !g.is_empty())
+                .collect(),
+            active: !matches!(active.as_str(), "false" | "0" | "no" | "n"),
+        });
+    }
+    Ok(records)
+}
+
+/// Split one CSV line, honoring double-quoted fields
+pub(crate) fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                current.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    fields.push(current);
+    fields
+}
+
+/// Reconcile the database with a full HR snapshot
+pub async fn reconcile(
+    db: &DatabaseConnection,
+    perm: Option<&PermissionEnforcer>,
+    config: &HrSyncConfig,
+    root_dir: &std::path::Path,
+    records: &[HrRecord],
+) -> anyhow::Result<SyncReport> {
+    let mut report = SyncReport::default();
+    let mut dept_cache: HashMap<String, i64> = HashMap::new();
+
+    // The username names the user's directory under the storage root
+    let mut valid = Vec::with_capacity(records.len());
+    for record in records {
+        match check_username(&record.username) {
+            Some(reason) => {
+                tracing::warn!("Skipping HR record with invalid username {:?}: {}", record.username, reason);
+                report.skipped.push(SkippedRecord {
+                    username: record.username.clone(),
+                    reason: reason.to_string(),
+                });
+            }
+            None => valid.push(record.clone()),
+        }
+    }
+    let records = valid.as_slice();
+
+    for record in records {
+        let (dept_id, dept_name) =
+            ensure_department(db, perm, &record.department, &mut dept_cache, &mut report).await?;
+
+        let existing = user::Entity::find()
+            .filter(user::Column::Username.eq(&record.username))
+            .one(db)
+            .await?;
+
+        match existing {
+            None => {
+                if !record.active {
+                    continue;
+                }
+                // Synced accounts get an unusable random password until reset
+                let password = bcrypt::hash(uuid::Uuid::new_v4().to_string(), 12)?;
+                let new_user = user::ActiveModel {
+                    username: Set(record.username.clone()),
+                    password: Set(password),
+                    full_name: Set(record.full_name.clone()),
+                    phone: Set(record.phone.clone()),
+                    email: Set(record.email.clone()),
+                    department_id: Set(dept_id),
+                    dept_name: Set(dept_name.clone()),
+                    status: Set(0),
+                    last_login: Set(0),
+                    ..Default::default()
+                };
+                new_user.insert(db).await?;
+
+                let user_dir = root_dir.join(&record.username);
+                if let Err(e) = tokio::fs::create_dir_all(&user_dir).await {
+                    tracing::error!("Failed to create user directory: {}", e);
+                }
+                if let Some(perm) = perm {
+                    if !config.default_role.is_empty() {
+                        if let Err(e) = perm.set_user_role(&record.username, Some(&config.default_role)).await {
+                            tracing::error!("Failed to assign role: {}", e);
+                        }
+                    }
+                    if let Err(e) = perm.set_user_department(&record.username, dept_id).await {
+                        tracing::error!("Failed to assign department: {}", e);
+                    }
+                }
+
+                report.users_created += 1;
+                let op_desc = format!("创建用户: {}, 所属部门: {}", record.username, dept_name);
+                log_admin_operation(SYNC_OPERATOR, OpType::HrSync, &op_desc, OP_SUCCESS, None);
+            }
+            Some(u) => {
+                let status = if record.active {
+                    if u.status == 2 { 1 } else { u.status }
+                } else {
+                    2
+                };
+                let changed = u.full_name != record.full_name
+                    || u.email != record.email
+                    || u.phone != record.phone
+                    || u.department_id != dept_id
+                    || u.status != status;
+                if !changed {
+                    continue;
+                }
+
+                let dept_changed = u.department_id != dept_id;
+                let disabled = status == 2 && u.status != 2;
+                let mut active: user::ActiveModel = u.into();
+                active.full_name = Set(record.full_name.clone());
+                active.email = Set(record.email.clone());
+                active.phone = Set(record.phone.clone());
+                active.department_id = Set(dept_id);
+                active.dept_name = Set(dept_name.clone());
+                active.status = Set(status);
+                active.update(db).await?;
+
+                if dept_changed {
+                    if let Some(perm) = perm {
+                        if let Err(e) = perm.set_user_department(&record.username, dept_id).await {
+                            tracing::error!("Failed to assign department: {}", e);
+                        }
+                    }
+                }
+
+                if disabled {
+                    report.users_disabled += 1;
+                    let op_desc = format!("禁用用户: {}", record.username);
+                    log_admin_operation(SYNC_OPERATOR, OpType::HrSync, &op_desc, OP_SUCCESS, None);
+                } else {
+                    report.users_updated += 1;
+                    let op_desc = format!("更新用户: {}, 所属部门: {}", record.username, dept_name);
+                    log_admin_operation(SYNC_OPERATOR, OpType::HrSync, &op_desc, OP_SUCCESS, None);
+                }
+            }
+        }
+    }
+
+    if config.disable_missing {
+        disable_missing_users(db, config, records, &mut report).await?;
+    }
+
+    sync_group_memberships(db, records, &mut report).await?;
+
+    Ok(report)
+}
+
+/// Find or create the department for a "a/b/c" path, returning (id, path)
+async fn ensure_department(
+    db: &DatabaseConnection,
+    perm: Option<&PermissionEnforcer>,
+    path: &str,
+    cache: &mut HashMap<String, i64>,
+    report: &mut SyncReport,
+) -> anyhow::Result<(i64, String)> {
+    let parts: Vec<&str> = path.split('/').map(str::trim).filter(|p| !p.is_empty()).collect();
+    let mut parent_id = 0;
+    let mut parent_path = String::new();
+
+    for (level, name) in parts.iter().enumerate() {
+        let current_path = if parent_path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", parent_path, name)
+        };
+
+        if let Some(id) = cache.get(&current_path) {
+            parent_id = *id;
+            parent_path = current_path;
+            continue;
+        }
+
+        let existing = department::Entity::find()
+            .filter(department::Column::Name.eq(*name))
+            .filter(department::Column::ParentId.eq(parent_id))
+            .one(db)
+            .await?;
+
+        let id = match existing {
+            Some(d) => d.id,
+            None => {
+                let dept = department::ActiveModel {
+                    name: Set(name.to_string()),
+                    level: Set(level as i32 + 1),
+                    parent_id: Set(parent_id),
+                    parent_name: Set(parent_path.clone()),
+                    ..Default::default()
+                }
+                .insert(db)
+                .await?;
+
+                if let Some(perm) = perm {
+                    if let Err(e) = perm.set_department_parent(dept.id, Some(parent_id)).await {
+                        tracing::error!("Failed to set department parent: {}", e);
+                    }
+                }
+
+                report.departments_created += 1;
+                let op_desc = format!("创建部门: {}", current_path);
+                log_admin_operation(SYNC_OPERATOR, OpType::HrSync, &op_desc, OP_SUCCESS, None);
+                dept.id
+            }
+        };
+
+        cache.insert(current_path.clone(), id);
+        parent_id = id;
+        parent_path = current_path;
+    }
+
+    Ok((parent_id, parent_path))
+}
+
+/// Disable active users that no longer appear in the HR snapshot
+async fn disable_missing_users(
+    db: &DatabaseConnection,
+    config: &HrSyncConfig,
+    records: &[HrRecord],
+    report: &mut SyncReport,
+) -> anyhow::Result<()> {
+    let known: HashSet<&str> = records.iter().map(|r| r.username.as_str()).collect();
+    let users = user::Entity::find()
+        .filter(user::Column::Status.ne(2))
+        .filter(user::Column::AccountType.ne(user::ACCOUNT_TYPE_SERVICE))
+        .all(db)
+        .await?;
+
+    for u in users {
+        if known.contains(u.username.as_str())
+            || config.protected_users.iter().any(|p| p == &u.username)
+        {
+            continue;
+        }
+        let username = u.username.clone();
+        let mut active: user::ActiveModel = u.into();
+        active.status = Set(2);
+        active.update(db).await?;
+
+        report.users_disabled += 1;
+        let op_desc = format!("禁用用户: {} (HR中不存在)", username);
+        log_admin_operation(SYNC_OPERATOR, OpType::HrSync, &op_desc, OP_SUCCESS, None);
+    }
+    Ok(())
+}
+
+/// Make memberships of every group named in the snapshot match the snapshot
+async fn sync_group_memberships(
+    db: &DatabaseConnection,
+    records: &[HrRecord],
+    report: &mut SyncReport,
+) -> anyhow::Result<()> {
+    let mut wanted: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for record in records.iter().filter(|r| r.active) {
+        for g in &record.groups {
+            wanted.entry(g.as_str()).or_default().insert(record.username.as_str());
+        }
+    }
+
+    for (group_name, usernames) in wanted {
+        let grp = match group::Entity::find()
+            .filter(group::Column::Name.eq(group_name))
+            .one(db)
+            .await?
+        {
+            Some(g) => g,
+            None => {
+                group::ActiveModel {
+                    name: Set(group_name.to_string()),
+                    member_write: Set(true),
+                    ..Default::default()
+                }
+                .insert(db)
+                .await?
+            }
+        };
+
+        let users = user::Entity::find()
+            .filter(user::Column::Username.is_in(usernames.iter().copied()))
+            .all(db)
+            .await?;
+        let wanted_ids: HashSet<i64> = users.iter().map(|u| u.id).collect();
+
+        let members = group_user::Entity::find()
+            .filter(group_user::Column::GroupId.eq(grp.id))
+            .all(db)
+            .await?;
+        let current_ids: HashSet<i64> = members.iter().map(|m| m.user_id).collect();
+
+        for u in users.iter().filter(|u| !current_ids.contains(&u.id)) {
+            group_user::ActiveModel {
+                user_id: Set(u.id),
+                group_id: Set(grp.id),
+                owner: Set(false),
+                ..Default::default()
+            }
+            .insert(db)
+            .await?;
+            report.memberships_added += 1;
+            let op_desc = format!("群组: {}, 添加成员: {}", group_name, u.username);
+            log_admin_operation(SYNC_OPERATOR, OpType::HrSync, &op_desc, OP_SUCCESS, None);
+        }
+
+        // Group owners are managed in the app and are never removed by sync
+        for m in members.iter().filter(|m| !m.owner && !wanted_ids.contains(&m.user_id)) {
+            group_user::Entity::delete_by_id(m.id).exec(db).await?;
+            report.memberships_removed += 1;
+            let op_desc = format!("群组: {}, 移除成员ID: {}", group_name, m.user_id);
+            log_admin_operation(SYNC_OPERATOR, OpType::HrSync, &op_desc, OP_SUCCESS, None);
+        }
+    }
+    Ok(())
+}
+
+/// Run one sync against the configured pull source
+pub async fn run_once(state: &AppState) -> anyhow::Result<SyncReport> {
+    let config = &state.config.hr_sync;
+    let source = build_source(&state.config).ok_or_else(|| anyhow::anyhow!("no HR sync source configured"))?;
+    let db = state.get_db().await.ok_or_else(|| anyhow::anyhow!("database not initialized"))?;
+    let perm = state.get_perm().await;
+
+    let records = source.fetch().await?;
+    reconcile(&db, perm.as_ref(), config, &state.config.root_dir, &records).await
+}
+
+/// Start the periodic sync job if a pull source is configured
+pub fn start(state: AppState) {
+    let config = &state.config.hr_sync;
+    if !config.enabled || build_source(&state.config).is_none() {
+        return;
+    }
+
+    let interval = std::time::Duration::from_secs(config.interval_secs.max(60));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match run_once(&state).await {
+                Ok(report) => tracing::info!("HR sync finished: {:?}", report),
+                Err(e) => {
+                    tracing::error!("HR sync failed: {}", e);
+                    log_admin_operation(SYNC_OPERATOR, OpType::HrSync, &e.to_string(), OP_FAILED, None);
+                }
+            }
+        }
+    });
+}
+
+/// POST /api/hr/sync - Webhook receiver for pushed HR snapshots
+///
+/// Authenticated by the `X-Sync-Token` header instead of a session.
+pub async fn sync_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(records): Json<Vec<HrRecord>>,
+) -> impl IntoResponse {
+    let config = &state.config.hr_sync;
+    let token = headers
+        .get(SYNC_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if !config.enabled
+        || config.webhook_token.is_empty()
+        || !bool::from(token.as_bytes().ct_eq(config.webhook_token.as_bytes()))
+    {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({"error": "unauthorized"})),
+        );
+    }
+
+    let db = match state.get_db().await {
+        Some(db) => db,
+        None => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({"error": "system not initialized"})),
+            )
+        }
+    };
+    let perm = state.get_perm().await;
+
+    match reconcile(&db, perm.as_ref(), config, &state.config.root_dir, &records).await {
+        Ok(report) => (StatusCode::OK, Json(serde_json::json!(report))),
+        Err(e) => {
+            tracing::error!("HR sync failed: {}", e);
+            log_admin_operation(SYNC_OPERATOR, OpType::HrSync, &e.to_string(), OP_FAILED, None);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": "internal error"})),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestEnv;
+
+    #[test]
+    fn test_split_csv_line() {
+        assert_eq!(split_csv_line("a,b,c"), vec!["a", "b", "c"]);
+        assert_eq!(split_csv_line(r#""x, y",z"#), vec!["x, y", "z"]);
+        assert_eq!(split_csv_line(r#""say ""hi""",,"#), vec![r#"say "hi""#, "", ""]);
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let text = "username,fullName,email,phone,department,groups,active\n\
+                    zhangsan,张三,zs@example.com,,总部/研发部,dev;ops,true\n\
+                    lisi,李四,,,总部,,false\n\
+                    ,nobody,,,,,\n";
+        let records = parse_csv(text).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].username, "zhangsan");
+        assert_eq!(records[0].department, "总部/研发部");
+        assert_eq!(records[0].groups, vec!["dev", "ops"]);
+        assert_eq!(records[0].phone, None);
+        assert!(records[0].active);
+        assert!(!records[1].active);
+    }
+
+    #[test]
+    fn test_parse_csv_requires_username() {
+        assert!(parse_csv("fullName,email\nfoo,bar\n").is_err());
+        assert!(parse_csv("").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_skips_invalid_usernames() {
+        let env = TestEnv::new().await;
+        let records: Vec<HrRecord> = ["../escape", "", "alice"]
+            .iter()
+            .map(|name| HrRecord { username: name.to_string(), active: true, ..Default::default() })
+            .collect();
+
+        let report = reconcile(&env.db, None, &HrSyncConfig::default(), &env.config.root_dir, &records)
+            .await
+            .unwrap();
+        assert_eq!(report.users_created, 1);
+        let skipped: Vec<&str> = report.skipped.iter().map(|s| s.username.as_str()).collect();
+        assert_eq!(skipped, vec!["../escape", ""]);
+        assert!(env.config.root_dir.join("alice").is_dir());
+        assert!(!env.dir.join("escape").exists());
+
+        env.close().await;
+    }
+}
@@ -0,0 +1,309 @@
+//! Operation journal
+//!
+//! Finishing an upload, moving and restoring from the trash each change the
+//! filesystem and several tables one after another. Before the first change
+//! the operation records its intent in `disk_journal`, and removes it when
+//! done. Entries found at startup belong to operations the process died in:
+//! what is on disk tells how far they got, and [`recover`] either completes
+//! the remaining steps or undoes the started ones.
+//!
+//! Every step of the recovery can run again, so dying during the recovery
+//! itself is harmless too.
+
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, Set};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::entity::{cold_file, file_info, file_tag, journal, sensitive_folder, trash};
+use crate::handlers::file::resolve_in_user_root;
+use crate::handlers::path;
+use crate::handlers::sensitive;
+use crate::handlers::tag;
+use crate::handlers::tiering;
+use crate::handlers::trash::{ensure_dir_id, register_tree};
+use crate::service::FileService;
+use crate::state::AppState;
+
+/// What an operation is about to do, paths relative to the owners' roots
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Intent {
+    /// Put the uploaded `tmp` at `path`
+    Upload {
+        owner: String,
+        tmp: PathBuf,
+        path: String,
+        size: i64,
+        sha256: Option<String>,
+    },
+    /// Move `from` to `to`, the tree of another owner or the same; `replaced`
+    /// if something was at `to` already
+    Move {
+        from_owner: String,
+        from: String,
+        to_owner: String,
+        to: String,
+        replaced: bool,
+    },
+    /// Put trash item `trash_id` back at `path`
+    Restore {
+        owner: String,
+        trash_id: i64,
+        trash_file: PathBuf,
+        path: String,
+    },
+}
+
+impl Intent {
+    fn op(&self) -> &'static str {
+        match self {
+            Intent::Upload { .. } => "upload",
+            Intent::Move { .. } => "move",
+            Intent::Restore { .. } => "restore",
+        }
+    }
+}
+
+/// Record `intent` before the operation changes anything, the id of the entry
+pub async fn begin(db: &DatabaseConnection, intent: &Intent) -> Result<i64, DbErr> {
+    let entry = journal::ActiveModel {
+        op: Set(intent.op().to_string()),
+        intent: Set(serde_json::to_string(intent).map_err(|e| DbErr::Custom(e.to_string()))?),
+        step: Set(journal::STEP_STARTED.to_string()),
+        create_time: Set(chrono::Utc::now().timestamp()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+    Ok(entry.id)
+}
+
+/// Record that the operation of entry `id` got to `step`
+pub async fn advance(db: &DatabaseConnection, id: i64, step: &str) -> Result<(), DbErr> {
+    journal::ActiveModel { id: Set(id), step: Set(step.to_string()), ..Default::default() }
+        .update(db)
+        .await?;
+    Ok(())
+}
+
+/// Remove the entry of a finished operation
+///
+/// An entry left behind is recovered at the next start, which only finds
+/// nothing left to do.
+pub async fn finish(db: &DatabaseConnection, id: i64) {
+    if let Err(e) = journal::Entity::delete_by_id(id).exec(db).await {
+        tracing::error!("Failed to remove journal entry {}: {}", id, e);
+    }
+}
+
+/// Complete or undo the operations interrupted by the last shutdown, before
+/// requests are served
+pub async fn recover(state: &AppState) {
+    let Some(db) = state.get_db().await else {
+        return;
+    };
+    match recover_all(&state.config, &db).await {
+        Ok(0) => {}
+        Ok(n) => tracing::warn!("Recovered {} file operations interrupted by restart", n),
+        Err(e) => tracing::error!("Failed to recover interrupted file operations: {}", e),
+    }
+}
+
+async fn recover_all(config: &Config, db: &DatabaseConnection) -> Result<usize, DbErr> {
+    let entries = journal::Entity::find().order_by_asc(journal::Column::Id).all(db).await?;
+    let mut recovered = 0;
+    for entry in entries {
+        match serde_json::from_str::<Intent>(&entry.intent) {
+            Ok(intent) => {
+                if let Err(e) = replay(config, db, &intent, &entry.step).await {
+                    // Kept for the next start
+                    tracing::error!("Failed to recover {} of journal entry {}: {}", entry.op, entry.id, e);
+                    continue;
+                }
+                recovered += 1;
+            }
+            Err(e) => tracing::error!("Dropping unreadable journal entry {}: {}", entry.id, e),
+        }
+        journal::Entity::delete_by_id(entry.id).exec(db).await?;
+    }
+    Ok(recovered)
+}
+
+/// Absolute path of `path` in the tree of `owner`
+fn resolve(config: &Config, owner: &str, path: &str) -> anyhow::Result<PathBuf> {
+    resolve_in_user_root(config, owner, path).ok_or_else(|| anyhow::anyhow!("invalid path {}", path))
+}
+
+async fn replay(config: &Config, db: &DatabaseConnection, intent: &Intent, step: &str) -> anyhow::Result<()> {
+    match intent {
+        Intent::Upload { owner, tmp, path, size, sha256 } => {
+            // Not put in place yet: the client uploads again
+            if tmp.exists() {
+                tracing::warn!("Undoing interrupted upload of {} by {}", path, owner);
+                tokio::fs::remove_file(tmp).await?;
+                return Ok(());
+            }
+            if !resolve(config, owner, path)?.is_file() {
+                return Ok(());
+            }
+            tracing::warn!("Completing interrupted upload of {} by {}", path, owner);
+            let (folder, name) = path::split(path);
+            let parent_id = ensure_dir_id(db, owner, &folder).await?;
+            FileService::new(config, db, owner)
+                .record_upload(path, parent_id, name, *size, sha256.clone())
+                .await?;
+        }
+        Intent::Move { from_owner, from, to_owner, to, replaced } => {
+            let (src, dst) = (resolve(config, from_owner, from)?, resolve(config, to_owner, to)?);
+            if src.exists() && step != journal::STEP_COPIED {
+                // Renamed nothing, or copied part of the source: it is still whole
+                if dst.exists() && !replaced {
+                    tracing::warn!("Undoing interrupted move of {} to {}", from, to);
+                    remove(&dst).await?;
+                }
+                return Ok(());
+            }
+            tracing::warn!("Completing interrupted move of {} to {}", from, to);
+            if src.exists() {
+                remove(&src).await?;
+            }
+            if from_owner == to_owner {
+                move_metadata(db, from_owner, from, to).await?;
+            }
+        }
+        Intent::Restore { owner, trash_id, trash_file, path } => {
+            let target = resolve(config, owner, path)?;
+            if trash_file.exists() {
+                // Copied from a trash on another volume in part, or in whole
+                // before the trash copy went: the trash item is still whole
+                if target.exists() {
+                    tracing::warn!("Undoing interrupted restore of {} by {}", path, owner);
+                    remove(&target).await?;
+                }
+                return Ok(());
+            }
+            if !target.exists() {
+                return Ok(());
+            }
+            tracing::warn!("Completing interrupted restore of {} by {}", path, owner);
+            let (folder, name) = path::split(path);
+            let parent_id = ensure_dir_id(db, owner, &folder).await?;
+            let row = file_info::Entity::find()
+                .filter(file_info::Column::Username.eq(owner))
+                .filter(file_info::Column::ParentId.eq(parent_id))
+                .filter(file_info::Column::Name.eq(name))
+                .one(db)
+                .await?;
+            if row.is_none() {
+                register_tree(db, owner, parent_id, &target).await?;
+            }
+            trash::Entity::delete_by_id(*trash_id).exec(db).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Move the tags, cold storage stubs and sensitive marks still at `from`
+/// along to `to`
+///
+/// Each moves only if something is left at `from`, as moving again would
+/// drop what was moved already as replaced.
+async fn move_metadata(db: &DatabaseConnection, owner: &str, from: &str, to: &str) -> Result<(), DbErr> {
+    let from = path::normalize(from);
+    if !path::rows_under::<file_tag::Entity>(db, owner, &from).await?.is_empty() {
+        tag::move_tags(db, owner, &from, to).await?;
+    }
+    if !path::rows_under::<cold_file::Entity>(db, owner, &from).await?.is_empty() {
+        tiering::move_stubs(db, owner, &from, to).await?;
+    }
+    if !path::rows_under::<sensitive_folder::Entity>(db, owner, &from).await?.is_empty() {
+        sensitive::move_marks(db, owner, &from, to).await?;
+    }
+    Ok(())
+}
+
+async fn remove(path: &Path) -> std::io::Result<()> {
+    if tokio::fs::symlink_metadata(path).await?.is_dir() {
+        tokio::fs::remove_dir_all(path).await
+    } else {
+        tokio::fs::remove_file(path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::file::get_user_path;
+    use crate::testing::TestEnv;
+    use sea_orm::PaginatorTrait;
+
+    #[tokio::test]
+    async fn test_recover() {
+        let env = TestEnv::new().await;
+        let root = get_user_path(&env.config, "alice");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+
+        // Upload put in place without its row, and one never put in place
+        std::fs::write(root.join("docs/a.txt"), b"hello").unwrap();
+        let tmp = env.dir.join("upload.tmp");
+        std::fs::write(&tmp, b"partial").unwrap();
+        let uploads = [
+            Intent::Upload { owner: "alice".into(), tmp: env.dir.join("gone.tmp"), path: "docs/a.txt".into(), size: 5, sha256: None },
+            Intent::Upload { owner: "alice".into(), tmp: tmp.clone(), path: "docs/b.txt".into(), size: 7, sha256: None },
+        ];
+        for intent in &uploads {
+            begin(&env.db, intent).await.unwrap();
+        }
+
+        // Move copied but not removed, and one that copied only part
+        std::fs::write(root.join("c.txt"), b"c").unwrap();
+        std::fs::write(root.join("docs/c.txt"), b"c").unwrap();
+        let copied = Intent::Move { from_owner: "alice".into(), from: "c.txt".into(), to_owner: "alice".into(), to: "docs/c.txt".into(), replaced: false };
+        let id = begin(&env.db, &copied).await.unwrap();
+        advance(&env.db, id, journal::STEP_COPIED).await.unwrap();
+        std::fs::write(root.join("d.txt"), b"ddd").unwrap();
+        std::fs::write(root.join("docs/d.txt"), b"d").unwrap();
+        let partial = Intent::Move { from_owner: "alice".into(), from: "d.txt".into(), to_owner: "alice".into(), to: "docs/d.txt".into(), replaced: false };
+        begin(&env.db, &partial).await.unwrap();
+
+        // Restore put back without its rows, the trash item still listed
+        let item = trash::ActiveModel {
+            username: Set("alice".into()),
+            name: Set("e.txt".into()),
+            trash_name: Set("e.txt.1".into()),
+            original_path: Set("".into()),
+            is_directory: Set(false),
+            size: Set(1),
+            delete_time: Set(0),
+            ..Default::default()
+        }
+        .insert(&env.db)
+        .await
+        .unwrap();
+        std::fs::write(root.join("e.txt"), b"e").unwrap();
+        let restore = Intent::Restore { owner: "alice".into(), trash_id: item.id, trash_file: env.dir.join("e.txt.1"), path: "e.txt".into() };
+        begin(&env.db, &restore).await.unwrap();
+
+        // Restore copying from a trash on another volume, the copy unfinished
+        let trash_file = env.dir.join("f.txt.1");
+        std::fs::write(&trash_file, b"fff").unwrap();
+        std::fs::write(root.join("f.txt"), b"f").unwrap();
+        let copying = Intent::Restore { owner: "alice".into(), trash_id: item.id + 1, trash_file: trash_file.clone(), path: "f.txt".into() };
+        begin(&env.db, &copying).await.unwrap();
+
+        assert_eq!(recover_all(&env.config, &env.db).await.unwrap(), 6);
+        assert!(trash_file.exists() && !root.join("f.txt").exists());
+        assert!(!tmp.exists());
+        assert!(!root.join("c.txt").exists() && root.join("docs/c.txt").exists());
+        assert!(root.join("d.txt").exists() && !root.join("docs/d.txt").exists());
+        let rows: Vec<String> = env.file_rows().await.into_iter().map(|(_, name)| name).collect();
+        assert_eq!(rows, ["docs", "e.txt", "a.txt"]);
+        assert_eq!(trash::Entity::find().count(&env.db).await.unwrap(), 0);
+        assert_eq!(journal::Entity::find().count(&env.db).await.unwrap(), 0);
+
+        // Nothing left to do
+        assert_eq!(recover_all(&env.config, &env.db).await.unwrap(), 0);
+        env.close().await;
+    }
+}
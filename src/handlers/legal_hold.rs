@@ -0,0 +1,308 @@
+//! Legal hold
+//!
+//! Auditors put files and folders of any user on hold. A held path, and
+//! everything below it, can't be deleted, overwritten, renamed, moved or
+//! purged from the trash until the hold is released, whoever asks. Folders
+//! containing a held path can't be deleted, renamed or moved either, as that
+//! would take the held path with them.
+//!
+//! Holds are stored by path, like tags. Every frontend asks [`held`] (through
+//! [`FileService::check_hold`](crate::service::FileService::check_hold) where
+//! it can) before it changes a path.
+
+use axum::{
+    extract::{Query, State},
+    response::Json,
+    Extension,
+};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    Set,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::entity::{legal_hold, trash};
+use crate::entity::op_log::OpType;
+use crate::handlers::abuse::record_denied;
+use crate::handlers::audit::service::log_admin_operation;
+use crate::handlers::file::resolve_in_user_root;
+use crate::handlers::path::{is_within, normalize};
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+
+const OP_SUCCESS: &str = "成功";
+
+/// Longest reason in characters
+const MAX_REASON_LENGTH: usize = 512;
+
+/// The hold that keeps `path` from being changed: one on the path itself, a
+/// folder above it or anything below it
+pub async fn held(
+    db: &DatabaseConnection,
+    username: &str,
+    path: &str,
+) -> Result<Option<legal_hold::Model>, DbErr> {
+    let path = normalize(path);
+    let holds = legal_hold::Entity::find()
+        .filter(legal_hold::Column::Username.eq(username))
+        .order_by_asc(legal_hold::Column::Path)
+        .all(db)
+        .await?;
+    Ok(holds
+        .into_iter()
+        .find(|hold| is_within(&path, &hold.path) || is_within(&hold.path, &path)))
+}
+
+/// Error message for a change refused by the hold on `path`
+pub fn message(path: &str) -> String {
+    format!("{} 处于法律保留状态，不能删除、覆盖或重命名", path)
+}
+
+/// Path of a trash item before it was deleted
+pub fn trash_item_path(item: &trash::Model) -> String {
+    match item.original_path.trim_matches('/') {
+        "" => format!("/{}", item.name),
+        parent => format!("/{}/{}", parent, item.name),
+    }
+}
+
+/// Query parameters for listing holds
+#[derive(Debug, Deserialize)]
+pub struct HoldListQuery {
+    /// Holds of this user; all holds if not set
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetHoldRequest {
+    pub username: String,
+    pub path: String,
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReleaseHoldRequest {
+    pub username: String,
+    pub path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HoldResponse {
+    pub username: String,
+    pub path: String,
+    pub reason: String,
+    #[serde(rename = "createdBy")]
+    pub created_by: String,
+    #[serde(rename = "createTime")]
+    pub create_time: i64,
+}
+
+impl From<legal_hold::Model> for HoldResponse {
+    fn from(hold: legal_hold::Model) -> Self {
+        Self {
+            username: hold.username,
+            path: hold.path,
+            reason: hold.reason,
+            created_by: hold.created_by,
+            create_time: hold.create_time,
+        }
+    }
+}
+
+/// GET /api/legal-hold/list - Holds of a user or of everyone
+pub async fn list_holds(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<HoldListQuery>,
+) -> Json<ApiResponse<Vec<HoldResponse>>> {
+    if !current_user.can_audit() {
+        record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let mut select = legal_hold::Entity::find();
+    if let Some(username) = query.username.as_deref().filter(|u| !u.is_empty()) {
+        select = select.filter(legal_hold::Column::Username.eq(username));
+    }
+    match select
+        .order_by_asc(legal_hold::Column::Username)
+        .order_by_asc(legal_hold::Column::Path)
+        .all(&*db)
+        .await
+    {
+        Ok(holds) => Json(ApiResponse::success(holds.into_iter().map(Into::into).collect())),
+        Err(e) => {
+            tracing::error!("Failed to list legal holds: {}", e);
+            Json(ApiResponse::error(500, "查询法律保留失败"))
+        }
+    }
+}
+
+/// Whether `path` of `username` is on disk or in the trash
+async fn path_exists(state: &AppState, db: &DatabaseConnection, username: &str, path: &str) -> Result<bool, DbErr> {
+    let on_disk = resolve_in_user_root(&state.config, username, path).is_some_and(|full| full.exists());
+    if on_disk {
+        return Ok(true);
+    }
+    let items = trash::Entity::find()
+        .filter(trash::Column::Username.eq(username))
+        .all(db)
+        .await?;
+    Ok(items.iter().any(|item| trash_item_path(item) == path))
+}
+
+/// POST /api/legal-hold/set - Put a file or folder on hold
+pub async fn set_hold(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<SetHoldRequest>,
+) -> Json<ApiResponse<()>> {
+    if !current_user.can_audit() {
+        record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+    let reason = req.reason.trim();
+    if reason.chars().count() > MAX_REASON_LENGTH {
+        return Json(ApiResponse::error(400, format!("保留原因不能超过{}个字符", MAX_REASON_LENGTH)));
+    }
+
+    let path = normalize(&req.path);
+    let result = async {
+        if !path_exists(&state, &db, &req.username, &path).await? {
+            return Ok(None);
+        }
+        let existing = legal_hold::Entity::find()
+            .filter(legal_hold::Column::Username.eq(&req.username))
+            .filter(legal_hold::Column::Path.eq(&path))
+            .one(&*db)
+            .await?;
+        match existing {
+            // Setting a hold again updates its reason
+            Some(hold) => {
+                let mut hold: legal_hold::ActiveModel = hold.into();
+                hold.reason = Set(reason.to_string());
+                hold.update(&*db).await?;
+            }
+            None => {
+                legal_hold::ActiveModel {
+                    username: Set(req.username.clone()),
+                    path: Set(path.clone()),
+                    reason: Set(reason.to_string()),
+                    created_by: Set(current_user.username.clone()),
+                    create_time: Set(chrono::Utc::now().timestamp()),
+                    ..Default::default()
+                }
+                .insert(&*db)
+                .await?;
+            }
+        }
+        Ok::<_, DbErr>(Some(()))
+    }
+    .await;
+
+    match result {
+        Ok(Some(())) => {
+            let op_desc = format!("{}:{} ({})", req.username, path, reason);
+            log_admin_operation(&current_user.username, OpType::SetLegalHold, &op_desc, OP_SUCCESS, None);
+            Json(ApiResponse::success_msg("已设置法律保留"))
+        }
+        Ok(None) => Json(ApiResponse::error(404, "文件不存在")),
+        Err(e) => {
+            tracing::error!("Failed to set legal hold: {}", e);
+            Json(ApiResponse::error(500, "设置法律保留失败"))
+        }
+    }
+}
+
+/// POST /api/legal-hold/release - Release the hold of a file or folder
+pub async fn release_hold(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<ReleaseHoldRequest>,
+) -> Json<ApiResponse<()>> {
+    if !current_user.can_audit() {
+        record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let path = normalize(&req.path);
+    let result = legal_hold::Entity::delete_many()
+        .filter(legal_hold::Column::Username.eq(&req.username))
+        .filter(legal_hold::Column::Path.eq(&path))
+        .exec(&*db)
+        .await;
+    match result {
+        Ok(res) if res.rows_affected == 0 => Json(ApiResponse::error(404, "该文件未设置法律保留")),
+        Ok(_) => {
+            let op_desc = format!("{}:{}", req.username, path);
+            log_admin_operation(&current_user.username, OpType::ReleaseLegalHold, &op_desc, OP_SUCCESS, None);
+            Json(ApiResponse::success_msg("已解除法律保留"))
+        }
+        Err(e) => {
+            tracing::error!("Failed to release legal hold: {}", e);
+            Json(ApiResponse::error(500, "解除法律保留失败"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission::perm;
+    use crate::service::{FileError, FileService};
+    use crate::testing::TestEnv;
+
+    #[tokio::test]
+    async fn test_hold_blocks_changes() {
+        let env = TestEnv::new().await;
+        let service = FileService::new(&env.config, &env.db, "alice");
+        service.mkdir("/", None, "case").await.unwrap();
+        service.mkdir("/", None, "other").await.unwrap();
+        let tmp = env.dir.join("upload.tmp");
+        for name in ["a.txt", "b.txt"] {
+            std::fs::write(&tmp, b"evidence").unwrap();
+            service.upload_finalize(&tmp, "/case", None, name, 8, None).await.unwrap();
+        }
+
+        let auditor = env.user("carol", &[perm::AUDIT]);
+        let set = |path: &str| {
+            let req = SetHoldRequest {
+                username: "alice".to_string(),
+                path: path.to_string(),
+                reason: "litigation".to_string(),
+            };
+            set_hold(State(env.state()), Extension(env.db_conn()), Extension(auditor.clone()), Json(req))
+        };
+        assert!(set("/case/a.txt").await.code);
+        assert!(!set("/case/missing.txt").await.code);
+        let user = env.user("alice", &[perm::FILE]);
+        let req = SetHoldRequest { username: "alice".to_string(), path: "/other".to_string(), reason: String::new() };
+        assert!(!set_hold(State(env.state()), Extension(env.db_conn()), Extension(user), Json(req)).await.code);
+
+        // The held file, and the folder holding it, are kept
+        assert!(matches!(service.delete("/case", "a.txt").await, Err(FileError::LegalHold(_))));
+        assert!(matches!(service.rename("/case/a.txt", "c.txt").await, Err(FileError::LegalHold(_))));
+        assert!(matches!(service.rename("/case", "closed").await, Err(FileError::LegalHold(_))));
+        assert!(matches!(service.delete("/", "case").await, Err(FileError::LegalHold(_))));
+        std::fs::write(&tmp, b"forged").unwrap();
+        assert!(matches!(
+            service.upload_finalize(&tmp, "/case", None, "a.txt", 6, None).await,
+            Err(FileError::LegalHold(_))
+        ));
+        assert_eq!(std::fs::read(env.config.root_dir.join("alice/case/a.txt")).unwrap(), b"evidence");
+
+        // Its siblings are not
+        service.rename("/case/b.txt", "c.txt").await.unwrap();
+        service.delete("/case", "c.txt").await.unwrap();
+
+        let req = ReleaseHoldRequest { username: "alice".to_string(), path: "case/a.txt".to_string() };
+        assert!(release_hold(Extension(env.db_conn()), Extension(auditor.clone()), Json(req)).await.code);
+        service.delete("/", "case").await.unwrap();
+
+        env.close().await;
+    }
+}
@@ -0,0 +1,279 @@
+//! Sorting, filtering and paging of directory listings
+//!
+//! `/api/file/list` takes `sortBy` (`name`, `size`, `mtime` or `type`),
+//! `order` (`asc` or `desc`), `filter`, `offset` and `limit`, so a directory
+//! of tens of thousands of entries is sent a page at a time rather than
+//! whole. Directories come before files whatever the order, and entries
+//! that compare equal are ordered by name, so pages don't overlap.
+//!
+//! A filter with `*` or `?` is a glob matched against names of files and
+//! directories, anything else a list of extensions (`pdf`, `.jpg,.png`)
+//! files are kept by. Both ignore case.
+
+use regex::Regex;
+use std::cmp::Ordering;
+
+use crate::mime;
+use crate::service::file::DirectoryItem;
+
+/// Response header with the number of entries before paging
+pub const TOTAL_COUNT: &str = "x-total-count";
+
+/// Key entries are sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SortBy {
+    Name,
+    Size,
+    Mtime,
+    Type,
+}
+
+impl SortBy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "name" => Some(Self::Name),
+            "size" => Some(Self::Size),
+            "mtime" => Some(Self::Mtime),
+            "type" => Some(Self::Type),
+            _ => None,
+        }
+    }
+}
+
+/// Entries kept in a listing
+#[derive(Debug)]
+pub enum Filter {
+    /// Names matching a glob
+    Glob(Regex),
+    /// Files with one of these extensions, lowercase without the dot
+    Extensions(Vec<String>),
+}
+
+impl Filter {
+    pub fn parse(filter: &str) -> Result<Self, String> {
+        let filter = filter.trim();
+        if filter.contains(['*', '?']) {
+            let pattern: String = filter
+                .chars()
+                .map(|c| match c {
+                    '*' => ".*".to_string(),
+                    '?' => ".".to_string(),
+                    c => regex::escape(&c.to_string()),
+                })
+                .collect();
+            return Regex::new(&format!("(?i)^{}$", pattern))
+                .map(Self::Glob)
+                .map_err(|e| format!("invalid filter: {}", e));
+        }
+        let extensions: Vec<String> = filter
+            .split(',')
+            .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect();
+        if extensions.is_empty() {
+            return Err("invalid filter: no extension".to_string());
+        }
+        Ok(Self::Extensions(extensions))
+    }
+
+    pub fn matches(&self, item: &DirectoryItem) -> bool {
+        match self {
+            Self::Glob(glob) => glob.is_match(&item.basename),
+            Self::Extensions(extensions) => {
+                !is_dir(item) && extensions.contains(&mime::extension(&item.basename))
+            }
+        }
+    }
+}
+
+/// How a listing is arranged
+#[derive(Debug)]
+pub struct Arrangement {
+    pub sort_by: SortBy,
+    pub descending: bool,
+    pub filter: Option<Filter>,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl Arrangement {
+    /// Arrangement of the query parameters, an error message for invalid ones
+    pub fn parse(
+        sort_by: Option<&str>,
+        order: Option<&str>,
+        filter: Option<&str>,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Self, String> {
+        let sort_by = match sort_by {
+            Some(value) => SortBy::parse(value).ok_or_else(|| format!("invalid sortBy: {}", value))?,
+            None => SortBy::Name,
+        };
+        let descending = match order.map(str::to_ascii_lowercase).as_deref() {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(value) => return Err(format!("invalid order: {}", value)),
+        };
+        let filter = filter.filter(|f| !f.trim().is_empty()).map(Filter::parse).transpose()?;
+        Ok(Self { sort_by, descending, filter, offset, limit })
+    }
+
+    /// Filter and sort `items`, returning the page asked for and the number
+    /// of entries kept by the filter
+    pub fn apply(&self, mut items: Vec<DirectoryItem>) -> (Vec<DirectoryItem>, usize) {
+        if let Some(filter) = &self.filter {
+            items.retain(|item| filter.matches(item));
+        }
+        items.sort_by(|a, b| self.compare(a, b));
+        let total = items.len();
+        let page = items
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect();
+        (page, total)
+    }
+
+    fn compare(&self, a: &DirectoryItem, b: &DirectoryItem) -> Ordering {
+        let by_key = match self.sort_by {
+            SortBy::Name => by_name(a, b),
+            SortBy::Size => a.size.cmp(&b.size),
+            // Listing times are all of the same ISO 8601 format
+            SortBy::Mtime => a.lastmod.cmp(&b.lastmod),
+            SortBy::Type => mime::extension(&a.basename).cmp(&mime::extension(&b.basename)),
+        };
+        is_dir(b)
+            .cmp(&is_dir(a))
+            .then(if self.descending { by_key.reverse() } else { by_key })
+            .then_with(|| by_name(a, b))
+    }
+
+    /// Whether the page depends on the recursive sizes of directories
+    pub fn needs_sizes(&self) -> bool {
+        self.sort_by == SortBy::Size
+    }
+
+    /// Tag of the arrangement, part of the ETag of the listing
+    pub fn tag(&self) -> String {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        (self.sort_by, self.descending, self.offset, self.limit).hash(&mut hasher);
+        match &self.filter {
+            Some(Filter::Glob(glob)) => glob.as_str().hash(&mut hasher),
+            Some(Filter::Extensions(extensions)) => extensions.hash(&mut hasher),
+            None => {}
+        }
+        format!("-{:x}", hasher.finish())
+    }
+}
+
+fn is_dir(item: &DirectoryItem) -> bool {
+    item.item_type == "directory"
+}
+
+fn by_name(a: &DirectoryItem, b: &DirectoryItem) -> Ordering {
+    a.basename
+        .to_lowercase()
+        .cmp(&b.basename.to_lowercase())
+        .then_with(|| a.basename.cmp(&b.basename))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    fn item(name: &str, dir: bool, size: i64, lastmod: &str) -> DirectoryItem {
+        DirectoryItem {
+            basename: name.to_string(),
+            filename: format!("/{}", name),
+            item_type: if dir { "directory" } else { "file" }.to_string(),
+            size,
+            lastmod: lastmod.to_string(),
+            mime: String::new(),
+            tier: None,
+            thumbnail: false,
+            size_pending: false,
+        }
+    }
+
+    fn items() -> Vec<DirectoryItem> {
+        vec![
+            item("b.txt", false, 30, "2024-01-03T00:00:00Z"),
+            item("Photos", true, 4096, "2024-01-01T00:00:00Z"),
+            item("a.PDF", false, 10, "2024-01-02T00:00:00Z"),
+            item("c.jpg", false, 20, "2024-01-04T00:00:00Z"),
+            item("archive", true, 4096, "2024-01-05T00:00:00Z"),
+        ]
+    }
+
+    fn names(arrangement: &Arrangement) -> Vec<String> {
+        arrangement.apply(items()).0.into_iter().map(|item| item.basename).collect()
+    }
+
+    fn arrangement(sort_by: &str, order: &str, filter: Option<&str>) -> Arrangement {
+        Arrangement::parse(Some(sort_by), Some(order), filter, 0, None).unwrap()
+    }
+
+    #[test]
+    fn test_sort() {
+        assert_eq!(names(&arrangement("name", "asc", None)), ["archive", "Photos", "a.PDF", "b.txt", "c.jpg"]);
+        assert_eq!(names(&arrangement("name", "desc", None)), ["Photos", "archive", "c.jpg", "b.txt", "a.PDF"]);
+        assert_eq!(names(&arrangement("size", "desc", None)), ["archive", "Photos", "b.txt", "c.jpg", "a.PDF"]);
+        assert_eq!(names(&arrangement("mtime", "desc", None)), ["archive", "Photos", "c.jpg", "b.txt", "a.PDF"]);
+        assert_eq!(names(&arrangement("type", "asc", None)), ["archive", "Photos", "c.jpg", "a.PDF", "b.txt"]);
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(names(&arrangement("name", "asc", Some("pdf, .JPG"))), ["a.PDF", "c.jpg"]);
+        assert_eq!(names(&arrangement("name", "asc", Some("?.*"))), ["a.PDF", "b.txt", "c.jpg"]);
+        assert_eq!(names(&arrangement("name", "asc", Some("ph*"))), ["Photos"]);
+        // Regex characters are taken literally
+        assert!(names(&arrangement("name", "asc", Some("(a).*"))).is_empty());
+        assert!(Arrangement::parse(None, None, Some(" , "), 0, None).is_err());
+        assert!(Arrangement::parse(Some("owner"), None, None, 0, None).is_err());
+        assert!(Arrangement::parse(None, Some("up"), None, 0, None).is_err());
+    }
+
+    #[test]
+    fn test_page() {
+        let arrangement = Arrangement::parse(None, None, None, 1, Some(2)).unwrap();
+        let (page, total) = arrangement.apply(items());
+        assert_eq!(total, 5);
+        let names: Vec<_> = page.iter().map(|item| item.basename.as_str()).collect();
+        assert_eq!(names, ["Photos", "a.PDF"]);
+        let past = Arrangement::parse(None, None, None, 10, Some(2)).unwrap();
+        let (page, total) = past.apply(items());
+        assert!(page.is_empty());
+        assert_eq!(total, 5);
+        assert_ne!(arrangement.tag(), past.tag());
+    }
+
+    #[tokio::test]
+    async fn test_listing() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        for (name, data) in [("c.txt", &b"ccc"[..]), ("a.txt", b"a"), ("b.md", b"bb")] {
+            assert!(admin.upload("/", name, data).await.status().is_success());
+        }
+        let list = |query: &'static str| {
+            let admin = &admin;
+            async move {
+                let res = admin.get(&format!("/api/file/list?path=/&{}", query)).await;
+                let total = res.headers()[TOTAL_COUNT].to_str().unwrap().parse::<usize>().unwrap();
+                let items: Vec<serde_json::Value> = res.json().await.unwrap();
+                let names: Vec<String> = items.iter().map(|item| item["basename"].as_str().unwrap().to_string()).collect();
+                (names, total)
+            }
+        };
+
+        assert_eq!(list("sortBy=size&order=desc").await, (vec!["c.txt".into(), "b.md".into(), "a.txt".into()], 3));
+        assert_eq!(list("offset=1&limit=1").await, (vec!["b.md".into()], 3));
+        assert_eq!(list("filter=*.txt&sortBy=name&order=desc").await, (vec!["c.txt".into(), "a.txt".into()], 2));
+        let res = admin.get("/api/file/list?path=/&sortBy=owner").await;
+        assert_eq!(res.status(), 400);
+        app.close().await;
+    }
+}
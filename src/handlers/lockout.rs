@@ -0,0 +1,194 @@
+//! Account lockout
+//!
+//! Failed logins are counted per user in `disk_login_attempt`, for the web
+//! login and WebDAV alike. After `max_failures` of them within the window the
+//! account is locked for `lock_minutes`, whatever password is sent, until the
+//! lock runs out or an administrator unlocks it (`POST /api/user/unlock`). A
+//! successful login forgets earlier failures. Locking and unlocking are
+//! written to the admin audit log.
+//!
+//! Logins of unknown users are not counted, so guessing names doesn't fill
+//! the table; the login rate limit slows those down per client IP.
+
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set,
+};
+
+use crate::config::LockoutConfig;
+use crate::entity::login_attempt;
+use crate::entity::op_log::OpType;
+use crate::handlers::audit::service::log_admin_operation;
+
+const OP_SUCCESS: &str = "成功";
+
+/// End of the lock of `username` (Unix timestamp), if it is locked now
+pub async fn locked_until(db: &DatabaseConnection, username: &str) -> Result<Option<i64>, DbErr> {
+    let now = chrono::Utc::now().timestamp();
+    let attempt = login_attempt::Entity::find()
+        .filter(login_attempt::Column::Username.eq(username))
+        .one(db)
+        .await?;
+    Ok(attempt.map(|a| a.locked_until).filter(|until| *until > now))
+}
+
+/// Count a failed login of `username` from `ip`
+///
+/// Returns the end of the lock if this failure locked the account.
+pub async fn record_failure(
+    db: &DatabaseConnection,
+    config: &LockoutConfig,
+    username: &str,
+    ip: Option<&str>,
+) -> Result<Option<i64>, DbErr> {
+    if config.max_failures == 0 {
+        return Ok(None);
+    }
+    let now = chrono::Utc::now().timestamp();
+    let window = (config.window_minutes * 60) as i64;
+    let existing = login_attempt::Entity::find()
+        .filter(login_attempt::Column::Username.eq(username))
+        .one(db)
+        .await?;
+
+    let (mut active, failures) = match existing {
+        // Still locked, the attempt was refused before checking the password
+        Some(attempt) if attempt.locked_until > now => return Ok(None),
+        // Counting on in the window, unless a lock ran out since
+        Some(attempt) if attempt.locked_until == 0 && now - attempt.first_failure < window => {
+            let failures = attempt.failures + 1;
+            let mut active: login_attempt::ActiveModel = attempt.into();
+            active.failures = Set(failures);
+            (active, failures)
+        }
+        Some(attempt) => {
+            let mut active: login_attempt::ActiveModel = attempt.into();
+            active.failures = Set(1);
+            active.first_failure = Set(now);
+            active.locked_until = Set(0);
+            (active, 1)
+        }
+        None => {
+            let active = login_attempt::ActiveModel {
+                username: Set(username.to_string()),
+                failures: Set(1),
+                first_failure: Set(now),
+                locked_until: Set(0),
+                ..Default::default()
+            };
+            (active, 1)
+        }
+    };
+    active.last_ip = Set(ip.map(str::to_string));
+
+    let locked = failures >= config.max_failures as i32;
+    let until = now + (config.lock_minutes * 60) as i64;
+    if locked {
+        active.locked_until = Set(until);
+    }
+    active.save(db).await?;
+
+    if !locked {
+        return Ok(None);
+    }
+    tracing::warn!("Account {} locked after {} failed logins", username, failures);
+    let op_desc = format!("连续{}次登录失败，锁定{}分钟", failures, config.lock_minutes);
+    log_admin_operation(username, OpType::LockUser, &op_desc, OP_SUCCESS, ip);
+    Ok(Some(until))
+}
+
+/// Forget the failed logins of `username`
+pub async fn clear(db: &DatabaseConnection, username: &str) -> Result<(), DbErr> {
+    login_attempt::Entity::delete_many()
+        .filter(login_attempt::Column::Username.eq(username))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Lift the lock of `username`, returning whether it was locked
+pub async fn unlock(db: &DatabaseConnection, username: &str) -> Result<bool, DbErr> {
+    let locked = locked_until(db, username).await?.is_some();
+    clear(db, username).await?;
+    Ok(locked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestApp, TestEnv};
+
+    #[tokio::test]
+    async fn test_lockout() {
+        let env = TestEnv::new().await;
+        let config = LockoutConfig {
+            max_failures: 3,
+            window_minutes: 15,
+            lock_minutes: 15,
+        };
+
+        for _ in 0..2 {
+            assert_eq!(record_failure(&env.db, &config, "alice", Some("10.0.0.1")).await.unwrap(), None);
+        }
+        assert_eq!(locked_until(&env.db, "alice").await.unwrap(), None);
+        let until = record_failure(&env.db, &config, "alice", Some("10.0.0.1")).await.unwrap();
+        assert!(until.is_some());
+        assert_eq!(locked_until(&env.db, "alice").await.unwrap(), until);
+        // Attempts while locked don't extend the lock
+        assert_eq!(record_failure(&env.db, &config, "alice", None).await.unwrap(), None);
+        assert_eq!(locked_until(&env.db, "alice").await.unwrap(), until);
+        // Other accounts are not affected
+        assert_eq!(locked_until(&env.db, "bob").await.unwrap(), None);
+
+        assert!(unlock(&env.db, "alice").await.unwrap());
+        assert!(!unlock(&env.db, "alice").await.unwrap());
+        assert_eq!(locked_until(&env.db, "alice").await.unwrap(), None);
+
+        // A lock that ran out starts the count over
+        record_failure(&env.db, &config, "bob", None).await.unwrap();
+        let attempt = login_attempt::Entity::find().one(&env.db).await.unwrap().unwrap();
+        let mut active: login_attempt::ActiveModel = attempt.into();
+        active.locked_until = Set(chrono::Utc::now().timestamp() - 1);
+        active.failures = Set(3);
+        active.update(&env.db).await.unwrap();
+        assert_eq!(record_failure(&env.db, &config, "bob", None).await.unwrap(), None);
+        assert_eq!(locked_until(&env.db, "bob").await.unwrap(), None);
+
+        let off = LockoutConfig { max_failures: 0, ..config };
+        for _ in 0..5 {
+            assert_eq!(record_failure(&env.db, &off, "carol", None).await.unwrap(), None);
+        }
+        env.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_login_lockout_and_unlock() {
+        let app = TestApp::spawn().await;
+        app.add_user("bob", "bob-password", "user").await;
+        let login = |password: &'static str| {
+            let client = app.client();
+            async move {
+                let body = serde_json::json!({ "username": "bob", "password": password });
+                client.post_json("/api/login", &body).await.status()
+            }
+        };
+
+        let max_failures = app.env.config.lockout.max_failures;
+        for _ in 1..max_failures {
+            assert_eq!(login("wrong").await, 400);
+        }
+        assert_eq!(login("wrong").await, 403);
+        // The right password doesn't help while locked
+        assert_eq!(login("bob-password").await, 403);
+
+        let admin = app.admin().await;
+        let res: serde_json::Value = admin
+            .post_json("/api/user/unlock", &serde_json::json!([{ "id": 0, "username": "bob" }]))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(res["code"], true);
+        assert!(app.login("bob", "bob-password").await.get("/api/user/current").await.status().is_success());
+        app.close().await;
+    }
+}
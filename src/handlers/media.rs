@@ -0,0 +1,618 @@
+//! Media streaming
+//!
+//! Audio and video previews are played by `<video>` and `<audio>` elements,
+//! which ask for byte ranges as they buffer and seek. [`stream`] serves them
+//! with their media type and honors `Range`, so a player can jump anywhere
+//! without the whole file being sent. Only the request starting at the
+//! beginning is audited, not every seek ([`is_first_request`]).
+//!
+//! Browsers can't play some containers, such as Matroska and AVI. With
+//! `[media] ffmpeg` set, the files listed in `convert` are remuxed into
+//! fragmented MP4 on the fly, or transcoded to H.264 and AAC with
+//! `transcode`. Converted streams start at the beginning and can't seek.
+//! Each one holds a worker of the job pool while ffmpeg runs, so a stream
+//! the pool has no room for is answered with 503.
+//!
+//! With ffmpeg, videos get a poster frame as thumbnail ([`poster`]), cached
+//! like the thumbnails of images.
+//!
+//! For players and playlists, `GET /api/file/audio/meta` reads the tags of
+//! an audio file (ID3, Vorbis comments, MP4 and RIFF tags) and its duration
+//! without decoding it ([`read_audio_meta`]).
+
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Json, Response},
+    Extension,
+};
+use serde::Serialize;
+use std::ffi::OsString;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+use symphonia::core::units::TimeBase;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use utoipa::ToSchema;
+
+use crate::config::{Config, MediaConfig};
+use crate::error::error_response;
+use crate::handlers::archive_download::parse_range;
+use crate::handlers::file::{get_user_path, locate, resolve_in_user_root, PathQuery};
+use crate::handlers::thumbnail;
+use crate::handlers::tiering;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::mime;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+use crate::task::TASK_MANAGER;
+
+/// Extensions of the audio files whose metadata is read
+const AUDIO_EXTENSIONS: [&str; 7] = ["mp3", "flac", "m4a", "ogg", "opus", "wav", "aac"];
+
+/// Extensions of the video files with poster frames
+const VIDEO_EXTENSIONS: [&str; 7] = ["mp4", "m4v", "webm", "ogv", "mov", "mkv", "avi"];
+
+/// Whether a request is the first one of a playback rather than a seek
+///
+/// Players start with the whole file or a range from its first byte.
+pub fn is_first_request(headers: &HeaderMap) -> bool {
+    match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(range) => range
+            .trim()
+            .strip_prefix("bytes=")
+            .and_then(|spec| spec.split_once('-'))
+            .is_some_and(|(start, _)| start.trim() == "0"),
+        None => true,
+    }
+}
+
+/// Whether files with the extension `ext` are converted before streaming
+pub fn converts(config: &MediaConfig, ext: &str) -> bool {
+    !config.ffmpeg.is_empty() && config.convert.iter().any(|e| e.eq_ignore_ascii_case(ext))
+}
+
+/// Serve the media file at `path` to `username`, converted if `[media]`
+/// says so
+pub async fn stream(
+    config: &MediaConfig,
+    username: &str,
+    path: &Path,
+    ext: &str,
+    content_type: &str,
+    headers: &HeaderMap,
+) -> Response {
+    if converts(config, ext) {
+        convert(config, username, path).await
+    } else {
+        serve_range(path, content_type, headers).await
+    }
+}
+
+/// Serve a file, or the byte range asked for
+async fn serve_range(path: &Path, content_type: &str, headers: &HeaderMap) -> Response {
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(f) => f,
+        Err(e) => {
+            tracing::error!("Failed to open media file: {}", e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to open file");
+        }
+    };
+    let len = match file.metadata().await {
+        Ok(m) => m.len(),
+        Err(e) => {
+            tracing::error!("Failed to stat media file: {}", e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to open file");
+        }
+    };
+    let filename = path.file_name().and_then(|n| n.to_str()).unwrap_or("media");
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_DISPOSITION, format!("inline; filename=\"{}\"", filename))
+        .header(header::ACCEPT_RANGES, "bytes");
+
+    let Some(value) = headers.get(header::RANGE).and_then(|v| v.to_str().ok()) else {
+        return builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, len)
+            .body(Body::from_stream(ReaderStream::new(file)))
+            .unwrap();
+    };
+    let Some((start, end)) = parse_range(value, len) else {
+        return Response::builder()
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+            .body(Body::empty())
+            .unwrap();
+    };
+    if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+        tracing::error!("Failed to seek media file: {}", e);
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to read file");
+    }
+    builder
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+        .header(header::CONTENT_LENGTH, end - start + 1)
+        .body(Body::from_stream(ReaderStream::new(file.take(end - start + 1))))
+        .unwrap()
+}
+
+/// Arguments of ffmpeg writing `path` as fragmented MP4 to its output
+fn ffmpeg_args(path: &Path, transcode: bool) -> Vec<OsString> {
+    let mut args: Vec<OsString> = ["-hide_banner", "-loglevel", "error", "-i"].map(OsString::from).to_vec();
+    args.push(path.as_os_str().to_owned());
+    // The first video and audio stream, either may be missing
+    args.extend(["-map", "0:v:0?", "-map", "0:a:0?"].map(OsString::from));
+    let codecs: &[&str] = if transcode {
+        &["-c:v", "libx264", "-preset", "veryfast", "-c:a", "aac"]
+    } else {
+        &["-c", "copy"]
+    };
+    args.extend(codecs.iter().map(OsString::from));
+    // Playable while it is written, without seeking back to the header
+    args.extend(["-movflags", "frag_keyframe+empty_moov+default_base_moof", "-f", "mp4", "pipe:1"].map(OsString::from));
+    args
+}
+
+/// Whether files with the extension `ext` get a poster frame as thumbnail
+pub fn has_poster(config: &MediaConfig, ext: &str) -> bool {
+    !config.ffmpeg.is_empty() && VIDEO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())
+}
+
+/// Arguments of ffmpeg writing a poster frame of the video at `path` to the
+/// JPEG `output`
+///
+/// The frame is the most typical of the first ones rather than the very
+/// first, which is often black.
+fn poster_args(path: &Path, output: &Path) -> Vec<OsString> {
+    let mut args: Vec<OsString> = ["-hide_banner", "-loglevel", "error", "-nostdin", "-i"].map(OsString::from).to_vec();
+    args.push(path.as_os_str().to_owned());
+    args.extend(["-map", "0:v:0", "-an", "-sn", "-vf", "thumbnail=50", "-frames:v", "1", "-y"].map(OsString::from));
+    args.push(output.as_os_str().to_owned());
+    args
+}
+
+/// Path of the poster frame of the video at `path`, extracted by ffmpeg now
+/// if it isn't cached yet
+///
+/// Callers hold a worker of the job pool, as thumbnails do.
+pub async fn poster(config: &Config, path: &Path) -> anyhow::Result<PathBuf> {
+    thumbnail::derived(config, "poster", path, |output| async move {
+        let mut command = tokio::process::Command::new(&config.media.ffmpeg);
+        command.args(poster_args(path, &output));
+        thumbnail::run_converter(command, &output).await
+    })
+    .await
+}
+
+/// Stream `path` converted to MP4 by ffmpeg, on a worker of the job pool
+/// taken for `username`
+///
+/// ffmpeg stops when the client goes away and the output can't be written,
+/// which frees the worker.
+async fn convert(config: &MediaConfig, username: &str, path: &Path) -> Response {
+    let Ok(worker) = TASK_MANAGER.jobs().worker(username).await else {
+        return thumbnail::busy_response();
+    };
+    let child = tokio::process::Command::new(&config.ffmpeg)
+        .args(ffmpeg_args(path, config.transcode))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            tracing::error!("Failed to start {}: {}", config.ffmpeg, e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to convert media");
+        }
+    };
+    let Some(stdout) = child.stdout.take() else {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to convert media");
+    };
+    tokio::spawn(async move {
+        let _worker = worker;
+        match child.wait().await {
+            Ok(status) if !status.success() => tracing::debug!("ffmpeg exited with {}", status),
+            Ok(_) => {}
+            Err(e) => tracing::error!("Failed to wait for ffmpeg: {}", e),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .header(header::CONTENT_DISPOSITION, "inline")
+        .header(header::ACCEPT_RANGES, "none")
+        .body(Body::from_stream(ReaderStream::new(stdout)))
+        .unwrap()
+}
+
+/// Tags and stream properties of an audio file
+///
+/// Fields the file doesn't have are left out.
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct AudioMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    #[serde(rename = "albumArtist", skip_serializing_if = "Option::is_none")]
+    pub album_artist: Option<String>,
+    /// Track number as tagged, such as `3` or `3/12`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    /// Length in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(rename = "sampleRate", skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<usize>,
+    /// Short name of the codec, such as `mp3` or `flac`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+}
+
+impl AudioMeta {
+    /// Take the tags of `revision` that aren't set yet
+    fn add_tags(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let field = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut self.title,
+                Some(StandardTagKey::Artist) => &mut self.artist,
+                Some(StandardTagKey::Album) => &mut self.album,
+                Some(StandardTagKey::AlbumArtist) => &mut self.album_artist,
+                Some(StandardTagKey::TrackNumber) => &mut self.track,
+                Some(StandardTagKey::Date) => &mut self.date,
+                Some(StandardTagKey::Genre) => &mut self.genre,
+                _ => continue,
+            };
+            let value = tag.value.to_string();
+            let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+            if field.is_none() && !value.is_empty() {
+                *field = Some(value.to_string());
+            }
+        }
+    }
+}
+
+/// Read the tags and duration of the audio file at `path`
+///
+/// Tags found before the container, such as ID3 tags of MP3 files, come
+/// first; the container's own fill in the rest.
+pub fn read_audio_meta(path: &Path) -> anyhow::Result<AudioMeta> {
+    let file = std::fs::File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(&mime::extension(&path.to_string_lossy()));
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let mut meta = AudioMeta::default();
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        meta.add_tags(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        meta.add_tags(revision);
+    }
+    if let Some(track) = probed.format.default_track() {
+        let params = &track.codec_params;
+        meta.sample_rate = params.sample_rate;
+        meta.channels = params.channels.map(|c| c.count());
+        meta.codec = symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|codec| codec.short_name.to_string());
+        let time_base = params.time_base.or(params.sample_rate.map(|rate| TimeBase::new(1, rate)));
+        if let (Some(time_base), Some(frames)) = (time_base, params.n_frames) {
+            let time = time_base.calc_time(frames);
+            meta.duration = Some(time.seconds as f64 + time.frac);
+        }
+    }
+    Ok(meta)
+}
+
+/// GET /api/file/audio/meta - Tags and duration of an audio file
+#[utoipa::path(
+    get,
+    path = "/api/file/audio/meta",
+    tag = "file",
+    params(PathQuery),
+    responses((status = 200, body = ApiResponse<AudioMeta>)),
+)]
+pub async fn audio_meta(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<PathQuery>,
+) -> Json<ApiResponse<AudioMeta>> {
+    if !AUDIO_EXTENSIONS.contains(&mime::extension(&query.path).as_str()) {
+        return Json(ApiResponse::error(400, "不支持的音频格式"));
+    }
+    let location = match locate(&state, &db, &current_user, &query.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    let Some(file_path) = resolve_in_user_root(&state.config, &location.owner, &location.path).filter(|p| p.is_file())
+    else {
+        return Json(ApiResponse::error(404, "文件不存在"));
+    };
+    let root = get_user_path(&state.config, &location.owner);
+    if let Err(e) = tiering::recall(&db, &location.owner, &root, &location.path).await {
+        tracing::error!("Failed to recall {} of {}: {}", location.path, location.owner, e);
+        return Json(ApiResponse::error(500, "failed to recall file from cold storage"));
+    }
+
+    match tokio::task::spawn_blocking(move || read_audio_meta(&file_path)).await {
+        Ok(Ok(meta)) => Json(ApiResponse::success(meta)),
+        Ok(Err(e)) => {
+            tracing::debug!("Failed to read audio metadata of {}: {}", query.path, e);
+            Json(ApiResponse::error(415, "无法读取音频信息"))
+        }
+        Err(e) => {
+            tracing::error!("Audio metadata worker failed: {}", e);
+            Json(ApiResponse::error(500, "internal error"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    #[test]
+    fn test_is_first_request() {
+        let range = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::RANGE, value.parse().unwrap());
+            headers
+        };
+        assert!(is_first_request(&HeaderMap::new()));
+        assert!(is_first_request(&range("bytes=0-")));
+        assert!(is_first_request(&range("bytes=0-1023")));
+        assert!(!is_first_request(&range("bytes=1048576-")));
+        assert!(!is_first_request(&range("bytes=-500")));
+    }
+
+    #[test]
+    fn test_convert_args() {
+        let config = MediaConfig { ffmpeg: "/usr/bin/ffmpeg".to_string(), ..MediaConfig::default() };
+        assert!(converts(&config, "MKV"));
+        assert!(!converts(&config, "mp4"));
+        assert!(!converts(&MediaConfig::default(), "mkv"));
+
+        let args = |transcode| {
+            ffmpeg_args(Path::new("/data/a b.mkv"), transcode)
+                .into_iter()
+                .map(|a| a.into_string().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let remux = args(false);
+        assert!(remux.contains(&"/data/a b.mkv".to_string()));
+        assert!(remux.windows(2).any(|w| w == ["-c", "copy"]));
+        assert_eq!(remux.last().unwrap(), "pipe:1");
+        assert!(args(true).windows(2).any(|w| w == ["-c:v", "libx264"]));
+    }
+
+    #[test]
+    fn test_poster_args() {
+        let config = MediaConfig { ffmpeg: "/usr/bin/ffmpeg".to_string(), ..MediaConfig::default() };
+        assert!(has_poster(&config, "MOV"));
+        assert!(!has_poster(&config, "mp3"));
+        assert!(!has_poster(&MediaConfig::default(), "mp4"));
+
+        let args: Vec<_> = poster_args(Path::new("/data/clip.mp4"), Path::new("/cache/p.jpg"))
+            .into_iter()
+            .map(|a| a.into_string().unwrap())
+            .collect();
+        assert!(args.windows(2).any(|w| w == ["-i", "/data/clip.mp4"]));
+        assert!(args.windows(2).any(|w| w == ["-frames:v", "1"]));
+        assert_eq!(args.last().unwrap(), "/cache/p.jpg");
+    }
+
+    #[tokio::test]
+    async fn test_poster_thumbnail() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stands in for ffmpeg, writing a 64x36 frame to the last argument
+        let dir = std::env::temp_dir().join(format!("poster-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let frame = dir.join("frame.jpg");
+        image::RgbImage::from_pixel(64, 36, image::Rgb([10, 10, 10])).save(&frame).unwrap();
+        let script = dir.join("ffmpeg");
+        let body = format!("#!/bin/sh\nfor last; do :; done\ncp {} \"$last\"\n", frame.display());
+        std::fs::write(&script, body).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let ffmpeg = script.display().to_string();
+        let app = TestApp::spawn_with(|app_config| app_config.media.ffmpeg = ffmpeg).await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "clip.mp4", b"not really a video").await.status().is_success());
+        assert!(admin.upload("/", "song.mp3", b"not really a song").await.status().is_success());
+
+        let res = admin.get("/api/file/thumbnail?path=/clip.mp4&size=32").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let thumb = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (32, 18));
+
+        let items: serde_json::Value = admin.get("/api/file/list?path=/").await.json().await.unwrap();
+        let thumbnail = |name: &str| {
+            let item = items.as_array().unwrap().iter().find(|item| item["basename"] == name).unwrap();
+            item.get("thumbnail").cloned()
+        };
+        assert_eq!(thumbnail("clip.mp4"), Some(serde_json::json!(true)));
+        assert_eq!(thumbnail("song.mp3"), None);
+        app.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// One second of 8 kHz stereo silence, with RIFF INFO tags
+    fn wav() -> Vec<u8> {
+        let mut info = b"INFO".to_vec();
+        for (id, value) in [(b"INAM", &b"Night Drive\0"[..]), (b"IART", &b"The Band\0"[..])] {
+            info.extend_from_slice(id);
+            info.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            info.extend_from_slice(value);
+            // Chunks are padded to an even length
+            if value.len() % 2 == 1 {
+                info.push(0);
+            }
+        }
+        let mut fmt = Vec::new();
+        for field in [1u16, 2] {
+            fmt.extend_from_slice(&field.to_le_bytes());
+        }
+        fmt.extend_from_slice(&8000u32.to_le_bytes());
+        fmt.extend_from_slice(&32000u32.to_le_bytes());
+        fmt.extend_from_slice(&4u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+
+        let mut body = b"WAVE".to_vec();
+        for (id, chunk) in [(b"fmt ", fmt), (b"LIST", info), (b"data", vec![0u8; 32000])] {
+            body.extend_from_slice(id);
+            body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            body.extend_from_slice(&chunk);
+        }
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        data.extend_from_slice(&body);
+        data
+    }
+
+    /// Silent MPEG frames behind an ID3v2 tag
+    fn mp3() -> Vec<u8> {
+        let mut frames = Vec::new();
+        for (id, text) in [(b"TIT2", "Morning"), (b"TALB", "Demos")] {
+            frames.extend_from_slice(id);
+            frames.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+            frames.extend_from_slice(&[0, 0, 0]);
+            frames.extend_from_slice(text.as_bytes());
+        }
+        let mut data = b"ID3\x03\x00\x00".to_vec();
+        // Synchsafe size, 7 bits per byte
+        let size = frames.len() as u32;
+        data.extend_from_slice(&[(size >> 21) as u8 & 0x7F, (size >> 14) as u8 & 0x7F, (size >> 7) as u8 & 0x7F, size as u8 & 0x7F]);
+        data.extend_from_slice(&frames);
+        // MPEG-1 layer III, 128 kbit/s, 44.1 kHz, mono: 417 bytes of 1152 samples each
+        for _ in 0..100 {
+            data.extend_from_slice(&[0xFF, 0xFB, 0x90, 0xC4]);
+            data.extend_from_slice(&[0u8; 413]);
+        }
+        data
+    }
+
+    #[test]
+    fn test_read_audio_meta() {
+        let dir = std::env::temp_dir().join(format!("audio-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("track.wav");
+        std::fs::write(&path, wav()).unwrap();
+        let meta = read_audio_meta(&path).unwrap();
+        assert_eq!(meta.title.as_deref(), Some("Night Drive"));
+        assert_eq!(meta.artist.as_deref(), Some("The Band"));
+        assert_eq!((meta.sample_rate, meta.channels), (Some(8000), Some(2)));
+        assert_eq!(meta.duration, Some(1.0));
+
+        let path = dir.join("track.mp3");
+        std::fs::write(&path, mp3()).unwrap();
+        let meta = read_audio_meta(&path).unwrap();
+        assert_eq!(meta.title.as_deref(), Some("Morning"));
+        assert_eq!(meta.album.as_deref(), Some("Demos"));
+        assert_eq!(meta.artist, None);
+        assert_eq!(meta.codec.as_deref(), Some("mp3"));
+        let duration = meta.duration.unwrap();
+        assert!((duration - 100.0 * 1152.0 / 44100.0).abs() < 0.1, "{}", duration);
+
+        std::fs::write(&path, b"not audio").unwrap();
+        assert!(read_audio_meta(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_audio_meta() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "song.wav", &wav()).await.status().is_success());
+        assert!(admin.upload("/", "notes.txt", b"la la").await.status().is_success());
+
+        let res: serde_json::Value = admin.get("/api/file/audio/meta?path=/song.wav").await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        assert_eq!(res["data"]["title"], "Night Drive");
+        assert_eq!(res["data"]["sampleRate"], 8000);
+        assert!(res["data"].get("album").is_none());
+        let res: serde_json::Value = admin.get("/api/file/audio/meta?path=/notes.txt").await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        let res: serde_json::Value = admin.get("/api/file/audio/meta?path=/missing.mp3").await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        app.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_stream_range() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let data: Vec<u8> = (0..=255).collect();
+        assert!(admin.upload("/", "clip.mp4", &data).await.status().is_success());
+
+        let url = app.url("/api/file/preview/single?path=/clip.mp4");
+        let res = admin.http.get(&url).send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "video/mp4");
+        assert_eq!(res.headers()[header::ACCEPT_RANGES], "bytes");
+        assert_eq!(res.bytes().await.unwrap().len(), 256);
+
+        let res = admin.http.get(&url).header(header::RANGE, "bytes=16-31").send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes 16-31/256");
+        assert_eq!(res.bytes().await.unwrap().as_ref(), &data[16..32]);
+
+        let res = admin.http.get(&url).header(header::RANGE, "bytes=300-").send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        app.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_converted_stream() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stands in for ffmpeg, writing the converted stream to its output
+        let dir = std::env::temp_dir().join(format!("convert-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("ffmpeg");
+        std::fs::write(&script, "#!/bin/sh\nprintf converted\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let ffmpeg = script.display().to_string();
+        let app = TestApp::spawn_with(|app_config| app_config.media.ffmpeg = ffmpeg).await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "clip.mkv", b"not really a video").await.status().is_success());
+
+        let res = admin.get("/api/file/preview/single?path=/clip.mkv").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "video/mp4");
+        assert_eq!(res.headers()[header::ACCEPT_RANGES], "none");
+        assert_eq!(res.text().await.unwrap(), "converted");
+        app.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
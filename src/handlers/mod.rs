@@ -1,15 +1,66 @@
 //! Request handlers module
 
+pub mod abuse;
+pub mod archive_create;
+pub mod archive_download;
+pub mod archive_extract;
 pub mod archive_preview;
+pub mod artifact;
 pub mod audit;
 pub mod auth;
+pub mod auth_provider;
+pub mod batch;
+pub mod bulk_rename;
+pub mod compression;
 pub mod config;
+pub mod dedup;
 pub mod department;
+pub mod dept_admin;
+pub mod dept_space;
+pub mod device;
+pub mod digest;
+pub mod dir_size;
+pub mod dir_version;
+pub mod dlp;
 pub mod editing;
+pub mod expiry;
 pub mod file;
+pub mod folder_hook;
+pub mod gallery;
 pub mod group;
+pub mod group_space;
+pub mod heic;
+pub mod hot_cache;
+pub mod hr_sync;
+pub mod journal;
+pub mod legal_hold;
+pub mod listing;
+pub mod lockout;
+pub mod media;
+pub mod office_pdf;
+pub mod path;
+pub mod preview;
+pub mod quota;
 pub mod recent;
 pub mod role;
+pub mod scheduler;
+pub mod sensitive;
+pub mod service_account;
 pub mod setup;
+pub mod shredder;
+pub mod signed;
+pub mod storage_area;
+pub mod tag;
 pub mod task;
+pub mod template;
+pub mod thumbnail;
+pub mod tiering;
+pub mod token;
+pub mod traffic;
+pub mod trash;
+pub mod undo;
+pub mod upload_limit;
 pub mod user;
+pub mod user_import;
+pub mod watch;
+pub mod webdav;
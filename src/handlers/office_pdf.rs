@@ -0,0 +1,261 @@
+//! Office documents previewed as PDF
+//!
+//! Installs without a document server can't open Word, Excel and PowerPoint
+//! files in the browser. With `[office_preview] soffice` set, such documents
+//! are converted to PDF by LibreOffice running headless, and
+//! `GET /api/file/preview/pdf` serves the conversion for the browser's PDF
+//! viewer. Conversions are cached with the thumbnails, keyed by the file and
+//! its modification time, so a document is converted once. Documents of
+//! sensitive folders are watermarked after the conversion like other PDFs.
+//!
+//! Every conversion runs in its own LibreOffice profile, on a worker of the
+//! job pool, as each one starts a whole office suite.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Response,
+    Extension,
+};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::config::{Config, OfficePreviewConfig};
+use crate::entity::op_log::OpType;
+use crate::error::error_response;
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::file::{get_user_path, locate, resolve_in_user_root, PathQuery};
+use crate::handlers::sensitive;
+use crate::handlers::thumbnail;
+use crate::handlers::tiering;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::mime;
+use crate::state::AppState;
+use crate::task::TASK_MANAGER;
+use crate::watermark::Kind;
+
+const OP_SUCCESS: &str = "成功";
+
+/// Whether files with the extension `ext` are converted to PDF
+pub fn converts(config: &OfficePreviewConfig, ext: &str) -> bool {
+    !config.soffice.is_empty()
+        && matches!(
+            ext.to_ascii_lowercase().as_str(),
+            "doc" | "docx" | "xls" | "xlsx" | "ppt" | "pptx" | "odt" | "ods" | "odp"
+        )
+}
+
+/// Path of the PDF conversion of the document at `src`, converted now if it
+/// isn't cached yet
+///
+/// Callers hold a worker of the job pool.
+pub async fn converted(config: &Config, src: &Path) -> anyhow::Result<PathBuf> {
+    thumbnail::derived_as(config, "office", "pdf", src, |output| async move {
+        let work = std::env::temp_dir().join(format!("office-pdf-{}", uuid::Uuid::new_v4()));
+        let result = convert(&config.office_preview, src, &work, &output).await;
+        let _ = fs::remove_dir_all(&work).await;
+        result
+    })
+    .await
+}
+
+/// Convert the document at `src` to `output`, working in the directory `work`
+///
+/// soffice names the PDF after its input, so it is given a link to the
+/// document under a fixed name rather than the name users chose.
+async fn convert(config: &OfficePreviewConfig, src: &Path, work: &Path, output: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(work).await?;
+    let input = work.join(format!("document.{}", mime::extension(&src.to_string_lossy())));
+    fs::symlink(src, &input).await?;
+    let out_dir = work.join("out");
+    let mut command = tokio::process::Command::new(&config.soffice);
+    command
+        .arg("--headless")
+        .arg("--norestore")
+        .arg(format!("-env:UserInstallation=file://{}", work.join("profile").display()))
+        .arg("--convert-to")
+        .arg("pdf")
+        .arg("--outdir")
+        .arg(&out_dir)
+        .arg(&input);
+    let pdf = out_dir.join("document.pdf");
+    thumbnail::run_converter(command, &pdf).await?;
+    // The work directory may be on another filesystem than the cache
+    fs::copy(&pdf, output).await?;
+    Ok(())
+}
+
+/// GET /api/file/preview/pdf - An Office document converted to PDF
+#[utoipa::path(
+    get,
+    path = "/api/file/preview/pdf",
+    tag = "file",
+    params(PathQuery),
+    responses(
+        (status = 200, description = "The document as PDF", content_type = "application/pdf"),
+        (status = 404, description = "Conversion to PDF not set up, or no such file"),
+        (status = 413, description = "Document too large to convert"),
+        (status = 415, description = "Not an Office document, or the conversion failed"),
+        (status = 503, description = "Too many conversions waiting, try again later"),
+    ),
+)]
+pub async fn preview_pdf(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<PathQuery>,
+) -> Response {
+    let config = &state.config.office_preview;
+    if config.soffice.is_empty() {
+        return error_response(StatusCode::NOT_FOUND, "office preview not enabled");
+    }
+    if !converts(config, &mime::extension(&query.path)) {
+        return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "not an office document");
+    }
+    let location = match locate(&state, &db, &current_user, &query.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return error_response(status, error),
+    };
+    let Some(file_path) = resolve_in_user_root(&state.config, &location.owner, &location.path) else {
+        return error_response(StatusCode::BAD_REQUEST, "invalid path");
+    };
+    match fs::metadata(&file_path).await {
+        Ok(metadata) if metadata.is_file() => {
+            if metadata.len() > config.max_size_mb * 1024 * 1024 {
+                return error_response(StatusCode::PAYLOAD_TOO_LARGE, "file too large to convert");
+            }
+        }
+        _ => return error_response(StatusCode::NOT_FOUND, "file not found"),
+    }
+    let root = get_user_path(&state.config, &location.owner);
+    if let Err(e) = tiering::recall(&db, &location.owner, &root, &location.path).await {
+        tracing::error!("Failed to recall {} of {}: {}", location.path, location.owner, e);
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to recall file from cold storage");
+    }
+
+    let Ok(worker) = TASK_MANAGER.jobs().worker(&current_user.username).await else {
+        return thumbnail::busy_response();
+    };
+    let pdf = converted(&state.config, &file_path).await;
+    // Watermarking takes a worker of its own
+    drop(worker);
+    let pdf = match pdf {
+        Ok(pdf) => pdf,
+        Err(e) => {
+            tracing::warn!("Failed to convert {:?} to PDF: {}", file_path, e);
+            return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "cannot convert document");
+        }
+    };
+    let clean_path = format!("/{}", query.path.trim_start_matches('/'));
+    log_operation(&current_user.username, OpType::OpenFile, &clean_path, OP_SUCCESS, None);
+    // Named after the document rather than the cache entry
+    let shown = file_path.with_extension("pdf");
+    sensitive::serve_converted(&db, &location, &pdf, &shown, Kind::Pdf, &current_user.username).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+    use axum::http::header;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// An soffice script writing a PDF into `--outdir` and counting its runs
+    /// in `runs`, or failing for documents containing `broken`
+    fn soffice(dir: &Path) -> String {
+        let script = dir.join("soffice.sh");
+        let body = format!(
+            "#!/bin/sh\necho run >> {runs}\nwhile [ $# -gt 1 ]; do\n  [ \"$1\" = --outdir ] && out=\"$2\"\n  shift\ndone\n\
+             grep -q broken \"$1\" && exit 1\nmkdir -p \"$out\"\nname=$(basename \"$1\")\n\
+             printf '%%PDF-1.4 converted' > \"$out/${{name%.*}}.pdf\"\n",
+            runs = dir.join("runs").display()
+        );
+        std::fs::write(&script, body).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        script.display().to_string()
+    }
+
+    fn runs(dir: &Path) -> usize {
+        std::fs::read_to_string(dir.join("runs")).map_or(0, |runs| runs.lines().count())
+    }
+
+    #[test]
+    fn test_converts() {
+        let config = OfficePreviewConfig { soffice: "soffice".to_string(), ..OfficePreviewConfig::default() };
+        assert!(converts(&config, "DOCX"));
+        assert!(converts(&config, "xls"));
+        assert!(!converts(&config, "pdf"));
+        assert!(!converts(&OfficePreviewConfig::default(), "docx"));
+    }
+
+    #[tokio::test]
+    async fn test_converted() {
+        let dir = std::env::temp_dir().join(format!("office-pdf-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config {
+            thumbnail_cache_dir: Some(dir.join("cache")),
+            office_preview: OfficePreviewConfig { soffice: soffice(&dir), ..OfficePreviewConfig::default() },
+            ..Config::default()
+        };
+        let src = dir.join("季度报告 -final.docx");
+        std::fs::write(&src, b"docx").unwrap();
+
+        let pdf = converted(&config, &src).await.unwrap();
+        assert_eq!(std::fs::read(&pdf).unwrap(), b"%PDF-1.4 converted");
+        assert!(pdf.starts_with(dir.join("cache").join("office")));
+        assert_eq!(pdf.extension().unwrap(), "pdf");
+        // Converted once
+        assert_eq!(converted(&config, &src).await.unwrap(), pdf);
+        assert_eq!(runs(&dir), 1);
+
+        let broken = dir.join("broken.xlsx");
+        std::fs::write(&broken, b"broken").unwrap();
+        assert!(converted(&config, &broken).await.is_err());
+        // Nothing is left behind by a failed conversion
+        let entries = std::fs::read_dir(pdf.parent().unwrap()).unwrap().count();
+        assert_eq!(entries, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_preview_pdf() {
+        let dir = std::env::temp_dir().join(format!("office-pdf-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = soffice(&dir);
+        let app = TestApp::spawn_with(|app_config| {
+            app_config.office_preview.soffice = script;
+            app_config.office_preview.max_size_mb = 1;
+        })
+        .await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "report.docx", b"docx").await.status().is_success());
+
+        let res = admin.get("/api/file/preview/pdf?path=/report.docx").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/pdf");
+        assert!(res.headers()[header::CONTENT_DISPOSITION].to_str().unwrap().contains("report.pdf"));
+        assert_eq!(res.bytes().await.unwrap().as_ref(), b"%PDF-1.4 converted");
+
+        let res: serde_json::Value = admin.get("/api/file/preview/info?path=/report.docx").await.json().await.unwrap();
+        assert_eq!(res["kind"], "office");
+        assert_eq!(res["pdf"], true);
+
+        let res = admin.get("/api/file/preview/pdf?path=/missing.docx").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(admin.upload("/", "notes.txt", b"text").await.status().is_success());
+        let res = admin.get("/api/file/preview/pdf?path=/notes.txt").await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(admin.upload("/", "broken.pptx", b"broken").await.status().is_success());
+        let res = admin.get("/api/file/preview/pdf?path=/broken.pptx").await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let large = vec![b'x'; 2 * 1024 * 1024];
+        assert!(admin.upload("/", "large.xlsx", &large).await.status().is_success());
+        let res = admin.get("/api/file/preview/pdf?path=/large.xlsx").await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(runs(&dir), 2);
+        app.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! Paths of entries in a user's tree
+//!
+//! Clients send paths with or without leading and trailing slashes. Tables
+//! and caches keyed by path keep them in one form, given by [`normalize`].
+//! Tables with rows stored by path implement [`PathRows`] to look up a
+//! subtree ([`rows_under`]) and follow renames ([`move_rows`]).
+
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+    TransactionTrait,
+};
+use std::collections::BTreeSet;
+
+/// `/path` form of a path relative to the user root
+pub(crate) fn normalize(path: &str) -> String {
+    format!("/{}", path.trim_matches('/'))
+}
+
+/// [`normalize`]d folder and name of a path
+pub(crate) fn split(path: &str) -> (String, &str) {
+    let path = path.trim_matches('/');
+    match path.rsplit_once('/') {
+        Some((folder, name)) => (format!("/{}", folder), name),
+        None => ("/".to_string(), path),
+    }
+}
+
+/// Whether `path` is `dir` or below it, both [`normalize`]d
+pub(crate) fn is_within(path: &str, dir: &str) -> bool {
+    path == dir || dir == "/" || path.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Table with rows stored by the path of an entry in a user's tree
+pub(crate) trait PathRows: EntityTrait {
+    /// Column of the owner's username
+    const USERNAME: Self::Column;
+    /// Column of the [`normalize`]d path
+    const PATH: Self::Column;
+
+    /// Path of a row
+    fn path(row: &Self::Model) -> &str;
+}
+
+/// Rows of `path` of `username` and everything below it
+pub(crate) async fn rows_under<E: PathRows>(
+    db: &impl ConnectionTrait,
+    username: &str,
+    path: &str,
+) -> Result<Vec<E::Model>, DbErr> {
+    let path = normalize(path);
+    let prefix = format!("{}/", path.trim_end_matches('/'));
+    let rows = E::find()
+        .filter(E::USERNAME.eq(username))
+        .filter(Condition::any().add(E::PATH.eq(&path)).add(E::PATH.starts_with(&prefix)))
+        .all(db)
+        .await?;
+    // LIKE treats `%` and `_` in names as wildcards
+    Ok(rows.into_iter().filter(|row| is_within(E::path(row), &path)).collect())
+}
+
+/// Move the rows of `old_path` of `username` and everything below it to
+/// `new_path`
+///
+/// Rows left at `new_path` by an entry that was replaced are dropped first.
+pub(crate) async fn move_rows<E: PathRows>(
+    db: &DatabaseConnection,
+    username: &str,
+    old_path: &str,
+    new_path: &str,
+) -> Result<(), DbErr> {
+    let (old_path, new_path) = (normalize(old_path), normalize(new_path));
+    if old_path == new_path {
+        return Ok(());
+    }
+
+    let txn = db.begin().await?;
+    let paths = |rows: Vec<E::Model>| rows.iter().map(|row| E::path(row).to_string()).collect::<BTreeSet<_>>();
+    let replaced = paths(rows_under::<E>(&txn, username, &new_path).await?);
+    if !replaced.is_empty() {
+        E::delete_many()
+            .filter(E::USERNAME.eq(username))
+            .filter(E::PATH.is_in(replaced))
+            .exec(&txn)
+            .await?;
+    }
+    for path in paths(rows_under::<E>(&txn, username, &old_path).await?) {
+        E::update_many()
+            .col_expr(E::PATH, Expr::value(format!("{}{}", new_path, &path[old_path.len()..])))
+            .filter(E::USERNAME.eq(username))
+            .filter(E::PATH.eq(path))
+            .exec(&txn)
+            .await?;
+    }
+    txn.commit().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("docs/a.txt"), "/docs/a.txt");
+        assert_eq!(normalize("/docs/old/"), "/docs/old");
+        assert_eq!(normalize(""), "/");
+        assert_eq!(normalize("/"), "/");
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("/drop/a.txt"), ("/drop".to_string(), "a.txt"));
+        assert_eq!(split("drop/sub/a.txt/"), ("/drop/sub".to_string(), "a.txt"));
+        assert_eq!(split("/a.txt"), ("/".to_string(), "a.txt"));
+        assert_eq!(split("a.txt"), ("/".to_string(), "a.txt"));
+    }
+
+    #[test]
+    fn test_is_within() {
+        assert!(is_within("/a/b", "/a"));
+        assert!(is_within("/a", "/a"));
+        assert!(is_within("/a", "/"));
+        assert!(!is_within("/ab", "/a"));
+        assert!(!is_within("/a", "/a/b"));
+    }
+}
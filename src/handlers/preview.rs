@@ -0,0 +1,436 @@
+//! Preview dispatcher
+//!
+//! Maps file extensions to preview handlers (text, image, pdf, office,
+//! archive, media). HEIC photos are converted to JPEG by [`heic`] if set up,
+//! Office documents to PDF by [`office_pdf`]. The file preview endpoints ask
+//! the registry for the handler of a file instead of matching extensions
+//! themselves. Deployments can plug in their own handlers with [`register`];
+//! they take precedence over the built-in ones.
+//!
+//! Previews are opened in the browser on the server's origin, so an uploaded
+//! HTML page would run its scripts with the viewer's session. [`sandbox`]
+//! renders HTML, SVG and XML under a CSP without scripts, or as plain text,
+//! per `[html_preview]`.
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+    Extension,
+};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, RwLock};
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
+
+use crate::config::{HtmlPreviewConfig, HtmlPreviewMode};
+use crate::error::error_response;
+use crate::handlers::archive_preview::list_entries;
+use crate::handlers::file::{resolve_in_user_root, PathQuery};
+use crate::handlers::heic;
+use crate::handlers::office_pdf;
+use crate::middleware::auth::CurrentUser;
+use crate::mime;
+use crate::state::AppState;
+
+/// How a file is previewed by clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewKind {
+    Text,
+    Image,
+    Pdf,
+    /// Opened in a read-only document session
+    Office,
+    /// Listing of the archive entries
+    Archive,
+    Media,
+}
+
+/// A preview strategy for a set of file extensions
+#[async_trait]
+pub trait PreviewHandler: Send + Sync {
+    /// Kind reported to clients
+    fn kind(&self) -> PreviewKind;
+
+    /// Whether files with this extension (lowercase, without dot) are handled
+    fn supports(&self, ext: &str) -> bool;
+
+    /// Content type the file is served with
+    fn content_type(&self, ext: &str) -> String;
+
+    /// Build the preview response, by default the file itself
+    async fn render(&self, path: &Path, ext: &str) -> Response {
+        serve_file(path, &self.content_type(ext)).await
+    }
+}
+
+/// Serve a file inline with the given content type
+pub async fn serve_file(path: &Path, content_type: &str) -> Response {
+    let file = match tokio::fs::File::open(path).await {
+        Ok(f) => f,
+        Err(e) => {
+            tracing::error!("Failed to open file: {}", e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to open file");
+        }
+    };
+    serve_reader(file, path, content_type)
+}
+
+/// Serve the content of the file at `path` read from `reader`, such as a
+/// decompressing one
+pub fn serve_reader(reader: impl AsyncRead + Send + 'static, path: &Path, content_type: &str) -> Response {
+    let filename = path.file_name().and_then(|n| n.to_str()).unwrap_or("preview");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("inline; filename=\"{}\"", filename),
+        )
+        .body(Body::from_stream(ReaderStream::new(reader)))
+        .unwrap()
+}
+
+/// Policy of rendered HTML, SVG and XML: a sandbox with an opaque origin and
+/// nothing but images, media and inline styles
+const SANDBOX_CSP: &str =
+    "sandbox; default-src 'none'; img-src 'self' data:; media-src 'self'; style-src 'self' 'unsafe-inline'";
+
+/// Host of `[html_preview] content_origin`, None if there is none
+fn content_host(config: &HtmlPreviewConfig) -> Option<String> {
+    let uri = config.content_origin.parse::<axum::http::Uri>().ok()?;
+    uri.authority().map(|authority| authority.as_str().to_ascii_lowercase())
+}
+
+/// Make a preview response safe to open in the browser
+///
+/// Content browsers run scripts in is rendered sandboxed or turned into
+/// plain text, per `[html_preview]`; with a `content_origin`, only requests
+/// to `host` there are rendered. Everything is marked `nosniff`, so nothing
+/// else is taken for HTML.
+pub fn sandbox(config: &HtmlPreviewConfig, host: Option<&str>, mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    let active = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(mime::is_active);
+    if !active {
+        return response;
+    }
+
+    let on_content_origin = match content_host(config) {
+        Some(content_host) => host.is_some_and(|host| host.eq_ignore_ascii_case(&content_host)),
+        None => true,
+    };
+    if config.mode == HtmlPreviewMode::Sandbox && on_content_origin {
+        headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static(SANDBOX_CSP));
+    } else {
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+    }
+    response
+}
+
+/// Text and source files
+pub struct TextPreview;
+
+impl PreviewHandler for TextPreview {
+    fn kind(&self) -> PreviewKind {
+        PreviewKind::Text
+    }
+
+    fn supports(&self, ext: &str) -> bool {
+        matches!(
+            ext,
+            "txt" | "md" | "log" | "csv" | "json" | "xml" | "yaml" | "yml" | "toml" | "ini" | "conf"
+                | "html" | "css" | "js" | "ts" | "rs" | "go" | "py" | "java" | "c" | "h" | "cpp"
+                | "sh" | "sql"
+        )
+    }
+
+    fn content_type(&self, ext: &str) -> String {
+        mime::from_extension(ext)
+            .filter(|m| mime::is_text(m))
+            .unwrap_or("text/plain")
+            .to_string()
+    }
+}
+
+/// Images shown inline
+pub struct ImagePreview;
+
+impl PreviewHandler for ImagePreview {
+    fn kind(&self) -> PreviewKind {
+        PreviewKind::Image
+    }
+
+    fn supports(&self, ext: &str) -> bool {
+        matches!(ext, "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp")
+    }
+
+    fn content_type(&self, ext: &str) -> String {
+        mime::from_extension(ext).unwrap_or(mime::OCTET_STREAM).to_string()
+    }
+}
+
+/// HEIC/HEIF photos, shown as JPEG when `[media] heif_convert` is set and
+/// as they are otherwise, for the browsers that can
+pub struct HeicPreview;
+
+impl PreviewHandler for HeicPreview {
+    fn kind(&self) -> PreviewKind {
+        PreviewKind::Image
+    }
+
+    fn supports(&self, ext: &str) -> bool {
+        matches!(ext, "heic" | "heif")
+    }
+
+    fn content_type(&self, ext: &str) -> String {
+        mime::from_extension(ext).unwrap_or(mime::OCTET_STREAM).to_string()
+    }
+}
+
+/// PDF documents
+pub struct PdfPreview;
+
+impl PreviewHandler for PdfPreview {
+    fn kind(&self) -> PreviewKind {
+        PreviewKind::Pdf
+    }
+
+    fn supports(&self, ext: &str) -> bool {
+        ext == "pdf"
+    }
+
+    fn content_type(&self, _ext: &str) -> String {
+        "application/pdf".to_string()
+    }
+}
+
+/// Office documents, previewed through a read-only document session
+pub struct OfficePreview;
+
+impl PreviewHandler for OfficePreview {
+    fn kind(&self) -> PreviewKind {
+        PreviewKind::Office
+    }
+
+    fn supports(&self, ext: &str) -> bool {
+        matches!(ext, "doc" | "docx" | "xls" | "xlsx" | "ppt" | "pptx" | "odt" | "ods" | "odp")
+    }
+
+    fn content_type(&self, ext: &str) -> String {
+        mime::from_extension(ext).unwrap_or(mime::OCTET_STREAM).to_string()
+    }
+}
+
+/// Archives, previewed as a listing of their entries
+pub struct ArchivePreview;
+
+#[async_trait]
+impl PreviewHandler for ArchivePreview {
+    fn kind(&self) -> PreviewKind {
+        PreviewKind::Archive
+    }
+
+    fn supports(&self, ext: &str) -> bool {
+        matches!(ext, "zip" | "rar" | "7z" | "tar" | "gz" | "tgz" | "xz" | "txz")
+    }
+
+    fn content_type(&self, _ext: &str) -> String {
+        "application/json".to_string()
+    }
+
+    async fn render(&self, path: &Path, _ext: &str) -> Response {
+        let path = path.to_path_buf();
+        match tokio::task::spawn_blocking(move || list_entries(&path)).await {
+            Ok(Some(Ok(entries))) => Json(entries).into_response(),
+            Ok(None) => error_response(StatusCode::BAD_REQUEST, "不支持的压缩格式"),
+            Ok(Some(Err(e))) => {
+                tracing::error!("Failed to preview archive: {}", e);
+                error_response(e.status(), &e.to_string())
+            }
+            Err(e) => {
+                tracing::error!("Archive preview worker failed: {}", e);
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+            }
+        }
+    }
+}
+
+/// Audio and video
+pub struct MediaPreview;
+
+impl PreviewHandler for MediaPreview {
+    fn kind(&self) -> PreviewKind {
+        PreviewKind::Media
+    }
+
+    fn supports(&self, ext: &str) -> bool {
+        matches!(
+            ext,
+            "mp4" | "m4v" | "webm" | "ogv" | "mov" | "mkv" | "avi" | "mp3" | "wav" | "ogg" | "opus" | "m4a" | "flac"
+                | "aac"
+        )
+    }
+
+    fn content_type(&self, ext: &str) -> String {
+        mime::from_extension(ext).unwrap_or(mime::OCTET_STREAM).to_string()
+    }
+}
+
+/// Registered handlers, searched in order
+static REGISTRY: LazyLock<RwLock<Vec<Arc<dyn PreviewHandler>>>> = LazyLock::new(|| {
+    RwLock::new(vec![
+        Arc::new(TextPreview),
+        Arc::new(ImagePreview),
+        Arc::new(HeicPreview),
+        Arc::new(PdfPreview),
+        Arc::new(OfficePreview),
+        Arc::new(ArchivePreview),
+        Arc::new(MediaPreview),
+    ])
+});
+
+/// Register a custom preview handler, taking precedence over the built-in ones
+pub fn register(handler: Arc<dyn PreviewHandler>) {
+    REGISTRY.write().unwrap().insert(0, handler);
+}
+
+/// Find the preview handler of a file
+pub fn find(file_name: &str) -> Option<Arc<dyn PreviewHandler>> {
+    let ext = mime::extension(file_name);
+    REGISTRY
+        .read()
+        .unwrap()
+        .iter()
+        .find(|h| h.supports(&ext))
+        .cloned()
+}
+
+/// Preview info response
+#[derive(Debug, Serialize)]
+pub struct PreviewInfo {
+    /// None if the file can't be previewed
+    pub kind: Option<PreviewKind>,
+    #[serde(rename = "contentType")]
+    pub content_type: String,
+    /// Whether the file is also served as PDF at `/api/file/preview/pdf`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub pdf: bool,
+}
+
+/// GET /api/file/preview/info - How a file can be previewed
+pub async fn get_preview_info(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<PathQuery>,
+) -> Response {
+    let Some(file_path) = resolve_in_user_root(&state.config, &current_user.username, &query.path) else {
+        return error_response(StatusCode::BAD_REQUEST, "invalid path");
+    };
+    if !file_path.is_file() {
+        return error_response(StatusCode::NOT_FOUND, "file not found");
+    }
+
+    let ext = mime::extension(&query.path);
+    let info = match find(&query.path) {
+        Some(_) if heic::converts(&state.config.media, &ext) => PreviewInfo {
+            kind: Some(PreviewKind::Image),
+            content_type: "image/jpeg".to_string(),
+            pdf: false,
+        },
+        Some(handler) => PreviewInfo {
+            kind: Some(handler.kind()),
+            content_type: handler.content_type(&ext),
+            pdf: office_pdf::converts(&state.config.office_preview, &ext),
+        },
+        None => PreviewInfo {
+            kind: None,
+            content_type: mime::detect_file(&file_path).await.to_string(),
+            pdf: false,
+        },
+    };
+    Json(info).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MarkdownPreview;
+
+    impl PreviewHandler for MarkdownPreview {
+        fn kind(&self) -> PreviewKind {
+            PreviewKind::Text
+        }
+
+        fn supports(&self, ext: &str) -> bool {
+            ext == "md"
+        }
+
+        fn content_type(&self, _ext: &str) -> String {
+            "text/markdown".to_string()
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let kind = |name: &str| find(name).map(|h| h.kind());
+        assert_eq!(kind("a.TXT"), Some(PreviewKind::Text));
+        assert_eq!(kind("photo.jpeg"), Some(PreviewKind::Image));
+        assert_eq!(kind("report.pdf"), Some(PreviewKind::Pdf));
+        assert_eq!(kind("sheet.xlsx"), Some(PreviewKind::Office));
+        assert_eq!(kind("backup.tar.gz"), Some(PreviewKind::Archive));
+        assert_eq!(kind("clip.mp4"), Some(PreviewKind::Media));
+        assert_eq!(kind("binary.exe"), None);
+        assert_eq!(kind("Makefile"), None);
+    }
+
+    #[test]
+    fn test_register_takes_precedence() {
+        assert_eq!(find("notes.md").unwrap().content_type("md"), "text/plain");
+        register(Arc::new(MarkdownPreview));
+        assert_eq!(find("notes.md").unwrap().content_type("md"), "text/markdown");
+        // Other extensions are unaffected
+        assert_eq!(find("a.txt").unwrap().content_type("txt"), "text/plain");
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let page = |content_type: &str| {
+            Response::builder().header(header::CONTENT_TYPE, content_type).body(Body::empty()).unwrap()
+        };
+        let header = |response: &Response, name| {
+            response.headers().get(name).map(|v| v.to_str().unwrap().to_string())
+        };
+
+        let config = HtmlPreviewConfig::default();
+        let response = sandbox(&config, Some("disk.example.com"), page("text/html"));
+        assert_eq!(header(&response, header::CONTENT_TYPE).unwrap(), "text/html");
+        assert!(header(&response, header::CONTENT_SECURITY_POLICY).unwrap().starts_with("sandbox;"));
+        let response = sandbox(&config, None, page("image/png"));
+        assert_eq!(header(&response, header::CONTENT_SECURITY_POLICY), None);
+        assert_eq!(header(&response, header::X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+
+        let config = HtmlPreviewConfig { mode: HtmlPreviewMode::Text, ..HtmlPreviewConfig::default() };
+        let response = sandbox(&config, None, page("image/svg+xml"));
+        assert_eq!(header(&response, header::CONTENT_TYPE).unwrap(), "text/plain; charset=utf-8");
+
+        // Rendered on the content origin only
+        let config = HtmlPreviewConfig {
+            content_origin: "https://usercontent.example.com".to_string(),
+            ..HtmlPreviewConfig::default()
+        };
+        let response = sandbox(&config, Some("disk.example.com"), page("text/html; charset=utf-8"));
+        assert_eq!(header(&response, header::CONTENT_TYPE).unwrap(), "text/plain; charset=utf-8");
+        let response = sandbox(&config, Some("UserContent.example.com"), page("text/html; charset=utf-8"));
+        assert!(header(&response, header::CONTENT_SECURITY_POLICY).is_some());
+    }
+}
@@ -0,0 +1,232 @@
+//! Storage quota
+//!
+//! Tracks the bytes used by each user's storage root and checks writes
+//! against the effective quota (user quota, otherwise the nearest department
+//! quota up the tree). Usage is scanned from disk on first use and then kept
+//! up to date by the handlers that add or remove files.
+
+use dashmap::DashMap;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+
+use crate::config::Config;
+use crate::entity::{department, user};
+use crate::handlers::{dept_space, group_space};
+use crate::handlers::file::get_user_path;
+use crate::handlers::trash::dir_size;
+
+/// Used bytes per username
+static USAGE: std::sync::LazyLock<DashMap<String, i64>> = std::sync::LazyLock::new(DashMap::new);
+
+/// Error returned when a write would exceed the quota
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub used: i64,
+    pub limit: i64,
+}
+
+impl QuotaExceeded {
+    /// User facing message
+    pub fn message(&self) -> String {
+        format!(
+            "存储空间不足, 已使用 {} / 配额 {}",
+            format_size(self.used),
+            format_size(self.limit)
+        )
+    }
+}
+
+/// Parse a quota string such as "10 GB", "500MB" or "1073741824" into bytes
+pub fn parse_quota(quota: &str) -> Option<i64> {
+    let quota = quota.trim();
+    let split = quota
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(quota.len());
+    let (number, unit) = quota.split_at(split);
+    let number: f64 = number.parse().ok()?;
+
+    let multiplier: i64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        "T" | "TB" => 1 << 40,
+        "P" | "PB" => 1 << 50,
+        _ => return None,
+    };
+    Some((number * multiplier as f64) as i64)
+}
+
+pub(crate) fn format_size(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Resolve effective quota (user overrides department, department inherits parent)
+pub async fn get_effective_quota(
+    db: &DatabaseConnection,
+    department_id: i64,
+    user_quota: Option<String>,
+) -> Option<String> {
+    if user_quota.is_some() {
+        return user_quota;
+    }
+
+    let mut current_id = department_id;
+    while current_id != 0 {
+        match department::Entity::find_by_id(current_id).one(db).await {
+            Ok(Some(dept)) => {
+                if dept.quota.is_some() {
+                    return dept.quota;
+                }
+                current_id = dept.parent_id;
+            }
+            _ => break,
+        }
+    }
+
+    None
+}
+
+/// Effective quota of a user in bytes, None if unlimited
+pub async fn quota_limit(db: &DatabaseConnection, config: &Config, username: &str) -> Option<i64> {
+    // Group spaces share one configured quota
+    if group_space::group_id(username).is_some() {
+        return parse_quota(&config.group_space.quota);
+    }
+    // A department space has the quota of its department
+    if let Some(dept_id) = dept_space::dept_id(username) {
+        let quota = get_effective_quota(db, dept_id, None).await?;
+        return parse_quota(&quota);
+    }
+    let db_user = user::Entity::find()
+        .filter(user::Column::Username.eq(username))
+        .one(db)
+        .await
+        .ok()
+        .flatten()?;
+    let quota = get_effective_quota(db, db_user.department_id, db_user.quota).await?;
+    parse_quota(&quota)
+}
+
+/// Size of a file, or of all files below a directory
+pub fn path_size(path: &std::path::Path) -> i64 {
+    match std::fs::metadata(path) {
+        Ok(m) if m.is_dir() => dir_size(path) as i64,
+        Ok(m) => m.len() as i64,
+        Err(_) => 0,
+    }
+}
+
+/// Bytes currently used by a user
+pub async fn used_bytes(config: &Config, username: &str) -> i64 {
+    if let Some(used) = USAGE.get(username) {
+        return *used;
+    }
+
+    let path = get_user_path(config, username);
+    let used = tokio::task::spawn_blocking(move || dir_size(&path) as i64)
+        .await
+        .unwrap_or(0);
+    *USAGE.entry(username.to_string()).or_insert(used)
+}
+
+/// Bytes a user may still write, None if unlimited
+pub async fn remaining(db: &DatabaseConnection, config: &Config, username: &str) -> Option<i64> {
+    let limit = quota_limit(db, config, username).await?;
+    Some((limit - used_bytes(config, username).await).max(0))
+}
+
+/// Check that writing `additional` bytes stays within the user's quota
+pub async fn check_quota(
+    db: &DatabaseConnection,
+    config: &Config,
+    username: &str,
+    additional: i64,
+) -> Result<(), QuotaExceeded> {
+    let Some(limit) = quota_limit(db, config, username).await else {
+        return Ok(());
+    };
+    let used = used_bytes(config, username).await;
+    if used + additional > limit {
+        return Err(QuotaExceeded { used, limit });
+    }
+    Ok(())
+}
+
+/// Adjust the tracked usage of a user after adding (positive) or removing
+/// (negative) bytes
+pub fn add_usage(username: &str, delta: i64) {
+    // Users that were never scanned are counted from disk on first use
+    if let Some(mut used) = USAGE.get_mut(username) {
+        *used = (*used + delta).max(0);
+    }
+}
+
+/// Tracked usage of the users scanned so far
+pub fn tracked_usage() -> Vec<(String, i64)> {
+    USAGE.iter().map(|e| (e.key().clone(), *e.value())).collect()
+}
+
+/// Forget the tracked usage so it is scanned again on next use
+pub fn invalidate(username: &str) {
+    USAGE.remove(username);
+}
+
+/// Recount the tracked usage of every scanned user from disk, returning the
+/// number of users whose count had drifted
+pub async fn reconcile(config: &Config) -> usize {
+    let mut corrected = 0;
+    for (username, tracked) in tracked_usage() {
+        let path = get_user_path(config, &username);
+        let Ok(used) = tokio::task::spawn_blocking(move || dir_size(&path) as i64).await else {
+            continue;
+        };
+        if used != tracked {
+            tracing::info!("Usage of {} corrected from {} to {} bytes", username, tracked, used);
+            USAGE.insert(username, used);
+            corrected += 1;
+        }
+    }
+    corrected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quota() {
+        assert_eq!(parse_quota("10 GB"), Some(10 << 30));
+        assert_eq!(parse_quota("500MB"), Some(500 << 20));
+        assert_eq!(parse_quota("1.5 tb"), Some(3 << 39));
+        assert_eq!(parse_quota("2048"), Some(2048));
+        assert_eq!(parse_quota("abc"), None);
+        assert_eq!(parse_quota("10 XB"), None);
+        assert_eq!(parse_quota(""), None);
+    }
+
+    #[test]
+    fn test_add_usage() {
+        USAGE.insert("quota-test".to_string(), 100);
+        add_usage("quota-test", 50);
+        assert_eq!(*USAGE.get("quota-test").unwrap(), 150);
+        add_usage("quota-test", -500);
+        assert_eq!(*USAGE.get("quota-test").unwrap(), 0);
+        invalidate("quota-test");
+        assert!(USAGE.get("quota-test").is_none());
+
+        // Unscanned users are left alone
+        add_usage("quota-unscanned", 10);
+        assert!(USAGE.get("quota-unscanned").is_none());
+    }
+}
@@ -8,17 +8,17 @@ use axum::{
     Extension,
 };
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-use crate::handlers::audit::service::log_operation;
+use crate::entity::op_log::OpType;
+use crate::handlers::abuse;
+use crate::handlers::audit::service::log_admin_operation;
 use crate::middleware::auth::CurrentUser;
 use crate::permission::{normalize_permissions, perm, RoleInfo};
-use crate::routes::ApiResponse;
+use crate::routes::{ApiMessage, ApiResponse};
 use crate::state::AppState;
 
 // Operation types
-const OP_CREATE_ROLE: &str = "创建角色";
-const OP_DELETE_ROLE: &str = "删除角色";
-const OP_UPDATE_ROLE: &str = "修改角色";
 const OP_SUCCESS: &str = "成功";
 
 /// Check if user has role management permission
@@ -27,7 +27,7 @@ fn can_manage_roles(user: &CurrentUser) -> bool {
 }
 
 /// Add role request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct AddRoleRequest {
     pub name: String,
     pub description: Option<String>,
@@ -36,7 +36,7 @@ pub struct AddRoleRequest {
 }
 
 /// Update role request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateRoleRequest {
     pub name: String,
     #[serde(rename = "oldName")]
@@ -46,7 +46,7 @@ pub struct UpdateRoleRequest {
 }
 
 /// Role response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct RoleResponse {
     pub name: String,
     pub description: Option<String>,
@@ -69,12 +69,20 @@ impl From<RoleInfo> for RoleResponse {
 }
 
 /// Query parameters for delete
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct NameQuery {
     pub name: String,
 }
 
 /// POST /api/role/add
+#[utoipa::path(
+    post,
+    path = "/api/role/add",
+    tag = "role",
+    request_body = AddRoleRequest,
+    responses((status = 200, body = ApiResponse<RoleResponse>)),
+)]
 pub async fn add_role(
     State(state): State<AppState>,
     Extension(user): Extension<CurrentUser>,
@@ -82,6 +90,7 @@ pub async fn add_role(
 ) -> Json<ApiResponse<Option<RoleResponse>>> {
     // Permission check
     if !can_manage_roles(&user) {
+        abuse::record_denied(&user.username);
         return Json(ApiResponse::error(403, "权限不足，仅管理员可创建角色"));
     }
 
@@ -118,7 +127,7 @@ pub async fn add_role(
     }
 
     let op_desc = format!("角色名称: {}", req.name);
-    log_operation(&user.username, OP_CREATE_ROLE, &op_desc, OP_SUCCESS, None);
+    log_admin_operation(&user.username, OpType::CreateRole, &op_desc, OP_SUCCESS, None);
 
     Json(ApiResponse::success(Some(RoleResponse {
         name: req.name,
@@ -129,6 +138,13 @@ pub async fn add_role(
 }
 
 /// POST /api/role/delete
+#[utoipa::path(
+    post,
+    path = "/api/role/delete",
+    tag = "role",
+    params(NameQuery),
+    responses((status = 200, body = ApiMessage)),
+)]
 pub async fn delete_role(
     State(state): State<AppState>,
     Extension(user): Extension<CurrentUser>,
@@ -136,6 +152,7 @@ pub async fn delete_role(
 ) -> Json<ApiResponse<()>> {
     // Permission check
     if !can_manage_roles(&user) {
+        abuse::record_denied(&user.username);
         return Json(ApiResponse::error(403, "权限不足，仅管理员可删除角色"));
     }
 
@@ -169,11 +186,18 @@ pub async fn delete_role(
     }
 
     let op_desc = format!("角色名称: {}", query.name);
-    log_operation(&user.username, OP_DELETE_ROLE, &op_desc, OP_SUCCESS, None);
+    log_admin_operation(&user.username, OpType::DeleteRole, &op_desc, OP_SUCCESS, None);
     Json(ApiResponse::success_msg("success"))
 }
 
 /// POST /api/role/update
+#[utoipa::path(
+    post,
+    path = "/api/role/update",
+    tag = "role",
+    request_body = UpdateRoleRequest,
+    responses((status = 200, body = ApiResponse<RoleResponse>)),
+)]
 pub async fn update_role(
     State(state): State<AppState>,
     Extension(user): Extension<CurrentUser>,
@@ -181,6 +205,7 @@ pub async fn update_role(
 ) -> Json<ApiResponse<Option<RoleResponse>>> {
     // Permission check
     if !can_manage_roles(&user) {
+        abuse::record_denied(&user.username);
         return Json(ApiResponse::error(403, "权限不足，仅管理员可修改角色"));
     }
 
@@ -223,7 +248,7 @@ pub async fn update_role(
     }
 
     let op_desc = format!("角色名称: {}", req.name);
-    log_operation(&user.username, OP_UPDATE_ROLE, &op_desc, OP_SUCCESS, None);
+    log_admin_operation(&user.username, OpType::UpdateRole, &op_desc, OP_SUCCESS, None);
 
     Json(ApiResponse::success(Some(RoleResponse {
         name: req.name,
@@ -234,13 +259,19 @@ pub async fn update_role(
 }
 
 /// Response format for role list
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct RoleListResponse {
     pub success: bool,
     pub data: Vec<RoleResponse>,
 }
 
 /// GET /api/role/list
+#[utoipa::path(
+    get,
+    path = "/api/role/list",
+    tag = "role",
+    responses((status = 200, body = RoleListResponse)),
+)]
 pub async fn get_roles(
     State(state): State<AppState>,
     Extension(_user): Extension<CurrentUser>,
@@ -275,13 +306,13 @@ pub async fn get_roles(
 }
 
 /// Response for available permissions
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct PermissionsResponse {
     pub success: bool,
     pub data: Vec<PermissionInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct PermissionInfo {
     pub key: String,
     pub name: String,
@@ -289,6 +320,12 @@ pub struct PermissionInfo {
 }
 
 /// GET /api/role/permissions - Get list of available permissions
+#[utoipa::path(
+    get,
+    path = "/api/role/permissions",
+    tag = "role",
+    responses((status = 200, body = PermissionsResponse)),
+)]
 pub async fn get_available_permissions() -> Json<PermissionsResponse> {
     let permissions = vec![
         PermissionInfo {
@@ -316,6 +353,16 @@ pub async fn get_available_permissions() -> Json<PermissionsResponse> {
             name: "审计".to_string(),
             description: "查看操作日志".to_string(),
         },
+        PermissionInfo {
+            key: perm::AUDIT_DEPT.to_string(),
+            name: "部门审计".to_string(),
+            description: "查看本部门及下级部门的操作日志".to_string(),
+        },
+        PermissionInfo {
+            key: perm::CONTACTS_DEPT.to_string(),
+            name: "部门管理员".to_string(),
+            description: "管理本部门及下级部门的用户、配额与部门空间".to_string(),
+        },
     ];
 
     Json(PermissionsResponse {
@@ -0,0 +1,484 @@
+//! Maintenance jobs
+//!
+//! Trash purge, removal of interrupted uploads, audit log retention, usage
+//! reconciliation and database backups run on the cron schedules of
+//! `[scheduler]`. A job runs once at a time: a run due while the last one is
+//! still going is skipped. Instances sharing the database run each job once
+//! too, the one taking its [lease](crate::lease) for a run does it. The
+//! outcome of the last run is kept in memory and listed at `/api/admin/jobs`,
+//! where admins can also start a job out of schedule.
+
+use axum::{extract::State, response::Json, Extension};
+use chrono::Local;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime};
+use utoipa::ToSchema;
+
+use crate::config::{Config, SchedulerConfig};
+use crate::db;
+use crate::entity::op_log::OpType;
+use crate::handlers::abuse;
+use crate::handlers::audit::service::{apply_retention, log_admin_operation};
+use crate::handlers::{quota, trash};
+use crate::lease;
+use crate::middleware::auth::CurrentUser;
+use crate::routes::{ApiMessage, ApiResponse};
+use crate::state::AppState;
+
+const OP_SUCCESS: &str = "成功";
+
+/// A maintenance job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Job {
+    TrashPurge,
+    UploadCleanup,
+    AuditRetention,
+    Reconcile,
+    Backup,
+}
+
+impl Job {
+    pub const ALL: [Job; 5] = [
+        Job::TrashPurge,
+        Job::UploadCleanup,
+        Job::AuditRetention,
+        Job::Reconcile,
+        Job::Backup,
+    ];
+
+    /// Name of the job, as in `[scheduler]`
+    pub fn name(self) -> &'static str {
+        match self {
+            Job::TrashPurge => "trash_purge",
+            Job::UploadCleanup => "upload_cleanup",
+            Job::AuditRetention => "audit_retention",
+            Job::Reconcile => "reconcile",
+            Job::Backup => "backup",
+        }
+    }
+
+    fn schedule(self, config: &SchedulerConfig) -> &str {
+        match self {
+            Job::TrashPurge => &config.trash_purge,
+            Job::UploadCleanup => &config.upload_cleanup,
+            Job::AuditRetention => &config.audit_retention,
+            Job::Reconcile => &config.reconcile,
+            Job::Backup => &config.backup,
+        }
+    }
+}
+
+/// Parse a cron schedule, "minute hour day month weekday" or with seconds first
+pub fn parse_schedule(expr: &str) -> Result<cron::Schedule, cron::error::Error> {
+    let expr = expr.trim();
+    if expr.split_whitespace().count() == 5 {
+        cron::Schedule::from_str(&format!("0 {}", expr))
+    } else {
+        cron::Schedule::from_str(expr)
+    }
+}
+
+/// Next run of a job, None if it isn't scheduled
+fn next_run(config: &SchedulerConfig, job: Job) -> Option<i64> {
+    let schedule = parse_schedule(job.schedule(config)).ok()?;
+    schedule.upcoming(Local).next().map(|t| t.timestamp())
+}
+
+/// Runs of one job
+#[derive(Debug, Clone, Default)]
+struct Runs {
+    running: bool,
+    last_start: Option<i64>,
+    last_end: Option<i64>,
+    last_success: Option<bool>,
+    last_message: String,
+}
+
+static RUNS: LazyLock<DashMap<Job, Runs>> = LazyLock::new(DashMap::new);
+
+/// Mark a job as running, false if it already is
+fn begin(job: Job) -> bool {
+    let mut runs = RUNS.entry(job).or_default();
+    if runs.running {
+        return false;
+    }
+    runs.running = true;
+    true
+}
+
+/// Undo `begin` for a run another instance does
+fn abandon(job: Job) {
+    RUNS.entry(job).or_default().running = false;
+}
+
+fn lease_ttl(config: &SchedulerConfig) -> Duration {
+    Duration::from_secs(config.lease_secs.max(3))
+}
+
+/// Take the lease of a job for its run scheduled at `slot`, false if another
+/// instance runs it
+///
+/// Before the system is set up there is no database to share, and no other
+/// instance to run the job.
+async fn claim(state: &AppState, job: Job, slot: i64) -> bool {
+    let Some(conn) = state.get_db().await else {
+        return true;
+    };
+    match lease::acquire(&conn, job.name(), slot, lease_ttl(&state.config.scheduler)).await {
+        Ok(taken) => taken,
+        Err(e) => {
+            tracing::error!("Failed to take the lease of job {}: {}", job.name(), e);
+            false
+        }
+    }
+}
+
+/// Do the work of a job, returning what it did
+async fn execute(state: &AppState, job: Job) -> Result<String, String> {
+    let config = &state.config;
+    if job == Job::UploadCleanup {
+        let config = config.clone();
+        let removed = tokio::task::spawn_blocking(move || clean_uploads(&config))
+            .await
+            .map_err(|e| e.to_string())?;
+        return Ok(format!("removed {} unfinished uploads", removed));
+    }
+    if job == Job::Reconcile {
+        return Ok(format!("corrected usage of {} users", quota::reconcile(config).await));
+    }
+
+    let Some(conn) = state.get_db().await else {
+        return Err("system not initialized".to_string());
+    };
+    match job {
+        Job::TrashPurge => trash::purge_expired(config, &conn)
+            .await
+            .map(|n| format!("purged {} trash items", n))
+            .map_err(|e| e.to_string()),
+        Job::AuditRetention => apply_retention(&conn, &config.audit)
+            .await
+            .map(|n| format!("deleted {} logs", n))
+            .map_err(|e| e.to_string()),
+        Job::Backup => db::backup(&conn, &config.database, &config.backup_dir(), config.scheduler.backup_keep)
+            .await
+            .map(|file| format!("wrote {}", file.display()))
+            .map_err(|e| e.to_string()),
+        Job::UploadCleanup | Job::Reconcile => unreachable!(),
+    }
+}
+
+/// Run a job marked as running by `begin` and claimed by `claim`
+async fn run(state: &AppState, job: Job) {
+    RUNS.entry(job).or_default().last_start = Some(chrono::Utc::now().timestamp());
+    let conn = state.get_db().await;
+    // Renewed while the job works, so no other instance takes it over
+    let ttl = lease_ttl(&state.config.scheduler);
+    let renewing = conn.clone().map(|conn| {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ttl / 3);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match lease::renew(&conn, job.name(), ttl).await {
+                    Ok(true) => {}
+                    Ok(false) => tracing::warn!("Lost the lease of job {}", job.name()),
+                    Err(e) => tracing::warn!("Failed to renew the lease of job {}: {}", job.name(), e),
+                }
+            }
+        })
+    });
+
+    let result = execute(state, job).await;
+    if let Some(renewing) = renewing {
+        renewing.abort();
+    }
+    if let Some(conn) = &conn {
+        if let Err(e) = lease::release(conn, job.name()).await {
+            tracing::warn!("Failed to release the lease of job {}: {}", job.name(), e);
+        }
+    }
+    match &result {
+        Ok(message) => tracing::info!("Job {}: {}", job.name(), message),
+        Err(e) => tracing::error!("Job {} failed: {}", job.name(), e),
+    }
+    let mut runs = RUNS.entry(job).or_default();
+    runs.running = false;
+    runs.last_end = Some(chrono::Utc::now().timestamp());
+    runs.last_success = Some(result.is_ok());
+    runs.last_message = result.unwrap_or_else(|e| e);
+}
+
+/// Files of uploads that never finished, found at the top of the user and
+/// space roots where uploads are streamed to
+fn upload_roots(config: &Config) -> Vec<PathBuf> {
+    let children = |dir: &Path| -> Vec<PathBuf> {
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+                    .map(|e| e.path())
+                    .filter(|p| p.is_dir())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let mut roots = children(&config.root_dir);
+    roots.extend(children(&config.dept_space.dir.clone().unwrap_or_else(|| config.root_dir.join(".dept"))));
+    roots.extend(config.dept_space.roots.values().cloned());
+    roots.extend(children(&config.group_space.dir.clone().unwrap_or_else(|| config.root_dir.join(".group"))));
+    roots
+}
+
+/// Remove files of uploads older than `upload_ttl_hours`
+fn clean_uploads(config: &Config) -> usize {
+    let cutoff = SystemTime::now() - Duration::from_secs(config.scheduler.upload_ttl_hours * 3600);
+    let mut removed = 0;
+    for root in upload_roots(config) {
+        let Ok(entries) = std::fs::read_dir(&root) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "uploading") {
+                continue;
+            }
+            let stale = entry.metadata().and_then(|m| m.modified()).is_ok_and(|t| t <= cutoff);
+            if stale && std::fs::remove_file(&path).is_ok() {
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
+
+/// Start the scheduled jobs
+pub fn start(state: AppState) {
+    for job in Job::ALL {
+        let expr = job.schedule(&state.config.scheduler).trim().to_string();
+        if expr.is_empty() {
+            continue;
+        }
+        let schedule = match parse_schedule(&expr) {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                tracing::error!("Invalid schedule '{}' of job {}: {}", expr, job.name(), e);
+                continue;
+            }
+        };
+        let state = state.clone();
+        tokio::spawn(async move {
+            while let Some(next) = schedule.upcoming(Local).next() {
+                let wait = (next - Local::now()).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+                if !begin(job) {
+                    tracing::warn!("Job {} still running, skipping its run at {}", job.name(), next);
+                    continue;
+                }
+                if claim(&state, job, next.timestamp()).await {
+                    run(&state, job).await;
+                } else {
+                    abandon(job);
+                    tracing::info!("Job {} run at {} taken by another instance", job.name(), next);
+                }
+            }
+        });
+    }
+}
+
+/// Status of a job
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JobStatus {
+    pub name: Job,
+    /// Cron schedule, empty if only run by hand
+    pub schedule: String,
+    /// Next scheduled run (Unix timestamp)
+    #[serde(rename = "nextRun")]
+    pub next_run: Option<i64>,
+    /// Running here or, when instances share the database, on another one
+    pub running: bool,
+    /// Instance running the job or the last to take it, when instances share
+    /// the database; the last run fields are those of this instance
+    pub instance: Option<String>,
+    /// Start of the last run (Unix timestamp)
+    #[serde(rename = "lastStart")]
+    pub last_start: Option<i64>,
+    /// End of the last finished run (Unix timestamp)
+    #[serde(rename = "lastEnd")]
+    pub last_end: Option<i64>,
+    /// Whether the last finished run succeeded
+    #[serde(rename = "lastSuccess")]
+    pub last_success: Option<bool>,
+    /// What the last run did, or why it failed
+    #[serde(rename = "lastMessage")]
+    pub last_message: String,
+}
+
+/// GET /api/admin/jobs
+#[utoipa::path(
+    get,
+    path = "/api/admin/jobs",
+    tag = "admin",
+    responses((status = 200, body = ApiResponse<Vec<JobStatus>>)),
+)]
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Json<ApiResponse<Vec<JobStatus>>> {
+    if !current_user.can_audit() {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let leases = match state.get_db().await {
+        Some(conn) => match lease::all(&conn).await {
+            Ok(leases) => leases,
+            Err(e) => {
+                tracing::error!("Failed to query job leases: {}", e);
+                return Json(ApiResponse::error(500, "查询任务失败"));
+            }
+        },
+        None => Vec::new(),
+    };
+    let now = chrono::Utc::now().timestamp();
+    let config = &state.config.scheduler;
+    let jobs = Job::ALL
+        .into_iter()
+        .map(|job| {
+            let runs = RUNS.get(&job).map(|r| r.clone()).unwrap_or_default();
+            let lease = leases.iter().find(|l| l.name == job.name() && !l.holder.is_empty());
+            JobStatus {
+                name: job,
+                schedule: job.schedule(config).to_string(),
+                next_run: next_run(config, job),
+                running: runs.running || lease.is_some_and(|l| l.expires_at > now),
+                instance: lease.map(|l| l.holder.clone()),
+                last_start: runs.last_start,
+                last_end: runs.last_end,
+                last_success: runs.last_success,
+                last_message: runs.last_message,
+            }
+        })
+        .collect();
+    Json(ApiResponse::success(jobs))
+}
+
+/// Run job request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RunJobRequest {
+    pub name: Job,
+}
+
+/// POST /api/admin/jobs/run - Start a job now, its outcome is listed when done
+#[utoipa::path(
+    post,
+    path = "/api/admin/jobs/run",
+    tag = "admin",
+    request_body = RunJobRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
+pub async fn run_job(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<RunJobRequest>,
+) -> Json<ApiResponse<()>> {
+    if !current_user.can_audit() {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+    if !begin(req.name) {
+        return Json(ApiResponse::error(409, "任务正在运行"));
+    }
+    if !claim(&state, req.name, chrono::Utc::now().timestamp()).await {
+        abandon(req.name);
+        return Json(ApiResponse::error(409, "任务正在其他实例上运行"));
+    }
+
+    log_admin_operation(&current_user.username, OpType::RunJob, req.name.name(), OP_SUCCESS, None);
+    tokio::spawn(async move { run(&state, req.name).await });
+    Json(ApiResponse::success_msg("started"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    #[test]
+    fn test_parse_schedule() {
+        assert!(parse_schedule("0 * * * *").is_ok());
+        assert!(parse_schedule("0 30 4 * * *").is_ok());
+        assert!(parse_schedule("0 2 * * Sun").is_ok());
+        assert!(parse_schedule("every hour").is_err());
+
+        let config = SchedulerConfig { backup: String::new(), ..SchedulerConfig::default() };
+        let now = Local::now().timestamp();
+        assert!(next_run(&config, Job::TrashPurge).is_some_and(|t| t > now && t <= now + 3600));
+        assert_eq!(next_run(&config, Job::Backup), None);
+    }
+
+    #[test]
+    fn test_clean_uploads() {
+        let mut config = Config {
+            root_dir: std::env::temp_dir().join(format!("datadisk-scheduler-{}", uuid::Uuid::new_v4())),
+            ..Config::default()
+        };
+        let user = config.root_dir.join("alice");
+        std::fs::create_dir_all(&user).unwrap();
+        std::fs::write(user.join("x.uploading"), b"partial").unwrap();
+        std::fs::write(user.join("report.txt"), b"done").unwrap();
+
+        config.scheduler.upload_ttl_hours = 1;
+        assert_eq!(clean_uploads(&config), 0);
+        config.scheduler.upload_ttl_hours = 0;
+        assert_eq!(clean_uploads(&config), 1);
+        assert!(!user.join("x.uploading").exists());
+        assert!(user.join("report.txt").exists());
+        std::fs::remove_dir_all(&config.root_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_jobs_api() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+
+        let body = serde_json::json!({ "name": "reconcile" });
+        let res: serde_json::Value = admin.post_json("/api/admin/jobs/run", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], true);
+
+        let mut job = serde_json::Value::Null;
+        for _ in 0..50 {
+            let res: serde_json::Value = admin.get("/api/admin/jobs").await.json().await.unwrap();
+            let jobs = res["data"].as_array().unwrap();
+            assert_eq!(jobs.len(), Job::ALL.len());
+            job = jobs.iter().find(|j| j["name"] == "reconcile").unwrap().clone();
+            if job["running"] == false {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(job["lastSuccess"], true);
+        assert_eq!(job["instance"], lease::INSTANCE.as_str());
+        assert_eq!(job["schedule"], "30 4 * * *");
+        assert!(job["nextRun"].is_i64());
+
+        // Running on another instance
+        let far = chrono::Utc::now().timestamp() + 3600;
+        assert!(lease::acquire(&app.env.db, "trash_purge", far, Duration::from_secs(3600)).await.unwrap());
+        let body = serde_json::json!({ "name": "trash_purge" });
+        let res: serde_json::Value = admin.post_json("/api/admin/jobs/run", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        let res: serde_json::Value = admin.get("/api/admin/jobs").await.json().await.unwrap();
+        let job = res["data"].as_array().unwrap().iter().find(|j| j["name"] == "trash_purge").unwrap().clone();
+        assert_eq!(job["running"], true);
+
+        let body = serde_json::json!({ "name": "defrag" });
+        assert!(!admin.post_json("/api/admin/jobs/run", &body).await.status().is_success());
+        app.close().await;
+    }
+}
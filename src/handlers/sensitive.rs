@@ -0,0 +1,450 @@
+//! Sensitive folders
+//!
+//! Users mark folders of trees they may change as sensitive. Images and PDFs
+//! below them are served with a watermark naming the viewer and the time
+//! ([`crate::watermark`]) wherever they are shown one by one: previews,
+//! thumbnails and single downloads. Zip downloads of folders holding them
+//! are refused, and so is WebDAV, which can't serve anything but the file.
+//!
+//! Marks are stored by path, like tags, and follow renames and moves
+//! ([`move_marks`]); copies are new files and are not marked. A mark is
+//! removed by whoever set it or by an auditor.
+
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{Json, Response},
+    Extension,
+};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::fs;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::entity::op_log::OpType;
+use crate::entity::sensitive_folder;
+use crate::error::error_response;
+use crate::handlers::abuse::record_denied;
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::dept_space::Location;
+use crate::handlers::file::{locate, locate_for_write, resolve_in_user_root};
+use crate::handlers::path::{is_within, move_rows, normalize, PathRows};
+use crate::handlers::preview;
+use crate::handlers::thumbnail;
+use crate::handlers::traffic;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::mime;
+use crate::routes::{ApiMessage, ApiResponse};
+use crate::state::AppState;
+use crate::task::{JobError, TASK_MANAGER};
+use crate::watermark::{self, Kind, Watermark};
+
+const OP_SUCCESS: &str = "成功";
+
+/// Largest file watermarked; larger ones below sensitive folders are not served
+pub const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+impl PathRows for sensitive_folder::Entity {
+    const USERNAME: sensitive_folder::Column = sensitive_folder::Column::Username;
+    const PATH: sensitive_folder::Column = sensitive_folder::Column::Path;
+
+    fn path(row: &sensitive_folder::Model) -> &str {
+        &row.path
+    }
+}
+
+/// Marks of the tree of `owner`
+async fn marks(db: &impl ConnectionTrait, owner: &str) -> Result<Vec<sensitive_folder::Model>, DbErr> {
+    sensitive_folder::Entity::find()
+        .filter(sensitive_folder::Column::Username.eq(owner))
+        .order_by_asc(sensitive_folder::Column::Path)
+        .all(db)
+        .await
+}
+
+/// Whether `path` of `owner` is a sensitive folder or below one
+pub async fn is_sensitive(db: &DatabaseConnection, owner: &str, path: &str) -> Result<bool, DbErr> {
+    let path = normalize(path);
+    Ok(marks(db, owner).await?.iter().any(|mark| is_within(&path, &mark.path)))
+}
+
+/// Whether `path` of `owner` is below a sensitive folder or holds one
+pub async fn contains_sensitive(db: &DatabaseConnection, owner: &str, path: &str) -> Result<bool, DbErr> {
+    let path = normalize(path);
+    Ok(marks(db, owner)
+        .await?
+        .iter()
+        .any(|mark| is_within(&path, &mark.path) || is_within(&mark.path, &path)))
+}
+
+/// Move the marks of `old_path` and the folders below it to `new_path`
+///
+/// Marks left at `new_path` by a folder that was replaced are dropped first.
+pub async fn move_marks(db: &DatabaseConnection, owner: &str, old_path: &str, new_path: &str) -> Result<(), DbErr> {
+    move_rows::<sensitive_folder::Entity>(db, owner, old_path, new_path).await
+}
+
+/// Kind of watermark the file at `path` of `owner` gets, None if it is
+/// served as it is
+pub async fn watermark_kind(db: &DatabaseConnection, owner: &str, path: &str) -> Result<Option<Kind>, DbErr> {
+    let Some(kind) = Kind::of(&mime::extension(path)) else {
+        return Ok(None);
+    };
+    Ok(is_sensitive(db, owner, path).await?.then_some(kind))
+}
+
+/// Watermark the `content` of a file for `viewer` on the job pool, returning
+/// the new content and its media type
+///
+/// Content that can't be watermarked is answered with an error, never
+/// served as it is.
+pub async fn render(viewer: &str, kind: Kind, content: Vec<u8>) -> Result<(Vec<u8>, &'static str), Response> {
+    let mark = Watermark::new(viewer, chrono::Local::now());
+    let rendered = TASK_MANAGER
+        .jobs()
+        .run(viewer, move || watermark::apply(kind, &content, &mark))
+        .await;
+    match rendered {
+        Ok(Ok(rendered)) => Ok(rendered),
+        Ok(Err(e)) => {
+            tracing::debug!("Failed to watermark file: {}", e);
+            Err(error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "cannot watermark file"))
+        }
+        Err(JobError::Busy) => Err(thumbnail::busy_response()),
+        Err(JobError::Failed(e)) => {
+            tracing::error!("Watermark worker failed: {}", e);
+            Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "internal error"))
+        }
+    }
+}
+
+/// Serve `converted`, a conversion of the file at `location` shown as
+/// `shown`, such as the JPEG of a photo, watermarked for `viewer` like a file
+/// of `kind` if the file is in a sensitive folder
+pub async fn serve_converted(
+    db: &DatabaseConnection,
+    location: &Location,
+    converted: &Path,
+    shown: &Path,
+    kind: Kind,
+    viewer: &str,
+) -> Response {
+    let content_type = match kind {
+        Kind::Image => "image/jpeg",
+        Kind::Pdf => "application/pdf",
+    };
+    let sensitive = match is_sensitive(db, &location.owner, &location.path).await {
+        Ok(sensitive) => sensitive,
+        Err(e) => {
+            tracing::error!("Failed to look up sensitive folders of {}: {}", location.path, e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "internal error");
+        }
+    };
+    if !sensitive {
+        return match fs::File::open(converted).await {
+            Ok(file) => preview::serve_reader(file, shown, content_type),
+            Err(e) => {
+                tracing::error!("Failed to open {:?}: {}", converted, e);
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to open file")
+            }
+        };
+    }
+
+    let content = match fs::read(converted).await {
+        Ok(content) if content.len() as u64 > MAX_SIZE => {
+            return error_response(StatusCode::PAYLOAD_TOO_LARGE, "file too large to watermark")
+        }
+        Ok(content) => content,
+        Err(e) => {
+            tracing::error!("Failed to read {:?}: {}", converted, e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to read file");
+        }
+    };
+    let (data, content_type) = match render(viewer, kind, content).await {
+        Ok(rendered) => rendered,
+        Err(response) => return response,
+    };
+    let filename = shown.file_name().and_then(|n| n.to_str()).unwrap_or("preview");
+    let body = futures::stream::iter([Ok::<_, std::io::Error>(data)]);
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        // Marked with the time, never reused
+        .header(header::CACHE_CONTROL, "private, no-store")
+        .header(header::CONTENT_DISPOSITION, format!("inline; filename=\"{}\"", filename))
+        .body(Body::from_stream(traffic::counted(viewer, body)))
+        .unwrap()
+}
+
+/// Mark or unmark request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SensitiveRequest {
+    /// Folder, in the file API
+    pub path: String,
+}
+
+/// Query parameters for listing sensitive folders
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SensitiveListQuery {
+    /// A path in the tree whose folders are listed; the user's own if not set
+    pub path: Option<String>,
+}
+
+/// A sensitive folder
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SensitiveFolder {
+    /// Folder, in the file API
+    pub path: String,
+    #[serde(rename = "createdBy")]
+    pub created_by: String,
+    #[serde(rename = "createTime")]
+    pub create_time: i64,
+}
+
+/// POST /api/file/sensitive/set - Mark a folder as sensitive
+#[utoipa::path(
+    post,
+    path = "/api/file/sensitive/set",
+    tag = "file",
+    request_body = SensitiveRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
+pub async fn set_sensitive(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<SensitiveRequest>,
+) -> Json<ApiResponse<()>> {
+    let location = match locate_for_write(&state, &db, &current_user, &req.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    let is_dir = resolve_in_user_root(&state.config, &location.owner, &location.path).is_some_and(|full| full.is_dir());
+    if !is_dir {
+        return Json(ApiResponse::error(404, "目录不存在"));
+    }
+
+    let path = normalize(&location.path);
+    let result = async {
+        let existing = sensitive_folder::Entity::find()
+            .filter(sensitive_folder::Column::Username.eq(&location.owner))
+            .filter(sensitive_folder::Column::Path.eq(&path))
+            .one(&*db)
+            .await?;
+        if existing.is_none() {
+            sensitive_folder::ActiveModel {
+                username: Set(location.owner.clone()),
+                path: Set(path.clone()),
+                created_by: Set(current_user.username.clone()),
+                create_time: Set(chrono::Utc::now().timestamp()),
+                ..Default::default()
+            }
+            .insert(&*db)
+            .await?;
+        }
+        Ok::<_, DbErr>(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => {
+            let shown = format!("{}{}", location.prefix, path);
+            log_operation(&current_user.username, OpType::SetSensitive, &shown, OP_SUCCESS, None);
+            Json(ApiResponse::success_msg("已标记为敏感文件夹"))
+        }
+        Err(e) => {
+            tracing::error!("Failed to mark sensitive folder: {}", e);
+            Json(ApiResponse::error(500, "标记敏感文件夹失败"))
+        }
+    }
+}
+
+/// POST /api/file/sensitive/unset - Remove the mark of a folder
+///
+/// Only whoever set the mark, or an auditor, may remove it.
+#[utoipa::path(
+    post,
+    path = "/api/file/sensitive/unset",
+    tag = "file",
+    request_body = SensitiveRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
+pub async fn unset_sensitive(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<SensitiveRequest>,
+) -> Json<ApiResponse<()>> {
+    let location = match locate_for_write(&state, &db, &current_user, &req.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+
+    let path = normalize(&location.path);
+    let mark = sensitive_folder::Entity::find()
+        .filter(sensitive_folder::Column::Username.eq(&location.owner))
+        .filter(sensitive_folder::Column::Path.eq(&path))
+        .one(&*db)
+        .await;
+    let mark = match mark {
+        Ok(Some(mark)) => mark,
+        Ok(None) => return Json(ApiResponse::error(404, "该文件夹未标记为敏感")),
+        Err(e) => {
+            tracing::error!("Failed to look up sensitive folder: {}", e);
+            return Json(ApiResponse::error(500, "取消敏感标记失败"));
+        }
+    };
+    if mark.created_by != current_user.username && !current_user.can_audit() {
+        record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "只有标记者或审计员可以取消敏感标记"));
+    }
+
+    match sensitive_folder::Entity::delete_by_id(mark.id).exec(&*db).await {
+        Ok(_) => {
+            let shown = format!("{}{}", location.prefix, path);
+            log_operation(&current_user.username, OpType::UnsetSensitive, &shown, OP_SUCCESS, None);
+            Json(ApiResponse::success_msg("已取消敏感标记"))
+        }
+        Err(e) => {
+            tracing::error!("Failed to unmark sensitive folder: {}", e);
+            Json(ApiResponse::error(500, "取消敏感标记失败"))
+        }
+    }
+}
+
+/// GET /api/file/sensitive/list - Sensitive folders of a tree
+#[utoipa::path(
+    get,
+    path = "/api/file/sensitive/list",
+    tag = "file",
+    params(SensitiveListQuery),
+    responses((status = 200, body = ApiResponse<Vec<SensitiveFolder>>)),
+)]
+pub async fn list_sensitive(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<SensitiveListQuery>,
+) -> Json<ApiResponse<Vec<SensitiveFolder>>> {
+    let location = match locate(&state, &db, &current_user, query.path.as_deref().unwrap_or("/")).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    match marks(&*db, &location.owner).await {
+        Ok(marks) => Json(ApiResponse::success(
+            marks
+                .into_iter()
+                .map(|mark| SensitiveFolder {
+                    path: format!("{}{}", location.prefix, mark.path),
+                    created_by: mark.created_by,
+                    create_time: mark.create_time,
+                })
+                .collect(),
+        )),
+        Err(e) => {
+            tracing::error!("Failed to list sensitive folders: {}", e);
+            Json(ApiResponse::error(500, "查询敏感文件夹失败"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::FileService;
+    use crate::testing::{TestApp, TestEnv};
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn test_marks() {
+        let env = TestEnv::new().await;
+        let service = FileService::new(&env.config, &env.db, "alice");
+        service.mkdir("/", None, "hr").await.unwrap();
+        service.mkdir("/hr", None, "salaries").await.unwrap();
+        let alice = env.user("alice", &[crate::permission::perm::FILE]);
+        let req = SensitiveRequest { path: "/hr/salaries".to_string() };
+        let res = set_sensitive(State(env.state()), Extension(env.db_conn()), Extension(alice.clone()), Json(req)).await;
+        assert!(res.code);
+
+        assert!(is_sensitive(&env.db, "alice", "hr/salaries/2026.pdf").await.unwrap());
+        assert!(!is_sensitive(&env.db, "alice", "/hr").await.unwrap());
+        assert!(!is_sensitive(&env.db, "alice", "/hr/salaries-old").await.unwrap());
+        assert!(contains_sensitive(&env.db, "alice", "/hr").await.unwrap());
+        assert!(!is_sensitive(&env.db, "bob", "/hr/salaries/2026.pdf").await.unwrap());
+        assert_eq!(watermark_kind(&env.db, "alice", "/hr/salaries/a.PDF").await.unwrap(), Some(Kind::Pdf));
+        assert_eq!(watermark_kind(&env.db, "alice", "/hr/salaries/a.txt").await.unwrap(), None);
+
+        // Marks follow renames of the folder and those above it
+        service.rename("/hr", "people").await.unwrap();
+        assert!(is_sensitive(&env.db, "alice", "/people/salaries/2026.pdf").await.unwrap());
+        assert!(!is_sensitive(&env.db, "alice", "/hr/salaries/2026.pdf").await.unwrap());
+
+        // Only by the one who set it, or an auditor, is it removed
+        let bob = env.user("bob", &[crate::permission::perm::FILE]);
+        let req = || Json(SensitiveRequest { path: "/people/salaries".to_string() });
+        assert!(!unset_sensitive(State(env.state()), Extension(env.db_conn()), Extension(bob), req()).await.code);
+        assert!(unset_sensitive(State(env.state()), Extension(env.db_conn()), Extension(alice), req()).await.code);
+        assert!(!contains_sensitive(&env.db, "alice", "/").await.unwrap());
+        env.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_watermarked_downloads() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let body = serde_json::json!({ "parentPath": "/", "name": "secret" });
+        assert!(admin.post_json("/api/file/mkdir", &body).await.status().is_success());
+        let mut png = Cursor::new(Vec::new());
+        image::RgbImage::from_pixel(400, 300, image::Rgb([255, 255, 255]))
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+        assert!(admin.upload("/secret", "plan.png", &png).await.status().is_success());
+        assert!(admin.upload("/secret", "notes.txt", b"plain").await.status().is_success());
+
+        let res: serde_json::Value = admin
+            .post_json("/api/file/sensitive/set", &serde_json::json!({ "path": "/secret" }))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        let list: serde_json::Value = admin.get("/api/file/sensitive/list").await.json().await.unwrap();
+        assert_eq!(list["data"][0]["path"], "/secret");
+        assert_eq!(list["data"][0]["createdBy"], "admin");
+
+        // Images come back marked, other files as they are
+        for url in ["/api/file/preview/single?path=/secret/plan.png", "/api/file/download/single?path=/secret/plan.png"] {
+            let res = admin.get(url).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()[header::CACHE_CONTROL], "private, no-store");
+            let data = res.bytes().await.unwrap();
+            assert_ne!(data.as_ref(), png.as_slice());
+            let image = image::load_from_memory(&data).unwrap().to_rgb8();
+            assert_eq!(image.dimensions(), (400, 300));
+            assert!(image.pixels().any(|p| p.0 != [255, 255, 255]));
+        }
+        let res = admin.get("/api/file/download/single?path=/secret/notes.txt").await;
+        assert_eq!(res.bytes().await.unwrap().as_ref(), b"plain");
+        let res = admin.get("/api/file/thumbnail?path=/secret/plan.png&size=200").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(header::ETAG).is_none());
+
+        // Zip downloads of the folder, or of what holds it, are refused
+        let body = serde_json::json!({ "parentDir": "/", "files": ["secret"] });
+        let res: serde_json::Value = admin.post_json("/api/file/download/pre", &body).await.json().await.unwrap();
+        let guid = res["guid"].as_str().unwrap();
+        let res = admin.get(&format!("/api/file/download?guid={}", guid)).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res: serde_json::Value = admin.post_json("/api/file/download/archive", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        app.close().await;
+    }
+}
@@ -0,0 +1,385 @@
+//! Service account handlers
+//!
+//! Service accounts are users for integrations (backup scripts, CI pipelines).
+//! They cannot log in with a password and authenticate with API tokens only.
+
+use axum::{
+    extract::State,
+    response::Json,
+    Extension,
+};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::entity::{api_token, file_info, user};
+use crate::entity::op_log::OpType;
+use crate::handlers::abuse;
+use crate::handlers::file::resolve_in_root;
+use crate::handlers::audit::service::log_admin_operation;
+use crate::handlers::legal_hold;
+use crate::handlers::token::{
+    insert_token, is_valid_token_name, CreatedTokenResponse, RevokeTokenRequest, TokenResponse,
+};
+use crate::handlers::user_import::check_username;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::permission::normalize_permissions;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+
+const OP_SUCCESS: &str = "成功";
+
+/// Add service account request
+#[derive(Debug, Deserialize)]
+pub struct AddServiceAccountRequest {
+    pub username: String,
+    #[serde(rename = "fullName", default)]
+    pub full_name: String,
+    #[serde(rename = "departmentId", default)]
+    pub department_id: i64,
+    pub role: Option<String>,
+    pub permissions: Option<String>,
+}
+
+/// Delete service account request
+#[derive(Debug, Deserialize)]
+pub struct DeleteServiceAccountRequest {
+    pub ids: Vec<i64>,
+}
+
+/// Create token request
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    pub username: String,
+    pub name: String,
+    /// Days until the token expires (0 or missing = never)
+    #[serde(rename = "expireDays", default)]
+    pub expire_days: i64,
+}
+
+/// Service account with its tokens
+#[derive(Debug, Serialize)]
+pub struct ServiceAccountResponse {
+    pub id: i64,
+    pub username: String,
+    #[serde(rename = "fullName")]
+    pub full_name: String,
+    #[serde(rename = "departmentId")]
+    pub department_id: i64,
+    pub status: i32,
+    pub tokens: Vec<TokenResponse>,
+}
+
+/// Check if user can manage service accounts
+fn can_manage_service_accounts(user: &CurrentUser) -> bool {
+    user.can_contacts()
+}
+
+/// GET /api/service-account/list
+pub async fn list_service_accounts(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Json<ApiResponse<Vec<ServiceAccountResponse>>> {
+    if !can_manage_service_accounts(&current_user) {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let accounts = match user::Entity::find()
+        .filter(user::Column::AccountType.eq(user::ACCOUNT_TYPE_SERVICE))
+        .order_by_asc(user::Column::Username)
+        .all(&*db)
+        .await
+    {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    let mut response = Vec::with_capacity(accounts.len());
+    for account in accounts {
+        let tokens = match api_token::Entity::find()
+            .filter(api_token::Column::Username.eq(&account.username))
+            .order_by_desc(api_token::Column::CreateTime)
+            .all(&*db)
+            .await
+        {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                tracing::error!("Database error: {}", e);
+                return Json(ApiResponse::error(500, "internal error"));
+            }
+        };
+        response.push(ServiceAccountResponse {
+            id: account.id,
+            username: account.username,
+            full_name: account.full_name,
+            department_id: account.department_id,
+            status: account.status,
+            tokens: tokens.into_iter().map(Into::into).collect(),
+        });
+    }
+
+    Json(ApiResponse::success(response))
+}
+
+/// POST /api/service-account/add
+pub async fn add_service_account(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<AddServiceAccountRequest>,
+) -> Json<ApiResponse<()>> {
+    if !can_manage_service_accounts(&current_user) {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+    if let Some(message) = check_username(&req.username) {
+        return Json(ApiResponse::error(400, message));
+    }
+
+    match user::Entity::find()
+        .filter(user::Column::Username.eq(&req.username))
+        .one(&*db)
+        .await
+    {
+        Ok(Some(_)) => return Json(ApiResponse::error(400, "用户名已存在")),
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+        Ok(None) => {}
+    }
+
+    // Random unusable password: service accounts never log in with a password
+    let password = match bcrypt::hash(uuid::Uuid::new_v4().to_string(), 12) {
+        Ok(h) => h,
+        Err(e) => {
+            tracing::error!("Failed to hash password: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    let full_name = if req.full_name.is_empty() { req.username.clone() } else { req.full_name.clone() };
+    let account = user::ActiveModel {
+        username: Set(req.username.clone()),
+        password: Set(password),
+        full_name: Set(full_name),
+        department_id: Set(req.department_id),
+        dept_name: Set(String::new()),
+        status: Set(1),
+        last_login: Set(0),
+        permissions: Set(String::new()),
+        account_type: Set(user::ACCOUNT_TYPE_SERVICE.to_string()),
+        ..Default::default()
+    };
+
+    if let Err(e) = account.insert(&*db).await {
+        tracing::error!("Failed to create service account: {}", e);
+        return Json(ApiResponse::error(500, "internal error"));
+    }
+
+    // Service accounts still own a storage root for the files they manage
+    let user_dir = state.config.root_dir.join(&req.username);
+    if let Err(e) = tokio::fs::create_dir_all(&user_dir).await {
+        tracing::error!("Failed to create user directory: {}", e);
+    }
+
+    if let Some(perm_enforcer) = state.get_perm().await.as_ref() {
+        if let Some(role) = &req.role {
+            if let Err(e) = perm_enforcer.set_user_role(&req.username, Some(role)).await {
+                tracing::error!("Failed to assign role: {}", e);
+            }
+        }
+        if let Some(perms) = req.permissions.as_deref() {
+            let perm_list = normalize_permissions(perms);
+            let perm_refs: Vec<&str> = perm_list.iter().map(String::as_str).collect();
+            if let Err(e) = perm_enforcer.set_permissions(&req.username, &perm_refs).await {
+                tracing::error!("Failed to set user permissions: {}", e);
+            }
+        }
+    }
+
+    let op_desc = format!("服务账号: {}", req.username);
+    log_admin_operation(&current_user.username, OpType::CreateServiceAccount, &op_desc, OP_SUCCESS, None);
+    Json(ApiResponse::success_msg("success"))
+}
+
+/// POST /api/service-account/delete
+pub async fn delete_service_accounts(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<DeleteServiceAccountRequest>,
+) -> Json<ApiResponse<()>> {
+    if !can_manage_service_accounts(&current_user) {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let accounts = match user::Entity::find()
+        .filter(user::Column::Id.is_in(req.ids))
+        .filter(user::Column::AccountType.eq(user::ACCOUNT_TYPE_SERVICE))
+        .all(&*db)
+        .await
+    {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    let perm_enforcer = state.get_perm().await;
+    for account in accounts {
+        // Deleting the account would delete the held files with it
+        if !matches!(legal_hold::held(&db, &account.username, "/").await, Ok(None)) {
+            tracing::warn!("Not deleting service account {}: files under legal hold", account.username);
+            continue;
+        }
+        if let Err(e) = user::Entity::delete_by_id(account.id).exec(&*db).await {
+            tracing::error!("Failed to delete service account {}: {}", account.username, e);
+            continue;
+        }
+        let _ = api_token::Entity::delete_many()
+            .filter(api_token::Column::Username.eq(&account.username))
+            .exec(&*db)
+            .await;
+        let _ = file_info::Entity::delete_many()
+            .filter(file_info::Column::Username.eq(&account.username))
+            .exec(&*db)
+            .await;
+        if let Some(ref enforcer) = perm_enforcer {
+            if let Err(e) = enforcer.set_user_role(&account.username, None).await {
+                tracing::error!("Failed to remove role: {}", e);
+            }
+            if let Err(e) = enforcer.set_permissions(&account.username, &[]).await {
+                tracing::error!("Failed to remove permissions: {}", e);
+            }
+        }
+        // Accounts created before usernames were checked may name a path outside the root
+        match resolve_in_root(&state.config.root_dir, &account.username) {
+            Some(user_dir) => {
+                if let Err(e) = tokio::fs::remove_dir_all(&user_dir).await {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        tracing::error!("Failed to delete user directory {}: {}", account.username, e);
+                    }
+                }
+            }
+            None => tracing::warn!("Not deleting directory of service account {}: outside the root", account.username),
+        }
+
+        let op_desc = format!("服务账号: {}", account.username);
+        log_admin_operation(&current_user.username, OpType::DeleteServiceAccount, &op_desc, OP_SUCCESS, None);
+    }
+
+    Json(ApiResponse::success_msg("success"))
+}
+
+/// POST /api/service-account/token - Create a token for a service account
+pub async fn create_token(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<CreateTokenRequest>,
+) -> Json<ApiResponse<CreatedTokenResponse>> {
+    if !can_manage_service_accounts(&current_user) {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+    if !is_valid_token_name(&req.name) {
+        return Json(ApiResponse::error(400, "令牌名称无效"));
+    }
+
+    match user::Entity::find()
+        .filter(user::Column::Username.eq(&req.username))
+        .filter(user::Column::AccountType.eq(user::ACCOUNT_TYPE_SERVICE))
+        .one(&*db)
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return Json(ApiResponse::error(404, "服务账号不存在")),
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    }
+
+    match insert_token(&db, &req.username, &req.name, req.expire_days, &[]).await {
+        Ok(created) => {
+            let op_desc = format!("服务账号: {}, 令牌: {}", req.username, req.name);
+            log_admin_operation(&current_user.username, OpType::CreateToken, &op_desc, OP_SUCCESS, None);
+            Json(ApiResponse::success(created))
+        }
+        Err(e) => {
+            tracing::error!("Failed to create token: {}", e);
+            Json(ApiResponse::error(500, "internal error"))
+        }
+    }
+}
+
+/// POST /api/service-account/token/revoke
+pub async fn revoke_token(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<RevokeTokenRequest>,
+) -> Json<ApiResponse<()>> {
+    if !can_manage_service_accounts(&current_user) {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let token = match api_token::Entity::find_by_id(req.id).one(&*db).await {
+        Ok(Some(token)) => token,
+        Ok(None) => return Json(ApiResponse::error(404, "令牌不存在")),
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    if let Err(e) = api_token::Entity::delete_by_id(token.id).exec(&*db).await {
+        tracing::error!("Failed to revoke token: {}", e);
+        return Json(ApiResponse::error(500, "internal error"));
+    }
+
+    let op_desc = format!("服务账号: {}, 令牌: {}", token.username, token.name);
+    log_admin_operation(&current_user.username, OpType::RevokeToken, &op_desc, OP_SUCCESS, None);
+    Json(ApiResponse::success_msg("success"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission::perm;
+    use crate::testing::TestEnv;
+
+    #[tokio::test]
+    async fn test_add_rejects_paths() {
+        let env = TestEnv::new().await;
+        std::fs::create_dir_all(&env.config.root_dir).unwrap();
+        let admin = env.user("admin", &[perm::CONTACTS]);
+        let add = |username: &str| {
+            let req = AddServiceAccountRequest {
+                username: username.to_string(),
+                full_name: String::new(),
+                department_id: 0,
+                role: None,
+                permissions: None,
+            };
+            add_service_account(State(env.state()), Extension(env.db_conn()), Extension(admin.clone()), Json(req))
+        };
+
+        assert!(!add("../x").await.code);
+        assert!(!add("a/../../etc").await.code);
+        assert!(!env.dir.join("x").exists());
+        assert!(user::Entity::find().all(&env.db).await.unwrap().is_empty());
+
+        assert!(add("backup").await.code);
+        assert!(env.config.root_dir.join("backup").is_dir());
+        env.close().await;
+    }
+}
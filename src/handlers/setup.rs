@@ -14,15 +14,57 @@ use crate::permission::PermissionEnforcer;
 use crate::state::AppState;
 
 /// Database connection test request
+///
+/// SQLite only needs `path`, PostgreSQL the network fields.
 #[derive(Debug, Deserialize)]
 pub struct TestDbRequest {
     #[serde(rename = "type")]
     pub db_type: String,
+    #[serde(default)]
     pub host: String,
+    #[serde(default)]
     pub port: String,
+    #[serde(default)]
     pub database: String,
+    #[serde(default)]
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    #[serde(default)]
+    pub path: String,
+}
+
+impl TestDbRequest {
+    /// Convert to a DatabaseConfig, None for unsupported types
+    fn into_config(self) -> Option<DatabaseConfig> {
+        match self.db_type.as_str() {
+            "postgres" => Some(DatabaseConfig {
+                db_type: self.db_type,
+                host: self.host,
+                port: self.port.parse().unwrap_or(5432),
+                name: self.database,
+                user: self.username,
+                password: self.password,
+                ..DatabaseConfig::default()
+            }),
+            "sqlite" if !self.path.trim().is_empty() => Some(DatabaseConfig {
+                db_type: self.db_type,
+                path: self.path.trim().into(),
+                ..DatabaseConfig::default()
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn invalid_db_type() -> (StatusCode, Json<SetupResponse>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(SetupResponse {
+            code: 1,
+            message: "不支持的数据库类型或缺少数据库文件路径".to_string(),
+        }),
+    )
 }
 
 /// Setup response
@@ -37,18 +79,11 @@ pub struct SetupResponse {
 pub async fn test_db_connection(
     Json(req): Json<TestDbRequest>,
 ) -> (StatusCode, Json<SetupResponse>) {
-    // Convert request to DatabaseConfig
-    let port: u16 = req.port.parse().unwrap_or(5432);
-    let config = DatabaseConfig {
-        db_type: req.db_type,
-        host: req.host,
-        port,
-        name: req.database,
-        user: req.username,
-        password: req.password,
+    let Some(config) = req.into_config() else {
+        return invalid_db_type();
     };
 
-    tracing::info!("Testing database connection: {}:{}/{}", config.host, config.port, config.name);
+    tracing::info!("Testing database connection: {}", config.location());
 
     // Test connection
     match db::test_connection(&config).await {
@@ -78,18 +113,11 @@ pub async fn init_db(
     State(state): State<AppState>,
     Json(req): Json<TestDbRequest>,
 ) -> (StatusCode, Json<SetupResponse>) {
-    // Convert request to DatabaseConfig
-    let port: u16 = req.port.parse().unwrap_or(5432);
-    let config = DatabaseConfig {
-        db_type: req.db_type,
-        host: req.host,
-        port,
-        name: req.database,
-        user: req.username,
-        password: req.password,
+    let Some(config) = req.into_config() else {
+        return invalid_db_type();
     };
 
-    tracing::info!("Initializing database: {}:{}/{}", config.host, config.port, config.name);
+    tracing::info!("Initializing database: {}", config.location());
 
     // Test connection first
     if let Err(e) = db::test_connection(&config).await {
@@ -0,0 +1,159 @@
+//! Shredder
+//!
+//! Removing large trees takes a while, and longer with `secure` set in
+//! `[shredder]`, which overwrites every file with zeros first. Data removed
+//! for good is therefore renamed into a queue folder, which is instant on
+//! the same filesystem, and a background worker removes it from there.
+//! Whatever is left in the queue at startup is removed then.
+//!
+//! Deleting many files at once runs as a [task](crate::task) instead of in
+//! the request, see [`is_large`].
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use tokio::sync::Notify;
+
+use crate::config::Config;
+use crate::state::AppState;
+
+/// Wakes the worker when something was queued
+static QUEUED: LazyLock<Notify> = LazyLock::new(Notify::new);
+
+/// Folder of the data waiting to be removed, on the volume of the trash it
+/// comes from
+pub fn queue_dir(config: &Config) -> PathBuf {
+    match &config.trash_dir {
+        Some(dir) => dir.join(".shred"),
+        None => config.root_dir.join(".shred"),
+    }
+}
+
+/// Hand `path` to the worker for removal
+pub async fn enqueue(config: &Config, path: &Path) -> std::io::Result<()> {
+    let queue = queue_dir(config);
+    tokio::fs::create_dir_all(&queue).await?;
+    tokio::fs::rename(path, queue.join(uuid::Uuid::new_v4().to_string())).await?;
+    QUEUED.notify_one();
+    Ok(())
+}
+
+/// Overwrite a file with zeros and flush it to disk
+fn overwrite(path: &Path) -> std::io::Result<()> {
+    let mut file = std::fs::File::options().write(true).open(path)?;
+    let zeros = vec![0u8; 1024 * 1024];
+    let mut left = file.metadata()?.len();
+    while left > 0 {
+        let n = left.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..n])?;
+        left -= n as u64;
+    }
+    file.sync_all()
+}
+
+/// Whether other names refer to the data of a file
+#[cfg(unix)]
+fn is_shared(metadata: &std::fs::Metadata) -> bool {
+    std::os::unix::fs::MetadataExt::nlink(metadata) > 1
+}
+
+#[cfg(not(unix))]
+fn is_shared(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Remove a file or folder, overwriting the files first if `secure`
+pub fn shred(path: &Path, secure: bool) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            shred(&entry?.path(), secure)?;
+        }
+        std::fs::remove_dir(path)
+    } else {
+        // The data of hard links is still in use
+        if secure && metadata.is_file() && !is_shared(&metadata) {
+            overwrite(path)?;
+        }
+        std::fs::remove_file(path)
+    }
+}
+
+/// Whether deleting `paths` touches more than `limit` entries, counting the
+/// ones inside folders
+///
+/// Stops counting once the limit is passed, so huge folders are cheap.
+pub fn is_large(paths: &[PathBuf], limit: usize) -> bool {
+    let mut count = 0;
+    let mut stack: Vec<PathBuf> = paths.to_vec();
+    while let Some(path) = stack.pop() {
+        count += 1;
+        if count > limit {
+            return true;
+        }
+        if std::fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir()) {
+            if let Ok(entries) = std::fs::read_dir(&path) {
+                stack.extend(entries.flatten().map(|e| e.path()));
+            }
+        }
+    }
+    false
+}
+
+/// Remove everything in the queue, the number of items removed
+pub async fn drain(config: &Config) -> usize {
+    let Ok(mut entries) = tokio::fs::read_dir(queue_dir(config)).await else {
+        return 0;
+    };
+    let mut removed = 0;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let (path, secure) = (entry.path(), config.shredder.secure);
+        match tokio::task::spawn_blocking(move || shred(&path, secure)).await {
+            Ok(Ok(())) => removed += 1,
+            Ok(Err(e)) => tracing::error!("Failed to shred {}: {}", entry.path().display(), e),
+            Err(e) => tracing::error!("Shredder worker panicked: {}", e),
+        }
+    }
+    removed
+}
+
+/// Start the background worker removing queued data
+pub fn start(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            match drain(&state.config).await {
+                0 => {}
+                n => tracing::info!("Shredded {} deleted items", n),
+            }
+            // Retry failed items now and then
+            let _ = tokio::time::timeout(std::time::Duration::from_secs(3600), QUEUED.notified()).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_enqueue_and_drain() {
+        let dir = std::env::temp_dir().join(format!("datadisk-shred-{}", uuid::Uuid::new_v4()));
+        let mut config = Config { root_dir: dir.clone(), ..Config::default() };
+        config.shredder.secure = true;
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(tree.join("a/b")).unwrap();
+        std::fs::write(tree.join("a/b/c.txt"), b"secret").unwrap();
+        std::fs::write(tree.join("d.txt"), b"secret").unwrap();
+
+        // Five entries including the tree itself
+        assert!(is_large(std::slice::from_ref(&tree), 4));
+        assert!(!is_large(std::slice::from_ref(&tree), 5));
+
+        enqueue(&config, &tree).await.unwrap();
+        assert!(!tree.exists());
+        assert_eq!(drain(&config).await, 1);
+        assert_eq!(std::fs::read_dir(queue_dir(&config)).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,219 @@
+//! Signed preview and download URLs
+//!
+//! `<img>` and `<video>` tags, and office viewers fetching a file from
+//! outside, can't send the session cookie or an API token. `/api/file/signed`
+//! hands out a short-lived URL of `/api/file/preview/single` or
+//! `/api/file/download/single` with a `signature` parameter: a token signed
+//! with `[signed_url]` that names the user, the file and the endpoint. The
+//! auth layer accepts it in place of a session for that request only, so the
+//! file is served, logged and counted as if the user opened it.
+//!
+//! With `[html_preview] content_origin`, preview URLs point at that second
+//! domain, the only one uploaded HTML is rendered on.
+
+use axum::{
+    extract::{Query, State},
+    http::Uri,
+    response::Json,
+    Extension,
+};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::SignedUrlConfig;
+use crate::handlers::file::{locate, resolve_in_user_root};
+use crate::handlers::path::normalize;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+
+const PREVIEW_PATH: &str = "/api/file/preview/single";
+const DOWNLOAD_PATH: &str = "/api/file/download/single";
+
+/// Key used when `[signed_url]` has none
+static RANDOM_SECRET: LazyLock<String> = LazyLock::new(|| uuid::Uuid::new_v4().simple().to_string());
+
+fn secret(config: &SignedUrlConfig) -> &str {
+    if config.secret.is_empty() {
+        &RANDOM_SECRET
+    } else {
+        &config.secret
+    }
+}
+
+/// What a signature allows
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Claims {
+    /// Username
+    sub: String,
+    /// File path as the user sees it
+    path: String,
+    /// Endpoint path
+    url: String,
+    exp: i64,
+}
+
+/// URL of `endpoint` for `path` with a signature
+fn with_query(endpoint: &str, path: &str, signature: &str) -> String {
+    format!(
+        "{}?path={}&signature={}",
+        endpoint,
+        utf8_percent_encode(path, NON_ALPHANUMERIC),
+        utf8_percent_encode(signature, NON_ALPHANUMERIC)
+    )
+}
+
+fn sign(config: &SignedUrlConfig, claims: &Claims) -> Result<String, jsonwebtoken::errors::Error> {
+    encode(&Header::default(), claims, &EncodingKey::from_secret(secret(config).as_bytes()))
+}
+
+/// Username a request is signed for, None if it carries no valid signature
+/// for its endpoint and file
+pub fn signed_user(config: &SignedUrlConfig, uri: &Uri) -> Option<String> {
+    if uri.path() != PREVIEW_PATH && uri.path() != DOWNLOAD_PATH {
+        return None;
+    }
+    let Query(query) = Query::<HashMap<String, String>>::try_from_uri(uri).ok()?;
+    let mut validation = Validation::default();
+    validation.leeway = 0;
+    let claims = decode::<Claims>(
+        query.get("signature")?,
+        &DecodingKey::from_secret(secret(config).as_bytes()),
+        &validation,
+    )
+    .ok()?
+    .claims;
+    (claims.url == uri.path() && claims.path == normalize(query.get("path")?)).then_some(claims.sub)
+}
+
+/// Signed URL query
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct SignedQuery {
+    /// File to sign the URL for
+    pub path: String,
+    /// Sign a download (attachment) URL instead of a preview
+    #[serde(default)]
+    pub download: bool,
+    /// Seconds the URL stays valid, capped at `max_ttl_secs`
+    pub ttl: Option<u64>,
+}
+
+/// Signed URL
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SignedUrl {
+    /// URL relative to the server, usable without a session; absolute on
+    /// `[html_preview] content_origin` for previews when there is one
+    pub url: String,
+    /// Unix timestamp the URL stops working at
+    #[serde(rename = "expiresAt")]
+    pub expires_at: i64,
+}
+
+/// GET /api/file/signed
+#[utoipa::path(
+    get,
+    path = "/api/file/signed",
+    tag = "file",
+    params(SignedQuery),
+    responses((status = 200, body = ApiResponse<SignedUrl>)),
+)]
+pub async fn signed_url(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<SignedQuery>,
+) -> Json<ApiResponse<SignedUrl>> {
+    // Sign only what the user can open now
+    let location = match locate(&state, &db, &current_user, &query.path).await {
+        Ok(location) => location,
+        Err((_, error)) => return Json(ApiResponse::error(403, error)),
+    };
+    let is_file = resolve_in_user_root(&state.config, &location.owner, &location.path)
+        .is_some_and(|path| path.is_file());
+    if !is_file {
+        return Json(ApiResponse::error(404, "文件不存在"));
+    }
+
+    let config = &state.config.signed_url;
+    let ttl = query.ttl.unwrap_or(config.ttl_secs).clamp(1, config.max_ttl_secs.max(1));
+    let url = if query.download { DOWNLOAD_PATH } else { PREVIEW_PATH };
+    let path = normalize(&query.path);
+    let claims = Claims {
+        sub: current_user.username.clone(),
+        path: path.clone(),
+        url: url.to_string(),
+        exp: chrono::Utc::now().timestamp() + ttl as i64,
+    };
+    // Previews are opened on the content origin, where the session is not
+    let origin = if query.download { "" } else { state.config.html_preview.content_origin.trim_end_matches('/') };
+    match sign(config, &claims) {
+        Ok(signature) => {
+            let url = format!("{}{}", origin, with_query(url, &path, &signature));
+            Json(ApiResponse::success(SignedUrl { url, expires_at: claims.exp }))
+        }
+        Err(e) => {
+            tracing::error!("Failed to sign URL: {}", e);
+            Json(ApiResponse::error(500, "internal error"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    fn uri(endpoint: &str, path: &str, signature: &str) -> Uri {
+        with_query(endpoint, path, signature).parse().unwrap()
+    }
+
+    #[test]
+    fn test_signed_user() {
+        let config = SignedUrlConfig::default();
+        let claims = |exp| Claims {
+            sub: "alice".to_string(),
+            path: "/photos/a.jpg".to_string(),
+            url: PREVIEW_PATH.to_string(),
+            exp,
+        };
+        let now = chrono::Utc::now().timestamp();
+        let signature = sign(&config, &claims(now + 60)).unwrap();
+        assert_eq!(signed_user(&config, &uri(PREVIEW_PATH, "photos/a.jpg", &signature)), Some("alice".to_string()));
+        // Another file, another endpoint, another key, expired
+        assert_eq!(signed_user(&config, &uri(PREVIEW_PATH, "/photos/b.jpg", &signature)), None);
+        assert_eq!(signed_user(&config, &uri(DOWNLOAD_PATH, "/photos/a.jpg", &signature)), None);
+        let other = SignedUrlConfig { secret: "other".to_string(), ..SignedUrlConfig::default() };
+        assert_eq!(signed_user(&other, &uri(PREVIEW_PATH, "/photos/a.jpg", &signature)), None);
+        let expired = sign(&config, &claims(now - 1)).unwrap();
+        assert_eq!(signed_user(&config, &uri(PREVIEW_PATH, "/photos/a.jpg", &expired)), None);
+    }
+
+    #[tokio::test]
+    async fn test_signed_url() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "a.txt", b"hello").await.status().is_success());
+
+        let res: serde_json::Value = admin.get("/api/file/signed?path=/a.txt&download=true").await.json().await.unwrap();
+        assert_eq!(res["code"], true);
+        let url = res["data"]["url"].as_str().unwrap().to_string();
+        assert!(url.starts_with(DOWNLOAD_PATH));
+
+        // Works without the session cookie
+        let anonymous = reqwest::Client::new();
+        let res = anonymous.get(app.url(&url)).send().await.unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(res.bytes().await.unwrap().as_ref(), b"hello");
+        let res = anonymous.get(app.url(&url.replace(DOWNLOAD_PATH, PREVIEW_PATH))).send().await.unwrap();
+        assert_eq!(res.status(), 401);
+
+        let res: serde_json::Value = admin.get("/api/file/signed?path=/missing.txt").await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        app.close().await;
+    }
+}
@@ -0,0 +1,178 @@
+//! Storage areas
+//!
+//! Besides the user trees, derived and auxiliary data lives in directories of
+//! its own, each of which can be put on another volume: the trash
+//! (`trash_dir`), the thumbnail cache (`thumbnail_cache_dir`), temp
+//! artifacts and the work directories of tasks (`[artifacts] dir`), database
+//! backups (`[scheduler] backup_dir`), compressed copies of files
+//! (`[compression] dir`) and uploads held for approval (`[dlp] hold_dir`).
+//! [`validate`] creates them and checks they can be written before the
+//! server starts, and `/api/admin/storage` reports the space each one takes.
+
+use axum::{extract::State, response::Json, Extension};
+use serde::Serialize;
+use std::path::PathBuf;
+use utoipa::ToSchema;
+
+use crate::config::Config;
+use crate::handlers::abuse;
+use crate::handlers::trash::dir_size;
+use crate::middleware::auth::CurrentUser;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+
+/// A directory of derived or auxiliary data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Area {
+    Trash,
+    Thumbnails,
+    /// Temp artifacts and task work directories
+    Artifacts,
+    Backups,
+    Compressed,
+    /// Uploads held for approval
+    DlpHold,
+}
+
+impl Area {
+    pub const ALL: [Area; 6] = [
+        Area::Trash,
+        Area::Thumbnails,
+        Area::Artifacts,
+        Area::Backups,
+        Area::Compressed,
+        Area::DlpHold,
+    ];
+
+    /// Directory of the area
+    pub fn dir(self, config: &Config) -> PathBuf {
+        match self {
+            Area::Trash => config.trash_dir(),
+            Area::Thumbnails => config.thumbnail_dir(),
+            Area::Artifacts => config.artifact_dir(),
+            Area::Backups => config.backup_dir(),
+            Area::Compressed => config.compression_dir(),
+            Area::DlpHold => config.dlp_hold_dir(),
+        }
+    }
+
+    /// Setting placing the area
+    pub fn setting(self) -> &'static str {
+        match self {
+            Area::Trash => "trash_dir",
+            Area::Thumbnails => "thumbnail_cache_dir",
+            Area::Artifacts => "artifacts.dir",
+            Area::Backups => "scheduler.backup_dir",
+            Area::Compressed => "compression.dir",
+            Area::DlpHold => "dlp.hold_dir",
+        }
+    }
+}
+
+/// Create the directory of every area and check files can be written there
+///
+/// Returns what is wrong with the first area that can't be used.
+pub fn validate(config: &Config) -> Result<(), String> {
+    for area in Area::ALL {
+        let dir = area.dir(config);
+        let fail = |e: std::io::Error| format!("{} ({}) is not usable: {}", dir.display(), area.setting(), e);
+        std::fs::create_dir_all(&dir).map_err(fail)?;
+        let probe = dir.join(format!(".probe-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&probe, b"").map_err(fail)?;
+        std::fs::remove_file(&probe).map_err(fail)?;
+    }
+    Ok(())
+}
+
+/// Space taken by an area
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AreaUsage {
+    pub area: Area,
+    pub path: String,
+    /// Setting placing the area
+    pub setting: String,
+    /// Bytes of the files in it
+    pub size: u64,
+}
+
+/// GET /api/admin/storage
+#[utoipa::path(
+    get,
+    path = "/api/admin/storage",
+    tag = "admin",
+    responses((status = 200, body = ApiResponse<Vec<AreaUsage>>)),
+)]
+pub async fn get_usage(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Json<ApiResponse<Vec<AreaUsage>>> {
+    if !current_user.can_audit() {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let config = state.config.clone();
+    let usage = tokio::task::spawn_blocking(move || {
+        Area::ALL
+            .into_iter()
+            .map(|area| {
+                let dir = area.dir(&config);
+                AreaUsage {
+                    area,
+                    path: dir.display().to_string(),
+                    setting: area.setting().to_string(),
+                    size: dir_size(&dir),
+                }
+            })
+            .collect()
+    })
+    .await;
+    match usage {
+        Ok(usage) => Json(ApiResponse::success(usage)),
+        Err(e) => {
+            tracing::error!("Failed to measure storage areas: {}", e);
+            Json(ApiResponse::error(500, "internal error"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    #[test]
+    fn test_validate() {
+        let dir = std::env::temp_dir().join(format!("datadisk-areas-{}", uuid::Uuid::new_v4()));
+        let mut config = Config { root_dir: dir.join("root"), ..Config::default() };
+        config.trash_dir = Some(dir.join("volume2/trash"));
+        validate(&config).unwrap();
+        assert!(dir.join("volume2/trash").is_dir());
+        assert!(dir.join("root/.thumbnails").is_dir());
+
+        // A file where a directory should be
+        std::fs::write(dir.join("blocked"), b"").unwrap();
+        config.scheduler.backup_dir = Some(dir.join("blocked/backups"));
+        let error = validate(&config).unwrap_err();
+        assert!(error.contains("scheduler.backup_dir"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_usage() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        assert!(admin.upload("/", "a.txt", b"hello").await.status().is_success());
+        let body = serde_json::json!({ "parentDir": "/", "files": ["a.txt"] });
+        assert!(admin.post_json("/api/file/delete", &body).await.status().is_success());
+
+        let res: serde_json::Value = admin.get("/api/admin/storage").await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        let areas = res["data"].as_array().unwrap();
+        assert_eq!(areas.len(), Area::ALL.len());
+        assert_eq!(areas[0]["area"], "trash");
+        assert_eq!(areas[0]["size"], 5);
+        app.close().await;
+    }
+}
@@ -0,0 +1,378 @@
+//! File tags
+//!
+//! Tags are stored by path, like the rest of the file API addresses files.
+//! Renames and moves carry them to the new path ([`move_tags`]); deleting a
+//! file leaves them in place, so a file restored from the trash gets its tags
+//! back, and queries skip paths that no longer exist.
+
+use axum::{
+    extract::{Query, State},
+    response::Json,
+    Extension,
+};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    Set,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::entity::file_tag;
+use crate::handlers::file::resolve_in_user_root;
+use crate::handlers::path::{move_rows, normalize, PathRows};
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::{ApiMessage, ApiResponse};
+use crate::state::AppState;
+
+/// Longest tag in characters
+const MAX_TAG_LENGTH: usize = 64;
+/// Tags of one file
+const MAX_TAGS_PER_FILE: usize = 32;
+
+/// Trimmed tag, None if it is empty, too long or has control characters
+fn check_tag(tag: &str) -> Option<&str> {
+    let tag = tag.trim();
+    let valid = !tag.is_empty()
+        && tag.chars().count() <= MAX_TAG_LENGTH
+        && !tag.chars().any(char::is_control);
+    valid.then_some(tag)
+}
+
+impl PathRows for file_tag::Entity {
+    const USERNAME: file_tag::Column = file_tag::Column::Username;
+    const PATH: file_tag::Column = file_tag::Column::Path;
+
+    fn path(row: &file_tag::Model) -> &str {
+        &row.path
+    }
+}
+
+/// Move the tags of `old_path` and everything below it to `new_path`
+///
+/// Tags left at `new_path` by a file that was replaced are dropped first.
+pub async fn move_tags(
+    db: &DatabaseConnection,
+    username: &str,
+    old_path: &str,
+    new_path: &str,
+) -> Result<(), DbErr> {
+    move_rows::<file_tag::Entity>(db, username, old_path, new_path).await
+}
+
+/// Add or remove tags request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TagRequest {
+    pub path: String,
+    pub tags: Vec<String>,
+}
+
+/// Query parameters for listing tags
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TagListQuery {
+    /// Tags of this file; all tags of the user if not set
+    pub path: Option<String>,
+}
+
+/// Query parameters for files by tag
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TagFilesQuery {
+    pub tag: String,
+}
+
+/// A tag and how many files have it
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: u64,
+}
+
+/// A file having a tag
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TaggedFile {
+    pub path: String,
+    #[serde(rename = "isDirectory")]
+    pub is_directory: bool,
+    pub size: i64,
+    /// Tags of the file
+    pub tags: Vec<String>,
+}
+
+/// POST /api/file/tag/add
+#[utoipa::path(
+    post,
+    path = "/api/file/tag/add",
+    tag = "file",
+    request_body = TagRequest,
+    responses((status = 200, body = ApiResponse<Vec<String>>)),
+)]
+pub async fn add_tags(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<TagRequest>,
+) -> Json<ApiResponse<Vec<String>>> {
+    let exists = resolve_in_user_root(&state.config, &current_user.username, &req.path)
+        .is_some_and(|full| full.exists());
+    if !exists {
+        return Json(ApiResponse::error(404, "文件不存在"));
+    }
+    let Some(tags) = check_tags(&req.tags) else {
+        return Json(ApiResponse::error(400, INVALID_TAG));
+    };
+
+    match add(&db, &current_user.username, &req.path, tags).await {
+        Ok(Some(tags)) => Json(ApiResponse::success(tags)),
+        Ok(None) => Json(ApiResponse::error(400, too_many_tags())),
+        Err(e) => {
+            tracing::error!("Failed to add tags: {}", e);
+            Json(ApiResponse::error(500, "添加标签失败"))
+        }
+    }
+}
+
+/// Message of tags refused by [`check_tags`]
+pub(crate) const INVALID_TAG: &str = "标签不能为空，且不超过64个字符";
+
+/// Message of [`add`] refusing more tags
+pub(crate) fn too_many_tags() -> String {
+    format!("每个文件最多{}个标签", MAX_TAGS_PER_FILE)
+}
+
+/// Trimmed tags, None if one of them is invalid
+pub(crate) fn check_tags(tags: &[String]) -> Option<Vec<String>> {
+    tags.iter().map(|tag| check_tag(tag).map(str::to_string)).collect()
+}
+
+/// Add checked `tags` to the file at `path`, returning all its tags, or None
+/// if it would have too many
+pub(crate) async fn add(
+    db: &DatabaseConnection,
+    username: &str,
+    path: &str,
+    tags: Vec<String>,
+) -> Result<Option<Vec<String>>, DbErr> {
+    let path = normalize(path);
+    let mut current = file_tags(db, username, &path).await?;
+    let now = chrono::Utc::now().timestamp();
+    for tag in tags {
+        if current.contains(&tag) {
+            continue;
+        }
+        if current.len() >= MAX_TAGS_PER_FILE {
+            return Ok(None);
+        }
+        file_tag::ActiveModel {
+            username: Set(username.to_string()),
+            path: Set(path.clone()),
+            tag: Set(tag.clone()),
+            create_time: Set(now),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+        current.push(tag);
+    }
+    Ok(Some(current))
+}
+
+/// Remove `tags` from the file at `path`
+pub(crate) async fn remove(
+    db: &DatabaseConnection,
+    username: &str,
+    path: &str,
+    tags: &[String],
+) -> Result<(), DbErr> {
+    let tags: Vec<&str> = tags.iter().map(|t| t.trim()).collect();
+    file_tag::Entity::delete_many()
+        .filter(file_tag::Column::Username.eq(username))
+        .filter(file_tag::Column::Path.eq(normalize(path)))
+        .filter(file_tag::Column::Tag.is_in(tags))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// POST /api/file/tag/remove
+#[utoipa::path(
+    post,
+    path = "/api/file/tag/remove",
+    tag = "file",
+    request_body = TagRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
+pub async fn remove_tags(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<TagRequest>,
+) -> Json<ApiResponse<()>> {
+    match remove(&db, &current_user.username, &req.path, &req.tags).await {
+        Ok(_) => Json(ApiResponse::success_msg("标签已删除")),
+        Err(e) => {
+            tracing::error!("Failed to remove tags: {}", e);
+            Json(ApiResponse::error(500, "删除标签失败"))
+        }
+    }
+}
+
+/// Tags of one file, in the order they were added
+async fn file_tags(db: &DatabaseConnection, username: &str, path: &str) -> Result<Vec<String>, DbErr> {
+    let tags = file_tag::Entity::find()
+        .filter(file_tag::Column::Username.eq(username))
+        .filter(file_tag::Column::Path.eq(path))
+        .order_by_asc(file_tag::Column::Id)
+        .all(db)
+        .await?;
+    Ok(tags.into_iter().map(|t| t.tag).collect())
+}
+
+/// GET /api/file/tag/list - Tags of a file, or all tags of the user with counts
+#[utoipa::path(
+    get,
+    path = "/api/file/tag/list",
+    tag = "file",
+    params(TagListQuery),
+    responses((status = 200, body = ApiResponse<Vec<TagCount>>)),
+)]
+pub async fn list_tags(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<TagListQuery>,
+) -> Json<ApiResponse<Vec<TagCount>>> {
+    let select = file_tag::Entity::find().filter(file_tag::Column::Username.eq(&current_user.username));
+    let select = match query.path.as_deref() {
+        Some(path) => select.filter(file_tag::Column::Path.eq(normalize(path))),
+        None => select,
+    };
+    match select.order_by_asc(file_tag::Column::Id).all(&*db).await {
+        Ok(rows) => {
+            // Order of adding for a file, alphabetical for the whole tree
+            let counts = if query.path.is_some() {
+                rows.into_iter().map(|t| TagCount { tag: t.tag, count: 1 }).collect()
+            } else {
+                let mut by_tag: BTreeMap<String, u64> = BTreeMap::new();
+                for row in rows {
+                    *by_tag.entry(row.tag).or_default() += 1;
+                }
+                by_tag.into_iter().map(|(tag, count)| TagCount { tag, count }).collect()
+            };
+            Json(ApiResponse::success(counts))
+        }
+        Err(e) => {
+            tracing::error!("Failed to list tags: {}", e);
+            Json(ApiResponse::error(500, "查询标签失败"))
+        }
+    }
+}
+
+/// GET /api/file/tag/files - Files having a tag
+#[utoipa::path(
+    get,
+    path = "/api/file/tag/files",
+    tag = "file",
+    params(TagFilesQuery),
+    responses((status = 200, body = ApiResponse<Vec<TaggedFile>>)),
+)]
+pub async fn files_by_tag(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<TagFilesQuery>,
+) -> Json<ApiResponse<Vec<TaggedFile>>> {
+    let username = &current_user.username;
+    let result = async {
+        let tagged = file_tag::Entity::find()
+            .filter(file_tag::Column::Username.eq(username))
+            .filter(file_tag::Column::Tag.eq(query.tag.trim()))
+            .order_by_asc(file_tag::Column::Path)
+            .all(&*db)
+            .await?;
+        let mut files = Vec::new();
+        for row in tagged {
+            // Deleted or moved outside of the file API
+            let Some(full) = resolve_in_user_root(&state.config, username, &row.path) else {
+                continue;
+            };
+            let Ok(metadata) = tokio::fs::metadata(&full).await else {
+                continue;
+            };
+            files.push(TaggedFile {
+                tags: file_tags(&db, username, &row.path).await?,
+                path: row.path,
+                is_directory: metadata.is_dir(),
+                size: if metadata.is_dir() { 0 } else { metadata.len() as i64 },
+            });
+        }
+        Ok::<_, DbErr>(files)
+    }
+    .await;
+
+    match result {
+        Ok(files) => Json(ApiResponse::success(files)),
+        Err(e) => {
+            tracing::error!("Failed to query files by tag: {}", e);
+            Json(ApiResponse::error(500, "查询标签失败"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission::perm;
+    use crate::testing::TestEnv;
+
+    #[test]
+    fn test_check_tag() {
+        assert_eq!(check_tag("  重要 "), Some("重要"));
+        assert_eq!(check_tag(" "), None);
+        assert_eq!(check_tag("a\nb"), None);
+        assert!(check_tag(&"标".repeat(64)).is_some());
+        assert!(check_tag(&"标".repeat(65)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tags_follow_moves() {
+        let env = TestEnv::new().await;
+        let root = env.config.root_dir.join("alice");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs/a.txt"), b"a").unwrap();
+        std::fs::write(root.join("docs_old.txt"), b"b").unwrap();
+        let user = env.user("alice", &[perm::FILE]);
+
+        let add = |path: &str, tags: &[&str]| {
+            let req = TagRequest {
+                path: path.to_string(),
+                tags: tags.iter().map(|t| t.to_string()).collect(),
+            };
+            add_tags(State(env.state()), Extension(env.db_conn()), Extension(user.clone()), Json(req))
+        };
+        assert_eq!(add("docs/a.txt", &["work", "work", " 重要"]).await.data, Some(vec!["work".into(), "重要".into()]));
+        assert!(add("/docs", &["work"]).await.code);
+        assert!(add("/docs_old.txt", &["work"]).await.code);
+        assert!(!add("/missing.txt", &["work"]).await.code);
+
+        // The directory and what's below it move, the sibling with the same prefix doesn't
+        std::fs::rename(root.join("docs"), root.join("notes")).unwrap();
+        move_tags(&env.db, "alice", "/docs", "/notes").await.unwrap();
+
+        let query = TagFilesQuery { tag: "work".to_string() };
+        let res = files_by_tag(State(env.state()), Extension(env.db_conn()), Extension(user.clone()), Query(query)).await;
+        let paths: Vec<_> = res.0.data.unwrap().into_iter().map(|f| (f.path, f.tags.len())).collect();
+        assert_eq!(
+            paths,
+            [("/docs_old.txt".to_string(), 1), ("/notes".to_string(), 1), ("/notes/a.txt".to_string(), 2)]
+        );
+
+        // Replacing a tagged file drops its tags
+        move_tags(&env.db, "alice", "/docs_old.txt", "/notes/a.txt").await.unwrap();
+        let query = TagListQuery { path: Some("/notes/a.txt".to_string()) };
+        let res = list_tags(Extension(env.db_conn()), Extension(user.clone()), Query(query)).await;
+        assert_eq!(res.0.data.unwrap().len(), 1);
+
+        env.close().await;
+    }
+}
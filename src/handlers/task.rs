@@ -7,41 +7,144 @@ use axum::{
     response::Json,
     Extension,
 };
-use serde::Deserialize;
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait,
+    QueryFilter, QueryOrder, QuerySelect, Set,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
+use crate::entity::op_log::OpType;
+use crate::entity::task;
+use crate::handlers::abuse;
+use crate::handlers::audit::service::log_admin_operation;
+use crate::metrics;
 use crate::middleware::auth::CurrentUser;
-use crate::routes::ApiResponse;
-use crate::task::{TaskStatus, TASK_MANAGER};
+use crate::middleware::DbConn;
+use crate::routes::{ApiMessage, ApiResponse};
+use crate::state::AppState;
+use crate::task::{ConflictInfo, Limits, TaskChange, TaskInfo, TaskStatus, TaskType, TASK_MANAGER};
+
+/// Error recorded on tasks that were interrupted by a server restart
+const INTERRUPTED_ERROR: &str = "服务重启, 任务中断";
+
+const OP_SUCCESS: &str = "成功";
 
 /// Task ID query
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct TaskIdQuery {
     pub id: Option<String>,
 }
 
 /// GET /api/task/query
 /// Returns task array directly (no ApiResponse wrapper, matching Go behavior)
+///
+/// Running tasks come from the task manager, tasks from before a restart
+/// from the database.
+#[utoipa::path(
+    get,
+    path = "/api/task/query",
+    tag = "task",
+    params(TaskIdQuery),
+    responses((status = 200, description = "The tasks of the user, or the task with `id`", body = Vec<TaskInfo>)),
+)]
 pub async fn get_tasks(
+    Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Query(query): Query<TaskIdQuery>,
 ) -> Json<serde_json::Value> {
     if let Some(id) = query.id {
         // Get specific task
-        match TASK_MANAGER.get_task(current_user.id, &id) {
-            Some(task) => {
-                let info = task.info();
-                Json(serde_json::to_value(info).unwrap_or_default())
+        if let Some(task) = TASK_MANAGER.get_task(current_user.id, &id) {
+            return Json(serde_json::to_value(task.info()).unwrap_or_default());
+        }
+        let stored = task::Entity::find_by_id(id)
+            .filter(task::Column::UserId.eq(current_user.id))
+            .filter(task::Column::Dismissed.eq(false))
+            .one(&*db)
+            .await;
+        match stored {
+            Ok(Some(model)) => Json(serde_json::to_value(task_info(model)).unwrap_or_default()),
+            Ok(None) => Json(serde_json::json!({"error": "Task is not found"})),
+            Err(e) => {
+                tracing::error!("Database error: {}", e);
+                Json(serde_json::json!({"error": "internal error"}))
             }
-            None => Json(serde_json::json!({"error": "Task is not found"})),
         }
     } else {
         // Get all tasks - return array directly
-        let tasks = TASK_MANAGER.get_tasks(current_user.id);
+        let live = TASK_MANAGER.get_tasks(current_user.id);
+        let stored = match task::Entity::find()
+            .filter(task::Column::UserId.eq(current_user.id))
+            .filter(task::Column::Dismissed.eq(false))
+            .order_by_asc(task::Column::CreatedAt)
+            .all(&*db)
+            .await
+        {
+            Ok(stored) => stored,
+            Err(e) => {
+                tracing::error!("Database error: {}", e);
+                Vec::new()
+            }
+        };
+        let tasks = merge_tasks(stored, live);
         Json(serde_json::to_value(tasks).unwrap_or(serde_json::json!([])))
     }
 }
 
+/// Merge stored tasks with the task manager's, preferring the live state
+///
+/// Live tasks that are not stored yet are appended.
+fn merge_tasks(stored: Vec<task::Model>, mut live: Vec<TaskInfo>) -> Vec<TaskInfo> {
+    let mut tasks: Vec<TaskInfo> = stored
+        .into_iter()
+        .map(|model| match live.iter().position(|t| t.id == model.id) {
+            Some(i) => live.remove(i),
+            None => task_info(model),
+        })
+        .collect();
+    tasks.extend(live);
+    tasks
+}
+
+/// Convert a stored task into the task info returned by the API
+fn task_info(model: task::Model) -> TaskInfo {
+    let task_type = TaskType::parse(&model.task_type).unwrap_or(TaskType::Copy);
+    TaskInfo {
+        id: model.id,
+        agent: model.agent,
+        created_at: model.created_at,
+        started_at: model.started_at,
+        updated_at: model.updated_at,
+        status: TaskStatus::parse(&model.status).unwrap_or(TaskStatus::Failed),
+        task_type,
+        user_id: model.user_id,
+        error: model.error,
+        is_copy: task_type == TaskType::Copy,
+        source: model.source,
+        target: model.target,
+        files: serde_json::from_str(&model.files).unwrap_or_default(),
+        conflict_info: ConflictInfo::default(),
+        current_file: String::new(),
+        current_file_size: 0,
+        current_file_copied_size: 0,
+        total_files: model.total_files,
+        copied_files: model.copied_files,
+        total_size: model.total_size,
+        copied_size: model.copied_size,
+        failed_files: model.failed_files.and_then(|f| serde_json::from_str(&f).ok()).unwrap_or_default(),
+    }
+}
+
 /// POST /api/task/cancel
+#[utoipa::path(
+    post,
+    path = "/api/task/cancel",
+    tag = "task",
+    params(TaskIdQuery),
+    responses((status = 200, body = ApiMessage)),
+)]
 pub async fn cancel_task(
     Extension(current_user): Extension<CurrentUser>,
     Query(query): Query<TaskIdQuery>,
@@ -62,6 +165,13 @@ pub async fn cancel_task(
 }
 
 /// POST /api/task/suspend
+#[utoipa::path(
+    post,
+    path = "/api/task/suspend",
+    tag = "task",
+    params(TaskIdQuery),
+    responses((status = 200, body = ApiMessage)),
+)]
 pub async fn suspend_task(
     Extension(current_user): Extension<CurrentUser>,
     Query(query): Query<TaskIdQuery>,
@@ -81,6 +191,13 @@ pub async fn suspend_task(
 }
 
 /// POST /api/task/resume
+#[utoipa::path(
+    post,
+    path = "/api/task/resume",
+    tag = "task",
+    params(TaskIdQuery),
+    responses((status = 200, body = ApiMessage)),
+)]
 pub async fn resume_task(
     Extension(current_user): Extension<CurrentUser>,
     Query(query): Query<TaskIdQuery>,
@@ -100,7 +217,15 @@ pub async fn resume_task(
 }
 
 /// DELETE /api/task/delete
+#[utoipa::path(
+    delete,
+    path = "/api/task/delete",
+    tag = "task",
+    params(TaskIdQuery),
+    responses((status = 200, body = ApiMessage)),
+)]
 pub async fn delete_task(
+    Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Query(query): Query<TaskIdQuery>,
 ) -> Json<ApiResponse<()>> {
@@ -121,6 +246,496 @@ pub async fn delete_task(
                 _ => Json(ApiResponse::error(400, "只能删除已完成、失败或取消的任务")),
             }
         }
-        None => Json(ApiResponse::error(404, "Task is not found")),
+        // Tasks from before a restart are only in the database, and all finished
+        None => match dismiss_task(&db, current_user.id, &id).await {
+            Ok(true) => Json(ApiResponse::success_msg("任务已删除")),
+            Ok(false) => Json(ApiResponse::error(404, "Task is not found")),
+            Err(e) => {
+                tracing::error!("Database error: {}", e);
+                Json(ApiResponse::error(500, "internal error"))
+            }
+        },
+    }
+}
+
+/// Task history query
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TaskHistoryQuery {
+    /// Task type: copy / move
+    #[serde(rename = "type")]
+    pub task_type: Option<String>,
+    /// Task status: completed / failed / cancelled
+    pub status: Option<String>,
+    /// Created after (Unix timestamp, inclusive)
+    pub start: Option<i64>,
+    /// Created before (Unix timestamp, inclusive)
+    pub end: Option<i64>,
+    /// Include tasks of all users (auditors only)
+    #[serde(default)]
+    pub all: bool,
+    #[serde(default = "default_page")]
+    pub page: u64,
+    #[serde(rename = "pageSize", default = "default_page_size")]
+    pub page_size: u64,
+}
+
+fn default_page() -> u64 {
+    1
+}
+
+fn default_page_size() -> u64 {
+    20
+}
+
+/// Aggregate statistics over the matching tasks
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct TaskStats {
+    pub total: u64,
+    pub completed: u64,
+    pub failed: u64,
+    pub cancelled: u64,
+    /// Completed / total, 0 when there are no tasks
+    #[serde(rename = "successRate")]
+    pub success_rate: f64,
+    /// Average throughput of completed tasks in bytes per second
+    #[serde(rename = "avgThroughput")]
+    pub avg_throughput: f64,
+}
+
+/// Task history response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TaskHistoryResponse {
+    pub tasks: Vec<task::Model>,
+    pub total: u64,
+    pub stats: TaskStats,
+}
+
+/// GET /api/task/history
+#[utoipa::path(
+    get,
+    path = "/api/task/history",
+    tag = "task",
+    params(TaskHistoryQuery),
+    responses((status = 200, body = ApiResponse<TaskHistoryResponse>)),
+)]
+pub async fn get_task_history(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<TaskHistoryQuery>,
+) -> Json<ApiResponse<TaskHistoryResponse>> {
+    if query.all && !current_user.can_audit() {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let mut select = task::Entity::find().filter(task::Column::FinishedAt.gt(0));
+    if !query.all {
+        select = select.filter(task::Column::UserId.eq(current_user.id));
+    }
+    if let Some(task_type) = query.task_type.as_deref().filter(|t| !t.is_empty()) {
+        select = select.filter(task::Column::TaskType.eq(task_type));
+    }
+    if let Some(status) = query.status.as_deref().filter(|s| !s.is_empty()) {
+        select = select.filter(task::Column::Status.eq(status));
+    }
+    if let Some(start) = query.start {
+        select = select.filter(task::Column::CreatedAt.gte(start));
+    }
+    if let Some(end) = query.end {
+        select = select.filter(task::Column::CreatedAt.lte(end));
+    }
+
+    let page = query.page.max(1);
+    let page_size = query.page_size.clamp(1, 100);
+    let paginator = select
+        .clone()
+        .order_by_desc(task::Column::FinishedAt)
+        .paginate(&*db, page_size);
+    let total = match paginator.num_items().await {
+        Ok(total) => total,
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+    let tasks = match paginator.fetch_page(page - 1).await {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    let rows: Vec<(String, i64, i64, i64)> = match select
+        .select_only()
+        .columns([
+            task::Column::Status,
+            task::Column::CopiedSize,
+            task::Column::StartedAt,
+            task::Column::FinishedAt,
+        ])
+        .into_tuple()
+        .all(&*db)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    Json(ApiResponse::success(TaskHistoryResponse {
+        tasks,
+        total,
+        stats: compute_stats(&rows),
+    }))
+}
+
+/// Compute statistics from (status, copied size, started at, finished at) rows
+fn compute_stats(rows: &[(String, i64, i64, i64)]) -> TaskStats {
+    let mut stats = TaskStats {
+        total: rows.len() as u64,
+        ..Default::default()
+    };
+    let mut bytes: i64 = 0;
+    let mut secs: i64 = 0;
+
+    for (status, copied_size, started_at, finished_at) in rows {
+        match status.as_str() {
+            "completed" => {
+                stats.completed += 1;
+                if *started_at > 0 {
+                    bytes += copied_size;
+                    // Sub-second tasks count as one second
+                    secs += (finished_at - started_at).max(1);
+                }
+            }
+            "failed" => stats.failed += 1,
+            "cancelled" => stats.cancelled += 1,
+            _ => {}
+        }
+    }
+
+    if stats.total > 0 {
+        stats.success_rate = stats.completed as f64 / stats.total as f64;
+    }
+    if secs > 0 {
+        stats.avg_throughput = bytes as f64 / secs as f64;
+    }
+    stats
+}
+
+/// GET /api/task/limit - Throughput limits of copy and move tasks
+#[utoipa::path(
+    get,
+    path = "/api/task/limit",
+    tag = "task",
+    responses((status = 200, body = ApiResponse<Limits>)),
+)]
+pub async fn get_limit() -> Json<ApiResponse<Limits>> {
+    Json(ApiResponse::success(TASK_MANAGER.throttle().limits()))
+}
+
+/// POST /api/task/limit - Change the throughput limits until the next restart
+#[utoipa::path(
+    post,
+    path = "/api/task/limit",
+    tag = "task",
+    request_body = Limits,
+    responses((status = 200, body = ApiResponse<Limits>)),
+)]
+pub async fn set_limit(
+    Extension(current_user): Extension<CurrentUser>,
+    Json(limits): Json<Limits>,
+) -> Json<ApiResponse<Limits>> {
+    if !current_user.can_audit() {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    TASK_MANAGER.throttle().set_limits(limits);
+    let desc = format!("per task {} B/s, global {} B/s", limits.per_task, limits.global);
+    log_admin_operation(&current_user.username, OpType::SetTaskLimit, &desc, OP_SUCCESS, None);
+    Json(ApiResponse::success(limits))
+}
+
+/// Save the state of a task
+///
+/// A finished task keeps its first terminal state, so a late transition
+/// (e.g. a failure racing a cancel) can't overwrite it.
+async fn save_task(db: &DatabaseConnection, info: &TaskInfo) -> Result<(), DbErr> {
+    let finished_at = if info.status.is_finished() { info.updated_at } else { 0 };
+    let model = task::ActiveModel {
+        id: Set(info.id.clone()),
+        user_id: Set(info.user_id),
+        agent: Set(info.agent.clone()),
+        task_type: Set(info.task_type.as_str().to_string()),
+        status: Set(info.status.as_str().to_string()),
+        source: Set(info.source.clone()),
+        target: Set(info.target.clone()),
+        files: Set(serde_json::to_string(&info.files).unwrap_or_default()),
+        total_files: Set(info.total_files),
+        copied_files: Set(info.copied_files),
+        total_size: Set(info.total_size),
+        copied_size: Set(info.copied_size),
+        error: Set(info.error.clone()),
+        failed_files: Set((!info.failed_files.is_empty()).then(|| serde_json::to_string(&info.failed_files).unwrap_or_default())),
+        created_at: Set(info.created_at),
+        started_at: Set(info.started_at),
+        updated_at: Set(info.updated_at),
+        finished_at: Set(finished_at),
+        dismissed: Set(false),
+    };
+
+    let mut update = model.clone();
+    update.id = sea_orm::NotSet;
+    update.dismissed = sea_orm::NotSet;
+    let updated = task::Entity::update_many()
+        .set(update)
+        .filter(task::Column::Id.eq(info.id.as_str()))
+        .filter(task::Column::FinishedAt.eq(0))
+        .exec(db)
+        .await?;
+    if updated.rows_affected > 0 {
+        return Ok(());
+    }
+
+    match task::Entity::insert(model)
+        .on_conflict(OnConflict::column(task::Column::Id).do_nothing().to_owned())
+        .exec(db)
+        .await
+    {
+        Ok(_) | Err(DbErr::RecordNotInserted) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Remove a finished task from the user's task list, keeping it in the history
+///
+/// Returns false if there is no such task.
+async fn dismiss_task(db: &DatabaseConnection, user_id: i64, id: &str) -> Result<bool, DbErr> {
+    let result = task::Entity::update_many()
+        .col_expr(task::Column::Dismissed, sea_orm::sea_query::Expr::value(true))
+        .filter(task::Column::Id.eq(id))
+        .filter(task::Column::UserId.eq(user_id))
+        .filter(task::Column::FinishedAt.gt(0))
+        .filter(task::Column::Dismissed.eq(false))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+/// Mark tasks left unfinished by a previous run as failed
+///
+/// Their workers died with the old process, so they can never finish.
+async fn fail_interrupted(db: &DatabaseConnection) -> Result<u64, DbErr> {
+    let now = chrono::Utc::now().timestamp();
+    let result = task::Entity::update_many()
+        .col_expr(task::Column::Status, sea_orm::sea_query::Expr::value(TaskStatus::Failed.as_str()))
+        .col_expr(task::Column::Error, sea_orm::sea_query::Expr::value(INTERRUPTED_ERROR))
+        .col_expr(task::Column::UpdatedAt, sea_orm::sea_query::Expr::value(now))
+        .col_expr(task::Column::FinishedAt, sea_orm::sea_query::Expr::value(now))
+        .filter(task::Column::FinishedAt.eq(0))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected)
+}
+
+/// Start persisting task changes
+///
+/// Tasks interrupted by the previous shutdown are marked failed first, before
+/// any task of this run is saved.
+pub fn start(state: AppState) {
+    let mut rx = TASK_MANAGER.subscribe_changes();
+
+    tokio::spawn(async move {
+        if let Some(db) = state.get_db().await {
+            match fail_interrupted(&db).await {
+                Ok(0) => {}
+                Ok(n) => tracing::warn!("Marked {} tasks interrupted by restart as failed", n),
+                Err(e) => tracing::error!("Failed to mark interrupted tasks: {}", e),
+            }
+        }
+
+        loop {
+            let change = match rx.recv().await {
+                Ok(change) => change,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Task persistence lagged, {} task changes not saved", n);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+            if let TaskChange::Updated(info) = &change {
+                if info.status.is_finished() {
+                    metrics::count_task_finished(info.task_type, info.status);
+                }
+            }
+            let Some(db) = state.get_db().await else {
+                continue;
+            };
+            let result = match &change {
+                TaskChange::Updated(info) => save_task(&db, info).await,
+                TaskChange::Removed { user_id, id } => dismiss_task(&db, *user_id, id).await.map(|_| ()),
+            };
+            if let Err(e) = result {
+                tracing::error!("Failed to save task: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    #[test]
+    fn test_compute_stats() {
+        let rows = vec![
+            ("completed".to_string(), 1000, 10, 20),
+            ("completed".to_string(), 500, 30, 30),
+            ("failed".to_string(), 0, 40, 41),
+            ("cancelled".to_string(), 100, 50, 60),
+        ];
+        let stats = compute_stats(&rows);
+        assert_eq!(stats.total, 4);
+        assert_eq!(stats.completed, 2);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.cancelled, 1);
+        assert_eq!(stats.success_rate, 0.5);
+        assert_eq!(stats.avg_throughput, 1500.0 / 11.0);
+
+        assert_eq!(compute_stats(&[]).success_rate, 0.0);
+    }
+
+    #[test]
+    fn test_merge_tasks() {
+        let mut stored = TaskInfo::new(1, "web", TaskType::Copy);
+        stored.status = TaskStatus::Completed;
+        let mut running = TaskInfo::new(1, "web", TaskType::Move);
+        running.status = TaskStatus::Running;
+        let new = TaskInfo::new(1, "web", TaskType::Copy);
+
+        let model = |info: &TaskInfo| task::Model {
+            id: info.id.clone(),
+            user_id: info.user_id,
+            agent: info.agent.clone(),
+            task_type: info.task_type.as_str().to_string(),
+            status: TaskStatus::Pending.as_str().to_string(),
+            source: "/a".to_string(),
+            target: "/b".to_string(),
+            files: r#"["x.txt"]"#.to_string(),
+            total_files: 1,
+            copied_files: 0,
+            total_size: 0,
+            copied_size: 0,
+            error: None,
+            failed_files: None,
+            created_at: info.created_at,
+            started_at: 0,
+            updated_at: info.updated_at,
+            finished_at: 0,
+            dismissed: false,
+        };
+        let mut stored_model = model(&stored);
+        stored_model.status = "completed".to_string();
+
+        let tasks = merge_tasks(
+            vec![stored_model, model(&running)],
+            vec![new.clone(), running.clone()],
+        );
+        let ids: Vec<_> = tasks.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, [stored.id.as_str(), running.id.as_str(), new.id.as_str()]);
+        assert_eq!(tasks[0].status, TaskStatus::Completed);
+        assert_eq!(tasks[0].files, ["x.txt"]);
+        // The live state wins over the stored one
+        assert_eq!(tasks[1].status, TaskStatus::Running);
+        assert!(!tasks[1].is_copy);
+    }
+
+    #[tokio::test]
+    async fn test_limit() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        app.add_user("bob", "bob-password", "user").await;
+        let bob = app.login("bob", "bob-password").await;
+
+        // High enough not to slow down the tasks of other tests
+        let limits = serde_json::json!({ "perTask": 1u64 << 40, "global": 0 });
+        let res: serde_json::Value = bob.post_json("/api/task/limit", &limits).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        let res: serde_json::Value = admin.post_json("/api/task/limit", &limits).await.json().await.unwrap();
+        assert_eq!(res["code"], true);
+        let res: serde_json::Value = bob.get("/api/task/limit").await.json().await.unwrap();
+        assert_eq!(res["data"], limits);
+
+        TASK_MANAGER.throttle().set_limits(Limits { per_task: 0, global: 0 });
+        app.close().await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failed_files() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        for name in ["docs", "backup"] {
+            let body = serde_json::json!({ "parentPath": "/", "name": name });
+            assert!(admin.post_json("/api/file/mkdir", &body).await.status().is_success());
+        }
+        assert!(admin.upload("/docs", "a.txt", b"hello").await.status().is_success());
+        let root = crate::handlers::file::get_user_path(&app.env.config, "admin");
+        std::os::unix::fs::symlink(root.join("missing"), root.join("docs/broken")).unwrap();
+
+        // The file that can't be read is reported, the others are copied
+        let mut ws = admin.ws().await;
+        let body = serde_json::json!({ "isCopy": true, "source": "/", "target": "/backup", "files": ["docs"] });
+        assert!(admin.post_json("/api/file/copy", &body).await.status().is_success());
+        let done = ws.wait_for(|m| m["data"]["target"] == "/backup" && m["data"]["status"] == "failed").await;
+        assert_eq!(done["data"]["failedFiles"][0]["path"], "/docs/broken");
+        assert_eq!(done["data"]["error"], "1 files failed");
+        assert_eq!(std::fs::read_to_string(root.join("backup/docs/a.txt")).unwrap(), "hello");
+
+        let tasks: serde_json::Value = admin.get("/api/task/query").await.json().await.unwrap();
+        assert!(tasks.to_string().contains("/docs/broken"));
+        app.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_copy_many_files() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let root = crate::handlers::file::get_user_path(&app.env.config, "admin");
+        for dir in ["tree/a", "tree/b/c"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for i in 0..300 {
+            let dir = ["tree", "tree/a", "tree/b/c"][i % 3];
+            std::fs::write(root.join(dir).join(format!("{}.txt", i)), i.to_string()).unwrap();
+        }
+        // Larger than the small files copied side by side
+        std::fs::write(root.join("tree/big.bin"), vec![7u8; 2 * 1024 * 1024]).unwrap();
+
+        let mut ws = admin.ws().await;
+        let body = serde_json::json!({ "isCopy": true, "source": "/", "target": "/", "files": ["tree"] });
+        assert!(admin.post_json("/api/file/copy", &body).await.status().is_success());
+        let done = ws.wait_for(|m| m["data"]["files"][0] == "tree" && m["data"]["status"] == "completed").await;
+        assert_eq!(done["data"]["copiedFiles"], 301);
+
+        // Copied next to the original under a new name
+        let copy = std::fs::read_dir(&root)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.file_name().unwrap() != "tree" && p.file_name().unwrap().to_string_lossy().starts_with("tree"))
+            .unwrap();
+        for i in 0..300 {
+            let dir = ["", "a", "b/c"][i % 3];
+            let content = std::fs::read_to_string(copy.join(dir).join(format!("{}.txt", i))).unwrap();
+            assert_eq!(content, i.to_string());
+        }
+        assert_eq!(std::fs::metadata(copy.join("big.bin")).unwrap().len(), 2 * 1024 * 1024);
+        app.close().await;
     }
 }
@@ -0,0 +1,101 @@
+//! Templates of new files
+//!
+//! `/api/file/touch` starts a document in the web UI rather than through an
+//! upload. A new file is empty, or made from one of the templates below,
+//! named by the extension of the file it makes:
+//! - `docx` and `xlsx`, an empty Word document or Excel workbook; an empty
+//!   file of these types can't be opened by the document server
+//! - `md`, a Markdown document headed by the name of the file
+//! - `txt`, an empty text file
+//!
+//! Office templates are built here as the smallest packages Word, Excel and
+//! LibreOffice open, rather than shipped as files.
+
+use std::io::Write;
+use zip::write::SimpleFileOptions;
+
+/// Names of the templates, the extensions of the files they make
+pub const NAMES: [&str; 4] = ["docx", "xlsx", "md", "txt"];
+
+const CONTENT_TYPES_DOCX: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/></Types>"#;
+
+const RELS_DOCX: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/></Relationships>"#;
+
+const DOCUMENT: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body><w:p/><w:sectPr><w:pgSz w:w="11906" w:h="16838"/><w:pgMar w:top="1440" w:right="1800" w:bottom="1440" w:left="1800" w:header="851" w:footer="992" w:gutter="0"/></w:sectPr></w:body></w:document>"#;
+
+const CONTENT_TYPES_XLSX: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/></Types>"#;
+
+const RELS_XLSX: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+
+const WORKBOOK: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="Sheet1" sheetId="1" r:id="rId1"/></sheets></workbook>"#;
+
+const WORKBOOK_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#;
+
+const SHEET: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData/></worksheet>"#;
+
+/// Zip package of `parts`, by name
+fn package(parts: &[(&str, &str)]) -> zip::result::ZipResult<Vec<u8>> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, content) in parts {
+        zip.start_file(*name, SimpleFileOptions::default())?;
+        zip.write_all(content.as_bytes())?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Content of a new file named `name` made from `template`, None if there is
+/// no such template
+pub fn render(template: &str, name: &str) -> Option<Vec<u8>> {
+    let content = match template.to_ascii_lowercase().as_str() {
+        "docx" => package(&[
+            ("[Content_Types].xml", CONTENT_TYPES_DOCX),
+            ("_rels/.rels", RELS_DOCX),
+            ("word/document.xml", DOCUMENT),
+        ]),
+        "xlsx" => package(&[
+            ("[Content_Types].xml", CONTENT_TYPES_XLSX),
+            ("_rels/.rels", RELS_XLSX),
+            ("xl/workbook.xml", WORKBOOK),
+            ("xl/_rels/workbook.xml.rels", WORKBOOK_RELS),
+            ("xl/worksheets/sheet1.xml", SHEET),
+        ]),
+        "md" => {
+            let title = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+            Ok(format!("# {}\n", title).into_bytes())
+        }
+        "txt" => Ok(Vec::new()),
+        _ => return None,
+    };
+    // Writing to memory only fails on a bug
+    Some(content.expect("template package"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mime;
+    use std::io::Read;
+
+    #[test]
+    fn test_render() {
+        for template in ["docx", "xlsx"] {
+            let content = render(template, "a").unwrap();
+            assert!(mime::signature_matches(template, &content));
+            let mut archive = zip::ZipArchive::new(std::io::Cursor::new(content)).unwrap();
+            let mut types = String::new();
+            archive.by_name("[Content_Types].xml").unwrap().read_to_string(&mut types).unwrap();
+            assert!(types.contains(if template == "docx" { "wordprocessingml" } else { "spreadsheetml" }));
+        }
+        assert_eq!(render("md", "周报.md").unwrap(), "# 周报\n".as_bytes());
+        assert_eq!(render("TXT", "a.txt").unwrap(), b"");
+        assert!(render("pptx", "a.pptx").is_none());
+    }
+}
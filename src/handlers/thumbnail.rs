@@ -0,0 +1,408 @@
+//! Thumbnail handlers
+//!
+//! Generates resized thumbnails of images for the file browser and caches
+//! them on disk, keyed by the file path and modification time so an edited
+//! image gets a fresh thumbnail. Images are decoded on the task manager's
+//! job pool; when its queue is full the request is answered with 503 and the
+//! browser asks again. HEIC photos are thumbnailed from their JPEG
+//! conversion ([`heic`]) and videos from a poster frame
+//! ([`media::poster`]), made on the same worker as the thumbnail.
+//! Thumbnails of images in sensitive folders are watermarked for the viewer
+//! and never cached.
+
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use image::ImageFormat;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::fs;
+
+use crate::config::Config;
+use crate::error::error_response;
+use crate::handlers::file::resolve_in_user_root;
+use crate::handlers::heic;
+use crate::handlers::media;
+use crate::handlers::sensitive;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::mime;
+use crate::state::AppState;
+use crate::task::{JobError, TASK_MANAGER};
+use crate::watermark::Watermark;
+
+/// Default thumbnail edge length in pixels
+const DEFAULT_SIZE: u32 = 256;
+/// Largest thumbnail edge length in pixels
+const MAX_SIZE: u32 = 1024;
+/// Images larger than this are not thumbnailed
+const MAX_SOURCE_SIZE: u64 = 64 * 1024 * 1024;
+/// Extensions of the images decoded for thumbnails
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "gif", "bmp", "webp"];
+/// Longest an external converter may take
+const CONVERT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Thumbnail query
+#[derive(Debug, Deserialize)]
+pub struct ThumbnailQuery {
+    pub path: String,
+    pub size: Option<u32>,
+    /// Output format: jpeg (default), png or webp
+    pub format: Option<String>,
+}
+
+/// Thumbnail output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Jpeg,
+    Png,
+    WebP,
+}
+
+impl OutputFormat {
+    fn parse(value: Option<&str>) -> Option<Self> {
+        match value.unwrap_or("jpeg").to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            "webp" => Some(Self::WebP),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::WebP => "webp",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::WebP => "image/webp",
+        }
+    }
+}
+
+/// GET /api/file/thumbnail?path=&size=&format=
+pub async fn get_thumbnail(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<ThumbnailQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(file_path) = resolve_in_user_root(&state.config, &current_user.username, &query.path) else {
+        return error_response(StatusCode::BAD_REQUEST, "invalid path");
+    };
+    let Some(format) = OutputFormat::parse(query.format.as_deref()) else {
+        return error_response(StatusCode::BAD_REQUEST, "invalid format");
+    };
+    let size = query.size.unwrap_or(DEFAULT_SIZE).clamp(16, MAX_SIZE);
+
+    let metadata = match fs::metadata(&file_path).await {
+        Ok(m) if m.is_file() => m,
+        _ => return error_response(StatusCode::NOT_FOUND, "file not found"),
+    };
+    // ffmpeg only reads the beginning of a video
+    let ext = mime::extension(&query.path);
+    if metadata.len() > MAX_SOURCE_SIZE && !media::has_poster(&state.config.media, &ext) {
+        return error_response(StatusCode::PAYLOAD_TOO_LARGE, "image too large");
+    }
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let sensitive = match sensitive::is_sensitive(&db, &current_user.username, &query.path).await {
+        Ok(sensitive) => sensitive,
+        Err(e) => {
+            tracing::error!("Failed to look up sensitive folders: {}", e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "internal error");
+        }
+    };
+    if sensitive {
+        let mark = Watermark::new(&current_user.username, chrono::Local::now());
+        return match thumbnail(&state.config, &current_user.username, &file_path, size, format, Some(mark)).await {
+            Ok(data) => Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, format.content_type())
+                .header(header::CACHE_CONTROL, "private, no-store")
+                .body(Body::from(data))
+                .unwrap(),
+            Err(response) => *response,
+        };
+    }
+
+    let key = cache_key(&current_user.username, &query.path, mtime, size, format);
+    let etag = format!("\"{}\"", key);
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v == etag)
+    {
+        return StatusCode::NOT_MODIFIED.into_response();
+    }
+
+    let cache_path = state
+        .config
+        .thumbnail_dir()
+        .join(&key[..2])
+        .join(format!("{}.{}", key, format.extension()));
+
+    let data = match fs::read(&cache_path).await {
+        Ok(data) => data,
+        Err(_) => {
+            let data = match thumbnail(&state.config, &current_user.username, &file_path, size, format, None).await {
+                Ok(data) => data,
+                Err(response) => return *response,
+            };
+            if let Err(e) = write_cache(&cache_path, &data).await {
+                tracing::warn!("Failed to cache thumbnail: {}", e);
+            }
+            data
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(header::CACHE_CONTROL, "private, max-age=86400")
+        .header(header::ETAG, etag)
+        .body(Body::from(data))
+        .unwrap()
+}
+
+/// Whether files with the extension `ext` have a thumbnail: images, HEIC
+/// photos converted by `[media] heif_convert` and videos with ffmpeg
+pub fn has_thumbnail(config: &Config, ext: &str) -> bool {
+    IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())
+        || heic::converts(&config.media, ext)
+        || media::has_poster(&config.media, ext)
+}
+
+/// Thumbnail of the file at `file_path` made on the job pool for
+/// `username`, or the response telling why there is none
+///
+/// The worker is taken before the image the thumbnail is made from is
+/// converted or extracted, so those are bounded by the pool as well.
+async fn thumbnail(
+    config: &Config,
+    username: &str,
+    file_path: &Path,
+    size: u32,
+    format: OutputFormat,
+    mark: Option<Watermark>,
+) -> Result<Vec<u8>, Box<Response>> {
+    let result = async {
+        let worker = TASK_MANAGER.jobs().worker(username).await?;
+        match source(config, file_path).await {
+            Ok(source) => worker.run(move || generate(&source, size, format, mark.as_ref())).await,
+            Err(e) => Ok(Err(e)),
+        }
+    }
+    .await;
+    generated(file_path, result)
+}
+
+/// The image a thumbnail of `file_path` is made from: the file itself, the
+/// JPEG conversion of a HEIC photo or the poster frame of a video
+async fn source(config: &Config, file_path: &Path) -> anyhow::Result<PathBuf> {
+    let ext = file_path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let derived = if heic::converts(&config.media, ext) {
+        heic::converted(config, file_path).await
+    } else if media::has_poster(&config.media, ext) {
+        media::poster(config, file_path).await
+    } else {
+        return Ok(file_path.to_path_buf());
+    };
+    derived.inspect_err(|e| tracing::warn!("Failed to make an image of {:?}: {}", file_path, e))
+}
+
+/// Thumbnail of the image at `path` generated on the job pool, or the
+/// response telling why there is none
+fn generated(path: &Path, result: Result<anyhow::Result<Vec<u8>>, JobError>) -> Result<Vec<u8>, Box<Response>> {
+    match result {
+        Ok(Ok(data)) => Ok(data),
+        Ok(Err(e)) => {
+            tracing::debug!("Failed to generate thumbnail for {:?}: {}", path, e);
+            Err(Box::new(error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported image")))
+        }
+        Err(JobError::Busy) => Err(Box::new(busy_response())),
+        Err(JobError::Failed(e)) => {
+            tracing::error!("Thumbnail worker failed: {}", e);
+            Err(Box::new(error_response(StatusCode::INTERNAL_SERVER_ERROR, "internal error")))
+        }
+    }
+}
+
+/// Response to a job the pool has no room for, asking the client to come
+/// back shortly
+pub fn busy_response() -> Response {
+    let mut response = error_response(StatusCode::SERVICE_UNAVAILABLE, "busy");
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, header::HeaderValue::from_static("2"));
+    response
+}
+
+/// Cache key of a thumbnail, unique per user, path, modification time, size and format
+fn cache_key(username: &str, path: &str, mtime: u64, size: u32, format: OutputFormat) -> String {
+    let input = format!(
+        "{}\0/{}\0{}\0{}\0{}",
+        username,
+        path.trim_start_matches('/'),
+        mtime,
+        size,
+        format.extension()
+    );
+    hex::encode(Sha256::digest(input.as_bytes()))
+}
+
+/// Path of the image derived from the file at `src` by `make`, such as its
+/// JPEG conversion or a video frame, cached under `kind` with the thumbnails
+/// by the file's path, modification time and size
+///
+/// `make` writes the image to the path it is given, made now if it isn't
+/// cached yet. The path ends in `.jpg`, for converters picking the format by
+/// the extension.
+pub async fn derived<F, Fut>(config: &Config, kind: &str, src: &Path, make: F) -> anyhow::Result<PathBuf>
+where
+    F: FnOnce(PathBuf) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    derived_as(config, kind, "jpg", src, make).await
+}
+
+/// Like [`derived`], for a file of another format, whose paths end in `.ext`
+pub async fn derived_as<F, Fut>(config: &Config, kind: &str, ext: &str, src: &Path, make: F) -> anyhow::Result<PathBuf>
+where
+    F: FnOnce(PathBuf) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let metadata = fs::metadata(src).await?;
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let input = format!("{}\0{}\0{}", src.display(), mtime, metadata.len());
+    let key = hex::encode(Sha256::digest(input.as_bytes()));
+    let dir = config.thumbnail_dir().join(kind).join(&key[..2]);
+    let path = dir.join(format!("{}.{}", key, ext));
+    if fs::try_exists(&path).await? {
+        return Ok(path);
+    }
+
+    fs::create_dir_all(&dir).await?;
+    let tmp = dir.join(format!("{}.{}.tmp.{}", key, uuid::Uuid::new_v4(), ext));
+    let result = match make(tmp.clone()).await {
+        Ok(()) => fs::rename(&tmp, &path).await.map_err(Into::into),
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp).await;
+    }
+    result.map(|_| path)
+}
+
+/// Run an external converter writing `output`, killed after [`CONVERT_TIMEOUT`]
+pub async fn run_converter(mut command: tokio::process::Command, output: &Path) -> anyhow::Result<()> {
+    let program = command.as_std().get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    let status = tokio::time::timeout(CONVERT_TIMEOUT, child.wait())
+        .await
+        .map_err(|_| anyhow::anyhow!("{} timed out", program))??;
+    if !status.success() {
+        anyhow::bail!("{} exited with {}", program, status);
+    }
+    if !fs::try_exists(output).await? {
+        anyhow::bail!("{} wrote nothing", program);
+    }
+    Ok(())
+}
+
+/// Write a cache entry atomically so concurrent readers never see partial data
+async fn write_cache(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let tmp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    fs::write(&tmp, data).await?;
+    fs::rename(&tmp, path).await
+}
+
+/// Decode an image and encode a thumbnail that fits in `size` x `size`,
+/// with `mark` drawn over it
+fn generate(path: &Path, size: u32, format: OutputFormat, mark: Option<&Watermark>) -> anyhow::Result<Vec<u8>> {
+    let img = image::ImageReader::open(path)?.with_guessed_format()?.decode()?;
+    let mut thumb = img.thumbnail(size, size);
+    if let Some(mark) = mark {
+        let mut marked = thumb.to_rgba8();
+        mark.draw(&mut marked);
+        thumb = marked.into();
+    }
+
+    let mut out = Cursor::new(Vec::new());
+    match format {
+        OutputFormat::Jpeg => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, 85);
+            thumb.to_rgb8().write_with_encoder(encoder)?;
+        }
+        OutputFormat::Png => thumb.write_to(&mut out, ImageFormat::Png)?,
+        OutputFormat::WebP => thumb.to_rgba8().write_to(&mut out, ImageFormat::WebP)?,
+    }
+    Ok(out.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_key() {
+        let a = cache_key("alice", "/photos/a.jpg", 100, 256, OutputFormat::Jpeg);
+        assert_eq!(a, cache_key("alice", "photos/a.jpg", 100, 256, OutputFormat::Jpeg));
+        assert_ne!(a, cache_key("alice", "/photos/a.jpg", 101, 256, OutputFormat::Jpeg));
+        assert_ne!(a, cache_key("alice", "/photos/a.jpg", 100, 128, OutputFormat::Jpeg));
+        assert_ne!(a, cache_key("bob", "/photos/a.jpg", 100, 256, OutputFormat::Jpeg));
+        assert_ne!(a, cache_key("alice", "/photos/a.jpg", 100, 256, OutputFormat::Png));
+    }
+
+    #[test]
+    fn test_generate() {
+        let dir = std::env::temp_dir().join(format!("thumb-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("src.png");
+        image::RgbaImage::from_pixel(400, 200, image::Rgba([255, 0, 0, 255]))
+            .save(&src)
+            .unwrap();
+
+        for format in [OutputFormat::Jpeg, OutputFormat::Png, OutputFormat::WebP] {
+            let data = generate(&src, 100, format, None).unwrap();
+            let thumb = image::load_from_memory(&data).unwrap();
+            assert_eq!((thumb.width(), thumb.height()), (100, 50));
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,385 @@
+//! Storage tiering
+//!
+//! Files nobody has read or changed for `after_days` move to the cold
+//! storage directory of `[tiering]`. An empty stub keeps their place in the
+//! tree and a `disk_cold_file` row remembers where the content went, so
+//! listings still show them, with their size and `tier: "cold"`.
+//!
+//! Reading a stub recalls the file first: downloads, previews, copies and
+//! WebDAV reads call [`recall`], and so does moving a file to the trash.
+//! Renames and moves take the rows along like tags, replacing a stub drops
+//! its cold copy.
+//!
+//! [Compressed files](crate::handlers::compression) are kept the same way,
+//! their rows have the size of the compressed copy.
+//!
+//! The last read is the access time of the file, so the data root must not
+//! be mounted with `noatime`.
+
+use sea_orm::{ActiveModelTrait, DatabaseConnection, DbErr, EntityTrait, Set};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::entity::{cold_file, department, group, user};
+use crate::handlers::path::{move_rows, normalize, rows_under, PathRows};
+use crate::handlers::{dept_space, group_space};
+use crate::handlers::file::get_user_path;
+use crate::handlers::{compression, dir_version, quota};
+use crate::state::AppState;
+
+const DAY_SECS: i64 = 86400;
+
+/// Tier of the files in cold storage
+pub const COLD: &str = "cold";
+
+/// Files move out and back one at a time, so a recall never races the job
+pub(crate) static LOCK: LazyLock<tokio::sync::Mutex<()>> = LazyLock::new(|| tokio::sync::Mutex::new(()));
+
+pub(crate) fn timestamp(time: std::io::Result<SystemTime>) -> i64 {
+    time.ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Replace a file with an empty stub keeping its modification time
+///
+/// The stub is a new file, so [hard links](crate::handlers::dedup) of the
+/// file keep their content.
+pub(crate) fn leave_stub(full: &Path, modified: std::io::Result<SystemTime>) -> std::io::Result<()> {
+    let tmp = full.with_file_name(format!(".{}.stub", uuid::Uuid::new_v4()));
+    let file = std::fs::File::create(&tmp)?;
+    let stubbed = match modified {
+        Ok(modified) => file.set_modified(modified),
+        Err(_) => Ok(()),
+    }
+    .and_then(|_| std::fs::rename(&tmp, full));
+    if stubbed.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    stubbed
+}
+
+impl PathRows for cold_file::Entity {
+    const USERNAME: cold_file::Column = cold_file::Column::Username;
+    const PATH: cold_file::Column = cold_file::Column::Path;
+
+    fn path(row: &cold_file::Model) -> &str {
+        &row.path
+    }
+}
+
+/// Stubs of the files right in `dir`, by name
+pub async fn stubs_in(
+    db: &DatabaseConnection,
+    username: &str,
+    dir: &str,
+) -> Result<HashMap<String, cold_file::Model>, DbErr> {
+    let dir = normalize(dir);
+    Ok(rows_under::<cold_file::Entity>(db, username, &dir)
+        .await?
+        .into_iter()
+        .filter_map(|stub| {
+            let (parent, name) = stub.path.rsplit_once('/')?;
+            let name = name.to_string();
+            (normalize(parent) == dir).then_some((name, stub))
+        })
+        .collect())
+}
+
+/// Drop the cold copies of `path` and the files below it, after they were replaced
+pub async fn discard(db: &DatabaseConnection, username: &str, path: &str) -> Result<(), DbErr> {
+    for stub in rows_under::<cold_file::Entity>(db, username, &normalize(path)).await? {
+        cold_file::Entity::delete_by_id(stub.id).exec(db).await?;
+        if let Err(e) = tokio::fs::remove_file(&stub.cold_path).await {
+            tracing::warn!("Failed to remove cold copy {}: {}", stub.cold_path, e);
+        }
+    }
+    Ok(())
+}
+
+/// Move the stubs of `old_path` and the files below it along to `new_path`
+pub async fn move_stubs(
+    db: &DatabaseConnection,
+    username: &str,
+    old_path: &str,
+    new_path: &str,
+) -> Result<(), DbErr> {
+    let (old_path, new_path) = (normalize(old_path), normalize(new_path));
+    if old_path == new_path {
+        return Ok(());
+    }
+    // Replaced stubs take their cold copies with them
+    discard(db, username, &new_path).await?;
+    move_rows::<cold_file::Entity>(db, username, &old_path, &new_path).await
+}
+
+/// Bring the files at `path` or below it in the tree at `root` back from
+/// cold storage, the number recalled
+pub async fn recall(
+    db: &DatabaseConnection,
+    username: &str,
+    root: &Path,
+    path: &str,
+) -> anyhow::Result<usize> {
+    let path = normalize(path);
+    if rows_under::<cold_file::Entity>(db, username, &path).await?.is_empty() {
+        return Ok(0);
+    }
+
+    let _guard = LOCK.lock().await;
+    let mut recalled = 0;
+    // Another request may have recalled them meanwhile
+    for stub in rows_under::<cold_file::Entity>(db, username, &path).await? {
+        let full = root.join(stub.path.trim_start_matches('/'));
+        match tokio::fs::metadata(&full).await {
+            Ok(metadata) if metadata.is_file() && metadata.len() == 0 => {
+                let tmp = full.with_file_name(format!(".{}.recall", uuid::Uuid::new_v4()));
+                let restored = async {
+                    match stub.compressed_size {
+                        Some(_) => {
+                            let (source, target) = (stub.cold_path.clone(), tmp.clone());
+                            tokio::task::spawn_blocking(move || compression::decompress(Path::new(&source), &target))
+                                .await??;
+                        }
+                        None => {
+                            tokio::fs::copy(&stub.cold_path, &tmp).await?;
+                        }
+                    }
+                    let modified = UNIX_EPOCH + Duration::from_secs(stub.modify_time.max(0) as u64);
+                    std::fs::File::options().write(true).open(&tmp)?.set_modified(modified)?;
+                    tokio::fs::rename(&tmp, &full).await
+                }
+                .await;
+                if let Err(e) = restored {
+                    let _ = tokio::fs::remove_file(&tmp).await;
+                    return Err(anyhow::anyhow!("failed to recall {}: {}", stub.path, e));
+                }
+                dir_version::bump_entry(username, &stub.path);
+                recalled += 1;
+            }
+            // The stub was deleted or replaced outside the web UI
+            _ => tracing::warn!("Stub {} of {} is gone, dropping its cold copy", stub.path, username),
+        }
+        cold_file::Entity::delete_by_id(stub.id).exec(db).await?;
+        if let Err(e) = tokio::fs::remove_file(&stub.cold_path).await {
+            tracing::warn!("Failed to remove cold copy {}: {}", stub.cold_path, e);
+        }
+    }
+    if recalled > 0 {
+        quota::invalidate(username);
+        tracing::info!("Recalled {} files of {} from cold storage", recalled, username);
+    }
+    Ok(recalled)
+}
+
+/// Move a file to cold storage and leave an empty stub, false if it changed
+/// while it was copied
+async fn freeze(
+    db: &DatabaseConnection,
+    cold_dir: &Path,
+    username: &str,
+    root: &Path,
+    path: &str,
+    now: i64,
+) -> anyhow::Result<bool> {
+    let full = root.join(path.trim_start_matches('/'));
+    let _guard = LOCK.lock().await;
+
+    let before = tokio::fs::metadata(&full).await?;
+    tokio::fs::create_dir_all(cold_dir).await?;
+    let cold_path = cold_dir.join(uuid::Uuid::new_v4().to_string());
+    tokio::fs::copy(&full, &cold_path).await?;
+    let after = tokio::fs::metadata(&full).await?;
+    if after.len() != before.len() || after.modified().ok() != before.modified().ok() {
+        let _ = tokio::fs::remove_file(&cold_path).await;
+        return Ok(false);
+    }
+
+    // Left by a stub replaced outside the web UI
+    discard(db, username, path).await?;
+    cold_file::ActiveModel {
+        username: Set(username.to_string()),
+        path: Set(path.to_string()),
+        cold_path: Set(cold_path.to_string_lossy().to_string()),
+        size: Set(before.len() as i64),
+        modify_time: Set(timestamp(before.modified())),
+        tier_time: Set(now),
+        compressed_size: Set(None),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    leave_stub(&full, before.modified())?;
+    dir_version::bump_entry(username, path);
+    Ok(true)
+}
+
+/// Files below `dir` of at least `min_size` bytes, last read or changed
+/// before `cutoff`, as paths relative to `root`
+pub(crate) fn candidates(root: &Path, dir: &Path, min_size: u64, cutoff: i64, found: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        // Hidden files, and temp files of uploads and recalls
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') || name.ends_with(".uploading") {
+            continue;
+        }
+        // Symlinks are not followed
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let path = entry.path();
+        if metadata.is_dir() {
+            candidates(root, &path, min_size, cutoff, found);
+        } else if metadata.is_file()
+            && metadata.len() >= min_size.max(1)
+            && timestamp(metadata.accessed()).max(timestamp(metadata.modified())) < cutoff
+        {
+            if let Ok(relative) = path.strip_prefix(root) {
+                found.push(normalize(&relative.to_string_lossy()));
+            }
+        }
+    }
+}
+
+/// Owners of the trees to look at: the users, and the department and group
+/// spaces
+pub(crate) async fn owners(config: &Config, db: &DatabaseConnection) -> Result<Vec<String>, DbErr> {
+    let mut owners: Vec<String> = user::Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|u| u.username)
+        .collect();
+    if config.dept_space.enabled {
+        let depts = department::Entity::find().all(db).await?;
+        owners.extend(depts.into_iter().map(|d| dept_space::owner(d.id)));
+    }
+    if config.group_space.enabled {
+        let groups = group::Entity::find().all(db).await?;
+        owners.extend(groups.into_iter().map(|g| group_space::owner(g.id)));
+    }
+    Ok(owners)
+}
+
+/// Move the files nobody read or changed for `after_days` to cold storage,
+/// the number moved
+pub async fn run(config: &Config, db: &DatabaseConnection, now: i64) -> anyhow::Result<usize> {
+    let tiering = &config.tiering;
+    let Some(cold_dir) = tiering.cold_dir.as_deref().filter(|_| tiering.enabled) else {
+        return Ok(0);
+    };
+    let cutoff = now - tiering.after_days as i64 * DAY_SECS;
+    let mut moved = 0;
+    for owner in owners(config, db).await? {
+        let root = get_user_path(config, &owner);
+        let (walk_root, min_size) = (root.clone(), tiering.min_size);
+        let files = tokio::task::spawn_blocking(move || {
+            let mut found = Vec::new();
+            candidates(&walk_root, &walk_root, min_size, cutoff, &mut found);
+            found
+        })
+        .await?;
+
+        let mut owner_moved = 0;
+        for path in files {
+            match freeze(db, cold_dir, &owner, &root, &path, now).await {
+                Ok(true) => owner_moved += 1,
+                Ok(false) => tracing::debug!("Not moving {} of {}: changed while copying", path, owner),
+                Err(e) => tracing::error!("Failed to move {} of {} to cold storage: {}", path, owner, e),
+            }
+        }
+        if owner_moved > 0 {
+            quota::invalidate(&owner);
+            moved += owner_moved;
+        }
+    }
+    Ok(moved)
+}
+
+/// Start the background job that moves files to cold storage
+pub fn start(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            ticker.tick().await;
+            let Some(db) = state.get_db().await else {
+                continue;
+            };
+            match run(&state.config, &db, chrono::Utc::now().timestamp()).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Moved {} files to cold storage", n),
+                Err(e) => tracing::error!("Failed to move files to cold storage: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::FileService;
+    use crate::testing::TestEnv;
+
+    #[tokio::test]
+    async fn test_freeze_and_recall() {
+        let mut env = TestEnv::new().await;
+        env.config.tiering.enabled = true;
+        env.config.tiering.min_size = 4;
+        env.config.tiering.cold_dir = Some(env.dir.join("cold"));
+        user::ActiveModel {
+            username: Set("alice".to_string()),
+            password: Set(String::new()),
+            full_name: Set("alice".to_string()),
+            department_id: Set(0),
+            dept_name: Set(String::new()),
+            status: Set(1),
+            last_login: Set(0),
+            permissions: Set(String::new()),
+            ..Default::default()
+        }
+        .insert(&env.db)
+        .await
+        .unwrap();
+
+        let service = FileService::new(&env.config, &env.db, "alice");
+        service.mkdir("/", None, "docs").await.unwrap();
+        let tmp = env.dir.join("upload.tmp");
+        for (name, data) in [("report.pdf", &b"old report"[..]), ("a.txt", b"x")] {
+            std::fs::write(&tmp, data).unwrap();
+            service.upload_finalize(&tmp, "/docs", None, name, data.len() as i64, None).await.unwrap();
+        }
+        let full = env.config.root_dir.join("alice/docs/report.pdf");
+        let modified = timestamp(std::fs::metadata(&full).unwrap().modified());
+
+        // Nothing is old yet, 200 days on the large file moves
+        let now = chrono::Utc::now().timestamp();
+        assert_eq!(run(&env.config, &env.db, now).await.unwrap(), 0);
+        assert_eq!(run(&env.config, &env.db, now + 200 * DAY_SECS).await.unwrap(), 1);
+        assert_eq!(std::fs::metadata(&full).unwrap().len(), 0);
+        let items = service.list("/docs").await.unwrap();
+        let report = items.iter().find(|i| i.basename == "report.pdf").unwrap();
+        assert_eq!((report.size, report.tier.as_deref()), (10, Some(COLD)));
+        assert!(items.iter().find(|i| i.basename == "a.txt").unwrap().tier.is_none());
+
+        // The stub follows a rename and is recalled when read
+        service.rename("/docs/report.pdf", "2025.pdf").await.unwrap();
+        let root = get_user_path(&env.config, "alice");
+        assert_eq!(recall(&env.db, "alice", &root, "/docs").await.unwrap(), 1);
+        let full = root.join("docs/2025.pdf");
+        assert_eq!(std::fs::read(&full).unwrap(), b"old report");
+        assert_eq!(timestamp(std::fs::metadata(&full).unwrap().modified()), modified);
+        assert!(cold_file::Entity::find().all(&env.db).await.unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(env.dir.join("cold")).unwrap().count(), 0);
+        assert_eq!(recall(&env.db, "alice", &root, "/docs").await.unwrap(), 0);
+
+        env.close().await;
+    }
+}
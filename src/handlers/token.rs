@@ -0,0 +1,238 @@
+//! Personal API token handlers
+//!
+//! Users create tokens for scripts and tools (curl, rclone, sync clients)
+//! that send `Authorization: Bearer <token>` instead of logging in. A token
+//! can be limited to a subset of its owner's permissions; the scopes are the
+//! Casbin permission names and are intersected with the owner's current
+//! permissions on every request.
+
+use axum::{response::Json, Extension};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait,
+    QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::entity::api_token;
+use crate::entity::op_log::OpType;
+use crate::handlers::audit::service::log_operation;
+use crate::middleware::auth::{generate_api_token, hash_api_token, perm, CurrentUser};
+use crate::middleware::DbConn;
+use crate::permission::normalize_permissions;
+use crate::routes::ApiResponse;
+
+const OP_SUCCESS: &str = "成功";
+
+/// Personal tokens a user may hold
+const MAX_TOKENS_PER_USER: u64 = 20;
+
+/// Create token request
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    /// Days until the token expires (0 or missing = never)
+    #[serde(rename = "expireDays", default)]
+    pub expire_days: i64,
+    /// Comma-separated permissions, empty for all of the user's permissions
+    #[serde(default)]
+    pub scopes: String,
+}
+
+/// Revoke token request
+#[derive(Debug, Deserialize)]
+pub struct RevokeTokenRequest {
+    pub id: i64,
+}
+
+/// Token info (never includes the token itself)
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub id: i64,
+    pub name: String,
+    pub scopes: String,
+    #[serde(rename = "createTime")]
+    pub create_time: i64,
+    #[serde(rename = "lastUsedTime")]
+    pub last_used_time: i64,
+    #[serde(rename = "expireTime")]
+    pub expire_time: i64,
+}
+
+impl From<api_token::Model> for TokenResponse {
+    fn from(m: api_token::Model) -> Self {
+        Self {
+            id: m.id,
+            name: m.name,
+            scopes: m.scopes,
+            create_time: m.create_time,
+            last_used_time: m.last_used_time,
+            expire_time: m.expire_time,
+        }
+    }
+}
+
+/// Newly created token, returned exactly once
+#[derive(Debug, Serialize)]
+pub struct CreatedTokenResponse {
+    pub id: i64,
+    pub token: String,
+}
+
+/// Check a token name
+pub fn is_valid_token_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().count() <= 64
+}
+
+/// Generate a token for a user and store its hash
+pub async fn insert_token(
+    db: &DatabaseConnection,
+    username: &str,
+    name: &str,
+    expire_days: i64,
+    scopes: &[String],
+) -> Result<CreatedTokenResponse, DbErr> {
+    let now = chrono::Utc::now().timestamp();
+    let token = generate_api_token();
+    let record = api_token::ActiveModel {
+        username: Set(username.to_string()),
+        name: Set(name.to_string()),
+        token_hash: Set(hash_api_token(&token)),
+        create_time: Set(now),
+        last_used_time: Set(0),
+        expire_time: Set(if expire_days > 0 { now + expire_days * 86400 } else { 0 }),
+        scopes: Set(scopes.join(",")),
+        ..Default::default()
+    };
+    let model = record.insert(db).await?;
+    Ok(CreatedTokenResponse { id: model.id, token })
+}
+
+/// Normalize requested scopes, rejecting any the user doesn't have
+fn check_scopes(user: &CurrentUser, scopes: &str) -> Result<Vec<String>, &'static str> {
+    let valid = scopes
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .all(|s| perm::ALL.contains(&s));
+    if !valid {
+        return Err("无效的令牌权限");
+    }
+    let normalized = normalize_permissions(scopes);
+    if !normalized.iter().all(|p| user.has_permission(p)) {
+        return Err("令牌权限不能超出用户权限");
+    }
+    Ok(normalized)
+}
+
+/// POST /api/token/create - Create a personal token
+pub async fn create_token(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<CreateTokenRequest>,
+) -> Json<ApiResponse<CreatedTokenResponse>> {
+    // A token could otherwise mint unrestricted tokens for itself
+    if current_user.token_id.is_some() {
+        return Json(ApiResponse::error(403, "请登录后创建令牌"));
+    }
+    if !is_valid_token_name(&req.name) {
+        return Json(ApiResponse::error(400, "令牌名称无效"));
+    }
+    let scopes = match check_scopes(&current_user, &req.scopes) {
+        Ok(scopes) => scopes,
+        Err(msg) => return Json(ApiResponse::error(400, msg)),
+    };
+
+    match api_token::Entity::find()
+        .filter(api_token::Column::Username.eq(&current_user.username))
+        .count(&*db)
+        .await
+    {
+        Ok(count) if count >= MAX_TOKENS_PER_USER => {
+            return Json(ApiResponse::error(400, "令牌数量已达上限"));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    }
+
+    match insert_token(&db, &current_user.username, &req.name, req.expire_days, &scopes).await {
+        Ok(created) => {
+            log_operation(&current_user.username, OpType::CreateToken, &req.name, OP_SUCCESS, None);
+            Json(ApiResponse::success(created))
+        }
+        Err(e) => {
+            tracing::error!("Failed to create token: {}", e);
+            Json(ApiResponse::error(500, "internal error"))
+        }
+    }
+}
+
+/// GET /api/token/list - List the current user's tokens
+pub async fn list_tokens(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Json<ApiResponse<Vec<TokenResponse>>> {
+    match api_token::Entity::find()
+        .filter(api_token::Column::Username.eq(&current_user.username))
+        .order_by_desc(api_token::Column::CreateTime)
+        .all(&*db)
+        .await
+    {
+        Ok(tokens) => Json(ApiResponse::success(tokens.into_iter().map(Into::into).collect())),
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            Json(ApiResponse::error(500, "internal error"))
+        }
+    }
+}
+
+/// POST /api/token/revoke - Revoke one of the current user's tokens
+pub async fn revoke_token(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<RevokeTokenRequest>,
+) -> Json<ApiResponse<()>> {
+    let token = match api_token::Entity::find_by_id(req.id)
+        .filter(api_token::Column::Username.eq(&current_user.username))
+        .one(&*db)
+        .await
+    {
+        Ok(Some(token)) => token,
+        Ok(None) => return Json(ApiResponse::error(404, "令牌不存在")),
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    if let Err(e) = api_token::Entity::delete_by_id(token.id).exec(&*db).await {
+        tracing::error!("Failed to revoke token: {}", e);
+        return Json(ApiResponse::error(500, "internal error"));
+    }
+
+    log_operation(&current_user.username, OpType::RevokeToken, &token.name, OP_SUCCESS, None);
+    Json(ApiResponse::success_msg("success"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestEnv;
+
+    #[tokio::test]
+    async fn test_check_scopes() {
+        let env = TestEnv::new().await;
+        let user = env.user("alice", &["file", "contacts"]);
+        assert_eq!(check_scopes(&user, ""), Ok(vec![]));
+        assert_eq!(check_scopes(&user, "file"), Ok(vec!["file".to_string()]));
+        assert_eq!(
+            check_scopes(&user, " file , contacts,file"),
+            Ok(vec!["contacts".to_string(), "file".to_string()])
+        );
+        assert!(check_scopes(&user, "audit").is_err());
+        assert!(check_scopes(&user, "file,bogus").is_err());
+        env.close().await;
+    }
+}
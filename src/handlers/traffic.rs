@@ -0,0 +1,474 @@
+//! Traffic accounting
+//!
+//! Bytes uploaded and downloaded by each user are added up in memory, by
+//! [`add_upload`] when an upload is stored and by the [`Counted`] wrapper
+//! around download bodies as chunks are sent. Every minute the counts are
+//! flushed into `disk_traffic`, one row per user and UTC day, for chargeback
+//! and spotting unusual transfer volumes.
+
+use axum::{
+    body::Body,
+    extract::Query,
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+    Extension,
+};
+use dashmap::DashMap;
+use futures::{Stream, StreamExt};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::entity::traffic;
+use crate::handlers::abuse;
+use crate::handlers::audit::{csv_field, department_usernames};
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+
+/// Days reported when no range is given
+const DEFAULT_DAYS: i64 = 30;
+
+/// Counts not yet flushed, (upload, download) by (username, day)
+static PENDING: std::sync::LazyLock<DashMap<(String, String), (u64, u64)>> =
+    std::sync::LazyLock::new(DashMap::new);
+
+fn today() -> String {
+    chrono::Utc::now().format("%Y-%m-%d").to_string()
+}
+
+fn add(username: &str, upload: u64, download: u64) {
+    let mut counts = PENDING.entry((username.to_string(), today())).or_default();
+    counts.0 += upload;
+    counts.1 += download;
+}
+
+/// Count bytes of a stored upload
+pub fn add_upload(username: &str, bytes: u64) {
+    add(username, bytes, 0);
+}
+
+/// Count bytes sent to a user
+pub fn add_download(username: &str, bytes: u64) {
+    add(username, 0, bytes);
+    abuse::record_download(username, bytes);
+}
+
+/// Download body counting the bytes actually sent
+///
+/// Aborted downloads only count what went out before the client left.
+pub struct Counted<S> {
+    inner: S,
+    username: String,
+}
+
+/// Count the chunks of `stream` as downloads of `username`
+pub fn counted<S>(username: &str, stream: S) -> Counted<S> {
+    Counted {
+        inner: stream,
+        username: username.to_string(),
+    }
+}
+
+impl<S, B, E> Stream for Counted<S>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = ready!(self.inner.poll_next_unpin(cx));
+        if let Some(Ok(chunk)) = &item {
+            add_download(&self.username, chunk.as_ref().len() as u64);
+        }
+        Poll::Ready(item)
+    }
+}
+
+/// Add the pending counts to the traffic table
+pub async fn flush(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let keys: Vec<_> = PENDING.iter().map(|e| e.key().clone()).collect();
+    for key in keys {
+        let Some(((username, day), (upload, download))) = PENDING.remove(&key) else {
+            continue;
+        };
+        if let Err(e) = add_row(db, &username, &day, upload, download).await {
+            // Keep the counts for the next flush
+            let mut counts = PENDING.entry((username, day)).or_default();
+            counts.0 += upload;
+            counts.1 += download;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+async fn add_row(
+    db: &DatabaseConnection,
+    username: &str,
+    day: &str,
+    upload: u64,
+    download: u64,
+) -> Result<(), DbErr> {
+    // Update first: an upsert needs the unique index when the statement is
+    // prepared, which SQLite connections opened before the migration miss
+    let updated = traffic::Entity::update_many()
+        .col_expr(
+            traffic::Column::UploadBytes,
+            Expr::col(traffic::Column::UploadBytes).add(upload as i64),
+        )
+        .col_expr(
+            traffic::Column::DownloadBytes,
+            Expr::col(traffic::Column::DownloadBytes).add(download as i64),
+        )
+        .filter(traffic::Column::Username.eq(username))
+        .filter(traffic::Column::Day.eq(day))
+        .exec(db)
+        .await?;
+    if updated.rows_affected > 0 {
+        return Ok(());
+    }
+
+    // A concurrent flush inserting the same day fails on the unique index,
+    // the counts are then kept for the next flush
+    traffic::ActiveModel {
+        username: Set(username.to_string()),
+        day: Set(day.to_string()),
+        upload_bytes: Set(upload as i64),
+        download_bytes: Set(download as i64),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map(|_| ())
+}
+
+/// Start flushing the counts every minute
+pub fn start(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            ticker.tick().await;
+            let Some(db) = state.get_db().await else {
+                continue;
+            };
+            if let Err(e) = flush(&db).await {
+                tracing::error!("Failed to save traffic counts: {}", e);
+            }
+        }
+    });
+}
+
+/// Query parameters for traffic statistics
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TrafficQuery {
+    /// First day (YYYY-MM-DD, inclusive), 30 days before `end` if not set
+    pub start: Option<String>,
+    /// Last day (YYYY-MM-DD, inclusive), today if not set
+    pub end: Option<String>,
+    /// Only this user
+    pub username: Option<String>,
+}
+
+/// Traffic of one user on one day
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TrafficDay {
+    pub username: String,
+    pub day: String,
+    #[serde(rename = "uploadBytes")]
+    pub upload_bytes: i64,
+    #[serde(rename = "downloadBytes")]
+    pub download_bytes: i64,
+}
+
+/// Traffic of one user over the queried range
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TrafficTotal {
+    pub username: String,
+    #[serde(rename = "uploadBytes")]
+    pub upload_bytes: i64,
+    #[serde(rename = "downloadBytes")]
+    pub download_bytes: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TrafficReport {
+    pub start: String,
+    pub end: String,
+    /// Rows by day, then user
+    pub days: Vec<TrafficDay>,
+    /// Totals by user, largest first
+    pub users: Vec<TrafficTotal>,
+}
+
+#[derive(Debug)]
+enum TrafficError {
+    BadDate,
+    Forbidden,
+    Db(DbErr),
+}
+
+impl From<DbErr> for TrafficError {
+    fn from(e: DbErr) -> Self {
+        TrafficError::Db(e)
+    }
+}
+
+impl TrafficError {
+    fn status(&self) -> StatusCode {
+        match self {
+            TrafficError::BadDate => StatusCode::BAD_REQUEST,
+            TrafficError::Forbidden => StatusCode::FORBIDDEN,
+            TrafficError::Db(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            TrafficError::BadDate => "日期格式错误，应为 YYYY-MM-DD",
+            TrafficError::Forbidden => "权限不足",
+            TrafficError::Db(e) => {
+                tracing::error!("Failed to query traffic: {}", e);
+                "查询流量统计失败"
+            }
+        }
+    }
+}
+
+/// Inclusive day range of a query
+fn day_range(query: &TrafficQuery) -> Result<(String, String), TrafficError> {
+    let parse = |s: &str| {
+        chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| TrafficError::BadDate)
+    };
+    let end = match query.end.as_deref().filter(|s| !s.is_empty()) {
+        Some(s) => parse(s)?,
+        None => chrono::Utc::now().date_naive(),
+    };
+    let start = match query.start.as_deref().filter(|s| !s.is_empty()) {
+        Some(s) => parse(s)?,
+        None => end - chrono::Duration::days(DEFAULT_DAYS - 1),
+    };
+    Ok((start.format("%Y-%m-%d").to_string(), end.format("%Y-%m-%d").to_string()))
+}
+
+/// Users whose traffic `user` may see, None for all
+///
+/// Auditors see everyone, department auditors their department subtree,
+/// other users only themselves.
+async fn visible_users(db: &DatabaseConnection, user: &CurrentUser) -> Result<Option<Vec<String>>, DbErr> {
+    if user.can_audit() {
+        Ok(None)
+    } else if user.can_audit_dept() {
+        department_usernames(db, user.department_id).await.map(Some)
+    } else {
+        Ok(Some(vec![user.username.clone()]))
+    }
+}
+
+async fn query_rows(
+    db: &DatabaseConnection,
+    user: &CurrentUser,
+    query: &TrafficQuery,
+) -> Result<(String, String, Vec<traffic::Model>), TrafficError> {
+    let (start, end) = day_range(query)?;
+    let visible = visible_users(db, user).await?;
+    let requested = query.username.as_deref().filter(|s| !s.is_empty());
+
+    let mut select = traffic::Entity::find()
+        .filter(traffic::Column::Day.gte(start.as_str()))
+        .filter(traffic::Column::Day.lte(end.as_str()));
+    match (requested, visible) {
+        (Some(name), Some(users)) if !users.iter().any(|u| u == name) => {
+            abuse::record_denied(&user.username);
+            return Err(TrafficError::Forbidden);
+        }
+        (Some(name), _) => select = select.filter(traffic::Column::Username.eq(name)),
+        (None, Some(users)) => select = select.filter(traffic::Column::Username.is_in(users)),
+        (None, None) => {}
+    }
+
+    // Include what was transferred since the last flush
+    flush(db).await?;
+    let rows = select
+        .order_by_asc(traffic::Column::Day)
+        .order_by_asc(traffic::Column::Username)
+        .all(db)
+        .await?;
+    Ok((start, end, rows))
+}
+
+/// Per-user totals, largest first
+fn totals(rows: &[traffic::Model]) -> Vec<TrafficTotal> {
+    let mut by_user: HashMap<&str, (i64, i64)> = HashMap::new();
+    for row in rows {
+        let total = by_user.entry(&row.username).or_default();
+        total.0 += row.upload_bytes;
+        total.1 += row.download_bytes;
+    }
+    let mut users: Vec<_> = by_user
+        .into_iter()
+        .map(|(username, (upload, download))| TrafficTotal {
+            username: username.to_string(),
+            upload_bytes: upload,
+            download_bytes: download,
+        })
+        .collect();
+    users.sort_by(|a, b| {
+        (b.upload_bytes + b.download_bytes)
+            .cmp(&(a.upload_bytes + a.download_bytes))
+            .then_with(|| a.username.cmp(&b.username))
+    });
+    users
+}
+
+/// GET /api/stats/traffic - Uploaded and downloaded bytes per user and day
+#[utoipa::path(
+    get,
+    path = "/api/stats/traffic",
+    tag = "stats",
+    params(TrafficQuery),
+    responses((status = 200, body = ApiResponse<TrafficReport>)),
+)]
+pub async fn get_traffic(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<TrafficQuery>,
+) -> Json<ApiResponse<TrafficReport>> {
+    match query_rows(&db, &current_user, &query).await {
+        Ok((start, end, rows)) => {
+            let users = totals(&rows);
+            let days = rows
+                .into_iter()
+                .map(|row| TrafficDay {
+                    username: row.username,
+                    day: row.day,
+                    upload_bytes: row.upload_bytes,
+                    download_bytes: row.download_bytes,
+                })
+                .collect();
+            Json(ApiResponse::success(TrafficReport { start, end, days, users }))
+        }
+        Err(e) => Json(ApiResponse::error(e.status().as_u16() as i32, e.message())),
+    }
+}
+
+/// GET /api/stats/traffic/export - Traffic report as CSV
+#[utoipa::path(
+    get,
+    path = "/api/stats/traffic/export",
+    tag = "stats",
+    params(TrafficQuery),
+    responses((status = 200, description = "CSV with one row per user and day", content_type = "text/csv")),
+)]
+pub async fn export_traffic(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<TrafficQuery>,
+) -> Response {
+    let (start, end, rows) = match query_rows(&db, &current_user, &query).await {
+        Ok(result) => result,
+        Err(e) => {
+            return (e.status(), Json(serde_json::json!({"error": e.message()}))).into_response();
+        }
+    };
+
+    let mut csv = String::from("\u{feff}day,username,upload_bytes,download_bytes\n");
+    for row in &rows {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            row.day,
+            csv_field(&row.username),
+            row.upload_bytes,
+            row.download_bytes
+        ));
+    }
+
+    let filename = format!("traffic_{}_{}.csv", start, end);
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename={}", filename))
+        .body(Body::from(csv))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission::perm;
+    use crate::testing::TestEnv;
+
+    #[test]
+    fn test_day_range() {
+        let query = TrafficQuery {
+            start: None,
+            end: Some("2026-03-10".to_string()),
+            username: None,
+        };
+        let (start, end) = day_range(&query).unwrap();
+        assert_eq!((start.as_str(), end.as_str()), ("2026-02-09", "2026-03-10"));
+
+        let query = TrafficQuery {
+            start: Some("2026-3-1x".to_string()),
+            end: None,
+            username: None,
+        };
+        assert!(day_range(&query).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_counted_and_report() {
+        let env = TestEnv::new().await;
+        let alice = "traffic-test-alice";
+        let bob = "traffic-test-bob";
+
+        add_upload(alice, 100);
+        let chunks = futures::stream::iter(vec![Ok::<_, std::io::Error>(vec![0u8; 7]), Ok(vec![0u8; 5])]);
+        let sent: Vec<_> = counted(alice, chunks).collect().await;
+        assert_eq!(sent.len(), 2);
+        add_download(bob, 1000);
+        flush(&env.db).await.unwrap();
+        // Counts of the same day are added to the existing row
+        add_upload(alice, 1);
+        flush(&env.db).await.unwrap();
+
+        let report = |username: Option<&str>, user: CurrentUser| {
+            let db = env.db_conn();
+            let query = TrafficQuery {
+                start: None,
+                end: None,
+                username: username.map(str::to_string),
+            };
+            async move { get_traffic(Extension(db), Extension(user), Query(query)).await.0 }
+        };
+
+        let res = report(None, env.user(alice, &[perm::FILE])).await;
+        let data = res.data.unwrap();
+        assert_eq!(data.days.len(), 1);
+        assert_eq!(data.days[0].upload_bytes, 101);
+        assert_eq!(data.days[0].download_bytes, 12);
+
+        // Other users are visible to auditors only
+        assert!(!report(Some(bob), env.user(alice, &[perm::FILE])).await.code);
+        let res = report(None, env.user("auditor", &[perm::AUDIT])).await;
+        let users = res.data.unwrap().users;
+        // Other tests may count traffic of their own users
+        let names: Vec<_> = users
+            .iter()
+            .map(|u| u.username.as_str())
+            .filter(|name| name.starts_with("traffic-test"))
+            .collect();
+        assert_eq!(names, [bob, alice]);
+
+        env.close().await;
+    }
+}
@@ -0,0 +1,619 @@
+//! Trash handlers
+//!
+//! Deleted files and directories are moved into a per-user trash area instead
+//! of being removed, and can be restored or purged from there. The trash may
+//! be on another volume (`trash_dir`), then they are copied over and removed.
+
+use axum::{
+    extract::State,
+    response::Json,
+    Extension,
+};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::config::Config;
+use crate::entity::{file_info, trash};
+use crate::entity::op_log::OpType;
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::dir_version;
+use crate::handlers::file::{get_user_path, resolve_in_user_root};
+use crate::handlers::journal::{self, Intent};
+use crate::handlers::legal_hold;
+use crate::handlers::quota;
+use crate::handlers::shredder;
+use crate::handlers::tiering;
+use crate::handlers::watch::{self, ChangeKind, Client};
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::mime;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+
+const OP_SUCCESS: &str = "成功";
+
+/// Trash item response
+#[derive(Debug, Serialize)]
+pub struct TrashItemResponse {
+    pub id: i64,
+    pub name: String,
+    #[serde(rename = "originalPath")]
+    pub original_path: String,
+    #[serde(rename = "isDirectory")]
+    pub is_directory: bool,
+    pub size: i64,
+    #[serde(rename = "deleteTime")]
+    pub delete_time: i64,
+    /// When the item will be purged automatically (0 = never)
+    #[serde(rename = "expireTime")]
+    pub expire_time: i64,
+}
+
+impl TrashItemResponse {
+    fn from_model(m: trash::Model, retention_days: u64) -> Self {
+        let expire_time = if retention_days > 0 {
+            m.delete_time + (retention_days * 86400) as i64
+        } else {
+            0
+        };
+        Self {
+            id: m.id,
+            name: m.name,
+            original_path: format!("/{}", m.original_path),
+            is_directory: m.is_directory,
+            size: m.size,
+            delete_time: m.delete_time,
+            expire_time,
+        }
+    }
+}
+
+/// Restore request
+#[derive(Debug, Deserialize)]
+pub struct RestoreRequest {
+    pub ids: Vec<i64>,
+}
+
+/// Purge request (no ids = empty the whole trash)
+#[derive(Debug, Deserialize)]
+pub struct PurgeRequest {
+    #[serde(default)]
+    pub ids: Vec<i64>,
+}
+
+/// Get the trash directory of a user
+/// Path format: {trash_dir}/{username}
+pub fn get_trash_path(config: &Config, username: &str) -> PathBuf {
+    config.trash_dir().join(username)
+}
+
+/// Copy a file or directory tree, for moves to another filesystem
+pub(crate) fn copy_tree(src: &Path, dst: &Path) -> std::io::Result<()> {
+    if src.is_dir() {
+        std::fs::create_dir_all(dst)?;
+        for entry in std::fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        std::fs::copy(src, dst).map(|_| ())
+    }
+}
+
+/// Move a file or directory, copying it over and removing it when `to` is on
+/// another filesystem
+///
+/// A failed copy is removed again, leaving `from` as it was.
+async fn move_entry(from: &Path, to: &Path) -> std::io::Result<()> {
+    match fs::rename(from, to).await {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            let (src, dst) = (from.to_path_buf(), to.to_path_buf());
+            let copied = tokio::task::spawn_blocking(move || copy_tree(&src, &dst))
+                .await
+                .map_err(std::io::Error::other)
+                .and_then(|copied| copied);
+            if let Err(e) = copied {
+                let _ = remove_entry(to).await;
+                return Err(e);
+            }
+            remove_entry(from).await
+        }
+        result => result,
+    }
+}
+
+async fn remove_entry(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path).await?.is_dir() {
+        fs::remove_dir_all(path).await
+    } else {
+        fs::remove_file(path).await
+    }
+}
+
+/// Move `{parent_path}/{name}` of the user into the trash
+///
+/// The caller is responsible for removing the `file_info` rows.
+pub async fn move_to_trash(
+    config: &Config,
+    db: &DatabaseConnection,
+    username: &str,
+    parent_path: &str,
+    name: &str,
+) -> anyhow::Result<trash::Model> {
+    let parent_path = parent_path.trim_matches('/');
+    let relative = format!("{}/{}", parent_path, name);
+    let source = resolve_in_user_root(config, username, &relative)
+        .ok_or_else(|| anyhow::anyhow!("invalid path"))?;
+    // The trash keeps the content, not stubs of it
+    tiering::recall(db, username, &get_user_path(config, username), &relative).await?;
+    let metadata = fs::metadata(&source).await?;
+
+    let size = if metadata.is_dir() {
+        let dir = source.clone();
+        tokio::task::spawn_blocking(move || dir_size(&dir)).await?
+    } else {
+        metadata.len()
+    };
+
+    let trash_dir = get_trash_path(config, username);
+    fs::create_dir_all(&trash_dir).await?;
+    let trash_name = uuid::Uuid::new_v4().to_string();
+    let trash_file = trash_dir.join(&trash_name);
+    move_entry(&source, &trash_file).await?;
+    dir_version::bump_entry(username, &relative);
+
+    let item = trash::ActiveModel {
+        username: Set(username.to_string()),
+        name: Set(name.to_string()),
+        original_path: Set(parent_path.to_string()),
+        trash_name: Set(trash_name),
+        is_directory: Set(metadata.is_dir()),
+        size: Set(size as i64),
+        delete_time: Set(chrono::Utc::now().timestamp()),
+        ..Default::default()
+    };
+
+    match item.insert(db).await {
+        Ok(model) => {
+            quota::add_usage(username, -model.size);
+            Ok(model)
+        }
+        Err(e) => {
+            // Put the file back so it isn't lost without a trash record
+            if let Err(err) = move_entry(&trash_file, &source).await {
+                tracing::error!("Failed to roll back trash move: {}", err);
+            }
+            Err(e.into())
+        }
+    }
+}
+
+/// Put a copy of the file `{parent_path}/{name}` of the user into the trash
+///
+/// Used to keep the previous content before a file is overwritten, so it can
+/// be restored like any deleted file. The copy is named after the original
+/// with a backup time suffix.
+pub async fn backup_to_trash(
+    config: &Config,
+    db: &DatabaseConnection,
+    username: &str,
+    parent_path: &str,
+    name: &str,
+) -> anyhow::Result<trash::Model> {
+    let parent_path = parent_path.trim_matches('/');
+    let relative = format!("{}/{}", parent_path, name);
+    let source = resolve_in_user_root(config, username, &relative)
+        .ok_or_else(|| anyhow::anyhow!("invalid path"))?;
+    tiering::recall(db, username, &get_user_path(config, username), &relative).await?;
+
+    let trash_dir = get_trash_path(config, username);
+    fs::create_dir_all(&trash_dir).await?;
+    let trash_name = uuid::Uuid::new_v4().to_string();
+    let trash_file = trash_dir.join(&trash_name);
+    let size = fs::copy(&source, &trash_file).await?;
+
+    let item = trash::ActiveModel {
+        username: Set(username.to_string()),
+        name: Set(backup_name(name, &chrono::Local::now().format("%Y%m%d%H%M%S").to_string())),
+        original_path: Set(parent_path.to_string()),
+        trash_name: Set(trash_name),
+        is_directory: Set(false),
+        size: Set(size as i64),
+        delete_time: Set(chrono::Utc::now().timestamp()),
+        ..Default::default()
+    };
+
+    match item.insert(db).await {
+        Ok(model) => Ok(model),
+        Err(e) => {
+            let _ = fs::remove_file(&trash_file).await;
+            Err(e.into())
+        }
+    }
+}
+
+/// Name of a backup copy, e.g. "report (备份 20240101120000).docx"
+fn backup_name(name: &str, time: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{} (备份 {}).{}", stem, time, ext),
+        _ => format!("{} (备份 {})", name, time),
+    }
+}
+
+/// Total size of all files below a directory
+pub(crate) fn dir_size(path: &Path) -> u64 {
+    let mut total = 0;
+    if let Ok(entries) = std::fs::read_dir(path) {
+        for entry in entries.flatten() {
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => total += dir_size(&entry.path()),
+                Ok(_) => total += entry.metadata().map(|m| m.len()).unwrap_or(0),
+                Err(_) => {}
+            }
+        }
+    }
+    total
+}
+
+/// Pick a name that doesn't exist in `dir`, e.g. "report (1).txt"
+fn unique_name(dir: &Path, name: &str) -> String {
+    if !dir.join(name).exists() {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(pos) if pos > 0 => (&name[..pos], &name[pos..]),
+        _ => (name, ""),
+    };
+    let mut n = 1;
+    loop {
+        let candidate = format!("{} ({}){}", stem, n, ext);
+        if !dir.join(&candidate).exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// Resolve the directory id of a path, creating missing `file_info` rows
+pub(crate) async fn ensure_dir_id(db: &DatabaseConnection, username: &str, path: &str) -> anyhow::Result<i64> {
+    let mut parent_id: i64 = -1;
+    for part in path.split('/').filter(|p| !p.is_empty()) {
+        let existing = file_info::Entity::find()
+            .filter(file_info::Column::ParentId.eq(parent_id))
+            .filter(file_info::Column::Username.eq(username))
+            .filter(file_info::Column::Name.eq(part))
+            .filter(file_info::Column::IsDirectory.eq(true))
+            .one(db)
+            .await?;
+
+        parent_id = match existing {
+            Some(f) => f.id,
+            None => {
+                let now = chrono::Utc::now().timestamp();
+                file_info::ActiveModel {
+                    username: Set(username.to_string()),
+                    file_type: Set("dir".to_string()),
+                    name: Set(part.to_string()),
+                    parent_id: Set(parent_id),
+                    create_time: Set(now),
+                    modify_time: Set(now),
+                    is_directory: Set(true),
+                    size: Set(0),
+                    ..Default::default()
+                }
+                .insert(db)
+                .await?
+                .id
+            }
+        };
+    }
+    Ok(parent_id)
+}
+
+/// Insert `file_info` rows for a restored file or directory tree
+pub(crate) async fn register_tree(
+    db: &DatabaseConnection,
+    username: &str,
+    parent_id: i64,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let metadata = fs::metadata(path).await?;
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let modify_time = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_else(|| chrono::Utc::now().timestamp());
+
+    let file_type = if metadata.is_dir() {
+        "dir".to_string()
+    } else {
+        mime::detect_file(path).await.to_string()
+    };
+    let row = file_info::ActiveModel {
+        username: Set(username.to_string()),
+        file_type: Set(file_type),
+        name: Set(name),
+        parent_id: Set(parent_id),
+        create_time: Set(modify_time),
+        modify_time: Set(modify_time),
+        is_directory: Set(metadata.is_dir()),
+        size: Set(if metadata.is_dir() { 0 } else { metadata.len() as i64 }),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    if metadata.is_dir() {
+        let mut entries = fs::read_dir(path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            Box::pin(register_tree(db, username, row.id, &entry.path())).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Move a trash item back to its original location, returning the restored path
+pub(crate) async fn restore_item(
+    config: &Config,
+    db: &DatabaseConnection,
+    item: &trash::Model,
+) -> anyhow::Result<String> {
+    let trash_file = get_trash_path(config, &item.username).join(&item.trash_name);
+    let target_dir = resolve_in_user_root(config, &item.username, &item.original_path)
+        .ok_or_else(|| anyhow::anyhow!("invalid path"))?;
+    fs::create_dir_all(&target_dir).await?;
+
+    let parent_id = ensure_dir_id(db, &item.username, &item.original_path).await?;
+    let name = unique_name(&target_dir, &item.name);
+    let target = target_dir.join(&name);
+    let path = format!("{}/{}", item.original_path.trim_matches('/'), name);
+    let path = path.trim_start_matches('/');
+    let intent = Intent::Restore {
+        owner: item.username.clone(),
+        trash_id: item.id,
+        trash_file: trash_file.clone(),
+        path: path.to_string(),
+    };
+    let entry = journal::begin(db, &intent).await?;
+    let result = put_back(db, item, &trash_file, &target, path, parent_id).await;
+    journal::finish(db, entry).await;
+    result?;
+
+    Ok(format!("/{}", Path::new(&item.original_path).join(name).display()).replace("//", "/"))
+}
+
+/// Rename a trash item to `target`, `path` in its owner's tree, and swap its
+/// trash row for `file_info` rows
+async fn put_back(
+    db: &DatabaseConnection,
+    item: &trash::Model,
+    trash_file: &Path,
+    target: &Path,
+    path: &str,
+    parent_id: i64,
+) -> anyhow::Result<()> {
+    move_entry(trash_file, target).await?;
+    dir_version::bump_entry(&item.username, path);
+
+    quota::add_usage(&item.username, item.size);
+    register_tree(db, &item.username, parent_id, target).await?;
+    trash::Entity::delete_by_id(item.id).exec(db).await?;
+    Ok(())
+}
+
+/// Permanently remove a trash item from disk and database
+///
+/// The data is handed to the [`shredder`], which removes it in the background.
+pub(crate) async fn purge_item(config: &Config, db: &DatabaseConnection, item: &trash::Model) -> anyhow::Result<()> {
+    let trash_file = get_trash_path(config, &item.username).join(&item.trash_name);
+    match shredder::enqueue(config, &trash_file).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    trash::Entity::delete_by_id(item.id).exec(db).await?;
+    Ok(())
+}
+
+/// GET /api/trash/list
+pub async fn list_trash(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Json<ApiResponse<Vec<TrashItemResponse>>> {
+    match trash::Entity::find()
+        .filter(trash::Column::Username.eq(&current_user.username))
+        .order_by_desc(trash::Column::DeleteTime)
+        .all(&*db)
+        .await
+    {
+        Ok(items) => {
+            let retention = state.config.trash_retention_days;
+            Json(ApiResponse::success(
+                items
+                    .into_iter()
+                    .map(|m| TrashItemResponse::from_model(m, retention))
+                    .collect(),
+            ))
+        }
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            Json(ApiResponse::error(500, "internal error"))
+        }
+    }
+}
+
+/// POST /api/trash/restore
+pub async fn restore_trash(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<RestoreRequest>,
+) -> Json<ApiResponse<()>> {
+    let items = match trash::Entity::find()
+        .filter(trash::Column::Username.eq(&current_user.username))
+        .filter(trash::Column::Id.is_in(req.ids.clone()))
+        .all(&*db)
+        .await
+    {
+        Ok(items) => items,
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    let mut success = 0;
+    let mut failed = req.ids.len() - items.len();
+    for item in &items {
+        match restore_item(&state.config, &db, item).await {
+            Ok(path) => {
+                let owner = &current_user.username;
+                watch::publish(owner, &path, owner, ChangeKind::Restored, Client::Web);
+                log_operation(&current_user.username, OpType::Restore, &path, OP_SUCCESS, None);
+                success += 1;
+            }
+            Err(e) => {
+                tracing::error!("Failed to restore {}: {}", item.name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    Json(ApiResponse::success_msg(format!("还原成功{}个文件，失败{}个文件", success, failed)))
+}
+
+/// POST /api/trash/purge
+pub async fn purge_trash(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<PurgeRequest>,
+) -> Json<ApiResponse<()>> {
+    let mut query = trash::Entity::find().filter(trash::Column::Username.eq(&current_user.username));
+    if !req.ids.is_empty() {
+        query = query.filter(trash::Column::Id.is_in(req.ids.clone()));
+    }
+    let items = match query.all(&*db).await {
+        Ok(items) => items,
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    let mut success = 0;
+    let mut failed = 0;
+    let mut held = Vec::new();
+    for item in &items {
+        match legal_hold::held(&db, &item.username, &legal_hold::trash_item_path(item)).await {
+            Ok(None) => {}
+            Ok(Some(hold)) => {
+                failed += 1;
+                held.push(hold.path);
+                continue;
+            }
+            Err(e) => {
+                tracing::error!("Failed to check legal hold of {}: {}", item.name, e);
+                failed += 1;
+                continue;
+            }
+        }
+        match purge_item(&state.config, &db, item).await {
+            Ok(_) => {
+                let op_desc = format!("/{}", Path::new(&item.original_path).join(&item.name).display())
+                    .replace("//", "/");
+                log_operation(&current_user.username, OpType::Purge, &op_desc, OP_SUCCESS, None);
+                success += 1;
+            }
+            Err(e) => {
+                tracing::error!("Failed to purge {}: {}", item.name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    let mut message = format!("删除成功{}个文件，失败{}个文件", success, failed);
+    for path in &held {
+        message.push('；');
+        message.push_str(&legal_hold::message(path));
+    }
+    Json(ApiResponse::success_msg(message))
+}
+
+/// Purge every trash item older than the retention period
+///
+/// Items under legal hold are kept until the hold is released.
+pub async fn purge_expired(config: &Config, db: &DatabaseConnection) -> anyhow::Result<usize> {
+    if config.trash_retention_days == 0 {
+        return Ok(0);
+    }
+    let cutoff = chrono::Utc::now().timestamp() - (config.trash_retention_days * 86400) as i64;
+    let items = trash::Entity::find()
+        .filter(trash::Column::DeleteTime.lt(cutoff))
+        .all(db)
+        .await?;
+
+    let mut purged = 0;
+    for item in &items {
+        if legal_hold::held(db, &item.username, &legal_hold::trash_item_path(item)).await?.is_some() {
+            continue;
+        }
+        match purge_item(config, db, item).await {
+            Ok(_) => purged += 1,
+            Err(e) => tracing::error!("Failed to purge expired trash item {}: {}", item.id, e),
+        }
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unique_name() {
+        let dir = std::env::temp_dir().join(format!("datadisk_trash_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(unique_name(&dir, "a.txt"), "a.txt");
+
+        std::fs::write(dir.join("a.txt"), b"x").unwrap();
+        assert_eq!(unique_name(&dir, "a.txt"), "a (1).txt");
+
+        std::fs::write(dir.join("a (1).txt"), b"x").unwrap();
+        assert_eq!(unique_name(&dir, "a.txt"), "a (2).txt");
+
+        std::fs::create_dir(dir.join(".hidden")).unwrap();
+        assert_eq!(unique_name(&dir, ".hidden"), ".hidden (1)");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dir_size() {
+        let dir = std::env::temp_dir().join(format!("datadisk_trash_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a"), b"12345").unwrap();
+        std::fs::write(dir.join("sub/b"), b"123").unwrap();
+        assert_eq!(dir_size(&dir), 8);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backup_name() {
+        assert_eq!(backup_name("report.docx", "20240101120000"), "report (备份 20240101120000).docx");
+        assert_eq!(backup_name("a.tar.gz", "1"), "a.tar (备份 1).gz");
+        assert_eq!(backup_name("README", "1"), "README (备份 1)");
+        assert_eq!(backup_name(".env", "1"), ".env (备份 1)");
+    }
+}
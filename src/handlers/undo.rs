@@ -0,0 +1,358 @@
+//! Undo of file operations
+//!
+//! Renames, moves and deletes to the trash made in the web UI are recorded in
+//! the `op_journal` with what it takes to invert them: the old names, the
+//! folders moved between, the trash items. `POST /api/file/undo` reverts the
+//! user's most recent operation if it was made in the last [`WINDOW`]
+//! seconds: renamed entries get their names back, deleted ones are restored
+//! from the trash and moved ones are moved back as a task. Each operation is
+//! undone once; older ones are forgotten as new ones are recorded.
+
+use axum::{extract::State, response::Json, Extension};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::entity::op_journal;
+use crate::entity::op_log::OpType;
+use crate::entity::trash;
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::dept_space::Location;
+use crate::handlers::file::{get_user_path, locate_for_write};
+use crate::handlers::trash::restore_item;
+use crate::handlers::watch::{self, ChangeKind, Client};
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::ApiResponse;
+use crate::service::FileService;
+use crate::state::AppState;
+use crate::task::{TaskDir, TASK_MANAGER};
+
+/// Seconds an operation can be undone for
+pub const WINDOW: i64 = 10 * 60;
+
+/// Agent of the tasks moving entries back, whose moves aren't recorded
+pub const AGENT: &str = "undo";
+
+const OP_SUCCESS: &str = "成功";
+
+/// A recorded operation, with paths as the user sees them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    /// Entries of `dir` renamed from the first name to the second
+    Rename { dir: String, renames: Vec<(String, String)> },
+    /// Entries moved from `source` into `target`, by their names there
+    Move { source: String, target: String, files: Vec<String> },
+    /// Entries of `dir` moved to the trash items `items`
+    Delete { dir: String, items: Vec<i64> },
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Rename { .. } => "rename",
+            Operation::Move { .. } => "move",
+            Operation::Delete { .. } => "delete",
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Operation::Rename { renames, .. } => renames.is_empty(),
+            Operation::Move { files, .. } => files.is_empty(),
+            Operation::Delete { items, .. } => items.is_empty(),
+        }
+    }
+}
+
+/// Record `operation` of `username` as the one to undo next
+///
+/// Operations that changed nothing aren't recorded. Failures are logged, the
+/// operation itself is done.
+pub async fn record(db: &DatabaseConnection, username: &str, operation: Operation) {
+    if operation.is_empty() {
+        return;
+    }
+    let now = chrono::Utc::now().timestamp();
+    let result = op_journal::Entity::delete_many()
+        .filter(op_journal::Column::Username.eq(username))
+        .filter(op_journal::Column::CreateTime.lt(now - WINDOW))
+        .exec(db)
+        .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to forget old operations of {}: {}", username, e);
+    }
+
+    let detail = match serde_json::to_string(&operation) {
+        Ok(detail) => detail,
+        Err(e) => {
+            tracing::error!("Failed to record operation: {}", e);
+            return;
+        }
+    };
+    let entry = op_journal::ActiveModel {
+        username: Set(username.to_string()),
+        op: Set(operation.as_str().to_string()),
+        detail: Set(detail),
+        create_time: Set(now),
+        ..Default::default()
+    };
+    if let Err(e) = entry.insert(db).await {
+        tracing::error!("Failed to record {} of {}: {}", operation.as_str(), username, e);
+    }
+}
+
+/// Take the operation of `username` to undo, if any is recent enough
+async fn take_latest(db: &DatabaseConnection, username: &str) -> Result<Option<Operation>, sea_orm::DbErr> {
+    let since = chrono::Utc::now().timestamp() - WINDOW;
+    let Some(entry) = op_journal::Entity::find()
+        .filter(op_journal::Column::Username.eq(username))
+        .filter(op_journal::Column::CreateTime.gte(since))
+        .order_by_desc(op_journal::Column::Id)
+        .one(db)
+        .await?
+    else {
+        return Ok(None);
+    };
+    // Whoever deletes the entry undoes it
+    let deleted = op_journal::Entity::delete_by_id(entry.id).exec(db).await?;
+    if deleted.rows_affected == 0 {
+        return Ok(None);
+    }
+    match serde_json::from_str(&entry.detail) {
+        Ok(operation) => Ok(Some(operation)),
+        Err(e) => {
+            tracing::error!("Invalid operation {} in op journal: {}", entry.id, e);
+            Ok(None)
+        }
+    }
+}
+
+/// What an undo did
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UndoResult {
+    /// The operation undone: rename, move or delete
+    pub op: String,
+    /// Entries put back as they were
+    pub succeeded: usize,
+    /// Entries that couldn't be put back
+    pub failed: usize,
+    /// Task moving the entries back, for moves
+    #[serde(rename = "taskId", skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
+}
+
+/// POST /api/file/undo - Undo the most recent rename, move or delete
+#[utoipa::path(
+    post,
+    path = "/api/file/undo",
+    tag = "file",
+    responses((status = 200, body = ApiResponse<UndoResult>)),
+)]
+pub async fn undo(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Json<ApiResponse<UndoResult>> {
+    let operation = match take_latest(&db, &current_user.username).await {
+        Ok(Some(operation)) => operation,
+        Ok(None) => return Json(ApiResponse::error(404, "没有可撤销的操作")),
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    let op = operation.as_str().to_string();
+    let result = match operation {
+        Operation::Rename { dir, renames } => undo_rename(&state, &db, &current_user, &dir, renames).await,
+        Operation::Delete { dir, items } => undo_delete(&state, &db, &current_user, &dir, items).await,
+        Operation::Move { source, target, files } => {
+            undo_move(&state, &db, &current_user, &source, &target, files).await
+        }
+    };
+    match result {
+        Ok((succeeded, failed, task_id)) => Json(ApiResponse::success(UndoResult { op, succeeded, failed, task_id })),
+        Err((status, error)) => Json(ApiResponse::error(status, error)),
+    }
+}
+
+type Undone = Result<(usize, usize, Option<String>), (i32, &'static str)>;
+
+async fn locate_dir(state: &AppState, db: &DbConn, user: &CurrentUser, dir: &str) -> Result<Location, (i32, &'static str)> {
+    locate_for_write(state, db, user, dir)
+        .await
+        .map_err(|(status, error)| (status.as_u16() as i32, error))
+}
+
+/// Give renamed entries their old names back
+async fn undo_rename(
+    state: &AppState,
+    db: &DbConn,
+    user: &CurrentUser,
+    dir: &str,
+    renames: Vec<(String, String)>,
+) -> Undone {
+    let location = locate_dir(state, db, user, dir).await?;
+    let service = FileService::new(&state.config, db, &location.owner)
+        .on_behalf_of(&user.username, &location.prefix);
+    let parent = location.path.trim_matches('/');
+    let (mut succeeded, mut failed) = (0, 0);
+    for (from, to) in renames {
+        // Audited by the service
+        match service.rename(&format!("{}/{}", parent, to), &from).await {
+            Ok(_) => succeeded += 1,
+            Err(e) => {
+                tracing::error!("Failed to rename {} back to {}: {}", to, from, e);
+                failed += 1;
+            }
+        }
+    }
+    Ok((succeeded, failed, None))
+}
+
+/// Restore deleted entries from the trash
+///
+/// Entries purged since can't be restored.
+async fn undo_delete(state: &AppState, db: &DbConn, user: &CurrentUser, dir: &str, ids: Vec<i64>) -> Undone {
+    let location = locate_dir(state, db, user, dir).await?;
+    let items = trash::Entity::find()
+        .filter(trash::Column::Username.eq(&location.owner))
+        .filter(trash::Column::Id.is_in(ids.clone()))
+        .all(&**db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Database error: {}", e);
+            (500, "internal error")
+        })?;
+
+    let mut succeeded = 0;
+    let mut failed = ids.len() - items.len();
+    for item in &items {
+        match restore_item(&state.config, db, item).await {
+            Ok(path) => {
+                watch::publish(&location.owner, &path, &user.username, ChangeKind::Restored, Client::Web);
+                log_operation(&user.username, OpType::Restore, &format!("{}{}", location.prefix, path), OP_SUCCESS, None);
+                succeeded += 1;
+            }
+            Err(e) => {
+                tracing::error!("Failed to restore {}: {}", item.name, e);
+                failed += 1;
+            }
+        }
+    }
+    Ok((succeeded, failed, None))
+}
+
+/// Move moved entries back where they came from, as a task
+async fn undo_move(
+    state: &AppState,
+    db: &DbConn,
+    user: &CurrentUser,
+    source: &str,
+    target: &str,
+    files: Vec<String>,
+) -> Undone {
+    let from = locate_dir(state, db, user, target).await?;
+    let to = locate_dir(state, db, user, source).await?;
+    let from_root = get_user_path(&state.config, &from.owner);
+    let to_root = get_user_path(&state.config, &to.owner);
+    let count = files.len();
+    let info = TASK_MANAGER.create_copy_task(
+        user.id,
+        &user.username,
+        AGENT,
+        false,
+        target.to_string(),
+        source.to_string(),
+        files,
+        TaskDir { owner: from.owner, root: from_root, path: from.path },
+        TaskDir { owner: to.owner, root: to_root, path: to.path },
+        db.0.clone(),
+    );
+    // The entries are moved back, and audited, as the task runs
+    Ok((count, 0, Some(info.id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestApp, TestEnv};
+
+    #[tokio::test]
+    async fn test_record() {
+        let env = TestEnv::new().await;
+        let rename = Operation::Rename { dir: "/docs".to_string(), renames: vec![("a".to_string(), "b".to_string())] };
+        record(&env.db, "alice", rename.clone()).await;
+        record(&env.db, "alice", Operation::Delete { dir: "/".to_string(), items: Vec::new() }).await;
+        // Too old to undo, and forgotten once another is recorded
+        op_journal::ActiveModel {
+            username: Set("alice".to_string()),
+            op: Set("move".to_string()),
+            detail: Set("{}".to_string()),
+            create_time: Set(chrono::Utc::now().timestamp() - WINDOW - 1),
+            ..Default::default()
+        }
+        .insert(&env.db)
+        .await
+        .unwrap();
+
+        assert_eq!(take_latest(&env.db, "bob").await.unwrap(), None);
+        assert_eq!(take_latest(&env.db, "alice").await.unwrap(), Some(rename.clone()));
+        assert_eq!(take_latest(&env.db, "alice").await.unwrap(), None);
+        record(&env.db, "alice", rename).await;
+        assert_eq!(op_journal::Entity::find().all(&env.db).await.unwrap().len(), 1);
+        env.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_undo() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let root = get_user_path(&app.env.config, "admin");
+        for name in ["docs", "archive"] {
+            let body = serde_json::json!({ "parentPath": "/", "name": name });
+            assert!(admin.post_json("/api/file/mkdir", &body).await.status().is_success());
+        }
+        assert!(admin.upload("/docs", "a.txt", b"a").await.status().is_success());
+        assert!(admin.upload("/docs", "b.txt", b"b").await.status().is_success());
+
+        // Renames get the old name back
+        let body = serde_json::json!({ "oldPath": "/docs/a.txt", "newName": "c.txt" });
+        let res: serde_json::Value = admin.post_json("/api/file/rename", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        let res: serde_json::Value = admin.post_json("/api/file/undo", &serde_json::json!({})).await.json().await.unwrap();
+        assert_eq!(res["data"]["op"], "rename", "{}", res);
+        assert!(root.join("docs/a.txt").exists() && !root.join("docs/c.txt").exists());
+
+        // Deletes are restored from the trash
+        let body = serde_json::json!({ "parentDir": "/docs", "files": ["a.txt", "b.txt"] });
+        let res: serde_json::Value = admin.post_json("/api/file/delete", &body).await.json().await.unwrap();
+        assert_eq!(res["data"]["success"], 2, "{}", res);
+        let res: serde_json::Value = admin.post_json("/api/file/undo", &serde_json::json!({})).await.json().await.unwrap();
+        assert_eq!(res["data"]["succeeded"], 2, "{}", res);
+        assert!(root.join("docs/a.txt").exists() && root.join("docs/b.txt").exists());
+        let trashed = trash::Entity::find().all(&app.env.db).await.unwrap();
+        assert!(trashed.is_empty());
+
+        // Moves are moved back by a task
+        let mut ws = admin.ws().await;
+        let body = serde_json::json!({ "isCopy": false, "source": "/docs", "target": "/archive", "files": ["a.txt"] });
+        let res: serde_json::Value = admin.post_json("/api/file/copy", &body).await.json().await.unwrap();
+        assert_eq!(res["code"], true, "{}", res);
+        ws.wait_for(|m| m["data"]["status"] == "completed").await;
+        assert!(root.join("archive/a.txt").exists());
+        let res: serde_json::Value = admin.post_json("/api/file/undo", &serde_json::json!({})).await.json().await.unwrap();
+        let id = res["data"]["taskId"].as_str().unwrap().to_string();
+        ws.wait_for(|m| m["data"]["id"] == id.as_str() && m["data"]["status"] == "completed").await;
+        assert!(root.join("docs/a.txt").exists() && !root.join("archive/a.txt").exists());
+
+        // Undone once, and the move back isn't recorded
+        let res: serde_json::Value = admin.post_json("/api/file/undo", &serde_json::json!({})).await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        app.close().await;
+    }
+}
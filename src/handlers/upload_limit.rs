@@ -0,0 +1,72 @@
+//! Upload size limits
+//!
+//! `max_upload_size` applies to everybody not listed in `[upload_limits]`,
+//! which sets limits of single users and of roles; a user's own entry wins
+//! over the one of their role. Limits are resolved per request, so the
+//! upload route has no fixed body limit and the upload handlers count the
+//! bytes themselves.
+
+use crate::config::Config;
+use crate::handlers::quota::parse_quota;
+use crate::state::AppState;
+
+/// Limit of a user with `role`
+fn resolve(config: &Config, username: &str, role: Option<&str>) -> usize {
+    let configured = config
+        .upload_limits
+        .users
+        .get(username)
+        .or_else(|| role.and_then(|role| config.upload_limits.roles.get(role)));
+    let Some(configured) = configured else {
+        return config.max_upload_size;
+    };
+    match parse_quota(configured) {
+        Some(limit) => limit.max(0) as usize,
+        None => {
+            tracing::warn!("Invalid upload limit {:?} for {}", configured, username);
+            config.max_upload_size
+        }
+    }
+}
+
+/// Largest file `username` may upload, in bytes
+pub async fn limit(state: &AppState, username: &str) -> usize {
+    let limits = &state.config.upload_limits;
+    if limits.users.contains_key(username) || limits.roles.is_empty() {
+        return resolve(&state.config, username, None);
+    }
+    let role = match state.get_perm().await {
+        Some(perm) => perm.get_user_role(username).await.unwrap_or_else(|e| {
+            tracing::error!("Failed to get the role of {}: {}", username, e);
+            None
+        }),
+        None => None,
+    };
+    resolve(&state.config, username, role.as_deref())
+}
+
+/// Error message of uploads over `limit`
+pub fn exceeded_message(limit: usize) -> String {
+    format!("文件大小超过限制，最大允许 {}MB", limit / (1024 * 1024))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let mut config = Config { max_upload_size: 1 << 30, ..Config::default() };
+        config.upload_limits.users.insert("alice".to_string(), "50G".to_string());
+        config.upload_limits.users.insert("bob".to_string(), "lots".to_string());
+        config.upload_limits.roles.insert("user".to_string(), "100M".to_string());
+
+        assert_eq!(resolve(&config, "alice", Some("user")), 50 << 30);
+        assert_eq!(resolve(&config, "carol", Some("user")), 100 << 20);
+        assert_eq!(resolve(&config, "carol", Some("admin")), 1 << 30);
+        assert_eq!(resolve(&config, "carol", None), 1 << 30);
+        // Invalid entries fall back to the global limit
+        assert_eq!(resolve(&config, "bob", Some("user")), 1 << 30);
+        assert_eq!(exceeded_message(100 << 20), "文件大小超过限制，最大允许 100MB");
+    }
+}
@@ -9,37 +9,70 @@ use axum::{
     response::{IntoResponse, Json, Response},
     Extension,
 };
+use sea_orm::sea_query::{Expr, Func, LikeExpr};
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set,
+    ActiveModelTrait, ColumnTrait, Condition, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
+    Set,
 };
 use serde::{Deserialize, Serialize};
-
-use crate::entity::user;
-use crate::handlers::audit::service::log_operation;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::entity::{api_token, user};
+use crate::entity::op_log::OpType;
+use crate::handlers::abuse;
+use crate::handlers::dept_admin::{admin_scope, can_assign_role, can_grant, can_manage_user, AdminScope};
+use crate::handlers::file::is_space_owner;
+use crate::handlers::audit::service::{log_admin_operation, log_operation};
+use crate::handlers::hot_cache;
+use crate::handlers::legal_hold;
+use crate::handlers::lockout;
+use crate::handlers::quota::get_effective_quota;
 use crate::middleware::auth::CurrentUser;
 use crate::middleware::DbConn;
 use crate::permission::normalize_permissions;
-use crate::routes::ApiResponse;
+use crate::routes::{ApiMessage, ApiResponse};
 use crate::state::AppState;
+use crate::watermark::font;
 
 // Operation types (matching Go version)
-const OP_CREATE_USER: &str = "创建用户信息";
-const OP_DELETE_USER: &str = "删除用户信息";
-const OP_UPDATE_USER: &str = "修改用户信息";
-const OP_QUERY_USER: &str = "查询用户信息";
-const OP_ENABLE_USER: &str = "启用用户";
-const OP_DISABLE_USER: &str = "禁用用户";
-const OP_UPDATE_PASSWORD: &str = "修改密码";
 const OP_SUCCESS: &str = "成功";
 const OP_FAILED: &str = "失败";
 
-/// Check if user has contacts permission (for user management)
-fn can_manage_users(user: &CurrentUser) -> bool {
-    user.can_contacts()
+/// Users and departments `user` may manage, none if they can't be read
+async fn manage_scope(db: &DbConn, user: &CurrentUser) -> AdminScope {
+    admin_scope(db, user).await.unwrap_or_else(|e| {
+        tracing::error!("Failed to load department subtree: {}", e);
+        AdminScope::None
+    })
+}
+
+/// Whether `user` may manage the account `id` named `username`
+async fn may_manage(state: &AppState, db: &DbConn, user: &CurrentUser, scope: &AdminScope, id: i64, username: &str) -> bool {
+    if *scope == AdminScope::Global {
+        return true;
+    }
+    match user::Entity::find_by_id(id).one(&**db).await {
+        Ok(Some(target)) => target.username == username && can_manage_user(state, user, scope, &target).await,
+        Ok(None) => false,
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            false
+        }
+    }
+}
+
+/// Whether `user` may give the role and direct permissions of a request
+async fn may_give(state: &AppState, user: &CurrentUser, role: Option<&str>, permissions: Option<&str>) -> bool {
+    if let Some(role) = role.filter(|r| !r.is_empty()) {
+        if !can_assign_role(state, user, role).await {
+            return false;
+        }
+    }
+    permissions.is_none_or(|p| can_grant(user, &normalize_permissions(p)))
 }
 
 /// Response with boolean code (matching Go version)
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct BoolCodeResponse {
     pub code: bool,
     pub message: String,
@@ -62,7 +95,7 @@ impl BoolCodeResponse {
 }
 
 /// Add user request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct AddUserRequest {
     pub username: String,
     pub password: String,
@@ -79,7 +112,7 @@ pub struct AddUserRequest {
 }
 
 /// Update user request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateUserRequest {
     pub id: i64,
     pub username: String,
@@ -99,7 +132,7 @@ pub struct UpdateUserRequest {
 }
 
 /// Delete user request (array of users)
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct DeleteUserItem {
     pub id: i64,
     pub username: String,
@@ -108,7 +141,7 @@ pub struct DeleteUserItem {
 }
 
 /// User response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct UserResponse {
     pub id: i64,
     pub username: String,
@@ -168,26 +201,55 @@ impl From<user::Model> for UserResponse {
 }
 
 /// Query parameters
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct DepartmentIdQuery {
     #[serde(rename = "departmentId")]
     pub department_id: i64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct UsernameQuery {
     pub username: String,
 }
 
+/// User search query parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UserSearchQuery {
+    #[serde(default)]
+    pub q: String,
+    #[serde(default = "default_page")]
+    pub page: u64,
+    #[serde(rename = "pageSize", default = "default_page_size")]
+    pub page_size: u64,
+}
+
+fn default_page() -> u64 {
+    1
+}
+
+fn default_page_size() -> u64 {
+    20
+}
+
+/// User search response with pagination
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserSearchResponse {
+    pub users: Vec<UserResponse>,
+    pub total: u64,
+}
+
 /// Enable/disable user request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UserStatusItem {
     pub id: i64,
     pub username: String,
 }
 
 /// Change password request (user changes their own password)
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ChangePasswordRequest {
     #[serde(rename = "oldPassword")]
     pub old_password: String,
@@ -196,7 +258,7 @@ pub struct ChangePasswordRequest {
 }
 
 /// Reset password request (admin resets user password)
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ResetPasswordRequest {
     pub id: i64,
     pub username: String,
@@ -204,16 +266,32 @@ pub struct ResetPasswordRequest {
 }
 
 /// POST /api/user/add
+#[utoipa::path(
+    post,
+    path = "/api/user/add",
+    tag = "user",
+    request_body = AddUserRequest,
+    responses((status = 200, body = BoolCodeResponse)),
+)]
 pub async fn add_user(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Json(req): Json<AddUserRequest>,
 ) -> Json<BoolCodeResponse> {
-    // Permission check: only admin can add users
-    if !can_manage_users(&current_user) {
+    // Permission check: only admins of the department can add users
+    if !manage_scope(&db, &current_user).await.covers(req.department_id) {
+        abuse::record_denied(&current_user.username);
         return Json(BoolCodeResponse::error("权限不足，仅管理员可添加用户"));
     }
+    if !may_give(&state, &current_user, req.role.as_deref(), req.permissions.as_deref()).await {
+        abuse::record_denied(&current_user.username);
+        return Json(BoolCodeResponse::error("权限不足，不能分配该角色或权限"));
+    }
+    // Reserved for the owners of shared spaces
+    if is_space_owner(&req.username) {
+        return Json(BoolCodeResponse::error("用户名无效"));
+    }
 
     let existing = user::Entity::find()
         .filter(user::Column::Username.eq(&req.username))
@@ -286,7 +364,7 @@ pub async fn add_user(
 
             // Log operation
             let op_desc = format!("所属部门: {}, 用户名: {}", dept_name, req.username);
-            log_operation(&current_user.username, OP_CREATE_USER, &op_desc, OP_SUCCESS, None);
+            log_admin_operation(&current_user.username, OpType::CreateUser, &op_desc, OP_SUCCESS, None);
             Json(BoolCodeResponse::success("success"))
         }
         Err(e) => {
@@ -297,6 +375,13 @@ pub async fn add_user(
 }
 
 /// POST /api/user/delete
+#[utoipa::path(
+    post,
+    path = "/api/user/delete",
+    tag = "user",
+    request_body = Vec<DeleteUserItem>,
+    responses((status = 200, body = BoolCodeResponse)),
+)]
 pub async fn delete_user(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
@@ -304,7 +389,9 @@ pub async fn delete_user(
     Json(users): Json<Vec<DeleteUserItem>>,
 ) -> Json<BoolCodeResponse> {
     // Permission check: only admin can delete users
-    if !can_manage_users(&current_user) {
+    let scope = manage_scope(&db, &current_user).await;
+    if scope.is_none() {
+        abuse::record_denied(&current_user.username);
         return Json(BoolCodeResponse::error("权限不足，仅管理员可删除用户"));
     }
 
@@ -322,6 +409,19 @@ pub async fn delete_user(
 
     for u in users {
         let op_desc = format!("所属部门: {}, 用户名: {}", dept_name, u.username);
+        if !may_manage(&state, &db, &current_user, &scope, u.id, &u.username).await {
+            abuse::record_denied(&current_user.username);
+            error_count += 1;
+            log_admin_operation(&current_user.username, OpType::DeleteUser, &op_desc, OP_FAILED, None);
+            continue;
+        }
+        // Deleting the account would delete the held files with it
+        if !matches!(legal_hold::held(&db, &u.username, "/").await, Ok(None)) {
+            tracing::warn!("Not deleting user {}: files under legal hold", u.username);
+            error_count += 1;
+            log_admin_operation(&current_user.username, OpType::DeleteUser, &op_desc, OP_FAILED, None);
+            continue;
+        }
         match user::Entity::delete_by_id(u.id).exec(&*db).await {
             Ok(_) => {
                 success_count += 1;
@@ -335,6 +435,15 @@ pub async fn delete_user(
                     tracing::error!("Failed to delete file info for user {}: {}", u.username, e);
                 }
 
+                // Delete user's API tokens
+                if let Err(e) = api_token::Entity::delete_many()
+                    .filter(api_token::Column::Username.eq(&u.username))
+                    .exec(&*db)
+                    .await
+                {
+                    tracing::error!("Failed to delete API tokens for user {}: {}", u.username, e);
+                }
+
                 // Delete user directory
                 let user_dir = state.config.root_dir.join(&u.username);
                 if let Err(e) = tokio::fs::remove_dir_all(&user_dir).await {
@@ -344,13 +453,13 @@ pub async fn delete_user(
                     }
                 }
                 // Log success
-                log_operation(&current_user.username, OP_DELETE_USER, &op_desc, OP_SUCCESS, None);
+                log_admin_operation(&current_user.username, OpType::DeleteUser, &op_desc, OP_SUCCESS, None);
             }
             Err(e) => {
                 tracing::error!("Failed to delete user {}: {}", u.username, e);
                 error_count += 1;
                 // Log failure
-                log_operation(&current_user.username, OP_DELETE_USER, &op_desc, OP_FAILED, None);
+                log_admin_operation(&current_user.username, OpType::DeleteUser, &op_desc, OP_FAILED, None);
             }
         }
     }
@@ -360,17 +469,19 @@ pub async fn delete_user(
 }
 
 /// POST /api/user/update
+#[utoipa::path(
+    post,
+    path = "/api/user/update",
+    tag = "user",
+    request_body = UpdateUserRequest,
+    responses((status = 200, body = BoolCodeResponse)),
+)]
 pub async fn update_user(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Json(req): Json<UpdateUserRequest>,
 ) -> Json<BoolCodeResponse> {
-    // Permission check: only admin can update other users
-    if !can_manage_users(&current_user) && req.id != current_user.id {
-        return Json(BoolCodeResponse::error("权限不足，仅管理员可修改其他用户"));
-    }
-
     let existing = user::Entity::find_by_id(req.id).one(&*db).await;
 
     let old_user = match existing {
@@ -382,6 +493,26 @@ pub async fn update_user(
         }
     };
 
+    // Permission check: only admins of the department can update other users,
+    // and move them to departments they administer
+    let scope = manage_scope(&db, &current_user).await;
+    let allowed = if scope == AdminScope::Global {
+        true
+    } else {
+        let own = req.id == current_user.id;
+        (own || can_manage_user(&state, &current_user, &scope, &old_user).await)
+            && (req.department_id == old_user.department_id || scope.covers(req.department_id))
+            && req.username == old_user.username
+    };
+    if !allowed {
+        abuse::record_denied(&current_user.username);
+        return Json(BoolCodeResponse::error("权限不足，仅管理员可修改其他用户"));
+    }
+    if !may_give(&state, &current_user, req.role.as_deref(), req.permissions.as_deref()).await {
+        abuse::record_denied(&current_user.username);
+        return Json(BoolCodeResponse::error("权限不足，不能分配该角色或权限"));
+    }
+
     let password = if let Some(new_pwd) = req.password {
         if !new_pwd.is_empty() {
             match bcrypt::hash(&new_pwd, 12) {
@@ -417,6 +548,7 @@ pub async fn update_user(
         quota: Set(quota),
         last_login: Set(old_user.last_login),
         permissions: Set(old_user.permissions), // Preserve existing permissions
+        account_type: Set(old_user.account_type),
     };
 
     match update_model.update(&*db).await {
@@ -442,7 +574,7 @@ pub async fn update_user(
 
             // Log operation
             let op_desc = format!("所属部门: {}, 用户名: {}", dept_name, req.username);
-            log_operation(&current_user.username, OP_UPDATE_USER, &op_desc, OP_SUCCESS, None);
+            log_admin_operation(&current_user.username, OpType::UpdateUser, &op_desc, OP_SUCCESS, None);
             Json(BoolCodeResponse::success("success"))
         }
         Err(e) => {
@@ -453,6 +585,13 @@ pub async fn update_user(
 }
 
 /// GET /api/user/query - Get users by department ID
+#[utoipa::path(
+    get,
+    path = "/api/user/query",
+    tag = "user",
+    params(DepartmentIdQuery),
+    responses((status = 200, body = ApiResponse<Vec<UserResponse>>)),
+)]
 pub async fn get_users_by_dept(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
@@ -462,6 +601,7 @@ pub async fn get_users_by_dept(
     let dept_name = get_department_name(&*db, query.department_id).await;
     match user::Entity::find()
         .filter(user::Column::DepartmentId.eq(query.department_id))
+        .filter(user::Column::AccountType.ne(user::ACCOUNT_TYPE_SERVICE))
         .order_by_asc(user::Column::Id)
         .all(&*db)
         .await
@@ -485,7 +625,7 @@ pub async fn get_users_by_dept(
 
             // Log operation
             let op_desc = format!("所属部门: {}", dept_name);
-            log_operation(&current_user.username, OP_QUERY_USER, &op_desc, OP_SUCCESS, None);
+            log_operation(&current_user.username, OpType::QueryUser, &op_desc, OP_SUCCESS, None);
             Json(ApiResponse::success(response))
         }
         Err(e) => {
@@ -495,7 +635,113 @@ pub async fn get_users_by_dept(
     }
 }
 
+/// GET /api/user/search - Search users across all departments
+///
+/// Matches username, full name, email and phone (case-insensitive).
+#[utoipa::path(
+    get,
+    path = "/api/user/search",
+    tag = "user",
+    params(UserSearchQuery),
+    responses((status = 200, body = ApiResponse<UserSearchResponse>)),
+)]
+pub async fn search_users(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<UserSearchQuery>,
+) -> Json<ApiResponse<UserSearchResponse>> {
+    let scope = manage_scope(&db, &current_user).await;
+    if scope.is_none() {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足"));
+    }
+
+    let keyword = query.q.trim();
+    let page = query.page.max(1);
+    let page_size = query.page_size.clamp(1, 100);
+
+    let mut select = user::Entity::find()
+        .filter(user::Column::AccountType.ne(user::ACCOUNT_TYPE_SERVICE));
+    if let AdminScope::Department { ids, .. } = &scope {
+        select = select.filter(user::Column::DepartmentId.is_in(ids.clone()));
+    }
+    if !keyword.is_empty() {
+        let pattern = format!("%{}%", escape_like(&keyword.to_lowercase()));
+        let mut cond = Condition::any();
+        for col in [
+            user::Column::Username,
+            user::Column::FullName,
+            user::Column::Email,
+            user::Column::Phone,
+        ] {
+            cond = cond.add(
+                Expr::expr(Func::lower(Expr::col(col)))
+                    .like(LikeExpr::new(pattern.clone()).escape('\\')),
+            );
+        }
+        select = select.filter(cond);
+    }
+
+    let paginator = select
+        .order_by_asc(user::Column::Username)
+        .paginate(&*db, page_size);
+
+    let total = match paginator.num_items().await {
+        Ok(total) => total,
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    let users = match paginator.fetch_page(page - 1).await {
+        Ok(users) => users,
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return Json(ApiResponse::error(500, "internal error"));
+        }
+    };
+
+    let perm_enforcer = state.get_perm().await;
+    let mut response: Vec<UserResponse> = Vec::new();
+    for u in users {
+        let (role, direct_permissions) = if let Some(ref enforcer) = perm_enforcer {
+            let role = enforcer.get_user_role(&u.username).await.ok().flatten();
+            let perms = enforcer.get_direct_permissions(&u.username).await.unwrap_or_default();
+            (role, perms)
+        } else {
+            (None, Vec::new())
+        };
+        let effective_quota = get_effective_quota(&db, u.department_id, u.quota.clone()).await;
+        response.push(UserResponse::from_model_with_role(u, role, direct_permissions, effective_quota));
+    }
+
+    let op_desc = format!("搜索用户: {}", keyword);
+    log_operation(&current_user.username, OpType::QueryUser, &op_desc, OP_SUCCESS, None);
+    Json(ApiResponse::success(UserSearchResponse { users: response, total }))
+}
+
+/// Escape LIKE wildcards so user input is matched literally
+fn escape_like(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
 /// GET /api/user/info - Get user by username
+#[utoipa::path(
+    get,
+    path = "/api/user/info",
+    tag = "user",
+    params(UsernameQuery),
+    responses((status = 200, body = ApiResponse<UserResponse>)),
+)]
 pub async fn get_user_by_username(
     State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
@@ -533,14 +779,23 @@ pub async fn get_user_by_username(
 }
 
 /// POST /api/user/enable
+#[utoipa::path(
+    post,
+    path = "/api/user/enable",
+    tag = "user",
+    request_body = Vec<UserStatusItem>,
+    responses((status = 200, body = BoolCodeResponse)),
+)]
 pub async fn enable_user(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Json(users): Json<Vec<UserStatusItem>>,
 ) -> Json<BoolCodeResponse> {
     // Permission check: only admin can enable users
-    if !can_manage_users(&current_user) {
+    let scope = manage_scope(&db, &current_user).await;
+    if scope.is_none() {
+        abuse::record_denied(&current_user.username);
         return Json(BoolCodeResponse::error("权限不足，仅管理员可启用用户"));
     }
 
@@ -548,22 +803,28 @@ pub async fn enable_user(
     let mut error_count = 0;
 
     for u in users {
+        let op_desc = format!("用户名: {}", u.username);
+        if !may_manage(&state, &db, &current_user, &scope, u.id, &u.username).await {
+            abuse::record_denied(&current_user.username);
+            error_count += 1;
+            log_admin_operation(&current_user.username, OpType::EnableUser, &op_desc, OP_FAILED, None);
+            continue;
+        }
         let update = user::ActiveModel {
             id: Set(u.id),
             status: Set(1),
             ..Default::default()
         };
 
-        let op_desc = format!("用户名: {}", u.username);
         match update.update(&*db).await {
             Ok(_) => {
                 success_count += 1;
-                log_operation(&current_user.username, OP_ENABLE_USER, &op_desc, OP_SUCCESS, None);
+                log_admin_operation(&current_user.username, OpType::EnableUser, &op_desc, OP_SUCCESS, None);
             }
             Err(e) => {
                 tracing::error!("Failed to enable user {}: {}", u.username, e);
                 error_count += 1;
-                log_operation(&current_user.username, OP_ENABLE_USER, &op_desc, OP_FAILED, None);
+                log_admin_operation(&current_user.username, OpType::EnableUser, &op_desc, OP_FAILED, None);
             }
         }
     }
@@ -572,15 +833,75 @@ pub async fn enable_user(
     Json(BoolCodeResponse::success(message))
 }
 
+/// POST /api/user/unlock - Lift the lockout of accounts locked by failed logins
+#[utoipa::path(
+    post,
+    path = "/api/user/unlock",
+    tag = "user",
+    request_body = Vec<UserStatusItem>,
+    responses((status = 200, body = BoolCodeResponse)),
+)]
+pub async fn unlock_user(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(users): Json<Vec<UserStatusItem>>,
+) -> Json<BoolCodeResponse> {
+    // Permission check: only admin can unlock users
+    let scope = manage_scope(&db, &current_user).await;
+    if scope.is_none() {
+        abuse::record_denied(&current_user.username);
+        return Json(BoolCodeResponse::error("权限不足，仅管理员可解锁用户"));
+    }
+
+    let mut success_count = 0;
+    let mut error_count = 0;
+
+    for u in users {
+        let op_desc = format!("用户名: {}", u.username);
+        if !may_manage(&state, &db, &current_user, &scope, u.id, &u.username).await {
+            abuse::record_denied(&current_user.username);
+            error_count += 1;
+            log_admin_operation(&current_user.username, OpType::UnlockUser, &op_desc, OP_FAILED, None);
+            continue;
+        }
+        match lockout::unlock(&db, &u.username).await {
+            Ok(true) => {
+                success_count += 1;
+                log_admin_operation(&current_user.username, OpType::UnlockUser, &op_desc, OP_SUCCESS, None);
+            }
+            // Not locked, nothing to log
+            Ok(false) => success_count += 1,
+            Err(e) => {
+                tracing::error!("Failed to unlock user {}: {}", u.username, e);
+                error_count += 1;
+                log_admin_operation(&current_user.username, OpType::UnlockUser, &op_desc, OP_FAILED, None);
+            }
+        }
+    }
+
+    let message = format!("成功解锁{}个用户, 失败{}个", success_count, error_count);
+    Json(BoolCodeResponse::success(message))
+}
+
 /// POST /api/user/disable
+#[utoipa::path(
+    post,
+    path = "/api/user/disable",
+    tag = "user",
+    request_body = Vec<UserStatusItem>,
+    responses((status = 200, body = BoolCodeResponse)),
+)]
 pub async fn disable_user(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Json(users): Json<Vec<UserStatusItem>>,
 ) -> Json<BoolCodeResponse> {
     // Permission check: only admin can disable users
-    if !can_manage_users(&current_user) {
+    let scope = manage_scope(&db, &current_user).await;
+    if scope.is_none() {
+        abuse::record_denied(&current_user.username);
         return Json(BoolCodeResponse::error("权限不足，仅管理员可禁用用户"));
     }
 
@@ -588,22 +909,28 @@ pub async fn disable_user(
     let mut error_count = 0;
 
     for u in users {
+        let op_desc = format!("用户名: {}", u.username);
+        if !may_manage(&state, &db, &current_user, &scope, u.id, &u.username).await {
+            abuse::record_denied(&current_user.username);
+            error_count += 1;
+            log_admin_operation(&current_user.username, OpType::DisableUser, &op_desc, OP_FAILED, None);
+            continue;
+        }
         let update = user::ActiveModel {
             id: Set(u.id),
             status: Set(2),
             ..Default::default()
         };
 
-        let op_desc = format!("用户名: {}", u.username);
         match update.update(&*db).await {
             Ok(_) => {
                 success_count += 1;
-                log_operation(&current_user.username, OP_DISABLE_USER, &op_desc, OP_SUCCESS, None);
+                log_admin_operation(&current_user.username, OpType::DisableUser, &op_desc, OP_SUCCESS, None);
             }
             Err(e) => {
                 tracing::error!("Failed to disable user {}: {}", u.username, e);
                 error_count += 1;
-                log_operation(&current_user.username, OP_DISABLE_USER, &op_desc, OP_FAILED, None);
+                log_admin_operation(&current_user.username, OpType::DisableUser, &op_desc, OP_FAILED, None);
             }
         }
     }
@@ -613,6 +940,13 @@ pub async fn disable_user(
 }
 
 /// POST /api/user/change-password
+#[utoipa::path(
+    post,
+    path = "/api/user/change-password",
+    tag = "user",
+    request_body = ChangePasswordRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
 pub async fn change_password(
     State(_state): State<AppState>,
     Extension(db): Extension<DbConn>,
@@ -653,7 +987,7 @@ pub async fn change_password(
     match update.update(&*db).await {
         Ok(_) => {
             // Log operation
-            log_operation(&current_user.username, OP_UPDATE_PASSWORD, "修改密码", OP_SUCCESS, None);
+            log_operation(&current_user.username, OpType::UpdatePassword, "修改密码", OP_SUCCESS, None);
             Json(ApiResponse::success_msg("success"))
         }
         Err(e) => {
@@ -664,12 +998,26 @@ pub async fn change_password(
 }
 
 /// POST /api/user/reset-password - Admin resets user password
+#[utoipa::path(
+    post,
+    path = "/api/user/reset-password",
+    tag = "user",
+    request_body = ResetPasswordRequest,
+    responses((status = 200, body = BoolCodeResponse)),
+)]
 pub async fn reset_password(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
     Extension(db): Extension<DbConn>,
     Extension(current_user): Extension<CurrentUser>,
     Json(req): Json<ResetPasswordRequest>,
 ) -> Json<BoolCodeResponse> {
+    // Permission check: only admins of the user's department can reset passwords
+    let scope = manage_scope(&db, &current_user).await;
+    if scope.is_none() || !may_manage(&state, &db, &current_user, &scope, req.id, &req.username).await {
+        abuse::record_denied(&current_user.username);
+        return Json(BoolCodeResponse::error("权限不足，仅管理员可重置密码"));
+    }
+
     // Hash the new password
     let new_hash = match bcrypt::hash(&req.password, 12) {
         Ok(h) => h,
@@ -689,7 +1037,7 @@ pub async fn reset_password(
         Ok(_) => {
             // Log operation
             let op_desc = format!("用户名: {}", req.username);
-            log_operation(&current_user.username, OP_UPDATE_PASSWORD, &op_desc, OP_SUCCESS, None);
+            log_admin_operation(&current_user.username, OpType::UpdatePassword, &op_desc, OP_SUCCESS, None);
             Json(BoolCodeResponse::success("密码修改成功"))
         }
         Err(e) => {
@@ -723,7 +1071,7 @@ fn get_department_names(db: &sea_orm::DatabaseConnection, id: i64) -> std::pin::
 }
 
 /// Helper function to get department name (wrapper for easier use)
-async fn get_department_name(db: &sea_orm::DatabaseConnection, id: i64) -> String {
+pub(crate) async fn get_department_name(db: &sea_orm::DatabaseConnection, id: i64) -> String {
     get_department_names(db, id).await
 }
 
@@ -738,57 +1086,74 @@ fn normalize_quota(quota: Option<String>) -> Option<String> {
     })
 }
 
-/// Resolve effective quota (user overrides department, department inherits parent)
-async fn get_effective_quota(
-    db: &sea_orm::DatabaseConnection,
-    department_id: i64,
-    user_quota: Option<String>,
-) -> Option<String> {
-    if user_quota.is_some() {
-        return user_quota;
-    }
-
-    use crate::entity::department;
-    let mut current_id = department_id;
-
-    while current_id != 0 {
-        match department::Entity::find_by_id(current_id).one(db).await {
-            Ok(Some(dept)) => {
-                if dept.quota.is_some() {
-                    return dept.quota;
-                }
-                current_id = dept.parent_id;
-            }
-            _ => break,
-        }
-    }
-
-    None
+/// Query of a user avatar
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AvatarQuery {
+    /// "svg" for a generated avatar as SVG instead of PNG
+    pub format: Option<String>,
 }
 
 /// GET /api/user/avatar/:username - Get user avatar
+///
+/// Serves the uploaded avatar if there is one, otherwise an initials avatar
+/// generated from the user's name, as PNG or, on request, SVG.
+#[utoipa::path(
+    get,
+    path = "/api/user/avatar/{username}",
+    tag = "user",
+    params(("username" = String, Path), AvatarQuery),
+    responses((status = 200, description = "PNG avatar; a generated one is SVG with format=svg", content_type = "image/png")),
+)]
 pub async fn get_user_avatar(
     State(state): State<AppState>,
-    Extension(_db): Extension<DbConn>,
+    Extension(db): Extension<DbConn>,
     Path(username): Path<String>,
+    Query(query): Query<AvatarQuery>,
 ) -> impl IntoResponse {
     let avatar_path = state.config.root_dir.join("avatar").join(&username).join("avatar.png");
 
-    // Check if avatar exists
+    // Fall back to a generated initials avatar
     if !avatar_path.exists() {
-        // Create default avatar
-        if let Err(e) = create_default_avatar(&state.config.root_dir, &username).await {
-            tracing::error!("Failed to create default avatar: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                [(header::CONTENT_TYPE, "application/json")],
-                Body::from(r#"{"error": "internal error"}"#),
-            ).into_response();
-        }
+        let full_name = match user::Entity::find()
+            .filter(user::Column::Username.eq(&username))
+            .one(&*db)
+            .await
+        {
+            Ok(Some(u)) => u.full_name,
+            Ok(None) => String::new(),
+            Err(e) => {
+                tracing::error!("Database error: {}", e);
+                String::new()
+            }
+        };
+
+        let (content_type, body) = if query.format.as_deref() == Some("svg") {
+            ("image/svg+xml", Body::from(create_default_avatar(&username, &full_name)))
+        } else {
+            ("image/png", Body::from(create_default_avatar_png(&username, &full_name)))
+        };
+        return Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CACHE_CONTROL, "public, max-age=3600")
+            .body(body)
+            .unwrap();
     }
 
-    // Read avatar file
-    match tokio::fs::read(&avatar_path).await {
+    // Read avatar file, kept in memory with the hot cache on
+    let cached = match tokio::fs::metadata(&avatar_path).await {
+        Ok(metadata) => hot_cache::read(&state.config.hot_cache, hot_cache::AVATARS, &username, &avatar_path, &metadata)
+            .await
+            .ok()
+            .flatten(),
+        Err(_) => None,
+    };
+    let data = match cached {
+        Some(cached) => Ok(cached.data.clone()),
+        None => tokio::fs::read(&avatar_path).await.map(Into::into),
+    };
+    match data {
         Ok(data) => {
             Response::builder()
                 .status(StatusCode::OK)
@@ -808,7 +1173,24 @@ pub async fn get_user_avatar(
     }
 }
 
+/// Multipart form of an avatar upload (documentation only)
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct AvatarForm {
+    username: String,
+    /// PNG image
+    #[schema(format = Binary, value_type = String)]
+    avatar: Vec<u8>,
+}
+
 /// POST /api/user/upload/avatar - Upload user avatar
+#[utoipa::path(
+    post,
+    path = "/api/user/upload/avatar",
+    tag = "user",
+    request_body(content = AvatarForm, content_type = "multipart/form-data"),
+    responses((status = 200, body = ApiResponse<serde_json::Value>)),
+)]
 pub async fn upload_user_avatar(
     State(state): State<AppState>,
     Extension(_db): Extension<DbConn>,
@@ -858,6 +1240,7 @@ pub async fn upload_user_avatar(
         tracing::error!("Failed to save avatar: {}", e);
         return Json(ApiResponse::error(500, "保存头像失败"));
     }
+    hot_cache::invalidate(hot_cache::AVATARS, &username);
 
     Json(ApiResponse::success(serde_json::json!({
         "large": format!("/api/user/avatar/{}", username)
@@ -865,6 +1248,13 @@ pub async fn upload_user_avatar(
 }
 
 /// DELETE /api/user/avatar/:username - Delete user avatar
+#[utoipa::path(
+    delete,
+    path = "/api/user/avatar/{username}",
+    tag = "user",
+    params(("username" = String, Path)),
+    responses((status = 200, body = ApiMessage)),
+)]
 pub async fn delete_user_avatar(
     State(state): State<AppState>,
     Extension(_db): Extension<DbConn>,
@@ -883,94 +1273,172 @@ pub async fn delete_user_avatar(
             return Json(ApiResponse::error(500, "删除头像失败"));
         }
     }
+    hot_cache::invalidate(hot_cache::AVATARS, &username);
 
     Json(ApiResponse::success_msg("success"))
 }
 
-/// Create a default avatar with random color
-async fn create_default_avatar(root_dir: &std::path::Path, username: &str) -> std::io::Result<()> {
-    let avatar_dir = root_dir.join("avatar").join(username);
-    let avatar_path = avatar_dir.join("avatar.png");
-
-    // Create directory
-    tokio::fs::create_dir_all(&avatar_dir).await?;
-
-    // Generate random color
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let seed = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_nanos() as u64;
-
-    // Simple random number generator
-    let r = ((seed >> 16) & 0xFF) as u8;
-    let g = ((seed >> 8) & 0xFF) as u8;
-    let b = (seed & 0xFF) as u8;
-
-    // Create a simple 150x150 PNG with solid color
-    // Using a minimal PNG structure
-    let png_data = create_solid_color_png(150, 150, r, g, b);
-
-    tokio::fs::write(&avatar_path, &png_data).await?;
-    Ok(())
+/// Background colors for generated avatars
+const AVATAR_COLORS: [[u8; 3]; 12] = [
+    [0xF5, 0x6A, 0x00], [0x72, 0x65, 0xE6], [0xFF, 0xBF, 0x00], [0x00, 0xA2, 0xAE],
+    [0x18, 0x90, 0xFF], [0x52, 0xC4, 0x1A], [0xEB, 0x2F, 0x96], [0xFA, 0x54, 0x1C],
+    [0x13, 0xC2, 0xC2], [0x2F, 0x54, 0xEB], [0x72, 0x2E, 0xD1], [0xA0, 0xD9, 0x11],
+];
+
+/// Side of generated avatars in pixels
+const AVATAR_SIZE: u32 = 150;
+
+/// Create a default SVG avatar showing the user's initials
+///
+/// The background color is derived from the username hash, so the avatar is
+/// the same on every request and across restarts.
+fn create_default_avatar(username: &str, full_name: &str) -> String {
+    let [r, g, b] = avatar_color(username);
+    let initials = avatar_initials(username, full_name)
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="150" height="150" viewBox="0 0 150 150"><rect width="150" height="150" fill="#{:02X}{:02X}{:02X}"/><text x="50%" y="50%" dy=".35em" text-anchor="middle" fill="#FFFFFF" font-family="Helvetica, Arial, 'PingFang SC', 'Microsoft YaHei', sans-serif" font-size="60">{}</text></svg>"##,
+        r, g, b, initials
+    )
 }
 
-/// Create a minimal PNG with solid color
-fn create_solid_color_png(width: u32, height: u32, r: u8, g: u8, b: u8) -> Vec<u8> {
-    use std::io::Write;
+/// Create a default PNG avatar showing the user's initials
+///
+/// Drawn in the built-in pixel font of the watermarks, which has Latin
+/// letters and digits only: names it can't draw, such as CJK names, show
+/// the username's initials instead, or no text if those can't be drawn
+/// either.
+fn create_default_avatar_png(username: &str, full_name: &str) -> Vec<u8> {
+    let [r, g, b] = avatar_color(username);
+    let mut image = image::RgbImage::from_pixel(AVATAR_SIZE, AVATAR_SIZE, image::Rgb([r, g, b]));
+
+    let drawable = |s: &String| !s.is_empty() && s.chars().all(font::has_glyph);
+    let text = Some(avatar_initials(username, full_name))
+        .filter(drawable)
+        .or_else(|| Some(avatar_initials(username, "")).filter(drawable));
+    if let Some(text) = text {
+        let glyphs: Vec<_> = text.chars().map(font::glyph).collect();
+        // One pixel of the font between glyphs
+        let columns = glyphs.len() * (font::WIDTH + 1) - 1;
+        let scale = (AVATAR_SIZE as usize / 2 / columns).min(AVATAR_SIZE as usize * 2 / 5 / font::HEIGHT);
+        let left = (AVATAR_SIZE as usize - columns * scale) / 2;
+        let top = (AVATAR_SIZE as usize - font::HEIGHT * scale) / 2;
+        for (i, glyph) in glyphs.iter().enumerate() {
+            for (y, bits) in glyph.iter().enumerate() {
+                for x in (0..font::WIDTH).filter(|x| bits >> (font::WIDTH - 1 - x) & 1 == 1) {
+                    let (px, py) = (left + (i * (font::WIDTH + 1) + x) * scale, top + y * scale);
+                    for (dx, dy) in (0..scale).flat_map(|dx| (0..scale).map(move |dy| (dx, dy))) {
+                        image.put_pixel((px + dx) as u32, (py + dy) as u32, image::Rgb([0xFF, 0xFF, 0xFF]));
+                    }
+                }
+            }
+        }
+    }
 
-    // PNG signature
-    let mut data = vec![0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+    let mut png = std::io::Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, image::ImageFormat::Png)
+        .expect("encoding an in-memory PNG");
+    png.into_inner()
+}
 
-    // IHDR chunk
-    let mut ihdr = Vec::new();
-    ihdr.write_all(&width.to_be_bytes()).unwrap();
-    ihdr.write_all(&height.to_be_bytes()).unwrap();
-    ihdr.push(8);  // bit depth
-    ihdr.push(2);  // color type (RGB)
-    ihdr.push(0);  // compression
-    ihdr.push(0);  // filter
-    ihdr.push(0);  // interlace
+/// Pick a stable background color for the username
+fn avatar_color(username: &str) -> [u8; 3] {
+    let hash = crc32fast::hash(username.as_bytes());
+    AVATAR_COLORS[(hash as usize) % AVATAR_COLORS.len()]
+}
 
-    write_png_chunk(&mut data, b"IHDR", &ihdr);
+/// Whether `c` is written in a CJK script (Han, Kana or Hangul)
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{2E80}'..='\u{2FDF}'     // CJK radicals
+        | '\u{3040}'..='\u{30FF}'   // Hiragana, Katakana
+        | '\u{3100}'..='\u{31BF}'   // Bopomofo, Hangul compatibility jamo
+        | '\u{31F0}'..='\u{31FF}'   // Katakana extensions
+        | '\u{3400}'..='\u{4DBF}'   // CJK extension A
+        | '\u{4E00}'..='\u{9FFF}'   // CJK unified ideographs
+        | '\u{AC00}'..='\u{D7AF}'   // Hangul syllables
+        | '\u{F900}'..='\u{FAFF}'   // CJK compatibility ideographs
+        | '\u{20000}'..='\u{3134F}' // CJK extensions B-G
+    )
+}
 
-    // IDAT chunk (image data)
-    let mut raw_data = Vec::new();
-    for _ in 0..height {
-        raw_data.push(0); // filter byte
-        for _ in 0..width {
-            raw_data.push(r);
-            raw_data.push(g);
-            raw_data.push(b);
-        }
+/// Get the letters shown on a generated avatar
+///
+/// CJK names use the last two characters (the given name), other names use
+/// the first letter of up to two words. Falls back to the username.
+fn avatar_initials(username: &str, full_name: &str) -> String {
+    let name = if full_name.trim().is_empty() { username.trim() } else { full_name.trim() };
+
+    if name.chars().any(is_cjk) {
+        let chars: Vec<char> = name.chars().filter(|c| is_cjk(*c)).collect();
+        let start = chars.len().saturating_sub(2);
+        return chars[start..].iter().collect();
     }
 
-    // Compress with deflate
-    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&raw_data, 6);
-    write_png_chunk(&mut data, b"IDAT", &compressed);
+    let initials: String = name
+        .split(|c: char| c.is_whitespace() || c == '.' || c == '_' || c == '-')
+        .filter_map(|word| word.chars().next())
+        .take(2)
+        .collect();
 
-    // IEND chunk
-    write_png_chunk(&mut data, b"IEND", &[]);
-
-    data
+    initials.to_uppercase()
 }
 
-/// Write a PNG chunk
-fn write_png_chunk(data: &mut Vec<u8>, chunk_type: &[u8; 4], chunk_data: &[u8]) {
-    use std::io::Write;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Length
-    data.write_all(&(chunk_data.len() as u32).to_be_bytes()).unwrap();
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("abc"), "abc");
+        assert_eq!(escape_like("50%_off\\"), "50\\%\\_off\\\\");
+    }
 
-    // Type
-    data.write_all(chunk_type).unwrap();
+    #[test]
+    fn test_avatar_initials() {
+        assert_eq!(avatar_initials("jdoe", "John Doe"), "JD");
+        assert_eq!(avatar_initials("alice", ""), "A");
+        assert_eq!(avatar_initials("zhang.san", ""), "ZS");
+        assert_eq!(avatar_initials("zhangsan", "张三丰"), "三丰");
+        assert_eq!(avatar_initials("lisi", "李"), "李");
+        assert_eq!(avatar_initials("jgarcia", "José García"), "JG");
+        assert_eq!(avatar_initials("ivan", "Иван Петров"), "ИП");
+        assert_eq!(avatar_initials("kim", "김민준"), "민준");
+        assert_eq!(avatar_initials("sato", "さとう"), "とう");
+    }
 
-    // Data
-    data.write_all(chunk_data).unwrap();
+    #[test]
+    fn test_default_avatar_png() {
+        for (username, full_name) in [("jdoe", "John Doe"), ("zhangsan", "张三丰"), ("李四", "")] {
+            let png = create_default_avatar_png(username, full_name);
+            let image = image::load_from_memory_with_format(&png, image::ImageFormat::Png).unwrap().to_rgb8();
+            assert_eq!(image.dimensions(), (AVATAR_SIZE, AVATAR_SIZE));
+            assert_eq!(image.get_pixel(0, 0).0, avatar_color(username));
+        }
+        // The initials are drawn in white, unless nothing can be drawn
+        let has_text = |png: Vec<u8>| {
+            let image = image::load_from_memory(&png).unwrap().to_rgb8();
+            image.pixels().any(|p| p.0 == [0xFF, 0xFF, 0xFF])
+        };
+        assert!(has_text(create_default_avatar_png("jdoe", "John Doe")));
+        assert!(has_text(create_default_avatar_png("zhangsan", "张三丰")));
+        assert!(!has_text(create_default_avatar_png("李四", "")));
+    }
+
+    #[test]
+    fn test_avatar_color_is_deterministic() {
+        assert_eq!(avatar_color("admin"), avatar_color("admin"));
+        assert!(AVATAR_COLORS.contains(&avatar_color("someone")));
+    }
 
-    // CRC32
-    let mut crc_data = chunk_type.to_vec();
-    crc_data.extend_from_slice(chunk_data);
-    let crc = crc32fast::hash(&crc_data);
-    data.write_all(&crc.to_be_bytes()).unwrap();
+    #[test]
+    fn test_default_avatar_escapes_name() {
+        let svg = create_default_avatar("x", "<a> b");
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("&lt;B"));
+    }
 }
@@ -0,0 +1,466 @@
+//! Bulk user import
+//!
+//! `/api/user/import` takes a CSV file with one user per row, e.g. exported
+//! from a spreadsheet. Every row is checked before anything is created; valid
+//! rows become users with a generated initial password, which is returned
+//! once in the report together with the reason each rejected row failed.
+//!
+//! Departments and roles must already exist: unlike the HR sync, an import
+//! never creates them, so a typo doesn't leave a stray department behind.
+
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::{Multipart, State},
+    response::Json,
+    Extension,
+};
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::entity::op_log::OpType;
+use crate::entity::{department, user};
+use crate::handlers::abuse;
+use crate::handlers::audit::service::log_admin_operation;
+use crate::handlers::file::is_space_owner;
+use crate::handlers::hr_sync::split_csv_line;
+use crate::handlers::quota::parse_quota;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::permission::PermissionEnforcer;
+use crate::routes::ApiResponse;
+use crate::state::AppState;
+
+const OP_SUCCESS: &str = "成功";
+
+/// Rows accepted in one file
+const MAX_ROWS: usize = 5000;
+
+/// Length of generated passwords
+const PASSWORD_LEN: usize = 12;
+
+/// Characters of generated passwords, without look-alikes such as 0/O and 1/l
+const PASSWORD_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnpqrstuvwxyz23456789";
+
+/// One user as given in the file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportRow {
+    /// Line in the file, counting the header
+    pub line: usize,
+    pub username: String,
+    pub full_name: String,
+    /// Department path, e.g. "总部/研发部"
+    pub department: String,
+    pub role: Option<String>,
+    pub quota: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+}
+
+/// Result of one row
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportRowResult {
+    pub line: usize,
+    pub username: String,
+    pub success: bool,
+    pub message: String,
+    /// Initial password of a created user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+/// Result of an import
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ImportReport {
+    pub created: usize,
+    pub failed: usize,
+    pub rows: Vec<ImportRowResult>,
+}
+
+impl ImportReport {
+    fn fail(&mut self, row: &ImportRow, message: impl Into<String>) {
+        self.failed += 1;
+        self.rows.push(ImportRowResult {
+            line: row.line,
+            username: row.username.clone(),
+            success: false,
+            message: message.into(),
+            password: None,
+        });
+    }
+}
+
+/// Multipart form of a user import (documentation only)
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct ImportForm {
+    /// CSV with a header row: username, fullName, department, role, quota,
+    /// and optionally email and phone. Chinese headers (用户名, 姓名, 部门,
+    /// 角色, 配额, 邮箱, 电话) work as well.
+    #[schema(format = Binary, value_type = String)]
+    file: Vec<u8>,
+}
+
+/// Parse the rows of an import file
+pub fn parse_csv(text: &str) -> Result<Vec<ImportRow>, String> {
+    // Spreadsheets save UTF-8 with a byte order mark
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut lines = text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+    let header: Vec<String> = match lines.next() {
+        Some((_, h)) => split_csv_line(h).into_iter().map(|s| s.trim().to_lowercase()).collect(),
+        None => return Err("文件为空".to_string()),
+    };
+    let col = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+    let username_col = col(&["username", "用户名"]).ok_or("缺少用户名列")?;
+    let full_name_col = col(&["fullname", "full_name", "姓名"]);
+    let dept_col = col(&["department", "部门"]).ok_or("缺少部门列")?;
+    let role_col = col(&["role", "角色"]);
+    let quota_col = col(&["quota", "配额"]);
+    let email_col = col(&["email", "邮箱"]);
+    let phone_col = col(&["phone", "电话"]);
+
+    let mut rows = Vec::new();
+    for (index, line) in lines {
+        if rows.len() == MAX_ROWS {
+            return Err(format!("一次最多导入{}个用户", MAX_ROWS));
+        }
+        let fields = split_csv_line(line);
+        let get = |idx: Option<usize>| {
+            idx.and_then(|i| fields.get(i))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        let non_empty = |s: String| if s.is_empty() { None } else { Some(s) };
+        rows.push(ImportRow {
+            line: index + 1,
+            username: get(Some(username_col)),
+            full_name: get(full_name_col),
+            department: get(Some(dept_col)),
+            role: non_empty(get(role_col)),
+            quota: non_empty(get(quota_col)),
+            email: non_empty(get(email_col)),
+            phone: non_empty(get(phone_col)),
+        });
+    }
+    Ok(rows)
+}
+
+/// Why a username can't be used, None if it can
+pub(crate) fn check_username(username: &str) -> Option<&'static str> {
+    if username.is_empty() {
+        return Some("用户名不能为空");
+    }
+    if username.chars().count() > 32 {
+        return Some("用户名不能超过32个字符");
+    }
+    // It names the user's directory
+    if username.starts_with('.')
+        || username.contains(['/', '\\'])
+        || username.chars().any(char::is_control)
+        || is_space_owner(username)
+    {
+        return Some("用户名无效");
+    }
+    None
+}
+
+/// A random initial password
+pub fn generate_password() -> String {
+    let bytes = [uuid::Uuid::new_v4().into_bytes(), uuid::Uuid::new_v4().into_bytes()].concat();
+    bytes[..PASSWORD_LEN]
+        .iter()
+        .map(|b| PASSWORD_CHARS[*b as usize % PASSWORD_CHARS.len()] as char)
+        .collect()
+}
+
+/// ID of the existing department at a "a/b/c" path
+async fn find_department(
+    db: &DatabaseConnection,
+    path: &str,
+    cache: &mut HashMap<String, Option<i64>>,
+) -> Result<Option<i64>, sea_orm::DbErr> {
+    if let Some(id) = cache.get(path) {
+        return Ok(*id);
+    }
+    let mut parent_id = 0;
+    for name in path.split('/').map(str::trim).filter(|p| !p.is_empty()) {
+        let found = department::Entity::find()
+            .filter(department::Column::Name.eq(name))
+            .filter(department::Column::ParentId.eq(parent_id))
+            .one(db)
+            .await?;
+        match found {
+            Some(d) => parent_id = d.id,
+            None => {
+                cache.insert(path.to_string(), None);
+                return Ok(None);
+            }
+        }
+    }
+    let id = (parent_id > 0).then_some(parent_id);
+    cache.insert(path.to_string(), id);
+    Ok(id)
+}
+
+/// Create the valid rows as users, `operator` being the importing admin
+pub async fn import(
+    db: &DatabaseConnection,
+    perm: Option<&PermissionEnforcer>,
+    root_dir: &std::path::Path,
+    operator: &str,
+    rows: &[ImportRow],
+) -> Result<ImportReport, sea_orm::DbErr> {
+    let mut report = ImportReport::default();
+    let mut departments = HashMap::new();
+    let mut roles: HashMap<String, bool> = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut valid = Vec::new();
+
+    for row in rows {
+        if let Some(message) = check_username(&row.username) {
+            report.fail(row, message);
+            continue;
+        }
+        if !seen.insert(row.username.clone()) {
+            report.fail(row, "文件中用户名重复");
+            continue;
+        }
+        if row.full_name.chars().count() > 64 {
+            report.fail(row, "姓名不能超过64个字符");
+            continue;
+        }
+        let exists = user::Entity::find()
+            .filter(user::Column::Username.eq(&row.username))
+            .one(db)
+            .await?;
+        if exists.is_some() {
+            report.fail(row, "用户名已存在");
+            continue;
+        }
+        let Some(dept_id) = find_department(db, &row.department, &mut departments).await? else {
+            report.fail(row, format!("部门不存在: {}", row.department));
+            continue;
+        };
+        if let Some(quota) = &row.quota {
+            if parse_quota(quota).is_none() {
+                report.fail(row, format!("配额格式错误: {}", quota));
+                continue;
+            }
+        }
+        if let (Some(role), Some(perm)) = (&row.role, perm) {
+            let exists = match roles.get(role) {
+                Some(exists) => *exists,
+                None => {
+                    let exists = perm.role_exists(role).await.unwrap_or(false);
+                    roles.insert(role.clone(), exists);
+                    exists
+                }
+            };
+            if !exists {
+                report.fail(row, format!("角色不存在: {}", role));
+                continue;
+            }
+        }
+        valid.push((row, dept_id));
+    }
+
+    // bcrypt is slow on purpose, hash the passwords of all rows at once
+    let passwords: Vec<String> = valid.iter().map(|_| generate_password()).collect();
+    let hashes = futures::future::join_all(passwords.iter().cloned().map(|password| {
+        tokio::task::spawn_blocking(move || bcrypt::hash(password, 12))
+    }))
+    .await;
+
+    for (((row, dept_id), password), hash) in valid.into_iter().zip(passwords).zip(hashes) {
+        let hash = match hash {
+            Ok(Ok(hash)) => hash,
+            Ok(Err(e)) => {
+                tracing::error!("Failed to hash password: {}", e);
+                report.fail(row, "密码加密失败");
+                continue;
+            }
+            Err(e) => {
+                tracing::error!("Hashing worker panicked: {}", e);
+                report.fail(row, "密码加密失败");
+                continue;
+            }
+        };
+        let dept_name = row
+            .department
+            .split('/')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .collect::<Vec<_>>()
+            .join("/");
+        let new_user = user::ActiveModel {
+            username: Set(row.username.clone()),
+            password: Set(hash),
+            full_name: Set(row.full_name.clone()),
+            phone: Set(row.phone.clone()),
+            email: Set(row.email.clone()),
+            department_id: Set(dept_id),
+            dept_name: Set(dept_name.clone()),
+            status: Set(0),
+            quota: Set(row.quota.clone()),
+            last_login: Set(0),
+            ..Default::default()
+        };
+        if let Err(e) = new_user.insert(db).await {
+            tracing::error!("Failed to create user {}: {}", row.username, e);
+            report.fail(row, "创建用户失败");
+            continue;
+        }
+
+        if let Err(e) = tokio::fs::create_dir_all(root_dir.join(&row.username)).await {
+            tracing::error!("Failed to create user directory: {}", e);
+        }
+        if let Some(perm) = perm {
+            if let Some(role) = &row.role {
+                if let Err(e) = perm.set_user_role(&row.username, Some(role)).await {
+                    tracing::error!("Failed to assign role: {}", e);
+                }
+            }
+            if let Err(e) = perm.set_user_department(&row.username, dept_id).await {
+                tracing::error!("Failed to assign department: {}", e);
+            }
+        }
+
+        let op_desc = format!("批量导入, 所属部门: {}, 用户名: {}", dept_name, row.username);
+        log_admin_operation(operator, OpType::CreateUser, &op_desc, OP_SUCCESS, None);
+        report.created += 1;
+        report.rows.push(ImportRowResult {
+            line: row.line,
+            username: row.username.clone(),
+            success: true,
+            message: "success".to_string(),
+            password: Some(password),
+        });
+    }
+
+    report.rows.sort_by_key(|r| r.line);
+    Ok(report)
+}
+
+/// POST /api/user/import - Create users from a CSV file
+#[utoipa::path(
+    post,
+    path = "/api/user/import",
+    tag = "user",
+    request_body(content = ImportForm, content_type = "multipart/form-data"),
+    responses((status = 200, body = ApiResponse<ImportReport>)),
+)]
+pub async fn import_users(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    mut multipart: Multipart,
+) -> Json<ApiResponse<ImportReport>> {
+    // Permission check: only admin can add users
+    if !current_user.can_contacts() {
+        abuse::record_denied(&current_user.username);
+        return Json(ApiResponse::error(403, "权限不足，仅管理员可添加用户"));
+    }
+
+    let mut data = None;
+    while let Some(field) = multipart.next_field().await.ok().flatten() {
+        if field.name() == Some("file") {
+            data = field.bytes().await.ok();
+        }
+    }
+    let Some(data) = data else {
+        return Json(ApiResponse::error(400, "请选择要导入的文件"));
+    };
+    let Ok(text) = std::str::from_utf8(&data) else {
+        return Json(ApiResponse::error(400, "文件须为 UTF-8 编码的 CSV"));
+    };
+    let rows = match parse_csv(text) {
+        Ok(rows) => rows,
+        Err(message) => return Json(ApiResponse::error(400, message)),
+    };
+
+    let perm = state.get_perm().await;
+    match import(&db, perm.as_ref(), &state.config.root_dir, &current_user.username, &rows).await {
+        Ok(report) => Json(ApiResponse::success(report)),
+        Err(e) => {
+            tracing::error!("Failed to import users: {}", e);
+            Json(ApiResponse::error(500, "导入用户失败"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestEnv;
+
+    #[test]
+    fn test_parse_csv() {
+        let text = "\u{feff}用户名,姓名,部门,角色,配额\n\
+                    zhangsan,张三,总部/研发部,user,10GB\n\
+                    \n\
+                    lisi,\"李, 四\",总部,,\n";
+        let rows = parse_csv(text).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].line, 2);
+        assert_eq!(rows[0].department, "总部/研发部");
+        assert_eq!(rows[0].quota.as_deref(), Some("10GB"));
+        assert_eq!(rows[1].line, 4);
+        assert_eq!(rows[1].full_name, "李, 四");
+        assert_eq!(rows[1].role, None);
+        assert!(parse_csv("fullName,department\n").is_err());
+        assert!(parse_csv("").is_err());
+    }
+
+    #[test]
+    fn test_generate_password() {
+        let password = generate_password();
+        assert_eq!(password.len(), PASSWORD_LEN);
+        assert!(password.bytes().all(|b| PASSWORD_CHARS.contains(&b)));
+        assert_ne!(password, generate_password());
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let env = TestEnv::new().await;
+        let dept = department::ActiveModel {
+            name: Set("总部".to_string()),
+            level: Set(1),
+            parent_id: Set(0),
+            parent_name: Set(String::new()),
+            ..Default::default()
+        }
+        .insert(&env.db)
+        .await
+        .unwrap();
+        let text = "username,fullName,department,quota\n\
+                    alice,Alice,总部,5GB\n\
+                    alice,Alice,总部,\n\
+                    bob,Bob,财务部,\n\
+                    carol,Carol,总部,lots\n\
+                    ../x,X,总部,\n";
+        let rows = parse_csv(text).unwrap();
+        let report = import(&env.db, None, &env.config.root_dir, "admin", &rows).await.unwrap();
+        assert_eq!((report.created, report.failed), (1, 4));
+        let messages: Vec<_> = report.rows.iter().map(|r| (r.line, r.success)).collect();
+        assert_eq!(messages, vec![(2, true), (3, false), (4, false), (5, false), (6, false)]);
+
+        let password = report.rows[0].password.clone().unwrap();
+        let alice = user::Entity::find()
+            .filter(user::Column::Username.eq("alice"))
+            .one(&env.db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(alice.department_id, dept.id);
+        assert_eq!(alice.quota.as_deref(), Some("5GB"));
+        assert!(bcrypt::verify(&password, &alice.password).unwrap());
+        assert!(env.config.root_dir.join("alice").is_dir());
+
+        // Already there the second time
+        let report = import(&env.db, None, &env.config.root_dir, "admin", &rows[..1]).await.unwrap();
+        assert_eq!(report.rows[0].message, "用户名已存在");
+
+        env.close().await;
+    }
+}
@@ -0,0 +1,576 @@
+//! Watched folders
+//!
+//! Users watch folders of their own tree or of the spaces they belong to.
+//! Every change of a file is published on an in-process bus ([`publish`]) by
+//! the code making it: the file API, WebDAV, copy/move tasks, the editor and
+//! trash restore. Publishing drops the changed files from the
+//! [hot file cache](crate::handlers::hot_cache). A single subscriber
+//! ([`start`]) matches changes against the watches of the tree, records a
+//! [`watch_event`] for each watcher and pushes it to the watcher's WebSocket
+//! clients. `digest` marks the watches to summarize in email digests.
+//!
+//! Changes the watchers make in the web UI themselves are not recorded, those
+//! made from their sync clients (WebDAV) are. [Folder hooks](crate::handlers::folder_hook)
+//! subscribe to the same bus.
+
+use axum::{
+    extract::{Query, State},
+    response::Json,
+    Extension,
+};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+use tokio::sync::broadcast;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::entity::{watch, watch_event};
+use crate::handlers::file::{locate, resolve_in_user_root};
+use crate::handlers::hot_cache;
+use crate::handlers::path::normalize;
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::routes::{ApiMessage, ApiResponse};
+use crate::state::AppState;
+use crate::ws::{WsMessage, HUB};
+
+/// Most events returned at once
+const MAX_EVENTS: u64 = 200;
+
+/// What happened to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    Renamed,
+    /// Moved away from or to the path
+    Moved,
+    Restored,
+}
+
+impl ChangeKind {
+    pub fn code(self) -> &'static str {
+        match self {
+            ChangeKind::Created => "create",
+            ChangeKind::Modified => "update",
+            ChangeKind::Deleted => "delete",
+            ChangeKind::Renamed => "rename",
+            ChangeKind::Moved => "move",
+            ChangeKind::Restored => "restore",
+        }
+    }
+}
+
+/// Where a change was made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Client {
+    /// The web UI and API
+    Web,
+    /// WebDAV, used by sync clients
+    WebDav,
+}
+
+impl Client {
+    pub fn code(self) -> &'static str {
+        match self {
+            Client::Web => "web",
+            Client::WebDav => "webdav",
+        }
+    }
+}
+
+/// A change of a file
+#[derive(Debug, Clone)]
+pub struct Change {
+    /// Owner of the tree
+    pub owner: String,
+    /// Path inside the tree
+    pub path: String,
+    /// User making the change
+    pub actor: String,
+    pub kind: ChangeKind,
+    pub client: Client,
+}
+
+static BUS: LazyLock<broadcast::Sender<Change>> = LazyLock::new(|| broadcast::channel(1024).0);
+
+/// Publish a change of `path` in the tree of `owner`
+pub fn publish(owner: &str, path: &str, actor: &str, kind: ChangeKind, client: Client) {
+    hot_cache::invalidate(owner, path);
+    // Nobody listens before start() or in tests
+    let _ = BUS.send(Change {
+        owner: owner.to_string(),
+        path: normalize(path),
+        actor: actor.to_string(),
+        kind,
+        client,
+    });
+}
+
+/// Receive the changes published from now on
+pub fn subscribe() -> broadcast::Receiver<Change> {
+    BUS.subscribe()
+}
+
+/// Path of the change as the watcher sees it, None if outside the folder
+fn shown_path(watch: &watch::Model, path: &str) -> Option<String> {
+    let rest = if watch.path == "/" {
+        path
+    } else if path == watch.path {
+        ""
+    } else {
+        path.strip_prefix(&watch.path).filter(|rest| rest.starts_with('/'))?
+    };
+    let shown = format!("{}{}", watch.shown_path.trim_end_matches('/'), rest);
+    Some(if shown.is_empty() { "/".to_string() } else { shown })
+}
+
+/// Record `change` for the users watching it, returning the new events
+pub async fn deliver(db: &DatabaseConnection, change: &Change) -> Result<Vec<watch_event::Model>, DbErr> {
+    let watches = watch::Entity::find()
+        .filter(watch::Column::Owner.eq(&change.owner))
+        .all(db)
+        .await?;
+    let now = chrono::Utc::now().timestamp();
+    let mut events = Vec::new();
+    for watch in watches {
+        if watch.username == change.actor && change.client == Client::Web {
+            continue;
+        }
+        let Some(path) = shown_path(&watch, &change.path) else {
+            continue;
+        };
+        let event = watch_event::ActiveModel {
+            watch_id: Set(watch.id),
+            username: Set(watch.username.clone()),
+            path: Set(path),
+            kind: Set(change.kind.code().to_string()),
+            actor: Set(change.actor.clone()),
+            client: Set(change.client.code().to_string()),
+            create_time: Set(now),
+            read: Set(false),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+        HUB.send(watch.user_id, WsMessage::WatchActivity(serde_json::json!(EventResponse::from(event.clone()))));
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Start recording the changes of watched folders
+pub fn start(state: AppState) {
+    let mut rx = subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let change = match rx.recv().await {
+                Ok(change) => change,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Watch delivery lagged, {} changes dropped", n);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let Some(db) = state.get_db().await else {
+                continue;
+            };
+            if let Err(e) = deliver(&db, &change).await {
+                tracing::error!("Failed to record change of {}: {}", change.path, e);
+            }
+        }
+    });
+}
+
+/// Watch request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct WatchRequest {
+    pub path: String,
+    /// Include the folder in the email digest
+    #[serde(default)]
+    pub digest: bool,
+}
+
+/// Unwatch request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UnwatchRequest {
+    pub id: i64,
+}
+
+/// A watched folder
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WatchResponse {
+    pub id: i64,
+    pub path: String,
+    pub digest: bool,
+    #[serde(rename = "createTime")]
+    pub create_time: i64,
+}
+
+impl From<watch::Model> for WatchResponse {
+    fn from(m: watch::Model) -> Self {
+        Self { id: m.id, path: m.shown_path, digest: m.digest, create_time: m.create_time }
+    }
+}
+
+/// A change in a watched folder
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EventResponse {
+    pub id: i64,
+    #[serde(rename = "watchId")]
+    pub watch_id: i64,
+    pub path: String,
+    /// create, update, delete, rename, move or restore
+    pub kind: String,
+    pub actor: String,
+    /// web or webdav
+    pub client: String,
+    pub time: i64,
+    pub read: bool,
+}
+
+impl From<watch_event::Model> for EventResponse {
+    fn from(m: watch_event::Model) -> Self {
+        Self {
+            id: m.id,
+            watch_id: m.watch_id,
+            path: m.path,
+            kind: m.kind,
+            actor: m.actor,
+            client: m.client,
+            time: m.create_time,
+            read: m.read,
+        }
+    }
+}
+
+/// Query parameters of the activity list
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventQuery {
+    /// Only unread events
+    #[serde(default)]
+    pub unread: bool,
+    /// Events older than this ID, for paging
+    pub before: Option<i64>,
+    pub limit: Option<u64>,
+}
+
+/// Mark events read request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ReadRequest {
+    /// Events to mark, all of the user's if not set
+    pub ids: Option<Vec<i64>>,
+}
+
+/// POST /api/file/watch/add
+#[utoipa::path(
+    post,
+    path = "/api/file/watch/add",
+    tag = "file",
+    request_body = WatchRequest,
+    responses((status = 200, body = ApiResponse<WatchResponse>)),
+)]
+pub async fn add_watch(
+    State(state): State<AppState>,
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<WatchRequest>,
+) -> Json<ApiResponse<WatchResponse>> {
+    let location = match locate(&state, &db, &current_user, &req.path).await {
+        Ok(location) => location,
+        Err((status, error)) => return Json(ApiResponse::error(status.as_u16() as i32, error)),
+    };
+    let is_dir = resolve_in_user_root(&state.config, &location.owner, &location.path)
+        .is_some_and(|full| full.is_dir());
+    if !is_dir {
+        return Json(ApiResponse::error(404, "文件夹不存在"));
+    }
+
+    let path = normalize(&location.path);
+    let result = async {
+        let existing = watch::Entity::find()
+            .filter(watch::Column::UserId.eq(current_user.id))
+            .filter(watch::Column::Owner.eq(&location.owner))
+            .filter(watch::Column::Path.eq(&path))
+            .one(&*db)
+            .await?;
+        match existing {
+            Some(existing) => {
+                let mut active: watch::ActiveModel = existing.into();
+                active.digest = Set(req.digest);
+                active.update(&*db).await
+            }
+            None => {
+                watch::ActiveModel {
+                    user_id: Set(current_user.id),
+                    username: Set(current_user.username.clone()),
+                    owner: Set(location.owner.clone()),
+                    path: Set(path.clone()),
+                    shown_path: Set(normalize(&req.path)),
+                    digest: Set(req.digest),
+                    create_time: Set(chrono::Utc::now().timestamp()),
+                    ..Default::default()
+                }
+                .insert(&*db)
+                .await
+            }
+        }
+    }
+    .await;
+
+    match result {
+        Ok(watch) => Json(ApiResponse::success(watch.into())),
+        Err(e) => {
+            tracing::error!("Failed to watch {}: {}", req.path, e);
+            Json(ApiResponse::error(500, "关注失败"))
+        }
+    }
+}
+
+/// POST /api/file/watch/remove
+#[utoipa::path(
+    post,
+    path = "/api/file/watch/remove",
+    tag = "file",
+    request_body = UnwatchRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
+pub async fn remove_watch(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<UnwatchRequest>,
+) -> Json<ApiResponse<()>> {
+    let result = async {
+        let removed = watch::Entity::delete_many()
+            .filter(watch::Column::Id.eq(req.id))
+            .filter(watch::Column::UserId.eq(current_user.id))
+            .exec(&*db)
+            .await?;
+        if removed.rows_affected > 0 {
+            watch_event::Entity::delete_many()
+                .filter(watch_event::Column::WatchId.eq(req.id))
+                .exec(&*db)
+                .await?;
+        }
+        Ok::<_, DbErr>(removed.rows_affected)
+    }
+    .await;
+
+    match result {
+        Ok(0) => Json(ApiResponse::error(404, "关注不存在")),
+        Ok(_) => Json(ApiResponse::success_msg("已取消关注")),
+        Err(e) => {
+            tracing::error!("Failed to unwatch {}: {}", req.id, e);
+            Json(ApiResponse::error(500, "取消关注失败"))
+        }
+    }
+}
+
+/// GET /api/file/watch/list
+#[utoipa::path(
+    get,
+    path = "/api/file/watch/list",
+    tag = "file",
+    responses((status = 200, body = ApiResponse<Vec<WatchResponse>>)),
+)]
+pub async fn list_watches(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> Json<ApiResponse<Vec<WatchResponse>>> {
+    let result = watch::Entity::find()
+        .filter(watch::Column::UserId.eq(current_user.id))
+        .order_by_asc(watch::Column::ShownPath)
+        .all(&*db)
+        .await;
+    match result {
+        Ok(watches) => Json(ApiResponse::success(watches.into_iter().map(Into::into).collect())),
+        Err(e) => {
+            tracing::error!("Failed to list watches: {}", e);
+            Json(ApiResponse::error(500, "查询关注失败"))
+        }
+    }
+}
+
+/// GET /api/file/watch/events - Changes in watched folders, newest first
+#[utoipa::path(
+    get,
+    path = "/api/file/watch/events",
+    tag = "file",
+    params(EventQuery),
+    responses((status = 200, body = ApiResponse<Vec<EventResponse>>)),
+)]
+pub async fn list_events(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Query(query): Query<EventQuery>,
+) -> Json<ApiResponse<Vec<EventResponse>>> {
+    let mut select = watch_event::Entity::find()
+        .filter(watch_event::Column::Username.eq(&current_user.username));
+    if query.unread {
+        select = select.filter(watch_event::Column::Read.eq(false));
+    }
+    if let Some(before) = query.before {
+        select = select.filter(watch_event::Column::Id.lt(before));
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_EVENTS);
+    match select.order_by_desc(watch_event::Column::Id).limit(limit).all(&*db).await {
+        Ok(events) => Json(ApiResponse::success(events.into_iter().map(Into::into).collect())),
+        Err(e) => {
+            tracing::error!("Failed to list watch events: {}", e);
+            Json(ApiResponse::error(500, "查询动态失败"))
+        }
+    }
+}
+
+/// POST /api/file/watch/read - Mark changes as read
+#[utoipa::path(
+    post,
+    path = "/api/file/watch/read",
+    tag = "file",
+    request_body = ReadRequest,
+    responses((status = 200, body = ApiMessage)),
+)]
+pub async fn mark_read(
+    Extension(db): Extension<DbConn>,
+    Extension(current_user): Extension<CurrentUser>,
+    Json(req): Json<ReadRequest>,
+) -> Json<ApiResponse<()>> {
+    let mut update = watch_event::Entity::update_many()
+        .col_expr(watch_event::Column::Read, sea_orm::sea_query::Expr::value(true))
+        .filter(watch_event::Column::Username.eq(&current_user.username));
+    if let Some(ids) = req.ids {
+        update = update.filter(watch_event::Column::Id.is_in(ids));
+    }
+    match update.exec(&*db).await {
+        Ok(_) => Json(ApiResponse::success_msg("success")),
+        Err(e) => {
+            tracing::error!("Failed to mark watch events read: {}", e);
+            Json(ApiResponse::error(500, "操作失败"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestApp, TestEnv};
+
+    fn change(owner: &str, path: &str, actor: &str, client: Client) -> Change {
+        Change {
+            owner: owner.to_string(),
+            path: normalize(path),
+            actor: actor.to_string(),
+            kind: ChangeKind::Created,
+            client,
+        }
+    }
+
+    #[test]
+    fn test_shown_path() {
+        let watch = |path: &str, shown_path: &str| watch::Model {
+            id: 1,
+            user_id: 1,
+            username: "alice".to_string(),
+            owner: "dept_1".to_string(),
+            path: path.to_string(),
+            shown_path: shown_path.to_string(),
+            digest: false,
+            create_time: 0,
+        };
+        let docs = watch("/docs", "/部门/docs");
+        assert_eq!(shown_path(&docs, "/docs/a.txt").as_deref(), Some("/部门/docs/a.txt"));
+        assert_eq!(shown_path(&docs, "/docs").as_deref(), Some("/部门/docs"));
+        assert_eq!(shown_path(&docs, "/docs2/a.txt"), None);
+        assert_eq!(shown_path(&docs, "/a.txt"), None);
+        let root = watch("/", "/");
+        assert_eq!(shown_path(&root, "/docs/a.txt").as_deref(), Some("/docs/a.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_deliver() {
+        let env = TestEnv::new().await;
+        for (user_id, username) in [(1, "alice"), (2, "bob")] {
+            watch::ActiveModel {
+                user_id: Set(user_id),
+                username: Set(username.to_string()),
+                owner: Set("dept_1".to_string()),
+                path: Set("/docs".to_string()),
+                shown_path: Set("/部门/docs".to_string()),
+                digest: Set(false),
+                create_time: Set(0),
+                ..Default::default()
+            }
+            .insert(&env.db)
+            .await
+            .unwrap();
+        }
+
+        let events = deliver(&env.db, &change("dept_1", "docs/a.txt", "alice", Client::Web)).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].username, "bob");
+        assert_eq!(events[0].path, "/部门/docs/a.txt");
+        assert_eq!(events[0].kind, "create");
+
+        // Changes from sync clients reach the one making them too
+        let events = deliver(&env.db, &change("dept_1", "docs/b.txt", "alice", Client::WebDav)).await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(deliver(&env.db, &change("dept_1", "other/a.txt", "carol", Client::Web)).await.unwrap().is_empty());
+        assert!(deliver(&env.db, &change("dept_2", "docs/a.txt", "carol", Client::Web)).await.unwrap().is_empty());
+        env.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_watch_api() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let res: serde_json::Value = admin
+            .post_json("/api/file/mkdir", &serde_json::json!({ "parentPath": "/", "name": "watched" }))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(res["code"], true);
+
+        let watch = |path: &'static str| {
+            let admin = &admin;
+            async move {
+                let body = serde_json::json!({ "path": path });
+                admin.post_json("/api/file/watch/add", &body).await
+            }
+        };
+        let res: serde_json::Value = watch("/missing").await.json().await.unwrap();
+        assert_eq!(res["code"], false);
+        let res: serde_json::Value = watch("/watched").await.json().await.unwrap();
+        assert_eq!(res["code"], true);
+        let id = res["data"]["id"].as_i64().unwrap();
+        // Watching again keeps one watch
+        watch("/watched").await;
+        let res: serde_json::Value = admin.get("/api/file/watch/list").await.json().await.unwrap();
+        assert_eq!(res["data"].as_array().unwrap().len(), 1);
+
+        deliver(&app.env.db, &change("admin", "watched/a.txt", "admin", Client::WebDav)).await.unwrap();
+        let res: serde_json::Value = admin.get("/api/file/watch/events?unread=true").await.json().await.unwrap();
+        assert_eq!(res["data"][0]["path"], "/watched/a.txt");
+        assert_eq!(res["data"][0]["client"], "webdav");
+        admin.post_json("/api/file/watch/read", &serde_json::json!({})).await;
+        let res: serde_json::Value = admin.get("/api/file/watch/events?unread=true").await.json().await.unwrap();
+        assert!(res["data"].as_array().unwrap().is_empty());
+
+        let res: serde_json::Value = admin
+            .post_json("/api/file/watch/remove", &serde_json::json!({ "id": id }))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(res["code"], true);
+        let res: serde_json::Value = admin.get("/api/file/watch/events").await.json().await.unwrap();
+        assert!(res["data"].as_array().unwrap().is_empty());
+        app.close().await;
+    }
+}
@@ -0,0 +1,790 @@
+//! WebDAV handlers
+//!
+//! Exposes each user's storage root under `/webdav` so desktop clients can
+//! mount the disk. Requests authenticate with HTTP basic auth against the
+//! `user` table, and writes keep `file_info` in sync the same way the web
+//! file handlers do.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use dashmap::DashMap;
+use futures::StreamExt;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use std::path::Path;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+use crate::config::Config;
+use crate::entity::{file_info, user};
+use crate::entity::op_log::OpType;
+use crate::filename;
+use crate::handlers::abuse;
+use crate::handlers::dir_version;
+use crate::handlers::dlp::{self, Verdict};
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::file::{
+    get_user_path, is_safe_filename, resolve_dir_id, resolve_in_user_root,
+};
+use crate::handlers::lockout;
+use crate::handlers::quota;
+use crate::handlers::sensitive;
+use crate::handlers::tag;
+use crate::handlers::tiering;
+use crate::handlers::traffic;
+use crate::handlers::upload_limit;
+use crate::handlers::trash::{ensure_dir_id, move_to_trash, register_tree};
+use crate::handlers::watch::{self, ChangeKind, Client};
+use crate::metrics;
+use crate::middleware::rate_limit::client_ip;
+use crate::mime;
+use crate::service::{FileError, FileService};
+use crate::state::AppState;
+
+/// URL prefix the WebDAV tree is mounted under
+pub const WEBDAV_PREFIX: &str = "/webdav";
+
+/// Methods advertised in `Allow`
+const ALLOWED_METHODS: &str = "OPTIONS, PROPFIND, GET, HEAD, PUT, MKCOL, DELETE, MOVE, COPY";
+
+/// Suffix of in-progress uploads, hidden from listings
+const UPLOAD_TMP_SUFFIX: &str = ".davtmp";
+
+/// How long verified credentials are cached, clients authenticate every request
+const AUTH_CACHE_SECS: i64 = 300;
+
+const OP_SUCCESS: &str = "成功";
+
+/// Characters escaped in hrefs
+const HREF_ENCODE: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'&')
+    .add(b'\'')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+/// Recently verified credentials: digest of (username, password, stored hash) -> expiry
+///
+/// Keyed on the stored hash as well so a password change invalidates the entry.
+static VERIFIED: std::sync::LazyLock<DashMap<String, i64>> =
+    std::sync::LazyLock::new(DashMap::new);
+
+/// Per-request context
+struct DavContext<'a> {
+    config: &'a Config,
+    db: &'a DatabaseConnection,
+    username: &'a str,
+}
+
+impl DavContext<'_> {
+    fn full_path(&self, path: &str) -> std::path::PathBuf {
+        get_user_path(self.config, self.username).join(path)
+    }
+
+    fn files(&self) -> FileService<'_> {
+        FileService::new(self.config, self.db, self.username).via(Client::WebDav)
+    }
+
+    /// Bring `path` back from cold storage before it's read
+    async fn recall(&self, path: &str) -> anyhow::Result<()> {
+        tiering::recall(self.db, self.username, &get_user_path(self.config, self.username), path).await?;
+        Ok(())
+    }
+}
+
+/// ANY /webdav/*path
+pub async fn handle(State(state): State<AppState>, request: Request<Body>) -> Response {
+    let Some(db) = state.get_db().await else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let ip = client_ip(request.headers(), peer, state.config.rate_limit.trust_proxy).map(|ip| ip.to_string());
+    let Some(username) = authenticate(&db, &state.config, request.headers(), ip.as_deref()).await else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"datadisk\"")],
+        )
+            .into_response();
+    };
+
+    let Some(path) = dav_path(request.uri().path()) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    if let Err(e) = fs::create_dir_all(get_user_path(&state.config, &username)).await {
+        tracing::error!("Failed to create user directory: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    if resolve_in_user_root(&state.config, &username, &path).is_none() {
+        abuse::record_denied(&username);
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let ctx = DavContext {
+        config: &state.config,
+        db: &db,
+        username: &username,
+    };
+    let method = request.method().clone();
+    let result = match method.as_str() {
+        "OPTIONS" => Ok(options()),
+        "PROPFIND" => propfind(&ctx, &path, request.headers()).await,
+        "GET" | "HEAD" => get(&ctx, &path, method == Method::HEAD).await,
+        "PUT" => {
+            let max_size = upload_limit::limit(&state, &username).await;
+            put(&ctx, &path, request.into_body(), max_size).await
+        }
+        "MKCOL" => mkcol(&ctx, &path).await,
+        "DELETE" => delete(&ctx, &path).await,
+        "COPY" | "MOVE" => copy_move(&ctx, &path, request.headers(), method.as_str() == "COPY").await,
+        _ => Ok((StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOWED_METHODS)]).into_response()),
+    };
+
+    result.unwrap_or_else(|e| {
+        tracing::error!("WebDAV {} /{} failed: {}", method, path, e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })
+}
+
+/// Check basic auth credentials, returning the username
+///
+/// Failed attempts count towards the account lockout, a locked account is
+/// refused even with cached credentials.
+async fn authenticate(
+    db: &DatabaseConnection,
+    config: &Config,
+    headers: &HeaderMap,
+    ip: Option<&str>,
+) -> Option<String> {
+    let (username, password) = basic_credentials(headers)?;
+
+    let db_user = match user::Entity::find()
+        .filter(user::Column::Username.eq(&username))
+        .one(db)
+        .await
+    {
+        Ok(Some(u)) => u,
+        Ok(None) => return None,
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return None;
+        }
+    };
+    // Service accounts authenticate with API tokens only, 2 = disabled
+    if db_user.is_service_account() || db_user.status == 2 {
+        return None;
+    }
+
+    match lockout::locked_until(db, &username).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            tracing::warn!("WebDAV login refused: account locked - {}", username);
+            return None;
+        }
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return None;
+        }
+    }
+
+    let key = hex::encode(Sha256::digest(
+        format!("{}\0{}\0{}", username, password, db_user.password).as_bytes(),
+    ));
+    let now = chrono::Utc::now().timestamp();
+    if VERIFIED.get(&key).is_some_and(|expiry| *expiry > now) {
+        return Some(username);
+    }
+
+    let hash = db_user.password.clone();
+    let valid = tokio::task::spawn_blocking(move || bcrypt::verify(&password, &hash).unwrap_or(false))
+        .await
+        .unwrap_or(false);
+    if !valid {
+        tracing::warn!("WebDAV login failed: {}", username);
+        if let Err(e) = lockout::record_failure(db, &config.lockout, &username, ip).await {
+            tracing::error!("Failed to record failed login of {}: {}", username, e);
+        }
+        return None;
+    }
+
+    if let Err(e) = lockout::clear(db, &username).await {
+        tracing::error!("Failed to clear failed logins of {}: {}", username, e);
+    }
+    VERIFIED.retain(|_, expiry| *expiry > now);
+    VERIFIED.insert(key, now + AUTH_CACHE_SECS);
+    Some(username)
+}
+
+/// Parse `Authorization: Basic ...` into (username, password)
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Convert a request path (or `Destination` URL) to a path relative to the user root
+///
+/// Returns None for paths outside `/webdav` or with unsafe segments.
+fn dav_path(uri_path: &str) -> Option<String> {
+    // Destination headers carry absolute URLs
+    let uri_path = match uri_path.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/')?..],
+        None => uri_path,
+    };
+    let rest = uri_path.strip_prefix(WEBDAV_PREFIX)?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+
+    let decoded = percent_decode_str(rest).decode_utf8().ok()?;
+    filename::check_path(&decoded).ok()?;
+    let mut segments = Vec::new();
+    for segment in decoded.split('/').filter(|s| !s.is_empty()) {
+        if !is_safe_filename(segment) {
+            return None;
+        }
+        segments.push(segment);
+    }
+    Some(segments.join("/"))
+}
+
+/// Whether the last segment of a path is acceptable as the name of a new file
+///
+/// Names are kept as sent, so clients find the file under the name they used.
+fn is_valid_new_name(path: &str) -> bool {
+    filename::check_new_name(split_path(path).1).is_ok()
+}
+
+/// Split a relative path into (parent path, name)
+fn split_path(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+/// Build the href of a relative path, directories end with '/'
+fn href(path: &str, is_dir: bool) -> String {
+    let mut href = format!("{}/{}", WEBDAV_PREFIX, utf8_percent_encode(path, HREF_ENCODE));
+    if is_dir && !path.is_empty() {
+        href.push('/');
+    }
+    href
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn modify_time(metadata: &std::fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// HTTP date (RFC 1123) of a Unix timestamp
+fn http_date(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+fn etag(size: u64, modified: i64) -> String {
+    format!("\"{:x}-{:x}\"", size, modified)
+}
+
+/// One `<D:response>` element of a PROPFIND multistatus
+fn prop_response(path: &str, metadata: &std::fs::Metadata) -> String {
+    let (_, name) = split_path(path);
+    let modified = modify_time(metadata);
+
+    let mut props = format!(
+        "<D:displayname>{}</D:displayname><D:getlastmodified>{}</D:getlastmodified>",
+        xml_escape(name),
+        http_date(modified)
+    );
+    if metadata.is_dir() {
+        props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        props.push_str(&format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>{}</D:getcontenttype><D:getetag>{}</D:getetag>",
+            metadata.len(),
+            xml_escape(mime::from_name(name)),
+            xml_escape(&etag(metadata.len(), modified))
+        ));
+    }
+
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        xml_escape(&href(path, metadata.is_dir())),
+        props
+    )
+}
+
+fn options() -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::ALLOW, ALLOWED_METHODS),
+            (header::HeaderName::from_static("dav"), "1"),
+            (header::HeaderName::from_static("ms-author-via"), "DAV"),
+        ],
+    )
+        .into_response()
+}
+
+/// PROPFIND - list properties of a resource and, for Depth 1, its children
+async fn propfind(ctx: &DavContext<'_>, path: &str, headers: &HeaderMap) -> anyhow::Result<Response> {
+    let full = ctx.full_path(path);
+    let Ok(metadata) = fs::metadata(&full).await else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let mut body = String::from(r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:">"#);
+    body.push_str(&prop_response(path, &metadata));
+
+    // Depth infinity is treated as 1 to keep listings bounded
+    let depth = headers.get("depth").and_then(|v| v.to_str().ok()).unwrap_or("1");
+    if metadata.is_dir() && depth != "0" {
+        let mut entries = fs::read_dir(&full).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.ends_with(UPLOAD_TMP_SUFFIX) {
+                continue;
+            }
+            let Ok(child_meta) = entry.metadata().await else {
+                continue;
+            };
+            let child_path = if path.is_empty() { name } else { format!("{}/{}", path, name) };
+            body.push_str(&prop_response(&child_path, &child_meta));
+        }
+    }
+    body.push_str("</D:multistatus>");
+
+    Ok(Response::builder()
+        .status(StatusCode::MULTI_STATUS)
+        .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+        .body(Body::from(body))?)
+}
+
+/// GET / HEAD - download a file
+async fn get(ctx: &DavContext<'_>, path: &str, head: bool) -> anyhow::Result<Response> {
+    let full = ctx.full_path(path);
+    if !head {
+        ctx.recall(path).await?;
+    }
+    let Ok(metadata) = fs::metadata(&full).await else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    if metadata.is_dir() {
+        return Ok((StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOWED_METHODS)]).into_response());
+    }
+    // Images and PDFs of sensitive folders are only served watermarked
+    if !head && sensitive::watermark_kind(ctx.db, ctx.username, path).await?.is_some() {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+
+    let modified = modify_time(&metadata);
+    let builder = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, mime::detect_file(&full).await)
+        .header(header::CONTENT_LENGTH, metadata.len())
+        .header(header::LAST_MODIFIED, http_date(modified))
+        .header(header::ETAG, etag(metadata.len(), modified));
+
+    if head {
+        return Ok(builder.body(Body::empty())?);
+    }
+
+    let file = fs::File::open(&full).await?;
+    log_operation(ctx.username, OpType::Download, &format!("/{}", path), OP_SUCCESS, None);
+    Ok(builder.body(Body::from_stream(traffic::counted(ctx.username, ReaderStream::new(file))))?)
+}
+
+/// PUT - upload a file, replacing an existing one
+async fn put(ctx: &DavContext<'_>, path: &str, body: Body, max_size: usize) -> anyhow::Result<Response> {
+    if path.is_empty() {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+    if !is_valid_new_name(path) {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    }
+    let (parent, name) = split_path(path);
+    if filename::check_upload_type(name).is_err() {
+        return Ok(StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response());
+    }
+    let parent_full = ctx.full_path(parent);
+    if !parent_full.is_dir() {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+    let full = parent_full.join(name);
+    if full.is_dir() {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+    let existed = full.exists();
+    if existed && held(ctx, path).await? {
+        return Ok(StatusCode::LOCKED.into_response());
+    }
+    let replaced_size = fs::metadata(&full).await.map(|m| m.len() as i64).unwrap_or(0);
+    // The replaced file's space is freed by the upload
+    let quota_remaining = quota::remaining(ctx.db, ctx.config, ctx.username)
+        .await
+        .map(|r| r + replaced_size);
+
+    // Stream into a temp file next to the target so a failed upload never
+    // leaves a truncated file behind
+    let tmp_path = parent_full.join(format!(".{}{}", uuid::Uuid::new_v4(), UPLOAD_TMP_SUFFIX));
+    let mut tmp_file = fs::File::create(&tmp_path).await?;
+    let mut size: usize = 0;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                tracing::warn!("WebDAV upload aborted: {}", e);
+                drop(tmp_file);
+                let _ = fs::remove_file(&tmp_path).await;
+                return Ok(StatusCode::BAD_REQUEST.into_response());
+            }
+        };
+        size += chunk.len();
+        if size > max_size {
+            drop(tmp_file);
+            let _ = fs::remove_file(&tmp_path).await;
+            return Ok(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+        }
+        if quota_remaining.is_some_and(|r| size as i64 > r) {
+            drop(tmp_file);
+            let _ = fs::remove_file(&tmp_path).await;
+            return Ok(StatusCode::INSUFFICIENT_STORAGE.into_response());
+        }
+        if let Err(e) = tmp_file.write_all(&chunk).await {
+            drop(tmp_file);
+            let _ = fs::remove_file(&tmp_path).await;
+            return Err(e.into());
+        }
+    }
+    tmp_file.flush().await?;
+    drop(tmp_file);
+    let target = dlp::Target {
+        uploader: ctx.username,
+        owner: ctx.username,
+        prefix: "",
+        parent_path: parent,
+        name,
+        size: size as i64,
+    };
+    match dlp::screen(ctx.config, ctx.db, &target, &tmp_path).await? {
+        Verdict::Store(_) => {}
+        Verdict::Held(_) => return Ok(StatusCode::ACCEPTED.into_response()),
+        Verdict::Blocked(_) => {
+            metrics::count_upload_rejected(metrics::UploadRejection::Content);
+            return Ok(StatusCode::FORBIDDEN.into_response());
+        }
+    }
+    if let Err(e) = fs::rename(&tmp_path, &full).await {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(e.into());
+    }
+    dir_version::bump_entry(ctx.username, path);
+    let kind = if existed { ChangeKind::Modified } else { ChangeKind::Created };
+    watch::publish(ctx.username, path, ctx.username, kind, Client::WebDav);
+    if let Err(e) = tiering::discard(ctx.db, ctx.username, path).await {
+        tracing::error!("Failed to drop the cold copy of /{}: {}", path, e);
+    }
+
+    quota::add_usage(ctx.username, size as i64 - replaced_size);
+    metrics::add_upload_bytes(size as u64);
+    traffic::add_upload(ctx.username, size as u64);
+
+    // Bookkeeping
+    let parent_id = ensure_dir_id(ctx.db, ctx.username, parent).await?;
+    let now = chrono::Utc::now().timestamp();
+    let existing = file_info::Entity::find()
+        .filter(file_info::Column::Username.eq(ctx.username))
+        .filter(file_info::Column::ParentId.eq(parent_id))
+        .filter(file_info::Column::Name.eq(name))
+        .one(ctx.db)
+        .await?;
+    match existing {
+        Some(row) => {
+            let mut row: file_info::ActiveModel = row.into();
+            row.size = Set(size as i64);
+            row.modify_time = Set(now);
+            row.update(ctx.db).await?;
+        }
+        None => {
+            file_info::ActiveModel {
+                username: Set(ctx.username.to_string()),
+                name: Set(name.to_string()),
+                file_type: Set(mime::detect_file(&full).await.to_string()),
+                size: Set(size as i64),
+                parent_id: Set(parent_id),
+                create_time: Set(now),
+                modify_time: Set(now),
+                is_directory: Set(false),
+                ..Default::default()
+            }
+            .insert(ctx.db)
+            .await?;
+        }
+    }
+
+    log_operation(ctx.username, OpType::Upload, &format!("/{}", path), OP_SUCCESS, None);
+    Ok(if existed { StatusCode::NO_CONTENT } else { StatusCode::CREATED }.into_response())
+}
+
+/// MKCOL - create a directory
+async fn mkcol(ctx: &DavContext<'_>, path: &str) -> anyhow::Result<Response> {
+    let full = ctx.full_path(path);
+    if path.is_empty() || full.exists() {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+    if !is_valid_new_name(path) {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    }
+    let (parent, _) = split_path(path);
+    if !ctx.full_path(parent).is_dir() {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+
+    fs::create_dir(&full).await?;
+    dir_version::bump_entry(ctx.username, path);
+    watch::publish(ctx.username, path, ctx.username, ChangeKind::Created, Client::WebDav);
+    ensure_dir_id(ctx.db, ctx.username, path).await?;
+
+    log_operation(ctx.username, OpType::Mkdir, &format!("/{}", path), OP_SUCCESS, None);
+    Ok(StatusCode::CREATED.into_response())
+}
+
+/// DELETE - move a file or directory to the trash
+async fn delete(ctx: &DavContext<'_>, path: &str) -> anyhow::Result<Response> {
+    if path.is_empty() {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+
+    let (parent, name) = split_path(path);
+    match ctx.files().delete(parent, name).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT.into_response()),
+        Err(FileError::NotFound) => Ok(StatusCode::NOT_FOUND.into_response()),
+        Err(FileError::LegalHold(_)) => Ok(StatusCode::LOCKED.into_response()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// COPY / MOVE - copy or move a resource to the `Destination` header
+async fn copy_move(
+    ctx: &DavContext<'_>,
+    path: &str,
+    headers: &HeaderMap,
+    is_copy: bool,
+) -> anyhow::Result<Response> {
+    let Some(dest) = headers
+        .get("destination")
+        .and_then(|v| v.to_str().ok())
+        .and_then(dav_path)
+    else {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    };
+    if path.is_empty() || dest.is_empty() || dest == path {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    if dest.starts_with(&format!("{}/", path)) {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+    if !is_valid_new_name(&dest) {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    }
+    if resolve_in_user_root(ctx.config, ctx.username, &dest).is_none() {
+        abuse::record_denied(ctx.username);
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+
+    let src_full = ctx.full_path(path);
+    if !src_full.exists() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let (dest_parent, dest_name) = split_path(&dest);
+    if !ctx.full_path(dest_parent).is_dir() {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+
+    // Replace an existing destination unless `Overwrite: F`
+    let dest_full = ctx.full_path(&dest);
+    let existed = dest_full.exists();
+    if existed {
+        let overwrite = headers.get("overwrite").and_then(|v| v.to_str().ok()) != Some("F");
+        if !overwrite {
+            return Ok(StatusCode::PRECONDITION_FAILED.into_response());
+        }
+    }
+
+    if (!is_copy && held(ctx, path).await?) || (existed && held(ctx, &dest).await?) {
+        return Ok(StatusCode::LOCKED.into_response());
+    }
+
+    let copy_size = if is_copy {
+        ctx.recall(path).await?;
+        let src = src_full.clone();
+        let size = tokio::task::spawn_blocking(move || quota::path_size(&src)).await?;
+        if quota::check_quota(ctx.db, ctx.config, ctx.username, size).await.is_err() {
+            return Ok(StatusCode::INSUFFICIENT_STORAGE.into_response());
+        }
+        size
+    } else {
+        0
+    };
+
+    if existed {
+        move_to_trash(ctx.config, ctx.db, ctx.username, dest_parent, dest_name).await?;
+        ctx.files().remove_rows(dest_parent, dest_name).await?;
+    }
+
+    let dest_parent_id = ensure_dir_id(ctx.db, ctx.username, dest_parent).await?;
+    if is_copy {
+        copy_recursive(&src_full, &dest_full).await?;
+        quota::add_usage(ctx.username, copy_size);
+        register_tree(ctx.db, ctx.username, dest_parent_id, &dest_full).await?;
+    } else {
+        fs::rename(&src_full, &dest_full).await?;
+        if let Err(e) = tag::move_tags(ctx.db, ctx.username, path, &dest).await {
+            tracing::error!("Failed to move tags of /{}: {}", path, e);
+        }
+        if let Err(e) = tiering::move_stubs(ctx.db, ctx.username, path, &dest).await {
+            tracing::error!("Failed to move cold storage stubs of /{}: {}", path, e);
+        }
+        if let Err(e) = sensitive::move_marks(ctx.db, ctx.username, path, &dest).await {
+            tracing::error!("Failed to move sensitive marks of /{}: {}", path, e);
+        }
+
+        let (src_parent, src_name) = split_path(path);
+        let src_parent_id = resolve_dir_id(ctx.db, ctx.username, src_parent).await;
+        let row = file_info::Entity::find()
+            .filter(file_info::Column::Username.eq(ctx.username))
+            .filter(file_info::Column::ParentId.eq(src_parent_id))
+            .filter(file_info::Column::Name.eq(src_name))
+            .one(ctx.db)
+            .await?;
+        match row {
+            Some(row) => {
+                let mut row: file_info::ActiveModel = row.into();
+                row.parent_id = Set(dest_parent_id);
+                row.name = Set(dest_name.to_string());
+                row.update(ctx.db).await?;
+            }
+            None => register_tree(ctx.db, ctx.username, dest_parent_id, &dest_full).await?,
+        }
+    }
+
+    dir_version::bump_entry(ctx.username, &dest);
+    let kind = if is_copy { ChangeKind::Created } else { ChangeKind::Moved };
+    watch::publish(ctx.username, &dest, ctx.username, kind, Client::WebDav);
+    if !is_copy {
+        dir_version::bump_entry(ctx.username, path);
+        watch::publish(ctx.username, path, ctx.username, ChangeKind::Moved, Client::WebDav);
+    }
+
+    let op = if is_copy { OpType::Copy } else { OpType::Move };
+    log_operation(ctx.username, op, &format!("/{} -> /{}", path, dest), OP_SUCCESS, None);
+    Ok(if existed { StatusCode::NO_CONTENT } else { StatusCode::CREATED }.into_response())
+}
+
+/// Whether a legal hold keeps `path` from being changed
+async fn held(ctx: &DavContext<'_>, path: &str) -> anyhow::Result<bool> {
+    match ctx.files().check_hold(path).await {
+        Ok(()) => Ok(false),
+        Err(FileError::LegalHold(_)) => Ok(true),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Copy a file or directory tree
+async fn copy_recursive(src: &Path, dst: &Path) -> std::io::Result<()> {
+    if fs::metadata(src).await?.is_dir() {
+        fs::create_dir(dst).await?;
+        let mut entries = fs::read_dir(src).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            Box::pin(copy_recursive(&entry.path(), &dst.join(entry.file_name()))).await?;
+        }
+    } else {
+        fs::copy(src, dst).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dav_path() {
+        assert_eq!(dav_path("/webdav").as_deref(), Some(""));
+        assert_eq!(dav_path("/webdav/").as_deref(), Some(""));
+        assert_eq!(dav_path("/webdav/a/b%20c.txt").as_deref(), Some("a/b c.txt"));
+        assert_eq!(dav_path("/webdav/%E6%96%87%E6%A1%A3/").as_deref(), Some("文档"));
+        assert_eq!(dav_path("http://host:8080/webdav/a/b").as_deref(), Some("a/b"));
+        assert_eq!(dav_path("/webdav/a/../b"), None);
+        assert_eq!(dav_path("/webdav/a/%2e%2e/b"), None);
+        assert_eq!(dav_path("/webdavx/a"), None);
+        assert_eq!(dav_path("/api/file"), None);
+    }
+
+    #[test]
+    fn test_is_valid_new_name() {
+        assert!(is_valid_new_name("docs/report.txt"));
+        assert!(!is_valid_new_name("docs/CON"));
+        assert!(!is_valid_new_name("nul.txt"));
+        assert!(!is_valid_new_name("docs/name."));
+    }
+
+    #[test]
+    fn test_href() {
+        assert_eq!(href("", true), "/webdav/");
+        assert_eq!(href("a b/c", true), "/webdav/a%20b/c/");
+        assert_eq!(href("x#1.txt", false), "/webdav/x%231.txt");
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Basic dXNlcjpwYTpzcw==".parse().unwrap());
+        assert_eq!(
+            basic_credentials(&headers),
+            Some(("user".to_string(), "pa:ss".to_string()))
+        );
+
+        headers.insert(header::AUTHORIZATION, "Bearer dd_abc".parse().unwrap());
+        assert_eq!(basic_credentials(&headers), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! Leases of single-writer work
+//!
+//! Instances sharing a database coordinate work that must run on one of them
+//! at a time, such as the maintenance jobs, through the rows of
+//! `disk_job_lease`. An instance takes the lease of a job for one scheduled
+//! run ([`acquire`]), keeps renewing it while it works ([`renew`]) and
+//! releases it when done ([`release`]):
+//! - a lease is taken by a single conditional update, so of the instances
+//!   racing for the same run exactly one wins
+//! - a run taken once is not taken again, even after the lease is released,
+//!   so instances waking up a little later don't repeat it
+//! - a lease that isn't renewed expires, and the job of an instance that
+//!   died is taken over by another one at its next run
+//!
+//! The database is the only thing instances share, no clock is compared
+//! across them but for expiry, which tolerates skew far below the lease time.
+
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set,
+};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use crate::entity::job_lease;
+
+/// This instance, as named in the leases it holds: the host, process and a
+/// random suffix telling restarts apart
+pub static INSTANCE: LazyLock<String> = LazyLock::new(|| {
+    let host = hostname::get().map_or_else(|_| "localhost".to_string(), |h| h.to_string_lossy().into_owned());
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+    format!("{}:{}:{}", host, std::process::id(), &suffix[..8])
+});
+
+/// Take the lease of `name` as `holder` for the run scheduled at `slot`
+async fn acquire_as(
+    db: &DatabaseConnection,
+    name: &str,
+    holder: &str,
+    slot: i64,
+    ttl: Duration,
+    now: i64,
+) -> Result<bool, DbErr> {
+    let row = job_lease::ActiveModel {
+        name: Set(name.to_string()),
+        holder: Set(String::new()),
+        slot: Set(0),
+        acquired_at: Set(0),
+        expires_at: Set(0),
+    };
+    match job_lease::Entity::insert(row)
+        .on_conflict(OnConflict::column(job_lease::Column::Name).do_nothing().to_owned())
+        .exec(db)
+        .await
+    {
+        Ok(_) | Err(DbErr::RecordNotInserted) => {}
+        Err(e) => return Err(e),
+    }
+
+    let taken = job_lease::Entity::update_many()
+        .col_expr(job_lease::Column::Holder, Expr::value(holder))
+        .col_expr(job_lease::Column::Slot, Expr::value(slot))
+        .col_expr(job_lease::Column::AcquiredAt, Expr::value(now))
+        .col_expr(job_lease::Column::ExpiresAt, Expr::value(now + ttl.as_secs() as i64))
+        .filter(job_lease::Column::Name.eq(name))
+        .filter(job_lease::Column::ExpiresAt.lte(now))
+        .filter(job_lease::Column::Slot.lt(slot))
+        .exec(db)
+        .await?;
+    Ok(taken.rows_affected == 1)
+}
+
+/// Take the lease of `name` for the run scheduled at `slot` (a Unix
+/// timestamp), false if another instance holds it or took that run already
+pub async fn acquire(db: &DatabaseConnection, name: &str, slot: i64, ttl: Duration) -> Result<bool, DbErr> {
+    acquire_as(db, name, &INSTANCE, slot, ttl, chrono::Utc::now().timestamp()).await
+}
+
+async fn renew_as(db: &DatabaseConnection, name: &str, holder: &str, ttl: Duration, now: i64) -> Result<bool, DbErr> {
+    let renewed = job_lease::Entity::update_many()
+        .col_expr(job_lease::Column::ExpiresAt, Expr::value(now + ttl.as_secs() as i64))
+        .filter(job_lease::Column::Name.eq(name))
+        .filter(job_lease::Column::Holder.eq(holder))
+        .exec(db)
+        .await?;
+    Ok(renewed.rows_affected == 1)
+}
+
+/// Extend the lease of `name` held by this instance, false if it was lost
+pub async fn renew(db: &DatabaseConnection, name: &str, ttl: Duration) -> Result<bool, DbErr> {
+    renew_as(db, name, &INSTANCE, ttl, chrono::Utc::now().timestamp()).await
+}
+
+async fn release_as(db: &DatabaseConnection, name: &str, holder: &str) -> Result<(), DbErr> {
+    job_lease::Entity::update_many()
+        .col_expr(job_lease::Column::ExpiresAt, Expr::value(0))
+        .filter(job_lease::Column::Name.eq(name))
+        .filter(job_lease::Column::Holder.eq(holder))
+        .exec(db)
+        .await
+        .map(|_| ())
+}
+
+/// Release the lease of `name` held by this instance
+pub async fn release(db: &DatabaseConnection, name: &str) -> Result<(), DbErr> {
+    release_as(db, name, &INSTANCE).await
+}
+
+/// All leases, by name
+pub async fn all(db: &DatabaseConnection) -> Result<Vec<job_lease::Model>, DbErr> {
+    job_lease::Entity::find().all(db).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestEnv;
+
+    #[tokio::test]
+    async fn test_lease() {
+        let env = TestEnv::new().await;
+        let db = &env.db;
+        let ttl = Duration::from_secs(60);
+        let now = 1_000_000;
+
+        // One of the instances racing for a run takes it
+        assert!(acquire_as(db, "purge", "a", 100, ttl, now).await.unwrap());
+        assert!(!acquire_as(db, "purge", "b", 100, ttl, now).await.unwrap());
+        // Not again once done, by whoever wakes up late
+        release_as(db, "purge", "a").await.unwrap();
+        assert!(!acquire_as(db, "purge", "b", 100, ttl, now + 1).await.unwrap());
+        assert!(acquire_as(db, "purge", "b", 200, ttl, now + 2).await.unwrap());
+
+        // A holder that stops renewing is taken over when the lease expires
+        assert!(!acquire_as(db, "purge", "a", 300, ttl, now + 30).await.unwrap());
+        assert!(renew_as(db, "purge", "b", ttl, now + 40).await.unwrap());
+        assert!(!acquire_as(db, "purge", "a", 300, ttl, now + 70).await.unwrap());
+        assert!(acquire_as(db, "purge", "a", 300, ttl, now + 100).await.unwrap());
+        // The lost lease can't be renewed or released by its old holder
+        assert!(!renew_as(db, "purge", "b", ttl, now + 101).await.unwrap());
+        release_as(db, "purge", "b").await.unwrap();
+        assert!(!acquire_as(db, "purge", "b", 400, ttl, now + 102).await.unwrap());
+
+        // Leases are per job
+        assert!(acquire_as(db, "backup", "b", 100, ttl, now).await.unwrap());
+        let leases = all(db).await.unwrap();
+        assert_eq!(leases.len(), 2);
+        assert!(leases.iter().any(|l| l.name == "purge" && l.holder == "a" && l.slot == 300));
+        env.close().await;
+    }
+}
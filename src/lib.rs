@@ -6,17 +6,33 @@
 // Allow dead code for reserved/future-use structures in entity and error modules
 #![allow(dead_code)]
 
+pub mod charset;
 pub mod config;
 pub mod db;
 pub mod entity;
 pub mod error;
+pub mod filename;
 pub mod handlers;
+pub mod lease;
+pub mod mail;
 pub mod middleware;
+pub mod metrics;
+pub mod migration;
+pub mod mime;
+pub mod outbound;
 pub mod permission;
+pub mod repository;
 pub mod routes;
+pub mod service;
 pub mod state;
+pub mod storage;
 pub mod task;
+pub mod telemetry;
+#[cfg(any(test, feature = "test_support"))]
+pub mod testing;
+pub mod watermark;
 pub mod ws;
+pub mod zip_stream;
 
 // Re-export commonly used types
 pub use config::Config;
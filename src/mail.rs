@@ -0,0 +1,47 @@
+//! Outgoing email
+//!
+//! Plain text messages sent through the SMTP server of `[mail]`. Nothing is
+//! sent while no server is configured.
+
+use anyhow::Context;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::time::Duration;
+
+use crate::config::MailConfig;
+
+/// Time allowed for talking to the SMTP server
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether email can be sent
+pub fn enabled(config: &MailConfig) -> bool {
+    !config.smtp_host.is_empty() && !config.from.is_empty()
+}
+
+/// Send a plain text message to `to`
+pub async fn send(config: &MailConfig, to: &str, subject: &str, body: String) -> anyhow::Result<()> {
+    let message = Message::builder()
+        .from(config.from.parse().context("invalid sender address")?)
+        .to(to.parse().context("invalid recipient address")?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body)?;
+
+    let host = config.smtp_host.as_str();
+    let mut transport = match config.security.as_str() {
+        "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+    }
+    .port(config.smtp_port)
+    .timeout(Some(TIMEOUT));
+    if !config.smtp_username.is_empty() {
+        transport = transport.credentials(Credentials::new(
+            config.smtp_username.clone(),
+            config.smtp_password.clone(),
+        ));
+    }
+    transport.build().send(message).await?;
+    Ok(())
+}
@@ -5,19 +5,35 @@ use std::env;
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tracing::info;
-use tracing_subscriber::{fmt, EnvFilter};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
+mod charset;
 mod config;
 mod db;
 mod entity;
 mod error;
+mod filename;
 mod handlers;
+mod lease;
+mod mail;
 mod middleware;
+mod metrics;
+mod migration;
+mod mime;
+mod outbound;
 mod permission;
+mod repository;
 mod routes;
+mod service;
 mod state;
+mod storage;
 mod task;
+mod telemetry;
+#[cfg(any(test, feature = "test_support"))]
+mod testing;
+mod watermark;
 mod ws;
+mod zip_stream;
 
 use config::Config;
 use state::AppState;
@@ -30,6 +46,7 @@ async fn main() -> anyhow::Result<()> {
         println!("Usage: datadisk [OPTIONS]");
         println!("Options:");
         println!("  -config <path>  Path to configuration file (default: ./etc/datadisk.toml)");
+        println!("  -migrate        Apply pending database migrations and exit");
         println!("  -help, --help   Print this help message");
         return Ok(());
     }
@@ -47,26 +64,44 @@ async fn main() -> anyhow::Result<()> {
         eprintln!("Could not load config file: {}, using defaults", e);
         Config::default()
     });
+    filename::init(&config.filename);
+    handlers::archive_preview::init(&config.archive_limits);
 
     // Initialize logging
     // Priority: RUST_LOG env var > config file > default "info"
     let env_filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new(&config.log.level));
 
-    fmt::fmt()
-        .with_env_filter(env_filter)
+    let log = fmt::layer()
         .with_target(true)
         .with_thread_ids(true)
         .with_file(true)
         .with_line_number(true)
-        .init();
+        .with_filter(env_filter);
+
+    // Spans exported to the collector of [telemetry], if there is one
+    let (otel, _telemetry) = telemetry::layer(&config.telemetry)?.unzip();
+    tracing_subscriber::registry().with(log).with(otel).init();
 
     info!("Starting Datadisk server...");
+    if !config.telemetry.otlp_endpoint.is_empty() {
+        info!("Exporting spans to {}", config.telemetry.otlp_endpoint);
+    }
     info!("Loading configuration from: {}", config_path);
 
+    // Connecting migrates the schema, nothing else to do
+    if args.iter().any(|arg| arg == "-migrate" || arg == "--migrate") {
+        if !config.initialized {
+            anyhow::bail!("System not initialized, no database to migrate");
+        }
+        db::init_database(&config.database).await?;
+        info!("Database migration completed");
+        return Ok(());
+    }
+
     // Initialize database connection only if system is initialized
     let (db, perm_enforcer) = if config.initialized {
-        let db_conn = db::init_database(&config.database).await.map_err(|e| {
+        let db_conn = db::connect(&config.database, &config.db_health).await.map_err(|e| {
             tracing::error!("Database initialization failed: {}", e);
             anyhow::anyhow!("Database initialization failed: {}", e)
         })?;
@@ -91,9 +126,86 @@ async fn main() -> anyhow::Result<()> {
         (None, None)
     };
 
+    if config.dlp.enabled {
+        handlers::dlp::check(&config.dlp).map_err(|e| {
+            tracing::error!("Invalid DLP rule: {}", e);
+            anyhow::anyhow!("Invalid DLP rule: {}", e)
+        })?;
+    }
+
+    // Trash, thumbnails and the other areas may be on volumes of their own
+    handlers::storage_area::validate(&config).map_err(|e| {
+        tracing::error!("Storage area check failed: {}", e);
+        anyhow::anyhow!("Storage area check failed: {}", e)
+    })?;
+
     // Create application state
     let state = AppState::new(db, perm_enforcer, config.clone());
 
+    // Watch the database, switching to maintenance mode while it's down
+    db::start(state.clone());
+
+    // Complete or undo file operations interrupted by the last shutdown
+    handlers::journal::recover(&state).await;
+
+    // Start periodic HR sync if configured
+    handlers::hr_sync::start(state.clone());
+
+    // Persist tasks, failing those interrupted by the last shutdown
+    handlers::task::start(state.clone());
+
+    // Start removal of expired temp artifacts
+    handlers::artifact::start(state.clone());
+
+    // Start removal of expired login sessions
+    middleware::session::start(state.clone());
+
+    // Route task notifications to WebSocket clients
+    ws::start();
+
+    // Record changes of watched folders
+    handlers::watch::start(state.clone());
+
+    // Run the hooks of folders files land in
+    handlers::folder_hook::start(state.clone());
+
+    // Email activity digests
+    handlers::digest::start(state.clone());
+
+    // Watch accounts for mass deletion, download bursts and denied requests
+    handlers::abuse::init(&config);
+
+    // Save upload and download counts
+    handlers::traffic::start(state.clone());
+
+    // Move expired folder contents to the trash
+    handlers::expiry::start(state.clone());
+
+    // Move files nobody reads to cold storage
+    handlers::tiering::start(state.clone());
+
+    // Store compressible files compressed
+    handlers::compression::start(state.clone());
+
+    // Bound the workers for thumbnails and conversions
+    task::TASK_MANAGER.configure_jobs(&config.workers);
+
+    // Limit the throughput of copies and moves
+    task::TASK_MANAGER.configure_limits(&config.task_limit);
+
+    // Bound the tasks running at the same time
+    task::TASK_MANAGER.configure_queue(&config.task_queue);
+
+    // Retry files copies and moves fail on for reasons that may go away
+    task::TASK_MANAGER.configure_retry(&config.task_retry);
+    task::TASK_MANAGER.configure_copy(&config.task_copy);
+
+    // Run trash purge, audit retention, backups and other maintenance jobs
+    handlers::scheduler::start(state.clone());
+
+    // Remove purged data in the background
+    handlers::shredder::start(state.clone());
+
     // Create router
     let app = routes::create_router(state);
 
@@ -107,7 +219,8 @@ async fn main() -> anyhow::Result<()> {
 
     // Start server
     let listener = TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    // Peer addresses are needed for per-IP rate limits
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
@@ -0,0 +1,303 @@
+//! Prometheus metrics
+//!
+//! Request counts and latencies are recorded by the metrics middleware,
+//! upload volume by the file service. Connection, task, job, storage and pool
+//! gauges are sampled when `/metrics` is scraped.
+//!
+//! The counters of failed tasks, lost audit entries, rejected uploads and
+//! WebSocket disconnects are meant for alerting on their rate.
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use sea_orm::{ConnectionTrait, DatabaseBackend, DatabaseConnection};
+use std::time::Duration;
+
+use crate::handlers::hot_cache;
+use crate::handlers::quota;
+use crate::task::{TaskStatus, TaskType, TASK_MANAGER};
+use crate::ws::HUB;
+
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    upload_bytes: IntCounter,
+    ws_connections: IntGauge,
+    tasks: IntGaugeVec,
+    jobs: IntGaugeVec,
+    user_storage: IntGaugeVec,
+    db_pool_connections: IntGaugeVec,
+    tasks_finished: IntCounterVec,
+    audit_failures: IntCounterVec,
+    upload_rejections: IntCounterVec,
+    ws_connects: IntCounter,
+    ws_disconnects: IntCounter,
+    hot_cache_reads: IntCounterVec,
+    hot_cache_bytes: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("datadisk".to_string()), None)
+            .expect("valid metrics prefix");
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route"),
+            &["method", "route"],
+        )
+        .unwrap();
+        let upload_bytes = IntCounter::new("upload_bytes_total", "Bytes of stored uploads").unwrap();
+        let ws_connections =
+            IntGauge::new("ws_connections", "Connected WebSocket clients").unwrap();
+        let tasks = IntGaugeVec::new(Opts::new("tasks", "Background tasks by status"), &["status"]).unwrap();
+        let jobs = IntGaugeVec::new(
+            Opts::new("jobs", "Thumbnail and conversion jobs by state"),
+            &["state"],
+        )
+        .unwrap();
+        let user_storage = IntGaugeVec::new(
+            Opts::new("user_storage_bytes", "Storage used by each user scanned since startup"),
+            &["username"],
+        )
+        .unwrap();
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections by state"),
+            &["state"],
+        )
+        .unwrap();
+
+        let tasks_finished = IntCounterVec::new(
+            Opts::new("tasks_finished_total", "Background tasks finished by type and final status"),
+            &["type", "status"],
+        )
+        .unwrap();
+        let audit_failures = IntCounterVec::new(
+            Opts::new("audit_log_failures_total", "Audit entries not written, by reason"),
+            &["reason"],
+        )
+        .unwrap();
+        let upload_rejections = IntCounterVec::new(
+            Opts::new("upload_rejections_total", "Uploads refused by reason (quota, size, type, content)"),
+            &["reason"],
+        )
+        .unwrap();
+        let ws_connects = IntCounter::new("ws_connects_total", "WebSocket clients connected").unwrap();
+        let ws_disconnects = IntCounter::new("ws_disconnects_total", "WebSocket clients disconnected").unwrap();
+        let hot_cache_reads = IntCounterVec::new(
+            Opts::new("hot_cache_reads_total", "Reads of small files through the hot file cache by result"),
+            &["result"],
+        )
+        .unwrap();
+        let hot_cache_bytes = IntGauge::new("hot_cache_bytes", "Bytes of file content in the hot file cache").unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_duration.clone())).unwrap();
+        registry.register(Box::new(upload_bytes.clone())).unwrap();
+        registry.register(Box::new(ws_connections.clone())).unwrap();
+        registry.register(Box::new(tasks.clone())).unwrap();
+        registry.register(Box::new(jobs.clone())).unwrap();
+        registry.register(Box::new(user_storage.clone())).unwrap();
+        registry.register(Box::new(db_pool_connections.clone())).unwrap();
+        registry.register(Box::new(tasks_finished.clone())).unwrap();
+        registry.register(Box::new(audit_failures.clone())).unwrap();
+        registry.register(Box::new(upload_rejections.clone())).unwrap();
+        registry.register(Box::new(ws_connects.clone())).unwrap();
+        registry.register(Box::new(ws_disconnects.clone())).unwrap();
+        registry.register(Box::new(hot_cache_reads.clone())).unwrap();
+        registry.register(Box::new(hot_cache_bytes.clone())).unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_duration,
+            upload_bytes,
+            ws_connections,
+            tasks,
+            jobs,
+            user_storage,
+            db_pool_connections,
+            tasks_finished,
+            audit_failures,
+            upload_rejections,
+            ws_connects,
+            ws_disconnects,
+            hot_cache_reads,
+            hot_cache_bytes,
+        }
+    }
+}
+
+static METRICS: std::sync::LazyLock<Metrics> = std::sync::LazyLock::new(Metrics::new);
+
+/// Record a finished HTTP request
+pub fn observe_request(method: &str, route: &str, status: u16, elapsed: Duration) {
+    let m = &*METRICS;
+    m.http_requests
+        .with_label_values(&[method, route, &status.to_string()])
+        .inc();
+    m.http_duration
+        .with_label_values(&[method, route])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Count bytes of a stored upload
+pub fn add_upload_bytes(bytes: u64) {
+    METRICS.upload_bytes.inc_by(bytes);
+}
+
+/// Count a task reaching its final status
+pub fn count_task_finished(task_type: TaskType, status: TaskStatus) {
+    METRICS
+        .tasks_finished
+        .with_label_values(&[task_type.as_str(), status.as_str()])
+        .inc();
+}
+
+/// Why an audit entry was lost
+#[derive(Debug, Clone, Copy)]
+pub enum AuditFailure {
+    /// The log channel was full or not set up
+    Dropped,
+    /// Writing to the database failed
+    Insert,
+}
+
+/// Count an audit entry that was not written
+pub fn count_audit_failure(reason: AuditFailure) {
+    let reason = match reason {
+        AuditFailure::Dropped => "dropped",
+        AuditFailure::Insert => "insert",
+    };
+    METRICS.audit_failures.with_label_values(&[reason]).inc();
+}
+
+/// Why an upload was refused
+#[derive(Debug, Clone, Copy)]
+pub enum UploadRejection {
+    /// The storage quota would be exceeded
+    Quota,
+    /// Over the upload size limit
+    Size,
+    /// The file type is not allowed
+    Type,
+    /// The content matched a data loss prevention rule
+    Content,
+}
+
+/// Count a refused upload
+pub fn count_upload_rejected(reason: UploadRejection) {
+    let reason = match reason {
+        UploadRejection::Quota => "quota",
+        UploadRejection::Size => "size",
+        UploadRejection::Type => "type",
+        UploadRejection::Content => "content",
+    };
+    METRICS.upload_rejections.with_label_values(&[reason]).inc();
+}
+
+/// Count a WebSocket client connecting
+pub fn count_ws_connect() {
+    METRICS.ws_connects.inc();
+}
+
+/// Count a WebSocket client going away
+pub fn count_ws_disconnect() {
+    METRICS.ws_disconnects.inc();
+}
+
+/// Count a read through the hot file cache, `hit` if it was served from memory
+pub fn count_hot_cache(hit: bool) {
+    METRICS
+        .hot_cache_reads
+        .with_label_values(&[if hit { "hit" } else { "miss" }])
+        .inc();
+}
+
+/// Sample the gauges and render all metrics in the Prometheus text format
+pub fn render(db: Option<&DatabaseConnection>) -> String {
+    let m = &*METRICS;
+
+    m.ws_connections.set(HUB.connection_count() as i64);
+    for status in [
+        TaskStatus::Pending,
+        TaskStatus::Queued,
+        TaskStatus::Starting,
+        TaskStatus::Running,
+        TaskStatus::Suspended,
+    ] {
+        m.tasks
+            .with_label_values(&[status.as_str()])
+            .set(TASK_MANAGER.count_status(status) as i64);
+    }
+
+    m.hot_cache_bytes.set(hot_cache::cached_bytes() as i64);
+
+    let jobs = TASK_MANAGER.jobs();
+    m.jobs.with_label_values(&["queued"]).set(jobs.queued() as i64);
+    m.jobs.with_label_values(&["running"]).set(jobs.running() as i64);
+
+    // Users leave the cache when their usage is invalidated
+    m.user_storage.reset();
+    for (username, used) in quota::tracked_usage() {
+        m.user_storage.with_label_values(&[&username]).set(used);
+    }
+
+    if let Some(db) = db {
+        let (size, idle) = match db.get_database_backend() {
+            DatabaseBackend::Postgres => {
+                let pool = db.get_postgres_connection_pool();
+                (pool.size(), pool.num_idle())
+            }
+            DatabaseBackend::Sqlite => {
+                let pool = db.get_sqlite_connection_pool();
+                (pool.size(), pool.num_idle())
+            }
+            DatabaseBackend::MySql => (0, 0),
+        };
+        m.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle as i64);
+        m.db_pool_connections
+            .with_label_values(&["active"])
+            .set(size as i64 - idle as i64);
+    }
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&m.registry.gather(), &mut buffer) {
+        tracing::error!("Failed to encode metrics: {}", e);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        observe_request("GET", "/api/file/list", 200, Duration::from_millis(12));
+        add_upload_bytes(5);
+        count_task_finished(TaskType::Copy, TaskStatus::Failed);
+        count_audit_failure(AuditFailure::Dropped);
+        count_upload_rejected(UploadRejection::Quota);
+
+        let text = render(None);
+        assert!(text.contains(
+            r#"datadisk_http_requests_total{method="GET",route="/api/file/list",status="200"}"#
+        ));
+        assert!(text.contains("datadisk_http_request_duration_seconds_bucket"));
+        assert!(text.contains("datadisk_upload_bytes_total"));
+        assert!(text.contains(r#"datadisk_tasks{status="running"} 0"#));
+        assert!(text.contains(r#"datadisk_jobs{state="queued"}"#));
+        assert!(text.contains("datadisk_ws_connections 0"));
+        assert!(text.contains(r#"datadisk_tasks_finished_total{status="failed",type="copy"}"#));
+        assert!(text.contains(r#"datadisk_audit_log_failures_total{reason="dropped"}"#));
+        assert!(text.contains(r#"datadisk_upload_rejections_total{reason="quota"}"#));
+    }
+}
@@ -1,26 +1,39 @@
 //! Authentication middleware
 //!
-//! Provides session-based authentication for API routes
+//! Provides session-based authentication for API routes, with bearer tokens
+//! (`Authorization: Bearer ...`) checked by the provider chain as a fallback
+//! for non-interactive clients. Browsers whose session ended open a new one
+//! with their remembered device.
 
 use axum::{
     body::Body,
-    extract::State,
-    http::{Request, StatusCode},
+    extract::{ConnectInfo, State},
+    http::{header, HeaderValue, Request, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
-use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait};
+use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set};
 use serde_json::json;
+use std::net::SocketAddr;
 use std::ops::Deref;
 use tower_sessions::Session;
 
-use crate::entity::user;
+use crate::entity::api_token;
+use crate::entity::op_log::OpType;
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::auth_provider::authenticate_bearer;
+use crate::handlers::device;
+use crate::handlers::signed::signed_user;
+use crate::middleware::rate_limit::client_ip;
+use crate::repository::UserRepository;
 use crate::state::AppState;
 
 /// Session key for storing username
 pub const SESSION_USER_KEY: &str = "user";
 pub const SESSION_TIMESTAMP_KEY: &str = "timestamp";
+/// Session key of the remembered device the session was opened with
+pub const SESSION_DEVICE_KEY: &str = "device";
 
 /// Database connection wrapper for use in handlers via Extension
 #[derive(Clone)]
@@ -48,6 +61,10 @@ pub struct CurrentUser {
     pub status: i32,
     /// Permissions loaded from Casbin (comma-separated for API compatibility)
     pub permissions: Vec<String>,
+    /// Whether this is a service account (authenticated by API token)
+    pub is_service: bool,
+    /// ID of the API token the request was authenticated with, None for sessions
+    pub token_id: Option<i64>,
 }
 
 impl CurrentUser {
@@ -66,6 +83,11 @@ impl CurrentUser {
         self.has_permission(perm::CONTACTS)
     }
 
+    /// Check if the user can manage users and departments of their own department
+    pub fn can_contacts_dept(&self) -> bool {
+        self.has_permission(perm::CONTACTS_DEPT)
+    }
+
     /// Check if the user has role management permission
     pub fn can_role(&self) -> bool {
         self.has_permission(perm::ROLE)
@@ -81,6 +103,11 @@ impl CurrentUser {
         self.has_permission(perm::AUDIT)
     }
 
+    /// Check if the user can view audit logs of their own department
+    pub fn can_audit_dept(&self) -> bool {
+        self.has_permission(perm::AUDIT_DEPT)
+    }
+
     /// Check if the user has all permissions
     pub fn has_all_permissions(&self) -> bool {
         perm::ALL.iter().all(|p: &&str| self.permissions.contains(&p.to_string()))
@@ -92,6 +119,72 @@ impl CurrentUser {
     }
 }
 
+/// Prefix of generated API tokens
+pub const API_TOKEN_PREFIX: &str = "dd_";
+
+/// Generate a new random API token
+pub fn generate_api_token() -> String {
+    format!(
+        "{}{}{}",
+        API_TOKEN_PREFIX,
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// Hash an API token for storage and lookup
+pub fn hash_api_token(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Extract the token from an `Authorization: Bearer <token>` header
+fn bearer_token(request: &Request<Body>) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+}
+
+/// Look up a valid, unexpired API token
+pub(crate) async fn find_token(db: &DatabaseConnection, token: &str) -> Option<api_token::Model> {
+    let record = api_token::Entity::find()
+        .filter(api_token::Column::TokenHash.eq(hash_api_token(token)))
+        .one(db)
+        .await
+        .map_err(|e| tracing::error!("Database error during token auth: {}", e))
+        .ok()??;
+
+    let now = chrono::Utc::now().timestamp();
+    if record.expire_time > 0 && record.expire_time < now {
+        return None;
+    }
+
+    let mut active: api_token::ActiveModel = record.clone().into();
+    active.last_used_time = Set(now);
+    if let Err(e) = active.update(db).await {
+        tracing::error!("Failed to update token last used time: {}", e);
+    }
+
+    Some(record)
+}
+
+/// Restrict a user's Casbin permissions to the scopes of a token
+///
+/// Scopes are comma-separated permission names; an empty scope list keeps
+/// all of the user's permissions. A token never grants more than its owner has.
+pub fn scoped_permissions(permissions: Vec<String>, scopes: &str) -> Vec<String> {
+    if scopes.trim().is_empty() {
+        return permissions;
+    }
+    let scopes = crate::permission::normalize_permissions(scopes);
+    permissions.into_iter().filter(|p| scopes.contains(p)).collect()
+}
+
 /// Paths that don't require authentication
 fn is_public_path(path: &str) -> bool {
     // Only authenticate API routes (except public ones)
@@ -115,6 +208,14 @@ fn is_public_path(path: &str) -> bool {
     if path == "/api/health" {
         return true;
     }
+    // API documentation
+    if path == "/api/openapi.json" || path == "/api/docs" || path.starts_with("/api/docs/") {
+        return true;
+    }
+    // HR sync webhook (authenticated by shared token)
+    if path == "/api/hr/sync" {
+        return true;
+    }
     // OnlyOffice editing callbacks
     if path.starts_with("/api/editing/save/") || path.starts_with("/api/editing/download/") {
         return true;
@@ -142,31 +243,80 @@ pub async fn auth_layer(
         return next.run(request).await;
     }
 
-    // Get username from session
-    let username: Option<String> = session.get(SESSION_USER_KEY).await.unwrap_or(None);
-
-    let Some(username) = username else {
+    // Check if database is initialized (get from extension we just set)
+    let Some(db_conn) = request.extensions().get::<DbConn>().cloned() else {
         return (
-            StatusCode::UNAUTHORIZED,
-            Json(json!({"error": "unauthorized"})),
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({"error": "system_not_initialized"})),
         ).into_response();
     };
 
-    // Check if database is initialized (get from extension we just set)
-    let Some(db_conn) = request.extensions().get::<DbConn>() else {
+    // Sessions opened with a remembered device end when it is revoked
+    let mut session_user: Option<String> = session.get(SESSION_USER_KEY).await.unwrap_or(None);
+    if session_user.is_some() {
+        if let Some(device_id) = session.get::<i64>(SESSION_DEVICE_KEY).await.unwrap_or(None) {
+            if !device::is_remembered(&db_conn, device_id).await {
+                let _ = session.flush().await;
+                session_user = None;
+            }
+        }
+    }
+
+    // Get username from session, falling back to a bearer token the provider
+    // chain accepts, then to a remembered device, then to a signed URL
+    let mut token: Option<api_token::Model> = None;
+    let mut bearer = false;
+    let mut resumed: Option<device::Resumed> = None;
+    let mut signed = false;
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+    let ip = client_ip(request.headers(), peer, state.config.rate_limit.trust_proxy).map(|ip| ip.to_string());
+    let username: Option<String> = match session_user {
+        Some(username) => Some(username),
+        None => match bearer_token(&request) {
+            Some(credential) => {
+                bearer = true;
+                authenticate_bearer(&state, &db_conn, credential).await.map(|user| {
+                    token = user.token;
+                    user.username
+                })
+            }
+            None => {
+                if let Some(value) = device::cookie_value(request.headers()) {
+                    resumed = device::resume(&db_conn, &state.config.remember, &value, ip.as_deref()).await;
+                }
+                match &resumed {
+                    Some(resumed) => Some(resumed.username.clone()),
+                    None => {
+                        let username = signed_user(&state.config.signed_url, request.uri());
+                        signed = username.is_some();
+                        username
+                    }
+                }
+            }
+        },
+    };
+
+    let Some(username) = username else {
         return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(json!({"error": "system_not_initialized"})),
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": "unauthorized"})),
         ).into_response();
     };
 
     // Look up user in database
-    let user_result = user::Entity::find()
-        .filter(user::Column::Username.eq(&username))
-        .one(&**db_conn)
-        .await;
+    let user_result = db_conn.find_user(&username).await;
 
     match user_result {
+        // Sessions of disabled users end at login, bearer tokens, remembered
+        // devices and signed URLs must be refused here
+        Ok(Some(user_model))
+            if user_model.status == 2 && (user_model.is_service_account() || bearer || resumed.is_some() || signed) =>
+        {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": "user is disabled"})),
+            ).into_response()
+        }
         Ok(Some(user_model)) => {
             // Get user permissions from Casbin
             let permissions = if let Some(perm_enforcer) = state.get_perm().await.as_ref() {
@@ -174,8 +324,13 @@ pub async fn auth_layer(
             } else {
                 Vec::new()
             };
+            let permissions = match &token {
+                Some(token) => scoped_permissions(permissions, &token.scopes),
+                None => permissions,
+            };
 
             // Create CurrentUser extension
+            let is_service = user_model.is_service_account();
             let current_user = CurrentUser {
                 id: user_model.id,
                 username: user_model.username,
@@ -185,12 +340,31 @@ pub async fn auth_layer(
                 dept_name: user_model.dept_name,
                 status: user_model.status,
                 permissions,
+                is_service,
+                token_id: token.map(|t| t.id),
             };
 
+            // A remembered device opens a new session
+            if let Some(resumed) = &resumed {
+                let opened = session.insert(SESSION_USER_KEY, &current_user.username).await;
+                let opened = opened.and(session.insert(SESSION_TIMESTAMP_KEY, chrono::Utc::now().timestamp()).await);
+                if let Err(e) = opened.and(session.insert(SESSION_DEVICE_KEY, resumed.device_id).await) {
+                    tracing::error!("Failed to save session: {}", e);
+                }
+                log_operation(&current_user.username, OpType::Login, "已记住的设备", "成功", ip.as_deref());
+            }
+
             // Insert into request extensions
             request.extensions_mut().insert(current_user);
 
-            next.run(request).await
+            let mut response = next.run(request).await;
+            if let Some(value) = resumed.and_then(|resumed| resumed.cookie) {
+                let cookie = device::cookie(value, state.config.remember.days);
+                if let Ok(value) = HeaderValue::from_str(&cookie.to_string()) {
+                    response.headers_mut().append(header::SET_COOKIE, value);
+                }
+            }
+            response
         }
         Ok(None) => {
             tracing::warn!("User not found in database: {}", username);
@@ -208,3 +382,43 @@ pub async fn auth_layer(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_token_generation() {
+        let a = generate_api_token();
+        let b = generate_api_token();
+        assert!(a.starts_with(API_TOKEN_PREFIX));
+        assert_ne!(a, b);
+        assert_eq!(hash_api_token(&a), hash_api_token(&a));
+        assert_eq!(hash_api_token(&a).len(), 64);
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let req = Request::builder()
+            .header(header::AUTHORIZATION, "Bearer dd_abc")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(bearer_token(&req), Some("dd_abc"));
+
+        let req = Request::builder()
+            .header(header::AUTHORIZATION, "Basic dXNlcjpwYXNz")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(bearer_token(&req), None);
+    }
+
+    #[test]
+    fn test_scoped_permissions() {
+        let perms = || vec!["file".to_string(), "contacts".to_string()];
+        assert_eq!(scoped_permissions(perms(), ""), perms());
+        assert_eq!(scoped_permissions(perms(), "file"), vec!["file"]);
+        // Scopes the owner doesn't have are not granted
+        assert_eq!(scoped_permissions(perms(), "file,audit"), vec!["file"]);
+        assert!(scoped_permissions(perms(), "bogus").is_empty());
+    }
+}
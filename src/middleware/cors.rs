@@ -0,0 +1,142 @@
+//! Cross-origin requests
+//!
+//! Desktop and browser clients served from another origin authenticate with
+//! an API token instead of the session cookie, and resume uploads with
+//! `Content-Range` or the TUS headers. A wildcard `Access-Control-Allow-Headers`
+//! doesn't cover `Authorization`, so the headers are listed one by one, and
+//! the ones a client reads back to resume are exposed.
+
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::CorsConfig;
+
+/// Request headers of token auth and the upload protocols
+const ALLOWED_HEADERS: [&str; 11] = [
+    "authorization",
+    "content-type",
+    "content-range",
+    "x-requested-with",
+    "tus-resumable",
+    "upload-offset",
+    "upload-length",
+    "upload-defer-length",
+    "upload-metadata",
+    "upload-concat",
+    "x-http-method-override",
+];
+
+/// Response headers clients read to resume an upload, name a download or
+/// page through a listing
+const EXPOSED_HEADERS: [&str; 11] = [
+    "location",
+    "content-range",
+    "content-disposition",
+    "tus-resumable",
+    "tus-version",
+    "tus-extension",
+    "tus-max-size",
+    "upload-offset",
+    "upload-length",
+    "upload-expires",
+    "x-total-count",
+];
+
+const METHODS: [Method; 7] = [
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+    Method::OPTIONS,
+];
+
+/// CORS layer of the configuration; invalid origins and header names are
+/// logged and left out
+pub fn cors_layer(config: &CorsConfig) -> CorsLayer {
+    let mut headers: Vec<HeaderName> = ALLOWED_HEADERS.iter().map(|h| HeaderName::from_static(h)).collect();
+    for name in &config.allowed_headers {
+        match HeaderName::try_from(name.trim().to_ascii_lowercase()) {
+            Ok(name) if !headers.contains(&name) => headers.push(name),
+            Ok(_) => {}
+            Err(_) => tracing::warn!("Ignoring invalid CORS header name {:?}", name),
+        }
+    }
+
+    let origin = if config.allowed_origins.is_empty() {
+        AllowOrigin::any()
+    } else {
+        let origins: Vec<HeaderValue> = config
+            .allowed_origins
+            .iter()
+            .filter_map(|origin| {
+                let value = HeaderValue::from_str(origin.trim_end_matches('/')).ok();
+                if value.is_none() {
+                    tracing::warn!("Ignoring invalid CORS origin {:?}", origin);
+                }
+                value
+            })
+            .collect();
+        AllowOrigin::list(origins)
+    };
+
+    CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods(METHODS.to_vec())
+        .allow_headers(headers)
+        .expose_headers(EXPOSED_HEADERS.iter().map(|h| HeaderName::from_static(h)).collect::<Vec<_>>())
+        .vary([header::ORIGIN, header::ACCESS_CONTROL_REQUEST_METHOD, header::ACCESS_CONTROL_REQUEST_HEADERS])
+        .max_age(Duration::from_secs(config.max_age_secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::post, Router};
+    use tower::ServiceExt;
+
+    fn preflight(origin: &str) -> Request<Body> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/file/upload")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization, upload-offset, x-client-id")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cors_layer() {
+        let config = CorsConfig {
+            allowed_origins: vec!["app://datadisk".to_string()],
+            allowed_headers: vec!["X-Client-Id".to_string()],
+            ..CorsConfig::default()
+        };
+        let app = Router::new()
+            .route("/api/file/upload", post(|| async { "ok" }))
+            .layer(cors_layer(&config));
+
+        let res = app.clone().oneshot(preflight("app://datadisk")).await.unwrap();
+        let allowed = res.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap();
+        assert!(allowed.contains("authorization") && allowed.contains("upload-offset") && allowed.contains("x-client-id"));
+        assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "app://datadisk");
+        assert_eq!(res.headers()[header::ACCESS_CONTROL_MAX_AGE], "3600");
+
+        // Other origins get no grant
+        let res = app.clone().oneshot(preflight("https://evil.example")).await.unwrap();
+        assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/api/file/upload")
+            .header(header::ORIGIN, "app://datadisk")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(request).await.unwrap();
+        let exposed = res.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS].to_str().unwrap();
+        assert!(exposed.contains("upload-offset") && exposed.contains("location"));
+    }
+}
@@ -0,0 +1,95 @@
+//! Maintenance mode while the database is down
+//!
+//! Once the database checks ([`crate::db::start`]) open the breaker, API and
+//! WebDAV requests are answered with 503 and a maintenance message right
+//! away, instead of each waiting for a connection and failing with a 500.
+//! The web app's static files are still served, so it loads and shows the
+//! message, and so are the health check and the setup status it polls until
+//! the database is back.
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::json;
+
+use crate::state::AppState;
+
+/// Shown by the web app while the database is down
+pub const MESSAGE: &str = "数据库暂时不可用，系统维护中，请稍后再试";
+
+/// Whether a request to `path` is served without the database
+fn is_static(path: &str) -> bool {
+    let needs_db = path.starts_with("/api/") || path == "/api" || path.starts_with("/webdav");
+    !needs_db || path == "/api/health" || path == "/api/setup/status"
+}
+
+/// Middleware answering requests needing the database with 503 while it's down
+pub async fn maintenance_layer(State(state): State<AppState>, request: Request<Body>, next: Next) -> Response {
+    if state.db_health.is_available() || is_static(request.uri().path()) {
+        return next.run(request).await;
+    }
+    let retry_after = state.db_health.retry_delay().as_secs().max(1);
+    let mut response = (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "error": "maintenance", "message": MESSAGE })),
+    )
+        .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    #[test]
+    fn test_is_static() {
+        assert!(is_static("/"));
+        assert!(is_static("/ui/file/mydocs"));
+        assert!(is_static("/assets/index.js"));
+        assert!(is_static("/api/health"));
+        assert!(is_static("/api/setup/status"));
+        assert!(!is_static("/api/file/list"));
+        assert!(!is_static("/api/login"));
+        assert!(!is_static("/webdav/a.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_maintenance() {
+        let app = TestApp::spawn().await;
+        let admin = app.admin().await;
+        let health = &app.state.db_health;
+        for _ in 0..app.env.config.db_health.failure_threshold {
+            health.record_failure();
+        }
+
+        let res = admin.get("/api/file/list?path=/").await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(res.headers().contains_key(header::RETRY_AFTER));
+        let body: serde_json::Value = res.json().await.unwrap();
+        assert_eq!(body["error"], "maintenance");
+        assert_eq!(body["message"], MESSAGE);
+
+        let res = admin.get("/api/health").await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = res.json().await.unwrap();
+        assert_eq!(body["data"]["status"], "degraded");
+        assert_eq!(body["data"]["database"]["available"], false);
+        assert!(body["data"]["database"]["downSince"].is_string());
+
+        // Back to normal with the first successful check
+        health.record_success();
+        assert_eq!(admin.get("/api/file/list?path=/").await.status(), StatusCode::OK);
+        let body: serde_json::Value = admin.get("/api/health").await.json().await.unwrap();
+        assert_eq!(body["data"]["status"], "healthy");
+        assert_eq!(body["data"]["database"]["available"], true);
+        app.close().await;
+    }
+}
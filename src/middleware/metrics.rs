@@ -0,0 +1,57 @@
+//! Request metrics middleware
+//!
+//! Records the count and latency of every request by method, route pattern
+//! and status. The route is the pattern that matched (`/api/user/avatar/:username`),
+//! so paths with IDs don't create a series each.
+
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+
+use crate::metrics;
+
+/// Route label of requests no route matched (static files)
+const UNMATCHED: &str = "unmatched";
+
+pub async fn metrics_layer(request: Request<Body>, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| UNMATCHED.to_string());
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    metrics::observe_request(&method, &route, response.status().as_u16(), started.elapsed());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_route_label() {
+        let api = Router::new().route("/item/:id", get(|| async { "ok" }));
+        let app = Router::new()
+            .nest("/api", api)
+            .layer(middleware::from_fn(metrics_layer));
+
+        for uri in ["/api/item/1", "/api/item/2", "/missing"] {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let text = metrics::render(None);
+        assert!(text.contains(r#"datadisk_http_requests_total{method="GET",route="/api/item/:id",status="200"} 2"#));
+        assert!(text.contains(r#"datadisk_http_requests_total{method="GET",route="unmatched",status="404"} 1"#));
+    }
+}
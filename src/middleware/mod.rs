@@ -1,5 +1,16 @@
 //! Middleware module
 
 pub mod auth;
+pub mod cors;
+pub mod maintenance;
+pub mod metrics;
+pub mod rate_limit;
+pub mod session;
+pub mod tarpit;
 
 pub use auth::{auth_layer, DbConn};
+pub use cors::cors_layer;
+pub use maintenance::maintenance_layer;
+pub use metrics::metrics_layer;
+pub use rate_limit::{rate_limit_layer, RateLimits};
+pub use tarpit::{tarpit_layer, Tarpit};
@@ -0,0 +1,243 @@
+//! Rate limiting middleware
+//!
+//! Counts requests per client IP and per authenticated user in fixed
+//! one-minute windows. Login and setup endpoints get a much smaller per-IP
+//! budget to slow down password guessing. Requests over a limit are answered
+//! with 429 and a `Retry-After` header.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use dashmap::DashMap;
+use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+use crate::handlers::abuse;
+use crate::middleware::auth::CurrentUser;
+
+/// Length of a counting window
+const WINDOW: Duration = Duration::from_secs(60);
+/// Checks between removals of finished windows
+const PRUNE_INTERVAL: u64 = 10_000;
+
+/// Fixed window counter per key
+pub struct RateLimiter {
+    limit: u32,
+    windows: DashMap<String, (Instant, u32)>,
+    checks: AtomicU64,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing `limit` requests per window (0 = unlimited)
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            windows: DashMap::new(),
+            checks: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a request for `key`
+    ///
+    /// Returns the time until the window resets if the limit is exceeded.
+    pub fn check(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+        if self.checks.fetch_add(1, Ordering::Relaxed) % PRUNE_INTERVAL == PRUNE_INTERVAL - 1 {
+            self.prune(now);
+        }
+
+        let mut window = self.windows.entry(key.to_string()).or_insert((now, 0));
+        let (start, count) = window.value_mut();
+        if now.duration_since(*start) >= WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            return Err(WINDOW.saturating_sub(now.duration_since(*start)));
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    /// Drop finished windows
+    fn prune(&self, now: Instant) {
+        self.windows.retain(|_, (start, _)| now.duration_since(*start) < WINDOW);
+    }
+}
+
+/// Limiters built from the configuration
+pub struct RateLimits {
+    config: RateLimitConfig,
+    ip: RateLimiter,
+    user: RateLimiter,
+    login: RateLimiter,
+}
+
+impl RateLimits {
+    pub fn new(config: &RateLimitConfig) -> Arc<Self> {
+        Arc::new(Self {
+            config: config.clone(),
+            ip: RateLimiter::new(config.per_ip),
+            user: RateLimiter::new(config.per_user),
+            login: RateLimiter::new(config.login),
+        })
+    }
+}
+
+/// Paths with the stricter login budget
+pub(crate) fn is_login_path(path: &str) -> bool {
+    path == "/api/login" || path.starts_with("/api/setup/")
+}
+
+/// Paths that are rate limited at all (static files are not)
+fn is_limited_path(path: &str) -> bool {
+    path.starts_with("/api") || path.starts_with("/webdav")
+}
+
+/// Client IP, from proxy headers if they are trusted
+///
+/// Of X-Forwarded-For only the last entry is used: it is the one added by the
+/// proxy, earlier ones come from the client and can be forged.
+pub fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>, trust_proxy: bool) -> Option<IpAddr> {
+    if trust_proxy {
+        let forwarded = headers
+            .get("x-real-ip")
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| {
+                headers
+                    .get("x-forwarded-for")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.rsplit(',').next())
+            })
+            .and_then(|v| v.trim().parse().ok());
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+    peer
+}
+
+fn too_many_requests(retry_after: Duration) -> Response {
+    let secs = retry_after.as_secs().max(1);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, secs.to_string())],
+        Json(json!({"error": "too many requests"})),
+    )
+        .into_response()
+}
+
+/// Rate limiting middleware, runs after authentication so users are known
+pub async fn rate_limit_layer(
+    State(limits): State<Arc<RateLimits>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if !is_limited_path(path) {
+        return next.run(request).await;
+    }
+    // Accounts throttled by abuse detection, even with rate limiting off
+    if let Some(user) = request.extensions().get::<CurrentUser>() {
+        if let Err(retry_after) = abuse::check_throttle(&user.username, Instant::now()) {
+            tracing::warn!("Throttled account {} over its budget", user.username);
+            return too_many_requests(retry_after);
+        }
+    }
+    if !limits.config.enabled {
+        return next.run(request).await;
+    }
+
+    let now = Instant::now();
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Some(ip) = client_ip(request.headers(), peer, limits.config.trust_proxy) {
+        let ip = ip.to_string();
+        if is_login_path(path) {
+            if let Err(retry_after) = limits.login.check(&ip, now) {
+                tracing::warn!("Login rate limit exceeded for {}", ip);
+                return too_many_requests(retry_after);
+            }
+        }
+        if let Err(retry_after) = limits.ip.check(&ip, now) {
+            return too_many_requests(retry_after);
+        }
+    }
+    if let Some(user) = request.extensions().get::<CurrentUser>() {
+        if let Err(retry_after) = limits.user.check(&user.username, now) {
+            return too_many_requests(retry_after);
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2);
+        let now = Instant::now();
+        assert!(limiter.check("a", now).is_ok());
+        assert!(limiter.check("a", now).is_ok());
+        let retry = limiter.check("a", now + Duration::from_secs(20)).unwrap_err();
+        assert_eq!(retry, Duration::from_secs(40));
+        // Other keys have their own budget
+        assert!(limiter.check("b", now).is_ok());
+        // The next window starts over
+        assert!(limiter.check("a", now + WINDOW).is_ok());
+
+        let unlimited = RateLimiter::new(0);
+        assert!((0..100).all(|_| unlimited.check("a", now).is_ok()));
+    }
+
+    #[test]
+    fn test_prune() {
+        let limiter = RateLimiter::new(5);
+        let now = Instant::now();
+        limiter.check("a", now).unwrap();
+        limiter.check("b", now + Duration::from_secs(30)).unwrap();
+        limiter.prune(now + Duration::from_secs(70));
+        assert_eq!(limiter.windows.len(), 1);
+        assert!(limiter.windows.contains_key("b"));
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let peer: Option<IpAddr> = Some("10.0.0.1".parse().unwrap());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "203.0.113.7, 10.0.0.2".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, false), peer);
+        assert_eq!(client_ip(&headers, peer, true), Some("10.0.0.2".parse().unwrap()));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", "198.51.100.1".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, true), Some("198.51.100.1".parse().unwrap()));
+        assert_eq!(client_ip(&HeaderMap::new(), peer, true), peer);
+    }
+
+    #[test]
+    fn test_paths() {
+        assert!(is_login_path("/api/login"));
+        assert!(is_login_path("/api/setup/init/user"));
+        assert!(!is_login_path("/api/logout"));
+        assert!(is_limited_path("/api/file/list"));
+        assert!(is_limited_path("/webdav/docs"));
+        assert!(!is_limited_path("/assets/app.js"));
+    }
+}
@@ -0,0 +1,371 @@
+//! Session storage backends
+//!
+//! Login sessions are kept in the database by default so they survive
+//! restarts and are shared by all instances behind a load balancer. Redis
+//! can be used instead, and the in-memory store remains for development.
+//! The backend is chosen by `[session] backend` in the configuration.
+
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use sea_orm::{sea_query::OnConflict, ColumnTrait, EntityTrait, QueryFilter, Set};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use tower_sessions::cookie::time::OffsetDateTime;
+use tower_sessions::session::{Id, Record};
+use tower_sessions::session_store::{self, SessionStore};
+use tower_sessions::MemoryStore;
+
+use crate::config::SessionConfig;
+use crate::entity::session;
+use crate::state::AppState;
+
+fn backend_error(e: impl fmt::Display) -> session_store::Error {
+    session_store::Error::Backend(e.to_string())
+}
+
+fn encode_data(record: &Record) -> session_store::Result<String> {
+    serde_json::to_string(&record.data).map_err(|e| session_store::Error::Encode(e.to_string()))
+}
+
+fn decode_data(data: &str) -> session_store::Result<HashMap<String, serde_json::Value>> {
+    serde_json::from_str(data).map_err(|e| session_store::Error::Decode(e.to_string()))
+}
+
+fn expiry_from_timestamp(ts: i64) -> session_store::Result<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp(ts).map_err(|e| session_store::Error::Decode(e.to_string()))
+}
+
+/// Sessions in the `disk_session` table
+///
+/// Until the system is set up there is no database; sessions are kept in
+/// memory meanwhile.
+#[derive(Clone)]
+pub struct DatabaseStore {
+    state: AppState,
+    fallback: MemoryStore,
+}
+
+impl fmt::Debug for DatabaseStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatabaseStore").finish_non_exhaustive()
+    }
+}
+
+impl DatabaseStore {
+    pub fn new(state: AppState) -> Self {
+        Self {
+            state,
+            fallback: MemoryStore::default(),
+        }
+    }
+
+    fn active_model(record: &Record) -> session_store::Result<session::ActiveModel> {
+        Ok(session::ActiveModel {
+            id: Set(record.id.to_string()),
+            data: Set(encode_data(record)?),
+            expiry_date: Set(record.expiry_date.unix_timestamp()),
+        })
+    }
+
+    /// Remove expired sessions
+    pub async fn delete_expired(&self) -> session_store::Result<u64> {
+        let Some(db) = self.state.get_db().await else {
+            return Ok(0);
+        };
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let result = session::Entity::delete_many()
+            .filter(session::Column::ExpiryDate.lte(now))
+            .exec(&db)
+            .await
+            .map_err(backend_error)?;
+        Ok(result.rows_affected)
+    }
+}
+
+#[async_trait]
+impl SessionStore for DatabaseStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        let Some(db) = self.state.get_db().await else {
+            return self.fallback.create(record).await;
+        };
+        // Pick a fresh ID on the (unlikely) collision with a stored session
+        while session::Entity::find_by_id(record.id.to_string())
+            .one(&db)
+            .await
+            .map_err(backend_error)?
+            .is_some()
+        {
+            record.id = Id::default();
+        }
+        session::Entity::insert(Self::active_model(record)?)
+            .exec(&db)
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let Some(db) = self.state.get_db().await else {
+            return self.fallback.save(record).await;
+        };
+        session::Entity::insert(Self::active_model(record)?)
+            .on_conflict(
+                OnConflict::column(session::Column::Id)
+                    .update_columns([session::Column::Data, session::Column::ExpiryDate])
+                    .to_owned(),
+            )
+            .exec(&db)
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        let Some(db) = self.state.get_db().await else {
+            return self.fallback.load(id).await;
+        };
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let row = session::Entity::find_by_id(id.to_string())
+            .filter(session::Column::ExpiryDate.gt(now))
+            .one(&db)
+            .await
+            .map_err(backend_error)?;
+        match row {
+            Some(row) => Ok(Some(Record {
+                id: *id,
+                data: decode_data(&row.data)?,
+                expiry_date: expiry_from_timestamp(row.expiry_date)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        let Some(db) = self.state.get_db().await else {
+            return self.fallback.delete(id).await;
+        };
+        session::Entity::delete_by_id(id.to_string())
+            .exec(&db)
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+}
+
+/// Sessions in Redis, expiring through key TTLs
+///
+/// Commands go through a multiplexed connection manager that reconnects
+/// on its own; it is opened on first use.
+pub struct RedisStore {
+    client: redis::Client,
+    conn: tokio::sync::OnceCell<ConnectionManager>,
+}
+
+impl fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("addr", &self.client.get_connection_info().addr)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Session record as stored in Redis
+#[derive(Serialize, Deserialize)]
+struct RedisRecord {
+    data: HashMap<String, serde_json::Value>,
+    expiry_date: i64,
+}
+
+impl RedisStore {
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: redis::Client::open(url)?,
+            conn: tokio::sync::OnceCell::new(),
+        })
+    }
+
+    async fn connection(&self) -> session_store::Result<ConnectionManager> {
+        self.conn
+            .get_or_try_init(|| self.client.get_connection_manager())
+            .await
+            .cloned()
+            .map_err(backend_error)
+    }
+
+    fn key(id: &Id) -> String {
+        format!("datadisk:session:{}", id)
+    }
+
+    /// SET the record with its TTL, only if the key doesn't exist when `nx`
+    async fn set(&self, record: &Record, nx: bool) -> session_store::Result<bool> {
+        let value = serde_json::to_vec(&RedisRecord {
+            data: record.data.clone(),
+            expiry_date: record.expiry_date.unix_timestamp(),
+        })
+        .map_err(|e| session_store::Error::Encode(e.to_string()))?;
+        let ttl = (record.expiry_date - OffsetDateTime::now_utc()).whole_seconds().max(1);
+
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(Self::key(&record.id)).arg(value).arg("EX").arg(ttl);
+        if nx {
+            cmd.arg("NX");
+        }
+        // NX replies nil if the key exists
+        let reply: Option<String> = cmd.query_async(&mut self.connection().await?).await.map_err(backend_error)?;
+        Ok(reply.is_some())
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        while !self.set(record, true).await? {
+            record.id = Id::default();
+        }
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.set(record, false).await.map(|_| ())
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        let value: Option<Vec<u8>> = redis::cmd("GET")
+            .arg(Self::key(id))
+            .query_async(&mut self.connection().await?)
+            .await
+            .map_err(backend_error)?;
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        let stored: RedisRecord =
+            serde_json::from_slice(&value).map_err(|e| session_store::Error::Decode(e.to_string()))?;
+        Ok(Some(Record {
+            id: *id,
+            data: stored.data,
+            expiry_date: expiry_from_timestamp(stored.expiry_date)?,
+        }))
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        redis::cmd("DEL")
+            .arg(Self::key(id))
+            .query_async::<()>(&mut self.connection().await?)
+            .await
+            .map_err(backend_error)
+    }
+}
+
+/// The configured session store
+#[derive(Debug, Clone)]
+pub enum Store {
+    Memory(MemoryStore),
+    Database(DatabaseStore),
+    Redis(std::sync::Arc<RedisStore>),
+}
+
+impl Store {
+    /// Build the store selected by the configuration
+    ///
+    /// Falls back to the database store if the Redis URL is invalid.
+    pub fn from_config(config: &SessionConfig, state: &AppState) -> Self {
+        match config.backend.as_str() {
+            "memory" => Store::Memory(MemoryStore::default()),
+            "redis" => match RedisStore::new(&config.redis_url) {
+                Ok(store) => Store::Redis(std::sync::Arc::new(store)),
+                Err(e) => {
+                    tracing::error!("Invalid redis URL, using database sessions: {}", e);
+                    Store::Database(DatabaseStore::new(state.clone()))
+                }
+            },
+            "database" => Store::Database(DatabaseStore::new(state.clone())),
+            other => {
+                tracing::warn!("Unknown session backend '{}', using database sessions", other);
+                Store::Database(DatabaseStore::new(state.clone()))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for Store {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        match self {
+            Store::Memory(store) => store.create(record).await,
+            Store::Database(store) => store.create(record).await,
+            Store::Redis(store) => store.create(record).await,
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        match self {
+            Store::Memory(store) => store.save(record).await,
+            Store::Database(store) => store.save(record).await,
+            Store::Redis(store) => store.save(record).await,
+        }
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        match self {
+            Store::Memory(store) => store.load(id).await,
+            Store::Database(store) => store.load(id).await,
+            Store::Redis(store) => store.load(id).await,
+        }
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        match self {
+            Store::Memory(store) => store.delete(id).await,
+            Store::Database(store) => store.delete(id).await,
+            Store::Redis(store) => store.delete(id).await,
+        }
+    }
+}
+
+/// Start periodic removal of expired database sessions
+pub fn start(state: AppState) {
+    if state.config.session.backend != "database" {
+        return;
+    }
+    let store = DatabaseStore::new(state);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            ticker.tick().await;
+            match store.delete_expired().await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Removed {} expired sessions", n),
+                Err(e) => tracing::error!("Failed to remove expired sessions: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redis_store_url() {
+        assert!(RedisStore::new("redis://127.0.0.1").is_ok());
+        assert!(RedisStore::new("redis://:secret@cache:6380/2").is_ok());
+        assert!(RedisStore::new("http://cache").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_database_store_without_db() {
+        // Before setup there is no database, sessions are kept in memory
+        let store = DatabaseStore::new(AppState::new(None, None, crate::config::Config::default()));
+        let mut record = Record {
+            id: Id::default(),
+            data: HashMap::from([("user".to_string(), serde_json::json!("alice"))]),
+            expiry_date: OffsetDateTime::now_utc() + tower_sessions::cookie::time::Duration::hours(1),
+        };
+        store.create(&mut record).await.unwrap();
+        let loaded = store.load(&record.id).await.unwrap().unwrap();
+        assert_eq!(loaded.data, record.data);
+        store.delete(&record.id).await.unwrap();
+        assert!(store.load(&record.id).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,272 @@
+//! Honeypot and tarpit middleware
+//!
+//! Scanners and credential stuffing leave a trail of failures: 401s and 404s
+//! on the API, refused logins and wrong WebDAV passwords. They are counted
+//! per client IP in fixed windows. Once an IP collects more than `threshold`
+//! of them, every further failure is answered after a delay growing with the
+//! count, up to `max_delay_secs`. Other requests of the IP go through
+//! unhindered, so users behind the same address only wait on their own
+//! mistakes, except at the login: there the delay comes first, whatever the
+//! outcome, so a fast answer doesn't give a right password away.
+//!
+//! Decoy paths (`[honeypot] decoys`) are requested by nothing but scanners.
+//! They get a slow 404 and put the IP over the threshold at once. An IP
+//! hitting a decoy or going over the threshold is written to the admin audit
+//! log, once per window.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, HoneypotConfig};
+use crate::entity::op_log::OpType;
+use crate::handlers::audit::service::log_admin_operation;
+use crate::middleware::rate_limit::{client_ip, is_login_path};
+
+const OP_RESULT_ALERT: &str = "告警";
+
+/// Checks between removals of finished windows
+const PRUNE_INTERVAL: u64 = 10_000;
+
+/// Failures of one IP in the current window
+struct Counter {
+    start: Instant,
+    failures: u32,
+    /// Written to the audit log in this window
+    reported: bool,
+}
+
+/// Failure counts per client IP
+pub struct Tarpit {
+    config: HoneypotConfig,
+    trust_proxy: bool,
+    counters: DashMap<IpAddr, Counter>,
+    checks: AtomicU64,
+}
+
+impl Tarpit {
+    pub fn new(config: &Config) -> Arc<Self> {
+        Arc::new(Self {
+            config: config.honeypot.clone(),
+            trust_proxy: config.rate_limit.trust_proxy,
+            counters: DashMap::new(),
+            checks: AtomicU64::new(0),
+        })
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.config.window_secs.max(1))
+    }
+
+    /// Whether `path` is a decoy or below one
+    fn is_decoy(&self, path: &str) -> bool {
+        self.config.decoys.iter().any(|decoy| {
+            let decoy = decoy.trim_end_matches('/');
+            !decoy.is_empty()
+                && path
+                    .strip_prefix(decoy)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    /// Delay for `failures` in a window
+    fn delay_for(&self, failures: u32) -> Duration {
+        let over = failures.saturating_sub(self.config.threshold) as u64;
+        Duration::from_millis(self.config.step_ms.saturating_mul(over))
+            .min(Duration::from_secs(self.config.max_delay_secs))
+    }
+
+    /// Current delay of `ip`
+    fn delay(&self, ip: IpAddr, now: Instant) -> Duration {
+        match self.counters.get(&ip) {
+            Some(counter) if now.duration_since(counter.start) < self.window() => self.delay_for(counter.failures),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Count `amount` failures of `ip`
+    ///
+    /// Returns the delay of the IP now, and whether it just went over the
+    /// threshold for the first time in the window.
+    fn record(&self, ip: IpAddr, amount: u32, now: Instant) -> (Duration, bool) {
+        let window = self.window();
+        if self.checks.fetch_add(1, Ordering::Relaxed) % PRUNE_INTERVAL == PRUNE_INTERVAL - 1 {
+            self.counters.retain(|_, counter| now.duration_since(counter.start) < window);
+        }
+
+        let mut counter = self.counters.entry(ip).or_insert(Counter {
+            start: now,
+            failures: 0,
+            reported: false,
+        });
+        if now.duration_since(counter.start) >= window {
+            *counter = Counter {
+                start: now,
+                failures: 0,
+                reported: false,
+            };
+        }
+        counter.failures = counter.failures.saturating_add(amount);
+        let report = counter.failures > self.config.threshold && !counter.reported;
+        counter.reported |= report;
+        (self.delay_for(counter.failures), report)
+    }
+}
+
+/// Whether a response counts as a failure
+///
+/// WebDAV clients routinely ask without credentials first and probe for
+/// files that don't exist, so there only wrong passwords count.
+fn is_failure(path: &str, status: StatusCode, has_credentials: bool) -> bool {
+    if is_login_path(path) {
+        return status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS;
+    }
+    if path.starts_with("/webdav") {
+        return status == StatusCode::UNAUTHORIZED && has_credentials;
+    }
+    path.starts_with("/api") && (status == StatusCode::UNAUTHORIZED || status == StatusCode::NOT_FOUND)
+}
+
+/// Honeypot and tarpit middleware, wraps authentication to see its 401s
+pub async fn tarpit_layer(State(tarpit): State<Arc<Tarpit>>, request: Request<Body>, next: Next) -> Response {
+    if !tarpit.config.enabled {
+        return next.run(request).await;
+    }
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let Some(ip) = client_ip(request.headers(), peer, tarpit.trust_proxy) else {
+        return next.run(request).await;
+    };
+    let path = request.uri().path().to_string();
+    let now = Instant::now();
+
+    if tarpit.is_decoy(&path) {
+        let (delay, report) = tarpit.record(ip, tarpit.config.threshold.saturating_add(1), now);
+        if report {
+            tracing::warn!("Decoy {} requested from {}", path, ip);
+            let desc = format!("{} 请求诱饵路径 {}", ip, path);
+            log_admin_operation("", OpType::ScannerDetected, &desc, OP_RESULT_ALERT, Some(&ip.to_string()));
+        }
+        tokio::time::sleep(delay.max(Duration::from_secs(tarpit.config.max_delay_secs))).await;
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let login = is_login_path(&path);
+    if login {
+        tokio::time::sleep(tarpit.delay(ip, now)).await;
+    }
+    let has_credentials = request.headers().contains_key(header::AUTHORIZATION);
+    let response = next.run(request).await;
+    if !is_failure(&path, response.status(), has_credentials) {
+        return response;
+    }
+
+    let (delay, report) = tarpit.record(ip, 1, Instant::now());
+    if report {
+        tracing::warn!("Failures from {} over the threshold, slowing them down", ip);
+        let desc = format!("{} 在{}秒内失败超过 {} 次，已延迟响应", ip, tarpit.window().as_secs(), tarpit.config.threshold);
+        log_admin_operation("", OpType::ScannerDetected, &desc, OP_RESULT_ALERT, Some(&ip.to_string()));
+    }
+    if !login {
+        tokio::time::sleep(delay).await;
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn tarpit() -> Arc<Tarpit> {
+        let mut config = Config::default();
+        config.honeypot.threshold = 2;
+        config.honeypot.window_secs = 60;
+        config.honeypot.step_ms = 100;
+        config.honeypot.max_delay_secs = 1;
+        config.honeypot.decoys = vec!["/.env".to_string(), "/wp-admin/".to_string()];
+        Tarpit::new(&config)
+    }
+
+    #[test]
+    fn test_record() {
+        let tarpit = tarpit();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let now = Instant::now();
+        assert_eq!(tarpit.record(ip, 1, now), (Duration::ZERO, false));
+        assert_eq!(tarpit.record(ip, 1, now), (Duration::ZERO, false));
+        assert_eq!(tarpit.record(ip, 1, now), (Duration::from_millis(100), true));
+        // Reported once per window, the delay keeps growing up to the limit
+        assert_eq!(tarpit.record(ip, 1, now), (Duration::from_millis(200), false));
+        assert_eq!(tarpit.record(ip, 100, now), (Duration::from_secs(1), false));
+        assert_eq!(tarpit.delay(ip, now), Duration::from_secs(1));
+        // Other IPs are counted separately
+        assert_eq!(tarpit.delay("203.0.113.8".parse().unwrap(), now), Duration::ZERO);
+
+        // The next window starts over
+        let later = now + Duration::from_secs(60);
+        assert_eq!(tarpit.delay(ip, later), Duration::ZERO);
+        assert_eq!(tarpit.record(ip, 3, later), (Duration::from_millis(100), true));
+    }
+
+    #[test]
+    fn test_is_decoy() {
+        let tarpit = tarpit();
+        assert!(tarpit.is_decoy("/.env"));
+        assert!(tarpit.is_decoy("/wp-admin"));
+        assert!(tarpit.is_decoy("/wp-admin/setup.php"));
+        assert!(!tarpit.is_decoy("/.environment"));
+        assert!(!tarpit.is_decoy("/api/file/list"));
+    }
+
+    #[test]
+    fn test_is_failure() {
+        assert!(is_failure("/api/login", StatusCode::BAD_REQUEST, false));
+        assert!(!is_failure("/api/login", StatusCode::TOO_MANY_REQUESTS, false));
+        assert!(is_failure("/api/file/list", StatusCode::UNAUTHORIZED, false));
+        assert!(is_failure("/api/nothing", StatusCode::NOT_FOUND, false));
+        assert!(!is_failure("/api/file/list", StatusCode::FORBIDDEN, false));
+        assert!(!is_failure("/webdav/a.txt", StatusCode::UNAUTHORIZED, false));
+        assert!(is_failure("/webdav/a.txt", StatusCode::UNAUTHORIZED, true));
+        assert!(!is_failure("/webdav/._a.txt", StatusCode::NOT_FOUND, true));
+        assert!(!is_failure("/assets/missing.js", StatusCode::NOT_FOUND, false));
+    }
+
+    #[tokio::test]
+    async fn test_decoy_slows_down_failures() {
+        let tarpit = tarpit();
+        let app = Router::new()
+            .route("/api/secret", get(|| async { StatusCode::UNAUTHORIZED }))
+            .route("/api/public", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(tarpit.clone(), tarpit_layer));
+        let send = |uri: &str| {
+            let mut request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            request.extensions_mut().insert(ConnectInfo(SocketAddr::from(([203, 0, 113, 7], 4000))));
+            app.clone().oneshot(request)
+        };
+
+        tokio::time::pause();
+        let started = tokio::time::Instant::now();
+        assert_eq!(send("/.env").await.unwrap().status(), StatusCode::NOT_FOUND);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+
+        // Failures of the IP are answered slowly from now on, the rest is not
+        let started = tokio::time::Instant::now();
+        assert_eq!(send("/api/public").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        assert_eq!(send("/api/secret").await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,66 @@
+//! Baseline schema
+//!
+//! Creates the tables of all entities. Tables that already exist (databases
+//! created before migrations were introduced) are left alone.
+
+use sea_orm::{EntityTrait, Schema};
+use sea_orm_migration::prelude::*;
+
+use crate::entity::{
+    api_token, casbin_rule, department, file_access, file_info, group, group_user, op_log, session,
+    task, trash, user,
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+pub(super) async fn create_table<E: EntityTrait>(manager: &SchemaManager<'_>, entity: E) -> Result<(), DbErr> {
+    let schema = Schema::new(manager.get_database_backend());
+    manager
+        .create_table(schema.create_table_from_entity(entity).if_not_exists().to_owned())
+        .await
+}
+
+pub(super) async fn drop_table<E: EntityTrait>(manager: &SchemaManager<'_>, entity: E) -> Result<(), DbErr> {
+    manager
+        .drop_table(Table::drop().table(entity.table_ref()).if_exists().to_owned())
+        .await
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Independent tables first
+        create_table(manager, department::Entity).await?;
+        create_table(manager, group::Entity).await?;
+        create_table(manager, op_log::Entity).await?;
+        create_table(manager, casbin_rule::Entity).await?;
+        create_table(manager, session::Entity).await?;
+
+        // Tables with foreign key dependencies
+        create_table(manager, user::Entity).await?;
+        create_table(manager, file_info::Entity).await?;
+        create_table(manager, group_user::Entity).await?;
+        create_table(manager, file_access::Entity).await?;
+        create_table(manager, trash::Entity).await?;
+        create_table(manager, api_token::Entity).await?;
+        create_table(manager, task::Entity).await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        drop_table(manager, task::Entity).await?;
+        drop_table(manager, api_token::Entity).await?;
+        drop_table(manager, trash::Entity).await?;
+        drop_table(manager, file_access::Entity).await?;
+        drop_table(manager, group_user::Entity).await?;
+        drop_table(manager, file_info::Entity).await?;
+        drop_table(manager, user::Entity).await?;
+        drop_table(manager, session::Entity).await?;
+        drop_table(manager, casbin_rule::Entity).await?;
+        drop_table(manager, op_log::Entity).await?;
+        drop_table(manager, group::Entity).await?;
+        drop_table(manager, department::Entity).await?;
+        Ok(())
+    }
+}
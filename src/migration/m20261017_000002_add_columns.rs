@@ -0,0 +1,51 @@
+//! Columns added to existing tables before migrations were introduced
+//!
+//! Databases created by older versions lack some of them; new databases
+//! already have them from the baseline, so each column is only added when
+//! missing.
+
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// (table, column, definition)
+const COLUMNS: &[(&str, &str, &str)] = &[
+    // Legacy, kept for compatibility
+    ("disk_user", "permissions", "VARCHAR(128) DEFAULT ''"),
+    ("disk_department", "quota", "VARCHAR(32)"),
+    // Admin audit stream
+    ("disk_op_log", "category", "VARCHAR(16) NOT NULL DEFAULT 'general'"),
+    // Service accounts
+    ("disk_user", "account_type", "VARCHAR(16) NOT NULL DEFAULT 'normal'"),
+    // Tasks persisted before they finish
+    ("disk_task", "agent", "VARCHAR(32) NOT NULL DEFAULT 'web'"),
+    ("disk_task", "updated_at", "BIGINT NOT NULL DEFAULT 0"),
+    ("disk_task", "dismissed", "BOOLEAN NOT NULL DEFAULT FALSE"),
+    // Scoped personal tokens
+    ("disk_api_token", "scopes", "VARCHAR(128) NOT NULL DEFAULT ''"),
+];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for (table, column, definition) in COLUMNS {
+            if !manager.has_column(table, column).await? {
+                tracing::info!("Adding column {}.{}", table, column);
+                manager
+                    .get_connection()
+                    .execute_unprepared(&format!(
+                        "ALTER TABLE {} ADD COLUMN {} {}",
+                        table, column, definition
+                    ))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        // The columns are part of the baseline entities, they can't be dropped
+        Ok(())
+    }
+}
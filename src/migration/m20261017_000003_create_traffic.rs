@@ -0,0 +1,33 @@
+//! Per-user daily traffic table
+
+use sea_orm_migration::prelude::*;
+
+use super::m20261017_000001_create_tables::{create_table, drop_table};
+use crate::entity::traffic;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        create_table(manager, traffic::Entity).await?;
+        // One row per user and day
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_traffic_username_day")
+                    .table(traffic::Entity)
+                    .col(traffic::Column::Username)
+                    .col(traffic::Column::Day)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        drop_table(manager, traffic::Entity).await
+    }
+}
@@ -0,0 +1,46 @@
+//! File tags
+
+use sea_orm_migration::prelude::*;
+
+use super::m20261017_000001_create_tables::{create_table, drop_table};
+use crate::entity::file_tag;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        create_table(manager, file_tag::Entity).await?;
+        // A tag once per file
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_file_tag_username_path_tag")
+                    .table(file_tag::Entity)
+                    .col(file_tag::Column::Username)
+                    .col(file_tag::Column::Path)
+                    .col(file_tag::Column::Tag)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        // Files by tag
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_file_tag_username_tag")
+                    .table(file_tag::Entity)
+                    .col(file_tag::Column::Username)
+                    .col(file_tag::Column::Tag)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        drop_table(manager, file_tag::Entity).await
+    }
+}
@@ -0,0 +1,33 @@
+//! Legal holds
+
+use sea_orm_migration::prelude::*;
+
+use super::m20261017_000001_create_tables::{create_table, drop_table};
+use crate::entity::legal_hold;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        create_table(manager, legal_hold::Entity).await?;
+        // A path is held once
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_legal_hold_username_path")
+                    .table(legal_hold::Entity)
+                    .col(legal_hold::Column::Username)
+                    .col(legal_hold::Column::Path)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        drop_table(manager, legal_hold::Entity).await
+    }
+}
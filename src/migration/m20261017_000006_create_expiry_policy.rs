@@ -0,0 +1,33 @@
+//! Folder expiration policies
+
+use sea_orm_migration::prelude::*;
+
+use super::m20261017_000001_create_tables::{create_table, drop_table};
+use crate::entity::expiry_policy;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        create_table(manager, expiry_policy::Entity).await?;
+        // One policy per folder
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_expiry_policy_username_path")
+                    .table(expiry_policy::Entity)
+                    .col(expiry_policy::Column::Username)
+                    .col(expiry_policy::Column::Path)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        drop_table(manager, expiry_policy::Entity).await
+    }
+}
@@ -0,0 +1,33 @@
+//! Storage tiering
+
+use sea_orm_migration::prelude::*;
+
+use super::m20261017_000001_create_tables::{create_table, drop_table};
+use crate::entity::cold_file;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        create_table(manager, cold_file::Entity).await?;
+        // One stub per file
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_cold_file_username_path")
+                    .table(cold_file::Entity)
+                    .col(cold_file::Column::Username)
+                    .col(cold_file::Column::Path)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        drop_table(manager, cold_file::Entity).await
+    }
+}
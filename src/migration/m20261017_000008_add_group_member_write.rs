@@ -0,0 +1,42 @@
+//! Write access of group members to the group space
+
+use sea_orm_migration::prelude::*;
+
+use crate::entity::group;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // New databases get it from the baseline
+        if manager.has_column("disk_group", "member_write").await? {
+            return Ok(());
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(group::Entity)
+                    .add_column(
+                        ColumnDef::new(group::Column::MemberWrite)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(group::Entity)
+                    .drop_column(group::Column::MemberWrite)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,37 @@
+//! Compressed copies of files
+
+use sea_orm_migration::prelude::*;
+
+use crate::entity::cold_file;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // New databases get it from the table's migration
+        if manager.has_column("disk_cold_file", "compressed_size").await? {
+            return Ok(());
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(cold_file::Entity)
+                    .add_column(ColumnDef::new(cold_file::Column::CompressedSize).big_integer().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(cold_file::Entity)
+                    .drop_column(cold_file::Column::CompressedSize)
+                    .to_owned(),
+            )
+            .await
+    }
+}
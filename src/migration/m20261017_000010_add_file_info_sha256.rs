@@ -0,0 +1,50 @@
+//! Content hashes of uploaded files
+
+use sea_orm_migration::prelude::*;
+
+use crate::entity::file_info;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // New databases get it from the table's migration
+        if !manager.has_column("disk_file_info", "sha256").await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(file_info::Entity)
+                        .add_column(ColumnDef::new(file_info::Column::Sha256).string_len(64).null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+        // Uploads look up files with the same content
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_file_info_sha256")
+                    .table(file_info::Entity)
+                    .col(file_info::Column::Sha256)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("idx_file_info_sha256").table(file_info::Entity).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(file_info::Entity)
+                    .drop_column(file_info::Column::Sha256)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,36 @@
+//! Operation types stored as codes instead of Chinese names
+
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use crate::entity::op_log::{self, Lang, OpType};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Set the type of the rows of type `from` to `to`
+async fn rename(manager: &SchemaManager<'_>, from: &str, to: &str) -> Result<(), DbErr> {
+    op_log::Entity::update_many()
+        .col_expr(op_log::Column::OpType, Expr::value(to))
+        .filter(op_log::Column::OpType.eq(from))
+        .exec(manager.get_connection())
+        .await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for op in OpType::ALL {
+            rename(manager, op.label(Lang::Zh), op.code()).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for op in OpType::ALL {
+            rename(manager, op.code(), op.label(Lang::Zh)).await?;
+        }
+        Ok(())
+    }
+}
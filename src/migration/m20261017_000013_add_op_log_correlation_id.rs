@@ -0,0 +1,50 @@
+//! Correlation IDs of operation logs written by one batch request
+
+use sea_orm_migration::prelude::*;
+
+use crate::entity::op_log;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // New databases get it from the table's migration
+        if !manager.has_column("disk_op_log", "correlation_id").await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(op_log::Entity)
+                        .add_column(ColumnDef::new(op_log::Column::CorrelationId).string_len(36).null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+        // The audit log is filtered by it
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_op_log_correlation_id")
+                    .table(op_log::Entity)
+                    .col(op_log::Column::CorrelationId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("idx_op_log_correlation_id").table(op_log::Entity).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(op_log::Entity)
+                    .drop_column(op_log::Column::CorrelationId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,45 @@
+//! Watched folders and their activity
+
+use sea_orm_migration::prelude::*;
+
+use super::m20261017_000001_create_tables::{create_table, drop_table};
+use crate::entity::{watch, watch_event};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        create_table(manager, watch::Entity).await?;
+        // Watches of a tree, looked up for every change
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_watch_owner")
+                    .table(watch::Entity)
+                    .col(watch::Column::Owner)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        create_table(manager, watch_event::Entity).await?;
+        // Activity of a user, newest first
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_watch_event_username_id")
+                    .table(watch_event::Entity)
+                    .col(watch_event::Column::Username)
+                    .col(watch_event::Column::Id)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        drop_table(manager, watch_event::Entity).await?;
+        drop_table(manager, watch::Entity).await
+    }
+}
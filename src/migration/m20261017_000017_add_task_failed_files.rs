@@ -0,0 +1,37 @@
+//! Files a copy or move task failed on
+
+use sea_orm_migration::prelude::*;
+
+use crate::entity::task;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // New databases get it from the table's migration
+        if manager.has_column("disk_task", "failed_files").await? {
+            return Ok(());
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(task::Entity)
+                    .add_column(ColumnDef::new(task::Column::FailedFiles).text().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(task::Entity)
+                    .drop_column(task::Column::FailedFiles)
+                    .to_owned(),
+            )
+            .await
+    }
+}
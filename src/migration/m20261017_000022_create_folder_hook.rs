@@ -0,0 +1,31 @@
+//! Hooks run when files land in a folder
+
+use sea_orm_migration::prelude::*;
+
+use super::m20261017_000001_create_tables::{create_table, drop_table};
+use crate::entity::folder_hook;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        create_table(manager, folder_hook::Entity).await?;
+        // Hooks of a folder, looked up for every change
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_folder_hook_owner")
+                    .table(folder_hook::Entity)
+                    .col(folder_hook::Column::Owner)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        drop_table(manager, folder_hook::Entity).await
+    }
+}
@@ -0,0 +1,109 @@
+//! Database schema migrations
+//!
+//! Versioned migrations run by `sea-orm-migration`. Applied migrations are
+//! recorded in `seaql_migrations`, pending ones run at startup (or with
+//! `-migrate`). Schema changes go into a new `mYYYYMMDD_NNNNNN_name` module
+//! appended to [`Migrator::migrations`]; released migrations must not change.
+
+use sea_orm::{DatabaseConnection, DbErr};
+use sea_orm_migration::prelude::*;
+use tracing::info;
+
+mod m20261017_000001_create_tables;
+mod m20261017_000002_add_columns;
+mod m20261017_000003_create_traffic;
+mod m20261017_000004_create_file_tag;
+mod m20261017_000005_create_legal_hold;
+mod m20261017_000006_create_expiry_policy;
+mod m20261017_000007_create_cold_file;
+mod m20261017_000008_add_group_member_write;
+mod m20261017_000009_add_cold_file_compressed_size;
+mod m20261017_000010_add_file_info_sha256;
+mod m20261017_000011_op_log_type_codes;
+mod m20261017_000012_create_login_attempt;
+mod m20261017_000013_add_op_log_correlation_id;
+mod m20261017_000014_create_watch;
+mod m20261017_000015_create_digest;
+mod m20261017_000016_create_journal;
+mod m20261017_000017_add_task_failed_files;
+mod m20261017_000018_create_device;
+mod m20261017_000019_create_op_journal;
+mod m20261017_000020_create_sensitive_folder;
+mod m20261017_000021_create_dlp_detection;
+mod m20261017_000022_create_folder_hook;
+mod m20261017_000023_create_job_lease;
+
+pub struct Migrator;
+
+#[async_trait::async_trait]
+impl MigratorTrait for Migrator {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        vec![
+            Box::new(m20261017_000001_create_tables::Migration),
+            Box::new(m20261017_000002_add_columns::Migration),
+            Box::new(m20261017_000003_create_traffic::Migration),
+            Box::new(m20261017_000004_create_file_tag::Migration),
+            Box::new(m20261017_000005_create_legal_hold::Migration),
+            Box::new(m20261017_000006_create_expiry_policy::Migration),
+            Box::new(m20261017_000007_create_cold_file::Migration),
+            Box::new(m20261017_000008_add_group_member_write::Migration),
+            Box::new(m20261017_000009_add_cold_file_compressed_size::Migration),
+            Box::new(m20261017_000010_add_file_info_sha256::Migration),
+            Box::new(m20261017_000011_op_log_type_codes::Migration),
+            Box::new(m20261017_000012_create_login_attempt::Migration),
+            Box::new(m20261017_000013_add_op_log_correlation_id::Migration),
+            Box::new(m20261017_000014_create_watch::Migration),
+            Box::new(m20261017_000015_create_digest::Migration),
+            Box::new(m20261017_000016_create_journal::Migration),
+            Box::new(m20261017_000017_add_task_failed_files::Migration),
+            Box::new(m20261017_000018_create_device::Migration),
+            Box::new(m20261017_000019_create_op_journal::Migration),
+            Box::new(m20261017_000020_create_sensitive_folder::Migration),
+            Box::new(m20261017_000021_create_dlp_detection::Migration),
+            Box::new(m20261017_000022_create_folder_hook::Migration),
+            Box::new(m20261017_000023_create_job_lease::Migration),
+        ]
+    }
+}
+
+/// Apply all pending migrations
+pub async fn run(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let pending = Migrator::get_pending_migrations(db).await?;
+    if pending.is_empty() {
+        info!("Database schema is up to date");
+        return Ok(());
+    }
+    for migration in &pending {
+        info!("Applying migration {}", migration.name());
+    }
+    Migrator::up(db, None).await?;
+    info!("Applied {} migration(s)", pending.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{ConnectionTrait, Database};
+
+    #[tokio::test]
+    async fn test_upgrade_database_without_migrations() {
+        let dir = std::env::temp_dir().join(format!("datadisk-migration-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.join("old.db").display());
+        let db = Database::connect(&url).await.unwrap();
+        // A table created by a version from before the scopes column
+        db.execute_unprepared("CREATE TABLE disk_api_token (id INTEGER PRIMARY KEY, name TEXT)")
+            .await
+            .unwrap();
+
+        run(&db).await.unwrap();
+        let manager = SchemaManager::new(&db);
+        assert!(manager.has_column("disk_api_token", "scopes").await.unwrap());
+        assert!(manager.has_table("disk_file_info").await.unwrap());
+        assert!(Migrator::get_pending_migrations(&db).await.unwrap().is_empty());
+
+        db.close().await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+//! MIME type detection
+//!
+//! Combines the extension table with magic-byte sniffing of the file head:
+//! - [`from_name`] only looks at the extension, for cheap per-entry use such
+//!   as directory listings
+//! - [`detect`] / [`detect_file`] also sniff the content, which wins when it
+//!   contradicts the extension (an `.jpg` that is really a zip is served as a
+//!   zip). Container formats keep the more specific type of the extension, so
+//!   a `.docx` stays a Word document although its content is a zip.
+//! - [`signature_matches`] checks that content is what its extension claims
+
+use std::io::Read;
+use std::path::Path;
+
+/// Fallback for unknown content
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Bytes of the file head needed for sniffing (tar has its magic at 257)
+pub const SNIFF_LEN: usize = 512;
+
+const ZIP: &str = "application/zip";
+const OLE: &str = "application/x-ole-storage";
+const GZIP: &str = "application/gzip";
+
+/// MIME type of a lowercase extension (without dot)
+pub fn from_extension(ext: &str) -> Option<&'static str> {
+    let mime = match ext {
+        // Images
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "tif" | "tiff" => "image/tiff",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
+        // Documents
+        "pdf" => "application/pdf",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "docm" => "application/vnd.ms-word.document.macroEnabled.12",
+        "dotx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.template",
+        "xlsm" => "application/vnd.ms-excel.sheet.macroEnabled.12",
+        "xltx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.template",
+        "pptm" => "application/vnd.ms-powerpoint.presentation.macroEnabled.12",
+        "potx" => "application/vnd.openxmlformats-officedocument.presentationml.template",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "ods" => "application/vnd.oasis.opendocument.spreadsheet",
+        "odp" => "application/vnd.oasis.opendocument.presentation",
+        "epub" => "application/epub+zip",
+        "rtf" => "application/rtf",
+        // Text
+        "txt" | "md" | "log" | "ini" | "conf" | "toml" | "yaml" | "yml" | "sql" | "sh" | "rs"
+        | "go" | "py" | "java" | "c" | "h" | "cpp" => "text/plain",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "js" => "application/javascript",
+        "css" => "text/css",
+        "html" | "htm" => "text/html",
+        "xml" => "application/xml",
+        // Archives
+        "zip" => ZIP,
+        "rar" => "application/vnd.rar",
+        "7z" => "application/x-7z-compressed",
+        "tar" => "application/x-tar",
+        "gz" | "tgz" => GZIP,
+        "xz" | "txz" => "application/x-xz",
+        // Audio and video
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "ogv" => "video/ogg",
+        "mov" => "video/quicktime",
+        "mkv" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" | "opus" => "audio/ogg",
+        "m4a" => "audio/mp4",
+        "flac" => "audio/flac",
+        "aac" => "audio/aac",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+/// Lowercase extension of a file name
+pub fn extension(file_name: &str) -> String {
+    Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+/// MIME type from the file name alone
+pub fn from_name(file_name: &str) -> &'static str {
+    from_extension(&extension(file_name)).unwrap_or(OCTET_STREAM)
+}
+
+/// Whether a MIME type is textual
+pub fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || matches!(mime, "application/json" | "application/javascript" | "application/xml")
+}
+
+/// Whether browsers run scripts in content of a MIME type
+pub fn is_active(mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or("").trim();
+    matches!(
+        essence.to_ascii_lowercase().as_str(),
+        "text/html" | "application/xhtml+xml" | "image/svg+xml" | "application/xml" | "text/xml"
+    )
+}
+
+/// MIME type from magic bytes at the start of the content
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"PK\x03\x04", ZIP),
+        (b"PK\x05\x06", ZIP),
+        (b"PK\x07\x08", ZIP),
+        (b"Rar!", "application/vnd.rar"),
+        (&[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C], "application/x-7z-compressed"),
+        (&[0x1F, 0x8B], GZIP),
+        (&[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00], "application/x-xz"),
+        (&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1], OLE),
+        (b"%PDF", "application/pdf"),
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (&[0xFF, 0xD8, 0xFF], "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"{\\rtf", "application/rtf"),
+        (b"ID3", "audio/mpeg"),
+        (b"fLaC", "audio/flac"),
+        (b"OggS", "audio/ogg"),
+        (&[0x1A, 0x45, 0xDF, 0xA3], "video/x-matroska"),
+    ];
+
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(mime);
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" {
+        match &head[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            b"AVI " => return Some("video/x-msvideo"),
+            _ => {}
+        }
+    }
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return Some(match &head[8..12] {
+            b"qt  " => "video/quicktime",
+            b"M4A " => "audio/mp4",
+            b"heic" | b"heix" | b"mif1" => "image/heic",
+            _ => "video/mp4",
+        });
+    }
+    if head.len() >= 262 && &head[257..262] == b"ustar" {
+        return Some("application/x-tar");
+    }
+    None
+}
+
+/// Container a MIME type is stored in, for types with a generic signature
+fn container(mime: &str) -> &str {
+    match mime {
+        m if m.starts_with("application/vnd.openxmlformats-officedocument.")
+            || m.ends_with(".macroEnabled.12")
+            || m.starts_with("application/vnd.oasis.opendocument.")
+            || m == "application/epub+zip" =>
+        {
+            ZIP
+        }
+        "application/msword" | "application/vnd.ms-excel" | "application/vnd.ms-powerpoint" => OLE,
+        "audio/mp4" | "image/heic" | "video/quicktime" => "video/mp4",
+        "video/webm" => "video/x-matroska",
+        "video/ogg" => "audio/ogg",
+        m => m,
+    }
+}
+
+/// MIME type from the file name and the head of its content
+pub fn detect(file_name: &str, head: &[u8]) -> &'static str {
+    let by_ext = from_extension(&extension(file_name));
+    match (by_ext, sniff(head)) {
+        (Some(by_ext), Some(sniffed)) if container(by_ext) == container(sniffed) => by_ext,
+        // Generic container type, shown as the more useful extension type
+        (_, Some(OLE)) => by_ext.unwrap_or(OCTET_STREAM),
+        (_, Some(sniffed)) => sniffed,
+        (Some(by_ext), None) => by_ext,
+        (None, None) => OCTET_STREAM,
+    }
+}
+
+/// Read the head of a file for sniffing
+pub fn read_head(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// MIME type of a file on disk, falling back to the name if it can't be read
+pub async fn detect_file(path: &Path) -> &'static str {
+    use tokio::io::AsyncReadExt;
+
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    let read = match tokio::fs::File::open(path).await {
+        Ok(file) => file.take(SNIFF_LEN as u64).read_to_end(&mut head).await,
+        Err(e) => Err(e),
+    };
+    match read {
+        Ok(_) => detect(name, &head),
+        Err(_) => from_name(name),
+    }
+}
+
+/// Whether content matches the signature its extension requires
+///
+/// Formats without a signature (text and the like) always match.
+pub fn signature_matches(ext: &str, head: &[u8]) -> bool {
+    let Some(expected) = from_extension(ext) else {
+        return true;
+    };
+    let expected = container(expected);
+    let has_signature = expected == ZIP
+        || expected == OLE
+        || matches!(
+            expected,
+            "application/pdf" | "image/png" | "image/jpeg" | "image/gif" | "application/x-7z-compressed"
+                | "application/vnd.rar" | GZIP
+        );
+    !has_signature || sniff(head).is_some_and(|sniffed| container(sniffed) == expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+    const ZIP_HEAD: &[u8] = b"PK\x03\x04\x14\0\0\0";
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(from_name("photo.JPG"), "image/jpeg");
+        assert_eq!(from_name("doc.pdf"), "application/pdf");
+        assert_eq!(from_name("unknown.bin"), OCTET_STREAM);
+        assert_eq!(from_name("Makefile"), OCTET_STREAM);
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(PNG), Some("image/png"));
+        assert_eq!(sniff(ZIP_HEAD), Some(ZIP));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"\0\0\0\x18ftypisom"), Some("video/mp4"));
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff(&tar), Some("application/x-tar"));
+        assert_eq!(sniff(b"hello world"), None);
+    }
+
+    #[test]
+    fn test_detect() {
+        // Content wins over a wrong extension
+        assert_eq!(detect("photo.jpg", PNG), "image/png");
+        assert_eq!(detect("photo.jpg", ZIP_HEAD), ZIP);
+        // Containers keep the extension type
+        assert_eq!(detect("report.docx", ZIP_HEAD), from_name("report.docx"));
+        assert_eq!(
+            detect("old.xls", &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1]),
+            "application/vnd.ms-excel"
+        );
+        // Unknown content falls back to the extension
+        assert_eq!(detect("notes.txt", b"hello"), "text/plain");
+        assert_eq!(detect("blob", b"hello"), OCTET_STREAM);
+        assert_eq!(detect("blob", PNG), "image/png");
+    }
+
+    #[test]
+    fn test_signature_matches() {
+        assert!(signature_matches("docx", ZIP_HEAD));
+        assert!(!signature_matches("docx", b"<html>"));
+        assert!(signature_matches("doc", &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1]));
+        assert!(!signature_matches("pdf", b"<html>"));
+        assert!(signature_matches("pdf", b"%PDF-1.7"));
+        assert!(signature_matches("txt", b"anything"));
+        assert!(signature_matches("bin", b"anything"));
+    }
+}
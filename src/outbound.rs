@@ -0,0 +1,265 @@
+//! Outbound HTTP policy
+//!
+//! Every request the server makes to a URL it was handed (document server
+//! callbacks, HR sync sources, alert webhooks, ...) goes through [`get`] or
+//! [`post_json`], which guard against server-side request forgery:
+//! - only http(s) URLs to allowed hosts are fetched
+//! - hosts are resolved up front and the request is pinned to the checked
+//!   addresses, so DNS rebinding can't swap in another target
+//! - link-local and cloud metadata addresses are always denied, private and
+//!   loopback addresses unless the host is explicitly trusted
+//! - redirects are followed manually, checking every hop
+//! - requests time out and response bodies are size limited
+
+use anyhow::{anyhow, bail};
+use reqwest::{Response, Url};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Redirects followed before giving up
+const MAX_REDIRECTS: usize = 5;
+
+/// How an address may be used as a request target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddrClass {
+    Public,
+    /// Loopback and private networks, only for trusted hosts
+    Private,
+    /// Link-local, metadata, multicast and unspecified, never allowed
+    Denied,
+}
+
+fn classify_v4(ip: Ipv4Addr) -> AddrClass {
+    let octets = ip.octets();
+    if ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast() || ip.is_multicast() || octets[0] == 0 {
+        AddrClass::Denied
+    } else if ip.is_loopback()
+        || ip.is_private()
+        // Carrier-grade NAT 100.64.0.0/10
+        || (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+    {
+        AddrClass::Private
+    } else {
+        AddrClass::Public
+    }
+}
+
+fn classify(ip: IpAddr) -> AddrClass {
+    match ip {
+        IpAddr::V4(ip) => classify_v4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return classify_v4(v4);
+            }
+            let segments = ip.segments();
+            // AWS metadata over IPv6
+            const METADATA: Ipv6Addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+            if ip.is_unspecified()
+                || ip.is_multicast()
+                || ip == METADATA
+                // Link-local fe80::/10
+                || (segments[0] & 0xffc0) == 0xfe80
+            {
+                AddrClass::Denied
+            } else if ip.is_loopback()
+                // Unique local fc00::/7
+                || (segments[0] & 0xfe00) == 0xfc00
+            {
+                AddrClass::Private
+            } else {
+                AddrClass::Public
+            }
+        }
+    }
+}
+
+/// Whether `host` matches an allowlist entry ("example.com" or "*.example.com")
+fn host_matches(host: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim().to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{}", domain)),
+        None => host == pattern,
+    }
+}
+
+/// Host of the configured document server, which is always trusted
+fn doc_server_host(config: &Config) -> Option<String> {
+    Url::parse(&config.doc.doc_server_url)
+        .ok()?
+        .host_str()
+        .map(|h| h.to_ascii_lowercase())
+}
+
+/// Check a URL against the policy, returning its checked addresses
+async fn check_url(config: &Config, url: &Url) -> anyhow::Result<Vec<SocketAddr>> {
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("scheme {} is not allowed", url.scheme());
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("URL has no host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase();
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let policy = &config.outbound;
+    let listed = policy.allowed_hosts.iter().any(|p| host_matches(&host, p))
+        || doc_server_host(config).as_deref() == Some(host.as_str());
+    if !policy.allowed_hosts.is_empty() && !listed {
+        bail!("host {} is not allowed", host);
+    }
+
+    let addrs: Vec<SocketAddr> = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => tokio::net::lookup_host((host.as_str(), port)).await?.collect(),
+    };
+    if addrs.is_empty() {
+        bail!("host {} did not resolve", host);
+    }
+    for addr in &addrs {
+        match classify(addr.ip()) {
+            AddrClass::Public => {}
+            AddrClass::Private if listed || policy.allow_private => {}
+            _ => bail!("address {} of host {} is not allowed", addr.ip(), host),
+        }
+    }
+    Ok(addrs)
+}
+
+/// Client for a checked URL, pinned to its checked addresses
+async fn pinned_client(config: &Config, url: &Url) -> anyhow::Result<reqwest::Client> {
+    let policy = &config.outbound;
+    let addrs = check_url(config, url).await?;
+    let mut builder = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .connect_timeout(Duration::from_secs(policy.timeout_secs.min(30)))
+        .timeout(Duration::from_secs(policy.timeout_secs));
+    if let Some(host) = url.host_str() {
+        builder = builder.resolve_to_addrs(host, &addrs);
+    }
+    Ok(builder.build()?)
+}
+
+/// GET a URL under the outbound policy
+///
+/// Fails on disallowed targets, too many redirects, and responses that
+/// announce a body larger than the limit. Bodies without a length must be
+/// read with [`read_body`] or checked by the caller.
+pub async fn get(config: &Config, url: &str) -> anyhow::Result<Response> {
+    let policy = &config.outbound;
+    let mut url = Url::parse(url)?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let response = pinned_client(config, &url).await?.get(url.clone()).send().await?;
+
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| anyhow!("redirect without location"))?;
+            url = url.join(location)?;
+            continue;
+        }
+        if response
+            .content_length()
+            .is_some_and(|len| len > policy.max_response_size)
+        {
+            bail!("response exceeds {} bytes", policy.max_response_size);
+        }
+        return Ok(response);
+    }
+    bail!("too many redirects")
+}
+
+/// POST a JSON body to a URL under the outbound policy
+///
+/// Redirects are not followed, the body would have to be sent again to a
+/// target the caller didn't choose.
+pub async fn post_json(config: &Config, url: &str, body: &serde_json::Value) -> anyhow::Result<Response> {
+    let url = Url::parse(url)?;
+    let response = pinned_client(config, &url).await?.post(url).json(body).send().await?;
+    if response.status().is_redirection() {
+        bail!("redirect not followed for POST");
+    }
+    Ok(response)
+}
+
+/// Read a whole response body, enforcing the size limit
+pub async fn read_body(config: &Config, mut response: Response) -> anyhow::Result<Vec<u8>> {
+    let limit = config.outbound.max_response_size;
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (body.len() + chunk.len()) as u64 > limit {
+            bail!("response exceeds {} bytes", limit);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let class = |s: &str| classify(s.parse().unwrap());
+        assert_eq!(class("8.8.8.8"), AddrClass::Public);
+        assert_eq!(class("2606:4700::1111"), AddrClass::Public);
+        assert_eq!(class("127.0.0.1"), AddrClass::Private);
+        assert_eq!(class("10.1.2.3"), AddrClass::Private);
+        assert_eq!(class("172.20.0.1"), AddrClass::Private);
+        assert_eq!(class("192.168.1.1"), AddrClass::Private);
+        assert_eq!(class("100.100.1.1"), AddrClass::Private);
+        assert_eq!(class("::1"), AddrClass::Private);
+        assert_eq!(class("fd12::1"), AddrClass::Private);
+        assert_eq!(class("169.254.169.254"), AddrClass::Denied);
+        assert_eq!(class("::ffff:169.254.169.254"), AddrClass::Denied);
+        assert_eq!(class("fd00:ec2::254"), AddrClass::Denied);
+        assert_eq!(class("fe80::1"), AddrClass::Denied);
+        assert_eq!(class("0.0.0.0"), AddrClass::Denied);
+        assert_eq!(class("224.0.0.1"), AddrClass::Denied);
+    }
+
+    #[test]
+    fn test_host_matches() {
+        assert!(host_matches("hr.example.com", "hr.example.com"));
+        assert!(host_matches("hr.example.com", "HR.example.com"));
+        assert!(host_matches("a.b.example.com", "*.example.com"));
+        assert!(!host_matches("example.com", "*.example.com"));
+        assert!(!host_matches("evilexample.com", "*.example.com"));
+        assert!(!host_matches("hr.example.com.evil.org", "hr.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_check_url() {
+        let mut config = Config::default();
+        let check = |config: &Config, url: &str| {
+            let config = config.clone();
+            let url = Url::parse(url).unwrap();
+            async move { check_url(&config, &url).await }
+        };
+
+        assert!(check(&config, "http://8.8.8.8/x").await.is_ok());
+        assert!(check(&config, "ftp://8.8.8.8/x").await.is_err());
+        assert!(check(&config, "http://127.0.0.1/x").await.is_err());
+        assert!(check(&config, "http://[::1]:8080/x").await.is_err());
+        assert!(check(&config, "http://169.254.169.254/latest").await.is_err());
+
+        // The document server may be on a private network
+        config.doc.doc_server_url = "http://127.0.0.1:8082".to_string();
+        assert!(check(&config, "http://127.0.0.1:8082/cache/doc.docx").await.is_ok());
+
+        config.outbound.allowed_hosts = vec!["169.254.169.254".to_string()];
+        assert!(check(&config, "http://169.254.169.254/latest").await.is_err());
+        assert!(check(&config, "http://8.8.8.8/x").await.is_err());
+
+        config.outbound.allowed_hosts.clear();
+        config.outbound.allow_private = true;
+        assert!(check(&config, "http://10.0.0.1/x").await.is_ok());
+    }
+}
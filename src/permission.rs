@@ -2,10 +2,11 @@
 //!
 //! Implements RBAC permission management with Casbin
 
-use casbin::{CoreApi, DefaultModel, Enforcer, MgmtApi};
+use casbin::{CoreApi, DefaultModel, Enforcer, MgmtApi, RbacApi};
 use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tracing::instrument;
 
 use crate::entity::casbin_rule;
 
@@ -16,9 +17,13 @@ pub mod perm {
     pub const ROLE: &str = "role";
     pub const GROUP: &str = "group";
     pub const AUDIT: &str = "audit";
+    /// View audit logs of own department subtree only
+    pub const AUDIT_DEPT: &str = "audit_dept";
+    /// Manage users and departments of own department subtree only
+    pub const CONTACTS_DEPT: &str = "contacts_dept";
 
     /// All permissions
-    pub const ALL: [&str; 5] = [FILE, CONTACTS, ROLE, GROUP, AUDIT];
+    pub const ALL: [&str; 7] = [FILE, CONTACTS, ROLE, GROUP, AUDIT, AUDIT_DEPT, CONTACTS_DEPT];
 }
 
 /// Action constants
@@ -75,6 +80,7 @@ impl PermissionEnforcer {
     }
 
     /// Check if user has permission
+    #[instrument(level = "debug", skip(self))]
     pub async fn check(&self, user: &str, obj: &str, act: &str) -> bool {
         let enforcer = self.enforcer.read().await;
         enforcer.enforce((user, obj, act)).unwrap_or(false)
@@ -679,6 +685,16 @@ impl PermissionEnforcer {
         Ok(())
     }
 
+    /// IDs of the departments of a user, including those above its own
+    pub async fn get_user_departments(&self, user: &str) -> Vec<i64> {
+        let enforcer = self.enforcer.read().await;
+        enforcer
+            .get_implicit_roles_for_user(user, None)
+            .iter()
+            .filter_map(|role| role.strip_prefix(Self::DEPT_PREFIX)?.parse().ok())
+            .collect()
+    }
+
     /// Remove a department role and related policies
     pub async fn remove_department(&self, dept_id: i64) -> anyhow::Result<()> {
         let role_name = Self::dept_role_name(dept_id);
@@ -0,0 +1,98 @@
+//! Repositories
+//!
+//! Narrow traits over the queries shared code runs on `file_info`, `user`
+//! and `op_log`. They are implemented for [`DatabaseConnection`], so callers
+//! keep passing the connection, and tests can hand in their own
+//! implementation instead of a database.
+
+use async_trait::async_trait;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+};
+
+use crate::entity::{file_info, op_log, user};
+
+/// Queries on the file tree
+#[async_trait]
+pub trait FileInfoRepository: Send + Sync {
+    /// The row of a user's file by ID
+    async fn find_file(&self, username: &str, id: i64) -> Result<Option<file_info::Model>, DbErr>;
+
+    /// The entry `name` in the directory `parent_id`
+    async fn find_child(
+        &self,
+        username: &str,
+        parent_id: i64,
+        name: &str,
+    ) -> Result<Option<file_info::Model>, DbErr>;
+
+    /// All entries of the directory `parent_id`
+    async fn children(&self, username: &str, parent_id: i64) -> Result<Vec<file_info::Model>, DbErr>;
+
+    async fn delete_file(&self, id: i64) -> Result<(), DbErr>;
+}
+
+/// Queries on user accounts
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn find_user(&self, username: &str) -> Result<Option<user::Model>, DbErr>;
+}
+
+/// Writes to the operation log
+#[async_trait]
+pub trait OpLogRepository: Send + Sync {
+    async fn insert_log(&self, log: op_log::ActiveModel) -> Result<(), DbErr>;
+}
+
+#[async_trait]
+impl FileInfoRepository for DatabaseConnection {
+    async fn find_file(&self, username: &str, id: i64) -> Result<Option<file_info::Model>, DbErr> {
+        file_info::Entity::find_by_id(id)
+            .filter(file_info::Column::Username.eq(username))
+            .one(self)
+            .await
+    }
+
+    async fn find_child(
+        &self,
+        username: &str,
+        parent_id: i64,
+        name: &str,
+    ) -> Result<Option<file_info::Model>, DbErr> {
+        file_info::Entity::find()
+            .filter(file_info::Column::ParentId.eq(parent_id))
+            .filter(file_info::Column::Username.eq(username))
+            .filter(file_info::Column::Name.eq(name))
+            .one(self)
+            .await
+    }
+
+    async fn children(&self, username: &str, parent_id: i64) -> Result<Vec<file_info::Model>, DbErr> {
+        file_info::Entity::find()
+            .filter(file_info::Column::ParentId.eq(parent_id))
+            .filter(file_info::Column::Username.eq(username))
+            .all(self)
+            .await
+    }
+
+    async fn delete_file(&self, id: i64) -> Result<(), DbErr> {
+        file_info::Entity::delete_by_id(id).exec(self).await.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl UserRepository for DatabaseConnection {
+    async fn find_user(&self, username: &str) -> Result<Option<user::Model>, DbErr> {
+        user::Entity::find()
+            .filter(user::Column::Username.eq(username))
+            .one(self)
+            .await
+    }
+}
+
+#[async_trait]
+impl OpLogRepository for DatabaseConnection {
+    async fn insert_log(&self, log: op_log::ActiveModel) -> Result<(), DbErr> {
+        log.insert(self).await.map(|_| ())
+    }
+}
@@ -1,13 +1,16 @@
-use axum::{extract::State, response::Json};
+use axum::{extract::State, http::StatusCode, response::Json};
 use serde::Serialize;
 
+use crate::db::DbStatus;
 use crate::state::AppState;
 use super::ApiResponse;
 
 #[derive(Serialize)]
 pub struct HealthStatus {
+    /// `healthy`, or `degraded` while the database is down
     pub status: String,
     pub version: String,
+    pub database: DbStatus,
 }
 
 #[derive(Serialize)]
@@ -15,12 +18,20 @@ pub struct SetupStatus {
     pub initialized: bool,
 }
 
-/// Health check endpoint
-pub async fn health_check() -> Json<ApiResponse<HealthStatus>> {
-    Json(ApiResponse::success(HealthStatus {
-        status: "healthy".to_string(),
+/// Health check endpoint, 503 while the database is down so load balancers
+/// and probes see it
+pub async fn health_check(State(state): State<AppState>) -> (StatusCode, Json<ApiResponse<HealthStatus>>) {
+    let database = state.db_health.status();
+    let (code, status) = match database.available {
+        true => (StatusCode::OK, "healthy"),
+        false => (StatusCode::SERVICE_UNAVAILABLE, "degraded"),
+    };
+    let health = HealthStatus {
+        status: status.to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
-    }))
+        database,
+    };
+    (code, Json(ApiResponse::success(health)))
 }
 
 /// Check if system is initialized
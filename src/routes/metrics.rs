@@ -0,0 +1,36 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::metrics;
+use crate::state::AppState;
+
+/// Prometheus scrape endpoint
+///
+/// Not found unless enabled in the configuration; authenticated by the
+/// configured bearer token instead of a session.
+pub async fn serve_metrics(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let config = &state.config.metrics;
+    if !config.enabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if !config.token.is_empty() {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim);
+        if token != Some(config.token.as_str()) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    let db = state.get_db().await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(db.as_ref()),
+    )
+        .into_response()
+}
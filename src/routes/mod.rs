@@ -3,26 +3,33 @@ use axum::{
     http::StatusCode,
     middleware,
     response::Json,
-    routing::{delete, get, post},
+    routing::{any, delete, get, post},
     Router,
 };
 use serde::Serialize;
+use std::time::Duration;
 use tower_http::{
-    cors::{Any, CorsLayer},
     services::{ServeDir, ServeFile},
+    timeout::TimeoutLayer,
     trace::TraceLayer,
 };
-use tower_sessions::{MemoryStore, SessionManagerLayer};
+use tower_sessions::SessionManagerLayer;
 
 use crate::handlers;
-use crate::middleware::auth_layer;
+use crate::middleware::session::Store;
+use crate::middleware::{
+    auth_layer, cors_layer, maintenance_layer, metrics_layer, rate_limit_layer, tarpit_layer, RateLimits, Tarpit,
+};
 use crate::state::AppState;
+use crate::telemetry;
 use crate::ws;
 
 pub mod health;
+pub mod metrics;
+pub mod openapi;
 
 /// API response wrapper
-#[derive(Serialize)]
+#[derive(Serialize, utoipa::ToSchema)]
 pub struct ApiResponse<T: Serialize> {
     pub code: bool,
     pub message: String,
@@ -48,6 +55,14 @@ impl<T: Serialize> ApiResponse<T> {
     }
 }
 
+/// Documented shape of `ApiResponse<()>`, which has no `data`
+#[derive(utoipa::ToSchema)]
+#[allow(dead_code)]
+pub struct ApiMessage {
+    pub code: bool,
+    pub message: String,
+}
+
 impl ApiResponse<()> {
     pub fn success_msg(message: impl Into<String>) -> Self {
         Self {
@@ -60,17 +75,20 @@ impl ApiResponse<()> {
 
 /// Create the main router
 pub fn create_router(state: AppState) -> Router {
-    // Session store (in-memory for now)
-    let session_store = MemoryStore::default();
+    // Session store selected by the configuration
+    let session_store = Store::from_config(&state.config.session, &state);
     let session_layer = SessionManagerLayer::new(session_store)
         .with_secure(false) // Set to true in production with HTTPS
         .with_http_only(true);
 
-    // CORS configuration
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    // CORS configuration, explicit so token-authenticated clients can upload
+    let cors = cors_layer(&state.config.cors);
+
+    // Rate limits, checked after authentication so per-user limits apply
+    let rate_limits = RateLimits::new(&state.config.rate_limit);
+
+    // Decoys and slowed down failures, around authentication to see its 401s
+    let tarpit = Tarpit::new(&state.config);
 
     // API routes
     let api_routes = Router::new()
@@ -85,6 +103,7 @@ pub fn create_router(state: AppState) -> Router {
         .route("/login", post(handlers::auth::login))
         .route("/logout", post(handlers::auth::logout))
         .route("/user/current", get(handlers::auth::current_user))
+        .route("/user/digest", get(handlers::digest::get_digest).post(handlers::digest::set_digest))
         // Config routes
         .route("/config", get(handlers::config::get_config))
         // Department routes
@@ -99,20 +118,37 @@ pub fn create_router(state: AppState) -> Router {
         .route("/user/update", post(handlers::user::update_user))
         .route("/user/info", get(handlers::user::get_user_by_username))
         .route("/user/query", get(handlers::user::get_users_by_dept))
+        .route("/user/search", get(handlers::user::search_users))
         .route("/user/enable", post(handlers::user::enable_user))
         .route("/user/disable", post(handlers::user::disable_user))
+        .route("/user/unlock", post(handlers::user::unlock_user))
         .route("/user/change-password", post(handlers::user::change_password))
         .route("/user/reset-password", post(handlers::user::reset_password))
         // Avatar routes
         .route("/user/avatar/:username", get(handlers::user::get_user_avatar))
-        .route("/user/upload/avatar", post(handlers::user::upload_user_avatar))
         .route("/user/avatar/:username", delete(handlers::user::delete_user_avatar))
+        // Service account routes
+        .route("/service-account/list", get(handlers::service_account::list_service_accounts))
+        .route("/service-account/add", post(handlers::service_account::add_service_account))
+        .route("/service-account/delete", post(handlers::service_account::delete_service_accounts))
+        .route("/service-account/token", post(handlers::service_account::create_token))
+        .route("/service-account/token/revoke", post(handlers::service_account::revoke_token))
+        // Personal API token routes
+        .route("/token/create", post(handlers::token::create_token))
+        .route("/token/list", get(handlers::token::list_tokens))
+        .route("/token/revoke", post(handlers::token::revoke_token))
+        // Remembered device routes
+        .route("/device/list", get(handlers::device::list_devices))
+        .route("/device/revoke", post(handlers::device::revoke_device))
+        // HR sync webhook
+        .route("/hr/sync", post(handlers::hr_sync::sync_webhook))
         // Group routes
         .route("/group/add", post(handlers::group::add_group))
         .route("/group/delete", post(handlers::group::delete_group))
         .route("/group/query", get(handlers::group::get_groups))
         .route("/group/addUsers", post(handlers::group::add_users_to_group))
         .route("/group/deleteUsers", post(handlers::group::delete_users_from_group))
+        .route("/group/setMemberWrite", post(handlers::group::set_member_write))
         .route("/group/query/users", get(handlers::group::get_group_users))
         // Role routes
         .route("/role/add", post(handlers::role::add_role))
@@ -124,46 +160,139 @@ pub fn create_router(state: AppState) -> Router {
         .route("/file/mkdir", post(handlers::file::mkdir))
         .route("/file/remove/file", post(handlers::file::remove_file))
         .route("/file/query/files", get(handlers::file::get_files))
-        .route(
-            "/file/upload",
-            post(handlers::file::upload_file)
-                .layer(DefaultBodyLimit::max(state.config.max_upload_size)),
-        )
-        .route("/file/download", get(handlers::file::download_file))
+        .route("/file/upload/check", post(handlers::file::check_upload))
+        .route("/file/upload/precheck", post(handlers::file::precheck_upload))
         .route("/file/download/pre", post(handlers::file::download_pre))
         .route("/file/list", get(handlers::file::list_directory))
         .route("/file/rename", post(handlers::file::rename_file))
-        .route("/file/content", get(handlers::file::get_file_content))
+        .route("/file/touch", post(handlers::file::touch))
         .route("/file/delete", post(handlers::file::delete_files))
-        .route("/file/download/single", get(handlers::file::download_single_file))
-        .route("/file/preview/single", get(handlers::file::preview_single_file))
         .route("/file/copy", post(handlers::file::copy_move_file))
+        .route("/file/compress", post(handlers::archive_create::compress))
         .route("/file/resolve-conflict", post(handlers::file::resolve_conflict))
-        // Archive preview
-        .route("/archive/preview", get(handlers::archive_preview::archive_preview))
+        // Batch operations
+        .route("/file/batch", post(handlers::batch::batch))
+        // Bulk rename routes
+        .route("/file/rename/preview", post(handlers::bulk_rename::preview))
+        .route("/file/rename/bulk", post(handlers::bulk_rename::bulk_rename))
+        // Undo of the last operation
+        .route("/file/undo", post(handlers::undo::undo))
+        // File tag routes
+        .route("/file/tag/add", post(handlers::tag::add_tags))
+        .route("/file/tag/remove", post(handlers::tag::remove_tags))
+        .route("/file/tag/list", get(handlers::tag::list_tags))
+        .route("/file/tag/files", get(handlers::tag::files_by_tag))
+        // Sensitive folder routes
+        .route("/file/sensitive/set", post(handlers::sensitive::set_sensitive))
+        .route("/file/sensitive/unset", post(handlers::sensitive::unset_sensitive))
+        .route("/file/sensitive/list", get(handlers::sensitive::list_sensitive))
+        // Signed URLs
+        .route("/file/signed", get(handlers::signed::signed_url))
+        // Folder hook routes
+        .route("/file/hook/add", post(handlers::folder_hook::add_hook))
+        .route("/file/hook/remove", post(handlers::folder_hook::remove_hook))
+        .route("/file/hook/list", get(handlers::folder_hook::list_hooks))
+        // Folder watch routes
+        .route("/file/watch/add", post(handlers::watch::add_watch))
+        .route("/file/watch/remove", post(handlers::watch::remove_watch))
+        .route("/file/watch/list", get(handlers::watch::list_watches))
+        .route("/file/watch/events", get(handlers::watch::list_events))
+        .route("/file/watch/read", post(handlers::watch::mark_read))
+        // Temp artifact routes
+        .route("/artifact/usage", get(handlers::artifact::get_usage))
+        .route("/artifact/clean", post(handlers::artifact::clean))
+        // Maintenance jobs
+        .route("/admin/jobs", get(handlers::scheduler::list_jobs))
+        .route("/admin/jobs/run", post(handlers::scheduler::run_job))
+        .route("/admin/storage", get(handlers::storage_area::get_usage))
+        // Transparent compression
+        .route("/compression/usage", get(handlers::compression::get_usage))
+        // Archive extraction
+        .route("/archive/extract", post(handlers::archive_extract::extract))
         // Recent files routes
         .route("/file/recent", get(handlers::recent::get_recent_files))
         .route("/file/recent", delete(handlers::recent::clear_recent_files))
         .route("/file/recent/:id", delete(handlers::recent::delete_recent_file))
+        // Trash routes
+        .route("/trash/list", get(handlers::trash::list_trash))
+        .route("/trash/restore", post(handlers::trash::restore_trash))
+        .route("/trash/purge", post(handlers::trash::purge_trash))
         // Task routes
         .route("/task/query", get(handlers::task::get_tasks))
         .route("/task/cancel", post(handlers::task::cancel_task))
         .route("/task/suspend", post(handlers::task::suspend_task))
         .route("/task/resume", post(handlers::task::resume_task))
         .route("/task/delete", delete(handlers::task::delete_task))
+        .route("/task/history", get(handlers::task::get_task_history))
+        .route("/task/limit", get(handlers::task::get_limit).post(handlers::task::set_limit))
+        // Traffic statistics
+        .route("/stats/traffic", get(handlers::traffic::get_traffic))
+        // Abuse detection
+        .route("/abuse/alerts", get(handlers::abuse::get_alerts))
+        .route("/abuse/release", post(handlers::abuse::release))
+        // Folder expiration policies
+        .route("/expiry/list", get(handlers::expiry::list_policies))
+        .route("/expiry/set", post(handlers::expiry::set_policy))
+        .route("/expiry/remove", post(handlers::expiry::remove_policy))
+        .route("/expiry/pending", get(handlers::expiry::pending_items))
+        // Legal hold
+        .route("/legal-hold/list", get(handlers::legal_hold::list_holds))
+        .route("/legal-hold/set", post(handlers::legal_hold::set_hold))
+        .route("/legal-hold/release", post(handlers::legal_hold::release_hold))
+        // Data loss prevention
+        .route("/dlp/detections", get(handlers::dlp::list_detections))
+        .route("/dlp/approve", post(handlers::dlp::approve))
+        .route("/dlp/reject", post(handlers::dlp::reject))
         // Audit log routes
         .route("/oplog/query", get(handlers::audit::query_oplog))
         .route("/oplog/delete", post(handlers::audit::delete_oplog))
+        .route("/oplog/types", get(handlers::audit::get_op_types))
         // Document editing routes (OnlyOffice integration)
         .route("/editing/create", post(handlers::editing::create_editing_session))
-        .route("/editing/save/:sessionId", post(handlers::editing::save_editing_session))
-        .route("/editing/download/:sessionId", get(handlers::editing::get_editing_session))
         .route("/editing/query", get(handlers::editing::get_editing_session_info))
         // WebSocket
         .route("/ws", get(ws::serve_ws));
 
-    // Note: upload route has custom DefaultBodyLimit from config.max_upload_size
-    // The upload handler streams large files and returns user-friendly error messages
+    // Uploads, including documents saved by the document server.
+    // Note: the file upload route has no body limit, the limit depends on the
+    // user (see handlers::upload_limit) and the upload handler counts the bytes
+    let upload_routes = Router::new()
+        .route(
+            "/file/upload",
+            post(handlers::file::upload_file).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/file/content", post(handlers::file::save_file_content))
+        .route("/user/upload/avatar", post(handlers::user::upload_user_avatar))
+        .route("/user/import", post(handlers::user_import::import_users))
+        .route("/editing/save/:sessionId", post(handlers::editing::save_editing_session));
+
+    let download_routes = Router::new()
+        .route("/file/download", get(handlers::file::download_file))
+        .route(
+            "/file/download/archive",
+            get(handlers::archive_download::download_archive).post(handlers::archive_download::create_archive),
+        )
+        .route("/file/download/single", get(handlers::file::download_single_file))
+        .route("/editing/download/:sessionId", get(handlers::editing::get_editing_session))
+        .route("/stats/traffic/export", get(handlers::traffic::export_traffic))
+        .route("/oplog/export", get(handlers::audit::export_oplog));
+
+    let preview_routes = Router::new()
+        .route("/file/content", get(handlers::file::get_file_content))
+        .route("/file/thumbnail", get(handlers::thumbnail::get_thumbnail))
+        .route("/file/preview/single", get(handlers::file::preview_single_file))
+        .route("/file/audio/meta", get(handlers::media::audio_meta))
+        .route("/file/gallery", get(handlers::gallery::gallery))
+        .route("/file/preview/info", get(handlers::preview::get_preview_info))
+        .route("/file/preview/pdf", get(handlers::office_pdf::preview_pdf))
+        .route("/archive/preview", get(handlers::archive_preview::archive_preview));
+
+    // Each group with its own timeout and body limit
+    let limits = &state.config.request_limits;
+    let api_routes = limited(api_routes, limits.api_timeout_secs, limits.api_max_body_mb)
+        .merge(limited(upload_routes, limits.upload_timeout_secs, limits.upload_max_body_mb))
+        .merge(limited(download_routes, limits.download_timeout_secs, limits.download_max_body_mb))
+        .merge(limited(preview_routes, limits.preview_timeout_secs, limits.preview_max_body_mb));
 
     // Static file service for frontend
     // Serves files from webapp/dist, falls back to index.html for SPA routing
@@ -174,14 +303,42 @@ pub fn create_router(state: AppState) -> Router {
 
     Router::new()
         .nest("/api", api_routes)
+        // WebDAV (basic auth, handled by the WebDAV handler itself)
+        .route("/webdav", any(handlers::webdav::handle))
+        .route("/webdav/*path", any(handlers::webdav::handle))
+        // API documentation: /api/openapi.json and Swagger UI at /api/docs
+        .merge(openapi::swagger_ui())
+        // Prometheus scrape endpoint (own bearer token, see [metrics])
+        .route("/metrics", get(metrics::serve_metrics))
         .fallback_service(serve_dir)
+        .layer(middleware::from_fn_with_state(rate_limits, rate_limit_layer))
         .layer(middleware::from_fn_with_state(state.clone(), auth_layer))
+        .layer(middleware::from_fn_with_state(tarpit, tarpit_layer))
         .layer(session_layer)
-        .layer(TraceLayer::new_for_http())
+        // Nothing below runs while the database is down
+        .layer(middleware::from_fn_with_state(state.clone(), maintenance_layer))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::request_span)
+                .on_response(telemetry::record_response),
+        )
+        .layer(middleware::from_fn(metrics_layer))
         .layer(cors)
         .with_state(state)
 }
 
+/// Apply a timeout and a request body limit to a group of routes, 0 = none
+fn limited(routes: Router<AppState>, timeout_secs: u64, max_body_mb: u64) -> Router<AppState> {
+    let routes = match max_body_mb {
+        0 => routes.layer(DefaultBodyLimit::disable()),
+        mb => routes.layer(DefaultBodyLimit::max((mb * 1024 * 1024) as usize)),
+    };
+    match timeout_secs {
+        0 => routes,
+        secs => routes.layer(TimeoutLayer::new(Duration::from_secs(secs))),
+    }
+}
+
 /// Fallback handler for 404
 pub async fn fallback() -> (StatusCode, Json<ApiResponse<()>>) {
     (
@@ -189,3 +346,23 @@ pub async fn fallback() -> (StatusCode, Json<ApiResponse<()>>) {
         Json(ApiResponse::error(404, "Not Found")),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestApp;
+    use reqwest::StatusCode;
+
+    #[tokio::test]
+    async fn test_body_limits() {
+        let app = TestApp::spawn_with(|config| config.request_limits.api_max_body_mb = 1).await;
+        let admin = app.admin().await;
+
+        let name = "a".repeat(1536 * 1024);
+        let res = admin.post_json("/api/file/mkdir", &serde_json::json!({ "parentPath": "/", "name": name })).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // Uploads have limits of their own
+        let res = admin.upload("/", "big.bin", &vec![7u8; 3 * 1024 * 1024]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        app.close().await;
+    }
+}
@@ -0,0 +1,153 @@
+//! OpenAPI document of the REST API
+//!
+//! Generated from the annotated handlers, served at `/api/openapi.json` and
+//! browsable with Swagger UI at `/api/docs`.
+
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::handlers::{archive_create, archive_extract, batch, bulk_rename, digest, expiry, file, folder_hook, gallery, media, office_pdf, role, scheduler, sensitive, signed, storage_area, tag, task, traffic, undo, user, user_import, watch};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Datadisk API",
+        description = "Requests are authenticated by the session cookie set by `POST /api/login`, \
+                       or by an API token sent as `Authorization: Bearer <token>`."
+    ),
+    paths(
+        file::mkdir,
+        file::touch,
+        file::get_files,
+        file::remove_file,
+        file::upload_file,
+        file::check_upload,
+        file::precheck_upload,
+        file::download_pre,
+        file::download_file,
+        file::list_directory,
+        file::rename_file,
+        file::get_file_content,
+        file::save_file_content,
+        file::delete_files,
+        file::download_single_file,
+        file::preview_single_file,
+        media::audio_meta,
+        office_pdf::preview_pdf,
+        gallery::gallery,
+        file::copy_move_file,
+        file::resolve_conflict,
+        archive_create::compress,
+        archive_extract::extract,
+        batch::batch,
+        bulk_rename::preview,
+        bulk_rename::bulk_rename,
+        undo::undo,
+        signed::signed_url,
+        tag::add_tags,
+        tag::remove_tags,
+        tag::list_tags,
+        tag::files_by_tag,
+        sensitive::set_sensitive,
+        sensitive::unset_sensitive,
+        sensitive::list_sensitive,
+        folder_hook::add_hook,
+        folder_hook::remove_hook,
+        folder_hook::list_hooks,
+        watch::add_watch,
+        watch::remove_watch,
+        watch::list_watches,
+        watch::list_events,
+        watch::mark_read,
+        expiry::list_policies,
+        expiry::set_policy,
+        expiry::remove_policy,
+        expiry::pending_items,
+        user::add_user,
+        user::delete_user,
+        user::update_user,
+        user::get_user_by_username,
+        user::get_users_by_dept,
+        user::search_users,
+        user::enable_user,
+        user::disable_user,
+        user::unlock_user,
+        user::change_password,
+        user::reset_password,
+        user_import::import_users,
+        user::get_user_avatar,
+        user::upload_user_avatar,
+        user::delete_user_avatar,
+        digest::get_digest,
+        digest::set_digest,
+        role::add_role,
+        role::delete_role,
+        role::update_role,
+        role::get_roles,
+        role::get_available_permissions,
+        task::get_tasks,
+        task::cancel_task,
+        task::suspend_task,
+        task::resume_task,
+        task::delete_task,
+        task::get_task_history,
+        task::get_limit,
+        task::set_limit,
+        traffic::get_traffic,
+        traffic::export_traffic,
+        scheduler::list_jobs,
+        scheduler::run_job,
+        storage_area::get_usage,
+    ),
+    modifiers(&Security),
+    security(("session" = []), ("token" = [])),
+    tags(
+        (name = "file", description = "Files and folders of the signed-in user"),
+        (name = "user", description = "User accounts"),
+        (name = "role", description = "Roles and their permissions"),
+        (name = "task", description = "Background copy, move and archive tasks"),
+        (name = "stats", description = "Usage statistics"),
+        (name = "admin", description = "Maintenance jobs and storage areas"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Session cookie and API token authentication
+struct Security;
+
+impl Modify for Security {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("id"))),
+        );
+        components.add_security_scheme("token", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+    }
+}
+
+/// Routes of the document and the Swagger UI
+pub fn swagger_ui() -> SwaggerUi {
+    SwaggerUi::new("/api/docs").url("/api/openapi.json", ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let paths = doc["paths"].as_object().unwrap();
+        assert!(paths.contains_key("/api/file/upload"));
+        assert!(paths.contains_key("/api/user/avatar/{username}"));
+        assert_eq!(doc["paths"]["/api/task/history"]["get"]["tags"][0], "task");
+
+        // Request and response shapes use the JSON field names
+        let schemas = &doc["components"]["schemas"];
+        assert!(schemas["MkdirRequest"]["properties"]["parentPath"].is_object());
+        assert!(schemas["TaskInfo"]["properties"]["copiedSize"].is_object());
+        assert!(schemas["UploadForm"]["properties"]["parentId"].is_object());
+    }
+}
@@ -0,0 +1,770 @@
+//! File operations of a user
+//!
+//! [`FileService`] keeps a user's directory tree and its `file_info` rows in
+//! step: it checks names and paths, changes the filesystem, updates the
+//! database and records the audit log. Callers only translate requests and
+//! results for their protocol.
+//!
+//! The directory tree is reached through a [`StorageBackend`], the local
+//! filesystem unless [`FileService::with_storage`] sets another one. Moving
+//! to the trash always works on the local filesystem.
+
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait,
+    QueryFilter, Set, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::config::Config;
+use crate::entity::{file_access, file_info, trash};
+use crate::entity::op_log::OpType;
+use crate::filename::{self, NameError};
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::dir_version;
+use crate::handlers::file::{
+    delete_children, get_user_path, resolve_dir_id, resolve_in_user_root,
+};
+use crate::handlers::journal::{self, Intent};
+use crate::handlers::legal_hold;
+use crate::handlers::quota;
+use crate::handlers::sensitive;
+use crate::handlers::tag;
+use crate::handlers::thumbnail;
+use crate::handlers::tiering;
+use crate::handlers::traffic;
+use crate::handlers::trash::{move_to_trash, purge_item};
+use crate::handlers::watch::{self, ChangeKind, Client};
+use crate::metrics;
+use crate::mime;
+use crate::storage::{LocalStorage, StorageBackend};
+
+const OP_SUCCESS: &str = "成功";
+
+/// Why a file operation failed
+#[derive(Debug, Error)]
+pub enum FileError {
+    #[error("{0}")]
+    InvalidName(#[from] NameError),
+    #[error("invalid path")]
+    InvalidPath,
+    #[error("file not found")]
+    NotFound,
+    #[error("file already exists")]
+    AlreadyExists,
+    #[error("parent directory does not exist")]
+    ParentNotFound,
+    /// The path of the hold keeping the file from being changed
+    #[error("{0} is under legal hold")]
+    LegalHold(String),
+    /// The path of a file changed since the client saw it
+    #[error("{0} was modified")]
+    Modified(String),
+    #[error("database error: {0}")]
+    Database(#[from] DbErr),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Other(#[from] anyhow::Error),
+}
+
+/// Directory listing item
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DirectoryItem {
+    pub basename: String,
+    pub filename: String,
+    #[serde(rename = "type")]
+    pub item_type: String,
+    pub size: i64,
+    pub lastmod: String,
+    pub mime: String,
+    /// `cold` for files moved to cold storage, recalled when read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tier: Option<String>,
+    /// Whether `/api/file/thumbnail` has a thumbnail of the file, an image
+    /// or a video poster frame
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub thumbnail: bool,
+    /// Whether the recursive size of the directory is still being computed,
+    /// with `withDirSize`
+    #[serde(rename = "sizePending", skip_serializing_if = "std::ops::Not::not")]
+    pub size_pending: bool,
+}
+
+/// `lastmod` of a listing item
+fn lastmod(modified: Option<std::time::SystemTime>) -> String {
+    modified
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .and_then(|d| chrono::DateTime::from_timestamp(d.as_secs() as i64, 0))
+        .map(|dt| dt.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .unwrap_or_default()
+}
+
+/// Modification time of a file as the client saw it, for changes that must
+/// not apply to a file replaced since
+///
+/// Either the `lastmod` of `/api/file/list` or the same time in Unix seconds.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum IfMatch {
+    Seconds(i64),
+    Lastmod(String),
+}
+
+impl IfMatch {
+    /// Whether a file last modified at `modified` is the one the client saw
+    pub fn matches(&self, modified: Option<std::time::SystemTime>) -> bool {
+        match self {
+            IfMatch::Seconds(seconds) => modified
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .is_some_and(|d| d.as_secs() as i64 == *seconds),
+            IfMatch::Lastmod(seen) => *seen == lastmod(modified),
+        }
+    }
+}
+
+/// `name` inside `parent`, relative to the user root
+fn join(parent: &str, name: &str) -> String {
+    match parent.trim_matches('/') {
+        "" => name.to_string(),
+        parent => format!("{}/{}", parent, name),
+    }
+}
+
+/// File operations on behalf of one user
+pub struct FileService<'a> {
+    config: &'a Config,
+    db: &'a DatabaseConnection,
+    /// Owner of the tree
+    username: &'a str,
+    storage: &'a dyn StorageBackend,
+    /// User the operations are recorded for
+    actor: &'a str,
+    /// Path of the tree's root as the actor sees it
+    prefix: &'a str,
+    /// Where the operations come from, for watchers of the changed folders
+    client: Client,
+}
+
+impl<'a> FileService<'a> {
+    pub fn new(config: &'a Config, db: &'a DatabaseConnection, username: &'a str) -> Self {
+        Self { config, db, username, storage: &LocalStorage, actor: username, prefix: "", client: Client::Web }
+    }
+
+    /// Operations made by `client` rather than the web UI
+    pub fn via(self, client: Client) -> Self {
+        Self { client, ..self }
+    }
+
+    /// Tell the watchers of `relative` about a change
+    fn changed(&self, relative: &str, kind: ChangeKind) {
+        watch::publish(self.username, relative, self.actor, kind, self.client);
+    }
+
+    /// Use `storage` instead of the local filesystem
+    pub fn with_storage(self, storage: &'a dyn StorageBackend) -> Self {
+        Self { storage, ..self }
+    }
+
+    /// Work in a tree that isn't the actor's own, such as a department space
+    /// shown at `prefix`
+    pub fn on_behalf_of(self, actor: &'a str, prefix: &'a str) -> Self {
+        Self { actor, prefix, ..self }
+    }
+
+    /// `relative` as the actor sees it, for the audit log
+    fn shown(&self, relative: &str) -> String {
+        match relative.trim_matches('/') {
+            "" if !self.prefix.is_empty() => self.prefix.to_string(),
+            relative => format!("{}/{}", self.prefix, relative),
+        }
+    }
+
+    /// Absolute path of a path relative to the user root
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, FileError> {
+        resolve_in_user_root(self.config, self.username, path).ok_or(FileError::InvalidPath)
+    }
+
+    /// Refuse changing `path` if a legal hold covers it or anything below it
+    pub async fn check_hold(&self, path: &str) -> Result<(), FileError> {
+        match legal_hold::held(self.db, self.username, path).await? {
+            Some(hold) => Err(FileError::LegalHold(self.shown(&hold.path))),
+            None => Ok(()),
+        }
+    }
+
+    /// Refuse changing `path` if it was modified since the client saw it
+    pub async fn check_unchanged(&self, path: &str, seen: &IfMatch) -> Result<(), FileError> {
+        let metadata = match self.storage.metadata(&self.resolve(path)?).await {
+            Ok(metadata) => metadata,
+            Err(_) => return Err(FileError::NotFound),
+        };
+        if seen.matches(metadata.modified) {
+            Ok(())
+        } else {
+            Err(FileError::Modified(self.shown(path)))
+        }
+    }
+
+    /// Row ID of a directory, -1 for the root
+    async fn dir_id(&self, path: &str) -> Result<i64, FileError> {
+        match resolve_dir_id(self.db, self.username, path).await {
+            0 => Err(FileError::ParentNotFound),
+            id => Ok(id),
+        }
+    }
+
+    /// Row ID of the parent directory, from the client's ID if it sent one
+    async fn parent_id(&self, parent_path: &str, parent_id: Option<i64>) -> Result<i64, FileError> {
+        match parent_id {
+            Some(id) if id > 0 => {
+                let count = file_info::Entity::find_by_id(id)
+                    .filter(file_info::Column::Username.eq(self.username))
+                    .filter(file_info::Column::IsDirectory.eq(true))
+                    .count(self.db)
+                    .await?;
+                if count == 0 {
+                    return Err(FileError::ParentNotFound);
+                }
+                Ok(id)
+            }
+            _ => self.dir_id(parent_path).await,
+        }
+    }
+
+    /// List a directory
+    #[instrument(level = "debug", skip(self), fields(user = self.username))]
+    pub async fn list(&self, path: &str) -> Result<Vec<DirectoryItem>, FileError> {
+        let full_path = self.resolve(path)?;
+        let path = if path.is_empty() { "/" } else { path };
+
+        // The user root is created on first access
+        self.storage.create_dir_all(&get_user_path(self.config, self.username)).await?;
+        if !self.storage.exists(&full_path).await {
+            return Err(FileError::NotFound);
+        }
+
+        let mut stubs = tiering::stubs_in(self.db, self.username, path).await?;
+        let mut items = Vec::new();
+        for entry in self.storage.read_dir(&full_path).await? {
+            let metadata = entry.metadata;
+            let basename = entry.name;
+            let filename = format!("{}/{}", self.shown(path).trim_end_matches('/'), basename);
+            let (item_type, mime) = if metadata.is_dir {
+                ("directory".to_string(), String::new())
+            } else {
+                ("file".to_string(), mime::from_name(&basename).to_string())
+            };
+            let lastmod = lastmod(metadata.modified);
+            // Stubs are empty, the size is the one of the file they stand for
+            let stub = stubs.remove(&basename).filter(|_| !metadata.is_dir && metadata.len == 0);
+            let thumbnail = !metadata.is_dir && thumbnail::has_thumbnail(self.config, &mime::extension(&basename));
+
+            items.push(DirectoryItem {
+                basename,
+                filename,
+                item_type,
+                size: stub.as_ref().map_or(metadata.len as i64, |stub| stub.size),
+                lastmod,
+                mime,
+                // Compressed files are read as quickly as any other
+                tier: stub.filter(|stub| stub.compressed_size.is_none()).map(|_| tiering::COLD.to_string()),
+                thumbnail,
+                size_pending: false,
+            });
+        }
+
+        self.log_open(path);
+        Ok(items)
+    }
+
+    /// Record that the actor opened the directory `path`
+    pub fn log_open(&self, path: &str) {
+        let path = if path.is_empty() { "/" } else { path };
+        log_operation(self.actor, OpType::OpenFile, &self.shown(path), OP_SUCCESS, None);
+    }
+
+    /// Create a directory
+    #[instrument(level = "debug", skip(self), fields(user = self.username))]
+    pub async fn mkdir(
+        &self,
+        parent_path: &str,
+        parent_id: Option<i64>,
+        name: &str,
+    ) -> Result<file_info::Model, FileError> {
+        let name = filename::check_new_name(name)?;
+        let relative = join(parent_path, &name);
+        let dir_path = self.resolve(&relative)?;
+        if self.storage.exists(&dir_path).await {
+            return Err(FileError::AlreadyExists);
+        }
+        let parent_id = self.parent_id(parent_path, parent_id).await?;
+
+        // The row is only kept if the directory could be created, dropping
+        // the transaction rolls it back
+        let now = chrono::Utc::now().timestamp();
+        let txn = self.db.begin().await?;
+        let model = file_info::ActiveModel {
+            username: Set(self.username.to_string()),
+            file_type: Set("dir".to_string()),
+            name: Set(name),
+            parent_id: Set(parent_id),
+            create_time: Set(now),
+            modify_time: Set(now),
+            is_directory: Set(true),
+            size: Set(0),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
+        self.storage.create_dir_all(&dir_path).await?;
+        txn.commit().await?;
+        dir_version::bump_entry(self.username, &relative);
+        self.changed(&relative, ChangeKind::Created);
+
+        log_operation(self.actor, OpType::Mkdir, &self.shown(&relative), OP_SUCCESS, None);
+        Ok(model)
+    }
+
+    /// Create the file `name` in `parent_path` holding `content`, such as an
+    /// empty file or one made from a template
+    #[instrument(level = "debug", skip(self, content), fields(user = self.username, len = content.len()))]
+    pub async fn touch(
+        &self,
+        parent_path: &str,
+        parent_id: Option<i64>,
+        name: &str,
+        content: &[u8],
+    ) -> Result<file_info::Model, FileError> {
+        let name = filename::check_new_name(name)?;
+        let relative = join(parent_path, &name);
+        let dest = self.resolve(&relative)?;
+        if self.storage.exists(&dest).await {
+            return Err(FileError::AlreadyExists);
+        }
+        let parent_id = self.parent_id(parent_path, parent_id).await?;
+
+        self.storage.write(&dest, content).await?;
+        let size = content.len() as i64;
+        let sha256 = hex::encode(Sha256::digest(content));
+        let model = match self.record_upload(&relative, parent_id, &name, size, Some(sha256)).await {
+            Ok(model) => model,
+            Err(e) => {
+                let _ = self.storage.remove_file(&dest).await;
+                return Err(e);
+            }
+        };
+        dir_version::bump_entry(self.username, &relative);
+        self.changed(&relative, ChangeKind::Created);
+        quota::add_usage(self.username, size);
+
+        log_operation(self.actor, OpType::CreateFile, &self.shown(&relative), OP_SUCCESS, None);
+        Ok(model)
+    }
+
+    /// Move an uploaded temp file to its place and record it
+    ///
+    /// An existing file of the same name is replaced. The temp file is
+    /// removed if the upload can't be stored. `sha256` is the hash of the
+    /// content if the caller computed it.
+    #[instrument(level = "debug", skip(self), fields(user = self.username))]
+    pub async fn upload_finalize(
+        &self,
+        tmp_path: &Path,
+        parent_path: &str,
+        parent_id: Option<i64>,
+        name: &str,
+        size: i64,
+        sha256: Option<String>,
+    ) -> Result<file_info::Model, FileError> {
+        let result = self.store_upload(tmp_path, parent_path, parent_id, name, size, sha256, OpType::Upload).await;
+        if result.is_err() {
+            let _ = self.storage.remove_file(tmp_path).await;
+        }
+        result
+    }
+
+    /// Replace the content of the file `{parent_path}/{name}` with the temp
+    /// file written by an editor, like an upload but logged as an edit
+    ///
+    /// The temp file is removed if it can't be stored.
+    #[instrument(level = "debug", skip(self), fields(user = self.username))]
+    pub async fn save_content(
+        &self,
+        tmp_path: &Path,
+        parent_path: &str,
+        name: &str,
+        size: i64,
+    ) -> Result<file_info::Model, FileError> {
+        let result = self.store_upload(tmp_path, parent_path, None, name, size, None, OpType::EditFile).await;
+        if result.is_err() {
+            let _ = self.storage.remove_file(tmp_path).await;
+        }
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn store_upload(
+        &self,
+        tmp_path: &Path,
+        parent_path: &str,
+        parent_id: Option<i64>,
+        name: &str,
+        size: i64,
+        sha256: Option<String>,
+        op: OpType,
+    ) -> Result<file_info::Model, FileError> {
+        let name = filename::check_new_name(name)?;
+        let relative = join(parent_path, &name);
+        let dest = self.resolve(&relative)?;
+        let parent_id = self.parent_id(parent_path, parent_id).await?;
+        let replaced = self.storage.metadata(&dest).await.ok();
+        if replaced.as_ref().is_some_and(|m| m.is_dir) {
+            return Err(FileError::AlreadyExists);
+        }
+        if replaced.is_some() {
+            self.check_hold(&relative).await?;
+        }
+        if let Some(parent) = dest.parent() {
+            self.storage.create_dir_all(parent).await?;
+        }
+
+        let kind = if replaced.is_some() { ChangeKind::Modified } else { ChangeKind::Created };
+        let replaced_size = replaced.map(|m| m.len as i64).unwrap_or(0);
+        let intent = Intent::Upload {
+            owner: self.username.to_string(),
+            tmp: tmp_path.to_path_buf(),
+            path: relative.clone(),
+            size,
+            sha256: sha256.clone(),
+        };
+        let entry = journal::begin(self.db, &intent).await?;
+        let result = self
+            .put_upload(tmp_path, &dest, &relative, parent_id, &name, size, sha256, kind, replaced_size, op)
+            .await;
+        journal::finish(self.db, entry).await;
+        result
+    }
+
+    /// Put an upload checked by [`Self::store_upload`] in place and record it
+    #[allow(clippy::too_many_arguments)]
+    async fn put_upload(
+        &self,
+        tmp_path: &Path,
+        dest: &Path,
+        relative: &str,
+        parent_id: i64,
+        name: &str,
+        size: i64,
+        sha256: Option<String>,
+        kind: ChangeKind,
+        replaced_size: i64,
+        op: OpType,
+    ) -> Result<file_info::Model, FileError> {
+        self.storage.rename(tmp_path, dest).await?;
+        dir_version::bump_entry(self.username, relative);
+        self.changed(relative, kind);
+        if let Err(e) = tiering::discard(self.db, self.username, relative).await {
+            tracing::error!("Failed to drop the cold copy of {}: {}", relative, e);
+        }
+        quota::add_usage(self.username, size - replaced_size);
+        metrics::add_upload_bytes(size.max(0) as u64);
+        traffic::add_upload(self.actor, size.max(0) as u64);
+
+        let model = self.record_upload(relative, parent_id, name, size, sha256).await?;
+        log_operation(self.actor, op, &self.shown(relative), OP_SUCCESS, None);
+        Ok(model)
+    }
+
+    /// Create or update the row of an uploaded file put at `relative`
+    pub(crate) async fn record_upload(
+        &self,
+        relative: &str,
+        parent_id: i64,
+        name: &str,
+        size: i64,
+        sha256: Option<String>,
+    ) -> Result<file_info::Model, FileError> {
+        // Record the type of the content, not the one claimed by the client
+        let dest = self.resolve(relative)?;
+        let content_type = match self.storage.read_head(&dest, mime::SNIFF_LEN).await {
+            Ok(head) => mime::detect(name, &head),
+            Err(_) => mime::from_name(name),
+        };
+        let now = chrono::Utc::now().timestamp();
+        let existing = file_info::Entity::find()
+            .filter(file_info::Column::Username.eq(self.username))
+            .filter(file_info::Column::ParentId.eq(parent_id))
+            .filter(file_info::Column::Name.eq(name))
+            .one(self.db)
+            .await?;
+        let model = match existing {
+            Some(row) => {
+                let mut row: file_info::ActiveModel = row.into();
+                row.file_type = Set(content_type.to_string());
+                row.size = Set(size);
+                row.modify_time = Set(now);
+                row.sha256 = Set(sha256);
+                row.update(self.db).await?
+            }
+            None => {
+                file_info::ActiveModel {
+                    username: Set(self.username.to_string()),
+                    name: Set(name.to_string()),
+                    file_type: Set(content_type.to_string()),
+                    size: Set(size),
+                    parent_id: Set(parent_id),
+                    create_time: Set(now),
+                    modify_time: Set(now),
+                    is_directory: Set(false),
+                    sha256: Set(sha256),
+                    ..Default::default()
+                }
+                .insert(self.db)
+                .await?
+            }
+        };
+        Ok(model)
+    }
+
+    /// Rename a file or directory in place, returning the stored new name
+    #[instrument(level = "debug", skip(self), fields(user = self.username))]
+    pub async fn rename(&self, old_path: &str, new_name: &str) -> Result<String, FileError> {
+        let old_relative = old_path.trim_matches('/');
+        if old_relative.is_empty() {
+            return Err(FileError::InvalidPath);
+        }
+        let new_name = filename::check_new_name(new_name)?;
+        let old_full = self.resolve(old_relative)?;
+        let (parent_path, old_name) = old_relative.rsplit_once('/').unwrap_or(("", old_relative));
+        let new_full = old_full.with_file_name(&new_name);
+
+        if !self.storage.exists(&old_full).await {
+            return Err(FileError::NotFound);
+        }
+        if self.storage.exists(&new_full).await {
+            return Err(FileError::AlreadyExists);
+        }
+        self.check_hold(old_relative).await?;
+        self.storage.rename(&old_full, &new_full).await?;
+
+        // Only rows of this directory, other directories may have the same name
+        let parent_id = resolve_dir_id(self.db, self.username, parent_path).await;
+        let result = file_info::Entity::update_many()
+            .col_expr(file_info::Column::Name, sea_orm::sea_query::Expr::value(&new_name))
+            .filter(file_info::Column::Username.eq(self.username))
+            .filter(file_info::Column::ParentId.eq(parent_id))
+            .filter(file_info::Column::Name.eq(old_name))
+            .exec(self.db)
+            .await;
+        if let Err(e) = result {
+            if let Err(re) = self.storage.rename(&new_full, &old_full).await {
+                tracing::error!("Failed to roll back rename of {}: {}", old_relative, re);
+            }
+            return Err(e.into());
+        }
+
+        let new_relative = join(parent_path, &new_name);
+        dir_version::bump_entry(self.username, old_relative);
+        dir_version::bump_entry(self.username, &new_relative);
+        self.changed(&new_relative, ChangeKind::Renamed);
+        if let Err(e) = tag::move_tags(self.db, self.username, old_relative, &new_relative).await {
+            tracing::error!("Failed to move tags of {}: {}", old_relative, e);
+        }
+        if let Err(e) = tiering::move_stubs(self.db, self.username, old_relative, &new_relative).await {
+            tracing::error!("Failed to move cold storage stubs of {}: {}", old_relative, e);
+        }
+        if let Err(e) = sensitive::move_marks(self.db, self.username, old_relative, &new_relative).await {
+            tracing::error!("Failed to move sensitive marks of {}: {}", old_relative, e);
+        }
+
+        let op_desc = format!("{} => {}", self.shown(old_relative), new_name);
+        log_operation(self.actor, OpType::Rename, &op_desc, OP_SUCCESS, None);
+        Ok(new_name)
+    }
+
+    /// Move a file or directory to the trash, returning the ID of the trash item
+    #[instrument(level = "debug", skip(self), fields(user = self.username))]
+    pub async fn delete(&self, parent_path: &str, name: &str) -> Result<i64, FileError> {
+        let item = self.trash(parent_path, name).await?;
+        log_operation(self.actor, OpType::Delete, &self.shown(&join(parent_path, name)), OP_SUCCESS, None);
+        Ok(item.id)
+    }
+
+    /// Remove a file or directory for good, without keeping it in the trash
+    ///
+    /// Passing through the trash keeps quota, cold storage and moves to another
+    /// volume in one place, and a file whose purge fails stays restorable.
+    #[instrument(level = "debug", skip(self), fields(user = self.username))]
+    pub async fn delete_permanently(&self, parent_path: &str, name: &str) -> Result<(), FileError> {
+        let item = self.trash(parent_path, name).await?;
+        let relative = join(parent_path, name);
+        let op_type = match purge_item(self.config, self.db, &item).await {
+            Ok(()) => OpType::Purge,
+            Err(e) => {
+                tracing::error!("Failed to purge {}, left in the trash: {}", relative, e);
+                OpType::Delete
+            }
+        };
+        log_operation(self.actor, op_type, &self.shown(&relative), OP_SUCCESS, None);
+        Ok(())
+    }
+
+    /// Move a file or directory to the trash and remove its rows
+    async fn trash(&self, parent_path: &str, name: &str) -> Result<trash::Model, FileError> {
+        if filename::check_name(name).is_err() {
+            return Err(FileError::InvalidPath);
+        }
+        let relative = join(parent_path, name);
+        let full_path = self.resolve(&relative)?;
+        if tokio::fs::symlink_metadata(&full_path).await.is_err() {
+            return Err(FileError::NotFound);
+        }
+        self.check_hold(&relative).await?;
+
+        let item = move_to_trash(self.config, self.db, self.username, parent_path, name).await?;
+        self.remove_rows(parent_path, name).await?;
+        self.changed(&relative, ChangeKind::Deleted);
+        Ok(item)
+    }
+
+    /// Move the file or directory with the row `id` in `parent_path` to the
+    /// trash, or remove it for good if `permanent`
+    ///
+    /// Returns the ID of the trash item it was moved to, `None` if removed.
+    #[instrument(level = "debug", skip(self), fields(user = self.username))]
+    pub async fn delete_by_id(&self, parent_path: &str, id: i64, permanent: bool) -> Result<Option<i64>, FileError> {
+        let name = self.name_by_id(id).await?;
+        if permanent {
+            self.delete_permanently(parent_path, &name).await.map(|()| None)
+        } else {
+            self.delete(parent_path, &name).await.map(Some)
+        }
+    }
+
+    /// Name of the row `id` in the tree
+    pub async fn name_by_id(&self, id: i64) -> Result<String, FileError> {
+        let row = file_info::Entity::find_by_id(id)
+            .filter(file_info::Column::Username.eq(self.username))
+            .one(self.db)
+            .await?
+            .ok_or(FileError::NotFound)?;
+        Ok(row.name)
+    }
+
+    /// Remove the rows of `name` in `parent_path`, with their contents and recent access
+    ///
+    /// Paths without rows (created outside the web UI) are ignored.
+    pub async fn remove_rows(&self, parent_path: &str, name: &str) -> Result<(), FileError> {
+        let parent_id = resolve_dir_id(self.db, self.username, parent_path).await;
+        if parent_id == 0 {
+            return Ok(());
+        }
+
+        let rows = file_info::Entity::find()
+            .filter(file_info::Column::Username.eq(self.username))
+            .filter(file_info::Column::ParentId.eq(parent_id))
+            .filter(file_info::Column::Name.eq(name))
+            .all(self.db)
+            .await?;
+        for row in rows {
+            file_access::Entity::delete_many()
+                .filter(file_access::Column::FileId.eq(row.id))
+                .exec(self.db)
+                .await?;
+            if row.is_directory {
+                delete_children(self.db, row.id, self.username).await;
+            } else {
+                file_info::Entity::delete_by_id(row.id).exec(self.db).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::testing::TestEnv;
+
+    #[tokio::test]
+    async fn test_mkdir_upload_list() {
+        let env = TestEnv::new().await;
+        let service = FileService::new(&env.config, &env.db, "alice");
+
+        let docs = service.mkdir("/", None, "docs").await.unwrap();
+        assert!(matches!(service.mkdir("/", None, "docs").await, Err(FileError::AlreadyExists)));
+        assert!(matches!(service.mkdir("/", None, "CON").await, Err(FileError::InvalidName(_))));
+        assert!(matches!(service.mkdir("/missing", None, "x").await, Err(FileError::ParentNotFound)));
+
+        let tmp = env.dir.join("upload.tmp");
+        std::fs::write(&tmp, b"hello").unwrap();
+        let file = service.upload_finalize(&tmp, "/docs", None, "a.txt", 5, None).await.unwrap();
+        assert_eq!(file.parent_id, docs.id);
+        assert_eq!(file.file_type, "text/plain");
+        // Replacing keeps a single row
+        std::fs::write(&tmp, b"hello world").unwrap();
+        service.upload_finalize(&tmp, "/docs", Some(docs.id), "a.txt", 11, None).await.unwrap();
+        assert_eq!(env.file_rows().await, vec![(-1, "docs".to_string()), (docs.id, "a.txt".to_string())]);
+
+        let items = service.list("/docs").await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].filename, "/docs/a.txt");
+        assert_eq!(items[0].size, 11);
+        assert!(matches!(service.list("/nope").await, Err(FileError::NotFound)));
+
+        env.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_rename_delete() {
+        let env = TestEnv::new().await;
+        let service = FileService::new(&env.config, &env.db, "alice");
+
+        let docs = service.mkdir("", None, "docs").await.unwrap();
+        let tmp = env.dir.join("upload.tmp");
+        std::fs::write(&tmp, b"hello").unwrap();
+        service.upload_finalize(&tmp, "docs", None, "a.txt", 5, None).await.unwrap();
+
+        assert_eq!(service.rename("/docs/a.txt", "b.txt").await.unwrap(), "b.txt");
+        assert!(env.config.root_dir.join("alice/docs/b.txt").is_file());
+        assert!(matches!(service.rename("/docs/a.txt", "c.txt").await, Err(FileError::NotFound)));
+        assert_eq!(env.file_rows().await, vec![(-1, "docs".to_string()), (docs.id, "b.txt".to_string())]);
+
+        service.delete("/", "docs").await.unwrap();
+        assert!(!env.config.root_dir.join("alice/docs").exists());
+        assert!(env.file_rows().await.is_empty());
+        assert!(matches!(service.delete("/", "docs").await, Err(FileError::NotFound)));
+
+        env.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage() {
+        let env = TestEnv::new().await;
+        let storage = MemoryStorage::new();
+        let service = FileService::new(&env.config, &env.db, "alice").with_storage(&storage);
+
+        let docs = service.mkdir("/", None, "docs").await.unwrap();
+        let tmp = env.dir.join("upload.tmp");
+        storage.create_dir_all(&env.dir).await.unwrap();
+        storage.write(&tmp, b"%PDF-1.7").await.unwrap();
+        let file = service.upload_finalize(&tmp, "/docs", None, "a.txt", 8, None).await.unwrap();
+        assert_eq!(file.parent_id, docs.id);
+        assert_eq!(file.file_type, "application/pdf");
+        assert_eq!(service.rename("/docs/a.txt", "a.pdf").await.unwrap(), "a.pdf");
+
+        let items = service.list("/docs").await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].filename, "/docs/a.pdf");
+        // Nothing was written to disk
+        assert!(!env.config.root_dir.exists());
+
+        env.close().await;
+    }
+}
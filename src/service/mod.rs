@@ -0,0 +1,8 @@
+//! Service layer
+//!
+//! Operations shared by the HTTP handlers and the other frontends (WebDAV),
+//! independent of how requests arrive and responses are shaped.
+
+pub mod file;
+
+pub use file::{FileError, FileService, IfMatch};
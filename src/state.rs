@@ -3,6 +3,7 @@ use std::sync::Arc;
 use tokio::sync::{broadcast, RwLock};
 
 use crate::config::Config;
+use crate::db::DbHealth;
 use crate::permission::PermissionEnforcer;
 
 /// WebSocket notification message
@@ -23,6 +24,8 @@ pub struct AppState {
     pub config: Arc<Config>,
     /// WebSocket notification sender
     pub ws_sender: broadcast::Sender<WsNotification>,
+    /// Whether the database is reachable, see [`crate::db::start`]
+    pub db_health: Arc<DbHealth>,
 }
 
 impl AppState {
@@ -37,6 +40,7 @@ impl AppState {
         Self {
             db: Arc::new(RwLock::new(db)),
             perm: Arc::new(RwLock::new(perm)),
+            db_health: Arc::new(DbHealth::new(&config.db_health)),
             config: Arc::new(config),
             ws_sender,
         }
@@ -0,0 +1,299 @@
+//! Storage backends
+//!
+//! [`StorageBackend`] is the part of the filesystem the file service works
+//! with. [`LocalStorage`] is the real one; with the `test_support` feature
+//! [`MemoryStorage`] keeps the tree in memory so tests don't need a
+//! directory on disk.
+//!
+//! Operations of [`LocalStorage`] are traced in `debug` spans, exported with
+//! [`crate::telemetry`].
+
+use async_trait::async_trait;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+use tracing::instrument;
+
+/// What the file service needs to know about an entry
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Entry of a directory listing
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+/// Filesystem operations used by the file service
+///
+/// Paths are absolute, already resolved inside the user root.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Metadata of an entry, following symlinks
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Entries of a directory, in no particular order
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>>;
+
+    /// Create a directory and its missing parents
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Move a file or directory
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Remove a file
+    async fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Up to `len` bytes from the start of a file
+    async fn read_head(&self, path: &Path, len: usize) -> io::Result<Vec<u8>>;
+
+    /// Create or replace a file
+    async fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    async fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).await.is_ok()
+    }
+}
+
+/// The local filesystem
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalStorage;
+
+impl From<std::fs::Metadata> for Metadata {
+    fn from(m: std::fs::Metadata) -> Self {
+        Self {
+            is_dir: m.is_dir(),
+            len: m.len(),
+            modified: m.modified().ok(),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    #[instrument(level = "debug", skip(self))]
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        tokio::fs::metadata(path).await.map(Metadata::from)
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let mut entries = tokio::fs::read_dir(path).await?;
+        let mut items = Vec::new();
+        while let Some(entry) = entries.next_entry().await.ok().flatten() {
+            // Entries that vanish or can't be read are left out
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            items.push(DirEntry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                metadata: metadata.into(),
+            });
+        }
+        Ok(items)
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::create_dir_all(path).await
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        tokio::fs::rename(from, to).await
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::remove_file(path).await
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    async fn read_head(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        use tokio::io::AsyncReadExt;
+
+        let file = tokio::fs::File::open(path).await?;
+        let mut head = Vec::with_capacity(len);
+        file.take(len as u64).read_to_end(&mut head).await?;
+        Ok(head)
+    }
+
+    #[instrument(level = "debug", skip(self, data), fields(len = data.len()))]
+    async fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        tokio::fs::write(path, data).await
+    }
+}
+
+#[cfg(any(test, feature = "test_support"))]
+pub use memory::MemoryStorage;
+
+#[cfg(any(test, feature = "test_support"))]
+mod memory {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    enum Node {
+        Dir,
+        File { data: Vec<u8>, modified: SystemTime },
+    }
+
+    impl Node {
+        fn metadata(&self) -> Metadata {
+            match self {
+                Node::Dir => Metadata { is_dir: true, len: 0, modified: None },
+                Node::File { data, modified } => Metadata {
+                    is_dir: false,
+                    len: data.len() as u64,
+                    modified: Some(*modified),
+                },
+            }
+        }
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path.display()))
+    }
+
+    /// A directory tree kept in memory
+    #[derive(Default)]
+    pub struct MemoryStorage {
+        nodes: Mutex<BTreeMap<PathBuf, Node>>,
+    }
+
+    impl MemoryStorage {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        fn check_parent(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> io::Result<()> {
+            match path.parent() {
+                Some(parent) if !matches!(nodes.get(parent), Some(Node::Dir)) => Err(not_found(parent)),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl StorageBackend for MemoryStorage {
+        async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+            let nodes = self.nodes.lock().unwrap();
+            nodes.get(path).map(Node::metadata).ok_or_else(|| not_found(path))
+        }
+
+        async fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+            let nodes = self.nodes.lock().unwrap();
+            if !matches!(nodes.get(path), Some(Node::Dir)) {
+                return Err(not_found(path));
+            }
+            Ok(nodes
+                .iter()
+                .filter(|(p, _)| p.parent() == Some(path))
+                .map(|(p, node)| DirEntry {
+                    name: p.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                    metadata: node.metadata(),
+                })
+                .collect())
+        }
+
+        async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            let mut nodes = self.nodes.lock().unwrap();
+            for dir in path.ancestors() {
+                match nodes.get(dir) {
+                    Some(Node::Dir) => break,
+                    Some(Node::File { .. }) => {
+                        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "not a directory"));
+                    }
+                    None => {
+                        nodes.insert(dir.to_path_buf(), Node::Dir);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            let mut nodes = self.nodes.lock().unwrap();
+            if !nodes.contains_key(from) {
+                return Err(not_found(from));
+            }
+            Self::check_parent(&nodes, to)?;
+
+            // Move the entry and everything below it
+            let moved: Vec<PathBuf> = nodes.keys().filter(|p| p.starts_with(from)).cloned().collect();
+            for old in moved {
+                let node = nodes.remove(&old).unwrap();
+                let new = to.join(old.strip_prefix(from).unwrap());
+                nodes.insert(new, node);
+            }
+            Ok(())
+        }
+
+        async fn remove_file(&self, path: &Path) -> io::Result<()> {
+            let mut nodes = self.nodes.lock().unwrap();
+            match nodes.get(path) {
+                Some(Node::File { .. }) => {
+                    nodes.remove(path);
+                    Ok(())
+                }
+                Some(Node::Dir) => Err(io::Error::new(io::ErrorKind::InvalidInput, "is a directory")),
+                None => Err(not_found(path)),
+            }
+        }
+
+        async fn read_head(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+            let nodes = self.nodes.lock().unwrap();
+            match nodes.get(path) {
+                Some(Node::File { data, .. }) => Ok(data[..len.min(data.len())].to_vec()),
+                Some(Node::Dir) => Err(io::Error::new(io::ErrorKind::InvalidInput, "is a directory")),
+                None => Err(not_found(path)),
+            }
+        }
+
+        async fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+            let mut nodes = self.nodes.lock().unwrap();
+            Self::check_parent(&nodes, path)?;
+            if matches!(nodes.get(path), Some(Node::Dir)) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "is a directory"));
+            }
+            let node = Node::File { data: data.to_vec(), modified: SystemTime::now() };
+            nodes.insert(path.to_path_buf(), node);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_storage() {
+        let storage = MemoryStorage::new();
+        let root = Path::new("/data/alice");
+
+        storage.create_dir_all(&root.join("docs")).await.unwrap();
+        storage.write(&root.join("docs/a.txt"), b"hello").await.unwrap();
+        assert!(storage.write(&root.join("missing/a.txt"), b"x").await.is_err());
+
+        let items = storage.read_dir(root).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].name, "docs");
+        assert!(items[0].metadata.is_dir);
+
+        // Renaming a directory moves its content
+        storage.rename(&root.join("docs"), &root.join("notes")).await.unwrap();
+        assert!(!storage.exists(&root.join("docs/a.txt")).await);
+        assert_eq!(storage.read_head(&root.join("notes/a.txt"), 4).await.unwrap(), b"hell");
+        assert_eq!(storage.metadata(&root.join("notes/a.txt")).await.unwrap().len, 5);
+
+        storage.remove_file(&root.join("notes/a.txt")).await.unwrap();
+        assert!(storage.read_dir(&root.join("notes")).await.unwrap().is_empty());
+    }
+}
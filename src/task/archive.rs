@@ -0,0 +1,192 @@
+//! Archive task implementation
+//!
+//! Builds a zip of a user's files into a temp file in the background, so very
+//! large folder downloads can be served with Range support and resumed.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use super::control::{Progress, TaskControl};
+use super::manager::{Task, TaskChange, TaskInfo, TaskNotification, TaskStatus, TaskType};
+use super::ConflictPolicy;
+
+/// Collect (archive name, path) of every file and empty directory below
+/// `files` of `base_dir`; names of directories end with '/'
+pub(super) fn collect(base_dir: &Path, files: &[String]) -> Result<Vec<(String, PathBuf)>, String> {
+    let mut entries = Vec::new();
+    let mut stack: Vec<PathBuf> = files.iter().map(|f| base_dir.join(f)).collect();
+
+    while let Some(path) = stack.pop() {
+        let name = path
+            .strip_prefix(base_dir)
+            .map(|p| p.to_string_lossy().replace('\\', "/"))
+            .map_err(|_| "invalid path".to_string())?;
+        let metadata = std::fs::symlink_metadata(&path)
+            .map_err(|e| format!("failed to stat {}: {}", name, e))?;
+        if metadata.is_dir() {
+            let children: Vec<PathBuf> = std::fs::read_dir(&path)
+                .map_err(|e| format!("failed to read directory {}: {}", name, e))?
+                .flatten()
+                .map(|e| e.path())
+                .collect();
+            if children.is_empty() {
+                entries.push((format!("{}/", name), path));
+            }
+            stack.extend(children);
+        } else if metadata.is_file() {
+            entries.push((name, path));
+        }
+    }
+    Ok(entries)
+}
+
+/// Write `entries` to a zip in `file`, reporting progress to `control`
+pub(super) fn write_zip(
+    control: &TaskControl,
+    file: std::fs::File,
+    entries: &[(String, PathBuf)],
+    method: zip::CompressionMethod,
+) -> Result<(), String> {
+    let mut zip = zip::ZipWriter::new(std::io::BufWriter::new(file));
+    let options: zip::write::FileOptions<()> =
+        zip::write::FileOptions::default().compression_method(method).large_file(true);
+
+    for (name, path) in entries {
+        control.checkpoint()?;
+        if name.ends_with('/') {
+            zip.add_directory(name.as_str(), options)
+                .map_err(|e| format!("failed to add {}: {}", name, e))?;
+        } else {
+            control.start_entry(name, path);
+            zip.start_file(name.as_str(), options)
+                .map_err(|e| format!("failed to add {}: {}", name, e))?;
+            let src = std::fs::File::open(path).map_err(|e| format!("failed to open {}: {}", name, e))?;
+            std::io::copy(&mut Progress::new(control, src), &mut zip)
+                .map_err(|e| format!("failed to add {}: {}", name, e))?;
+        }
+        control.update(|info| info.copied_files += 1);
+    }
+
+    zip.finish().map_err(|e| format!("failed to finish archive: {}", e))?;
+    Ok(())
+}
+
+/// Archive build task
+pub struct ArchiveTask {
+    control: TaskControl,
+    /// Directory the listed files are relative to
+    base_dir: PathBuf,
+    /// Final archive location
+    archive_path: PathBuf,
+}
+
+impl ArchiveTask {
+    pub fn new(
+        user_id: i64,
+        parent_dir: String,
+        files: Vec<String>,
+        user_dir: &Path,
+        archive_dir: &Path,
+        notify_tx: broadcast::Sender<TaskNotification>,
+        change_tx: broadcast::Sender<TaskChange>,
+    ) -> Self {
+        let mut info = TaskInfo::new(user_id, "web", TaskType::Archive);
+        let archive_path = archive_dir.join(format!("{}.zip", info.id));
+        info.is_copy = false;
+        info.source = parent_dir.clone();
+        info.target = format!("{}.zip", info.id);
+        info.files = files;
+
+        Self {
+            control: TaskControl::new(info, notify_tx, change_tx),
+            base_dir: user_dir.join(parent_dir.trim_start_matches('/')),
+            archive_path,
+        }
+    }
+
+    /// Write the archive, reporting progress as bytes are added
+    fn build(&self) -> Result<(), String> {
+        let files = self.control.read().files.clone();
+        let entries = collect(&self.base_dir, &files)?;
+        let total_size = entries
+            .iter()
+            .filter(|(name, _)| !name.ends_with('/'))
+            .map(|(_, path)| std::fs::metadata(path).map(|m| m.len() as i64).unwrap_or(0))
+            .sum();
+        self.control.update(|info| {
+            info.status = TaskStatus::Running;
+            info.total_files = entries.len() as i64;
+            info.total_size = total_size;
+        });
+
+        if let Some(parent) = self.archive_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("failed to create archive directory: {}", e))?;
+        }
+        let part_path = self.archive_path.with_extension("zip.part");
+        let file = std::fs::File::create(&part_path).map_err(|e| format!("failed to create archive: {}", e))?;
+
+        // Stored, like the streaming download: archives are mostly already-compressed data
+        let result = write_zip(&self.control, file, &entries, zip::CompressionMethod::Stored);
+        match result {
+            Ok(()) => std::fs::rename(&part_path, &self.archive_path)
+                .map_err(|e| format!("failed to finish archive: {}", e)),
+            Err(e) => {
+                let _ = std::fs::remove_file(&part_path);
+                Err(e)
+            }
+        }
+    }
+
+    fn run(&self) {
+        self.control.update(|info| {
+            info.status = TaskStatus::Starting;
+            info.started_at = chrono::Utc::now().timestamp();
+        });
+
+        let result = self.build();
+        if self.control.is_cancelled() {
+            // Status was already set by cancel()
+            return;
+        }
+        match result {
+            Ok(()) => self.control.update(|info| info.status = TaskStatus::Completed),
+            Err(e) => self.control.update(|info| {
+                info.status = TaskStatus::Failed;
+                info.error = Some(e);
+            }),
+        }
+    }
+}
+
+impl Task for ArchiveTask {
+    fn info(&self) -> TaskInfo {
+        self.control.info()
+    }
+
+    fn id(&self) -> String {
+        self.control.id()
+    }
+
+    fn enqueue(&self) {
+        self.control.enqueue()
+    }
+
+    fn start(self: Arc<Self>) {
+        tokio::task::spawn_blocking(move || self.run());
+    }
+
+    fn cancel(&self) {
+        self.control.cancel()
+    }
+
+    fn suspend(&self) {
+        self.control.suspend()
+    }
+
+    fn resume(&self) {
+        self.control.resume()
+    }
+
+    fn resolve_conflict(&self, _policy: ConflictPolicy) {}
+}
@@ -0,0 +1,288 @@
+//! Compress task implementation
+//!
+//! Packs files of a folder into a zip or tar.gz archive stored in the user's
+//! tree, so a selection can be archived without downloading and uploading
+//! it again. The archive is written to a work directory first and moved into
+//! the target folder when complete, under a free name.
+
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+use super::archive::{collect, write_zip};
+use super::control::{Progress, TaskControl};
+use super::manager::{Task, TaskChange, TaskDir, TaskInfo, TaskNotification, TaskStatus, TaskType};
+use super::ConflictPolicy;
+use crate::entity::op_log::OpType;
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::dir_version;
+use crate::handlers::file::resolve_in_root;
+use crate::handlers::quota;
+use crate::handlers::watch::{self as watched, ChangeKind, Client};
+
+const OP_SUCCESS: &str = "成功";
+const OP_FAILED: &str = "失败";
+
+/// Format of a created archive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Extension of the archive file, with the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => ".zip",
+            ArchiveFormat::TarGz => ".tar.gz",
+        }
+    }
+
+    /// File name of an archive called `name`, adding the extension if missing
+    pub fn file_name(self, name: &str) -> String {
+        if name.to_ascii_lowercase().ends_with(self.extension()) {
+            name.to_string()
+        } else {
+            format!("{}{}", name, self.extension())
+        }
+    }
+}
+
+/// Name of `file_name` not taken in `dir`, numbering it before the extension
+fn free_name(dir: &Path, file_name: &str, format: ArchiveFormat) -> String {
+    let stem = &file_name[..file_name.len() - format.extension().len()];
+    let mut candidate = file_name.to_string();
+    let mut n = 1;
+    while dir.join(&candidate).exists() {
+        candidate = format!("{} ({}){}", stem, n, format.extension());
+        n += 1;
+    }
+    candidate
+}
+
+/// Task packing files into an archive
+pub struct CompressTask {
+    control: TaskControl,
+    /// User the archive is created for
+    username: String,
+    /// Folder of the files
+    from: TaskDir,
+    /// Folder the archive is created in
+    to: TaskDir,
+    /// File name of the archive, with extension
+    file_name: String,
+    format: ArchiveFormat,
+    /// Where the archive is built
+    work_dir: PathBuf,
+}
+
+impl CompressTask {
+    /// `source` and `target` are the folders as the user sees them
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        user_id: i64,
+        username: &str,
+        source: String,
+        target: String,
+        files: Vec<String>,
+        from: TaskDir,
+        to: TaskDir,
+        file_name: String,
+        format: ArchiveFormat,
+        work_dir: PathBuf,
+        notify_tx: broadcast::Sender<TaskNotification>,
+        change_tx: broadcast::Sender<TaskChange>,
+    ) -> Self {
+        let mut info = TaskInfo::new(user_id, "web", TaskType::Compress);
+        info.source = source;
+        info.target = format!("{}/{}", target.trim_end_matches('/'), file_name);
+        info.files = files;
+
+        Self {
+            control: TaskControl::new(info, notify_tx, change_tx),
+            username: username.to_string(),
+            from,
+            to,
+            file_name,
+            format,
+            work_dir,
+        }
+    }
+
+    /// Build the archive and move it into the target folder, returning its
+    /// path relative to the target root
+    fn build(&self) -> Result<String, String> {
+        let base_dir = resolve_in_root(&self.from.root, &self.from.path).ok_or("invalid source path")?;
+        let files = self.control.read().files.clone();
+        let entries = collect(&base_dir, &files)?;
+        let total_size = entries
+            .iter()
+            .filter(|(name, _)| !name.ends_with('/'))
+            .map(|(_, path)| std::fs::metadata(path).map(|m| m.len() as i64).unwrap_or(0))
+            .sum();
+        self.control.update(|info| {
+            info.status = TaskStatus::Running;
+            info.total_files = entries.len() as i64;
+            info.total_size = total_size;
+        });
+
+        std::fs::create_dir_all(&self.work_dir).map_err(|e| format!("failed to create work directory: {}", e))?;
+        let part_path = self.work_dir.join(format!("{}.part", self.control.read().id));
+        let file = std::fs::File::create(&part_path).map_err(|e| format!("failed to create archive: {}", e))?;
+        let written = match self.format {
+            ArchiveFormat::Zip => write_zip(&self.control, file, &entries, zip::CompressionMethod::Deflated),
+            ArchiveFormat::TarGz => self.write_tar_gz(file, &entries),
+        };
+        let placed = written.and_then(|()| self.place(&part_path));
+        if placed.is_err() {
+            let _ = std::fs::remove_file(&part_path);
+        }
+        placed
+    }
+
+    /// Move the built archive into the target folder
+    fn place(&self, part_path: &Path) -> Result<String, String> {
+        let dir = resolve_in_root(&self.to.root, &self.to.path).ok_or("invalid target path")?;
+        let name = free_name(&dir, &self.file_name, self.format);
+        let path = dir.join(&name);
+        if std::fs::rename(part_path, &path).is_err() {
+            // The work directory may be on another filesystem
+            std::fs::copy(part_path, &path).map_err(|e| format!("failed to store archive: {}", e))?;
+            let _ = std::fs::remove_file(part_path);
+        }
+        let size = std::fs::metadata(&path).map(|m| m.len() as i64).unwrap_or(0);
+        quota::add_usage(&self.to.owner, size);
+        let relative = path
+            .strip_prefix(&self.to.root)
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or(name);
+        Ok(relative)
+    }
+
+    fn write_tar_gz(&self, file: std::fs::File, entries: &[(String, PathBuf)]) -> Result<(), String> {
+        let gz = GzEncoder::new(BufWriter::new(file), flate2::Compression::default());
+        let mut tar = tar::Builder::new(gz);
+
+        for (name, path) in entries {
+            self.control.checkpoint()?;
+            if name.ends_with('/') {
+                tar.append_dir(name.trim_end_matches('/'), path)
+                    .map_err(|e| format!("failed to add {}: {}", name, e))?;
+            } else {
+                self.control.start_entry(name, path);
+                let src = std::fs::File::open(path).map_err(|e| format!("failed to open {}: {}", name, e))?;
+                let metadata = src.metadata().map_err(|e| format!("failed to stat {}: {}", name, e))?;
+                let mut header = tar::Header::new_gnu();
+                header.set_metadata(&metadata);
+                tar.append_data(&mut header, name, Progress::new(&self.control, src))
+                    .map_err(|e| format!("failed to add {}: {}", name, e))?;
+            }
+            self.control.update(|info| info.copied_files += 1);
+        }
+
+        let mut out = tar
+            .into_inner()
+            .and_then(|gz| gz.finish())
+            .map_err(|e| format!("failed to finish archive: {}", e))?;
+        out.flush().map_err(|e| format!("failed to finish archive: {}", e))
+    }
+
+    fn run(&self) {
+        self.control.update(|info| {
+            info.status = TaskStatus::Starting;
+            info.started_at = chrono::Utc::now().timestamp();
+        });
+
+        let result = self.build();
+        if self.control.is_cancelled() {
+            // Status was already set by cancel()
+            return;
+        }
+        let (source, files) = {
+            let info = self.control.read();
+            (info.source.clone(), info.files.join(", "))
+        };
+        match result {
+            Ok(relative) => {
+                dir_version::bump_entry(&self.to.owner, &relative);
+                watched::publish(&self.to.owner, &relative, &self.username, ChangeKind::Created, Client::Web);
+                let name = relative.rsplit('/').next().unwrap_or(&relative);
+                self.control.update(|info| {
+                    // Shown with the name it got
+                    if let Some((folder, _)) = info.target.rsplit_once('/') {
+                        info.target = format!("{}/{}", folder, name);
+                    }
+                    info.status = TaskStatus::Completed;
+                });
+                let target = self.control.read().target.clone();
+                log_operation(&self.username, OpType::Compress, &format!("{}: {} => {}", source, files, target), OP_SUCCESS, None);
+            }
+            Err(e) => {
+                log_operation(&self.username, OpType::Compress, &format!("{}: {}: {}", source, files, e), OP_FAILED, None);
+                self.control.update(|info| {
+                    info.status = TaskStatus::Failed;
+                    info.error = Some(e);
+                });
+            }
+        }
+    }
+}
+
+impl Task for CompressTask {
+    fn info(&self) -> TaskInfo {
+        self.control.info()
+    }
+
+    fn id(&self) -> String {
+        self.control.id()
+    }
+
+    fn enqueue(&self) {
+        self.control.enqueue()
+    }
+
+    fn start(self: Arc<Self>) {
+        tokio::task::spawn_blocking(move || self.run());
+    }
+
+    fn cancel(&self) {
+        self.control.cancel()
+    }
+
+    fn suspend(&self) {
+        self.control.suspend()
+    }
+
+    fn resume(&self) {
+        self.control.resume()
+    }
+
+    fn resolve_conflict(&self, _policy: ConflictPolicy) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        assert_eq!(ArchiveFormat::Zip.file_name("photos"), "photos.zip");
+        assert_eq!(ArchiveFormat::TarGz.file_name("photos.TAR.GZ"), "photos.TAR.GZ");
+        assert_eq!(ArchiveFormat::TarGz.file_name("photos.zip"), "photos.zip.tar.gz");
+
+        let dir = std::env::temp_dir().join(format!("datadisk-compress-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(free_name(&dir, "a.tar.gz", ArchiveFormat::TarGz), "a.tar.gz");
+        std::fs::write(dir.join("a.tar.gz"), b"x").unwrap();
+        assert_eq!(free_name(&dir, "a.tar.gz", ArchiveFormat::TarGz), "a (1).tar.gz");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! Control state of the tasks running on their own
+//!
+//! Tasks doing their work outside the task manager keep their info, the
+//! cancel and suspend flags and the channels listeners are told about
+//! changes on in a [`TaskControl`], which also answers the [`Task`] calls
+//! changing their status.
+//!
+//! [`Task`]: super::manager::Task
+
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{RwLock, RwLockReadGuard};
+use tokio::sync::broadcast;
+
+use super::manager::{TaskChange, TaskInfo, TaskNotification, TaskStatus};
+
+/// Info, flags and listeners of a task
+pub(super) struct TaskControl {
+    info: RwLock<TaskInfo>,
+    cancelled: AtomicBool,
+    suspended: AtomicBool,
+    notify_tx: broadcast::Sender<TaskNotification>,
+    change_tx: broadcast::Sender<TaskChange>,
+}
+
+impl TaskControl {
+    pub fn new(
+        info: TaskInfo,
+        notify_tx: broadcast::Sender<TaskNotification>,
+        change_tx: broadcast::Sender<TaskChange>,
+    ) -> Self {
+        Self {
+            info: RwLock::new(info),
+            cancelled: AtomicBool::new(false),
+            suspended: AtomicBool::new(false),
+            notify_tx,
+            change_tx,
+        }
+    }
+
+    /// Current task info
+    pub fn read(&self) -> RwLockReadGuard<'_, TaskInfo> {
+        self.info.read().unwrap()
+    }
+
+    pub fn info(&self) -> TaskInfo {
+        self.read().clone()
+    }
+
+    pub fn id(&self) -> String {
+        self.read().id.clone()
+    }
+
+    /// Update the task info and notify listeners
+    pub fn update(&self, f: impl FnOnce(&mut TaskInfo)) {
+        let mut info = self.info.write().unwrap();
+        let status = info.status;
+        f(&mut info);
+        info.updated_at = chrono::Utc::now().timestamp();
+        let _ = self.notify_tx.send(TaskNotification::TaskInfo(info.clone()));
+        if info.status != status {
+            let _ = self.change_tx.send(TaskChange::Updated(Box::new(info.clone())));
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Wait while suspended, returning an error once cancelled
+    ///
+    /// Blocks the thread; async tasks use [`wait`](Self::wait).
+    pub fn checkpoint(&self) -> Result<(), String> {
+        while self.suspended.load(Ordering::Relaxed) && !self.is_cancelled() {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        if self.is_cancelled() {
+            return Err("task cancelled".to_string());
+        }
+        Ok(())
+    }
+
+    /// Wait while suspended, returning false once cancelled
+    pub async fn wait(&self) -> bool {
+        while self.suspended.load(Ordering::Relaxed) && !self.is_cancelled() {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        !self.is_cancelled()
+    }
+
+    /// Report the start of the file `name` at `path`
+    pub fn start_entry(&self, name: &str, path: &Path) {
+        let size = std::fs::metadata(path).map(|m| m.len() as i64).unwrap_or(0);
+        self.update(|info| {
+            info.current_file = name.to_string();
+            info.current_file_size = size;
+            info.current_file_copied_size = 0;
+        });
+    }
+
+    pub fn enqueue(&self) {
+        if self.read().status == TaskStatus::Pending {
+            self.update(|info| info.status = TaskStatus::Queued);
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.update(|info| info.status = TaskStatus::Cancelled);
+    }
+
+    pub fn suspend(&self) {
+        self.suspended.store(true, Ordering::Relaxed);
+        if self.read().status == TaskStatus::Running {
+            self.update(|info| info.status = TaskStatus::Suspended);
+        }
+    }
+
+    pub fn resume(&self) {
+        self.suspended.store(false, Ordering::Relaxed);
+        if self.read().status == TaskStatus::Suspended {
+            self.update(|info| info.status = TaskStatus::Running);
+        }
+    }
+}
+
+/// Reader reporting the bytes read as progress, failing once cancelled
+pub(super) struct Progress<'a, R> {
+    control: &'a TaskControl,
+    inner: R,
+}
+
+impl<'a, R: Read> Progress<'a, R> {
+    /// Read in large chunks, so progress isn't sent for every few kilobytes
+    pub fn new(control: &'a TaskControl, inner: R) -> BufReader<Self> {
+        BufReader::with_capacity(1024 * 1024, Self { control, inner })
+    }
+}
+
+impl<R: Read> Read for Progress<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.control.checkpoint().map_err(std::io::Error::other)?;
+        let n = self.inner.read(buf)?;
+        self.control.update(|info| {
+            info.current_file_copied_size += n as i64;
+            info.copied_size += n as i64;
+        });
+        Ok(n)
+    }
+}
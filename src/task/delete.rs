@@ -0,0 +1,157 @@
+//! Delete task implementation
+//!
+//! Moves many files to the trash, or removes them for good, in the
+//! background, so large deletions return at once and show their progress in
+//! the task list.
+
+use sea_orm::DatabaseConnection;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use super::control::TaskControl;
+use super::manager::{Task, TaskChange, TaskInfo, TaskNotification, TaskStatus, TaskType};
+use super::ConflictPolicy;
+use crate::config::Config;
+use crate::handlers::dept_space::Location;
+use crate::handlers::legal_hold;
+use crate::handlers::undo;
+use crate::service::{FileError, FileService};
+
+/// Task moving files to the trash
+pub struct DeleteTask {
+    control: TaskControl,
+    /// User the deletions are recorded for
+    username: String,
+    /// Folder of the files
+    location: Location,
+    /// Remove the files for good instead of moving them to the trash
+    permanent: bool,
+    config: Arc<Config>,
+    db: DatabaseConnection,
+}
+
+impl DeleteTask {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        user_id: i64,
+        username: &str,
+        parent_dir: String,
+        files: Vec<String>,
+        permanent: bool,
+        location: Location,
+        config: Arc<Config>,
+        db: DatabaseConnection,
+        notify_tx: broadcast::Sender<TaskNotification>,
+        change_tx: broadcast::Sender<TaskChange>,
+    ) -> Self {
+        let mut info = TaskInfo::new(user_id, "web", TaskType::Delete);
+        info.source = parent_dir;
+        info.total_files = files.len() as i64;
+        info.files = files;
+
+        Self {
+            control: TaskControl::new(info, notify_tx, change_tx),
+            username: username.to_string(),
+            location,
+            permanent,
+            config,
+            db,
+        }
+    }
+
+    #[tracing::instrument(level = "debug", name = "delete_task", skip(self), fields(id = %self.id()))]
+    async fn run(&self) {
+        self.control.update(|info| {
+            info.status = TaskStatus::Running;
+            info.started_at = chrono::Utc::now().timestamp();
+        });
+
+        let files = self.control.read().files.clone();
+        let service = FileService::new(&self.config, &self.db, &self.location.owner)
+            .on_behalf_of(&self.username, &self.location.prefix);
+        let parent_dir = self.location.path.trim_start_matches('/');
+        let mut failed = 0;
+        let mut held = Vec::new();
+        let mut trashed = Vec::new();
+        let mut cancelled = false;
+        for file in &files {
+            if !self.control.wait().await {
+                cancelled = true;
+                break;
+            }
+            self.control.update(|info| info.current_file = file.clone());
+            // Each deleted file is audited by the service
+            let result = if self.permanent {
+                service.delete_permanently(parent_dir, file).await.map(|()| None)
+            } else {
+                service.delete(parent_dir, file).await.map(Some)
+            };
+            match result {
+                Ok(item) => trashed.extend(item),
+                Err(FileError::LegalHold(path)) => {
+                    failed += 1;
+                    held.push(path);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to delete {}: {}", file, e);
+                    failed += 1;
+                }
+            }
+            self.control.update(|info| info.copied_files += 1);
+        }
+
+        // What was moved to the trash can be restored, even if cancelled
+        let dir = self.control.read().source.clone();
+        undo::record(&self.db, &self.username, undo::Operation::Delete { dir, items: trashed }).await;
+        if cancelled {
+            // Status was already set by cancel()
+            return;
+        }
+
+        if failed == 0 {
+            self.control.update(|info| info.status = TaskStatus::Completed);
+            return;
+        }
+        let mut error = format!("删除失败{}个文件", failed);
+        for path in &held {
+            error.push('；');
+            error.push_str(&legal_hold::message(path));
+        }
+        self.control.update(|info| {
+            info.status = TaskStatus::Failed;
+            info.error = Some(error);
+        });
+    }
+}
+
+impl Task for DeleteTask {
+    fn info(&self) -> TaskInfo {
+        self.control.info()
+    }
+
+    fn id(&self) -> String {
+        self.control.id()
+    }
+
+    fn enqueue(&self) {
+        self.control.enqueue()
+    }
+
+    fn start(self: Arc<Self>) {
+        tokio::spawn(async move { self.run().await });
+    }
+
+    fn cancel(&self) {
+        self.control.cancel()
+    }
+
+    fn suspend(&self) {
+        self.control.suspend()
+    }
+
+    fn resume(&self) {
+        self.control.resume()
+    }
+
+    fn resolve_conflict(&self, _policy: ConflictPolicy) {}
+}
@@ -0,0 +1,502 @@
+//! Extract task implementation
+//!
+//! Unpacks a zip, tar, tar.gz, tar.xz, 7z or rar archive into a folder of the
+//! user's tree. Entries are unpacked to a work directory first, so a broken
+//! or cancelled archive leaves nothing behind, then the top-level entries are
+//! moved into the target folder one by one, resolving conflicts like copies
+//! do, and recorded in `file_info`.
+//!
+//! The sizes an archive declares were checked against `[archive_limits]`
+//! when it was listed; the bytes actually unpacked are checked again, so an
+//! archive lying about its sizes fails instead of filling the disk.
+
+use sea_orm::DatabaseConnection;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+
+use super::control::TaskControl;
+use super::manager::{
+    ConflictFileInfo, CopyTask, Task, TaskChange, TaskDir, TaskInfo, TaskNotification, TaskStatus, TaskType,
+};
+use super::ConflictPolicy;
+use crate::config::Config;
+use crate::entity::op_log::OpType;
+use crate::handlers::archive_preview::{self, ArchiveKind};
+use crate::handlers::audit::service::log_operation;
+use crate::handlers::dir_version;
+use crate::handlers::file::resolve_in_root;
+use crate::handlers::legal_hold;
+use crate::handlers::quota;
+use crate::handlers::tiering;
+use crate::handlers::trash::{copy_tree, ensure_dir_id, register_tree};
+use crate::handlers::watch::{self as watched, ChangeKind, Client};
+use crate::service::FileService;
+
+const OP_SUCCESS: &str = "成功";
+const OP_FAILED: &str = "失败";
+
+/// Path of an archive entry below the extraction folder, None for entries
+/// that would land outside it
+fn safe_relative(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            part if part.contains('\0') => return None,
+            part => path.push(part),
+        }
+    }
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+fn conflict_file(path: &Path) -> ConflictFileInfo {
+    let metadata = std::fs::metadata(path).ok();
+    ConflictFileInfo {
+        name: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+        size: metadata.as_ref().map(|m| m.len() as i64).unwrap_or(0),
+        modify_time: metadata
+            .as_ref()
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0),
+        is_directory: metadata.is_some_and(|m| m.is_dir()),
+    }
+}
+
+/// Task unpacking an archive into a folder
+pub struct ExtractTask {
+    control: TaskControl,
+    /// User the extraction is recorded for
+    username: String,
+    /// The archive file
+    archive: PathBuf,
+    kind: ArchiveKind,
+    /// Folder the entries are extracted to
+    to: TaskDir,
+    /// Where the entries are unpacked first
+    work_dir: PathBuf,
+    config: Arc<Config>,
+    db: DatabaseConnection,
+    conflict_tx: mpsc::Sender<ConflictPolicy>,
+    conflict_rx: tokio::sync::Mutex<mpsc::Receiver<ConflictPolicy>>,
+}
+
+impl ExtractTask {
+    /// `source` is the archive and `target` the folder as the user sees them,
+    /// `total_size` the unpacked size of the entries
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        user_id: i64,
+        username: &str,
+        source: String,
+        target: String,
+        archive: PathBuf,
+        kind: ArchiveKind,
+        total_size: i64,
+        to: TaskDir,
+        conflict_policy: ConflictPolicy,
+        work_dir: PathBuf,
+        config: Arc<Config>,
+        db: DatabaseConnection,
+        notify_tx: broadcast::Sender<TaskNotification>,
+        change_tx: broadcast::Sender<TaskChange>,
+    ) -> Self {
+        let mut info = TaskInfo::new(user_id, "web", TaskType::Extract);
+        info.files = source.rsplit('/').next().map(|name| vec![name.to_string()]).unwrap_or_default();
+        info.source = source;
+        info.target = target;
+        info.total_size = total_size;
+        info.conflict_info.conflict_policy = conflict_policy;
+        let (conflict_tx, conflict_rx) = mpsc::channel(1);
+
+        Self {
+            control: TaskControl::new(info, notify_tx, change_tx),
+            username: username.to_string(),
+            archive,
+            kind,
+            to,
+            work_dir,
+            config,
+            db,
+            conflict_tx,
+            conflict_rx: tokio::sync::Mutex::new(conflict_rx),
+        }
+    }
+
+    /// Error once the bytes unpacked so far are over the limit
+    fn check_unpacked(&self, copied: i64) -> Result<(), String> {
+        archive_preview::check_size(archive_preview::limits(), copied.max(0) as u64).map_err(|e| e.to_string())
+    }
+
+    /// Unpack all entries into `staging`
+    fn unpack(&self, staging: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(staging).map_err(|e| format!("failed to create work directory: {}", e))?;
+        let open = || std::fs::File::open(&self.archive).map_err(|e| format!("failed to open archive: {}", e));
+        match self.kind {
+            ArchiveKind::Zip => self.unpack_zip(open()?, staging),
+            ArchiveKind::Tar => self.unpack_tar(open()?, staging),
+            ArchiveKind::TarGz => self.unpack_tar(flate2::read::GzDecoder::new(open()?), staging),
+            ArchiveKind::TarXz => self.unpack_tar(xz2::read::XzDecoder::new(open()?), staging),
+            ArchiveKind::Rar => self.unpack_rar(staging),
+            ArchiveKind::SevenZ => self.unpack_7z(staging),
+        }
+    }
+
+    /// Write one file entry, reporting progress
+    fn write_entry(&self, name: &str, size: i64, reader: &mut dyn Read, dest: &Path) -> Result<(), String> {
+        self.control.update(|info| {
+            info.current_file = name.to_string();
+            info.current_file_size = size;
+            info.current_file_copied_size = 0;
+        });
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("failed to create {}: {}", name, e))?;
+        }
+        let mut file = std::fs::File::create(dest).map_err(|e| format!("failed to create {}: {}", name, e))?;
+
+        let mut buf = vec![0u8; 1024 * 1024];
+        loop {
+            self.control.checkpoint()?;
+            let n = reader.read(&mut buf).map_err(|e| format!("failed to read {}: {}", name, e))?;
+            if n == 0 {
+                break;
+            }
+            file.write_all(&buf[..n]).map_err(|e| format!("failed to write {}: {}", name, e))?;
+            let mut copied = 0;
+            self.control.update(|info| {
+                info.current_file_copied_size += n as i64;
+                info.copied_size += n as i64;
+                copied = info.copied_size;
+            });
+            self.check_unpacked(copied)?;
+        }
+        self.control.update(|info| info.copied_files += 1);
+        Ok(())
+    }
+
+    fn create_dir(&self, name: &str, dest: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(dest).map_err(|e| format!("failed to create {}: {}", name, e))
+    }
+
+    fn unpack_zip(&self, file: std::fs::File, staging: &Path) -> Result<(), String> {
+        let mut zip = zip::ZipArchive::new(file).map_err(|e| format!("failed to read archive: {}", e))?;
+        for i in 0..zip.len() {
+            self.control.checkpoint()?;
+            let mut entry = zip.by_index(i).map_err(|e| format!("failed to read archive: {}", e))?;
+            let name = entry.name().to_string();
+            let Some(relative) = safe_relative(&name) else {
+                tracing::warn!("Skipping unsafe archive entry {}", name);
+                continue;
+            };
+            if entry.is_dir() {
+                self.create_dir(&name, &staging.join(relative))?;
+            } else if !entry.is_symlink() {
+                let size = entry.size() as i64;
+                self.write_entry(&name, size, &mut entry, &staging.join(relative))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn unpack_tar(&self, reader: impl Read, staging: &Path) -> Result<(), String> {
+        let mut tar = tar::Archive::new(reader);
+        for entry in tar.entries().map_err(|e| format!("failed to read archive: {}", e))? {
+            self.control.checkpoint()?;
+            let mut entry = entry.map_err(|e| format!("failed to read archive: {}", e))?;
+            let name = entry.path().map_err(|e| e.to_string())?.to_string_lossy().into_owned();
+            let Some(relative) = safe_relative(&name) else {
+                tracing::warn!("Skipping unsafe archive entry {}", name);
+                continue;
+            };
+            // Links and special files are left out
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_dir() {
+                self.create_dir(&name, &staging.join(relative))?;
+            } else if entry_type.is_file() {
+                let size = entry.header().size().unwrap_or(0) as i64;
+                self.write_entry(&name, size, &mut entry, &staging.join(relative))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn unpack_7z(&self, staging: &Path) -> Result<(), String> {
+        let mut failure = None;
+        let result = sevenz_rust::decompress_file_with_extract_fn(&self.archive, staging, |entry, reader, _| {
+            let name = entry.name().to_string();
+            let Some(relative) = safe_relative(&name) else {
+                tracing::warn!("Skipping unsafe archive entry {}", name);
+                return Ok(true);
+            };
+            let written = if entry.is_directory() {
+                self.create_dir(&name, &staging.join(relative))
+            } else {
+                self.write_entry(&name, entry.size() as i64, reader, &staging.join(relative))
+            };
+            match written {
+                Ok(()) => Ok(true),
+                Err(e) => {
+                    failure = Some(e);
+                    Ok(false)
+                }
+            }
+        });
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        result.map_err(|e| format!("failed to read archive: {:?}", e))
+    }
+
+    fn unpack_rar(&self, staging: &Path) -> Result<(), String> {
+        let mut archive = unrar::Archive::new(&self.archive)
+            .open_for_processing()
+            .map_err(|e| format!("failed to open archive: {:?}", e))?;
+        while let Some(header) = archive.read_header().map_err(|e| format!("failed to read archive: {:?}", e))? {
+            self.control.checkpoint()?;
+            let entry = header.entry();
+            let name = entry.filename.to_string_lossy().into_owned();
+            let relative = safe_relative(&name);
+            archive = match relative {
+                Some(relative) if entry.is_file() => {
+                    let dest = staging.join(relative);
+                    let size = entry.unpacked_size as i64;
+                    self.control.update(|info| {
+                        info.current_file = name.clone();
+                        info.current_file_size = size;
+                        info.current_file_copied_size = 0;
+                    });
+                    if let Some(parent) = dest.parent() {
+                        self.create_dir(&name, parent)?;
+                    }
+                    let archive = header.extract_to(&dest).map_err(|e| format!("failed to extract {}: {:?}", name, e))?;
+                    let mut copied = 0;
+                    self.control.update(|info| {
+                        info.current_file_copied_size = size;
+                        info.copied_size += size;
+                        info.copied_files += 1;
+                        copied = info.copied_size;
+                    });
+                    self.check_unpacked(copied)?;
+                    archive
+                }
+                Some(relative) => {
+                    if entry.is_directory() {
+                        self.create_dir(&name, &staging.join(relative))?;
+                    }
+                    header.skip().map_err(|e| format!("failed to read archive: {:?}", e))?
+                }
+                None => {
+                    tracing::warn!("Skipping unsafe archive entry {}", name);
+                    header.skip().map_err(|e| format!("failed to read archive: {:?}", e))?
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Ask the user how to resolve a conflict of `src` with `dst`
+    async fn ask(&self, src: &Path, dst: &Path) -> Result<ConflictPolicy, String> {
+        self.control.update(|info| {
+            info.conflict_info.need_confirm = true;
+            info.conflict_info.src_file = conflict_file(src);
+            info.conflict_info.dst_file = conflict_file(dst);
+        });
+        let policy = self.conflict_rx.lock().await.recv().await.ok_or("conflict channel closed")?;
+        self.control.update(|info| {
+            info.conflict_info.need_confirm = false;
+            info.conflict_info.src_file = ConflictFileInfo::default();
+            info.conflict_info.dst_file = ConflictFileInfo::default();
+            // Remember the policy for subsequent conflicts
+            info.conflict_info.conflict_policy = policy;
+        });
+        if self.control.is_cancelled() {
+            return Err("task cancelled".to_string());
+        }
+        Ok(policy)
+    }
+
+    /// Remove `dst` of the target tree with its rows, before it is replaced
+    async fn replace(&self, dst: &Path) -> Result<(), String> {
+        let relative = dst.strip_prefix(&self.to.root).map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+        match legal_hold::held(&self.db, &self.to.owner, &relative).await {
+            Ok(None) => {}
+            Ok(Some(hold)) => return Err(format!("{} is under legal hold", hold.path)),
+            Err(e) => return Err(format!("failed to check legal hold: {}", e)),
+        }
+        let name = dst.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        FileService::new(&self.config, &self.db, &self.to.owner)
+            .remove_rows(&self.to.path, &name)
+            .await
+            .map_err(|e| format!("failed to replace {}: {}", name, e))?;
+        if let Err(e) = tiering::discard(&self.db, &self.to.owner, &relative).await {
+            tracing::error!("Failed to drop the cold copies of {}: {}", relative, e);
+        }
+        let removed = if dst.is_dir() {
+            tokio::fs::remove_dir_all(dst).await
+        } else {
+            tokio::fs::remove_file(dst).await
+        };
+        removed.map_err(|e| format!("failed to replace {}: {}", name, e))
+    }
+
+    /// Move the unpacked top-level entries into the target folder, returning
+    /// the paths they got relative to the target root
+    async fn place(&self, staging: &Path) -> Result<Vec<String>, String> {
+        let dir = resolve_in_root(&self.to.root, &self.to.path).ok_or("invalid target path")?;
+        if !dir.is_dir() {
+            return Err("target path is not a directory".to_string());
+        }
+        let mut names = Vec::new();
+        let mut entries = tokio::fs::read_dir(staging).await.map_err(|e| format!("failed to read work directory: {}", e))?;
+        while let Some(entry) = entries.next_entry().await.map_err(|e| format!("failed to read work directory: {}", e))? {
+            names.push(entry.file_name());
+        }
+        names.sort();
+
+        let parent_id = ensure_dir_id(&self.db, &self.to.owner, &self.to.path)
+            .await
+            .map_err(|e| format!("failed to record files: {}", e))?;
+        let mut policy = self.control.read().conflict_info.conflict_policy;
+        let mut placed = Vec::new();
+        for name in names {
+            if self.control.is_cancelled() {
+                return Err("task cancelled".to_string());
+            }
+            let src = staging.join(&name);
+            let mut dst = dir.join(&name);
+            if dst.exists() {
+                if policy == ConflictPolicy::Ask {
+                    policy = self.ask(&src, &dst).await?;
+                }
+                match policy {
+                    ConflictPolicy::Abort => return Err("conflict detected, aborting".to_string()),
+                    ConflictPolicy::Skip => continue,
+                    ConflictPolicy::Rename => dst = CopyTask::generate_unique_path(&dst),
+                    ConflictPolicy::Overwrite => self.replace(&dst).await?,
+                    // Answered with Ask again, which decides nothing
+                    ConflictPolicy::Ask => continue,
+                }
+            }
+
+            if tokio::fs::rename(&src, &dst).await.is_err() {
+                // The work directory may be on another filesystem
+                let (from, to) = (src.clone(), dst.clone());
+                tokio::task::spawn_blocking(move || copy_tree(&from, &to))
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|copied| copied.map_err(|e| e.to_string()))
+                    .map_err(|e| format!("failed to store {}: {}", name.to_string_lossy(), e))?;
+            }
+            if let Err(e) = register_tree(&self.db, &self.to.owner, parent_id, &dst).await {
+                tracing::error!("Failed to record {}: {}", dst.display(), e);
+            }
+            let relative = dst.strip_prefix(&self.to.root).map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+            dir_version::bump_entry(&self.to.owner, &relative);
+            watched::publish(&self.to.owner, &relative, &self.username, ChangeKind::Created, Client::Web);
+            placed.push(relative);
+        }
+        Ok(placed)
+    }
+
+    #[tracing::instrument(level = "debug", name = "extract_task", skip(self), fields(id = %self.id()))]
+    async fn run(self: Arc<Self>) {
+        self.control.update(|info| {
+            info.status = TaskStatus::Starting;
+            info.started_at = chrono::Utc::now().timestamp();
+        });
+        let staging = self.work_dir.join(self.id());
+
+        self.control.update(|info| info.status = TaskStatus::Running);
+        let task = self.clone();
+        let unpack_dir = staging.clone();
+        let result = match tokio::task::spawn_blocking(move || task.unpack(&unpack_dir)).await {
+            Ok(Ok(())) => self.place(&staging).await,
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = tokio::fs::remove_dir_all(&staging).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Failed to remove work directory {}: {}", staging.display(), e);
+            }
+        }
+        // Partly placed entries count as well
+        quota::invalidate(&self.to.owner);
+
+        if self.control.is_cancelled() {
+            // Status was already set by cancel()
+            return;
+        }
+        let (source, target) = {
+            let info = self.control.read();
+            (info.source.clone(), info.target.clone())
+        };
+        match result {
+            Ok(_) => {
+                self.control.update(|info| info.status = TaskStatus::Completed);
+                log_operation(&self.username, OpType::Extract, &format!("{} => {}", source, target), OP_SUCCESS, None);
+            }
+            Err(e) => {
+                log_operation(&self.username, OpType::Extract, &format!("{} => {}: {}", source, target, e), OP_FAILED, None);
+                self.control.update(|info| {
+                    info.status = TaskStatus::Failed;
+                    info.error = Some(e);
+                });
+            }
+        }
+    }
+}
+
+impl Task for ExtractTask {
+    fn info(&self) -> TaskInfo {
+        self.control.info()
+    }
+
+    fn id(&self) -> String {
+        self.control.id()
+    }
+
+    fn enqueue(&self) {
+        self.control.enqueue()
+    }
+
+    fn start(self: Arc<Self>) {
+        tokio::spawn(self.run());
+    }
+
+    fn cancel(&self) {
+        self.control.cancel();
+        // Wake a task waiting for a conflict answer
+        let _ = self.conflict_tx.try_send(ConflictPolicy::Abort);
+    }
+
+    fn suspend(&self) {
+        self.control.suspend()
+    }
+
+    fn resume(&self) {
+        self.control.resume()
+    }
+
+    fn resolve_conflict(&self, policy: ConflictPolicy) {
+        let _ = self.conflict_tx.try_send(policy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_relative() {
+        assert_eq!(safe_relative("docs/a.txt"), Some(PathBuf::from("docs/a.txt")));
+        assert_eq!(safe_relative("/docs/./a.txt"), Some(PathBuf::from("docs/a.txt")));
+        assert_eq!(safe_relative("docs\\a.txt"), Some(PathBuf::from("docs/a.txt")));
+        assert_eq!(safe_relative("../a.txt"), None);
+        assert_eq!(safe_relative("docs/../../a.txt"), None);
+        assert_eq!(safe_relative("./"), None);
+    }
+}
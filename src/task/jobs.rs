@@ -0,0 +1,220 @@
+//! Worker pool for CPU-heavy jobs
+//!
+//! Thumbnails and watermarks are decoded on blocking threads, and HEIC
+//! photos, video posters and streams and Office documents are converted by
+//! external tools. Opening a folder of thousands of photos would otherwise
+//! start thousands of them at once, so jobs wait for one of a fixed number of
+//! workers instead. The queue is bounded too, overall and per user: a job that
+//! doesn't fit is refused and the client asks again later.
+//!
+//! Closures run on a worker with [`JobPool::run`]. Jobs waiting on another
+//! process hold a [`Worker`] while it runs instead.
+
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::WorkersConfig;
+
+/// Why a job didn't run
+#[derive(Debug)]
+pub enum JobError {
+    /// The queue is full
+    Busy,
+    /// The job panicked
+    Failed(tokio::task::JoinError),
+}
+
+/// A worker taken for a job, free again when dropped
+pub struct Worker<'a> {
+    admission: Admission<'a>,
+    permit: OwnedSemaphorePermit,
+}
+
+impl Worker<'_> {
+    /// Run `job` on the worker
+    pub async fn run<T, F>(self, job: F) -> Result<T, JobError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let Self { admission, permit } = self;
+        // The worker is busy until the job ends, even if nobody waits for it
+        let result = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            job()
+        })
+        .await
+        .map_err(JobError::Failed);
+        drop(admission);
+        result
+    }
+}
+
+/// Bounded pool of blocking workers
+pub struct JobPool {
+    workers: Arc<Semaphore>,
+    threads: usize,
+    max_queued: usize,
+    max_per_user: usize,
+    /// Jobs waiting for a worker
+    queued: AtomicUsize,
+    /// Waiting and running jobs by user
+    per_user: DashMap<String, usize>,
+}
+
+/// Counts a job as waiting or running until dropped, also when the request
+/// giving up drops it while waiting
+struct Admission<'a> {
+    pool: &'a JobPool,
+    username: String,
+    waiting: bool,
+}
+
+impl Admission<'_> {
+    fn started(&mut self) {
+        self.waiting = false;
+        self.pool.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        if self.waiting {
+            self.pool.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+        if let Some(mut count) = self.pool.per_user.get_mut(&self.username) {
+            *count = count.saturating_sub(1);
+        }
+        self.pool.per_user.remove_if(&self.username, |_, count| *count == 0);
+    }
+}
+
+impl JobPool {
+    pub fn new(config: &WorkersConfig) -> Self {
+        let threads = match config.threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| (n.get() / 2).max(1)),
+            n => n,
+        };
+        Self {
+            workers: Arc::new(Semaphore::new(threads)),
+            threads,
+            max_queued: config.queue,
+            max_per_user: config.per_user,
+            queued: AtomicUsize::new(0),
+            per_user: DashMap::new(),
+        }
+    }
+
+    /// Jobs waiting for a worker
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Jobs being worked on
+    pub fn running(&self) -> usize {
+        self.threads - self.workers.available_permits()
+    }
+
+    fn admit(&self, username: &str) -> Option<Admission<'_>> {
+        let mut count = self.per_user.entry(username.to_string()).or_insert(0);
+        *count += 1;
+        let user_full = self.max_per_user > 0 && *count > self.max_per_user;
+        drop(count);
+        let mut admission = Admission {
+            pool: self,
+            username: username.to_string(),
+            waiting: false,
+        };
+        // Dropping a refused admission undoes the count
+        if user_full || self.queued.fetch_add(1, Ordering::Relaxed) >= self.max_queued.max(1) {
+            if !user_full {
+                self.queued.fetch_sub(1, Ordering::Relaxed);
+            }
+            return None;
+        }
+        admission.waiting = true;
+        Some(admission)
+    }
+
+    /// Wait for a worker for `username`
+    pub async fn worker(&self, username: &str) -> Result<Worker<'_>, JobError> {
+        let Some(mut admission) = self.admit(username) else {
+            return Err(JobError::Busy);
+        };
+        let permit = self
+            .workers
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("worker semaphore is never closed");
+        admission.started();
+        Ok(Worker { admission, permit })
+    }
+
+    /// Run `job` for `username` on a worker once one is free
+    pub async fn run<T, F>(&self, username: &str, job: F) -> Result<T, JobError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        self.worker(username).await?.run(job).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_job_pool() {
+        let pool = Arc::new(JobPool::new(&WorkersConfig {
+            threads: 1,
+            queue: 2,
+            per_user: 2,
+        }));
+        assert_eq!(pool.run("alice", || 1 + 1).await.unwrap(), 2);
+
+        // Hold the only worker
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let blocker = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run("bob", move || rx.recv().unwrap()).await }
+        });
+        while pool.running() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // Two of alice's jobs fit in the queue, a third is refused
+        let first = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run("alice", || 1).await }
+        });
+        let second = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run("alice", || 2).await }
+        });
+        while pool.queued() < 2 {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(pool.run("alice", || 3).await, Err(JobError::Busy)));
+        // The queue is full for everybody else as well
+        assert!(matches!(pool.run("carol", || 4).await, Err(JobError::Busy)));
+
+        tx.send(()).unwrap();
+        blocker.await.unwrap().unwrap();
+        assert_eq!(first.await.unwrap().unwrap(), 1);
+        assert_eq!(second.await.unwrap().unwrap(), 2);
+        assert_eq!(pool.queued(), 0);
+        assert!(pool.per_user.is_empty());
+        assert_eq!(pool.run("alice", || 5).await.unwrap(), 5);
+
+        // A worker held while another process runs counts as a running job
+        let worker = pool.worker("alice").await.unwrap();
+        assert_eq!(pool.running(), 1);
+        drop(worker);
+        assert_eq!(pool.running(), 0);
+        assert!(pool.per_user.is_empty());
+    }
+}
@@ -3,21 +3,61 @@
 //! Manages background tasks for file operations
 
 use dashmap::DashMap;
+use futures::StreamExt;
+use sea_orm::DatabaseConnection;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::{broadcast, watch, RwLock};
+use tracing::{field::Empty, instrument};
+
+use crate::entity::op_log::OpType;
+use crate::handlers::audit::service::log_operation;
+use super::archive::ArchiveTask;
+use super::compress::{ArchiveFormat, CompressTask};
+use super::delete::DeleteTask;
+use super::rename::RenameTask;
+use super::extract::ExtractTask;
+use super::jobs::JobPool;
+use super::queue::TaskQueue;
+use super::retry::{self, RetryPolicy};
+use super::throttle::{Pacer, Throttle};
+use crate::handlers::file::resolve_in_root;
+use crate::config::{Config, TaskCopyConfig, TaskLimitConfig, TaskQueueConfig, TaskRetryConfig, WorkersConfig};
+use crate::handlers::archive_preview::ArchiveKind;
+use crate::handlers::dept_space::Location;
+use crate::entity::journal as journal_entry;
+use crate::handlers::dir_version;
+use crate::handlers::journal::{self, Intent};
+use crate::handlers::legal_hold;
+use crate::handlers::quota;
+use crate::handlers::sensitive;
+use crate::handlers::tag;
+use crate::handlers::tiering;
+use crate::handlers::undo;
+use crate::handlers::watch::{self as watched, ChangeKind, Client};
+
+const OP_SUCCESS: &str = "成功";
+const OP_FAILED: &str = "失败";
+const OP_SKIPPED: &str = "跳过";
+const OP_CANCELLED: &str = "取消";
+
+/// Error of a task stopped by the `abort` conflict policy
+const CONFLICT_ABORTED: &str = "conflict detected, aborting";
 
 /// Global task manager instance
 pub static TASK_MANAGER: std::sync::LazyLock<TaskManager> =
     std::sync::LazyLock::new(TaskManager::new);
 
 /// Task status
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum TaskStatus {
     Pending,
+    /// Waiting for a running slot
+    Queued,
     Starting,
     Running,
     Suspended,
@@ -26,16 +66,82 @@ pub enum TaskStatus {
     Failed,
 }
 
+impl TaskStatus {
+    /// Whether the task has reached a terminal state
+    pub fn is_finished(&self) -> bool {
+        matches!(self, TaskStatus::Completed | TaskStatus::Cancelled | TaskStatus::Failed)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskStatus::Pending => "pending",
+            TaskStatus::Queued => "queued",
+            TaskStatus::Starting => "starting",
+            TaskStatus::Running => "running",
+            TaskStatus::Suspended => "suspended",
+            TaskStatus::Completed => "completed",
+            TaskStatus::Cancelled => "cancelled",
+            TaskStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(TaskStatus::Pending),
+            "queued" => Some(TaskStatus::Queued),
+            "starting" => Some(TaskStatus::Starting),
+            "running" => Some(TaskStatus::Running),
+            "suspended" => Some(TaskStatus::Suspended),
+            "completed" => Some(TaskStatus::Completed),
+            "cancelled" => Some(TaskStatus::Cancelled),
+            "failed" => Some(TaskStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
 /// Task type
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum TaskType {
     Copy,
     Move,
+    Archive,
+    Delete,
+    Compress,
+    Extract,
+    Rename,
+}
+
+impl TaskType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskType::Copy => "copy",
+            TaskType::Move => "move",
+            TaskType::Archive => "archive",
+            TaskType::Delete => "delete",
+            TaskType::Compress => "compress",
+            TaskType::Extract => "extract",
+            TaskType::Rename => "rename",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "copy" => Some(TaskType::Copy),
+            "move" => Some(TaskType::Move),
+            "archive" => Some(TaskType::Archive),
+            "delete" => Some(TaskType::Delete),
+            "compress" => Some(TaskType::Compress),
+            "extract" => Some(TaskType::Extract),
+            "rename" => Some(TaskType::Rename),
+            _ => None,
+        }
+    }
 }
 
 /// Conflict policy
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum ConflictPolicy {
     Ask,
@@ -52,7 +158,7 @@ impl Default for ConflictPolicy {
 }
 
 /// File info for conflict display
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct ConflictFileInfo {
     pub name: String,
     pub size: i64,
@@ -63,7 +169,7 @@ pub struct ConflictFileInfo {
 }
 
 /// Conflict info
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct ConflictInfo {
     #[serde(rename = "needConfirm")]
     pub need_confirm: bool,
@@ -76,7 +182,7 @@ pub struct ConflictInfo {
 }
 
 /// Base task information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TaskInfo {
     pub id: String,
     pub agent: String,
@@ -115,6 +221,17 @@ pub struct TaskInfo {
     pub total_size: i64,
     #[serde(rename = "copiedSize")]
     pub copied_size: i64,
+    /// Files that failed while the others went on
+    #[serde(rename = "failedFiles")]
+    pub failed_files: Vec<FailedFile>,
+}
+
+/// A file a task failed on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FailedFile {
+    /// Path as shown to the user
+    pub path: String,
+    pub error: String,
 }
 
 impl TaskInfo {
@@ -142,6 +259,7 @@ impl TaskInfo {
             copied_files: 0,
             total_size: 0,
             copied_size: 0,
+            failed_files: Vec::new(),
         }
     }
 }
@@ -150,6 +268,8 @@ impl TaskInfo {
 pub trait Task: Send + Sync {
     fn info(&self) -> TaskInfo;
     fn id(&self) -> String;
+    /// Mark the task as waiting for a running slot
+    fn enqueue(&self);
     fn start(self: Arc<Self>);
     fn cancel(&self);
     fn suspend(&self);
@@ -157,28 +277,77 @@ pub trait Task: Send + Sync {
     fn resolve_conflict(&self, policy: ConflictPolicy);
 }
 
+/// A folder files are copied or moved from or to
+#[derive(Debug, Clone)]
+pub struct TaskDir {
+    /// Owner of the tree, the user or a shared space
+    pub owner: String,
+    /// Root directory of the tree
+    pub root: PathBuf,
+    /// The folder, relative to `root`
+    pub path: String,
+}
+
+/// Why copying the content of a file stopped
+enum CopyFailure {
+    Cancelled,
+    /// What was being done, and the error
+    Io(&'static str, std::io::Error),
+}
+
+impl std::fmt::Display for CopyFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CopyFailure::Cancelled => write!(f, "task cancelled"),
+            CopyFailure::Io(context, e) => write!(f, "{}: {}", context, e),
+        }
+    }
+}
+
 /// Copy task implementation
 pub struct CopyTask {
     info: RwLock<TaskInfo>,
-    user_dir: PathBuf,
+    username: String,
+    from: TaskDir,
+    to: TaskDir,
+    /// For moving the tags of moved files
+    db: DatabaseConnection,
+    /// Throughput limits of all tasks
+    throttle: Arc<Throttle>,
+    pacer: Pacer,
+    retry: RetryPolicy,
+    /// Small files copied at the same time
+    copy: TaskCopyConfig,
     cancel_tx: watch::Sender<bool>,
     suspend_tx: watch::Sender<bool>,
     conflict_tx: tokio::sync::mpsc::Sender<ConflictPolicy>,
     conflict_rx: RwLock<Option<tokio::sync::mpsc::Receiver<ConflictPolicy>>>,
     notify_tx: broadcast::Sender<TaskNotification>,
+    change_tx: broadcast::Sender<TaskChange>,
+    /// Last status sent to `change_tx`
+    last_status: std::sync::Mutex<TaskStatus>,
+    /// Names of the moved entries in the target, for undoing the move
+    moved: std::sync::Mutex<Vec<String>>,
 }
 
 impl CopyTask {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         user_id: i64,
-        _username: &str,
+        username: &str,
         agent: &str,
         is_copy: bool,
         source: String,
         target: String,
         files: Vec<String>,
-        user_dir: PathBuf,
+        from: TaskDir,
+        to: TaskDir,
+        db: DatabaseConnection,
+        throttle: Arc<Throttle>,
+        retry: RetryPolicy,
+        copy: TaskCopyConfig,
         notify_tx: broadcast::Sender<TaskNotification>,
+        change_tx: broadcast::Sender<TaskChange>,
     ) -> Self {
         let task_type = if is_copy { TaskType::Copy } else { TaskType::Move };
         let mut info = TaskInfo::new(user_id, agent, task_type);
@@ -199,77 +368,81 @@ impl CopyTask {
 
         Self {
             info: RwLock::new(info),
-            user_dir,
+            username: username.to_string(),
+            from,
+            to,
+            db,
+            throttle,
+            pacer: Pacer::new(),
+            retry,
+            copy,
             cancel_tx,
             suspend_tx,
             conflict_tx,
             conflict_rx: RwLock::new(Some(conflict_rx)),
             notify_tx,
+            change_tx,
+            last_status: std::sync::Mutex::new(TaskStatus::Pending),
+            moved: std::sync::Mutex::new(Vec::new()),
         }
     }
 
     fn notify(&self, info: &TaskInfo) {
         let _ = self.notify_tx.send(TaskNotification::TaskInfo(info.clone()));
+        let mut last_status = self.last_status.lock().unwrap();
+        if *last_status != info.status {
+            *last_status = info.status;
+            let _ = self.change_tx.send(TaskChange::Updated(Box::new(info.clone())));
+        }
     }
 
-    /// Join user path safely
-    fn join_user_path(&self, paths: &[&str]) -> Result<PathBuf, String> {
-        // Get the canonical user directory first
-        let user_dir_canonical = self.user_dir.canonicalize()
-            .map_err(|e| format!("failed to canonicalize user_dir: {}", e))?;
+    /// Record an audit entry for this task
+    fn audit(&self, is_copy: bool, desc: &str, result: &str) {
+        let op = if is_copy { OpType::Copy } else { OpType::Move };
+        log_operation(&self.username, op, desc, result, None);
+    }
 
-        let mut full = user_dir_canonical.clone();
-        for p in paths {
-            let trimmed = p.trim_start_matches('/');
-            if !trimmed.is_empty() {
-                full = full.join(trimmed);
-            }
-        }
+    /// Join a path below `dir` safely, see [`resolve_in_root`]
+    fn join_user_path(dir: &TaskDir, file: &str) -> Result<PathBuf, String> {
+        resolve_in_root(&dir.root, &format!("{}/{}", dir.path, file))
+            .ok_or_else(|| "accessing path outside user directory".to_string())
+    }
 
-        // Try to canonicalize, but if path doesn't exist yet, normalize manually
-        let full = if full.exists() {
-            full.canonicalize().unwrap_or(full)
-        } else {
-            // For non-existent paths, normalize while keeping as absolute path
-            Self::normalize_path(&full)
+    /// Refuse changing `path` of the tree of `dir` if it is under legal hold
+    async fn check_hold(&self, dir: &TaskDir, path: &Path) -> Result<(), String> {
+        let Ok(relative) = path.strip_prefix(&dir.root) else {
+            return Ok(());
         };
-
-        // Check if path is still under user_dir
-        if !full.starts_with(&user_dir_canonical) {
-            tracing::warn!("Path check failed: full={:?}, user_dir_canonical={:?}",
-                full, user_dir_canonical);
-            return Err("accessing path outside user directory".to_string());
+        match legal_hold::held(&self.db, &dir.owner, &relative.to_string_lossy()).await {
+            Ok(None) => Ok(()),
+            Ok(Some(hold)) => Err(format!("{} is under legal hold", hold.path)),
+            Err(e) => Err(format!("failed to check legal hold: {}", e)),
         }
-        Ok(full)
     }
 
-    /// Normalize path by resolving . and ..
-    fn normalize_path(path: &Path) -> PathBuf {
-        let mut components = Vec::new();
-        for component in path.components() {
-            match component {
-                std::path::Component::ParentDir => {
-                    components.pop();
-                }
-                std::path::Component::CurDir => {}
-                c => components.push(c),
-            }
-        }
-        components.iter().collect()
+    /// Bring `path` of the tree of `dir` back from cold storage
+    async fn recall(&self, dir: &TaskDir, path: &Path) -> Result<(), String> {
+        let Ok(relative) = path.strip_prefix(&dir.root) else {
+            return Ok(());
+        };
+        tiering::recall(&self.db, &dir.owner, &dir.root, &relative.to_string_lossy())
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("failed to recall from cold storage: {}", e))
     }
 
     /// Calculate source files total size and count
+    #[instrument(level = "debug", skip(self))]
     async fn calc_source(&self) -> Result<(), String> {
         let info = self.info.read().await;
         let files = info.files.clone();
-        let source = info.source.clone();
         drop(info);
 
         let mut total_files: i64 = 0;
         let mut total_size: i64 = 0;
 
         for file in &files {
-            let full_path = self.join_user_path(&[&source, file])?;
+            let full_path = Self::join_user_path(&self.from, file)?;
 
             let metadata = tokio::fs::metadata(&full_path).await
                 .map_err(|e| format!("failed to stat source file: {}", e))?;
@@ -307,12 +480,9 @@ impl CopyTask {
     }
 
     /// Check target directory exists
+    #[instrument(level = "debug", skip(self))]
     async fn check_target(&self) -> Result<(), String> {
-        let info = self.info.read().await;
-        let target = info.target.clone();
-        drop(info);
-
-        let full_path = self.join_user_path(&[&target])?;
+        let full_path = Self::join_user_path(&self.to, "")?;
 
         let metadata = tokio::fs::metadata(&full_path).await
             .map_err(|_| "target path does not exist".to_string())?;
@@ -324,7 +494,7 @@ impl CopyTask {
     }
 
     /// Generate unique path for rename policy
-    fn generate_unique_path(path: &Path) -> PathBuf {
+    pub(super) fn generate_unique_path(path: &Path) -> PathBuf {
         let parent = path.parent().unwrap_or(Path::new(""));
         let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
         let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
@@ -344,6 +514,7 @@ impl CopyTask {
     }
 
     /// Copy or move files
+    #[instrument(level = "debug", skip(self))]
     async fn copy_or_move(&self) -> Result<(), String> {
         // Take the conflict receiver
         let mut conflict_rx = self.conflict_rx.write().await.take()
@@ -358,137 +529,268 @@ impl CopyTask {
         drop(info);
 
         for file in &files {
-            // Check cancelled
-            if *self.cancel_tx.borrow() {
-                return Err("task cancelled".to_string());
+            let src_desc = if source == "/" {
+                format!("/{}", file)
+            } else {
+                format!("{}/{}", source, file)
+            };
+            let desc = format!("{} => {}", src_desc, target);
+
+            let failed = self.info.read().await.failed_files.len();
+            match self
+                .process_file(file, is_copy, &mut conflict_policy, &mut conflict_rx)
+                .await
+            {
+                Ok(true) => {
+                    let failed = self.info.read().await.failed_files.len() - failed;
+                    if failed > 0 {
+                        self.audit(is_copy, &format!("{}: {} files failed", desc, failed), OP_FAILED);
+                    } else {
+                        self.audit(is_copy, &desc, OP_SUCCESS);
+                    }
+                }
+                Ok(false) => self.audit(is_copy, &desc, OP_SKIPPED),
+                // Cancellation is recorded once for the whole task
+                Err(e) if *self.cancel_tx.borrow() => return Err(e),
+                Err(e) if e == CONFLICT_ABORTED => {
+                    self.audit(is_copy, &format!("{}: {}", desc, e), OP_FAILED);
+                    return Err(e);
+                }
+                // The other entries go on
+                Err(e) => {
+                    self.audit(is_copy, &format!("{}: {}", desc, e), OP_FAILED);
+                    if let Ok(src_path) = Self::join_user_path(&self.from, file) {
+                        self.fail_file(&src_path, e).await;
+                    }
+                }
             }
+        }
 
-            let src_path = self.join_user_path(&[&source, file])?;
-            let mut dst_path = self.join_user_path(&[&target, file])?;
+        match self.info.read().await.failed_files.len() {
+            0 => Ok(()),
+            n => Err(format!("{} files failed", n)),
+        }
+    }
 
-            // Create parent directories
-            if let Some(parent) = dst_path.parent() {
-                tokio::fs::create_dir_all(parent).await
-                    .map_err(|e| format!("failed to create target directories: {}", e))?;
-            }
+    /// Copy or move a single top-level entry, returning false if it was skipped
+    #[instrument(level = "trace", skip(self, conflict_policy, conflict_rx))]
+    async fn process_file(
+        &self,
+        file: &str,
+        is_copy: bool,
+        conflict_policy: &mut ConflictPolicy,
+        conflict_rx: &mut tokio::sync::mpsc::Receiver<ConflictPolicy>,
+    ) -> Result<bool, String> {
+        // Check cancelled
+        if *self.cancel_tx.borrow() {
+            return Err("task cancelled".to_string());
+        }
 
-            // Check for conflict
-            if dst_path.exists() {
-                match conflict_policy {
-                    ConflictPolicy::Abort => {
-                        return Err("conflict detected, aborting".to_string());
-                    }
-                    ConflictPolicy::Skip => {
-                        continue;
+        let src_path = Self::join_user_path(&self.from, file)?;
+        let mut dst_path = Self::join_user_path(&self.to, file)?;
+
+        // Create parent directories
+        if let Some(parent) = dst_path.parent() {
+            tokio::fs::create_dir_all(parent).await
+                .map_err(|e| format!("failed to create target directories: {}", e))?;
+        }
+
+        // Check for conflict
+        if dst_path.exists() {
+            match *conflict_policy {
+                ConflictPolicy::Abort => {
+                    return Err(CONFLICT_ABORTED.to_string());
+                }
+                ConflictPolicy::Skip => {
+                    return Ok(false);
+                }
+                ConflictPolicy::Rename => {
+                    dst_path = Self::generate_unique_path(&dst_path);
+                }
+                ConflictPolicy::Overwrite => {
+                    // Proceed to overwrite
+                }
+                ConflictPolicy::Ask => {
+                    // Get conflict info
+                    let src_meta = tokio::fs::metadata(&src_path).await
+                        .map_err(|e| format!("failed to stat source: {}", e))?;
+                    let dst_meta = tokio::fs::metadata(&dst_path).await
+                        .map_err(|e| format!("failed to stat dest: {}", e))?;
+
+                    {
+                        let mut info = self.info.write().await;
+                        info.conflict_info.need_confirm = true;
+                        info.conflict_info.src_file = ConflictFileInfo {
+                            name: src_path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string(),
+                            size: src_meta.len() as i64,
+                            modify_time: src_meta.modified()
+                                .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
+                                .unwrap_or(0),
+                            is_directory: src_meta.is_dir(),
+                        };
+                        info.conflict_info.dst_file = ConflictFileInfo {
+                            name: dst_path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string(),
+                            size: dst_meta.len() as i64,
+                            modify_time: dst_meta.modified()
+                                .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
+                                .unwrap_or(0),
+                            is_directory: dst_meta.is_dir(),
+                        };
+                        info.updated_at = chrono::Utc::now().timestamp();
+                        self.notify(&info);
                     }
-                    ConflictPolicy::Rename => {
-                        dst_path = Self::generate_unique_path(&dst_path);
+
+                    // Wait for conflict resolution
+                    let policy = conflict_rx.recv().await
+                        .ok_or("conflict channel closed")?;
+
+                    // Clear conflict info
+                    {
+                        let mut info = self.info.write().await;
+                        info.conflict_info.need_confirm = false;
+                        info.conflict_info.src_file = ConflictFileInfo::default();
+                        info.conflict_info.dst_file = ConflictFileInfo::default();
+                        // Remember the policy for subsequent conflicts
+                        info.conflict_info.conflict_policy = policy;
                     }
-                    ConflictPolicy::Overwrite => {
-                        // Proceed to overwrite
+                    *conflict_policy = policy;
+
+                    // Check cancelled after waiting
+                    if *self.cancel_tx.borrow() {
+                        return Err("task cancelled".to_string());
                     }
-                    ConflictPolicy::Ask => {
-                        // Get conflict info
-                        let src_meta = tokio::fs::metadata(&src_path).await
-                            .map_err(|e| format!("failed to stat source: {}", e))?;
-                        let dst_meta = tokio::fs::metadata(&dst_path).await
-                            .map_err(|e| format!("failed to stat dest: {}", e))?;
-
-                        {
-                            let mut info = self.info.write().await;
-                            info.conflict_info.need_confirm = true;
-                            info.conflict_info.src_file = ConflictFileInfo {
-                                name: src_path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string(),
-                                size: src_meta.len() as i64,
-                                modify_time: src_meta.modified()
-                                    .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
-                                    .unwrap_or(0),
-                                is_directory: src_meta.is_dir(),
-                            };
-                            info.conflict_info.dst_file = ConflictFileInfo {
-                                name: dst_path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string(),
-                                size: dst_meta.len() as i64,
-                                modify_time: dst_meta.modified()
-                                    .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
-                                    .unwrap_or(0),
-                                is_directory: dst_meta.is_dir(),
-                            };
-                            info.updated_at = chrono::Utc::now().timestamp();
-                            self.notify(&info);
-                        }
 
-                        // Wait for conflict resolution
-                        let policy = conflict_rx.recv().await
-                            .ok_or("conflict channel closed")?;
-
-                        // Clear conflict info
-                        {
-                            let mut info = self.info.write().await;
-                            info.conflict_info.need_confirm = false;
-                            info.conflict_info.src_file = ConflictFileInfo::default();
-                            info.conflict_info.dst_file = ConflictFileInfo::default();
-                            // Remember the policy for subsequent conflicts
-                            info.conflict_info.conflict_policy = policy;
+                    match policy {
+                        ConflictPolicy::Abort => {
+                            return Err(CONFLICT_ABORTED.to_string());
                         }
-                        conflict_policy = policy;
-
-                        // Check cancelled after waiting
-                        if *self.cancel_tx.borrow() {
-                            return Err("task cancelled".to_string());
+                        ConflictPolicy::Skip => {
+                            return Ok(false);
                         }
-
-                        match policy {
-                            ConflictPolicy::Abort => {
-                                return Err("conflict detected, aborting".to_string());
-                            }
-                            ConflictPolicy::Skip => {
-                                continue;
-                            }
-                            ConflictPolicy::Rename => {
-                                dst_path = Self::generate_unique_path(&dst_path);
-                            }
-                            _ => {}
+                        ConflictPolicy::Rename => {
+                            dst_path = Self::generate_unique_path(&dst_path);
                         }
+                        _ => {}
                     }
                 }
             }
+        }
 
-            // Get source metadata
-            let src_meta = tokio::fs::metadata(&src_path).await
-                .map_err(|e| format!("failed to stat source: {}", e))?;
+        // Moving takes the source away, overwriting replaces the destination
+        if !is_copy {
+            self.check_hold(&self.from, &src_path).await?;
+        }
+        if dst_path.exists() {
+            self.check_hold(&self.to, &dst_path).await?;
+        }
 
-            // Update current file info
-            {
-                let mut info = self.info.write().await;
-                info.current_file = file.clone();
-                info.current_file_size = src_meta.len() as i64;
-                info.current_file_copied_size = 0;
-            }
+        // Stubs of files in cold storage can be moved within a tree, copies
+        // and replaced files need the content
+        let same_tree = self.from.owner == self.to.owner;
+        if is_copy || !same_tree {
+            self.recall(&self.from, &src_path).await?;
+        }
+        if dst_path.exists() {
+            self.recall(&self.to, &dst_path).await?;
+        }
 
-            if is_copy {
-                self.copy_file(&src_path, &dst_path).await?;
-            } else {
-                // Move: try rename first, fall back to copy+delete
-                if tokio::fs::rename(&src_path, &dst_path).await.is_err() {
-                    self.copy_file(&src_path, &dst_path).await?;
-                    if src_meta.is_dir() {
-                        tokio::fs::remove_dir_all(&src_path).await
-                            .map_err(|e| format!("failed to remove source dir: {}", e))?;
-                    } else {
-                        tokio::fs::remove_file(&src_path).await
-                            .map_err(|e| format!("failed to remove source file: {}", e))?;
-                    }
-                }
+        // Get source metadata
+        let src_meta = tokio::fs::metadata(&src_path).await
+            .map_err(|e| format!("failed to stat source: {}", e))?;
 
-                // Update progress for move
-                let mut info = self.info.write().await;
-                info.copied_files += 1;
-                info.copied_size += info.current_file_size;
-                info.updated_at = chrono::Utc::now().timestamp();
-                self.notify(&info);
+        // Update current file info
+        {
+            let mut info = self.info.write().await;
+            info.current_file = file.to_string();
+            info.current_file_size = src_meta.len() as i64;
+            info.current_file_copied_size = 0;
+        }
+
+        if is_copy {
+            self.copy_file(&src_path, &dst_path).await?;
+        } else {
+            let entry = self.journal_move(&src_path, &dst_path).await?;
+            let result = self.move_file(&src_path, &dst_path, src_meta.is_dir(), same_tree, entry).await;
+            journal::finish(&self.db, entry).await;
+            result?;
+            if let Some(name) = dst_path.file_name() {
+                self.moved.lock().unwrap().push(name.to_string_lossy().into_owned());
             }
+
+            // Update progress for move
+            let mut info = self.info.write().await;
+            info.copied_files += 1;
+            info.copied_size += info.current_file_size;
+            info.updated_at = chrono::Utc::now().timestamp();
+            self.notify(&info);
         }
 
+        if let Ok(to) = dst_path.strip_prefix(&self.to.root) {
+            dir_version::bump_entry(&self.to.owner, &to.to_string_lossy());
+            let kind = if is_copy { ChangeKind::Created } else { ChangeKind::Moved };
+            watched::publish(&self.to.owner, &to.to_string_lossy(), &self.username, kind, Client::Web);
+        }
+        if let (false, Ok(from)) = (is_copy, src_path.strip_prefix(&self.from.root)) {
+            dir_version::bump_entry(&self.from.owner, &from.to_string_lossy());
+            watched::publish(&self.from.owner, &from.to_string_lossy(), &self.username, ChangeKind::Moved, Client::Web);
+        }
+
+        Ok(true)
+    }
+
+    /// Record the intent to move `src` to `dst` in the journal, the id of
+    /// the entry
+    async fn journal_move(&self, src: &Path, dst: &Path) -> Result<i64, String> {
+        let relative = |dir: &TaskDir, path: &Path| {
+            path.strip_prefix(&dir.root)
+                .map(|p| p.to_string_lossy().into_owned())
+                .map_err(|_| format!("{} is outside of the tree", path.display()))
+        };
+        let intent = Intent::Move {
+            from_owner: self.from.owner.clone(),
+            from: relative(&self.from, src)?,
+            to_owner: self.to.owner.clone(),
+            to: relative(&self.to, dst)?,
+            replaced: dst.exists(),
+        };
+        journal::begin(&self.db, &intent).await
+            .map_err(|e| format!("failed to record move: {}", e))
+    }
+
+    /// Move a file or directory whose move is journal entry `entry`
+    async fn move_file(&self, src_path: &Path, dst_path: &Path, is_dir: bool, same_tree: bool, entry: i64) -> Result<(), String> {
+        // Try rename first, fall back to copy+delete
+        if tokio::fs::rename(src_path, dst_path).await.is_err() {
+            let failed = self.info.read().await.failed_files.len();
+            self.copy_file(src_path, dst_path).await?;
+            if self.info.read().await.failed_files.len() > failed {
+                return Err("not all files were copied, the source was kept".to_string());
+            }
+            // From here on the source goes, even if the process dies
+            journal::advance(&self.db, entry, journal_entry::STEP_COPIED).await
+                .map_err(|e| format!("failed to record move: {}", e))?;
+            if is_dir {
+                tokio::fs::remove_dir_all(src_path).await
+                    .map_err(|e| format!("failed to remove source dir: {}", e))?;
+            } else {
+                tokio::fs::remove_file(src_path).await
+                    .map_err(|e| format!("failed to remove source file: {}", e))?;
+            }
+        }
+        // Tags stay with the tree they were set in
+        let moved = (src_path.strip_prefix(&self.from.root), dst_path.strip_prefix(&self.to.root));
+        if let (true, Ok(from), Ok(to)) = (same_tree, moved.0, moved.1) {
+            let owner = &self.from.owner;
+            if let Err(e) = tag::move_tags(&self.db, owner, &from.to_string_lossy(), &to.to_string_lossy()).await {
+                tracing::error!("Failed to move tags of {}: {}", from.display(), e);
+            }
+            if let Err(e) = tiering::move_stubs(&self.db, owner, &from.to_string_lossy(), &to.to_string_lossy()).await {
+                tracing::error!("Failed to move cold storage stubs of {}: {}", from.display(), e);
+            }
+            if let Err(e) = sensitive::move_marks(&self.db, owner, &from.to_string_lossy(), &to.to_string_lossy()).await {
+                tracing::error!("Failed to move sensitive marks of {}: {}", from.display(), e);
+            }
+        }
         Ok(())
     }
 
@@ -502,11 +804,41 @@ impl CopyTask {
                 return self.copy_dir(src, dst).await;
             }
 
-        // Copy file with progress tracking
+            let mut attempt = 1;
+            let copied = loop {
+                match self.copy_contents(src, dst).await {
+                    Ok(copied) => break copied,
+                    Err(CopyFailure::Io(context, e)) if retry::is_transient(&e) => {
+                        attempt += 1;
+                        let Some(delay) = self.retry.delay(attempt) else {
+                            return Err(CopyFailure::Io(context, e).to_string());
+                        };
+                        tracing::warn!("Retrying {} in {:?}, {}: {}", src.display(), delay, context, e);
+                        tokio::time::sleep(delay).await;
+                    }
+                    Err(e) => return Err(e.to_string()),
+                }
+            };
+
+            // Update copied count
+            let mut info = self.info.write().await;
+            info.copied_files += 1;
+            info.copied_size += copied;
+            info.updated_at = chrono::Utc::now().timestamp();
+            self.notify(&info);
+
+            Ok(())
+        })
+    }
+
+    /// Copy the content of a file with progress tracking, returning its size
+    async fn copy_contents(&self, src: &Path, dst: &Path) -> Result<i64, CopyFailure> {
+        use tokio::io::AsyncReadExt;
+
         let mut src_file = tokio::fs::File::open(src).await
-            .map_err(|e| format!("failed to open source: {}", e))?;
+            .map_err(|e| CopyFailure::Io("failed to open source", e))?;
         let mut dst_file = tokio::fs::File::create(dst).await
-            .map_err(|e| format!("failed to create dest: {}", e))?;
+            .map_err(|e| CopyFailure::Io("failed to create dest", e))?;
 
         let mut buf = vec![0u8; 1024 * 1024]; // 1MB buffer
         let mut copied: i64 = 0;
@@ -514,52 +846,63 @@ impl CopyTask {
         loop {
             // Check cancelled
             if *self.cancel_tx.borrow() {
-                return Err("task cancelled".to_string());
+                return Err(CopyFailure::Cancelled);
             }
 
             // Check suspended
             while *self.suspend_tx.borrow() {
                 tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
                 if *self.cancel_tx.borrow() {
-                    return Err("task cancelled".to_string());
+                    return Err(CopyFailure::Cancelled);
                 }
             }
 
-            use tokio::io::{AsyncReadExt, AsyncWriteExt};
             let n = src_file.read(&mut buf).await
-                .map_err(|e| format!("failed to read: {}", e))?;
+                .map_err(|e| CopyFailure::Io("failed to read", e))?;
 
             if n == 0 {
                 break;
             }
 
             dst_file.write_all(&buf[..n]).await
-                .map_err(|e| format!("failed to write: {}", e))?;
+                .map_err(|e| CopyFailure::Io("failed to write", e))?;
 
             copied += n as i64;
 
             // Update progress
-            let mut info = self.info.write().await;
-            info.current_file_copied_size = copied;
-            info.updated_at = chrono::Utc::now().timestamp();
-            self.notify(&info);
+            {
+                let mut info = self.info.write().await;
+                info.current_file_copied_size = copied;
+                info.updated_at = chrono::Utc::now().timestamp();
+                self.notify(&info);
+            }
+
+            self.throttle.wait(&self.pacer, n as u64).await;
         }
 
         dst_file.flush().await
-            .map_err(|e| format!("failed to flush: {}", e))?;
-
-            // Update copied count
-            let mut info = self.info.write().await;
-            info.copied_files += 1;
-            info.copied_size += copied;
-            info.updated_at = chrono::Utc::now().timestamp();
-            self.notify(&info);
+            .map_err(|e| CopyFailure::Io("failed to flush", e))?;
+        Ok(copied)
+    }
 
-            Ok(())
-        })
+    /// Add `path` of the source tree to the files that failed
+    async fn fail_file(&self, path: &Path, error: String) {
+        let folder = self.from.root.join(self.from.path.trim_matches('/'));
+        let mut info = self.info.write().await;
+        let shown = match path.strip_prefix(&folder) {
+            Ok(relative) => format!("{}/{}", info.source.trim_end_matches('/'), relative.to_string_lossy()),
+            Err(_) => path.to_string_lossy().into_owned(),
+        };
+        tracing::warn!("Task {} failed on {}: {}", info.id, shown, error);
+        info.failed_files.push(FailedFile { path: shown, error });
+        info.updated_at = chrono::Utc::now().timestamp();
+        self.notify(&info);
     }
 
     /// Copy directory recursively
+    ///
+    /// Small files are copied by several workers at once once the folder
+    /// was read, larger files and subfolders one after the other.
     fn copy_dir<'a>(&'a self, src: &'a Path, dst: &'a Path) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), String>> + Send + 'a>> {
         Box::pin(async move {
             tokio::fs::create_dir_all(dst).await
@@ -567,6 +910,9 @@ impl CopyTask {
 
             let mut entries = tokio::fs::read_dir(src).await
                 .map_err(|e| format!("failed to read dir: {}", e))?;
+            let workers = self.copy.workers.max(1);
+            let small_file_size = self.copy.small_file_kb * 1024;
+            let mut small_files = Vec::new();
 
             while let Some(entry) = entries.next_entry().await
                 .map_err(|e| format!("failed to read entry: {}", e))?
@@ -584,47 +930,97 @@ impl CopyTask {
                 let src_path = entry.path();
                 let dst_path = dst.join(entry.file_name());
 
-                let meta = entry.metadata().await
-                    .map_err(|e| format!("failed to get metadata: {}", e))?;
-
-                // Update current file
-                {
-                    let mut info = self.info.write().await;
-                    info.current_file = entry.file_name().to_string_lossy().to_string();
-                    info.current_file_size = meta.len() as i64;
-                    info.current_file_copied_size = 0;
+                let meta = match entry.metadata().await {
+                    Ok(meta) => meta,
+                    Err(e) => {
+                        self.fail_file(&src_path, format!("failed to get metadata: {}", e)).await;
+                        continue;
+                    }
+                };
+                if workers > 1 && meta.is_file() && meta.len() <= small_file_size {
+                    small_files.push((src_path, dst_path, meta.len()));
+                    continue;
                 }
 
-                self.copy_file(&src_path, &dst_path).await?;
+                self.copy_entry(&src_path, &dst_path, meta.len()).await?;
+            }
+
+            futures::stream::iter(small_files)
+                .for_each_concurrent(workers, |(src_path, dst_path, size)| async move {
+                    while *self.suspend_tx.borrow() && !*self.cancel_tx.borrow() {
+                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                    }
+                    // Files not started yet are left alone once cancelled
+                    if !*self.cancel_tx.borrow() {
+                        let _ = self.copy_entry(&src_path, &dst_path, size).await;
+                    }
+                })
+                .await;
+            if *self.cancel_tx.borrow() {
+                return Err("task cancelled".to_string());
             }
 
             Ok(())
         })
     }
 
+    /// Copy an entry of a folder being copied, recording it if it fails
+    ///
+    /// Fails only if the task was cancelled, one file failing doesn't stop
+    /// the others.
+    async fn copy_entry(&self, src: &Path, dst: &Path, size: u64) -> Result<(), String> {
+        // Update current file
+        {
+            let mut info = self.info.write().await;
+            info.current_file = src.file_name().unwrap_or_default().to_string_lossy().to_string();
+            info.current_file_size = size as i64;
+            info.current_file_copied_size = 0;
+        }
+
+        if let Err(e) = self.copy_file(src, dst).await {
+            if *self.cancel_tx.borrow() {
+                return Err(e);
+            }
+            self.fail_file(src, e).await;
+        }
+        Ok(())
+    }
+
+    /// Record the entries moved so far for undoing, unless the task is an undo
+    #[instrument(level = "debug", skip(self))]
+    async fn record_move(&self) {
+        let files = std::mem::take(&mut *self.moved.lock().unwrap());
+        let (source, target) = {
+            let info = self.info.read().await;
+            if info.agent == undo::AGENT {
+                return;
+            }
+            (info.source.clone(), info.target.clone())
+        };
+        undo::record(&self.db, &self.username, undo::Operation::Move { source, target, files }).await;
+    }
+
     /// Run the copy task
+    #[instrument(level = "debug", name = "copy_task", skip(self), fields(id = Empty, is_copy = Empty))]
     async fn run_async(&self) {
         // Update status to starting
-        {
+        let (is_copy, task_desc) = {
             let mut info = self.info.write().await;
+            tracing::Span::current().record("id", info.id.as_str()).record("is_copy", info.is_copy);
             info.status = TaskStatus::Starting;
             info.started_at = chrono::Utc::now().timestamp();
             info.updated_at = info.started_at;
             self.notify(&info);
-        }
-
-        // Calculate source
-        if let Err(e) = self.calc_source().await {
-            let mut info = self.info.write().await;
-            info.status = TaskStatus::Failed;
-            info.error = Some(e);
-            info.updated_at = chrono::Utc::now().timestamp();
-            self.notify(&info);
-            return;
-        }
+            (info.is_copy, format!("{} => {}", info.source, info.target))
+        };
 
-        // Check target
-        if let Err(e) = self.check_target().await {
+        // Calculate source and check target
+        let prepared = match self.calc_source().await {
+            Ok(()) => self.check_target().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = prepared {
+            self.audit(is_copy, &format!("任务失败 {}: {}", task_desc, e), OP_FAILED);
             let mut info = self.info.write().await;
             info.status = TaskStatus::Failed;
             info.error = Some(e);
@@ -640,9 +1036,25 @@ impl CopyTask {
             info.updated_at = chrono::Utc::now().timestamp();
             self.notify(&info);
         }
-
-        // Copy or move
-        if let Err(e) = self.copy_or_move().await {
+        self.audit(is_copy, &format!("任务开始 {}", task_desc), OP_SUCCESS);
+
+        // Copy or move, each file is audited as it finishes
+        let result = self.copy_or_move().await;
+        self.record_move().await;
+        if is_copy {
+            // Copies (possibly partial, possibly overwriting) change the used
+            // space in ways that are simplest to rescan
+            quota::invalidate(&self.to.owner);
+        } else if self.from.owner != self.to.owner {
+            quota::invalidate(&self.from.owner);
+            quota::invalidate(&self.to.owner);
+        }
+        if let Err(e) = result {
+            if *self.cancel_tx.borrow() {
+                // Status was already set by cancel()
+                self.audit(is_copy, &format!("任务取消 {}", task_desc), OP_CANCELLED);
+                return;
+            }
             let mut info = self.info.write().await;
             info.status = TaskStatus::Failed;
             info.error = Some(e);
@@ -658,6 +1070,7 @@ impl CopyTask {
             info.updated_at = chrono::Utc::now().timestamp();
             self.notify(&info);
         }
+        self.audit(is_copy, &format!("任务完成 {}", task_desc), OP_SUCCESS);
     }
 }
 
@@ -670,6 +1083,17 @@ impl Task for CopyTask {
         futures::executor::block_on(async { self.info.read().await.id.clone() })
     }
 
+    fn enqueue(&self) {
+        futures::executor::block_on(async {
+            let mut info = self.info.write().await;
+            if info.status == TaskStatus::Pending {
+                info.status = TaskStatus::Queued;
+                info.updated_at = chrono::Utc::now().timestamp();
+                self.notify(&info);
+            }
+        });
+    }
+
     fn start(self: Arc<Self>) {
         tokio::spawn(async move {
             self.run_async().await;
@@ -722,7 +1146,28 @@ pub enum TaskNotification {
     #[serde(rename = "taskInfo")]
     TaskInfo(TaskInfo),
     #[serde(rename = "taskDeleted")]
-    TaskDeleted(String),
+    TaskDeleted { user_id: i64, id: String },
+}
+
+impl TaskNotification {
+    /// User the notification is for
+    pub fn user_id(&self) -> i64 {
+        match self {
+            TaskNotification::TaskInfo(info) => info.user_id,
+            TaskNotification::TaskDeleted { user_id, .. } => *user_id,
+        }
+    }
+}
+
+/// Task lifecycle change, used to persist tasks
+///
+/// Only creation and status transitions are sent, not progress updates.
+#[derive(Debug, Clone)]
+pub enum TaskChange {
+    /// Task created or its status changed
+    Updated(Box<TaskInfo>),
+    /// Task removed from the user's task list
+    Removed { user_id: i64, id: String },
 }
 
 /// Task Manager
@@ -731,21 +1176,88 @@ pub struct TaskManager {
     tasks: DashMap<i64, Vec<Arc<dyn Task>>>,
     /// Notification channel
     notify_tx: broadcast::Sender<TaskNotification>,
+    /// Channel of task lifecycle changes, kept separate from the
+    /// high-volume progress notifications so persistence never lags
+    change_tx: broadcast::Sender<TaskChange>,
+    /// Workers for thumbnails and conversions
+    jobs: std::sync::OnceLock<JobPool>,
+    /// Throughput limits of copies and moves
+    throttle: Arc<Throttle>,
+    /// Running slots, no limit until configured
+    queue: Arc<TaskQueue>,
+    /// Retries of files copies and moves fail on
+    retry: std::sync::Mutex<RetryPolicy>,
+    /// Small files copies and moves copy at the same time
+    copy: std::sync::Mutex<TaskCopyConfig>,
 }
 
 impl TaskManager {
     pub fn new() -> Self {
         let (notify_tx, _) = broadcast::channel(100);
+        let (change_tx, _) = broadcast::channel(1024);
         Self {
             tasks: DashMap::new(),
             notify_tx,
+            change_tx,
+            jobs: std::sync::OnceLock::new(),
+            throttle: Arc::new(Throttle::default()),
+            queue: Arc::new(TaskQueue::default()),
+            retry: std::sync::Mutex::new(RetryPolicy::default()),
+            copy: std::sync::Mutex::new(TaskCopyConfig::default()),
         }
     }
 
+    /// Set the throughput limits of copies and moves from the configuration
+    pub fn configure_limits(&self, config: &TaskLimitConfig) {
+        self.throttle.configure(config);
+    }
+
+    /// Throughput limits of copies and moves
+    pub fn throttle(&self) -> &Throttle {
+        &self.throttle
+    }
+
+    /// Set how many tasks run at the same time from the configuration
+    pub fn configure_queue(&self, config: &TaskQueueConfig) {
+        self.queue.configure(config);
+    }
+
+    /// Set the retries of failing files from the configuration, for tasks
+    /// created from now on
+    pub fn configure_retry(&self, config: &TaskRetryConfig) {
+        *self.retry.lock().unwrap() = RetryPolicy::new(config);
+    }
+
+    /// Set how many small files copies and moves copy at the same time from
+    /// the configuration, for tasks created from now on
+    pub fn configure_copy(&self, config: &TaskCopyConfig) {
+        *self.copy.lock().unwrap() = *config;
+    }
+
+    /// Size the job pool, before the first job
+    pub fn configure_jobs(&self, config: &WorkersConfig) {
+        if self.jobs.set(JobPool::new(config)).is_err() {
+            tracing::warn!("Job pool already in use, worker settings not applied");
+        }
+    }
+
+    /// Pool running thumbnails and conversions
+    pub fn jobs(&self) -> &JobPool {
+        self.jobs.get_or_init(|| JobPool::new(&WorkersConfig::default()))
+    }
+
+    /// Number of tasks in `status`, over all users
+    pub fn count_status(&self, status: TaskStatus) -> usize {
+        self.tasks
+            .iter()
+            .map(|tasks| tasks.iter().filter(|t| t.info().status == status).count())
+            .sum()
+    }
+
     /// Add a task
     pub fn add_task(&self, task: Arc<dyn Task>) {
         let info = task.info();
-        let user_id = info.user_id;
+        let (user_id, task_type) = (info.user_id, info.task_type);
 
         self.tasks
             .entry(user_id)
@@ -753,13 +1265,45 @@ impl TaskManager {
             .push(task.clone());
 
         // Notify about new task
+        let _ = self.change_tx.send(TaskChange::Updated(Box::new(info.clone())));
         let _ = self.notify_tx.send(TaskNotification::TaskInfo(info));
 
-        // Start task in background
-        task.start();
+        // Start task in background once it gets a slot
+        let mut slot = self.queue.enter(user_id, task_type);
+        let change_tx = self.change_tx.clone();
+        tokio::spawn(async move {
+            let slot = match slot.try_recv() {
+                Ok(slot) => slot,
+                Err(_) => {
+                    task.enqueue();
+                    match slot.await {
+                        Ok(slot) => slot,
+                        Err(_) => return,
+                    }
+                }
+            };
+            // Cancelled while waiting
+            if task.info().status.is_finished() {
+                return;
+            }
+            let mut changes = change_tx.subscribe();
+            let id = task.id();
+            task.clone().start();
+            loop {
+                match changes.recv().await {
+                    Ok(TaskChange::Updated(info)) if info.id == id && info.status.is_finished() => break,
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(_)) if task.info().status.is_finished() => break,
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            drop(slot);
+        });
     }
 
     /// Create and add a copy task
+    #[allow(clippy::too_many_arguments)]
     pub fn create_copy_task(
         &self,
         user_id: i64,
@@ -769,7 +1313,9 @@ impl TaskManager {
         source: String,
         target: String,
         files: Vec<String>,
-        user_dir: PathBuf,
+        from: TaskDir,
+        to: TaskDir,
+        db: DatabaseConnection,
     ) -> TaskInfo {
         let task = Arc::new(CopyTask::new(
             user_id,
@@ -779,8 +1325,182 @@ impl TaskManager {
             source,
             target,
             files,
+            from,
+            to,
+            db,
+            self.throttle.clone(),
+            *self.retry.lock().unwrap(),
+            *self.copy.lock().unwrap(),
+            self.notify_tx.clone(),
+            self.change_tx.clone(),
+        ));
+
+        let info = task.info();
+        self.add_task(task);
+        info
+    }
+
+    /// Create and add a task building a zip of `files` under `parent_dir`
+    ///
+    /// The archive is written to `{archive_dir}/{task id}.zip`.
+    pub fn create_archive_task(
+        &self,
+        user_id: i64,
+        parent_dir: String,
+        files: Vec<String>,
+        user_dir: &Path,
+        archive_dir: &Path,
+    ) -> TaskInfo {
+        let task = Arc::new(ArchiveTask::new(
+            user_id,
+            parent_dir,
+            files,
             user_dir,
+            archive_dir,
+            self.notify_tx.clone(),
+            self.change_tx.clone(),
+        ));
+
+        let info = task.info();
+        self.add_task(task);
+        info
+    }
+
+    /// Create and add a task packing `files` of `from` into an archive in `to`
+    ///
+    /// `source` and `target` are the folders as the user sees them; the
+    /// archive is built in `work_dir`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_compress_task(
+        &self,
+        user_id: i64,
+        username: &str,
+        source: String,
+        target: String,
+        files: Vec<String>,
+        from: TaskDir,
+        to: TaskDir,
+        file_name: String,
+        format: ArchiveFormat,
+        work_dir: PathBuf,
+    ) -> TaskInfo {
+        let task = Arc::new(CompressTask::new(
+            user_id,
+            username,
+            source,
+            target,
+            files,
+            from,
+            to,
+            file_name,
+            format,
+            work_dir,
+            self.notify_tx.clone(),
+            self.change_tx.clone(),
+        ));
+
+        let info = task.info();
+        self.add_task(task);
+        info
+    }
+
+    /// Create and add a task unpacking `archive` into `to`
+    ///
+    /// Entries are unpacked to `{work_dir}/{task id}` first.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_extract_task(
+        &self,
+        user_id: i64,
+        username: &str,
+        source: String,
+        target: String,
+        archive: PathBuf,
+        kind: ArchiveKind,
+        total_size: i64,
+        to: TaskDir,
+        conflict_policy: ConflictPolicy,
+        work_dir: PathBuf,
+        config: Arc<Config>,
+        db: DatabaseConnection,
+    ) -> TaskInfo {
+        let task = Arc::new(ExtractTask::new(
+            user_id,
+            username,
+            source,
+            target,
+            archive,
+            kind,
+            total_size,
+            to,
+            conflict_policy,
+            work_dir,
+            config,
+            db,
             self.notify_tx.clone(),
+            self.change_tx.clone(),
+        ));
+
+        let info = task.info();
+        self.add_task(task);
+        info
+    }
+
+    /// Create and add a task moving `files` in `location` to the trash, or
+    /// removing them for good if `permanent`
+    ///
+    /// `parent_dir` is the folder as the user sees it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_delete_task(
+        &self,
+        user_id: i64,
+        username: &str,
+        parent_dir: String,
+        files: Vec<String>,
+        permanent: bool,
+        location: Location,
+        config: Arc<Config>,
+        db: DatabaseConnection,
+    ) -> TaskInfo {
+        let task = Arc::new(DeleteTask::new(
+            user_id,
+            username,
+            parent_dir,
+            files,
+            permanent,
+            location,
+            config,
+            db,
+            self.notify_tx.clone(),
+            self.change_tx.clone(),
+        ));
+
+        let info = task.info();
+        self.add_task(task);
+        info
+    }
+
+    /// Create and add a task renaming entries of `location` from the first name of each pair to the second
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_rename_task(
+        &self,
+        user_id: i64,
+        username: &str,
+        dir: String,
+        renames: Vec<(String, String)>,
+        location: Location,
+        config: Arc<Config>,
+        db: DatabaseConnection,
+    ) -> TaskInfo {
+        let task = Arc::new(RenameTask::new(
+            user_id,
+            username,
+            dir,
+            renames,
+            location,
+            config,
+            db,
+            self.notify_tx.clone(),
+            self.change_tx.clone(),
         ));
 
         let info = task.info();
@@ -811,11 +1531,18 @@ impl TaskManager {
         if let Some(mut tasks) = self.tasks.get_mut(&user_id) {
             tasks.retain(|t| t.id() != task_id);
         }
+        let _ = self.change_tx.send(TaskChange::Removed {
+            user_id,
+            id: task_id.to_string(),
+        });
 
         // Notify about task deletion
         let _ = self
             .notify_tx
-            .send(TaskNotification::TaskDeleted(task_id.to_string()));
+            .send(TaskNotification::TaskDeleted {
+                user_id,
+                id: task_id.to_string(),
+            });
     }
 
     /// Get notification receiver
@@ -823,6 +1550,11 @@ impl TaskManager {
         self.notify_tx.subscribe()
     }
 
+    /// Get receiver of task lifecycle changes
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<TaskChange> {
+        self.change_tx.subscribe()
+    }
+
     /// Get notification sender (for creating tasks)
     pub fn notify_sender(&self) -> broadcast::Sender<TaskNotification> {
         self.notify_tx.clone()
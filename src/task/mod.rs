@@ -2,6 +2,19 @@
 //!
 //! Provides background task management for file operations like copy/move
 
+mod archive;
+mod compress;
+mod control;
+mod delete;
+mod extract;
+mod jobs;
 mod manager;
+mod queue;
+mod rename;
+mod retry;
+mod throttle;
 
-pub use manager::{ConflictPolicy, TaskNotification, TaskStatus, TASK_MANAGER};
+pub use compress::ArchiveFormat;
+pub use jobs::JobError;
+pub use manager::{ConflictInfo, ConflictPolicy, TaskChange, TaskDir, TaskInfo, TaskNotification, TaskStatus, TaskType, TASK_MANAGER};
+pub use throttle::Limits;
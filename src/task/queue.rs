@@ -0,0 +1,167 @@
+//! Queue of tasks waiting to run
+//!
+//! Every task running at once reads and writes the same disks, so only a
+//! configured number run together, overall and per user. The others wait
+//! with the `queued` status and start in order as running ones finish:
+//! archives built for a download and deletions first, as someone waits for
+//! them, then the rest by age. A task whose user is at the limit doesn't
+//! hold up those of other users behind it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+use super::manager::TaskType;
+use crate::config::TaskQueueConfig;
+
+/// Order in which waiting tasks of `task_type` start, higher first
+fn priority(task_type: TaskType) -> u8 {
+    match task_type {
+        TaskType::Archive | TaskType::Delete | TaskType::Rename => 1,
+        TaskType::Copy | TaskType::Move | TaskType::Compress | TaskType::Extract => 0,
+    }
+}
+
+struct Waiter {
+    user_id: i64,
+    priority: u8,
+    start: oneshot::Sender<Slot>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    /// Tasks running at once (0 = no limit)
+    max_running: usize,
+    /// Tasks of one user running at once (0 = no limit)
+    max_per_user: usize,
+    running: usize,
+    per_user: HashMap<i64, usize>,
+    /// In the order they start in
+    waiting: Vec<Waiter>,
+}
+
+impl QueueState {
+    fn has_room(&self, user_id: i64) -> bool {
+        let user = self.per_user.get(&user_id).copied().unwrap_or(0);
+        (self.max_running == 0 || self.running < self.max_running)
+            && (self.max_per_user == 0 || user < self.max_per_user)
+    }
+}
+
+/// Slots of running tasks
+#[derive(Default)]
+pub struct TaskQueue {
+    state: Mutex<QueueState>,
+}
+
+/// Running slot of a task, given back when dropped
+pub struct Slot {
+    queue: Arc<TaskQueue>,
+    user_id: i64,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        {
+            let mut state = self.queue.state.lock().unwrap();
+            state.running = state.running.saturating_sub(1);
+            if let Some(count) = state.per_user.get_mut(&self.user_id) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    state.per_user.remove(&self.user_id);
+                }
+            }
+        }
+        self.queue.promote();
+    }
+}
+
+impl TaskQueue {
+    /// Set the limits, starting waiting tasks they now leave room for
+    pub fn configure(self: &Arc<Self>, config: &TaskQueueConfig) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.max_running = config.max_running;
+            state.max_per_user = config.per_user;
+        }
+        self.promote();
+    }
+
+    /// Line a task of `user_id` up for a slot, received once it may start
+    pub fn enter(self: &Arc<Self>, user_id: i64, task_type: TaskType) -> oneshot::Receiver<Slot> {
+        let (start, slot) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            let priority = priority(task_type);
+            let at = state.waiting.iter().position(|w| w.priority < priority).unwrap_or(state.waiting.len());
+            state.waiting.insert(at, Waiter { user_id, priority, start });
+        }
+        self.promote();
+        slot
+    }
+
+    /// Hand slots to the first waiting tasks there is room for
+    fn promote(self: &Arc<Self>) {
+        let mut granted = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            let mut i = 0;
+            while i < state.waiting.len() {
+                if state.max_running > 0 && state.running >= state.max_running {
+                    break;
+                }
+                let user_id = state.waiting[i].user_id;
+                if !state.has_room(user_id) {
+                    i += 1;
+                    continue;
+                }
+                let waiter = state.waiting.remove(i);
+                state.running += 1;
+                *state.per_user.entry(user_id).or_insert(0) += 1;
+                granted.push(waiter);
+            }
+        }
+        // Outside the lock: the slot of a task given up is dropped right away
+        for waiter in granted {
+            let _ = waiter.start.send(Slot { queue: self.clone(), user_id: waiter.user_id });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue() {
+        let queue = Arc::new(TaskQueue::default());
+        queue.configure(&TaskQueueConfig { max_running: 2, per_user: 1 });
+
+        let mut a1 = queue.enter(1, TaskType::Copy);
+        let mut a2 = queue.enter(1, TaskType::Copy);
+        let mut b1 = queue.enter(2, TaskType::Move);
+        let mut c1 = queue.enter(3, TaskType::Copy);
+        let mut c2 = queue.enter(3, TaskType::Delete);
+        // User 1 is at its limit, user 2 gets the other slot
+        let a1 = a1.try_recv().unwrap();
+        assert!(a2.try_recv().is_err());
+        let b1 = b1.try_recv().unwrap();
+
+        // Deletions go first
+        drop(b1);
+        let c2 = c2.try_recv().unwrap();
+        assert!(c1.try_recv().is_err());
+        drop(a1);
+        let a2 = a2.try_recv().unwrap();
+        drop(c2);
+        let _c1 = c1.try_recv().unwrap();
+        assert!(queue.state.lock().unwrap().waiting.is_empty());
+
+        // Slots of tasks given up go to the next one
+        let given_up = queue.enter(4, TaskType::Copy);
+        let mut next = queue.enter(5, TaskType::Copy);
+        drop(given_up);
+        drop(a2);
+        assert!(next.try_recv().is_ok());
+    }
+}
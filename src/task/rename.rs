@@ -0,0 +1,149 @@
+//! Rename task implementation
+//!
+//! Renames the entries of a folder planned by a bulk rename, one after the
+//! other, showing the progress in the task list.
+
+use sea_orm::DatabaseConnection;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use super::control::TaskControl;
+use super::manager::{Task, TaskChange, TaskInfo, TaskNotification, TaskStatus, TaskType};
+use super::ConflictPolicy;
+use crate::config::Config;
+use crate::handlers::dept_space::Location;
+use crate::handlers::legal_hold;
+use crate::handlers::undo;
+use crate::service::{FileError, FileService};
+
+/// Task renaming entries of a folder
+pub struct RenameTask {
+    control: TaskControl,
+    /// User the renames are recorded for
+    username: String,
+    /// Folder of the entries
+    location: Location,
+    /// Current and new names
+    renames: Vec<(String, String)>,
+    config: Arc<Config>,
+    db: DatabaseConnection,
+}
+
+impl RenameTask {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        user_id: i64,
+        username: &str,
+        dir: String,
+        renames: Vec<(String, String)>,
+        location: Location,
+        config: Arc<Config>,
+        db: DatabaseConnection,
+        notify_tx: broadcast::Sender<TaskNotification>,
+        change_tx: broadcast::Sender<TaskChange>,
+    ) -> Self {
+        let mut info = TaskInfo::new(user_id, "web", TaskType::Rename);
+        info.source = dir;
+        info.total_files = renames.len() as i64;
+        info.files = renames.iter().map(|(from, _)| from.clone()).collect();
+
+        Self {
+            control: TaskControl::new(info, notify_tx, change_tx),
+            username: username.to_string(),
+            location,
+            renames,
+            config,
+            db,
+        }
+    }
+
+    #[tracing::instrument(level = "debug", name = "rename_task", skip(self), fields(id = %self.id()))]
+    async fn run(&self) {
+        self.control.update(|info| {
+            info.status = TaskStatus::Running;
+            info.started_at = chrono::Utc::now().timestamp();
+        });
+
+        let service = FileService::new(&self.config, &self.db, &self.location.owner)
+            .on_behalf_of(&self.username, &self.location.prefix);
+        let dir = self.location.path.trim_matches('/');
+        let mut failed = 0;
+        let mut held = Vec::new();
+        let mut renamed = Vec::new();
+        let mut cancelled = false;
+        for (from, to) in &self.renames {
+            if !self.control.wait().await {
+                cancelled = true;
+                break;
+            }
+            self.control.update(|info| info.current_file = from.clone());
+            // Each rename is audited by the service
+            match service.rename(&format!("{}/{}", dir, from), to).await {
+                Ok(name) => renamed.push((from.clone(), name)),
+                Err(FileError::LegalHold(path)) => {
+                    failed += 1;
+                    held.push(path);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to rename {} to {}: {}", from, to, e);
+                    failed += 1;
+                }
+            }
+            self.control.update(|info| info.copied_files += 1);
+        }
+
+        // What was renamed can be renamed back, even if cancelled
+        let folder = self.control.read().source.clone();
+        undo::record(&self.db, &self.username, undo::Operation::Rename { dir: folder, renames: renamed }).await;
+        if cancelled {
+            // Status was already set by cancel()
+            return;
+        }
+
+        if failed == 0 {
+            self.control.update(|info| info.status = TaskStatus::Completed);
+            return;
+        }
+        let mut error = format!("重命名失败{}个文件", failed);
+        for path in &held {
+            error.push('；');
+            error.push_str(&legal_hold::message(path));
+        }
+        self.control.update(|info| {
+            info.status = TaskStatus::Failed;
+            info.error = Some(error);
+        });
+    }
+}
+
+impl Task for RenameTask {
+    fn info(&self) -> TaskInfo {
+        self.control.info()
+    }
+
+    fn id(&self) -> String {
+        self.control.id()
+    }
+
+    fn enqueue(&self) {
+        self.control.enqueue()
+    }
+
+    fn start(self: Arc<Self>) {
+        tokio::spawn(async move { self.run().await });
+    }
+
+    fn cancel(&self) {
+        self.control.cancel()
+    }
+
+    fn suspend(&self) {
+        self.control.suspend()
+    }
+
+    fn resume(&self) {
+        self.control.resume()
+    }
+
+    fn resolve_conflict(&self, _policy: ConflictPolicy) {}
+}
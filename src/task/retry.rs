@@ -0,0 +1,83 @@
+//! Retries of transient I/O errors
+//!
+//! Network filesystems and busy disks now and then fail with errors that go
+//! away on their own: timeouts, interrupted calls, dropped connections. Copies
+//! try a file failing like that again a few times, waiting longer each time,
+//! before giving up on it. Other errors, such as a missing file or a full
+//! disk, fail the file right away.
+
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use crate::config::TaskRetryConfig;
+
+/// Longest wait between two attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Whether trying again may get past `e`
+pub fn is_transient(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ResourceBusy
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+    )
+}
+
+/// How often and how patiently a file is tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in all, at least one
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for each one after
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(config: &TaskRetryConfig) -> Self {
+        Self {
+            attempts: config.attempts.max(1),
+            backoff: Duration::from_millis(config.backoff_ms),
+        }
+    }
+
+    /// Wait before attempt `attempt`, counted from 1, or None if there are
+    /// no attempts left
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt < 2 || attempt > self.attempts {
+            return None;
+        }
+        Some(self.backoff.saturating_mul(1 << (attempt - 2).min(16)).min(MAX_BACKOFF))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(&TaskRetryConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy::new(&TaskRetryConfig { attempts: 4, backoff_ms: 500 });
+        assert_eq!(policy.delay(1), None);
+        assert_eq!(policy.delay(2), Some(Duration::from_millis(500)));
+        assert_eq!(policy.delay(4), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay(5), None);
+        let policy = RetryPolicy::new(&TaskRetryConfig { attempts: 20, backoff_ms: 1000 });
+        assert_eq!(policy.delay(20), Some(MAX_BACKOFF));
+        assert_eq!(RetryPolicy::new(&TaskRetryConfig { attempts: 0, backoff_ms: 0 }).attempts, 1);
+
+        assert!(is_transient(&Error::from(ErrorKind::TimedOut)));
+        assert!(!is_transient(&Error::from(ErrorKind::NotFound)));
+    }
+}
@@ -0,0 +1,122 @@
+//! Throughput limits of copy and move tasks
+//!
+//! Each chunk a task copies is given a time slot: a pacer hands out slots
+//! one after another, each as long as the chunk takes at the limit, and the
+//! task waits for its slot before going on. Every task has its own pacer
+//! and all share the global one, so the stricter of both applies.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+use crate::config::TaskLimitConfig;
+
+/// Time slots of one stream of chunks
+#[derive(Debug)]
+pub struct Pacer {
+    /// End of the last slot handed out
+    next: Mutex<Option<Instant>>,
+}
+
+impl Pacer {
+    pub fn new() -> Self {
+        Self { next: Mutex::new(None) }
+    }
+
+    /// Reserve the slot of `bytes` at `rate` bytes per second starting at
+    /// `now` or later, the time to wait for it
+    fn reserve(&self, bytes: u64, rate: u64, now: Instant) -> Duration {
+        let mut next = self.next.lock().unwrap();
+        // Idle time isn't saved up for a burst later
+        let start = next.filter(|next| *next > now).unwrap_or(now);
+        *next = Some(start + Duration::from_secs_f64(bytes as f64 / rate as f64));
+        start - now
+    }
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Current limits, in bytes per second (0 = no limit)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Limits {
+    #[serde(rename = "perTask")]
+    pub per_task: u64,
+    pub global: u64,
+}
+
+/// Limits of all tasks
+#[derive(Debug, Default)]
+pub struct Throttle {
+    per_task: AtomicU64,
+    global: AtomicU64,
+    /// Pacer shared by all tasks
+    shared: Pacer,
+}
+
+impl Throttle {
+    pub fn limits(&self) -> Limits {
+        Limits {
+            per_task: self.per_task.load(Ordering::Relaxed),
+            global: self.global.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Change the limits, applied from the next chunk of running tasks on
+    pub fn set_limits(&self, limits: Limits) {
+        self.per_task.store(limits.per_task, Ordering::Relaxed);
+        self.global.store(limits.global, Ordering::Relaxed);
+    }
+
+    pub fn configure(&self, config: &TaskLimitConfig) {
+        self.set_limits(Limits { per_task: config.per_task, global: config.global });
+    }
+
+    /// Time a task with `pacer` has to wait before going on after copying
+    /// `bytes`
+    fn delay(&self, pacer: &Pacer, bytes: u64, now: Instant) -> Duration {
+        let Limits { per_task, global } = self.limits();
+        let own = if per_task > 0 { pacer.reserve(bytes, per_task, now) } else { Duration::ZERO };
+        let shared = if global > 0 { self.shared.reserve(bytes, global, now) } else { Duration::ZERO };
+        own.max(shared)
+    }
+
+    /// Wait as long as the limits ask for after copying `bytes`
+    pub async fn wait(&self, pacer: &Pacer, bytes: u64) {
+        let delay = self.delay(pacer, bytes, Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let throttle = Throttle::default();
+        let (a, b) = (Pacer::new(), Pacer::new());
+        let now = Instant::now();
+        assert_eq!(throttle.delay(&a, 1000, now), Duration::ZERO);
+
+        // 1000 bytes a second per task: the second chunk waits a second
+        throttle.set_limits(Limits { per_task: 1000, global: 0 });
+        assert_eq!(throttle.delay(&a, 1000, now), Duration::ZERO);
+        assert_eq!(throttle.delay(&a, 1000, now), Duration::from_secs(1));
+        assert_eq!(throttle.delay(&b, 1000, now), Duration::ZERO);
+
+        // 1000 bytes a second together: tasks queue behind each other
+        throttle.set_limits(Limits { per_task: 0, global: 1000 });
+        let later = now + Duration::from_secs(10);
+        assert_eq!(throttle.delay(&a, 500, later), Duration::ZERO);
+        assert_eq!(throttle.delay(&b, 500, later), Duration::from_millis(500));
+        assert_eq!(throttle.limits(), Limits { per_task: 0, global: 1000 });
+    }
+}
@@ -0,0 +1,138 @@
+//! Tracing exported over OpenTelemetry
+//!
+//! With `[telemetry] otlp_endpoint` set, spans are sent over OTLP/HTTP to a
+//! collector such as Jaeger or Tempo, besides the log printed as before. The
+//! trace of a request shows where its time went:
+//! - the request, named after the route that matched ([`request_span`])
+//! - database queries, the spans SeaORM opens at `sea_orm=trace`
+//! - filesystem operations of the file service and storage backend
+//! - Casbin permission checks
+//! - background tasks, and the steps of copies and moves
+//!
+//! Spans are exported by `[telemetry] filter` rather than the log level, so
+//! the log can stay at `info` while traces go down to single queries.
+
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::{Request, Response},
+};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::time::Duration;
+use tracing::{field::Empty, Span, Subscriber};
+use tracing_subscriber::{registry::LookupSpan, EnvFilter, Layer};
+
+use crate::config::TelemetryConfig;
+
+/// Route of requests no route matched (static files)
+const UNMATCHED: &str = "unmatched";
+
+/// Exports spans until dropped, flushing those not sent yet
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Failed to flush spans: {}", e);
+        }
+    }
+}
+
+/// URL of the traces at the collector `endpoint`
+///
+/// The exporter posts to a configured endpoint as is, while collectors are
+/// usually given as `http://host:4318`.
+fn traces_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{}/v1/traces", endpoint)
+    }
+}
+
+/// Layer exporting spans to the collector of `config`, None if none is set
+pub fn layer<S>(config: &TelemetryConfig) -> anyhow::Result<Option<(impl Layer<S>, Telemetry)>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if config.otlp_endpoint.is_empty() {
+        return Ok(None);
+    }
+    let filter = EnvFilter::try_new(&config.filter)
+        .map_err(|e| anyhow::anyhow!("Invalid telemetry filter '{}': {}", config.filter, e))?;
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpBinary)
+        .with_endpoint(traces_url(&config.otlp_endpoint))
+        .with_timeout(Duration::from_secs(10))
+        .build()?;
+    let ratio = config.sample_ratio.clamp(0.0, 1.0);
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio))))
+        .with_resource(Resource::builder().with_service_name(config.service_name.clone()).build())
+        .build();
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("datadisk"))
+        .with_filter(filter);
+    Ok(Some((layer, Telemetry { provider })))
+}
+
+/// Span of a request, named after its method and the route that matched
+/// (`GET /api/file/list`), so requests of a handler are found together
+pub fn request_span(request: &Request<Body>) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED, |p| p.as_str());
+    tracing::debug_span!(
+        "request",
+        otel.name = %format_args!("{} {}", request.method(), route),
+        otel.kind = "server",
+        otel.status_code = Empty,
+        http.request.method = %request.method(),
+        http.route = route,
+        url.path = request.uri().path(),
+        http.response.status_code = Empty,
+    )
+}
+
+/// Record the status of the response in the span of its request
+pub fn record_response(response: &Response<Body>, _latency: Duration, span: &Span) {
+    let status = response.status();
+    span.record("http.response.status_code", status.as_u16());
+    if status.is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traces_url() {
+        assert_eq!(traces_url("http://tempo:4318"), "http://tempo:4318/v1/traces");
+        assert_eq!(traces_url("http://tempo:4318/"), "http://tempo:4318/v1/traces");
+        assert_eq!(traces_url("http://jaeger/otlp/v1/traces"), "http://jaeger/otlp/v1/traces");
+    }
+
+    #[test]
+    fn test_layer() {
+        use tracing_subscriber::Registry;
+
+        assert!(layer::<Registry>(&TelemetryConfig::default()).unwrap().is_none());
+        let config = TelemetryConfig {
+            otlp_endpoint: "http://localhost:4318".to_string(),
+            filter: "datadisk=loud".to_string(),
+            ..TelemetryConfig::default()
+        };
+        assert!(layer::<Registry>(&config).is_err());
+    }
+}
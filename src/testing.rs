@@ -0,0 +1,361 @@
+//! Test harness
+//!
+//! Built for unit tests and, with the `test_support` feature, for
+//! integration tests.
+//!
+//! - [`TestEnv`] gives a migrated SQLite database and a data root in a temp
+//!   directory, so handlers can be called directly without PostgreSQL or a
+//!   prepared directory tree.
+//! - [`TestApp`] runs the whole server on a [`TestEnv`] with a seeded
+//!   administrator, for black-box tests over HTTP and WebSocket.
+//!
+//! ```ignore
+//! let app = TestApp::spawn().await;
+//! let admin = app.admin().await;
+//! let res = admin.upload("/", "a.txt", b"hello").await;
+//! assert!(res.status().is_success());
+//! app.close().await;
+//! ```
+
+use futures::StreamExt;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, Set};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::{tungstenite, MaybeTlsStream, WebSocketStream};
+
+use crate::config::{Config, DatabaseConfig};
+use crate::entity::{file_info, user};
+use crate::middleware::auth::CurrentUser;
+use crate::middleware::DbConn;
+use crate::permission::PermissionEnforcer;
+use crate::state::AppState;
+
+/// Administrator created by [`TestApp::spawn`]
+pub const ADMIN_USERNAME: &str = "admin";
+pub const ADMIN_PASSWORD: &str = "admin-password";
+
+/// A database and data root for one test
+pub struct TestEnv {
+    pub dir: PathBuf,
+    pub config: Config,
+    pub db: DatabaseConnection,
+}
+
+impl TestEnv {
+    /// A fresh environment in its own temp directory
+    pub async fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("datadisk-test-{}", uuid::Uuid::new_v4()));
+        let database = DatabaseConfig {
+            db_type: "sqlite".to_string(),
+            path: dir.join("datadisk.db"),
+            ..DatabaseConfig::default()
+        };
+        let mut config = Config {
+            root_dir: dir.join("root"),
+            config_dir: dir.join("etc"),
+            casbin_conf: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/etc/casbin_model.conf")),
+            database: database.clone(),
+            ..Config::default()
+        };
+        // Tests send bursts of requests from one address
+        config.rate_limit.enabled = false;
+        config.honeypot.enabled = false;
+        let db = crate::db::init_database(&database)
+            .await
+            .expect("failed to create test database");
+        Self { dir, config, db }
+    }
+
+    /// Application state on this environment's database and config
+    pub fn state(&self) -> AppState {
+        AppState::new(Some(self.db.clone()), None, self.config.clone())
+    }
+
+    /// The database as handlers receive it
+    pub fn db_conn(&self) -> DbConn {
+        DbConn(self.db.clone())
+    }
+
+    /// A signed-in user with the given permissions
+    pub fn user(&self, username: &str, permissions: &[&str]) -> CurrentUser {
+        CurrentUser {
+            id: 0,
+            username: username.to_string(),
+            full_name: username.to_string(),
+            email: String::new(),
+            department_id: 0,
+            dept_name: String::new(),
+            status: 1,
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            is_service: false,
+            token_id: None,
+        }
+    }
+
+    /// `(parent_id, name)` of all `file_info` rows, sorted
+    pub async fn file_rows(&self) -> Vec<(i64, String)> {
+        let mut rows: Vec<_> = file_info::Entity::find()
+            .all(&self.db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.parent_id, m.name))
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    /// Close the database and remove the temp directory
+    pub async fn close(self) {
+        self.db.close().await.unwrap();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The whole server listening on a local port
+///
+/// WebSocket notifications go through the process-wide hub, so tests running
+/// in parallel may see each other's task events; match them by task ID.
+pub struct TestApp {
+    pub env: TestEnv,
+    pub state: AppState,
+    pub addr: SocketAddr,
+    server: JoinHandle<()>,
+}
+
+impl TestApp {
+    /// Set up an initialized system with [`ADMIN_USERNAME`] and start serving
+    pub async fn spawn() -> Self {
+        Self::spawn_with(|_| {}).await
+    }
+
+    /// [`spawn`](Self::spawn) with the configuration changed by `configure`
+    pub async fn spawn_with(configure: impl FnOnce(&mut Config)) -> Self {
+        let mut env = TestEnv::new().await;
+        configure(&mut env.config);
+        std::fs::create_dir_all(&env.config.config_dir).unwrap();
+        std::fs::File::create(env.config.config_dir.join("sys_inited")).unwrap();
+
+        let perm = PermissionEnforcer::new(env.db.clone(), env.config.casbin_conf.to_str().unwrap())
+            .await
+            .expect("failed to load casbin model");
+        perm.ensure_default_roles().await.unwrap();
+        let state = AppState::new(Some(env.db.clone()), Some(perm), env.config.clone());
+
+        let app = Self::serve(env, state).await;
+        app.add_user(ADMIN_USERNAME, ADMIN_PASSWORD, "admin").await;
+        app
+    }
+
+    async fn serve(env: TestEnv, state: AppState) -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = crate::routes::create_router(state.clone());
+        crate::ws::start();
+        let server = tokio::spawn(async move {
+            let service = router.into_make_service_with_connect_info::<SocketAddr>();
+            if let Err(e) = axum::serve(listener, service).await {
+                tracing::error!("Test server failed: {}", e);
+            }
+        });
+        Self { env, state, addr, server }
+    }
+
+    /// Create an active user with `role` and a home directory
+    pub async fn add_user(&self, username: &str, password: &str, role: &str) -> user::Model {
+        let model = user::ActiveModel {
+            username: Set(username.to_string()),
+            // Lowest cost, hashing is slow in debug builds
+            password: Set(bcrypt::hash(password, 4).unwrap()),
+            full_name: Set(username.to_string()),
+            email: Set(Some(format!("{}@example.com", username))),
+            department_id: Set(0),
+            dept_name: Set(String::new()),
+            status: Set(1),
+            last_login: Set(0),
+            permissions: Set(String::new()),
+            ..Default::default()
+        }
+        .insert(&self.env.db)
+        .await
+        .unwrap();
+        std::fs::create_dir_all(self.env.config.root_dir.join(username)).unwrap();
+        if let Some(perm) = self.state.get_perm().await.as_ref() {
+            perm.assign_user_role(username, role).await.unwrap();
+        }
+        model
+    }
+
+    /// URL of `path` on this server
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// A client without a session
+    pub fn client(&self) -> TestClient {
+        let jar = Arc::new(reqwest::cookie::Jar::default());
+        let http = reqwest::Client::builder()
+            .cookie_provider(jar.clone())
+            .build()
+            .unwrap();
+        TestClient { http, jar, base: self.url("") }
+    }
+
+    /// A client signed in as `username`
+    ///
+    /// Panics if the login is refused.
+    pub async fn login(&self, username: &str, password: &str) -> TestClient {
+        let client = self.client();
+        let res = client
+            .post_json("/api/login", &serde_json::json!({ "username": username, "password": password }))
+            .await;
+        assert!(res.status().is_success(), "login of {} failed: {}", username, res.status());
+        client
+    }
+
+    /// A client signed in as the administrator
+    pub async fn admin(&self) -> TestClient {
+        self.login(ADMIN_USERNAME, ADMIN_PASSWORD).await
+    }
+
+    /// Stop the server and remove its data
+    pub async fn close(self) {
+        self.server.abort();
+        let _ = self.server.await;
+        drop(self.state);
+        self.env.close().await;
+    }
+}
+
+/// HTTP client keeping the session cookie of one user
+pub struct TestClient {
+    pub http: reqwest::Client,
+    jar: Arc<reqwest::cookie::Jar>,
+    base: String,
+}
+
+impl TestClient {
+    pub async fn get(&self, path: &str) -> reqwest::Response {
+        self.http.get(format!("{}{}", self.base, path)).send().await.unwrap()
+    }
+
+    pub async fn post_json(&self, path: &str, body: &serde_json::Value) -> reqwest::Response {
+        self.http
+            .post(format!("{}{}", self.base, path))
+            .json(body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Upload `data` as `name` into the directory `parent_path`
+    pub async fn upload(&self, parent_path: &str, name: &str, data: &[u8]) -> reqwest::Response {
+        let part = reqwest::multipart::Part::bytes(data.to_vec()).file_name(name.to_string());
+        let form = reqwest::multipart::Form::new()
+            .text("parentPath", parent_path.to_string())
+            .part("file", part);
+        self.http
+            .post(format!("{}/api/file/upload", self.base))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Open the notification WebSocket with this client's session
+    pub async fn ws(&self) -> TestWs {
+        use reqwest::cookie::CookieStore;
+        use tungstenite::client::IntoClientRequest;
+
+        let url = format!("{}/api/ws", self.base.replacen("http", "ws", 1));
+        let mut request = url.as_str().into_client_request().unwrap();
+        if let Some(cookie) = self.jar.cookies(&self.base.parse().unwrap()) {
+            request.headers_mut().insert(reqwest::header::COOKIE, cookie);
+        }
+        let (stream, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .expect("WebSocket connection refused");
+        TestWs(stream)
+    }
+}
+
+/// Notification WebSocket of a [`TestClient`]
+pub struct TestWs(WebSocketStream<MaybeTlsStream<TcpStream>>);
+
+impl TestWs {
+    /// Next JSON message, None if nothing arrives within `timeout`
+    pub async fn next_message(&mut self, timeout: Duration) -> Option<serde_json::Value> {
+        loop {
+            let msg = tokio::time::timeout(timeout, self.0.next()).await.ok()??.ok()?;
+            if let tungstenite::Message::Text(text) = msg {
+                return serde_json::from_str(&text).ok();
+            }
+        }
+    }
+
+    /// Wait up to five seconds for a message matching `pred`, skipping others
+    ///
+    /// Panics if none arrives.
+    pub async fn wait_for(&mut self, pred: impl Fn(&serde_json::Value) -> bool) -> serde_json::Value {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+            match self.next_message(left).await {
+                Some(msg) if pred(&msg) => return msg,
+                Some(_) => continue,
+                None => panic!("no matching WebSocket message"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_app_login_upload_copy() {
+        let app = TestApp::spawn().await;
+
+        // Sessions are required
+        let anonymous = app.client();
+        assert_eq!(anonymous.get("/api/user/current").await.status(), 401);
+
+        let admin = app.admin().await;
+        let current: serde_json::Value = admin.get("/api/user/current").await.json().await.unwrap();
+        assert_eq!(current["username"], ADMIN_USERNAME);
+
+        let res = admin.upload("/", "a.txt", b"hello").await;
+        assert!(res.status().is_success());
+        let res: serde_json::Value = admin
+            .post_json("/api/file/mkdir", &serde_json::json!({ "parentPath": "/", "name": "docs" }))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(res["code"], true);
+
+        // Copy runs as a task reported over the WebSocket
+        let mut ws = admin.ws().await;
+        let res: serde_json::Value = admin
+            .post_json(
+                "/api/file/copy",
+                &serde_json::json!({ "isCopy": true, "source": "/", "target": "/docs", "files": ["a.txt"] }),
+            )
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(res["code"], true);
+        ws.wait_for(|m| m["type"] == "taskInfo" && m["data"]["status"] == "completed").await;
+
+        let items: serde_json::Value = admin.get("/api/file/list?path=/docs").await.json().await.unwrap();
+        assert_eq!(items[0]["filename"], "/docs/a.txt");
+
+        app.close().await;
+    }
+}
@@ -0,0 +1,224 @@
+//! Watermarks of sensitive files
+//!
+//! Images and PDFs below a [sensitive folder](crate::handlers::sensitive)
+//! are served with the name of the viewer and the time drawn over them,
+//! repeated diagonally across the whole picture or page, so a screenshot or
+//! a copy passed on tells who it came from.
+//!
+//! The text is drawn in a built-in 5×7 pixel font, needing neither font files
+//! nor a PDF library: images get its pixels blended in, PDF pages get them as
+//! filled squares added by an incremental update ([`pdf`]). The font has
+//! digits, Latin letters, drawn as capitals, and a few signs; any other
+//! character is drawn as `?`.
+
+pub(crate) mod font;
+mod pdf;
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use std::io::Cursor;
+
+/// Angle of the text in degrees, rising to the right
+const ANGLE: f32 = 30.0;
+/// Opacity of the text on images
+const OPACITY: f32 = 0.3;
+/// Gray level of the text on images
+const GRAY: f32 = 128.0;
+
+/// Kinds of files that get a watermark
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Image,
+    Pdf,
+}
+
+impl Kind {
+    /// Kind of a file by its extension, None if it isn't watermarked
+    pub fn of(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" => Some(Kind::Image),
+            "pdf" => Some(Kind::Pdf),
+            _ => None,
+        }
+    }
+}
+
+/// Text drawn over a file
+pub struct Watermark {
+    text: String,
+    /// Pixels of the text in the font, top row first
+    rows: [Vec<bool>; font::HEIGHT],
+}
+
+impl Watermark {
+    /// Watermark naming `viewer` and the minute of `time`
+    pub fn new(viewer: &str, time: DateTime<Local>) -> Self {
+        Self::from_text(&format!("{} {}", viewer, time.format("%Y-%m-%d %H:%M")))
+    }
+
+    fn from_text(text: &str) -> Self {
+        let text = text.to_ascii_uppercase();
+        let mut rows: [Vec<bool>; font::HEIGHT] = Default::default();
+        for (i, c) in text.chars().enumerate() {
+            for (row, bits) in rows.iter_mut().zip(font::glyph(c)) {
+                // One pixel between glyphs
+                if i > 0 {
+                    row.push(false);
+                }
+                row.extend((0..font::WIDTH).rev().map(|bit| bits >> bit & 1 == 1));
+            }
+        }
+        Self { text, rows }
+    }
+
+    /// The text as drawn
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Width of the text in pixels of the font
+    fn width(&self) -> usize {
+        self.rows[0].len()
+    }
+
+    fn is_set(&self, x: usize, y: usize) -> bool {
+        self.rows.get(y).and_then(|row| row.get(x)).copied().unwrap_or(false)
+    }
+
+    /// Distance between the copies of the text along and across it
+    fn steps(&self) -> (f32, f32) {
+        (self.width() as f32 * 1.5, font::HEIGHT as f32 * 6.0)
+    }
+
+    /// Whether the point `u` along the text and `v` across it, downwards,
+    /// from the center is covered by one of the copies
+    ///
+    /// Distances are in pixels of the font. Every other row of copies is
+    /// shifted by half a step, the copies start at [`tiles`](Self::tiles).
+    fn covers(&self, u: f32, v: f32) -> bool {
+        let (step_u, step_v) = self.steps();
+        let row = (v / step_v).floor();
+        let u = if (row as i64).rem_euclid(2) == 1 { u - step_u / 2.0 } else { u };
+        let x = u - (u / step_u).floor() * step_u;
+        let y = v - row * step_v;
+        self.is_set(x as usize, y as usize)
+    }
+
+    /// Origins of the copies of the text reaching `radius` pixels of the
+    /// font around the center, along and across the text
+    fn tiles(&self, radius: f32) -> Vec<(f32, f32)> {
+        let (step_u, step_v) = self.steps();
+        let (nu, nv) = ((radius / step_u).ceil() as i64 + 1, (radius / step_v).ceil() as i64 + 1);
+        let mut tiles = Vec::new();
+        for j in -nv..=nv {
+            let shift = if j.rem_euclid(2) == 1 { step_u / 2.0 } else { 0.0 };
+            tiles.extend((-nu..=nu).map(|i| (i as f32 * step_u + shift, j as f32 * step_v)));
+        }
+        tiles
+    }
+
+    /// Draw the text over all of `image`
+    pub fn draw(&self, image: &mut RgbaImage) {
+        let (width, height) = image.dimensions();
+        // Pixels of the image per pixel of the font
+        let scale = (width.min(height) / 250).max(1) as f32;
+        let (sin, cos) = ANGLE.to_radians().sin_cos();
+        let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+            let u = (dx * cos - dy * sin) / scale;
+            let v = (dx * sin + dy * cos) / scale;
+            if self.covers(u, v) {
+                blend(pixel);
+            }
+        }
+    }
+}
+
+/// Lay the gray of the text over a pixel
+fn blend(pixel: &mut Rgba<u8>) {
+    let [r, g, b, a] = pixel.0;
+    let a = a as f32 / 255.0;
+    let alpha = OPACITY + a * (1.0 - OPACITY);
+    let mix = |c: u8| ((GRAY * OPACITY + c as f32 * a * (1.0 - OPACITY)) / alpha).round() as u8;
+    pixel.0 = [mix(r), mix(g), mix(b), (alpha * 255.0).round() as u8];
+}
+
+/// Watermark the content of an image or PDF, returning the new content and
+/// its media type
+///
+/// JPEG images stay JPEG, other images become PNG.
+pub fn apply(kind: Kind, data: &[u8], mark: &Watermark) -> Result<(Vec<u8>, &'static str)> {
+    match kind {
+        Kind::Image => {
+            let reader = image::ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+            let jpeg = reader.format() == Some(ImageFormat::Jpeg);
+            let mut image = reader.decode()?.to_rgba8();
+            mark.draw(&mut image);
+
+            let mut out = Cursor::new(Vec::new());
+            if jpeg {
+                let encoder = JpegEncoder::new_with_quality(&mut out, 90);
+                DynamicImage::ImageRgba8(image).to_rgb8().write_with_encoder(encoder)?;
+                Ok((out.into_inner(), "image/jpeg"))
+            } else {
+                image.write_to(&mut out, ImageFormat::Png)?;
+                Ok((out.into_inner(), "image/png"))
+            }
+        }
+        Kind::Pdf => Ok((pdf::apply(data, mark)?, "application/pdf")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        let mark = Watermark::from_text("bob 2026-10-18 09:30");
+        assert_eq!(mark.text(), "BOB 2026-10-18 09:30");
+        assert_eq!(mark.width(), 20 * 6 - 1);
+        // The left edge of B, the gap after it, the bar of the dash
+        assert!((0..font::HEIGHT).all(|y| mark.is_set(0, y)));
+        assert!(!(0..font::HEIGHT).any(|y| mark.is_set(5, y)));
+        assert!(mark.is_set(8 * 6, 3) && !mark.is_set(8 * 6, 2));
+        assert_eq!(Watermark::from_text("张").rows, Watermark::from_text("?").rows);
+    }
+
+    #[test]
+    fn test_draw() {
+        let mark = Watermark::from_text("alice 2026-10-18 09:30");
+        let mut image = RgbaImage::from_pixel(600, 400, Rgba([255, 255, 255, 255]));
+        mark.draw(&mut image);
+        let marked = image.pixels().filter(|p| p.0 != [255, 255, 255, 255]).count();
+        let share = marked as f32 / (600 * 400) as f32;
+        assert!(share > 0.02 && share < 0.3, "{}", share);
+        assert!(image.pixels().all(|p| p.0 == [255, 255, 255, 255] || p.0 == [217, 217, 217, 255]));
+
+        // Transparent pixels get the text too
+        let mut clear = RgbaImage::new(300, 300);
+        mark.draw(&mut clear);
+        assert!(clear.pixels().any(|p| p.0 == [128, 128, 128, 77]));
+    }
+
+    #[test]
+    fn test_apply_image() {
+        let mark = Watermark::from_text("alice");
+        let source = RgbaImage::from_pixel(300, 200, Rgba([0, 0, 255, 255]));
+        for (format, content_type) in [(ImageFormat::Jpeg, "image/jpeg"), (ImageFormat::Png, "image/png"), (ImageFormat::Bmp, "image/png")] {
+            let mut data = Cursor::new(Vec::new());
+            DynamicImage::ImageRgba8(source.clone()).to_rgb8().write_to(&mut data, format).unwrap();
+            let (out, ty) = apply(Kind::Image, data.get_ref(), &mark).unwrap();
+            assert_eq!(ty, content_type);
+            let image = image::load_from_memory(&out).unwrap();
+            assert_eq!((image.width(), image.height()), (300, 200));
+        }
+        assert!(apply(Kind::Image, b"not an image", &mark).is_err());
+        assert_eq!(Kind::of("JPG"), Some(Kind::Image));
+        assert_eq!(Kind::of("pdf"), Some(Kind::Pdf));
+        assert_eq!(Kind::of("txt"), None);
+    }
+}
@@ -0,0 +1,611 @@
+//! Watermarks on PDF pages
+//!
+//! The document is kept as it is and an incremental update appended to it,
+//! as editors save changes: every page object is written again with its
+//! `/Contents` put between two new streams, the first saving the graphics
+//! state and the last restoring it and drawing the text as light gray
+//! squares, which need no font or other resource of the page. The update
+//! ends with a cross-reference section of the kind the document uses, a
+//! table or a stream.
+//!
+//! Pages are found by scanning the file for objects, including those packed
+//! in compressed object streams; of objects written more than once the last
+//! counts, as in the updates they come from. The drawing covers pages up to
+//! about A3. Encrypted documents are refused.
+
+use anyhow::{anyhow, bail, Result};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use regex::bytes::Regex;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::sync::LazyLock;
+
+use super::{Watermark, ANGLE};
+
+/// Center of the drawing in points, that of an A3 page
+const CENTER: (f32, f32) = (421.0, 595.0);
+/// Reach of the drawing from the center in points
+const RADIUS: f32 = 740.0;
+/// Points per pixel of the font
+const SCALE: f32 = 2.5;
+/// Gray level of the text, 1 being white
+const GRAY: f32 = 0.8;
+
+/// Start of an object: `12 0 obj`
+static OBJECT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?-u)(\d+)\s+(\d+)\s+obj\b").unwrap());
+
+/// A page object
+struct Page {
+    number: u32,
+    generation: u16,
+    dict: Vec<u8>,
+}
+
+/// Entries of the trailer needed to continue it
+struct Trailer {
+    size: u32,
+    root: String,
+    info: Option<String>,
+    id: Option<String>,
+    /// Whether cross references are kept in streams
+    xref_stream: bool,
+    /// Offset of the last cross-reference section
+    prev: usize,
+}
+
+/// Add the watermark to every page of the PDF `data`
+pub fn apply(data: &[u8], mark: &Watermark) -> Result<Vec<u8>> {
+    let trailer = trailer(data)?;
+    let pages = pages(data);
+    if pages.is_empty() {
+        bail!("no pages found");
+    }
+
+    let mut out = data.to_vec();
+    if !out.ends_with(b"\n") && !out.ends_with(b"\r") {
+        out.push(b'\n');
+    }
+    let (open, close) = (trailer.size, trailer.size + 1);
+    let mut offsets: Vec<(u32, u16, usize)> = Vec::new();
+
+    offsets.push((open, 0, out.len()));
+    write!(out, "{} 0 obj\n<< /Length 2 >>\nstream\nq\n\nendstream\nendobj\n", open)?;
+    let drawing = deflate(format!("Q\n{}", drawing(mark)).as_bytes())?;
+    offsets.push((close, 0, out.len()));
+    write!(out, "{} 0 obj\n<< /Length {} /Filter /FlateDecode >>\nstream\n", close, drawing.len())?;
+    out.extend_from_slice(&drawing);
+    out.extend_from_slice(b"\nendstream\nendobj\n");
+
+    for page in &pages {
+        offsets.push((page.number, page.generation, out.len()));
+        writeln!(out, "{} {} obj", page.number, page.generation)?;
+        out.extend_from_slice(&wrap_contents(&page.dict, open, close)?);
+        out.extend_from_slice(b"\nendobj\n");
+    }
+
+    let mut entries = format!("/Root {}", trailer.root);
+    if let Some(info) = &trailer.info {
+        entries.push_str(&format!(" /Info {}", info));
+    }
+    if let Some(id) = &trailer.id {
+        entries.push_str(&format!(" /ID {}", id));
+    }
+    entries.push_str(&format!(" /Prev {}", trailer.prev));
+
+    let xref = out.len();
+    if trailer.xref_stream {
+        // The stream lists itself as well
+        let number = trailer.size + 2;
+        offsets.push((number, 0, xref));
+        let mut rows = Vec::new();
+        for (_, generation, offset) in &offsets {
+            let offset = u32::try_from(*offset).map_err(|_| anyhow!("document too large"))?;
+            rows.push(1);
+            rows.extend_from_slice(&offset.to_be_bytes());
+            rows.extend_from_slice(&generation.to_be_bytes());
+        }
+        let index: Vec<String> = offsets.iter().map(|(n, _, _)| format!("{} 1", n)).collect();
+        write!(
+            out,
+            "{} 0 obj\n<< /Type /XRef /Size {} /W [1 4 2] /Index [{}] {} /Length {} >>\nstream\n",
+            number,
+            number + 1,
+            index.join(" "),
+            entries,
+            rows.len()
+        )?;
+        out.extend_from_slice(&rows);
+        out.extend_from_slice(b"\nendstream\nendobj\n");
+    } else {
+        out.extend_from_slice(b"xref\n");
+        for (number, generation, offset) in &offsets {
+            write!(out, "{} 1\n{:010} {:05} n\r\n", number, offset, generation)?;
+        }
+        write!(out, "trailer\n<< /Size {} {} >>\n", trailer.size + 2, entries)?;
+    }
+    write!(out, "startxref\n{}\n%%EOF\n", xref)?;
+    Ok(out)
+}
+
+/// Content stream drawing the copies of the text
+fn drawing(mark: &Watermark) -> String {
+    // The text once, in pixels of the font, rows going down from y = 0
+    let mut text = String::new();
+    for (y, row) in mark.rows.iter().enumerate() {
+        let mut x = 0;
+        while x < row.len() {
+            if !row[x] {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < row.len() && row[x] {
+                x += 1;
+            }
+            text.push_str(&format!("{} {} {} 1 re ", start, -(y as i64) - 1, x - start));
+        }
+    }
+
+    let (sin, cos) = ANGLE.to_radians().sin_cos();
+    let (a, b, c, d) = (SCALE * cos, SCALE * sin, -SCALE * sin, SCALE * cos);
+    let mut ops = format!("q {} g\n", GRAY);
+    for (u, v) in mark.tiles(RADIUS / SCALE) {
+        // Along the text, and across it downwards, from the center
+        let e = CENTER.0 + SCALE * (u * cos + v * sin);
+        let f = CENTER.1 + SCALE * (u * sin - v * cos);
+        ops.push_str(&format!("q {:.3} {:.3} {:.3} {:.3} {:.2} {:.2} cm {}f Q\n", a, b, c, d, e, f, text));
+    }
+    ops.push_str("Q\n");
+    ops
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Page dictionary with its content streams put between `open` and `close`
+fn wrap_contents(dict: &[u8], open: u32, close: u32) -> Result<Vec<u8>> {
+    let entries = entries(dict).ok_or_else(|| anyhow!("malformed page"))?;
+    let mut out = Vec::new();
+    match entries.iter().find(|(key, _)| *key == b"/Contents") {
+        Some((_, (start, end))) => {
+            let value = &dict[*start..*end];
+            let inner = match value.strip_prefix(b"[") {
+                Some(array) => &array[..array.len() - 1],
+                None => value,
+            };
+            out.extend_from_slice(&dict[..*start]);
+            write!(out, "[{} 0 R ", open)?;
+            out.extend_from_slice(inner);
+            write!(out, " {} 0 R]", close)?;
+            out.extend_from_slice(&dict[*end..]);
+        }
+        None => {
+            out.extend_from_slice(&dict[..dict.len() - 2]);
+            write!(out, " /Contents [{} 0 R {} 0 R]>>", open, close)?;
+        }
+    }
+    Ok(out)
+}
+
+/// Read the trailer of the last cross-reference section
+fn trailer(data: &[u8]) -> Result<Trailer> {
+    let at = rfind(data, b"startxref").ok_or_else(|| anyhow!("no startxref"))?;
+    let start = skip_space(data, at + b"startxref".len());
+    let end = token_end(data, start);
+    let prev = std::str::from_utf8(&data[start..end])?.parse::<usize>()?;
+    if prev >= data.len() {
+        bail!("bad startxref");
+    }
+
+    let (dict, xref_stream) = if data[prev..].starts_with(b"xref") {
+        let at = find(&data[prev..], b"trailer").ok_or_else(|| anyhow!("no trailer"))? + prev;
+        (skip_space(data, at + b"trailer".len()), false)
+    } else {
+        let object = OBJECT
+            .find_at(data, prev)
+            .filter(|m| m.start() == prev)
+            .ok_or_else(|| anyhow!("bad startxref"))?;
+        (skip_space(data, object.end()), true)
+    };
+    let end = object_end(data, dict).ok_or_else(|| anyhow!("malformed trailer"))?;
+    let entries = entries(&data[dict..end]).ok_or_else(|| anyhow!("malformed trailer"))?;
+    let value = |key: &[u8]| {
+        entries
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, (s, e))| String::from_utf8_lossy(&data[dict + s..dict + e]).into_owned())
+    };
+    if value(b"/Encrypt").is_some() {
+        bail!("encrypted document");
+    }
+    Ok(Trailer {
+        size: value(b"/Size").and_then(|s| s.parse().ok()).ok_or_else(|| anyhow!("no /Size"))?,
+        root: value(b"/Root").ok_or_else(|| anyhow!("no /Root"))?,
+        info: value(b"/Info"),
+        id: value(b"/ID"),
+        xref_stream,
+        prev,
+    })
+}
+
+/// Page objects of the document, by object number
+fn pages(data: &[u8]) -> Vec<Page> {
+    // The last object written with a number, if it is a page
+    let mut objects: BTreeMap<u32, Option<Page>> = BTreeMap::new();
+    let mut skip_to = 0;
+    for caps in OBJECT.captures_iter(data) {
+        let whole = caps.get(0).unwrap();
+        if whole.start() < skip_to || whole.start() > 0 && !is_space(data[whole.start() - 1]) {
+            continue;
+        }
+        let (Some(number), Some(generation)) = (parse::<u32>(&caps[1]), parse::<u16>(&caps[2])) else {
+            continue;
+        };
+        let start = skip_space(data, whole.end());
+        if !data[start..].starts_with(b"<<") {
+            objects.insert(number, None);
+            continue;
+        }
+        let Some(end) = object_end(data, start) else {
+            continue;
+        };
+        let dict = &data[start..end];
+        let stream = stream(data, dict, end);
+        if let Some((_, stream_end)) = stream {
+            skip_to = stream_end;
+        }
+
+        match value(dict, b"/Type") {
+            Some(b"/Page") => {
+                objects.insert(number, Some(Page { number, generation, dict: dict.to_vec() }));
+            }
+            Some(b"/ObjStm") => {
+                objects.insert(number, None);
+                let Some(content) = stream.and_then(|(s, e)| decode(dict, &data[s..e])) else {
+                    continue;
+                };
+                for (number, dict) in packed(dict, &content) {
+                    let page = (value(&dict, b"/Type") == Some(&b"/Page"[..])).then_some(Page { number, generation: 0, dict });
+                    objects.insert(number, page);
+                }
+            }
+            _ => {
+                objects.insert(number, None);
+            }
+        }
+    }
+    objects.into_values().flatten().collect()
+}
+
+/// Start and end of the data of the stream following `dict`, which ends at `end`
+fn stream(data: &[u8], dict: &[u8], end: usize) -> Option<(usize, usize)> {
+    let at = skip_space(data, end);
+    if !data[at..].starts_with(b"stream") {
+        return None;
+    }
+    let mut start = at + b"stream".len();
+    if data[start..].starts_with(b"\r\n") {
+        start += 2;
+    } else if data[start..].starts_with(b"\n") {
+        start += 1;
+    }
+    // A direct length is trusted if the stream ends there
+    if let Some(length) = value(dict, b"/Length").and_then(parse::<usize>) {
+        let stream_end = start.saturating_add(length);
+        if stream_end <= data.len() && data[skip_space(data, stream_end)..].starts_with(b"endstream") {
+            return Some((start, stream_end));
+        }
+    }
+    find(&data[start..], b"endstream").map(|len| (start, start + len))
+}
+
+/// Decompressed content of a stream, None unless it is deflated or plain
+fn decode(dict: &[u8], content: &[u8]) -> Option<Vec<u8>> {
+    match value(dict, b"/Filter") {
+        None => Some(content.to_vec()),
+        Some(b"/FlateDecode") | Some(b"[/FlateDecode]") | Some(b"[ /FlateDecode ]") => {
+            let mut out = Vec::new();
+            ZlibDecoder::new(content).read_to_end(&mut out).ok()?;
+            Some(out)
+        }
+        Some(_) => None,
+    }
+}
+
+/// Dictionaries packed in an object stream, by object number
+fn packed(dict: &[u8], content: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let (Some(count), Some(first)) = (
+        value(dict, b"/N").and_then(parse::<usize>),
+        value(dict, b"/First").and_then(parse::<usize>),
+    ) else {
+        return Vec::new();
+    };
+    let mut header = Vec::new();
+    let mut at = 0;
+    while header.len() < count * 2 && at < first.min(content.len()) {
+        at = skip_space(content, at);
+        let end = token_end(content, at);
+        let Some(n) = parse::<usize>(&content[at..end]) else {
+            break;
+        };
+        header.push(n);
+        at = end;
+    }
+
+    let mut objects = Vec::new();
+    for pair in header.chunks_exact(2) {
+        let start = first.saturating_add(pair[1]);
+        if start >= content.len() || !content[start..].starts_with(b"<<") {
+            continue;
+        }
+        if let (Ok(number), Some(end)) = (u32::try_from(pair[0]), object_end(content, start)) {
+            objects.push((number, content[start..end].to_vec()));
+        }
+    }
+    objects
+}
+
+/// Keys of a dictionary with the start and end of their values
+type Entries<'a> = Vec<(&'a [u8], (usize, usize))>;
+
+/// Entries of a dictionary
+fn entries(dict: &[u8]) -> Option<Entries<'_>> {
+    let mut entries = Vec::new();
+    let mut at = skip_space(dict, 0);
+    if !dict[at..].starts_with(b"<<") {
+        return None;
+    }
+    at += 2;
+    loop {
+        at = skip_space(dict, at);
+        if dict[at..].starts_with(b">>") {
+            return Some(entries);
+        }
+        if dict.get(at) != Some(&b'/') {
+            return None;
+        }
+        let key_end = token_end(dict, at + 1);
+        let key = &dict[at..key_end];
+        let start = skip_space(dict, key_end);
+        let mut end = object_end(dict, start)?;
+        // A reference is three tokens: 12 0 R
+        if parse::<u32>(&dict[start..end]).is_some() {
+            let generation = skip_space(dict, end);
+            let generation_end = token_end(dict, generation);
+            let r = skip_space(dict, generation_end);
+            if parse::<u16>(&dict[generation..generation_end]).is_some() && token_end(dict, r) == r + 1 && dict[r] == b'R' {
+                end = r + 1;
+            }
+        }
+        entries.push((key, (start, end)));
+        at = end;
+    }
+}
+
+/// Value of `key` in a dictionary
+fn value<'a>(dict: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+    entries(dict)?
+        .into_iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, (start, end))| &dict[start..end])
+}
+
+/// End of the object starting at `at`
+fn object_end(data: &[u8], at: usize) -> Option<usize> {
+    match *data.get(at)? {
+        b'<' if data.get(at + 1) == Some(&b'<') => {
+            let mut i = at + 2;
+            loop {
+                i = skip_space(data, i);
+                if data[i..].starts_with(b">>") {
+                    return Some(i + 2);
+                }
+                i = object_end(data, i)?;
+            }
+        }
+        b'<' => find(&data[at..], b">").map(|end| at + end + 1),
+        b'[' => {
+            let mut i = at + 1;
+            loop {
+                i = skip_space(data, i);
+                if *data.get(i)? == b']' {
+                    return Some(i + 1);
+                }
+                i = object_end(data, i)?;
+            }
+        }
+        b'(' => {
+            let mut depth = 0;
+            let mut i = at;
+            loop {
+                match *data.get(i)? {
+                    b'\\' => i += 1,
+                    b'(' => depth += 1,
+                    b')' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+        }
+        b'/' => Some(token_end(data, at + 1)),
+        b'>' | b']' | b')' => None,
+        _ => {
+            let end = token_end(data, at);
+            (end > at).then_some(end)
+        }
+    }
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r' | b'\x0c' | b'\0')
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(b, b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%')
+}
+
+/// Skip whitespace and comments
+fn skip_space(data: &[u8], mut at: usize) -> usize {
+    while at < data.len() {
+        if data[at] == b'%' {
+            while at < data.len() && data[at] != b'\n' && data[at] != b'\r' {
+                at += 1;
+            }
+        } else if is_space(data[at]) {
+            at += 1;
+        } else {
+            break;
+        }
+    }
+    at
+}
+
+fn token_end(data: &[u8], mut at: usize) -> usize {
+    while at < data.len() && !is_space(data[at]) && !is_delimiter(data[at]) {
+        at += 1;
+    }
+    at
+}
+
+fn parse<T: std::str::FromStr>(token: &[u8]) -> Option<T> {
+    std::str::from_utf8(token).ok()?.parse().ok()
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+fn rfind(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).rposition(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A one page document with a cross-reference table
+    fn document() -> Vec<u8> {
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] /Contents 4 0 R /Resources << >> >>".to_string(),
+            "<< /Length 17 >>\nstream\n0 0 m 100 100 l S\n\nendstream".to_string(),
+        ];
+        let mut data = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(data.len());
+            data.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+        }
+        let xref = data.len();
+        data.extend_from_slice(b"xref\n0 5\n0000000000 65535 f\r\n");
+        for offset in offsets {
+            data.extend_from_slice(format!("{:010} 00000 n\r\n", offset).as_bytes());
+        }
+        data.extend_from_slice(format!("trailer\n<< /Size 5 /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", xref).as_bytes());
+        data
+    }
+
+    #[test]
+    fn test_entries() {
+        let dict = b"<< /Type /Page /Contents [4 0 R 5 0 R] /Parent 2 0 R /Name (a >> b) /Res << /X <ab> >> >>";
+        assert_eq!(value(dict, b"/Type"), Some(&b"/Page"[..]));
+        assert_eq!(value(dict, b"/Contents"), Some(&b"[4 0 R 5 0 R]"[..]));
+        assert_eq!(value(dict, b"/Parent"), Some(&b"2 0 R"[..]));
+        assert_eq!(value(dict, b"/Name"), Some(&b"(a >> b)"[..]));
+        assert_eq!(value(dict, b"/X"), None);
+
+        let wrapped = wrap_contents(b"<< /Type /Page /Contents 4 0 R >>", 10, 11).unwrap();
+        assert_eq!(wrapped, b"<< /Type /Page /Contents [10 0 R 4 0 R 11 0 R] >>");
+        let wrapped = wrap_contents(dict, 10, 11).unwrap();
+        assert_eq!(value(&wrapped, b"/Contents"), Some(&b"[10 0 R 4 0 R 5 0 R 11 0 R]"[..]));
+        let wrapped = wrap_contents(b"<< /Type /Page >>", 10, 11).unwrap();
+        assert_eq!(value(&wrapped, b"/Contents"), Some(&b"[10 0 R 11 0 R]"[..]));
+    }
+
+    #[test]
+    fn test_apply() {
+        let data = document();
+        let mark = Watermark::from_text("alice");
+        let out = apply(&data, &mark).unwrap();
+        assert!(out.starts_with(&data));
+
+        // The page again, wrapped, with a section pointing back
+        let update = &out[data.len()..];
+        let last = trailer_of(&out);
+        assert!(!last.xref_stream);
+        assert_eq!(last.size, 7);
+        assert_eq!(last.root, "1 0 R");
+        assert_eq!(last.prev, data.len() + find(update, b"xref\n").unwrap());
+        let found = pages(&out);
+        assert_eq!(found.len(), 1);
+        assert_eq!(value(&found[0].dict, b"/Contents"), Some(&b"[5 0 R 4 0 R 6 0 R]"[..]));
+        // Each offset of the table starts its object
+        let table = &update[find(update, b"xref\n").unwrap()..];
+        for line in table.split(|&b| b == b'\n').filter(|l| l.ends_with(b" n\r")) {
+            let offset: usize = parse(&line[..10]).unwrap();
+            assert!(OBJECT.find_at(&out, offset).is_some_and(|m| m.start() == offset));
+        }
+
+        let drawing = pages_stream(&out, 6);
+        assert!(drawing.starts_with(b"Q\nq 0.8 g\n"));
+        assert!(find(&drawing, b" re ").is_some());
+
+        // Updates go on from the last one
+        let again = apply(&out, &mark).unwrap();
+        assert_eq!(trailer_of(&again).size, 9);
+        assert_eq!(value(&pages(&again)[0].dict, b"/Contents"), Some(&b"[7 0 R 5 0 R 4 0 R 6 0 R 8 0 R]"[..]));
+
+        let encrypted = String::from_utf8_lossy(&data).replace("/Root 1 0 R", "/Root 1 0 R /Encrypt 9 0 R");
+        assert!(apply(encrypted.as_bytes(), &mark).is_err());
+        assert!(apply(b"%PDF-1.4\nnothing", &mark).is_err());
+    }
+
+    #[test]
+    fn test_object_streams() {
+        // Pages packed in a compressed object stream, cross references in a stream
+        let packed = b"3 0 << /Type /Page /Parent 2 0 R /Contents 4 0 R >>";
+        let content = deflate(packed).unwrap();
+        let mut data = b"%PDF-1.5\n".to_vec();
+        data.extend_from_slice(b"1 0 obj\n<< /Type /Catalog /Pages 2 0 R >>\nendobj\n");
+        data.extend_from_slice(format!("5 0 obj\n<< /Type /ObjStm /N 1 /First 4 /Filter /FlateDecode /Length {} >>\nstream\n", content.len()).as_bytes());
+        data.extend_from_slice(&content);
+        data.extend_from_slice(b"\nendstream\nendobj\n");
+        let xref = data.len();
+        data.extend_from_slice(b"6 0 obj\n<< /Type /XRef /Size 7 /Root 1 0 R /W [1 4 2] /Length 0 >>\nstream\n\nendstream\nendobj\n");
+        data.extend_from_slice(format!("startxref\n{}\n%%EOF", xref).as_bytes());
+
+        let out = apply(&data, &Watermark::from_text("bob")).unwrap();
+        let last = trailer_of(&out);
+        assert!(last.xref_stream);
+        assert_eq!(last.size, 10);
+        let found = pages(&out);
+        assert_eq!(found.len(), 1);
+        assert_eq!(value(&found[0].dict, b"/Contents"), Some(&b"[7 0 R 4 0 R 8 0 R]"[..]));
+        assert!(pages_stream(&out, 8).starts_with(b"Q\n"));
+    }
+
+    fn trailer_of(data: &[u8]) -> Trailer {
+        trailer(data).unwrap()
+    }
+
+    /// Decoded content of the stream object `number`
+    fn pages_stream(data: &[u8], number: u32) -> Vec<u8> {
+        let start = find(data, format!("\n{} 0 obj\n", number).as_bytes()).unwrap() + 1;
+        let found = OBJECT.find_at(data, start).unwrap();
+        let dict_start = skip_space(data, found.end());
+        let dict_end = object_end(data, dict_start).unwrap();
+        let (s, e) = stream(data, &data[dict_start..dict_end], dict_end).unwrap();
+        decode(&data[dict_start..dict_end], &data[s..e]).unwrap()
+    }
+}
@@ -1,6 +1,7 @@
 //! WebSocket Hub implementation
 //!
-//! Manages WebSocket connections and broadcasts messages
+//! Manages WebSocket connections and routes messages to the clients of the
+//! user they are for
 
 use axum::{
     extract::{
@@ -10,11 +11,12 @@ use axum::{
     response::IntoResponse,
     Extension,
 };
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
+use crate::metrics;
 use crate::middleware::auth::CurrentUser;
 use crate::state::AppState;
 use crate::task::{TaskNotification, TASK_MANAGER};
@@ -30,6 +32,18 @@ pub enum WsMessage {
     TaskInfo(serde_json::Value),
     #[serde(rename = "taskDeleted")]
     TaskDeleted(String),
+    /// Suspicious account activity, sent to auditors
+    #[serde(rename = "abuseAlert")]
+    AbuseAlert(serde_json::Value),
+    /// Entries of a folder about to expire, sent to the owner
+    #[serde(rename = "expiryNotice")]
+    ExpiryNotice(serde_json::Value),
+    /// A change in a watched folder, sent to the watcher
+    #[serde(rename = "watchActivity")]
+    WatchActivity(serde_json::Value),
+    /// A file landed in a folder with a notify hook, sent to the group
+    #[serde(rename = "folderHook")]
+    FolderHook(serde_json::Value),
     #[serde(rename = "ping")]
     Ping,
     #[serde(rename = "pong")]
@@ -40,33 +54,71 @@ pub enum WsMessage {
 pub struct Hub {
     /// Connected clients by user ID
     clients: DashMap<i64, Vec<mpsc::UnboundedSender<WsMessage>>>,
+    /// Connected users with the audit permission
+    auditors: DashSet<i64>,
 }
 
 impl Hub {
     pub fn new() -> Self {
         Self {
             clients: DashMap::new(),
+            auditors: DashSet::new(),
         }
     }
 
     /// Register a new client
     pub fn register(&self, user_id: i64, tx: mpsc::UnboundedSender<WsMessage>) {
         self.clients.entry(user_id).or_insert_with(Vec::new).push(tx);
+        metrics::count_ws_connect();
         tracing::debug!("WebSocket client registered for user {}", user_id);
     }
 
     /// Unregister a client
     pub fn unregister(&self, user_id: i64, tx: &mpsc::UnboundedSender<WsMessage>) {
         if let Some(mut clients) = self.clients.get_mut(&user_id) {
+            let before = clients.len();
             clients.retain(|c| !c.same_channel(tx));
+            if clients.len() < before {
+                metrics::count_ws_disconnect();
+            }
             if clients.is_empty() {
                 drop(clients);
                 self.clients.remove(&user_id);
+                self.auditors.remove(&user_id);
             }
         }
         tracing::debug!("WebSocket client unregistered for user {}", user_id);
     }
 
+    /// Mark a registered user as auditor, receiving [`Hub::send_auditors`]
+    pub fn add_auditor(&self, user_id: i64) {
+        self.auditors.insert(user_id);
+    }
+
+    /// Send a message to every client of the connected auditors
+    pub fn send_auditors(&self, msg: WsMessage) {
+        let auditors: Vec<i64> = self.auditors.iter().map(|id| *id).collect();
+        for user_id in auditors {
+            self.send(user_id, msg.clone());
+        }
+    }
+
+    /// Number of connected clients
+    pub fn connection_count(&self) -> usize {
+        self.clients.iter().map(|clients| clients.len()).sum()
+    }
+
+    /// Send a message to every client of a user
+    pub fn send(&self, user_id: i64, msg: WsMessage) {
+        if let Some(mut clients) = self.clients.get_mut(&user_id) {
+            // Drop clients whose connection has gone away
+            let before = clients.len();
+            clients.retain(|c| c.send(msg.clone()).is_ok());
+            for _ in clients.len()..before {
+                metrics::count_ws_disconnect();
+            }
+        }
+    }
 }
 
 impl Default for Hub {
@@ -75,6 +127,35 @@ impl Default for Hub {
     }
 }
 
+/// Start routing task notifications to the WebSocket clients of their user
+///
+/// A single subscriber dispatches every notification, so clients never see
+/// other users' task events.
+pub fn start() {
+    let mut task_rx = TASK_MANAGER.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let notification = match task_rx.recv().await {
+                Ok(notification) => notification,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("WebSocket dispatch lagged, {} task notifications dropped", n);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+            let user_id = notification.user_id();
+            let message = match notification {
+                TaskNotification::TaskInfo(info) => {
+                    WsMessage::TaskInfo(serde_json::to_value(info).unwrap_or_default())
+                }
+                TaskNotification::TaskDeleted { id, .. } => WsMessage::TaskDeleted(id),
+            };
+            HUB.send(user_id, message);
+        }
+    });
+}
+
 /// WebSocket upgrade handler
 pub async fn serve_ws(
     ws: WebSocketUpgrade,
@@ -91,45 +172,18 @@ async fn handle_socket(socket: WebSocket, user: CurrentUser) {
 
     // Register client
     HUB.register(user.id, tx.clone());
+    if user.can_audit() {
+        HUB.add_auditor(user.id);
+    }
 
-    // Subscribe to task notifications
-    let mut task_rx = TASK_MANAGER.subscribe();
-
-    // Spawn task to handle outgoing messages
-    let user_id = user.id;
+    // Spawn task to handle outgoing messages, task notifications for this
+    // user arrive through the hub
     let tx_clone = tx.clone();
     let send_task = tokio::spawn(async move {
-        loop {
-            tokio::select! {
-                // Handle messages from channel
-                Some(msg) = rx.recv() => {
-                    let text = serde_json::to_string(&msg).unwrap_or_default();
-                    if sender.send(Message::Text(text)).await.is_err() {
-                        break;
-                    }
-                }
-                // Handle task notifications
-                Ok(notification) = task_rx.recv() => {
-                    // Only send notifications for this user
-                    let should_send = match &notification {
-                        TaskNotification::TaskInfo(info) => info.user_id == user_id,
-                        TaskNotification::TaskDeleted(_) => true, // Send all delete notifications
-                    };
-
-                    if should_send {
-                        let message = match notification {
-                            TaskNotification::TaskInfo(info) => {
-                                WsMessage::TaskInfo(serde_json::to_value(info).unwrap_or_default())
-                            }
-                            TaskNotification::TaskDeleted(id) => WsMessage::TaskDeleted(id),
-                        };
-                        let text = serde_json::to_string(&message).unwrap_or_default();
-                        if sender.send(Message::Text(text)).await.is_err() {
-                            break;
-                        }
-                    }
-                }
-                else => break,
+        while let Some(msg) = rx.recv().await {
+            let text = serde_json::to_string(&msg).unwrap_or_default();
+            if sender.send(Message::Text(text)).await.is_err() {
+                break;
             }
         }
     });
@@ -164,3 +218,36 @@ async fn handle_socket(socket: WebSocket, user: CurrentUser) {
     // Unregister client
     HUB.unregister(user.id, &tx);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_only_reaches_user() {
+        let hub = Hub::new();
+        let (alice_tx, mut alice_rx) = mpsc::unbounded_channel();
+        let (alice2_tx, mut alice2_rx) = mpsc::unbounded_channel();
+        let (bob_tx, mut bob_rx) = mpsc::unbounded_channel();
+        hub.register(1, alice_tx);
+        hub.register(1, alice2_tx);
+        hub.register(2, bob_tx);
+
+        hub.send(1, WsMessage::TaskDeleted("t1".to_string()));
+        assert!(matches!(alice_rx.try_recv(), Ok(WsMessage::TaskDeleted(id)) if id == "t1"));
+        assert!(matches!(alice2_rx.try_recv(), Ok(WsMessage::TaskDeleted(id)) if id == "t1"));
+        assert!(bob_rx.try_recv().is_err());
+
+        // Closed clients are dropped
+        drop(alice2_rx);
+        hub.send(1, WsMessage::Ping);
+        assert_eq!(hub.clients.get(&1).unwrap().len(), 1);
+        assert!(matches!(alice_rx.try_recv(), Ok(WsMessage::Ping)));
+
+        // Auditor messages only reach auditors
+        hub.add_auditor(2);
+        hub.send_auditors(WsMessage::Pong);
+        assert!(matches!(bob_rx.try_recv(), Ok(WsMessage::Pong)));
+        assert!(alice_rx.try_recv().is_err());
+    }
+}
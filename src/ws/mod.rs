@@ -4,4 +4,4 @@
 
 mod hub;
 
-pub use hub::serve_ws;
+pub use hub::{serve_ws, start, WsMessage, HUB};
@@ -0,0 +1,409 @@
+//! Streaming Zip64 writer
+//!
+//! Writes zip archives to a plain [`Write`], for downloads streamed while
+//! they are built. Entries are always written in Zip64 form: sizes follow
+//! each entry in a 64-bit data descriptor, and the central directory holds
+//! 64-bit sizes and offsets and ends in a Zip64 end record. Archives over
+//! 4 GB, with entries over 4 GB or with more than 65535 entries need that,
+//! and deciding it per entry would need sizes before the data is read.
+//!
+//! The `zip` crate can stream too, but it leaves the sizes of large entries
+//! out of the central directory when it can't seek back.
+
+use chrono::{Datelike, Timelike};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{self, Read, Write};
+use std::time::SystemTime;
+
+const LOCAL_HEADER_SIG: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIG: u32 = 0x0807_4b50;
+const CENTRAL_HEADER_SIG: u32 = 0x0201_4b50;
+const ZIP64_END_SIG: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIG: u32 = 0x0706_4b50;
+const END_SIG: u32 = 0x0605_4b50;
+
+/// Zip 4.5, the first version with Zip64
+const VERSION: u16 = 45;
+/// Made on Unix, so readers apply the permissions
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION;
+/// Sizes in a data descriptor, names in UTF-8
+const FLAGS: u16 = (1 << 3) | (1 << 11);
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const FILE_MODE: u32 = 0o100644;
+const DIR_MODE: u32 = 0o040755;
+/// MS-DOS directory attribute
+const DOS_DIR: u32 = 0x10;
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Writer counting the bytes passed on
+struct Counter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// An entry written, as listed in the central directory
+struct Entry {
+    name: String,
+    method: u16,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed: u64,
+    size: u64,
+    offset: u64,
+    attributes: u32,
+}
+
+/// Zip archive written to a stream
+pub struct ZipStream<W: Write> {
+    out: Counter<W>,
+    level: Option<u32>,
+    entries: Vec<Entry>,
+}
+
+impl<W: Write> ZipStream<W> {
+    /// Write an archive to `writer`, deflating files at `level` (1 to 9) if
+    /// given, storing them otherwise
+    pub fn new(writer: W, level: Option<u32>) -> Self {
+        Self {
+            out: Counter { inner: writer, count: 0 },
+            level: level.map(|level| level.clamp(1, 9)),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Add an empty directory named `name` (without the trailing slash)
+    pub fn add_dir(&mut self, name: &str, modified: Option<SystemTime>) -> io::Result<()> {
+        let mut entry = self.start_entry(format!("{}/", name), STORED, modified, (DIR_MODE << 16) | DOS_DIR)?;
+        entry.crc = crc32fast::Hasher::new().finalize();
+        self.finish_entry(entry)
+    }
+
+    /// Add a file named `name` with the contents of `reader`
+    pub fn add_file(&mut self, name: &str, mut reader: impl Read, modified: Option<SystemTime>) -> io::Result<()> {
+        let method = if self.level.is_some() { DEFLATED } else { STORED };
+        let mut entry = self.start_entry(name.to_string(), method, modified, FILE_MODE << 16)?;
+        let start = self.out.count;
+        let mut crc = crc32fast::Hasher::new();
+        entry.size = match self.level {
+            Some(level) => {
+                let mut encoder = DeflateEncoder::new(&mut self.out, Compression::new(level));
+                let size = copy_hashed(&mut reader, &mut encoder, &mut crc)?;
+                encoder.finish()?;
+                size
+            }
+            None => copy_hashed(&mut reader, &mut self.out, &mut crc)?,
+        };
+        entry.crc = crc.finalize();
+        entry.compressed = self.out.count - start;
+        self.finish_entry(entry)
+    }
+
+    /// Write the local header of an entry
+    fn start_entry(&mut self, name: String, method: u16, modified: Option<SystemTime>, attributes: u32) -> io::Result<Entry> {
+        let (time, date) = dos_time(modified);
+        let entry = Entry {
+            name,
+            method,
+            time,
+            date,
+            crc: 0,
+            compressed: 0,
+            size: 0,
+            offset: self.out.count,
+            attributes,
+        };
+
+        let mut header = Vec::with_capacity(50 + entry.name.len());
+        put_u32(&mut header, LOCAL_HEADER_SIG);
+        put_u16(&mut header, VERSION);
+        put_u16(&mut header, FLAGS);
+        put_u16(&mut header, method);
+        put_u16(&mut header, time);
+        put_u16(&mut header, date);
+        // CRC and sizes follow in the data descriptor
+        put_u32(&mut header, 0);
+        put_u32(&mut header, u32::MAX);
+        put_u32(&mut header, u32::MAX);
+        put_u16(&mut header, entry.name.len() as u16);
+        put_u16(&mut header, 20);
+        header.extend_from_slice(entry.name.as_bytes());
+        put_u16(&mut header, ZIP64_EXTRA_ID);
+        put_u16(&mut header, 16);
+        put_u64(&mut header, 0);
+        put_u64(&mut header, 0);
+        self.out.write_all(&header)?;
+        Ok(entry)
+    }
+
+    /// Write the data descriptor of an entry
+    fn finish_entry(&mut self, entry: Entry) -> io::Result<()> {
+        let mut descriptor = Vec::with_capacity(24);
+        put_u32(&mut descriptor, DATA_DESCRIPTOR_SIG);
+        put_u32(&mut descriptor, entry.crc);
+        put_u64(&mut descriptor, entry.compressed);
+        put_u64(&mut descriptor, entry.size);
+        self.out.write_all(&descriptor)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Write the central directory, returning the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let central_start = self.out.count;
+        let mut central = Vec::new();
+        for entry in &self.entries {
+            put_u32(&mut central, CENTRAL_HEADER_SIG);
+            put_u16(&mut central, VERSION_MADE_BY);
+            put_u16(&mut central, VERSION);
+            put_u16(&mut central, FLAGS);
+            put_u16(&mut central, entry.method);
+            put_u16(&mut central, entry.time);
+            put_u16(&mut central, entry.date);
+            put_u32(&mut central, entry.crc);
+            put_u32(&mut central, u32::MAX);
+            put_u32(&mut central, u32::MAX);
+            put_u16(&mut central, entry.name.len() as u16);
+            put_u16(&mut central, 28);
+            put_u16(&mut central, 0); // comment length
+            put_u16(&mut central, 0); // disk
+            put_u16(&mut central, 0); // internal attributes
+            put_u32(&mut central, entry.attributes);
+            put_u32(&mut central, u32::MAX);
+            central.extend_from_slice(entry.name.as_bytes());
+            put_u16(&mut central, ZIP64_EXTRA_ID);
+            put_u16(&mut central, 24);
+            put_u64(&mut central, entry.size);
+            put_u64(&mut central, entry.compressed);
+            put_u64(&mut central, entry.offset);
+            if central.len() >= BUFFER_SIZE {
+                self.out.write_all(&central)?;
+                central.clear();
+            }
+        }
+        self.out.write_all(&central)?;
+        let central_size = self.out.count - central_start;
+        let count = self.entries.len() as u64;
+
+        let zip64_end = self.out.count;
+        let mut end = Vec::with_capacity(98);
+        put_u32(&mut end, ZIP64_END_SIG);
+        put_u64(&mut end, 44); // size of the rest of the record
+        put_u16(&mut end, VERSION_MADE_BY);
+        put_u16(&mut end, VERSION);
+        put_u32(&mut end, 0); // disk
+        put_u32(&mut end, 0); // disk of the central directory
+        put_u64(&mut end, count);
+        put_u64(&mut end, count);
+        put_u64(&mut end, central_size);
+        put_u64(&mut end, central_start);
+
+        put_u32(&mut end, ZIP64_LOCATOR_SIG);
+        put_u32(&mut end, 0); // disk of the Zip64 end record
+        put_u64(&mut end, zip64_end);
+        put_u32(&mut end, 1); // disks
+
+        put_u32(&mut end, END_SIG);
+        put_u16(&mut end, 0); // disk
+        put_u16(&mut end, 0); // disk of the central directory
+        put_u16(&mut end, count.min(u16::MAX as u64) as u16);
+        put_u16(&mut end, count.min(u16::MAX as u64) as u16);
+        put_u32(&mut end, central_size.min(u32::MAX as u64) as u32);
+        put_u32(&mut end, central_start.min(u32::MAX as u64) as u32);
+        put_u16(&mut end, 0); // comment length
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        Ok(self.out.inner)
+    }
+}
+
+/// Copy `reader` to `writer`, hashing what is copied; returns the bytes copied
+fn copy_hashed(reader: &mut impl Read, writer: &mut impl Write, crc: &mut crc32fast::Hasher) -> io::Result<u64> {
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut size = 0;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(size),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        crc.update(&buffer[..n]);
+        writer.write_all(&buffer[..n])?;
+        size += n as u64;
+    }
+}
+
+/// MS-DOS time and date of `modified` in local time, now if unknown
+fn dos_time(modified: Option<SystemTime>) -> (u16, u16) {
+    let time: chrono::DateTime<chrono::Local> = modified.map(Into::into).unwrap_or_else(chrono::Local::now);
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let date = (((time.year() - 1980).min(127) as u16) << 9) | ((time.month() as u16) << 5) | time.day() as u16;
+    let clock = ((time.hour() as u16) << 11) | ((time.minute() as u16) << 5) | (time.second() as u16 / 2);
+    (clock, date)
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    #[test]
+    fn test_round_trip() {
+        for level in [None, Some(6)] {
+            let mut zip = ZipStream::new(Vec::new(), level);
+            zip.add_file("a.txt", &b"hello hello hello"[..], None).unwrap();
+            zip.add_dir("empty", None).unwrap();
+            zip.add_file("docs/文档.txt", &b"world"[..], Some(SystemTime::UNIX_EPOCH)).unwrap();
+            let data = zip.finish().unwrap();
+
+            let mut archive = zip::ZipArchive::new(Cursor::new(data)).unwrap();
+            assert_eq!(archive.len(), 3);
+            let mut content = String::new();
+            archive.by_name("a.txt").unwrap().read_to_string(&mut content).unwrap();
+            assert_eq!(content, "hello hello hello");
+            assert!(archive.by_name("empty/").unwrap().is_dir());
+            let mut file = archive.by_name("docs/文档.txt").unwrap();
+            assert_eq!(file.unix_mode(), Some(FILE_MODE));
+            content.clear();
+            file.read_to_string(&mut content).unwrap();
+            assert_eq!(content, "world");
+        }
+    }
+
+    #[test]
+    fn test_many_entries() {
+        let mut zip = ZipStream::new(Vec::new(), None);
+        for i in 0..70_000 {
+            zip.add_file(&format!("dir{}/{}.txt", i % 100, i), &b"x"[..], None).unwrap();
+        }
+        let data = zip.finish().unwrap();
+
+        // The classic end record can't count them, the Zip64 one does
+        let end = &data[data.len() - 22..];
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), u16::MAX);
+        let archive = zip::ZipArchive::new(Cursor::new(data)).unwrap();
+        assert_eq!(archive.len(), 70_000);
+        assert_eq!(archive.name_for_index(69_999), Some("dir99/69999.txt"));
+    }
+
+    /// Keeps the beginning and the end of what is written
+    #[derive(Default)]
+    struct Ends {
+        head: Vec<u8>,
+        tail: Vec<u8>,
+        len: u64,
+    }
+
+    const KEPT: usize = 1024 * 1024;
+
+    impl Write for Ends {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let head = buf.len().min(KEPT - self.head.len());
+            self.head.extend_from_slice(&buf[..head]);
+            self.tail.extend_from_slice(buf);
+            if self.tail.len() > 2 * KEPT {
+                self.tail.drain(..self.tail.len() - KEPT);
+            }
+            self.len += buf.len() as u64;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// What was written to [`Ends`], zeros in between
+    struct Sparse {
+        ends: Ends,
+        pos: u64,
+    }
+
+    impl Read for Sparse {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let tail_start = self.ends.len - self.ends.tail.len() as u64;
+            let n = if self.pos < self.ends.head.len() as u64 {
+                let from = &self.ends.head[self.pos as usize..];
+                let n = from.len().min(buf.len());
+                buf[..n].copy_from_slice(&from[..n]);
+                n
+            } else if self.pos >= tail_start {
+                let from = &self.ends.tail[(self.pos - tail_start) as usize..];
+                let n = from.len().min(buf.len());
+                buf[..n].copy_from_slice(&from[..n]);
+                n
+            } else {
+                let n = buf.len().min((tail_start - self.pos) as usize);
+                buf[..n].fill(0);
+                n
+            };
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for Sparse {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.pos = match pos {
+                SeekFrom::Start(pos) => pos,
+                SeekFrom::End(offset) => self.ends.len.checked_add_signed(offset).unwrap(),
+                SeekFrom::Current(offset) => self.pos.checked_add_signed(offset).unwrap(),
+            };
+            Ok(self.pos)
+        }
+    }
+
+    #[test]
+    fn test_large_entry() {
+        let big = 4 * 1024 * 1024 * 1024 + 1024;
+        let mut zip = ZipStream::new(Ends::default(), None);
+        zip.add_file("a.txt", &b"hello"[..], None).unwrap();
+        zip.add_file("big.bin", io::repeat(0).take(big), None).unwrap();
+        zip.add_file("b.txt", &b"world"[..], None).unwrap();
+        let ends = zip.finish().unwrap();
+        assert!(ends.len > big);
+
+        // Sizes and offsets past 4 GB come from the Zip64 fields
+        let mut archive = zip::ZipArchive::new(Sparse { ends, pos: 0 }).unwrap();
+        let file = archive.by_name("big.bin").unwrap();
+        assert_eq!((file.size(), file.compressed_size()), (big, big));
+        drop(file);
+        let mut file = archive.by_name("b.txt").unwrap();
+        assert!(file.header_start() > big);
+        let mut content = String::new();
+        file.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "world");
+    }
+}
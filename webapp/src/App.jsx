@@ -15,9 +15,11 @@ import AuditView from './views/AuditView'
 import GroupView from './views/GroupView'
 import GlobalUploader from './components/uploader/GlobalUploader'
 import DefaultRedirect from './components/DefaultRedirect'
+import MaintenanceBanner from './components/MaintenanceBanner'
 
 const App = () => (
   <>
+    <MaintenanceBanner />
     <Routes>
       <Route path="/ui/login" element={<LoginView />} />
       <Route path="/" element={<HomeView />}>
@@ -0,0 +1,60 @@
+import React, { useEffect, useState } from 'react'
+import { t } from '../lib/i18n'
+import { MAINTENANCE_EVENT } from '../lib/http'
+
+// Seconds between health checks while the server is in maintenance mode,
+// unless it says when to check again
+const DEFAULT_RETRY_SECS = 10
+
+const checkHealth = async () => {
+  try {
+    const res = await fetch('/api/health', { cache: 'no-store' })
+    const body = await res.json()
+    return body?.data?.database ?? null
+  } catch {
+    return null
+  }
+}
+
+// Shown while the database is down: the API answers 503 with a maintenance
+// message, the banner checks /api/health until it is back and reloads the page
+const MaintenanceBanner = () => {
+  const [active, setActive] = useState(false)
+
+  useEffect(() => {
+    const onMaintenance = () => setActive(true)
+    window.addEventListener(MAINTENANCE_EVENT, onMaintenance)
+    checkHealth().then((database) => {
+      if (database && !database.available) setActive(true)
+    })
+    return () => window.removeEventListener(MAINTENANCE_EVENT, onMaintenance)
+  }, [])
+
+  useEffect(() => {
+    if (!active) return undefined
+    let timer
+    const poll = async () => {
+      const database = await checkHealth()
+      if (database?.available) {
+        window.location.reload()
+        return
+      }
+      const secs = Math.max(database?.retryInSecs || DEFAULT_RETRY_SECS, 2)
+      timer = setTimeout(poll, secs * 1000)
+    }
+    timer = setTimeout(poll, DEFAULT_RETRY_SECS * 1000)
+    return () => clearTimeout(timer)
+  }, [active])
+
+  if (!active) return null
+  return (
+    <div
+      role="alert"
+      className="fixed inset-x-0 top-0 z-50 bg-amber-500 px-4 py-2 text-center text-sm font-medium text-white shadow"
+    >
+      {t('maintenance')}
+    </div>
+  )
+}
+
+export default MaintenanceBanner
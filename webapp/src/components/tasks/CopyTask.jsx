@@ -22,6 +22,8 @@ const statusFormatter = (row) => {
   switch (row.status) {
     case 'pending':
       return '等待中'
+    case 'queued':
+      return '排队中'
     case 'starting':
       return '启动中'
     case 'running':
@@ -45,6 +47,7 @@ const statusTagType = (status) => {
       return 'default'
     case 'suspended':
     case 'pending':
+    case 'queued':
     case 'starting':
       return 'secondary'
     case 'failed':
@@ -206,7 +209,7 @@ const CopyTask = ({ task, onTaskDeleted, onTaskUpdated }) => {
                 <Play className="h-4 w-4" />
               </Button>
             )}
-            {['running', 'suspended', 'starting', 'pending'].includes(task.status) && (
+            {['running', 'suspended', 'starting', 'pending', 'queued'].includes(task.status) && (
               <Button variant="destructive" size="icon" onClick={() => cancelTask(task.id)}>
                 <X className="h-4 w-4" />
               </Button>
@@ -284,6 +287,17 @@ const CopyTask = ({ task, onTaskDeleted, onTaskUpdated }) => {
             <span>{task.error}</span>
           </div>
         )}
+
+        {task.failedFiles?.length > 0 && (
+          <div className="task-error">
+            <strong>失败的文件:</strong>
+            <ul>
+              {task.failedFiles.map((file) => (
+                <li key={file.path}>{file.path}: {file.error}</li>
+              ))}
+            </ul>
+          </div>
+        )}
       </Card>
 
       <Dialog open={conflictDialogVisible} onOpenChange={setConflictDialogVisible}>
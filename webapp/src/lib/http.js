@@ -1,5 +1,8 @@
 import axios from 'axios'
 
+// Fired when the server is in maintenance mode (database down)
+export const MAINTENANCE_EVENT = 'datadisk:maintenance'
+
 const http = axios.create()
 
 http.interceptors.response.use(
@@ -17,6 +20,9 @@ http.interceptors.response.use(
     if (error?.response?.status === 401) {
       window.location.href = '/ui/login'
     }
+    if (error?.response?.status === 503 && error.response.data?.error === 'maintenance') {
+      window.dispatchEvent(new CustomEvent(MAINTENANCE_EVENT))
+    }
     return Promise.reject(error)
   }
 )
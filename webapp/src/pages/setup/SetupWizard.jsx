@@ -21,7 +21,8 @@ const SetupWizard = () => {
     port: '5432',
     database: '',
     username: '',
-    password: ''
+    password: '',
+    path: './datadisk.db'
   })
   const [adminForm, setAdminForm] = useState({
     username: '',
@@ -30,6 +31,13 @@ const SetupWizard = () => {
     email: ''
   })
 
+  const isDbFormComplete = () => {
+    if (dbForm.type === 'sqlite') {
+      return !!dbForm.path
+    }
+    return !!(dbForm.host && dbForm.port && dbForm.database && dbForm.username && dbForm.password)
+  }
+
   useEffect(() => {
     const checkStatus = async () => {
       try {
@@ -45,7 +53,7 @@ const SetupWizard = () => {
   }, [])
 
   const testConnection = async () => {
-    if (!dbForm.host || !dbForm.port || !dbForm.database || !dbForm.username || !dbForm.password) {
+    if (!isDbFormComplete()) {
       alertError('请填写完整的数据库配置信息')
       return
     }
@@ -66,7 +74,7 @@ const SetupWizard = () => {
 
   const handleNext = async () => {
     if (activeStep === 0) {
-      if (!dbForm.host || !dbForm.port || !dbForm.database || !dbForm.username || !dbForm.password) {
+      if (!isDbFormComplete()) {
         alertError('请填写完整的数据库配置信息')
         return
       }
@@ -140,17 +148,27 @@ const SetupWizard = () => {
                 onChange={(event) => setDbForm((prev) => ({ ...prev, type: event.target.value }))}
               >
                 <option value="postgres">PostgreSQL</option>
+                <option value="sqlite">SQLite</option>
               </select>
-              <label>主机地址</label>
-              <Input value={dbForm.host} onChange={(event) => setDbForm((prev) => ({ ...prev, host: event.target.value }))} />
-              <label>端口</label>
-              <Input value={dbForm.port} onChange={(event) => setDbForm((prev) => ({ ...prev, port: event.target.value }))} />
-              <label>数据库名</label>
-              <Input value={dbForm.database} onChange={(event) => setDbForm((prev) => ({ ...prev, database: event.target.value }))} />
-              <label>用户名</label>
-              <Input value={dbForm.username} onChange={(event) => setDbForm((prev) => ({ ...prev, username: event.target.value }))} />
-              <label>密码</label>
-              <Input type="password" value={dbForm.password} onChange={(event) => setDbForm((prev) => ({ ...prev, password: event.target.value }))} />
+              {dbForm.type === 'sqlite' ? (
+                <>
+                  <label>数据库文件路径</label>
+                  <Input value={dbForm.path} onChange={(event) => setDbForm((prev) => ({ ...prev, path: event.target.value }))} />
+                </>
+              ) : (
+                <>
+                  <label>主机地址</label>
+                  <Input value={dbForm.host} onChange={(event) => setDbForm((prev) => ({ ...prev, host: event.target.value }))} />
+                  <label>端口</label>
+                  <Input value={dbForm.port} onChange={(event) => setDbForm((prev) => ({ ...prev, port: event.target.value }))} />
+                  <label>数据库名</label>
+                  <Input value={dbForm.database} onChange={(event) => setDbForm((prev) => ({ ...prev, database: event.target.value }))} />
+                  <label>用户名</label>
+                  <Input value={dbForm.username} onChange={(event) => setDbForm((prev) => ({ ...prev, username: event.target.value }))} />
+                  <label>密码</label>
+                  <Input type="password" value={dbForm.password} onChange={(event) => setDbForm((prev) => ({ ...prev, password: event.target.value }))} />
+                </>
+              )}
               <div className="step-actions">
                 <Button variant="secondary" onClick={testConnection} disabled={loading}>
                   测试连接
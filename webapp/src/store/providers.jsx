@@ -127,6 +127,7 @@ export const TaskProvider = ({ children }) => {
         running: 1,
         suspended: 1,
         pending: 2,
+        queued: 2,
         starting: 3,
         completed: 4,
         cancelled: 4,
@@ -137,7 +138,7 @@ export const TaskProvider = ({ children }) => {
         if (statusOrder[a.status] !== statusOrder[b.status]) {
           return statusOrder[a.status] - statusOrder[b.status]
         }
-        if (a.status === 'running' || a.status === 'suspended' || a.status === 'pending') {
+        if (a.status === 'running' || a.status === 'suspended' || a.status === 'pending' || a.status === 'queued') {
           return b.createdAt - a.createdAt
         }
         return b.updatedAt - a.updatedAt
@@ -70,6 +70,7 @@ export const useTaskStore = defineStore('tasks', () => {
         running: 1,
         suspended: 1,
         pending: 2,
+        queued: 2,
         starting: 3,
         completed: 4,
         cancelled: 4,
@@ -80,7 +81,7 @@ export const useTaskStore = defineStore('tasks', () => {
         return statusOrder[a.status] - statusOrder[b.status]
       }
 
-      if (a.status === 'running' || a.status === 'suspended' || a.status === 'pending') {
+      if (a.status === 'running' || a.status === 'suspended' || a.status === 'pending' || a.status === 'queued') {
         return b.createdAt - a.createdAt
       }
 
@@ -7,6 +7,7 @@ import './LoginView.css'
 const LoginView = () => {
   const [username, setUsername] = useState('')
   const [password, setPassword] = useState('')
+  const [remember, setRemember] = useState(false)
 
   const login = () => {
     if (!username) {
@@ -20,7 +21,8 @@ const LoginView = () => {
     http
       .post('/api/login', {
         username,
-        password
+        password,
+        remember
       })
       .then(() => {
         window.location.href = '/ui/file'
@@ -94,8 +96,14 @@ const LoginView = () => {
               </div>
               <div className="OptionsContainer">
                 <div className="checkboxContainer">
-                  <input type="checkbox" id="RememberMe" className="checkbox" />
-                  <label htmlFor="RememberMe">{t('remember password')}</label>
+                  <input
+                    type="checkbox"
+                    id="RememberMe"
+                    className="checkbox"
+                    checked={remember}
+                    onChange={(event) => setRemember(event.target.checked)}
+                  />
+                  <label htmlFor="RememberMe">{t('remember this device')}</label>
                 </div>
                 <a href="#" className="ForgotPasswordLink">
                   {t('forgot password')}?
@@ -16,21 +16,42 @@ import {
   DropdownMenuItem,
   DropdownMenuTrigger
 } from '../../components/ui/dropdown-menu'
-import { Folder, MoreHorizontal, RefreshCw, Upload, Download, Trash2, Copy, Scissors, ClipboardPaste } from 'lucide-react'
+import { FilePlus, Folder, HardDrive, MoreHorizontal, RefreshCw, Upload, Download, Trash2, Copy, Scissors, ClipboardPaste } from 'lucide-react'
 import { Dialog, DialogContent, DialogFooter, DialogHeader, DialogTitle } from '../../components/ui/dialog'
 import { Input } from '../../components/ui/input'
 import { Table, TableBody, TableCell, TableHead, TableHeader, TableRow } from '../../components/ui/table'
 import './MyDocsView.css'
 
+// Kinds of new files, made by /api/file/touch; the server adds the template's
+// extension to the name
+const NEW_FILE_TEMPLATES = [
+  { label: '空白文件', template: null },
+  { label: 'Word 文档', template: 'docx' },
+  { label: 'Excel 表格', template: 'xlsx' },
+  { label: 'Markdown 文档', template: 'md' },
+  { label: '文本文件', template: 'txt' }
+]
+
+// Whether folders are listed with the size of everything in them
+const DIR_SIZE_KEY = 'mydocs.withDirSize'
+
+// Entries fetched at a time, large folders are listed page by page
+const PAGE_SIZE = 500
+
 const MyDocsView = () => {
   const navigate = useNavigate()
   const [searchParams] = useSearchParams()
   const [fileList, setFileList] = useState([])
+  const [total, setTotal] = useState(0)
   const [parentPath, setParentPath] = useState('/')
   const [breadcrumbs, setBreadcrumbs] = useState([])
   const [selectedRows, setSelectedRows] = useState([])
   const [mkdirDialog, setMkdirDialog] = useState(false)
   const [folderName, setFolderName] = useState('')
+  const [touchDialog, setTouchDialog] = useState(false)
+  const [touchTemplate, setTouchTemplate] = useState(null)
+  const [fileName, setFileName] = useState('')
+  const [withDirSize, setWithDirSize] = useState(() => localStorage.getItem(DIR_SIZE_KEY) === 'true')
   const renameDialogRef = useRef(null)
   const imagePreviewRef = useRef(null)
   const textPreviewRef = useRef(null)
@@ -61,16 +82,15 @@ const MyDocsView = () => {
     setBreadcrumbs(next)
   }
 
-  const getFiles = async (path) => {
+  // Folders first, then the latest changed, sorted by the server
+  const getFiles = async (path, offset = 0) => {
     try {
-      const response = await http.get('/api/file/list', { params: { path } })
+      const params = { path, sortBy: 'mtime', order: 'desc', offset, limit: PAGE_SIZE }
+      if (withDirSize) params.withDirSize = true
+      const response = await http.get('/api/file/list', { params })
       const list = response.data || []
-      list.sort((a, b) => {
-        if (a.type === 'directory' && b.type !== 'directory') return -1
-        if (a.type !== 'directory' && b.type === 'directory') return 1
-        return new Date(b.lastmod) - new Date(a.lastmod)
-      })
-      setFileList(list)
+      setFileList((current) => (offset === 0 ? list : [...current, ...list]))
+      setTotal(Number(response.headers['x-total-count'] ?? list.length))
     } catch (error) {
       console.error('Failed to get files:', error)
       alertError('获取文件列表失败')
@@ -84,7 +104,12 @@ const MyDocsView = () => {
     setParentPath(path)
     updateBreadcrumbs(path)
     getFiles(path)
-  }, [searchParams])
+  }, [searchParams, withDirSize])
+
+  const toggleDirSize = () => {
+    localStorage.setItem(DIR_SIZE_KEY, String(!withDirSize))
+    setWithDirSize(!withDirSize)
+  }
 
   useEffect(() => {
     const onFileAdded = (file) => file.resume?.()
@@ -202,6 +227,24 @@ const MyDocsView = () => {
     }
   }
 
+  const openTouchDialog = (template) => {
+    setTouchTemplate(template)
+    setFileName('')
+    setTouchDialog(true)
+  }
+
+  const touch = async () => {
+    try {
+      await http.post('/api/file/touch', { parentPath, name: fileName, template: touchTemplate })
+      setTouchDialog(false)
+      getFiles(parentPath)
+      alertSuccess('文件创建成功')
+    } catch (error) {
+      console.error('Failed to create file:', error)
+      alertError(error.message || '文件创建失败')
+    }
+  }
+
   const deleteFile = async () => {
     if (selectedRows.length === 0) {
       alert('请选择要删除的文件')
@@ -405,6 +448,21 @@ const MyDocsView = () => {
             <Folder className="mr-1 h-3.5 w-3.5" />
             新建文件夹
           </button>
+          <DropdownMenu>
+            <DropdownMenuTrigger asChild>
+              <button type="button" className="segment-btn">
+                <FilePlus className="mr-1 h-3.5 w-3.5" />
+                新建文件
+              </button>
+            </DropdownMenuTrigger>
+            <DropdownMenuContent align="start">
+              {NEW_FILE_TEMPLATES.map((item) => (
+                <DropdownMenuItem key={item.label} onClick={() => openTouchDialog(item.template)}>
+                  {item.label}
+                </DropdownMenuItem>
+              ))}
+            </DropdownMenuContent>
+          </DropdownMenu>
         </div>
         <div className="toolbar-segment">
           <button type="button" className="segment-btn" onClick={copyFile}>
@@ -441,6 +499,10 @@ const MyDocsView = () => {
               <Trash2 className="mr-2 h-4 w-4" />
               删除
             </DropdownMenuItem>
+            <DropdownMenuItem onClick={toggleDirSize}>
+              <HardDrive className="mr-2 h-4 w-4" />
+              {withDirSize ? '隐藏文件夹大小' : '显示文件夹大小'}
+            </DropdownMenuItem>
           </DropdownMenuContent>
         </DropdownMenu>
         <Button size="sm" variant="ghost" className="toolbar-refresh" onClick={refresh}>
@@ -505,13 +567,22 @@ const MyDocsView = () => {
                     <span>{row.basename}</span>
                   </div>
                 </TableCell>
-                <TableCell>{row.size === 0 ? '-' : formatFileSize(row.size)}</TableCell>
+                <TableCell>
+                  {row.sizePending ? '计算中…' : row.size === 0 ? '-' : formatFileSize(row.size)}
+                </TableCell>
                 <TableCell>{moment(row.lastmod).format('YYYY-MM-DD HH:mm:ss')}</TableCell>
                 <TableCell>{row.status || '-'}</TableCell>
               </TableRow>
             ))}
           </TableBody>
         </Table>
+        {fileList.length < total && (
+          <div className="flex justify-center py-3">
+            <Button variant="secondary" size="sm" onClick={() => getFiles(parentPath, fileList.length)}>
+              加载更多（{fileList.length}/{total}）
+            </Button>
+          </div>
+        )}
       </div>
 
       <Dialog open={mkdirDialog} onOpenChange={setMkdirDialog}>
@@ -529,6 +600,21 @@ const MyDocsView = () => {
         </DialogContent>
       </Dialog>
 
+      <Dialog open={touchDialog} onOpenChange={setTouchDialog}>
+        <DialogContent className="max-w-lg">
+          <DialogHeader>
+            <DialogTitle>新建文件</DialogTitle>
+          </DialogHeader>
+          <Input value={fileName} onChange={(event) => setFileName(event.target.value)} />
+          <DialogFooter>
+            <Button variant="secondary" onClick={() => setTouchDialog(false)}>
+              取消
+            </Button>
+            <Button onClick={touch}>保存</Button>
+          </DialogFooter>
+        </DialogContent>
+      </Dialog>
+
       {contextMenu.visible && (
         <div className="context-menu" style={{ left: contextMenu.x, top: contextMenu.y }}>
           <button type="button" className="context-menu-item" onClick={() => handleContextMenuAction('copy')}>
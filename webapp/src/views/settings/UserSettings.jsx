@@ -24,6 +24,7 @@ const UserSettings = () => {
     newPassword: '',
     confirmPassword: ''
   })
+  const [devices, setDevices] = useState([])
 
   const getUserInfo = async (username) => {
     if (!username) return
@@ -38,9 +39,39 @@ const UserSettings = () => {
     }
   }
 
+  const getDevices = async () => {
+    try {
+      const res = await http.get('/api/device/list')
+      if (res.data.code) {
+        setDevices(res.data.data || [])
+      }
+    } catch (error) {
+      alertError('获取设备列表失败')
+    }
+  }
+
+  const revokeDevice = async (device) => {
+    try {
+      const res = await http.post('/api/device/revoke', { id: device.id })
+      if (!res.data.code) {
+        alertError(res.data.message || '移除失败')
+        return
+      }
+      if (device.current) {
+        navigate('/ui/login')
+        return
+      }
+      alertSuccess('设备已移除')
+      getDevices()
+    } catch (error) {
+      alertError(error.response?.data?.message || '移除失败')
+    }
+  }
+
   useEffect(() => {
     setUserForm((prev) => ({ ...prev, username: loginUser }))
     getUserInfo(loginUser)
+    getDevices()
   }, [loginUser])
 
   const updateUserInfo = async () => {
@@ -222,6 +253,38 @@ const UserSettings = () => {
           </CardContent>
         </Card>
       </div>
+      <div className="settings-form">
+        <Card className="settings-card">
+          <CardHeader>
+            <CardTitle>已记住的设备</CardTitle>
+          </CardHeader>
+          <CardContent>
+            {devices.length === 0 ? (
+              <div className="device-empty">登录时勾选"记住此设备"的浏览器会显示在这里</div>
+            ) : (
+              <ul className="device-list">
+                {devices.map((device) => (
+                  <li key={device.id} className="device-item">
+                    <div className="device-info">
+                      <div className="device-name">
+                        {device.name}
+                        {device.current && <span className="device-current">当前设备</span>}
+                      </div>
+                      <div className="device-meta">
+                        最近使用 {new Date(device.lastUsedTime * 1000).toLocaleString()}
+                        {device.lastIp && ` · ${device.lastIp}`}
+                      </div>
+                    </div>
+                    <Button variant="destructive" className="btn-pill" onClick={() => revokeDevice(device)}>
+                      移除
+                    </Button>
+                  </li>
+                ))}
+              </ul>
+            )}
+          </CardContent>
+        </Card>
+      </div>
     </div>
   )
 }